@@ -0,0 +1,244 @@
+// Batch analysis of games: `attix analyze [--depth <n>] [--nodes <n>]
+// [--net <file>] <pgn>` searches every position of the games of a PGN file and
+// prints the games back with the evaluations as `[%eval]` comments, which GUIs
+// and lichess plot, and the mistakes marked with NAGs.
+//
+// The evaluations are from the perspective of white, in pawns or `#n` for a
+// mate in n moves. A move is judged by the expected score it loses for the
+// side that played it (see nnue for the scale): the score of the position
+// before the move, where the search chose the best move, against the score of
+// the position after it. Comments and variations of the input are dropped.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use preprocessing::nnue::Network;
+use shakmaty::zobrist::Zobrist64;
+use shakmaty::{CastlingMode, Chess, Color, Position};
+
+use crate::eval::{Eval, HandCrafted};
+use crate::nnue::{self, Nnue};
+use crate::perft;
+use crate::search::{self, Options, Search, MATE, MAX_PLY};
+use crate::time::Limits;
+use crate::tt::{self, TranspositionTable};
+
+pub const DEFAULT_DEPTH: u32 = 8;
+
+// NAGs of the moves losing at least this much of the expected score.
+const ANNOTATIONS: [(f32, u8); 3] = [
+    // Blunder (??).
+    (0.15, 4),
+    // Mistake (?).
+    (0.1, 2),
+    // Dubious move (?!).
+    (0.05, 6),
+];
+// Maximum length of the lines of the movetext.
+const LINE_WIDTH: usize = 80;
+
+// The headers and the mainline of a game.
+#[derive(Default)]
+struct Game {
+    headers: Vec<(String, String)>,
+    moves: Vec<SanPlus>,
+}
+
+impl Game {
+    fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+struct GameReader {
+    game: Game,
+}
+
+impl Visitor for GameReader {
+    type Result = Game;
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let key = String::from_utf8_lossy(key).into_owned();
+        let value = value.decode_utf8_lossy().into_owned();
+        self.game.headers.push((key, value));
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        self.game.moves.push(san_plus);
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Game {
+        std::mem::take(&mut self.game)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// Score of the position for the side to move, from the search or, when the
+// game is over, from the rules.
+fn score(
+    pos: &Chess,
+    history: &[Zobrist64],
+    limits: Limits,
+    castling_mode: CastlingMode,
+    tt: &Arc<TranspositionTable>,
+    network: Option<&Arc<Network>>,
+) -> i32 {
+    if pos.is_checkmate() {
+        return -MATE;
+    }
+    if pos.legal_moves().is_empty() {
+        return 0;
+    }
+    let mut search = Search::new(
+        pos.clone(),
+        history.to_vec(),
+        limits,
+        tt.clone(),
+        Arc::new(AtomicBool::new(false)),
+        castling_mode,
+        Options::default(),
+    );
+    let eval: Box<dyn Eval> = match network {
+        Some(network) => Box::new(Nnue::new(network.clone())),
+        None => Box::new(HandCrafted::default()),
+    };
+    search.set_eval(eval);
+    search.set_silent();
+    search.run();
+    search.score().expect("the first iteration always finishes")
+}
+
+fn is_mate(score: i32) -> bool {
+    score.abs() >= MATE - MAX_PLY as i32
+}
+
+// The `[%eval]` comment of a score from the perspective of white.
+fn eval_comment(score: i32) -> String {
+    if is_mate(score) {
+        let plies = MATE - score.abs();
+        format!("[%eval #{}]", score.signum() * (plies + 1) / 2)
+    } else {
+        format!("[%eval {:.2}]", score as f32 / 100.0)
+    }
+}
+
+// Expected score of the side to move with the score.
+fn expected_score(score: i32) -> f32 {
+    let score = if is_mate(score) {
+        score.signum() * MATE
+    } else {
+        score
+    };
+    1.0 / (1.0 + 10f32.powf(-score as f32 / nnue::SCALE))
+}
+
+// The NAG of a move from a position with the score `before` to one with the
+// score `after`, both for the side to move.
+fn annotation(before: i32, after: i32) -> Option<u8> {
+    let loss = expected_score(before) - expected_score(-after);
+    ANNOTATIONS
+        .iter()
+        .find(|(threshold, _)| loss >= *threshold)
+        .map(|(_, nag)| *nag)
+}
+
+// Searches every position of the game and writes it with the annotations.
+fn annotate<W: Write>(
+    game: &Game,
+    limits: Limits,
+    network: Option<&Arc<Network>>,
+    out: &mut W,
+) -> io::Result<()> {
+    let (mut pos, castling_mode) = match game.header("FEN") {
+        Some(fen) => perft::parse_fen(fen)?,
+        None => (Chess::default(), CastlingMode::Standard),
+    };
+    let tt = Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB));
+    let mut history = vec![search::hash(&pos)];
+    let evaluate = |pos: &Chess, history: &[Zobrist64]| {
+        score(pos, history, limits, castling_mode, &tt, network)
+    };
+    let mut before = evaluate(&pos, &history);
+    // The movetext, in the units the lines are wrapped between.
+    let mut words = Vec::new();
+    for san_plus in &game.moves {
+        let (turn, number) = (pos.turn(), pos.fullmoves());
+        let m = san_plus
+            .san
+            .to_move(&pos)
+            .map_err(|err| invalid(format!("move {number} {san_plus}: {err}")))?;
+        pos.play_unchecked(&m);
+        history.push(search::hash(&pos));
+        let after = evaluate(&pos, &history);
+
+        words.push(match turn {
+            Color::White => format!("{number}."),
+            Color::Black => format!("{number}..."),
+        });
+        words.push(san_plus.to_string());
+        if let Some(nag) = annotation(before, after) {
+            words.push(format!("${nag}"));
+        }
+        // No evaluation after the mate.
+        if !pos.is_checkmate() {
+            let white = match turn {
+                Color::White => -after,
+                Color::Black => after,
+            };
+            words.extend(["{".to_string(), eval_comment(white), "}".to_string()]);
+        }
+        before = after;
+    }
+    words.push(game.header("Result").unwrap_or("*").to_string());
+
+    for (key, value) in &game.headers {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "[{key} \"{value}\"]")?;
+    }
+    writeln!(out)?;
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+            writeln!(out, "{line}")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    writeln!(out, "{line}")?;
+    writeln!(out)?;
+    out.flush()
+}
+
+// Annotates the games of the PGN file to the standard output, searching
+// every position with the limits.
+pub fn run(path: &str, limits: Limits, network: Option<Network>) -> io::Result<()> {
+    let network = network.map(Arc::new);
+    let file =
+        File::open(path).map_err(|err| io::Error::new(err.kind(), format!("{path}: {err}")))?;
+    let mut reader = BufferedReader::new(file);
+    let mut out = io::stdout().lock();
+    let mut games = 0;
+    while let Some(game) = reader.read_game(&mut GameReader::default())? {
+        games += 1;
+        annotate(&game, limits, network.as_ref(), &mut out)
+            .map_err(|err| io::Error::new(err.kind(), format!("game {games}: {err}")))?;
+    }
+    Ok(())
+}
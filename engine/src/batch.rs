@@ -0,0 +1,103 @@
+// Asynchronous evaluation of positions on a network in a background thread,
+// for the tree search. Networks on accelerators (see onnx) are only fast with
+// large batches and keep the search waiting otherwise.
+//
+// The searches submit their leaves to the queue and continue gathering more
+// while the network evaluates them (see mcts). The worker merges the requests
+// waiting in the queue, up to MAX_BATCH positions, into one batch, waiting up
+// to MAX_WAIT for more requests after the first one, and sends every request
+// its part of the results.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use shakmaty::Chess;
+
+use crate::mcts::{Evaluation, Network};
+
+const MAX_BATCH: usize = 1024;
+const MAX_WAIT: Duration = Duration::from_millis(1);
+
+struct Request {
+    lines: Vec<Vec<Chess>>,
+    reply: Sender<io::Result<Vec<Evaluation>>>,
+}
+
+// Handle of the queue, the worker stops once all handles are dropped.
+#[derive(Clone)]
+pub struct BatchQueue {
+    requests: Sender<Request>,
+}
+
+// Results of a submitted request.
+pub struct Pending {
+    reply: Receiver<io::Result<Vec<Evaluation>>>,
+}
+
+impl Pending {
+    pub fn wait(self) -> io::Result<Vec<Evaluation>> {
+        self.reply
+            .recv()
+            .map_err(|_| io::Error::other("the evaluation thread stopped"))?
+    }
+}
+
+impl BatchQueue {
+    pub fn new(mut network: Box<dyn Network>) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        thread::spawn(move || work(network.as_mut(), receiver));
+        BatchQueue { requests }
+    }
+
+    // Queues the last positions of the lines for evaluation, see
+    // Network::evaluate.
+    pub fn submit(&self, lines: Vec<Vec<Chess>>) -> Pending {
+        let (reply, receiver) = mpsc::channel();
+        // Without the worker, the reply channel is dropped and `wait` fails.
+        let _ = self.requests.send(Request { lines, reply });
+        Pending { reply: receiver }
+    }
+}
+
+fn work(network: &mut dyn Network, receiver: Receiver<Request>) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + MAX_WAIT;
+        let mut size = first.lines.len();
+        let mut requests = vec![first];
+        while size < MAX_BATCH {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(request) => {
+                    size += request.lines.len();
+                    requests.push(request);
+                }
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let sizes: Vec<usize> = requests.iter().map(|request| request.lines.len()).collect();
+        let mut lines = Vec::with_capacity(size);
+        for request in &mut requests {
+            lines.append(&mut request.lines);
+        }
+        // The searches may have stopped waiting, the replies are best effort.
+        match network.evaluate(&lines) {
+            Ok(evaluations) => {
+                let mut evaluations = evaluations.into_iter();
+                for (request, len) in requests.into_iter().zip(sizes) {
+                    let _ = request
+                        .reply
+                        .send(Ok(evaluations.by_ref().take(len).collect()));
+                }
+            }
+            Err(err) => {
+                for request in requests {
+                    let err = io::Error::new(err.kind(), err.to_string());
+                    let _ = request.reply.send(Err(err));
+                }
+            }
+        }
+    }
+}
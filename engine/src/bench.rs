@@ -0,0 +1,195 @@
+// Fixed-depth searches of a suite of positions, reporting the number of nodes
+// searched. The total is a signature of the search: changes which should not
+// affect it (e.g. speedups) must keep it, and the changes of the pruning and
+// the move ordering are compared by it, fewer nodes at the same depth meaning
+// earlier cutoffs. Run as `attix bench [depth] [threads]` or as the `bench`
+// UCI command, with the threads of the Threads option. The last line is
+// `<nodes> nodes <nps> nps`, as testing frameworks expect.
+//
+// With several threads (see smp) the nodes depend on the timing of the
+// threads and differ between runs, so they are no signature. The suite is
+// searched twice instead, checking that the principal variations are legal
+// and reporting how many best moves the second run reproduces, and the nodes
+// of every thread are reported.
+//
+// The hits of the caches of the evaluation (see cache) are reported for all
+// threads and positions.
+
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
+
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess, Move, Position};
+
+use crate::cache::CacheStats;
+use crate::eval::{Eval, HandCrafted};
+use crate::search::{self, Options, Search};
+use crate::smp::{self, ParallelSearch};
+use crate::time::Limits;
+use crate::tt::{self, TranspositionTable};
+
+pub const DEFAULT_DEPTH: u32 = 6;
+
+// Openings, middlegames with tactics and endgames.
+const POSITIONS: &[&str] = &[
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+    "rnbqkb1r/pp1p1ppp/4pn2/2p5/2PP4/2N5/PP2PPPP/R1BQKBNR w KQkq - 0 4",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "r2q1rk1/pb1nbppp/1p2pn2/2pp4/2PP4/1PN1PN2/PB2BPPP/R2Q1RK1 w - - 0 10",
+    "2rq1rk1/pp1bppbp/3p1np1/4n3/3NP3/1BN1BP2/PPPQ2PP/2KR3R b - - 0 13",
+    "r1b2rk1/2q1bppp/p2ppn2/1p6/3BPP2/2NB4/PPPQ2PP/2KR3R w - - 0 13",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "8/5pk1/6p1/8/3R4/6P1/5PK1/2r5 w - - 0 40",
+    "6k1/5ppp/8/8/8/8/5PPP/3Q2K1 w - - 0 1",
+];
+
+fn position(fen: &str) -> Chess {
+    let fen: Fen = fen.parse().expect("the suite has valid FENs");
+    fen.into_position(CastlingMode::Standard)
+        .expect("the suite has legal positions")
+}
+
+// Searches the position to the depth with an empty transposition table,
+// returns the principal variation, the nodes of every thread and the
+// statistics of the caches.
+fn search(
+    pos: &Chess,
+    depth: u32,
+    threads: usize,
+) -> (Vec<Move>, Vec<u64>, Vec<(&'static str, CacheStats)>) {
+    let history = vec![search::hash(pos)];
+    let limits = Limits {
+        depth: Some(depth),
+        ..Limits::default()
+    };
+    let search = Search::new(
+        pos.clone(),
+        history,
+        limits,
+        Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+        Arc::new(AtomicBool::new(false)),
+        CastlingMode::Standard,
+        Options::default(),
+    );
+    let eval = || -> Box<dyn Eval> { Box::new(HandCrafted::default()) };
+    let mut search = ParallelSearch::new(search, threads, eval);
+    search.run();
+    (search.pv().to_vec(), search.nodes(), search.cache_stats())
+}
+
+fn is_legal(pos: &Chess, pv: &[Move]) -> bool {
+    let mut pos = pos.clone();
+    pv.iter().all(|m| {
+        let legal = pos.is_legal(m);
+        if legal {
+            pos.play_unchecked(m);
+        }
+        legal
+    })
+}
+
+// Searches every position of the suite to the depth and prints the nodes of
+// each and in total, with several threads also the nodes of every thread and
+// the reproduced best moves. Fails on illegal principal variations.
+pub fn run(depth: u32, threads: usize) -> io::Result<()> {
+    let threads = threads.clamp(1, smp::MAX_THREADS);
+    let runs = if threads > 1 { 2 } else { 1 };
+    let start = Instant::now();
+    let mut thread_nodes = vec![0; threads];
+    let mut reproduced = 0;
+    let mut caches: Vec<(&str, CacheStats)> = Vec::new();
+    for (idx, fen) in POSITIONS.iter().enumerate() {
+        println!("Position {}/{}: {fen}", idx + 1, POSITIONS.len());
+        let pos = position(fen);
+        let mut best_moves = Vec::new();
+        for _ in 0..runs {
+            let (pv, nodes, cache_stats) = search(&pos, depth, threads);
+            if !is_legal(&pos, &pv) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("illegal principal variation in {fen}"),
+                ));
+            }
+            best_moves.push(pv.first().cloned());
+            for (total, nodes) in thread_nodes.iter_mut().zip(nodes) {
+                *total += nodes;
+            }
+            for (name, stats) in cache_stats {
+                match caches.iter_mut().find(|(cache, _)| *cache == name) {
+                    Some((_, total)) => *total += stats,
+                    None => caches.push((name, stats)),
+                }
+            }
+        }
+        if best_moves.iter().all(|m| *m == best_moves[0]) {
+            reproduced += 1;
+        }
+    }
+    let millis = start.elapsed().as_millis() as u64;
+    let total: u64 = thread_nodes.iter().sum();
+    if threads > 1 {
+        for (thread, nodes) in thread_nodes.iter().enumerate() {
+            println!("Thread {thread} nodes: {nodes}");
+        }
+        println!("Best moves reproduced: {reproduced}/{}", POSITIONS.len());
+    }
+    for (name, stats) in &caches {
+        println!(
+            "{name} cache hits: {}/{} ({:.1}%)",
+            stats.hits,
+            stats.probes,
+            stats.hit_rate()
+        );
+    }
+    let nps = total * 1000 / millis.max(1);
+    println!("Nodes searched: {total}");
+    println!("Nodes/second: {nps}");
+    // The summary read by testing frameworks such as OpenBench.
+    println!("{total} nodes {nps} nps");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Low enough for debug builds, deep enough for the pruning to matter.
+    const TEST_DEPTH: u32 = 4;
+
+    // The signature of a single thread for a few positions of the suite: the
+    // start position, a middlegame, a rook endgame and a pawn endgame. Changes
+    // of the search that should not affect it must keep these, the others
+    // update them.
+    const NODES: &[(usize, u64)] = &[(0, 471), (1, 5184), (8, 524), (9, 162)];
+
+    #[test]
+    fn nodes() {
+        for &(idx, expected) in NODES {
+            let fen = POSITIONS[idx];
+            let pos = position(fen);
+            let (pv, nodes, _) = search(&pos, TEST_DEPTH, 1);
+            assert!(is_legal(&pos, &pv), "illegal principal variation in {fen}");
+            assert_eq!(nodes, [expected], "{fen} at depth {TEST_DEPTH}");
+        }
+    }
+
+    #[test]
+    fn threads_agree_on_mates() {
+        // The mates in one of the suite, which the helpers cannot change.
+        for idx in [2, 11] {
+            let fen = POSITIONS[idx];
+            let pos = position(fen);
+            let (single, _, _) = search(&pos, TEST_DEPTH, 1);
+            let (parallel, nodes, _) = search(&pos, TEST_DEPTH, 4);
+            assert!(is_legal(&pos, &parallel), "{fen}");
+            assert_eq!(single.first(), parallel.first(), "{fen}");
+            assert_eq!(nodes.len(), 4);
+            assert!(nodes.iter().all(|&nodes| nodes > 0), "{fen}: {nodes:?}");
+        }
+    }
+}
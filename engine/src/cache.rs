@@ -0,0 +1,73 @@
+// Small caches of the evaluations, owned by the evaluation of every thread so
+// that they need no synchronization. The search reaches the same positions
+// and pawn structures through transpositions and the re-searches of iterative
+// deepening, aspiration windows and reductions, whose evaluations are looked
+// up instead of computed again.
+//
+// Every cache is direct-mapped: a value goes to the slot of its hash and
+// replaces the value there. The full key is kept with the value, so that a
+// lookup never returns the value of another key. The probes and hits are
+// counted for `bench`.
+
+use std::ops::AddAssign;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub probes: u64,
+    pub hits: u64,
+}
+
+impl CacheStats {
+    // Percentage of the probes that hit.
+    pub fn hit_rate(&self) -> f64 {
+        100.0 * self.hits as f64 / self.probes.max(1) as f64
+    }
+}
+
+impl AddAssign for CacheStats {
+    fn add_assign(&mut self, other: CacheStats) {
+        self.probes += other.probes;
+        self.hits += other.hits;
+    }
+}
+
+pub struct Cache<K, V> {
+    entries: Vec<Option<(K, V)>>,
+    stats: CacheStats,
+}
+
+impl<K: PartialEq, V: Clone> Cache<K, V> {
+    // A cache of `size` entries, a power of two.
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+        Cache {
+            entries: (0..size).map(|_| None).collect(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        hash as usize & (self.entries.len() - 1)
+    }
+
+    // The value of the key, whose hash picks the slot.
+    pub fn get(&mut self, hash: u64, key: &K) -> Option<V> {
+        self.stats.probes += 1;
+        let slot = self.slot(hash);
+        let value = match &self.entries[slot] {
+            Some((cached, value)) if cached == key => Some(value.clone()),
+            _ => None,
+        };
+        self.stats.hits += u64::from(value.is_some());
+        value
+    }
+
+    pub fn insert(&mut self, hash: u64, key: K, value: V) {
+        let slot = self.slot(hash);
+        self.entries[slot] = Some((key, value));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
@@ -0,0 +1,104 @@
+// Static evaluation of the positions, behind the `Eval` trait so that the
+// search works with any evaluator: the hand-crafted evaluation of
+// preprocessing::eval with the parameters tuned by the tune command, or an
+// NNUE network (see nnue).
+//
+// The hand-crafted evaluation caches the evaluations of the positions by their
+// hash and the pawn structures by the pawns (see cache).
+
+use preprocessing::eval::{Params, PawnStructure};
+use shakmaty::{Chess, Color, Position};
+
+use crate::cache::{Cache, CacheStats};
+use crate::search;
+
+const EVAL_CACHE_SIZE: usize = 1 << 13;
+const PAWN_CACHE_SIZE: usize = 1 << 11;
+
+// Evaluates the positions of a search. The search reports the moves it makes
+// and takes back, so that evaluators can update their state incrementally
+// instead of computing it from scratch for every position.
+pub trait Eval: Send {
+    // Starts a search of the position.
+    fn reset(&mut self, pos: &Chess);
+
+    // The search moved from `parent`, the current position, to `child`.
+    fn push(&mut self, parent: &Chess, child: &Chess);
+
+    // The search took back the last move.
+    fn pop(&mut self);
+
+    // Evaluation of the current position in centipawns from the perspective
+    // of the side to move.
+    fn evaluate(&mut self, pos: &Chess) -> i32;
+
+    // The statistics of the caches of the evaluation by name, see cache.
+    fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        Vec::new()
+    }
+}
+
+pub struct HandCrafted {
+    params: Params,
+    // Evaluations from the perspective of white by the position hash, and
+    // pawn structures by the white and black pawns.
+    evals: Cache<u64, i32>,
+    pawns: Cache<(u64, u64), PawnStructure>,
+}
+
+impl Default for HandCrafted {
+    fn default() -> Self {
+        HandCrafted {
+            params: Params::default(),
+            evals: Cache::new(EVAL_CACHE_SIZE),
+            pawns: Cache::new(PAWN_CACHE_SIZE),
+        }
+    }
+}
+
+impl HandCrafted {
+    fn pawn_structure(&mut self, pos: &Chess) -> PawnStructure {
+        let board = pos.board();
+        let pawns = board.pawns();
+        let key = ((pawns & board.white()).0, (pawns & board.black()).0);
+        // The pawns of white and black mixed by multiplying with odd
+        // constants.
+        let hash = key.0.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ key.1.wrapping_mul(0xc2b2_ae3d_27d4_eb4f).rotate_left(32);
+        if let Some(structure) = self.pawns.get(hash, &key) {
+            return structure;
+        }
+        let structure = PawnStructure::new(board);
+        self.pawns.insert(hash, key, structure);
+        structure
+    }
+}
+
+impl Eval for HandCrafted {
+    fn reset(&mut self, _pos: &Chess) {}
+
+    fn push(&mut self, _parent: &Chess, _child: &Chess) {}
+
+    fn pop(&mut self) {}
+
+    fn evaluate(&mut self, pos: &Chess) -> i32 {
+        let hash = search::hash(pos).0;
+        let white = match self.evals.get(hash, &hash) {
+            Some(white) => white,
+            None => {
+                let pawns = self.pawn_structure(pos);
+                let white = self.params.evaluate_with_pawns(pos.board(), &pawns);
+                self.evals.insert(hash, hash, white);
+                white
+            }
+        };
+        match pos.turn() {
+            Color::White => white,
+            Color::Black => -white,
+        }
+    }
+
+    fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        vec![("Eval", self.evals.stats()), ("Pawn", self.pawns.stats())]
+    }
+}
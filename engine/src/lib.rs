@@ -0,0 +1,15 @@
+// The alpha-beta search and the evaluations of the engine as a library, which
+// the attix binary (see main) and the WebAssembly build (see the wasm crate)
+// are built on. It assumes neither threads nor files: the search runs on the
+// calling thread, its clock works in browsers, and the `info` lines can be
+// passed to a callback instead of the standard output.
+
+pub mod cache;
+pub mod eval;
+pub mod movepick;
+pub mod nnue;
+pub mod search;
+pub mod syzygy;
+pub mod time;
+pub mod tree;
+pub mod tt;
@@ -0,0 +1,186 @@
+// Chess engine speaking the UCI protocol over its standard input and output,
+// so that it can be played in GUIs and in matches with cutechess-cli, with
+// the options of a configuration file as `attix --config <file>` (see
+// options). `attix --protocol xboard` speaks CECP instead (see xboard).
+//
+// Instead, as subcommands:
+// - `attix bench [depth] [threads]` searches a fixed suite of positions (see
+//   bench).
+// - `attix perft <depth> [fen]` and `attix perft suite [depth]` count the
+//   legal moves (see perft).
+// - `attix eval [--net <file>] [fen]` prints the static evaluation (see
+//   trace).
+// - `attix analyze [--depth <n>] [--nodes <n>] [--net <file>] <pgn>` annotates
+//   the games of a PGN file with the evaluations of the search (see analyze).
+// - `attix testsuite [--movetime <ms>] [--net <file>] <epd>` runs an EPD test
+//   suite (see testsuite).
+// - `attix tree [--depth <n>] [--plies <n>] [--format json|dot] [fen]` dumps
+//   the search tree for debugging (see tree).
+
+mod analyze;
+mod batch;
+mod bench;
+mod mate;
+mod mcts;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod options;
+mod perft;
+mod smp;
+mod strength;
+mod testsuite;
+mod trace;
+mod uci;
+mod xboard;
+
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use engine::time::Limits;
+use engine::{cache, eval, nnue, search, syzygy, time, tree, tt};
+use preprocessing::network;
+
+// The argument parsed, the default without one.
+fn parse_arg<T>(arg: Option<&String>, default: T) -> io::Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err)),
+        None => Ok(default),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("bench") => {
+            let depth = parse_arg(args.get(1), bench::DEFAULT_DEPTH)?;
+            let threads = parse_arg(args.get(2), 1)?;
+            bench::run(depth, threads)
+        }
+        Some("perft") if args.get(1).is_some_and(|arg| arg == "suite") => {
+            perft::run_suite(parse_arg(args.get(2), perft::DEFAULT_SUITE_DEPTH)?)
+        }
+        Some("perft") => {
+            let depth = parse_arg(args.get(1), 1)?;
+            let fen = match args.get(2..).filter(|fen| !fen.is_empty()) {
+                Some(fen) => fen.join(" "),
+                None => perft::STARTPOS.to_string(),
+            };
+            let (pos, castling_mode) = perft::parse_fen(&fen)?;
+            perft::divide(&pos, depth, castling_mode).map(|_| ())
+        }
+        Some("analyze") => {
+            let mut limits = Limits::default();
+            let mut net = None;
+            let mut rest = &args[1..];
+            while let [flag, value, tail @ ..] = rest {
+                match flag.as_str() {
+                    "--depth" => limits.depth = Some(parse_arg(Some(value), 0)?),
+                    "--nodes" => limits.nodes = Some(parse_arg(Some(value), 0)?),
+                    "--net" => net = Some(network::load(value)?),
+                    _ => break,
+                }
+                rest = tail;
+            }
+            let [path] = rest else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: attix analyze [--depth <n>] [--nodes <n>] [--net <file>] <pgn>",
+                ));
+            };
+            if limits.depth.is_none() && limits.nodes.is_none() {
+                limits.depth = Some(analyze::DEFAULT_DEPTH);
+            }
+            analyze::run(path, limits, net)
+        }
+        Some("testsuite") => {
+            let mut movetime = testsuite::DEFAULT_MOVETIME;
+            let mut net = None;
+            let mut rest = &args[1..];
+            while let [flag, value, tail @ ..] = rest {
+                match flag.as_str() {
+                    "--movetime" => movetime = Duration::from_millis(parse_arg(Some(value), 0)?),
+                    "--net" => net = Some(network::load(value)?),
+                    _ => break,
+                }
+                rest = tail;
+            }
+            let [path] = rest else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: attix testsuite [--movetime <ms>] [--net <file>] <epd>",
+                ));
+            };
+            testsuite::run(path, movetime, net)
+        }
+        Some("eval") => {
+            let mut rest = &args[1..];
+            let net = match rest {
+                [flag, path, tail @ ..] if flag == "--net" => {
+                    rest = tail;
+                    Some(network::load(path)?)
+                }
+                _ => None,
+            };
+            let fen = match rest {
+                [] => perft::STARTPOS.to_string(),
+                fen => fen.join(" "),
+            };
+            let (pos, _) = perft::parse_fen(&fen)?;
+            trace::run(&pos, net.as_ref());
+            Ok(())
+        }
+        Some("tree") => {
+            let mut depth = tree::DEFAULT_DEPTH;
+            let mut plies = tree::DEFAULT_PLIES;
+            let mut format = tree::Format::Json;
+            let mut rest = &args[1..];
+            while let [flag, value, tail @ ..] = rest {
+                match flag.as_str() {
+                    "--depth" => depth = parse_arg(Some(value), depth)?,
+                    "--plies" => plies = parse_arg(Some(value), plies)?,
+                    "--format" => format = value.parse()?,
+                    _ => break,
+                }
+                rest = tail;
+            }
+            let fen = match rest {
+                [] => perft::STARTPOS.to_string(),
+                fen => fen.join(" "),
+            };
+            let (pos, castling_mode) = perft::parse_fen(&fen)?;
+            tree::run(pos, castling_mode, depth, plies, format)
+        }
+        _ => {
+            let mut config = None;
+            let mut protocol = "uci";
+            let mut rest = &args[..];
+            while let [flag, value, tail @ ..] = rest {
+                match flag.as_str() {
+                    "--config" => config = Some(value.as_str()),
+                    "--protocol" => protocol = value.as_str(),
+                    _ => break,
+                }
+                rest = tail;
+            }
+            match (protocol, config) {
+                ("uci", config) => uci::run(config),
+                ("xboard", None) => xboard::run(),
+                ("xboard", Some(_)) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the configuration files set UCI options",
+                )),
+                (protocol, _) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown protocol {protocol}, expected uci or xboard"),
+                )),
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+// Search for forced mates, for `go mate <moves>`: whether the side to move
+// mates in at most the given number of moves against every defence, and how.
+//
+// Unlike the alpha-beta search, nothing is pruned or evaluated, so a mate is
+// found if and only if it exists, which is what puzzles and compositions are
+// checked for. The number of moves is deepened from 1, so the shortest mate
+// is found first. The attacker tries the checks first, then the captures and
+// the other moves, and on its last move only the checks. Every move of the
+// defender has to be refuted, a stalemate refutes the line. Repetitions and
+// the fifty-move rule are not considered.
+//
+// A found mate is reported as `info depth <plies> score mate <moves> ... pv`
+// with the defence of the first reply refuted, otherwise the search reports
+// that there is none and plays the first legal move.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use shakmaty::{CastlingMode, Chess, Move, Position};
+
+use crate::time::Limits;
+
+const CHECK_INTERVAL: u64 = 1024;
+
+pub struct MateSearch {
+    pos: Chess,
+    // The most moves of the side to move to the mate.
+    moves: u32,
+    limits: Limits,
+    stop: Arc<AtomicBool>,
+    castling_mode: CastlingMode,
+    start: Instant,
+    nodes: u64,
+    stopped: bool,
+    // The mating lines found at every ply of the current line.
+    pv: Vec<Vec<Move>>,
+}
+
+impl MateSearch {
+    pub fn new(
+        pos: Chess,
+        moves: u32,
+        limits: Limits,
+        stop: Arc<AtomicBool>,
+        castling_mode: CastlingMode,
+    ) -> Self {
+        MateSearch {
+            pos,
+            moves,
+            limits,
+            stop,
+            castling_mode,
+            start: Instant::now(),
+            nodes: 0,
+            stopped: false,
+            pv: vec![Vec::new(); 2 * moves as usize + 1],
+        }
+    }
+
+    fn check_limits(&mut self) {
+        self.stopped = self.stop.load(Ordering::Relaxed)
+            || self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes)
+            || self
+                .limits
+                .time
+                .is_some_and(|time| self.start.elapsed() >= time);
+    }
+
+    fn visit(&mut self) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.check_limits();
+        }
+    }
+
+    // The moves of the attacker and the positions after them, checks first,
+    // then captures. Only the checks if `checks_only`.
+    fn attacks(pos: &Chess, checks_only: bool) -> Vec<(Move, Chess)> {
+        let mut children: Vec<(Move, Chess)> = pos
+            .legal_moves()
+            .into_iter()
+            .map(|m| {
+                let mut child = pos.clone();
+                child.play_unchecked(&m);
+                (m, child)
+            })
+            .filter(|(_, child)| !checks_only || child.is_check())
+            .collect();
+        children.sort_by_key(|(m, child)| (!child.is_check(), !m.is_capture()));
+        children
+    }
+
+    // Whether the side to move mates in at most `moves` moves, the line in
+    // `pv[ply]` if so.
+    fn attack(&mut self, pos: &Chess, moves: u32, ply: usize) -> bool {
+        self.visit();
+        for (m, child) in Self::attacks(pos, moves == 1) {
+            if self.stopped {
+                return false;
+            }
+            if self.defend(&child, moves, ply + 1) {
+                let mut pv = vec![m];
+                pv.extend_from_slice(&self.pv[ply + 1]);
+                self.pv[ply] = pv;
+                return true;
+            }
+        }
+        false
+    }
+
+    // Whether every move of the side to move is mated in the rest of the
+    // `moves` of the attacker, which made one of them already.
+    fn defend(&mut self, pos: &Chess, moves: u32, ply: usize) -> bool {
+        self.visit();
+        let replies = pos.legal_moves();
+        if replies.is_empty() {
+            self.pv[ply].clear();
+            return pos.is_checkmate();
+        }
+        if moves == 1 {
+            return false;
+        }
+        let mut line = None;
+        for m in replies {
+            let mut child = pos.clone();
+            child.play_unchecked(&m);
+            if !self.attack(&child, moves - 1, ply + 1) {
+                return false;
+            }
+            if line.is_none() {
+                let mut pv = vec![m];
+                pv.extend_from_slice(&self.pv[ply + 1]);
+                line = Some(pv);
+            }
+        }
+        self.pv[ply] = line.unwrap_or_default();
+        true
+    }
+
+    fn print_info(&self, moves: u32) {
+        let millis = self.start.elapsed().as_millis() as u64;
+        let nps = self.nodes * 1000 / millis.max(1);
+        let pv: Vec<String> = self.pv[0]
+            .iter()
+            .map(|m| m.to_uci(self.castling_mode).to_string())
+            .collect();
+        println!(
+            "info depth {} score mate {moves} nodes {} nps {nps} time {millis} pv {}",
+            2 * moves - 1,
+            self.nodes,
+            pv.join(" ")
+        );
+    }
+
+    // Searches for the shortest mate and returns its first move, otherwise
+    // the first legal move, None if there are no legal moves.
+    pub fn run(&mut self) -> Option<Move> {
+        let pos = self.pos.clone();
+        for moves in 1..=self.moves {
+            if self.attack(&pos, moves, 0) {
+                self.print_info(moves);
+                return self.pv[0].first().cloned();
+            }
+            if self.stopped {
+                break;
+            }
+        }
+        if self.stopped {
+            println!("info string no mate found before the search stopped");
+        } else {
+            println!("info string no mate in {}", self.moves);
+        }
+        pos.legal_moves().first().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+
+    fn mate_search(fen: &str, moves: u32) -> MateSearch {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let pos = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        MateSearch::new(
+            pos,
+            moves,
+            Limits::default(),
+            Arc::new(AtomicBool::new(false)),
+            CastlingMode::Standard,
+        )
+    }
+
+    // Whether the line ends in checkmate.
+    fn mates(fen: &str, pv: &[Move]) -> bool {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let mut pos: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        for m in pv {
+            pos = pos.play(m).expect("legal moves");
+        }
+        pos.is_checkmate()
+    }
+
+    #[test]
+    fn mate_in_two() {
+        // The king takes the opposition first, e.g. 1. Kb6 Kb8 2. Rh8#, and
+        // every defence has to be refuted.
+        let fen = "k7/8/2K5/8/8/8/8/7R w - - 0 1";
+        let mut search = mate_search(fen, 3);
+        let pos = search.pos.clone();
+        assert!(!search.attack(&pos, 1, 0));
+        assert!(search.attack(&pos, 2, 0));
+        let pv = search.pv[0].clone();
+        assert_eq!(pv.len(), 3);
+        assert!(mates(fen, &pv));
+
+        // The search reports the shortest mate.
+        let mut search = mate_search(fen, 3);
+        let best = search.run().expect("the position has legal moves");
+        assert_eq!(search.pv[0].first(), Some(&best));
+        assert_eq!(search.pv[0].len(), 3);
+    }
+
+    #[test]
+    fn mate_in_one() {
+        // Scholar's mate in one, the queen takes on f7.
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        let mut search = mate_search(fen, 1);
+        let best = search.run().expect("the position has legal moves");
+        assert_eq!(best.to_uci(CastlingMode::Standard).to_string(), "h5f7");
+        assert!(mates(fen, &search.pv[0]));
+    }
+
+    #[test]
+    fn no_mate() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut search = mate_search(fen, 2);
+        let pos = search.pos.clone();
+        assert!(!search.attack(&pos, 2, 0));
+        // The first legal move is played anyway.
+        assert!(search.run().is_some());
+    }
+}
@@ -0,0 +1,558 @@
+// Monte Carlo tree search guided by a policy and value network, as in lc0,
+// the alternative to the alpha-beta search (the SearchMode UCI option).
+//
+// Every playout walks down the tree from the root, choosing at each node the
+// child maximizing the PUCT score
+//
+//     Q + C_PUCT * P * sqrt(N(parent)) / (1 + N)
+//
+// where Q is the average value of the child for the side choosing it, P its
+// prior probability from the policy and N the number of visits. Children that
+// were not visited yet have the value of the parent reduced by FPU_REDUCTION
+// (first play urgency). The leaf is expanded with the priors of its legal
+// moves, and the value of its position is backed up along the path, negated
+// at every ply. The move played is the most visited child of the root.
+//
+// The leaves are evaluated in minibatches (the MinibatchSize UCI option),
+// which the networks running on accelerators need to be fast. While a leaf
+// waits for its evaluation, its path counts as lost for the following
+// playouts (virtual loss), so that they spread over other leaves. A playout
+// reaching a waiting leaf again ends the minibatch. The network runs behind a
+// batching queue (see batch), which evaluates up to PIPELINE_DEPTH minibatches
+// in the background while the search gathers the next one.
+//
+// The network sees the positions like the lc0 networks the training data comes
+// from: the policy is indexed by `preprocessing::moves` and the value is the
+// expected score of the side to move in [-1, 1], so networks trained on the
+// preprocessed data plug into `Network`. Without one, `HeuristicNetwork`
+// derives both from the static evaluation.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use preprocessing::encoder::HISTORY_LENGTH;
+use preprocessing::moves::idx_from_move;
+use preprocessing::record::POLICY_SIZE;
+use shakmaty::zobrist::Zobrist64;
+use shakmaty::{CastlingMode, Chess, Move, Position};
+
+use crate::batch::{BatchQueue, Pending};
+use crate::eval::Eval;
+use crate::search::{self, MAX_PLY};
+use crate::time::Limits;
+
+const C_PUCT: f32 = 1.745;
+const FPU_REDUCTION: f32 = 0.33;
+// Temperature of the softmax turning the policy logits into priors, as in
+// lc0.
+const POLICY_TEMPERATURE: f32 = 1.359;
+// The tree stops growing at this many nodes, to bound the memory.
+const MAX_NODES: usize = 1 << 23;
+const INFO_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_BATCH_SIZE: usize = 1;
+pub const MAX_BATCH_SIZE: usize = 1024;
+// Minibatches submitted to the queue at the same time.
+const PIPELINE_DEPTH: usize = 2;
+
+// Output of the network for a position: logits of the moves indexed as in the
+// lc0 policy head and the expected score in [-1, 1], both for the side to
+// move.
+pub struct Evaluation {
+    pub policy: Vec<f32>,
+    pub value: f32,
+}
+
+// Evaluation of the positions for the tree search.
+pub trait Network: Send {
+    // Evaluates the last position of every line. The positions before it are
+    // the last ones of the game leading to it, oldest first, at most
+    // HISTORY_LENGTH in total.
+    fn evaluate(&mut self, lines: &[Vec<Chess>]) -> io::Result<Vec<Evaluation>>;
+}
+
+// Converts between centipawns and expected scores with the formula of lc0.
+fn value_from_cp(cp: i32) -> f32 {
+    (cp as f32 / 90.0).atan() / 1.563_754_2
+}
+
+fn cp_from_value(value: f32) -> i32 {
+    (90.0 * (1.563_754_2 * value.clamp(-0.999, 0.999)).tan()) as i32
+}
+
+// The static evaluation as the value and the material won by the captures
+// and promotions as the logits.
+pub struct HeuristicNetwork {
+    eval: Box<dyn Eval>,
+}
+
+impl HeuristicNetwork {
+    pub fn new(eval: Box<dyn Eval>) -> Self {
+        HeuristicNetwork { eval }
+    }
+}
+
+impl Network for HeuristicNetwork {
+    fn evaluate(&mut self, lines: &[Vec<Chess>]) -> io::Result<Vec<Evaluation>> {
+        let evaluate = |pos: &Chess| {
+            let mut policy = vec![0.0; POLICY_SIZE];
+            for m in pos.legal_moves() {
+                if let Some(idx) = idx_from_move(&m, pos.turn()) {
+                    policy[idx as usize] = search::capture_score(&m).max(0) as f32 / 10.0;
+                }
+            }
+            // The positions are not related, there is nothing to update.
+            self.eval.reset(pos);
+            let value = value_from_cp(self.eval.evaluate(pos));
+            Evaluation { policy, value }
+        };
+        Ok(lines
+            .iter()
+            .map(|line| line.last().expect("lines are not empty"))
+            .map(evaluate)
+            .collect())
+    }
+}
+
+struct Node {
+    // The move from the parent, None at the root.
+    m: Option<Move>,
+    prior: f32,
+    visits: u32,
+    // Playouts through the node waiting for the evaluation of their leaf.
+    in_flight: u32,
+    // Sum of the values backed up through the node, for the side that played
+    // the move.
+    value: f32,
+    // The children are stored contiguously, there are none before the
+    // expansion.
+    first_child: usize,
+    children: usize,
+    // Value of a finished game for the side to move.
+    terminal: Option<f32>,
+}
+
+impl Node {
+    fn new(m: Option<Move>, prior: f32) -> Self {
+        Node {
+            m,
+            prior,
+            visits: 0,
+            in_flight: 0,
+            value: 0.0,
+            first_child: 0,
+            children: 0,
+            terminal: None,
+        }
+    }
+
+    fn q(&self) -> f32 {
+        self.value / self.visits.max(1) as f32
+    }
+}
+
+// A playout waiting for the evaluation of its leaf, the last node of the path
+// and the last position of the line.
+struct Leaf {
+    path: Vec<usize>,
+    line: Vec<Chess>,
+}
+
+// A minibatch submitted to the queue, the paths to the leaves and the
+// positions of the leaves.
+struct Submitted {
+    paths: Vec<Vec<usize>>,
+    positions: Vec<Chess>,
+    pending: Pending,
+}
+
+enum Descent {
+    Leaf(Leaf),
+    // The playout ended in a finished game and was backed up.
+    Terminal,
+    // The leaf is already waiting for its evaluation.
+    Collision,
+}
+
+pub struct Mcts {
+    pos: Chess,
+    // Hashes of the positions of the game, the searched position last.
+    history: Vec<Zobrist64>,
+    // The last positions of the game for the history of the network.
+    line: Vec<Chess>,
+    limits: Limits,
+    stop: Arc<AtomicBool>,
+    castling_mode: CastlingMode,
+    queue: BatchQueue,
+    batch_size: usize,
+    start: Instant,
+    nodes: Vec<Node>,
+    playouts: u64,
+    // Sum and maximum of the depths of the playouts.
+    total_depth: u64,
+    max_depth: usize,
+}
+
+impl Mcts {
+    // `game` has the positions of the game, the searched position last, the
+    // queue evaluates them with the network.
+    pub fn new(
+        game: &[Chess],
+        limits: Limits,
+        stop: Arc<AtomicBool>,
+        castling_mode: CastlingMode,
+        queue: BatchQueue,
+    ) -> Self {
+        let pos = game.last().expect("the game has a position").clone();
+        let line = game[game.len().saturating_sub(HISTORY_LENGTH)..].to_vec();
+        Mcts {
+            pos,
+            history: game.iter().map(search::hash).collect(),
+            line,
+            limits,
+            stop,
+            castling_mode,
+            queue,
+            batch_size: DEFAULT_BATCH_SIZE,
+            start: Instant::now(),
+            nodes: vec![Node::new(None, 1.0)],
+            playouts: 0,
+            total_depth: 0,
+            max_depth: 0,
+        }
+    }
+
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.clamp(1, MAX_BATCH_SIZE);
+    }
+
+    fn average_depth(&self) -> u64 {
+        self.total_depth / self.playouts.max(1)
+    }
+
+    // `go depth` limits the average depth of the playouts.
+    fn limit_reached(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self.nodes.len() >= MAX_NODES
+            || self
+                .limits
+                .nodes
+                .is_some_and(|nodes| self.playouts >= nodes)
+            || self
+                .limits
+                .soft
+                .or(self.limits.time)
+                .is_some_and(|time| self.start.elapsed() >= time)
+            || self
+                .limits
+                .depth
+                .is_some_and(|depth| self.average_depth() >= depth as u64)
+    }
+
+    // The child of the node with the highest PUCT score, the playouts in
+    // flight count as visits with a loss.
+    fn select(&self, idx: usize) -> usize {
+        let node = &self.nodes[idx];
+        let sqrt_visits = ((node.visits + node.in_flight) as f32).sqrt();
+        // The value of the parent for the side choosing the child.
+        let fpu = -node.q() - FPU_REDUCTION;
+        let children = node.first_child..node.first_child + node.children;
+        let score = |child: &Node| {
+            let visits = child.visits + child.in_flight;
+            let q = if visits == 0 {
+                fpu
+            } else {
+                (child.value - child.in_flight as f32) / visits as f32
+            };
+            q + C_PUCT * child.prior * sqrt_visits / (1 + visits) as f32
+        };
+        children
+            .max_by(|&a, &b| score(&self.nodes[a]).total_cmp(&score(&self.nodes[b])))
+            .expect("expanded nodes have children")
+    }
+
+    // Value of the position of the node for the side to move if the game is
+    // over.
+    fn terminal(&mut self, idx: usize, pos: &Chess, history: &[Zobrist64]) -> Option<f32> {
+        if self.nodes[idx].terminal.is_none() && self.nodes[idx].children == 0 {
+            self.nodes[idx].terminal = if pos.legal_moves().is_empty() {
+                Some(if pos.is_check() { -1.0 } else { 0.0 })
+            } else if idx != 0 && search::is_draw(pos, history) {
+                Some(0.0)
+            } else {
+                None
+            };
+        }
+        self.nodes[idx].terminal
+    }
+
+    // Walks down the tree to a leaf and marks its path as in flight.
+    fn descend(&mut self) -> Descent {
+        let mut pos = self.pos.clone();
+        let mut history = self.history.clone();
+        let mut line = self.line.clone();
+        let mut path = vec![0];
+        let mut idx = 0;
+        while self.nodes[idx].children > 0 && path.len() <= MAX_PLY {
+            idx = self.select(idx);
+            let m = self.nodes[idx].m.as_ref().expect("children have moves");
+            pos.play_unchecked(m);
+            history.push(search::hash(&pos));
+            if line.len() == HISTORY_LENGTH {
+                line.remove(0);
+            }
+            line.push(pos.clone());
+            path.push(idx);
+        }
+        if let Some(value) = self.terminal(idx, &pos, &history) {
+            self.backup(&path, value);
+            return Descent::Terminal;
+        }
+        if self.nodes[idx].in_flight > 0 {
+            return Descent::Collision;
+        }
+        for &idx in &path {
+            self.nodes[idx].in_flight += 1;
+        }
+        Descent::Leaf(Leaf { path, line })
+    }
+
+    // Adds the children of the node with the priors of the policy. Playouts
+    // reaching the maximum ply end at expanded nodes, which stay as they are.
+    fn expand(&mut self, idx: usize, pos: &Chess, policy: &[f32]) {
+        if self.nodes[idx].children > 0 {
+            return;
+        }
+        let moves = pos.legal_moves();
+        let logits: Vec<f32> = moves
+            .iter()
+            .map(|m| {
+                idx_from_move(m, pos.turn()).map_or(f32::NEG_INFINITY, |idx| {
+                    policy[idx as usize] / POLICY_TEMPERATURE
+                })
+            })
+            .collect();
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let weights: Vec<f32> = logits.iter().map(|logit| (logit - max).exp()).collect();
+        let sum: f32 = weights.iter().sum();
+        let first_child = self.nodes.len();
+        for (m, weight) in moves.into_iter().zip(weights) {
+            self.nodes.push(Node::new(Some(m), weight / sum));
+        }
+        let children = self.nodes.len() - first_child;
+        let node = &mut self.nodes[idx];
+        node.first_child = first_child;
+        node.children = children;
+    }
+
+    // Backs up the value of the leaf for its side to move along the path.
+    fn backup(&mut self, path: &[usize], mut value: f32) {
+        // Every node keeps the value for the side that moved into it.
+        for &idx in path.iter().rev() {
+            value = -value;
+            let node = &mut self.nodes[idx];
+            node.visits += 1;
+            node.value += value;
+        }
+        self.playouts += 1;
+        self.total_depth += path.len() as u64 - 1;
+        self.max_depth = self.max_depth.max(path.len() - 1);
+    }
+
+    // Waits for the evaluations of the minibatch, expands the leaves with
+    // them and backs up the values.
+    fn apply(&mut self, submitted: Submitted) -> io::Result<()> {
+        let evaluations = submitted.pending.wait()?;
+        let leaves = submitted.paths.iter().zip(&submitted.positions);
+        for ((path, pos), evaluation) in leaves.zip(evaluations) {
+            let leaf = *path.last().expect("paths start at the root");
+            self.expand(leaf, pos, &evaluation.policy);
+            for &idx in path {
+                self.nodes[idx].in_flight -= 1;
+            }
+            self.backup(path, evaluation.value);
+        }
+        Ok(())
+    }
+
+    // Submits the leaves to the queue and waits for the oldest minibatch once
+    // PIPELINE_DEPTH are submitted or no new leaves were found. `flush` waits
+    // for all minibatches.
+    fn evaluate(
+        &mut self,
+        leaves: Vec<Leaf>,
+        submitted: &mut VecDeque<Submitted>,
+        flush: bool,
+    ) -> io::Result<()> {
+        let (paths, lines): (Vec<_>, Vec<_>) = leaves
+            .into_iter()
+            .map(|leaf| (leaf.path, leaf.line))
+            .unzip();
+        let found = !paths.is_empty();
+        if found {
+            let positions = lines
+                .iter()
+                .map(|line| line.last().expect("lines are not empty").clone())
+                .collect();
+            submitted.push_back(Submitted {
+                paths,
+                positions,
+                pending: self.queue.submit(lines),
+            });
+        }
+        while flush || !found || submitted.len() >= PIPELINE_DEPTH {
+            let Some(oldest) = submitted.pop_front() else {
+                break;
+            };
+            self.apply(oldest)?;
+            if !flush {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // The most visited child of the node.
+    fn best_child(&self, idx: usize) -> Option<usize> {
+        let node = &self.nodes[idx];
+        (node.first_child..node.first_child + node.children)
+            .max_by_key(|&child| self.nodes[child].visits)
+    }
+
+    fn print_info(&self) {
+        let mut pv = Vec::new();
+        let mut idx = 0;
+        while let Some(child) = self
+            .best_child(idx)
+            .filter(|&child| self.nodes[child].visits > 0)
+        {
+            let m = self.nodes[child].m.as_ref().expect("children have moves");
+            pv.push(m.to_uci(self.castling_mode).to_string());
+            idx = child;
+        }
+        let score = self
+            .best_child(0)
+            .map_or(0, |child| cp_from_value(self.nodes[child].q()));
+        let millis = self.start.elapsed().as_millis() as u64;
+        let nps = self.playouts * 1000 / millis.max(1);
+        println!(
+            "info depth {} seldepth {} score cp {score} nodes {} nps {nps} time {millis} pv {}",
+            self.average_depth(),
+            self.max_depth,
+            self.playouts,
+            pv.join(" ")
+        );
+    }
+
+    // Runs playouts until a limit is reached, printing `info` lines along the
+    // way. Returns the best move, None if there are no legal moves or the
+    // network failed before the root was expanded.
+    pub fn run(&mut self) -> Option<Move> {
+        if self.pos.legal_moves().is_empty() {
+            return None;
+        }
+        let mut last_info = Instant::now();
+        let mut submitted = VecDeque::new();
+        // The first minibatch expands the root.
+        loop {
+            let mut leaves = Vec::new();
+            while leaves.len() < self.batch_size {
+                match self.descend() {
+                    Descent::Leaf(leaf) => leaves.push(leaf),
+                    Descent::Terminal => {}
+                    Descent::Collision => break,
+                }
+                if self.limit_reached() {
+                    break;
+                }
+            }
+            let stop = self.limit_reached();
+            if let Err(err) = self.evaluate(leaves, &mut submitted, stop) {
+                println!("info string failed to evaluate the positions: {err}");
+                break;
+            }
+            if last_info.elapsed() >= INFO_INTERVAL {
+                self.print_info();
+                last_info = Instant::now();
+            }
+            if stop {
+                break;
+            }
+        }
+        self.print_info();
+        let best = self.best_child(0)?;
+        self.nodes[best].m.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+    use crate::eval::HandCrafted;
+
+    // Runs the playouts from the position, returns the best move in UCI and
+    // the tree.
+    fn search(fen: &str, playouts: u64) -> (Option<String>, Mcts) {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let pos: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        let limits = Limits {
+            nodes: Some(playouts),
+            ..Limits::default()
+        };
+        let mut mcts = Mcts::new(
+            &[pos],
+            limits,
+            Arc::new(AtomicBool::new(false)),
+            CastlingMode::Standard,
+            BatchQueue::new(Box::new(HeuristicNetwork::new(Box::new(
+                HandCrafted::default(),
+            )))),
+        );
+        let best = mcts
+            .run()
+            .map(|m| m.to_uci(CastlingMode::Standard).to_string());
+        (best, mcts)
+    }
+
+    #[test]
+    fn playouts_visit_the_root() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let (best, mcts) = search(fen, 200);
+        assert!(best.is_some());
+        // The minibatches in flight finish after the limit.
+        assert!(mcts.playouts >= 200);
+        let root = &mcts.nodes[0];
+        assert_eq!(root.visits as u64, mcts.playouts);
+        assert_eq!(root.children, 20);
+        // The first playout expands the root, the others pass a child.
+        let children = root.first_child..root.first_child + root.children;
+        let visits: u32 = children.map(|child| mcts.nodes[child].visits).sum();
+        assert_eq!(visits, root.visits - 1);
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        // The queen mates on the back rank.
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/3Q2K1 w - - 0 1";
+        assert_eq!(search(fen, 500).0.as_deref(), Some("d1d8"));
+    }
+
+    #[test]
+    fn takes_hanging_queen() {
+        let fen = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        assert_eq!(search(fen, 500).0.as_deref(), Some("d1d5"));
+    }
+
+    #[test]
+    fn no_moves() {
+        // Checkmate.
+        let fen = "R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1";
+        assert_eq!(search(fen, 10).0, None);
+    }
+}
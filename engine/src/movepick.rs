@@ -0,0 +1,136 @@
+// Static exchange evaluation (SEE): the material a move wins or loses once
+// both sides have made all the captures on its destination square that pay
+// off, taking the square with their least valuable piece first. Each side may
+// stop capturing when going on would lose material, and sliders behind the
+// capturing pieces join in as the square opens up (x-rays). The king only
+// captures if the opponent has no attacker of the square left.
+//
+// The search orders the captures that lose material after the quiet moves and
+// skips them in the quiescence search. Pins, checks and promotions during the
+// exchange are not considered.
+
+use shakmaty::{Bitboard, Chess, Color, Move, Position, Role, Square};
+
+// Values of the pieces in centipawns, the king is never traded.
+fn see_value(role: Role) -> i32 {
+    match role {
+        Role::Pawn => 100,
+        Role::Knight => 320,
+        Role::Bishop => 330,
+        Role::Rook => 500,
+        Role::Queen => 900,
+        Role::King => 20_000,
+    }
+}
+
+// The least valuable piece of the side attacking the square through the
+// occupied squares.
+fn least_valuable_attacker(
+    pos: &Chess,
+    square: Square,
+    side: Color,
+    occupied: Bitboard,
+) -> Option<(Square, Role)> {
+    let board = pos.board();
+    let attackers = board.attacks_to(square, side, occupied) & occupied;
+    Role::ALL.into_iter().find_map(|role| {
+        let square = (attackers & board.by_role(role)).first()?;
+        Some((square, role))
+    })
+}
+
+// The material the move wins for the side to move in centipawns, negative if
+// it loses material, 0 for castling.
+pub fn see(pos: &Chess, m: &Move) -> i32 {
+    let (Some(from), to) = (m.from(), m.to()) else {
+        return 0;
+    };
+    if m.is_castle() {
+        return 0;
+    }
+    let mut occupied = pos.board().occupied() ^ from;
+    let mut victim = m.capture().map_or(0, see_value);
+    if m.is_en_passant() {
+        occupied ^= Square::from_coords(to.file(), from.rank());
+    }
+    if let Some(promotion) = m.promotion() {
+        victim += see_value(promotion) - see_value(Role::Pawn);
+    }
+    // The material won by the side making each capture of the exchange if
+    // it were the last one.
+    let mut gains = vec![victim];
+    let mut on_square = see_value(m.promotion().unwrap_or(m.role()));
+    let mut side = !pos.turn();
+    while let Some((square, role)) = least_valuable_attacker(pos, to, side, occupied) {
+        if role == Role::King
+            && least_valuable_attacker(pos, to, !side, occupied ^ square).is_some()
+        {
+            break;
+        }
+        let gain = on_square - gains.last().expect("the move is a gain");
+        gains.push(gain);
+        on_square = see_value(role);
+        occupied ^= square;
+        side = !side;
+    }
+    // Every side stops capturing when that is better for it.
+    for idx in (1..gains.len()).rev() {
+        let opponent_best = gains[idx].max(-gains[idx - 1]);
+        gains[idx - 1] = -opponent_best;
+    }
+    gains[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+    use shakmaty::san::San;
+    use shakmaty::CastlingMode;
+
+    use super::*;
+
+    fn see_of(fen: &str, san: &str) -> i32 {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let pos: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        let m = san
+            .parse::<San>()
+            .expect("valid SAN")
+            .to_move(&pos)
+            .expect("legal move");
+        see(&pos, &m)
+    }
+
+    #[test]
+    fn undefended_capture() {
+        assert_eq!(see_of("4k3/8/8/3p4/8/8/8/3RK3 w - - 0 1", "Rxd5"), 100);
+    }
+
+    #[test]
+    fn defended_capture() {
+        assert_eq!(see_of("4k3/4p3/3p4/8/8/8/8/3RK3 w - - 0 1", "Rxd6"), -400);
+        assert_eq!(see_of("4k3/8/2p5/3n4/4P3/8/8/4K3 w - - 0 1", "exd5"), 220);
+    }
+
+    #[test]
+    fn x_ray() {
+        assert_eq!(see_of("3rk3/8/3p4/8/8/8/3R4/3RK3 w - - 0 1", "Rxd6"), 100);
+    }
+
+    #[test]
+    fn en_passant() {
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6"), 100);
+    }
+
+    #[test]
+    fn king_does_not_capture_defended_piece() {
+        assert_eq!(see_of("4k3/3p4/8/8/8/8/3R4/3QK3 w - - 0 1", "Rxd7"), 100);
+    }
+
+    #[test]
+    fn quiet_move_to_attacked_square() {
+        assert_eq!(see_of("4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1", "Qd5"), -900);
+        assert_eq!(see_of("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "Qd5"), 0);
+    }
+}
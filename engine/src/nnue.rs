@@ -0,0 +1,443 @@
+// NNUE evaluation with the quantized networks of the quantize command, see
+// preprocessing::nnue for the features and the layers.
+//
+// The accumulators (the outputs of the feature transformer) of both
+// perspectives are kept on a stack with an entry for every position of the
+// searched line. A move only changes the features of the few squares it
+// touches, so the accumulator of the child is the one of the parent with the
+// weights of these features subtracted and added. Only the moves of a king
+// change all features of its perspective. Its accumulator is then computed
+// from the one of the last position with the king on the same square, kept in
+// a refresh cache, updated with the features of the squares whose pieces
+// differ, which are fewer than all features of the position. The evaluations
+// of the positions are cached by their hash (see cache).
+
+use std::f32::consts::LN_10;
+use std::io;
+use std::sync::Arc;
+
+use preprocessing::network;
+use preprocessing::nnue::{FeatureSet, Network};
+use shakmaty::{Bitboard, Board, Chess, Color, Position, Role, Square};
+
+use crate::cache::{Cache, CacheStats};
+use crate::eval::Eval;
+use crate::search::{self, MATE, MAX_PLY};
+
+// The network predicts the logit of the expected score, which is converted to
+// centipawns with the scale of the tune command: 1 / (1 + 10^(-cp / SCALE)).
+pub const SCALE: f32 = 400.0;
+// Features changing with a move per perspective: castling moves two pieces.
+const MAX_CHANGED: usize = 4;
+const EVAL_CACHE_SIZE: usize = 1 << 13;
+
+// The network embedded into the binary with the embedded-net feature, the
+// file nets/default.attix of the engine crate, see the README for how it was
+// trained.
+#[cfg(feature = "embedded-net")]
+const EMBEDDED_NETWORK: &[u8] =
+    include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/nets/default.attix"));
+
+// The value of EvalFile selecting the embedded network, its default if there
+// is one.
+pub const EMBEDDED: &str = "embedded";
+pub const DEFAULT_EVAL_FILE: &str = if cfg!(feature = "embedded-net") {
+    EMBEDDED
+} else {
+    ""
+};
+
+pub struct Nnue {
+    network: Arc<Network>,
+    // Accumulators of the white and black perspectives of the positions of
+    // the searched line, the first `len` are in use, the last of them belongs
+    // to the current position. The entries are reused between lines.
+    stack: Vec<[Vec<i16>; 2]>,
+    len: usize,
+    // Boards and accumulators of the last positions refreshed for every
+    // perspective and square of its king, indexed by `perspective * 64 +
+    // king`, and the features changed since them.
+    refreshes: Vec<Option<(Board, Vec<i16>)>>,
+    refresh_stats: CacheStats,
+    added: Vec<u32>,
+    removed: Vec<u32>,
+    evals: Cache<u64, i32>,
+}
+
+// Squares whose pieces differ between the boards.
+fn changed_squares(parent: &Board, child: &Board) -> Bitboard {
+    let colors = Color::ALL
+        .into_iter()
+        .map(|color| parent.by_color(color) ^ child.by_color(color));
+    let roles = Role::ALL
+        .into_iter()
+        .map(|role| parent.by_role(role) ^ child.by_role(role));
+    colors
+        .chain(roles)
+        .fold(Bitboard::EMPTY, |acc, bb| acc | bb)
+}
+
+// The embedded network, checked like the files when it is read.
+#[cfg(feature = "embedded-net")]
+pub fn embedded() -> Option<Network> {
+    Some(network::read(EMBEDDED_NETWORK).expect("the embedded network is valid"))
+}
+
+#[cfg(not(feature = "embedded-net"))]
+pub fn embedded() -> Option<Network> {
+    None
+}
+
+// The network of a value of EvalFile: none for "" (the hand-crafted
+// evaluation), the embedded network or the network of the file. Files of
+// another version of the format or with layers that do not fit together are
+// rejected (see preprocessing::network), whatever their extension. The files
+// are either in the attix format or HalfKP .nnue files of Stockfish 12.
+pub fn load(eval_file: &str) -> io::Result<Option<Network>> {
+    match eval_file {
+        "" => Ok(None),
+        EMBEDDED => embedded().map(Some).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "the engine is built without an embedded network",
+            )
+        }),
+        path => network::load(path).map(Some),
+    }
+}
+
+// The architecture of the network: the feature set and the sizes of the
+// layers, e.g. "HalfKP 2x256-32-32-1".
+pub fn architecture(network: &Network) -> String {
+    let set = match network.transformer.set() {
+        FeatureSet::HalfKp => "HalfKP",
+        FeatureSet::HalfKaV2Hm => "HalfKAv2_hm",
+    };
+    let mut architecture = format!("{set} 2x{}", network.transformer.size());
+    for layer in &network.layers {
+        architecture += &format!("-{}", layer.outputs);
+    }
+    architecture
+}
+
+// Centipawns of the logit predicted by the network, below the mate scores.
+pub fn centipawns(logit: f32) -> i32 {
+    let max = MATE - MAX_PLY as i32 - 1;
+    ((logit * SCALE / LN_10) as i32).clamp(-max, max)
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>) -> Self {
+        Nnue {
+            network,
+            stack: Vec::new(),
+            len: 0,
+            refreshes: vec![None; 2 * 64],
+            refresh_stats: CacheStats::default(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            evals: Cache::new(EVAL_CACHE_SIZE),
+        }
+    }
+
+    fn refresh(&self, board: &Board) -> [Vec<i16>; 2] {
+        let transformer = &self.network.transformer;
+        Color::ALL.map(|perspective| transformer.refresh(board, perspective))
+    }
+
+    // Computes the accumulator of the perspective whose king moved into `to`
+    // from the refresh cache, or from scratch if its king was never on the
+    // square.
+    fn refresh_perspective(
+        &mut self,
+        board: &Board,
+        perspective: Color,
+        king: Square,
+        to: &mut [i16],
+    ) {
+        let transformer = &self.network.transformer;
+        let set = transformer.set();
+        let entry = &mut self.refreshes[perspective as usize * 64 + king as usize];
+        self.refresh_stats.probes += 1;
+        match entry {
+            Some((cached, accumulator)) => {
+                self.refresh_stats.hits += 1;
+                self.added.clear();
+                self.removed.clear();
+                for square in changed_squares(cached, board) {
+                    let feature = |piece| set.feature(perspective, king, piece, square);
+                    self.removed
+                        .extend(cached.piece_at(square).and_then(feature));
+                    self.added.extend(board.piece_at(square).and_then(feature));
+                }
+                transformer.update(accumulator, to, &self.added, &self.removed);
+                cached.clone_from(board);
+                accumulator.copy_from_slice(to);
+            }
+            None => {
+                to.copy_from_slice(&transformer.refresh(board, perspective));
+                *entry = Some((board.clone(), to.to_vec()));
+            }
+        }
+    }
+}
+
+impl Eval for Nnue {
+    fn reset(&mut self, pos: &Chess) {
+        let accumulators = self.refresh(pos.board());
+        self.stack.truncate(1);
+        match self.stack.first_mut() {
+            Some(entry) => *entry = accumulators,
+            None => self.stack.push(accumulators),
+        }
+        self.len = 1;
+    }
+
+    fn push(&mut self, parent: &Chess, child: &Chess) {
+        let (parent, child) = (parent.board(), child.board());
+        if self.stack.len() == self.len {
+            let size = self.network.transformer.size();
+            self.stack.push([vec![0; size], vec![0; size]]);
+        }
+        let changed = changed_squares(parent, child);
+        for (idx, perspective) in Color::ALL.into_iter().enumerate() {
+            let king = child.king_of(perspective);
+            let (Some(king), true) = (king, king == parent.king_of(perspective)) else {
+                let mut accumulator = std::mem::take(&mut self.stack[self.len][idx]);
+                match king {
+                    Some(king) => {
+                        self.refresh_perspective(child, perspective, king, &mut accumulator)
+                    }
+                    None => accumulator = self.network.transformer.refresh(child, perspective),
+                }
+                self.stack[self.len][idx] = accumulator;
+                continue;
+            };
+            let transformer = &self.network.transformer;
+            let set = transformer.set();
+            let (mut added, mut removed) = ([0; MAX_CHANGED], [0; MAX_CHANGED]);
+            let (mut num_added, mut num_removed) = (0, 0);
+            for square in changed {
+                let feature = |piece| set.feature(perspective, king, piece, square);
+                if let Some(feature) = parent.piece_at(square).and_then(feature) {
+                    removed[num_removed] = feature;
+                    num_removed += 1;
+                }
+                if let Some(feature) = child.piece_at(square).and_then(feature) {
+                    added[num_added] = feature;
+                    num_added += 1;
+                }
+            }
+            let (done, rest) = self.stack.split_at_mut(self.len);
+            transformer.update(
+                &done[self.len - 1][idx],
+                &mut rest[0][idx],
+                &added[..num_added],
+                &removed[..num_removed],
+            );
+        }
+        self.len += 1;
+    }
+
+    fn pop(&mut self) {
+        self.len -= 1;
+    }
+
+    fn evaluate(&mut self, pos: &Chess) -> i32 {
+        let [white, black] = &self.stack[self.len - 1];
+        let (us, them) = match pos.turn() {
+            Color::White => (white, black),
+            Color::Black => (black, white),
+        };
+        let hash = search::hash(pos).0;
+        if let Some(eval) = self.evals.get(hash, &hash) {
+            return eval;
+        }
+        let eval = centipawns(self.network.forward(us, them));
+        self.evals.insert(hash, hash, eval);
+        eval
+    }
+
+    fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        vec![
+            ("NNUE eval", self.evals.stats()),
+            ("NNUE refresh", self.refresh_stats),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shakmaty::fen::Fen;
+    use shakmaty::CastlingMode;
+
+    fn evaluate(network: &Arc<Network>, fen: &str) -> i32 {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let pos: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        let mut nnue = Nnue::new(Arc::clone(network));
+        nnue.reset(&pos);
+        nnue.evaluate(&pos)
+    }
+
+    // A HalfKP 2x256-32-32-1 network of Stockfish 12 with pseudo-random
+    // weights: the biases and the weights of the feature transformer and of
+    // the dense layers.
+    struct Stockfish {
+        biases: Vec<i16>,
+        weights: Vec<i16>,
+        layers: Vec<(Vec<i32>, Vec<i8>)>,
+    }
+
+    impl Stockfish {
+        fn new() -> Self {
+            let mut state = 1u64;
+            let mut next = |range: i64| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 33) as i64 % (2 * range + 1) - range
+            };
+            let dimensions = FeatureSet::HalfKp.dimensions();
+            let biases = (0..256).map(|_| (next(32) + 32) as i16).collect();
+            let weights = (0..dimensions * 256).map(|_| next(8) as i16).collect();
+            let layers = [(512, 32, 8), (32, 32, 32), (32, 1, 127)]
+                .into_iter()
+                .map(|(inputs, outputs, range)| {
+                    let biases = (0..outputs).map(|_| next(1000) as i32).collect();
+                    let weights = (0..inputs * outputs).map(|_| next(range) as i8).collect();
+                    (biases, weights)
+                })
+                .collect();
+            Stockfish {
+                biases,
+                weights,
+                layers,
+            }
+        }
+
+        // The .nnue file with the version and the hash of the architecture.
+        fn bytes(&self, version: u32, hash: u32) -> Vec<u8> {
+            let description = b"random weights";
+            let mut bytes = Vec::new();
+            bytes.extend(version.to_le_bytes());
+            bytes.extend(hash.to_le_bytes());
+            bytes.extend((description.len() as u32).to_le_bytes());
+            bytes.extend(description);
+            bytes.extend(0x5D69_D7B8u32.to_le_bytes());
+            for value in self.biases.iter().chain(&self.weights) {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes.extend(0x6333_7156u32.to_le_bytes());
+            for (biases, weights) in &self.layers {
+                for bias in biases {
+                    bytes.extend(bias.to_le_bytes());
+                }
+                bytes.extend(weights.iter().map(|&weight| weight as u8));
+            }
+            bytes
+        }
+
+        // The evaluation of Stockfish in centipawns.
+        fn evaluate(&self, pos: &Chess) -> f32 {
+            let accumulator = |perspective| {
+                let mut accumulator: Vec<i32> = self.biases.iter().map(|&b| b as i32).collect();
+                for feature in FeatureSet::HalfKp.active_features(pos.board(), perspective) {
+                    let offset = feature as usize * 256;
+                    for (value, weight) in accumulator.iter_mut().zip(&self.weights[offset..]) {
+                        *value += *weight as i32;
+                    }
+                }
+                accumulator
+            };
+            let mut x: Vec<i32> = [pos.turn(), !pos.turn()]
+                .into_iter()
+                .flat_map(accumulator)
+                .map(|value| value.clamp(0, 127))
+                .collect();
+            for (idx, (biases, weights)) in self.layers.iter().enumerate() {
+                x = weights
+                    .chunks_exact(x.len())
+                    .zip(biases)
+                    .map(|(weights, bias)| {
+                        let products = x.iter().zip(weights).map(|(&x, &w)| x * w as i32);
+                        bias + products.sum::<i32>()
+                    })
+                    .map(|value| match idx {
+                        2 => value,
+                        _ => (value >> 6).clamp(0, 127),
+                    })
+                    .collect();
+            }
+            x[0] as f32 / 16.0 * 100.0 / 208.0
+        }
+    }
+
+    #[test]
+    fn stockfish_networks() {
+        let stockfish = Stockfish::new();
+        let path = std::env::temp_dir().join(format!("attix-{}.nnue", std::process::id()));
+        std::fs::write(&path, stockfish.bytes(0x7AF3_2F16, 0x3E5A_A6EE)).expect("writable");
+        let network = load(path.to_str().expect("UTF-8 path"))
+            .expect("a valid network")
+            .expect("a network");
+        assert_eq!(architecture(&network), "HalfKP 2x256-32-32-1");
+        let network = Arc::new(network);
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/3Q2K1 b - - 0 1",
+        ] {
+            let pos: Chess = fen
+                .parse::<Fen>()
+                .expect("valid FEN")
+                .into_position(CastlingMode::Standard)
+                .expect("legal position");
+            let (eval, expected) = (evaluate(&network, fen), stockfish.evaluate(&pos));
+            assert!(
+                (eval as f32 - expected).abs() < 5.0,
+                "{fen}: {eval} {expected}"
+            );
+        }
+
+        for (version, hash, error) in [
+            (
+                0x7AF3_2F20,
+                0x3E5A_A6EE,
+                "unsupported Stockfish network version 0x7af32f20, expected 0x7af32f16",
+            ),
+            (
+                0x7AF3_2F16,
+                0x3E5A_A6EF,
+                "architecture hash 0x3e5aa6ef is not the one of HalfKP 2x256-32-32-1 (0x3e5aa6ee)",
+            ),
+            (0x1234_5678, 0, "not an attix or Stockfish network"),
+        ] {
+            std::fs::write(&path, stockfish.bytes(version, hash)).expect("writable");
+            let err = load(path.to_str().expect("UTF-8 path"))
+                .err()
+                .expect("an error");
+            assert_eq!(err.to_string(), format!("{}: {error}", path.display()));
+        }
+        std::fs::remove_file(&path).expect("removable");
+    }
+
+    #[test]
+    fn default_network() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/nets/default.attix");
+        let network = Arc::new(network::load(path).expect("the default network is valid"));
+        assert_eq!(architecture(&network), "HalfKAv2_hm 2x32-32-32-1");
+
+        let startpos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let eval = evaluate(&network, startpos);
+        assert!((-50..50).contains(&eval), "{eval}");
+        let queen_odds = "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1";
+        let eval = evaluate(&network, queen_odds);
+        assert!(eval < -150, "{eval}");
+    }
+}
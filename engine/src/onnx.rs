@@ -0,0 +1,156 @@
+// Evaluation of lc0 networks converted to ONNX (e.g. with `lc0 leela2onnx`)
+// through ONNX Runtime, for the tree search. Built with the onnx feature and
+// loaded with the WeightsFile UCI option.
+//
+// The positions are encoded with preprocessing::encoder, which produces the
+// classical input format of lc0 (112 planes), so only the networks trained
+// with that format work. The graph takes the planes as its first input,
+// [batch, 112, 8, 8], and gives the policy logits [batch, 1858] and either the
+// win, draw and loss probabilities [batch, 3] or the value [batch, 1]:
+//
+//     /input/planes -> /output/policy, /output/wdl or /output/value
+//
+// The outputs are looked up by these names, which leela2onnx uses by default.
+//
+// Built with the cuda feature, the network runs on the GPU with the CUDA
+// execution provider of ONNX Runtime (the Backend UCI option).
+
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "cuda")]
+use ort::execution_providers::CUDAExecutionProvider;
+use ort::session::builder::SessionBuilder;
+use ort::session::Session;
+use ort::value::Tensor;
+use preprocessing::encoder::{encode, NUM_NETWORK_PLANES};
+use preprocessing::record::POLICY_SIZE;
+use shakmaty::Chess;
+
+use crate::mcts::{Evaluation, Network};
+
+const POLICY_OUTPUT: &str = "/output/policy";
+const WDL_OUTPUT: &str = "/output/wdl";
+const VALUE_OUTPUT: &str = "/output/value";
+
+fn onnx_error(err: ort::Error) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Cpu,
+    #[cfg(feature = "cuda")]
+    Cuda,
+}
+
+impl Backend {
+    fn configure(self, builder: SessionBuilder) -> ort::Result<SessionBuilder> {
+        match self {
+            Backend::Cpu => Ok(builder),
+            // Fails instead of falling back to the CPU silently.
+            #[cfg(feature = "cuda")]
+            Backend::Cuda => builder.with_execution_providers([CUDAExecutionProvider::default()
+                .build()
+                .error_on_failure()]),
+        }
+    }
+}
+
+// The value head of the network.
+#[derive(Debug, Clone, Copy)]
+enum ValueHead {
+    Wdl,
+    Value,
+}
+
+pub struct OnnxNetwork {
+    session: Session,
+    input: String,
+    value_head: ValueHead,
+}
+
+impl OnnxNetwork {
+    pub fn load(path: &Path, backend: Backend) -> io::Result<Self> {
+        let session = Session::builder()
+            .and_then(|builder| backend.configure(builder))
+            .and_then(|builder| builder.commit_from_file(path))
+            .map_err(onnx_error)?;
+        let input = session
+            .inputs
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the model has no inputs"))?
+            .name
+            .clone();
+        let has_output = |name: &str| session.outputs.iter().any(|output| output.name == name);
+        let value_head = match (has_output(WDL_OUTPUT), has_output(VALUE_OUTPUT)) {
+            (true, _) => ValueHead::Wdl,
+            (false, true) => ValueHead::Value,
+            (false, false) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the model has neither {WDL_OUTPUT} nor {VALUE_OUTPUT}"),
+                ))
+            }
+        };
+        if !has_output(POLICY_OUTPUT) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the model has no {POLICY_OUTPUT}"),
+            ));
+        }
+        Ok(OnnxNetwork {
+            session,
+            input,
+            value_head,
+        })
+    }
+}
+
+impl Network for OnnxNetwork {
+    fn evaluate(&mut self, lines: &[Vec<Chess>]) -> io::Result<Vec<Evaluation>> {
+        let planes: Vec<f32> = lines
+            .iter()
+            .flat_map(|line| {
+                let (pos, history) = line.split_last().expect("lines are not empty");
+                encode(pos, history).to_dense()
+            })
+            .collect();
+        let input = Tensor::from_array(([lines.len(), NUM_NETWORK_PLANES, 8, 8], planes))
+            .map_err(onnx_error)?;
+        let inputs = ort::inputs![self.input.as_str() => input].map_err(onnx_error)?;
+        let outputs = self.session.run(inputs).map_err(onnx_error)?;
+
+        let (_, policy) = outputs[POLICY_OUTPUT]
+            .try_extract_raw_tensor::<f32>()
+            .map_err(onnx_error)?;
+        let values: Vec<f32> = match self.value_head {
+            ValueHead::Wdl => {
+                let (_, wdl) = outputs[WDL_OUTPUT]
+                    .try_extract_raw_tensor::<f32>()
+                    .map_err(onnx_error)?;
+                wdl.chunks_exact(3).map(|wdl| wdl[0] - wdl[2]).collect()
+            }
+            ValueHead::Value => {
+                let (_, value) = outputs[VALUE_OUTPUT]
+                    .try_extract_raw_tensor::<f32>()
+                    .map_err(onnx_error)?;
+                value.to_vec()
+            }
+        };
+        if policy.len() != lines.len() * POLICY_SIZE || values.len() != lines.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the outputs of the model do not match the batch",
+            ));
+        }
+        Ok(policy
+            .chunks_exact(POLICY_SIZE)
+            .zip(values)
+            .map(|(policy, value)| Evaluation {
+                policy: policy.to_vec(),
+                value,
+            })
+            .collect())
+    }
+}
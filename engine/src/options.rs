@@ -0,0 +1,318 @@
+// The UCI options of the engine, declared once each with their type, default
+// and the callback applying a new value, from which the `option` lines of the
+// reply to `uci`, the handling of `setoption` and the configuration files are
+// derived.
+//
+// The values are checked against the declaration before the callback sees
+// them: spins are clamped to their range, checks are `true` or `false`,
+// combos are one of their variants (in the spelling of the declaration) and
+// the `<empty>` string of the GUIs is passed as "". Hidden options work like
+// the others but are not listed in the reply to `uci`.
+//
+// A configuration file sets options at startup (`attix --config <file>`),
+// one `Name = value` per line, with `#` starting comments:
+//
+//     Hash = 256
+//     Threads = 4
+//     SyzygyPath = /path/to/syzygy
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+enum Kind<T> {
+    Spin {
+        default: i64,
+        min: i64,
+        max: i64,
+        set: fn(&mut T, i64),
+    },
+    Check {
+        default: bool,
+        set: fn(&mut T, bool),
+    },
+    String {
+        default: &'static str,
+        set: fn(&mut T, &str),
+    },
+    Combo {
+        default: &'static str,
+        vars: &'static [&'static str],
+        set: fn(&mut T, &str),
+    },
+}
+
+struct UciOption<T> {
+    name: &'static str,
+    hidden: bool,
+    kind: Kind<T>,
+}
+
+impl<T> UciOption<T> {
+    // The line of the option in the reply to `uci`.
+    fn declaration(&self) -> String {
+        let name = self.name;
+        match &self.kind {
+            Kind::Spin {
+                default, min, max, ..
+            } => format!("option name {name} type spin default {default} min {min} max {max}"),
+            Kind::Check { default, .. } => {
+                format!("option name {name} type check default {default}")
+            }
+            Kind::String { default, .. } => {
+                let default = if default.is_empty() {
+                    "<empty>"
+                } else {
+                    default
+                };
+                format!("option name {name} type string default {default}")
+            }
+            Kind::Combo { default, vars, .. } => {
+                let vars: Vec<String> = vars.iter().map(|var| format!("var {var}")).collect();
+                format!(
+                    "option name {name} type combo default {default} {}",
+                    vars.join(" ")
+                )
+            }
+        }
+    }
+
+    fn set(&self, target: &mut T, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid {} value {value}", self.name);
+        match &self.kind {
+            Kind::Spin { min, max, set, .. } => {
+                let value: i64 = value.parse().map_err(|_| invalid())?;
+                set(target, value.clamp(*min, *max));
+            }
+            Kind::Check { set, .. } => match value.to_lowercase().as_str() {
+                "true" => set(target, true),
+                "false" => set(target, false),
+                _ => return Err(invalid()),
+            },
+            Kind::String { set, .. } => match value {
+                "<empty>" => set(target, ""),
+                value => set(target, value),
+            },
+            Kind::Combo { vars, set, .. } => {
+                let var = vars
+                    .iter()
+                    .find(|var| var.eq_ignore_ascii_case(value))
+                    .ok_or_else(invalid)?;
+                set(target, var);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The options of a `T`, which their callbacks change.
+pub struct Registry<T> {
+    options: Vec<UciOption<T>>,
+}
+
+impl<T> Registry<T> {
+    pub fn new() -> Self {
+        Registry {
+            options: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, name: &'static str, kind: Kind<T>) -> &mut Self {
+        self.options.push(UciOption {
+            name,
+            hidden: false,
+            kind,
+        });
+        self
+    }
+
+    pub fn spin(
+        &mut self,
+        name: &'static str,
+        default: i64,
+        min: i64,
+        max: i64,
+        set: fn(&mut T, i64),
+    ) -> &mut Self {
+        let kind = Kind::Spin {
+            default,
+            min,
+            max,
+            set,
+        };
+        self.add(name, kind)
+    }
+
+    pub fn check(&mut self, name: &'static str, default: bool, set: fn(&mut T, bool)) -> &mut Self {
+        self.add(name, Kind::Check { default, set })
+    }
+
+    pub fn string(
+        &mut self,
+        name: &'static str,
+        default: &'static str,
+        set: fn(&mut T, &str),
+    ) -> &mut Self {
+        self.add(name, Kind::String { default, set })
+    }
+
+    pub fn combo(
+        &mut self,
+        name: &'static str,
+        default: &'static str,
+        vars: &'static [&'static str],
+        set: fn(&mut T, &str),
+    ) -> &mut Self {
+        self.add(name, Kind::Combo { default, vars, set })
+    }
+
+    // Leaves the option added last out of the reply to `uci`.
+    pub fn hidden(&mut self) -> &mut Self {
+        if let Some(option) = self.options.last_mut() {
+            option.hidden = true;
+        }
+        self
+    }
+
+    // The `option` lines of the reply to `uci`.
+    pub fn print(&self) {
+        for option in self.options.iter().filter(|option| !option.hidden) {
+            println!("{}", option.declaration());
+        }
+    }
+
+    // Sets the option, whose name is case-insensitive.
+    pub fn set(&self, target: &mut T, name: &str, value: Option<&str>) -> Result<(), String> {
+        let option = self
+            .options
+            .iter()
+            .find(|option| option.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown option {name}"))?;
+        let value = value.ok_or_else(|| format!("no value for option {}", option.name))?;
+        option.set(target, value)
+    }
+
+    // Sets the options of a configuration file, failing on the first line
+    // that is not a valid option.
+    pub fn load<P: AsRef<Path>>(&self, target: &mut T, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let invalid = |line: usize, message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {message}", path.display(), line + 1),
+            )
+        };
+        for (idx, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(idx, "expected Name = value".to_string()))?;
+            self.set(target, name.trim(), Some(value.trim()))
+                .map_err(|err| invalid(idx, err))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Settings {
+        hash: i64,
+        ponder: bool,
+        path: String,
+        mode: String,
+    }
+
+    fn registry() -> Registry<Settings> {
+        let mut registry: Registry<Settings> = Registry::new();
+        registry
+            .spin("Hash", 16, 1, 1024, |settings, value| settings.hash = value)
+            .check("Ponder", false, |settings, value| settings.ponder = value)
+            .string("SyzygyPath", "", |settings, value| {
+                settings.path = value.to_string()
+            })
+            .combo("Mode", "Fast", &["Fast", "Slow"], |settings, value| {
+                settings.mode = value.to_string()
+            })
+            .hidden();
+        registry
+    }
+
+    #[test]
+    fn declarations() {
+        let registry = registry();
+        let lines: Vec<String> = registry
+            .options
+            .iter()
+            .map(UciOption::declaration)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "option name Hash type spin default 16 min 1 max 1024",
+                "option name Ponder type check default false",
+                "option name SyzygyPath type string default <empty>",
+                "option name Mode type combo default Fast var Fast var Slow",
+            ]
+        );
+        let hidden: Vec<bool> = registry
+            .options
+            .iter()
+            .map(|option| option.hidden)
+            .collect();
+        assert_eq!(hidden, [false, false, false, true]);
+    }
+
+    #[test]
+    fn values_are_checked() {
+        let registry = registry();
+        let mut settings = Settings::default();
+        registry.set(&mut settings, "hash", Some("4096")).unwrap();
+        assert_eq!(settings.hash, 1024);
+        registry.set(&mut settings, "Ponder", Some("TRUE")).unwrap();
+        assert!(settings.ponder);
+        registry
+            .set(&mut settings, "SyzygyPath", Some("<empty>"))
+            .unwrap();
+        assert_eq!(settings.path, "");
+        registry.set(&mut settings, "Mode", Some("slow")).unwrap();
+        assert_eq!(settings.mode, "Slow");
+
+        assert!(registry.set(&mut settings, "Hash", Some("big")).is_err());
+        assert!(registry.set(&mut settings, "Ponder", Some("yes")).is_err());
+        assert!(registry.set(&mut settings, "Mode", Some("Medium")).is_err());
+        assert!(registry.set(&mut settings, "Hash", None).is_err());
+        assert!(registry.set(&mut settings, "Threads", Some("2")).is_err());
+        assert_eq!((settings.hash, settings.mode.as_str()), (1024, "Slow"));
+    }
+
+    #[test]
+    fn configuration_files() {
+        let registry = registry();
+        let mut settings = Settings::default();
+        let path = std::env::temp_dir().join(format!("attix-options-{}.conf", process::id()));
+        fs::write(
+            &path,
+            "# Engine settings\nHash = 256\n\nPonder = true # on\n",
+        )
+        .unwrap();
+        registry.load(&mut settings, &path).unwrap();
+        assert_eq!((settings.hash, settings.ponder), (256, true));
+
+        fs::write(&path, "Hash = 64\nThreads\n").unwrap();
+        let err = registry.load(&mut settings, &path).unwrap_err();
+        assert!(
+            err.to_string().ends_with(":2: expected Name = value"),
+            "{err}"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
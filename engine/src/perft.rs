@@ -0,0 +1,177 @@
+// Perft: the number of leaves of the tree of legal moves to a depth, which is
+// known for many positions and validates the move handling of the engine:
+// generating and playing the moves the way the search does, and their UCI
+// notation the way the GUI sends them (see uci).
+//
+// `attix perft <depth> [fen]` and the `go perft <depth>` UCI command print
+// the count below every move of the root (divide), so that a wrong count can
+// be narrowed down to a move by comparing with another engine.
+// `attix perft suite [depth]` checks the counts of positions with castling,
+// en passant, promotions and Chess960 castling up to the depth.
+
+use std::io;
+use std::time::Instant;
+
+use shakmaty::fen::Fen;
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Move, Position};
+
+pub const DEFAULT_SUITE_DEPTH: u32 = 4;
+pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// The FEN and the counts at depth 1, 2, ...
+const SUITE: &[(&str, &[u64])] = &[
+    (STARTPOS, &[20, 400, 8_902, 197_281, 4_865_609]),
+    // Kiwipete: castling through attacked squares, pins, en passant.
+    (
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        &[48, 2_039, 97_862, 4_085_603],
+    ),
+    // En passant captures exposing the king on the rank.
+    (
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        &[14, 191, 2_812, 43_238, 674_624],
+    ),
+    // Promotions with capture and castling rights of one side.
+    (
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        &[6, 264, 9_467, 422_333],
+    ),
+    (
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        &[44, 1_486, 62_379, 2_103_487],
+    ),
+    (
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        &[46, 2_079, 89_890, 3_894_594],
+    ),
+    // Chess960: the king and the rooks start on other files.
+    (
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        &[21, 528, 12_189, 326_672, 8_146_062],
+    ),
+    (
+        "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+        &[21, 807, 18_002, 667_366, 16_253_601],
+    ),
+];
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// The position of the FEN and whether it is a Chess960 position, which is
+// detected from the squares of the king and the rooks.
+pub fn parse_fen(fen: &str) -> io::Result<(Chess, CastlingMode)> {
+    let fen: Fen = fen
+        .parse()
+        .map_err(|err| invalid(format!("{fen}: {err}")))?;
+    let castling_mode = CastlingMode::detect(fen.as_setup());
+    let pos = fen
+        .into_position(castling_mode)
+        .map_err(|err| invalid(format!("{err}")))?;
+    Ok((pos, castling_mode))
+}
+
+pub fn perft(pos: &Chess, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = pos.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .iter()
+        .map(|m| {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            perft(&child, depth - 1)
+        })
+        .sum()
+}
+
+// The UCI notation of the move, checking that it parses back to the move.
+fn to_uci(pos: &Chess, m: &Move, castling_mode: CastlingMode) -> io::Result<UciMove> {
+    let uci = m.to_uci(castling_mode);
+    let parsed = UciMove::from_ascii(uci.to_string().as_bytes())
+        .ok()
+        .and_then(|uci| uci.to_move(pos).ok());
+    if parsed.as_ref() != Some(m) {
+        return Err(invalid(format!("{uci} does not parse back to the move")));
+    }
+    Ok(uci)
+}
+
+// Prints the count below every move of the position and returns the total.
+pub fn divide(pos: &Chess, depth: u32, castling_mode: CastlingMode) -> io::Result<u64> {
+    let mut total = 0;
+    for m in pos.legal_moves() {
+        let uci = to_uci(pos, &m, castling_mode)?;
+        let mut child = pos.clone();
+        child.play_unchecked(&m);
+        let count = perft(&child, depth.saturating_sub(1));
+        println!("{uci}: {count}");
+        total += count;
+    }
+    println!();
+    println!("Nodes searched: {total}");
+    Ok(total)
+}
+
+// Checks the counts of the suite up to the depth and the UCI notation of the
+// moves of the roots, fails on the first wrong one.
+pub fn run_suite(max_depth: u32) -> io::Result<()> {
+    let start = Instant::now();
+    let mut total = 0;
+    for (fen, counts) in SUITE {
+        let (pos, castling_mode) = parse_fen(fen)?;
+        for m in pos.legal_moves() {
+            to_uci(&pos, &m, castling_mode)?;
+        }
+        for (depth, &expected) in (1..=max_depth).zip(counts.iter()) {
+            let count = perft(&pos, depth);
+            if count != expected {
+                return Err(invalid(format!(
+                    "{fen} at depth {depth}: {count} leaves instead of {expected}"
+                )));
+            }
+            total += count;
+        }
+        println!("{fen}: ok");
+    }
+    let millis = start.elapsed().as_millis() as u64;
+    println!("Nodes searched: {total}");
+    println!("Nodes/second: {}", total * 1000 / millis.max(1));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deep enough for the castling, en passant and promotions of the suite,
+    // and fast in debug builds. `attix perft suite` checks the deeper counts.
+    const TEST_DEPTH: u32 = 3;
+
+    #[test]
+    fn suite() {
+        for (fen, counts) in SUITE {
+            let (pos, castling_mode) = parse_fen(fen).expect("the suite has valid FENs");
+            for m in pos.legal_moves() {
+                to_uci(&pos, &m, castling_mode).expect("the notation parses back");
+            }
+            for (depth, &expected) in (1..=TEST_DEPTH).zip(counts.iter()) {
+                assert_eq!(perft(&pos, depth), expected, "{fen} at depth {depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn chess960_castling_is_detected() {
+        let (_, castling_mode) = parse_fen(SUITE[6].0).expect("valid FEN");
+        assert_eq!(castling_mode, CastlingMode::Chess960);
+        let (_, castling_mode) = parse_fen(STARTPOS).expect("valid FEN");
+        assert_eq!(castling_mode, CastlingMode::Standard);
+    }
+}
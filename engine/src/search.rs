@@ -0,0 +1,1148 @@
+// Search for the best move of a position.
+//
+// Negamax alpha-beta search with iterative deepening: the position is
+// searched to depth 1, 2, ... until a limit is reached, and every finished
+// iteration reports its score and principal variation as an `info` line.
+// The moves are ordered so that the cutoffs come early: the best move of the
+// previous iteration or of the transposition table first, then the captures
+// by the value of the captured piece (MVV-LVA), the killer moves (quiet moves
+// that caused a cutoff at the same ply), the countermove (the quiet move that
+// caused a cutoff last after the same previous move), the other quiet moves by
+// their history and last the captures losing material by the static exchange
+// evaluation (see movepick), which the quiescence search skips. The history
+// of a quiet move is how deep the searches it caused cutoffs in were, by
+// itself (the main history) and after each of the two previous moves of the
+// line (the continuation history). An iteration
+// interrupted by the limits is discarded, except that at least the first one
+// always finishes, so that there is a move to play. No iteration starts after
+// the soft limit of the time (see time), which is extended while the best
+// move is unstable or the score drops.
+//
+// With the MultiPV option, every iteration searches the root once per line,
+// without the first moves of the lines found before, and reports the lines
+// sorted by their scores.
+//
+// The results of the searched positions are kept in the transposition table
+// (see tt), which cuts off positions searched deep enough before, reached by
+// another order of moves or in an earlier iteration, and provides the best
+// move found for them to search first.
+//
+// From ASPIRATION_MIN_DEPTH, every line is searched within a window of
+// ASPIRATION_WINDOW centipawns around its score of the previous iteration
+// first, which cuts off more moves than the full window. If the score falls
+// outside the window, it is searched again with the window doubled on that
+// side, until it exceeds MAX_ASPIRATION_WINDOW and the full window is
+// searched.
+//
+// The move of the transposition table is extended by a ply if it is singular,
+// i.e. all other moves fail low against a margin below its score in a search
+// of half the depth without it. Such forced moves are then searched deeper
+// than their alternatives, which are unlikely to matter.
+//
+// Two selective techniques skip the parts of the tree that are unlikely to
+// matter, each can be turned off with a UCI option (see `Options`), as can
+// the aspiration windows and the singular extensions:
+// - Null-move pruning: the side to move passes, and if a reduced search still
+//   fails high, so would a real move, except in zugzwang. So it is not tried in
+//   check, without pieces or right after another null move, and at high depths
+//   a reduced search without null moves verifies the cutoff.
+// - Late move reductions: the quiet moves ordered late are searched shallower
+//   with a null window, the deeper the later they come, and searched again to
+//   the full depth only if they raise alpha.
+//
+// At the horizon, the quiescence search plays out the captures and
+// promotions until the position is quiet, so that the static evaluation is
+// not taken in the middle of an exchange. The side to move may always stand
+// pat, i.e. take the static evaluation instead of capturing, and captures
+// that cannot raise alpha even winning the piece by a margin are skipped
+// (delta pruning). In check, all moves are searched instead.
+//
+// Repetitions of a position of the game or the search and the fifty-move
+// rule score as draws, which are worth the Contempt option less than an
+// equal position to the engine. With the Syzygy tablebases (see syzygy), the
+// positions they have are scored by them, and the root is not searched.
+//
+// Several searches of the same position run in parallel with the Threads
+// option (see smp). Each thread counts its nodes and adds them to the total of
+// the search when it checks the limits, by which the node limit and the
+// reports go.
+
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use preprocessing::phase::{self, MAX_SCORE};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Move, Position, Role};
+use web_time::Instant;
+
+use crate::cache::CacheStats;
+use crate::eval::{Eval, HandCrafted};
+use crate::movepick;
+use crate::syzygy::Syzygy;
+use crate::time::Limits;
+use crate::tree::SearchTree;
+use crate::tt::{self, Bound, Entry, TranspositionTable};
+
+// Score of being mated at the root, mates further away score closer to 0.
+pub const MATE: i32 = 30_000;
+pub const MAX_PLY: usize = 128;
+pub const MAX_MULTI_PV: usize = 256;
+// The limits are checked every this many nodes.
+const CHECK_INTERVAL: u64 = 1024;
+// Killer moves kept per ply.
+const KILLERS: usize = 2;
+// The history scores are halved once one of them exceeds this, so that they
+// stay below the scores of the captures and killers.
+const MAX_HISTORY: i32 = 1 << 16;
+// Moves by the side, the moved piece and the destination, see piece_to.
+const PIECE_TO: usize = 2 * 6 * 64;
+// Positional gain a capture may bring on top of the captured material, in
+// centipawns, see the delta pruning of the quiescence search.
+const DELTA_MARGIN: i32 = 200;
+// Null moves are tried from this depth, reduced by NULL_REDUCTION plus a
+// ply every NULL_REDUCTION_DIVISOR plies of depth, and the cutoffs are
+// verified from NULL_VERIFICATION_DEPTH.
+const NULL_MIN_DEPTH: u32 = 3;
+const NULL_REDUCTION: u32 = 3;
+const NULL_REDUCTION_DIVISOR: u32 = 6;
+const NULL_VERIFICATION_DEPTH: u32 = 8;
+// Moves from this index in the ordering are reduced, from this depth.
+const LMR_MIN_MOVES: usize = 3;
+const LMR_MIN_DEPTH: u32 = 3;
+// The entry of the transposition table of a singular extension is at most
+// this many plies shallower than the search.
+const SINGULAR_TT_DEPTH: u32 = 3;
+// Aspiration windows, see the search of the lines.
+const ASPIRATION_MIN_DEPTH: u32 = 5;
+const ASPIRATION_WINDOW: i32 = 25;
+const MAX_ASPIRATION_WINDOW: i32 = 800;
+// The soft limit of the time is scaled by 1 plus the best move changes,
+// decaying by half every iteration, plus FAIL_LOW_EXTENSION if the score
+// dropped by FAIL_LOW_MARGIN since the previous iteration, up to
+// MAX_SOFT_SCALE.
+const FAIL_LOW_MARGIN: i32 = 30;
+const FAIL_LOW_EXTENSION: f64 = 0.5;
+const MAX_SOFT_SCALE: f64 = 3.0;
+// The iterations skipped by the helper threads of a parallel search, so that
+// they search other depths than the main thread at the same time: helper `i`
+// skips the depths `d` with an odd `(d + SKIP_PHASE[i]) / SKIP_SIZE[i]`, the
+// scheme of Stockfish 10.
+const SKIP_SIZE: [u32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [u32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+// Selective search techniques, aspiration windows and singular extensions,
+// which are all on by default and turned off for A/B testing with the hidden
+// UCI options NullMove, LMR, Aspiration and Singular. The margins of the
+// singular extensions are hidden UCI spin options as well, which tuners
+// playing matches (e.g. SPSA) set.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub null_move: bool,
+    pub lmr: bool,
+    pub aspiration: bool,
+    pub singular: bool,
+    // Singular extensions are tried from this depth, with a margin of this
+    // many centipawns per ply of depth.
+    pub singular_min_depth: u32,
+    pub singular_margin: i32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            null_move: true,
+            lmr: true,
+            aspiration: true,
+            singular: true,
+            singular_min_depth: 8,
+            singular_margin: 2,
+        }
+    }
+}
+
+// Receives the `info` lines of a search instead of the standard output.
+pub type InfoCallback = Box<dyn Fn(&str) + Send>;
+
+pub struct Search {
+    pos: Chess,
+    limits: Limits,
+    stop: Arc<AtomicBool>,
+    castling_mode: CastlingMode,
+    options: Options,
+    eval: Box<dyn Eval>,
+    tablebases: Option<Arc<Syzygy>>,
+    // Centipawns a draw is worth less than an equal position to the side to
+    // move at the root, and more to its opponent, see draw_score.
+    contempt: i32,
+    tt: Arc<TranspositionTable>,
+    start: Instant,
+    nodes: u64,
+    // Index of the thread in a parallel search, 0 for the main thread, which
+    // alone reports the iterations.
+    thread: usize,
+    // Nodes of all threads, and the nodes of this one added to them so far.
+    total_nodes: Arc<AtomicU64>,
+    reported_nodes: u64,
+    // Set once a limit is reached, the running iteration is abandoned.
+    stopped: bool,
+    // No `info` lines are printed, e.g. when analyzing games (see analyze),
+    // or they are passed to `info` instead of the standard output.
+    silent: bool,
+    info: Option<InfoCallback>,
+    // Hashes of the positions of the game and of the current line, the last
+    // one is the position being searched.
+    history: Vec<Zobrist64>,
+    // Principal variations found at every ply of the current line.
+    pv: Vec<Vec<Move>>,
+    // Lines searched per iteration, and the root moves of the lines searched
+    // before the current one in the running iteration, which it skips.
+    multi_pv: usize,
+    excluded: Vec<Move>,
+    // The move of the previous iteration searched first at the root.
+    root_first: Option<Move>,
+    // The move skipped at every ply of the current line by the verification
+    // search of a singular extension.
+    singular: Vec<Option<Move>>,
+    // Scores and principal variations of the lines of the last finished
+    // iteration, best first, and its depth.
+    lines: Vec<(i32, Vec<Move>)>,
+    completed_depth: u32,
+    // Quiet moves that caused the latest cutoffs at every ply.
+    killers: Vec<[Option<Move>; KILLERS]>,
+    // Cutoff scores of the quiet moves by side to move, from and to square.
+    quiet_history: Box<[[[i32; 64]; 64]; 2]>,
+    // Cutoff scores of the quiet moves after the previous move, both by
+    // piece_to, and the quiet move that caused the latest cutoff after it.
+    continuation_history: Vec<[i32; PIECE_TO]>,
+    countermoves: Vec<Option<Move>>,
+    // The piece_to of the move made at every ply of the current line, None
+    // for null moves.
+    line: Vec<Option<usize>>,
+    // The tree of the last iteration, recorded for debugging, see tree.
+    tree: Option<SearchTree>,
+}
+
+pub fn hash(pos: &Chess) -> Zobrist64 {
+    pos.zobrist_hash(EnPassantMode::Legal)
+}
+
+// Material values in pawns.
+fn role_value(role: Role) -> i32 {
+    match role {
+        Role::Pawn => 1,
+        Role::Knight | Role::Bishop => 3,
+        Role::Rook => 5,
+        Role::Queen => 9,
+        Role::King => 100,
+    }
+}
+
+// Most valuable victim first, then least valuable attacker.
+pub fn capture_score(m: &Move) -> i32 {
+    let victim = m.capture().map_or(0, role_value);
+    let promotion = m.promotion().map_or(0, role_value);
+    10 * (victim + promotion) - role_value(m.role())
+}
+
+// Whether the side to move has pieces other than pawns and the king, without
+// which zugzwang is common.
+fn has_pieces(pos: &Chess) -> bool {
+    (pos.us() ^ pos.our(Role::Pawn) ^ pos.our(Role::King)).any()
+}
+
+// Plies by which the move at the index of the ordering is reduced.
+fn lmr_reduction(depth: u32, idx: usize) -> u32 {
+    let reduction = 0.75 + (depth as f64).ln() * (idx as f64).ln() / 2.25;
+    // At least a ply is left to search.
+    (reduction as u32).clamp(1, depth - 1)
+}
+
+// Whether the position is drawn by the fifty-move rule, insufficient material
+// or a repetition of a position in `history`, which ends with the position.
+pub fn is_draw(pos: &Chess, history: &[Zobrist64]) -> bool {
+    if pos.halfmoves() >= 100 || pos.is_insufficient_material() {
+        return true;
+    }
+    // Only positions since the last capture or pawn move can repeat, and only
+    // with the same side to move.
+    let current = history.last().expect("the position is in the history");
+    history
+        .iter()
+        .rev()
+        .take(pos.halfmoves() as usize + 1)
+        .skip(2)
+        .step_by(2)
+        .any(|hash| hash == current)
+}
+
+// Index of the move by the side, the moved piece and the destination.
+fn piece_to(side: Color, m: &Move) -> usize {
+    (side as usize * 6 + m.role() as usize - 1) * 64 + m.to() as usize
+}
+
+fn history_index(pos: &Chess, m: &Move) -> (usize, usize, usize) {
+    let from = m.from().expect("moves have an origin");
+    (pos.turn() as usize, from as usize, m.to() as usize)
+}
+
+impl Search {
+    // `history` has the hashes of the positions of the game, the searched
+    // position last.
+    pub fn new(
+        pos: Chess,
+        history: Vec<Zobrist64>,
+        limits: Limits,
+        tt: Arc<TranspositionTable>,
+        stop: Arc<AtomicBool>,
+        castling_mode: CastlingMode,
+        options: Options,
+    ) -> Self {
+        Search {
+            pos,
+            limits,
+            stop,
+            castling_mode,
+            options,
+            eval: Box::new(HandCrafted::default()),
+            tablebases: None,
+            contempt: 0,
+            tt,
+            start: Instant::now(),
+            nodes: 0,
+            thread: 0,
+            total_nodes: Arc::new(AtomicU64::new(0)),
+            reported_nodes: 0,
+            stopped: false,
+            silent: false,
+            info: None,
+            history,
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            multi_pv: 1,
+            excluded: Vec::new(),
+            root_first: None,
+            singular: vec![None; MAX_PLY + 1],
+            lines: Vec::new(),
+            completed_depth: 0,
+            killers: vec![[None, None]; MAX_PLY + 1],
+            quiet_history: Box::new([[[0; 64]; 64]; 2]),
+            continuation_history: vec![[0; PIECE_TO]; PIECE_TO],
+            countermoves: vec![None; PIECE_TO],
+            line: vec![None; MAX_PLY + 1],
+            tree: None,
+        }
+    }
+
+    // Replaces the hand-crafted evaluation.
+    pub fn set_eval(&mut self, eval: Box<dyn Eval>) {
+        self.eval = eval;
+    }
+
+    pub fn set_tablebases(&mut self, tablebases: Arc<Syzygy>) {
+        self.tablebases = Some(tablebases);
+    }
+
+    pub fn set_contempt(&mut self, contempt: i32) {
+        self.contempt = contempt;
+    }
+
+    // Records the tree of every iteration, replacing the previous one.
+    pub fn set_tree(&mut self, tree: SearchTree) {
+        self.tree = Some(tree);
+    }
+
+    pub fn tree(&self) -> Option<&SearchTree> {
+        self.tree.as_ref()
+    }
+
+    // A helper thread of the search, which shares the transposition table
+    // and the node count, and is stopped with `stop` instead of the limits,
+    // except for the depth.
+    pub fn helper(&self, thread: usize, stop: Arc<AtomicBool>) -> Search {
+        let limits = Limits {
+            depth: self.limits.depth,
+            ..Limits::default()
+        };
+        let mut helper = Search::new(
+            self.pos.clone(),
+            self.history.clone(),
+            limits,
+            self.tt.clone(),
+            stop,
+            self.castling_mode,
+            self.options,
+        );
+        helper.thread = thread;
+        helper.total_nodes = self.total_nodes.clone();
+        helper.tablebases = self.tablebases.clone();
+        helper.contempt = self.contempt;
+        helper
+    }
+
+    // Nodes searched by this thread.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // Statistics of the caches of the evaluation, see cache.
+    pub fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        self.eval.cache_stats()
+    }
+
+    // Principal variation of the last finished iteration.
+    pub fn pv(&self) -> &[Move] {
+        self.lines.first().map_or(&[], |(_, pv)| pv)
+    }
+
+    // Depth of the last finished iteration.
+    pub fn depth(&self) -> u32 {
+        self.completed_depth
+    }
+
+    // Scores and principal variations of the lines of the last finished
+    // iteration, best first.
+    pub fn lines(&self) -> &[(i32, Vec<Move>)] {
+        &self.lines
+    }
+
+    // Score of the principal variation of the last finished iteration, None
+    // before the first one.
+    pub fn score(&self) -> Option<i32> {
+        self.lines.first().map(|(score, _)| *score)
+    }
+
+    pub fn set_silent(&mut self) {
+        self.silent = true;
+    }
+
+    // Passes the `info` lines to `info` instead of printing them.
+    pub fn set_info(&mut self, info: InfoCallback) {
+        self.info = Some(info);
+    }
+
+    // Searches the best `multi_pv` lines instead of one.
+    pub fn set_multi_pv(&mut self, multi_pv: usize) {
+        self.multi_pv = multi_pv.clamp(1, MAX_MULTI_PV);
+    }
+
+    // Adds the new nodes of the thread to the total and returns it.
+    fn report_nodes(&mut self) -> u64 {
+        let new = self.nodes - self.reported_nodes;
+        self.reported_nodes = self.nodes;
+        self.total_nodes.fetch_add(new, Ordering::Relaxed) + new
+    }
+
+    fn skips(&self, depth: u32) -> bool {
+        let Some(helper) = self.thread.checked_sub(1) else {
+            return false;
+        };
+        let idx = helper % SKIP_SIZE.len();
+        (depth + SKIP_PHASE[idx]) / SKIP_SIZE[idx] % 2 == 1
+    }
+
+    // The piece_to of the moves one and two plies before the ply, if they
+    // were made in the search and were not null moves.
+    fn previous_moves(&self, ply: usize) -> [Option<usize>; 2] {
+        [1, 2].map(|back| ply.checked_sub(back).and_then(|ply| self.line[ply]))
+    }
+
+    // Orders the moves, highest score first: the given first move, captures
+    // and promotions, killers, the countermove, the quiet moves by history and
+    // the captures losing material.
+    fn order(&self, pos: &Chess, moves: &mut [Move], first: Option<&Move>, ply: usize) {
+        let killers = &self.killers[ply];
+        let previous = self.previous_moves(ply);
+        let countermove = previous[0].and_then(|idx| self.countermoves[idx].as_ref());
+        moves.sort_by_cached_key(|m| {
+            let score = if Some(m) == first {
+                i32::MAX
+            } else if m.is_capture() && movepick::see(pos, m) < 0 {
+                -3 * MAX_HISTORY + capture_score(m)
+            } else if m.is_capture() || m.is_promotion() {
+                3 * MAX_HISTORY + capture_score(m)
+            } else if let Some(idx) = killers.iter().position(|k| k.as_ref() == Some(m)) {
+                2 * MAX_HISTORY - idx as i32
+            } else if countermove == Some(m) {
+                2 * MAX_HISTORY - KILLERS as i32
+            } else {
+                // The mean of the histories, which stays below MAX_HISTORY.
+                let (side, from, to) = history_index(pos, m);
+                let idx = piece_to(pos.turn(), m);
+                let continuation: i32 = previous
+                    .iter()
+                    .flatten()
+                    .map(|&previous| self.continuation_history[previous][idx])
+                    .sum();
+                (self.quiet_history[side][from][to] + continuation) / 3
+            };
+            Reverse(score)
+        });
+    }
+
+    // Remembers the quiet move that caused a cutoff at the depth.
+    fn record_cutoff(&mut self, pos: &Chess, m: &Move, depth: u32, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0].as_ref() != Some(m) {
+            killers[1] = killers[0].take();
+            killers[0] = Some(m.clone());
+        }
+        let previous = self.previous_moves(ply);
+        if let Some(idx) = previous[0] {
+            self.countermoves[idx] = Some(m.clone());
+        }
+        let bonus = (depth * depth) as i32;
+        let (side, from, to) = history_index(pos, m);
+        let score = &mut self.quiet_history[side][from][to];
+        *score += bonus;
+        let mut max = *score;
+        let idx = piece_to(pos.turn(), m);
+        for previous in previous.into_iter().flatten() {
+            let score = &mut self.continuation_history[previous][idx];
+            *score += bonus;
+            max = max.max(*score);
+        }
+        if max > MAX_HISTORY {
+            self.age_history();
+        }
+    }
+
+    // Halves the scores of the main and the continuation history, so that the
+    // recent cutoffs weigh more.
+    fn age_history(&mut self) {
+        for side in self.quiet_history.iter_mut() {
+            for from in side.iter_mut() {
+                from.iter_mut().for_each(|score| *score /= 2);
+            }
+        }
+        for previous in self.continuation_history.iter_mut() {
+            previous.iter_mut().for_each(|score| *score /= 2);
+        }
+    }
+
+    fn check_limits(&mut self) {
+        let total = self.report_nodes();
+        self.stopped = self.stop.load(Ordering::Relaxed)
+            || self.limits.nodes.is_some_and(|nodes| total >= nodes)
+            || self
+                .limits
+                .time
+                .is_some_and(|time| self.start.elapsed() >= time);
+    }
+
+    // Score of a draw from the perspective of the side to move: below 0 for
+    // the side to move at the root with a positive contempt, above 0 for its
+    // opponent. The full contempt applies with all pieces on the board, half
+    // of it without pieces, where draws are the likely result anyway.
+    fn draw_score(&self, pos: &Chess) -> i32 {
+        if self.contempt == 0 {
+            return 0;
+        }
+        let (phase, max) = (phase::board_score(pos.board()) as i32, MAX_SCORE as i32);
+        let contempt = self.contempt * (max + phase) / (2 * max);
+        if pos.turn() == self.pos.turn() {
+            -contempt
+        } else {
+            contempt
+        }
+    }
+
+    fn uci(&self, m: &Move) -> String {
+        m.to_uci(self.castling_mode).to_string()
+    }
+
+    // Labels the next node of the tree, if it is recorded.
+    fn label(&mut self, label: impl FnOnce(CastlingMode) -> String) {
+        if let Some(tree) = &mut self.tree {
+            tree.label(label(self.castling_mode));
+        }
+    }
+
+    // Notes a decision at the current node of the tree, if it is recorded.
+    fn note(&mut self, note: &'static str) {
+        if let Some(tree) = &mut self.tree {
+            tree.note(note);
+        }
+    }
+
+    // negamax_node, recorded in the tree if there is one.
+    fn negamax(
+        &mut self,
+        pos: &Chess,
+        depth: u32,
+        ply: usize,
+        alpha: i32,
+        beta: i32,
+        null: bool,
+    ) -> i32 {
+        let Some(tree) = &mut self.tree else {
+            return self.negamax_node(pos, depth, ply, alpha, beta, null);
+        };
+        tree.enter(depth, alpha, beta);
+        let score = self.negamax_node(pos, depth, ply, alpha, beta, null);
+        if let Some(tree) = &mut self.tree {
+            tree.leave(score);
+        }
+        score
+    }
+
+    // Score of the position from the perspective of the side to move,
+    // searched `depth` plies deep. Scores outside of (alpha, beta) are only
+    // bounds. `null` allows a null move, which is not played twice in a row
+    // or in verification searches.
+    fn negamax_node(
+        &mut self,
+        pos: &Chess,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        null: bool,
+    ) -> i32 {
+        self.pv[ply].clear();
+        // The first iteration always finishes.
+        if self.completed_depth > 0 && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+        if ply > 0 && is_draw(pos, &self.history) {
+            self.note("draw");
+            return self.draw_score(pos);
+        }
+        if depth == 0 {
+            self.note("quiescence");
+            return self.quiescence(pos, ply, alpha, beta);
+        }
+        self.nodes += 1;
+
+        let parent_hash = *self.history.last().expect("the position is in the history");
+        let entry = self.tt.probe(parent_hash);
+        // The verification search of a singular extension is of the position
+        // without a move.
+        let verifying = self.singular[ply].is_some();
+        let cutoff_entry = entry.filter(|entry| ply > 0 && !verifying && entry.depth >= depth);
+        if let Some(entry) = cutoff_entry {
+            let score = tt::from_tt(entry.score, ply);
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cutoff {
+                self.note("transposition table cutoff");
+                return score;
+            }
+        }
+        let tablebases = self.tablebases.as_ref().filter(|_| ply > 0);
+        if let Some(score) = tablebases.and_then(|tablebases| tablebases.probe_wdl(pos, ply)) {
+            self.note("tablebase");
+            return score;
+        }
+
+        let in_check = pos.is_check();
+        if null
+            && self.options.null_move
+            && ply > 0
+            && !verifying
+            && !in_check
+            && depth >= NULL_MIN_DEPTH
+        {
+            if let Some(score) = self.null_move(pos, depth, ply, beta) {
+                self.note("null move cutoff");
+                return score;
+            }
+        }
+
+        let mut moves = pos.legal_moves();
+        if moves.is_empty() {
+            self.note(if in_check { "checkmate" } else { "stalemate" });
+            return if in_check {
+                -MATE + ply as i32
+            } else {
+                self.draw_score(pos)
+            };
+        }
+        if ply == 0 {
+            moves.retain(|m| !self.excluded.contains(m));
+        }
+        if let Some(skipped) = &self.singular[ply] {
+            moves.retain(|m| m != skipped);
+            // The skipped move is the only one.
+            if moves.is_empty() {
+                return alpha;
+            }
+        }
+        if ply >= MAX_PLY {
+            return self.eval.evaluate(pos);
+        }
+
+        // The best move of the previous iteration comes first, or the one
+        // found by an earlier search of the position.
+        let first = match (ply, &self.root_first, entry) {
+            (0, Some(m), _) => Some(m.clone()),
+            (_, _, Some(entry)) => moves
+                .iter()
+                .find(|m| tt::encode_move(m) == entry.best_move)
+                .cloned(),
+            _ => None,
+        };
+        let extended = match (&first, entry) {
+            (Some(m), Some(entry)) if ply > 0 && !verifying => {
+                self.is_singular(pos, m, &entry, depth, ply)
+            }
+            _ => false,
+        };
+        if self.stopped {
+            return 0;
+        }
+        if extended {
+            self.note("singular extension");
+        }
+        self.order(pos, &mut moves, first.as_ref(), ply);
+        let mut best_move = None;
+        for (idx, m) in moves.into_iter().enumerate() {
+            let mut child = pos.clone();
+            child.play_unchecked(&m);
+            let child_hash = hash(&child);
+            self.tt.prefetch(child_hash);
+            self.history.push(child_hash);
+            self.eval.push(pos, &child);
+            self.line[ply] = Some(piece_to(pos.turn(), &m));
+            let reduce = self.options.lmr
+                && idx >= LMR_MIN_MOVES
+                && depth >= LMR_MIN_DEPTH
+                && !in_check
+                && !m.is_capture()
+                && !m.is_promotion()
+                && !child.is_check()
+                && !self.killers[ply].contains(&Some(m.clone()));
+            // Reduced moves that raise alpha are searched again to the full
+            // depth with the full window.
+            let mut score = alpha + 1;
+            if reduce {
+                let reduced = depth - 1 - lmr_reduction(depth, idx);
+                self.label(|mode| format!("{} reduced", m.to_uci(mode)));
+                score = -self.negamax(&child, reduced, ply + 1, -alpha - 1, -alpha, true);
+            }
+            if score > alpha {
+                let extension = u32::from(extended && first.as_ref() == Some(&m));
+                let depth = depth - 1 + extension;
+                self.label(|mode| match (reduce, extension) {
+                    (true, _) => format!("{} re-searched", m.to_uci(mode)),
+                    (_, 1) => format!("{} extended", m.to_uci(mode)),
+                    _ => m.to_uci(mode).to_string(),
+                });
+                score = -self.negamax(&child, depth, ply + 1, -beta, -alpha, true);
+            }
+            self.eval.pop();
+            self.history.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score > alpha {
+                alpha = score;
+                best_move = Some(tt::encode_move(&m));
+                if alpha >= beta && !m.is_capture() && !m.is_promotion() {
+                    self.record_cutoff(pos, &m, depth, ply);
+                }
+                let (line, rest) = self.pv.split_at_mut(ply + 1);
+                let line = &mut line[ply];
+                line.clear();
+                line.push(m);
+                line.extend_from_slice(&rest[0]);
+                if alpha >= beta {
+                    self.note("beta cutoff");
+                    break;
+                }
+            }
+        }
+
+        let bound = match best_move {
+            _ if alpha >= beta => Bound::Lower,
+            Some(_) => Bound::Exact,
+            None => Bound::Upper,
+        };
+        let entry = Entry {
+            best_move: best_move.unwrap_or(0),
+            score: tt::to_tt(alpha, ply),
+            depth,
+            bound,
+        };
+        // The root without some of its moves is not the position.
+        if (self.excluded.is_empty() || ply > 0) && !verifying {
+            self.tt.store(parent_hash, entry);
+        }
+        alpha
+    }
+
+    // Whether the move of the transposition table is singular: all other
+    // moves fail low against a margin below its score, in a search of half
+    // the depth without it.
+    fn is_singular(
+        &mut self,
+        pos: &Chess,
+        m: &Move,
+        entry: &Entry,
+        depth: u32,
+        ply: usize,
+    ) -> bool {
+        let score = tt::from_tt(entry.score, ply);
+        if !self.options.singular
+            || depth < self.options.singular_min_depth
+            || entry.bound == Bound::Upper
+            || entry.depth + SINGULAR_TT_DEPTH < depth
+            || score.abs() >= MATE - MAX_PLY as i32
+        {
+            return false;
+        }
+        let singular_beta = score - self.options.singular_margin * depth as i32;
+        self.singular[ply] = Some(m.clone());
+        self.label(|mode| format!("singular verification without {}", m.to_uci(mode)));
+        let verified = self.negamax(pos, depth / 2, ply, singular_beta - 1, singular_beta, false);
+        self.singular[ply] = None;
+        // The line of the verification is not the one of this search.
+        self.pv[ply].clear();
+        !self.stopped && verified < singular_beta
+    }
+
+    // Lets the side to move pass and searches the position reduced, returns
+    // the score if it still fails high.
+    fn null_move(&mut self, pos: &Chess, depth: u32, ply: usize, beta: i32) -> Option<i32> {
+        if !has_pieces(pos) || self.eval.evaluate(pos) < beta {
+            return None;
+        }
+        // The side to move is not in check, so that passing is legal.
+        let child = pos.clone().swap_turn().ok()?;
+        let reduction = NULL_REDUCTION + depth / NULL_REDUCTION_DIVISOR;
+        let reduced = depth.saturating_sub(1 + reduction);
+        let child_hash = hash(&child);
+        self.tt.prefetch(child_hash);
+        self.history.push(child_hash);
+        self.eval.push(pos, &child);
+        self.line[ply] = None;
+        self.label(|_| "null".to_string());
+        let score = -self.negamax(&child, reduced, ply + 1, -beta, -beta + 1, false);
+        self.eval.pop();
+        self.history.pop();
+        if self.stopped || score < beta {
+            return None;
+        }
+        // Mates found after passing are not proven.
+        let score = score.min(MATE - MAX_PLY as i32 - 1);
+        if depth < NULL_VERIFICATION_DEPTH {
+            return Some(score);
+        }
+        self.label(|_| "null move verification".to_string());
+        let verified = self.negamax(pos, depth - reduction, ply, beta - 1, beta, false);
+        // The line of the verification is not the one of this search.
+        self.pv[ply].clear();
+        (!self.stopped && verified >= beta).then_some(score)
+    }
+
+    // Score of the position from the perspective of the side to move,
+    // searching only captures and promotions, or all moves in check.
+    fn quiescence(&mut self, pos: &Chess, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.completed_depth > 0 && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+        self.nodes += 1;
+
+        let in_check = pos.is_check();
+        let mut moves = pos.legal_moves();
+        if in_check && moves.is_empty() {
+            return -MATE + ply as i32;
+        }
+        let stand_pat = self.eval.evaluate(pos);
+        if ply >= MAX_PLY {
+            return stand_pat;
+        }
+        if !in_check {
+            if stand_pat >= beta {
+                return beta;
+            }
+            alpha = alpha.max(stand_pat);
+            moves.retain(|m| m.is_capture() || m.is_promotion());
+        }
+
+        moves.sort_by_key(|m| Reverse(capture_score(m)));
+        for m in moves {
+            let promotion = m.promotion().map_or(0, |role| role_value(role) - 1);
+            let gain = 100 * (m.capture().map_or(0, role_value) + promotion);
+            if !in_check && stand_pat + gain + DELTA_MARGIN <= alpha {
+                continue;
+            }
+            if !in_check && movepick::see(pos, &m) < 0 {
+                continue;
+            }
+            let mut child = pos.clone();
+            child.play_unchecked(&m);
+            self.eval.push(pos, &child);
+            let score = -self.quiescence(&child, ply + 1, -beta, -alpha);
+            self.eval.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn print_info(&self, depth: u32, multi_pv: usize, score: i32, pv: &[Move]) {
+        let score = if score.abs() >= MATE - MAX_PLY as i32 {
+            // In moves, negative if the engine is mated.
+            let plies = MATE - score.abs();
+            format!("mate {}", score.signum() * (plies + 1) / 2)
+        } else {
+            format!("cp {score}")
+        };
+        let millis = self.start.elapsed().as_millis() as u64;
+        let nodes = self.total_nodes.load(Ordering::Relaxed);
+        let nps = nodes * 1000 / millis.max(1);
+        let pv: Vec<String> = pv.iter().map(|m| self.uci(m)).collect();
+        let line = format!(
+            "info depth {depth} multipv {multi_pv} score {score} nodes {nodes} nps {nps} \
+             time {millis} pv {}",
+            pv.join(" ")
+        );
+        match &self.info {
+            Some(info) => info(&line),
+            None => println!("{line}"),
+        }
+    }
+
+    // Searches the root within a window around the score of the line in the
+    // previous iteration, widening it until the score is inside.
+    fn aspiration(&mut self, pos: &Chess, depth: u32, previous: Option<i32>) -> i32 {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match previous {
+            Some(score)
+                if self.options.aspiration
+                    && depth >= ASPIRATION_MIN_DEPTH
+                    && score.abs() < MATE - MAX_PLY as i32 =>
+            {
+                (score - delta, score + delta)
+            }
+            _ => (-MATE, MATE),
+        };
+        loop {
+            let score = self.negamax(pos, depth, 0, alpha, beta, true);
+            if self.stopped {
+                return score;
+            }
+            let fail_low = score <= alpha && alpha > -MATE;
+            let fail_high = score >= beta && beta < MATE;
+            if !fail_low && !fail_high {
+                return score;
+            }
+            delta *= 2;
+            if delta > MAX_ASPIRATION_WINDOW {
+                (alpha, beta) = (-MATE, MATE);
+            } else if fail_low {
+                alpha = (score - delta).max(-MATE);
+            } else {
+                beta = (score + delta).min(MATE);
+            }
+        }
+    }
+
+    // Searches the lines of an iteration, None if it is interrupted.
+    fn search_lines(&mut self, pos: &Chess, depth: u32) -> Option<Vec<(i32, Vec<Move>)>> {
+        let num_moves = pos.legal_moves().len();
+        let mut lines: Vec<(i32, Vec<Move>)> = Vec::new();
+        self.excluded.clear();
+        for idx in 0..self.multi_pv.min(num_moves) {
+            let previous = self.lines.get(idx);
+            self.root_first = previous.and_then(|(_, pv)| pv.first().cloned());
+            let previous = previous.map(|(score, _)| *score);
+            let score = self.aspiration(pos, depth, previous);
+            if self.stopped {
+                break;
+            }
+            self.excluded.push(self.pv[0][0].clone());
+            lines.push((score, self.pv[0].clone()));
+        }
+        self.excluded.clear();
+        if self.stopped {
+            return None;
+        }
+        lines.sort_by_key(|(score, _)| Reverse(*score));
+        Some(lines)
+    }
+
+    // Searches the position and prints the result of every iteration as an
+    // `info` line. Returns the best move, None if there are no legal moves.
+    pub fn run(&mut self) -> Option<Move> {
+        let pos = self.pos.clone();
+        if pos.legal_moves().is_empty() {
+            return None;
+        }
+        let root = self
+            .tablebases
+            .as_ref()
+            .and_then(|tablebases| tablebases.probe_root(&pos));
+        if let Some((m, score)) = root {
+            if self.thread == 0 && !self.silent {
+                self.print_info(0, 1, score, std::slice::from_ref(&m));
+            }
+            return Some(m);
+        }
+        if self.thread == 0 {
+            self.tt.new_search();
+        }
+        self.eval.reset(&pos);
+        let max_depth = self.limits.depth.unwrap_or(MAX_PLY as u32).max(1);
+        let mut previous: Option<(Move, i32)> = None;
+        let mut best_move_changes = 0.0;
+        for depth in 1..=max_depth {
+            if self.skips(depth) {
+                continue;
+            }
+            if let Some(tree) = &mut self.tree {
+                tree.clear();
+            }
+            let Some(lines) = self.search_lines(&pos, depth) else {
+                break;
+            };
+            self.lines = lines;
+            self.completed_depth = depth;
+            self.check_limits();
+            if self.thread == 0 && !self.silent {
+                for (idx, (score, pv)) in self.lines.iter().enumerate() {
+                    self.print_info(depth, idx + 1, *score, pv);
+                }
+            }
+            let (score, pv) = &self.lines[0];
+            let score = *score;
+            // A mate within the depth is found for certain.
+            if self.stopped || score.abs() >= MATE - depth as i32 {
+                break;
+            }
+            let best = pv[0].clone();
+            best_move_changes /= 2.0;
+            let mut scale = 1.0;
+            if let Some((previous_best, previous_score)) = &previous {
+                if *previous_best != best {
+                    best_move_changes += 1.0;
+                }
+                if score < previous_score - FAIL_LOW_MARGIN {
+                    scale += FAIL_LOW_EXTENSION;
+                }
+            }
+            scale = (scale + best_move_changes).min(MAX_SOFT_SCALE);
+            let soft = self.limits.soft.map(|soft| soft.mul_f64(scale));
+            if soft.is_some_and(|soft| self.start.elapsed() >= soft) {
+                break;
+            }
+            previous = Some((best, score));
+        }
+        self.report_nodes();
+        self.pv().first().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+
+    // Searches the position to the depth with an empty transposition table,
+    // returns the best move in UCI, its score and the number of nodes.
+    fn search(fen: &str, depth: u32, options: Options) -> (String, i32, u64) {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let pos: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        let limits = Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        };
+        let mut search = Search::new(
+            pos.clone(),
+            vec![hash(&pos)],
+            limits,
+            Arc::new(TranspositionTable::new(1)),
+            Arc::new(AtomicBool::new(false)),
+            CastlingMode::Standard,
+            options,
+        );
+        let best = search.run().expect("the position has legal moves");
+        let score = search.score().expect("the search is complete");
+        (search.uci(&best), score, search.nodes())
+    }
+
+    fn unpruned() -> Options {
+        Options {
+            null_move: false,
+            lmr: false,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn pruning_searches_fewer_nodes() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let (_, _, pruned) = search(fen, 5, Options::default());
+        let (_, _, unpruned) = search(fen, 5, unpruned());
+        assert!(
+            pruned < unpruned,
+            "{pruned} nodes with pruning, {unpruned} without"
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_tactics() {
+        // The knight forks the king and the rook.
+        let fen = "r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1";
+        for options in [Options::default(), unpruned()] {
+            assert_eq!(search(fen, 4, options).0, "b5c7");
+        }
+    }
+
+    #[test]
+    fn aspiration_keeps_result() {
+        // Without pruning, the windows only change how often the root is
+        // searched, not the move and the score found.
+        let full = Options {
+            aspiration: false,
+            ..unpruned()
+        };
+        for fen in [
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        ] {
+            let (best, score, _) = search(fen, 6, unpruned());
+            let (full_best, full_score, _) = search(fen, 6, full);
+            assert_eq!((best, score), (full_best, full_score), "{fen}");
+        }
+    }
+
+    #[test]
+    fn singular_extends_forced_moves() {
+        // Only taking the queen back keeps the material even.
+        let fen = "rnb1kbnr/pppp1ppp/8/4p3/3qP3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 4";
+        let singular = Options {
+            singular_min_depth: 4,
+            ..Options::default()
+        };
+        let plain = Options {
+            singular: false,
+            ..singular
+        };
+        let (best, _, extended) = search(fen, 6, singular);
+        let (plain_best, _, nodes) = search(fen, 6, plain);
+        assert_eq!((best.as_str(), plain_best.as_str()), ("f3d4", "f3d4"));
+        // The verification searches and the extended recapture cost nodes.
+        assert!(
+            extended > nodes,
+            "{extended} nodes extended, {nodes} without"
+        );
+    }
+}
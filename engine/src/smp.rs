@@ -0,0 +1,97 @@
+// Lazy SMP: the alpha-beta search of a position by several threads at once,
+// which share nothing but the transposition table. The helper threads search
+// the position like the main thread, skipping some of the iterations (see
+// search), and the entries they store cut off parts of the tree of the main
+// thread and provide the moves it searches first. The threads keep their own
+// move ordering and evaluation.
+//
+// Only the main thread checks the limits and reports its iterations, its best
+// move is played, and the helpers are stopped once it finishes.
+
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use shakmaty::Move;
+
+use crate::cache::CacheStats;
+use crate::eval::Eval;
+use crate::search::Search;
+
+pub const MAX_THREADS: usize = 256;
+
+pub struct ParallelSearch {
+    main: Search,
+    helpers: Vec<Search>,
+    // Stops the helpers once the main thread finishes.
+    stop: Arc<AtomicBool>,
+}
+
+impl ParallelSearch {
+    // Searches with `threads` threads, `main` and its helpers, evaluating the
+    // positions with an evaluation made by `eval` for every thread.
+    pub fn new(mut main: Search, threads: usize, eval: impl Fn() -> Box<dyn Eval>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        main.set_eval(eval());
+        let helpers = (1..threads.clamp(1, MAX_THREADS))
+            .map(|thread| {
+                let mut helper = main.helper(thread, stop.clone());
+                helper.set_eval(eval());
+                helper
+            })
+            .collect();
+        ParallelSearch {
+            main,
+            helpers,
+            stop,
+        }
+    }
+
+    // Runs the threads until the main thread finishes, see Search::run.
+    pub fn run(&mut self) -> Option<Move> {
+        let (main, stop) = (&mut self.main, &self.stop);
+        thread::scope(|scope| {
+            for helper in &mut self.helpers {
+                scope.spawn(move || helper.run());
+            }
+            let best = main.run();
+            stop.store(true, Ordering::Relaxed);
+            best
+        })
+    }
+
+    // Nodes searched by every thread, the main thread first.
+    pub fn nodes(&self) -> Vec<u64> {
+        iter::once(&self.main)
+            .chain(&self.helpers)
+            .map(Search::nodes)
+            .collect()
+    }
+
+    // Statistics of the caches of the evaluations of all threads.
+    pub fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        let mut total = self.main.cache_stats();
+        for helper in &self.helpers {
+            for (idx, (_, stats)) in helper.cache_stats().into_iter().enumerate() {
+                total[idx].1 += stats;
+            }
+        }
+        total
+    }
+
+    // Depth of the main thread, see Search::depth.
+    pub fn depth(&self) -> u32 {
+        self.main.depth()
+    }
+
+    // Lines of the main thread, see Search::lines.
+    pub fn lines(&self) -> &[(i32, Vec<Move>)] {
+        self.main.lines()
+    }
+
+    // Principal variation of the main thread.
+    pub fn pv(&self) -> &[Move] {
+        self.main.pv()
+    }
+}
@@ -0,0 +1,80 @@
+// Limited playing strength, for UCI_LimitStrength and UCI_Elo: the engine
+// plays like a weaker player by searching less and choosing worse moves.
+//
+// Three knobs weaken the alpha-beta search, all driven by the weakness, which
+// goes from 0 at MAX_ELO to 1 at MIN_ELO:
+// - The nodes of a move are capped, from MAX_NODES down to MIN_NODES on a
+//   logarithmic scale, so that the search misses deeper tactics.
+// - The search reports LINES lines (MultiPV), and the move is picked among
+//   them instead of the best one.
+// - The scores of the lines are perturbed by uniform noise of up to
+//   MAX_NOISE centipawns times the weakness before picking the best, so that
+//   a weak player misjudges close positions often and loses material
+//   sometimes.
+//
+// The Elo scale is approximate, it is not measured against rated opponents.
+
+use preprocessing::rng::Rng;
+use shakmaty::Move;
+
+use crate::search::{MATE, MAX_PLY};
+use crate::time::Limits;
+
+pub const MIN_ELO: u32 = 1000;
+pub const MAX_ELO: u32 = 2400;
+pub const LINES: usize = 4;
+
+const MIN_NODES: f64 = 200.0;
+const MAX_NODES: f64 = 200_000.0;
+const MAX_NOISE: f64 = 300.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Strength {
+    elo: u32,
+}
+
+impl Strength {
+    pub fn new(elo: u32) -> Self {
+        Strength {
+            elo: elo.clamp(MIN_ELO, MAX_ELO),
+        }
+    }
+
+    fn weakness(&self) -> f64 {
+        (MAX_ELO - self.elo) as f64 / (MAX_ELO - MIN_ELO) as f64
+    }
+
+    fn nodes(&self) -> u64 {
+        (MAX_NODES * (MIN_NODES / MAX_NODES).powf(self.weakness())) as u64
+    }
+
+    // The limits of the search with the nodes capped.
+    pub fn limits(&self, limits: Limits) -> Limits {
+        let nodes = limits
+            .nodes
+            .map_or(self.nodes(), |nodes| nodes.min(self.nodes()));
+        Limits {
+            nodes: Some(nodes),
+            ..limits
+        }
+    }
+
+    // The move to play among the lines of the search, best first, None
+    // without lines.
+    pub fn pick(&self, lines: &[(i32, Vec<Move>)], rng: &mut Rng) -> Option<Move> {
+        let noise = MAX_NOISE * self.weakness();
+        lines
+            .iter()
+            .filter_map(|(score, pv)| {
+                // Mates are never missed nor walked into by the noise.
+                let score = if score.abs() >= MATE - MAX_PLY as i32 {
+                    *score as f64
+                } else {
+                    *score as f64 + noise * (2.0 * rng.next_f64() - 1.0)
+                };
+                Some((score, pv.first()?))
+            })
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, m)| m.clone())
+    }
+}
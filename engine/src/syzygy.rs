@@ -0,0 +1,91 @@
+// Syzygy endgame tablebases, loaded with the SyzygyPath UCI option: the
+// directories of the table files, separated by `:` (`;` on Windows) as in
+// other engines.
+//
+// The search probes the WDL tables (win, draw or loss) of the positions right
+// after a capture or a pawn move, which is when their results hold under the
+// fifty-move rule, and scores wins and losses below the mates. At the root,
+// the move keeping the result and winning fastest by DTZ (distance to the
+// next capture or pawn move) is played without a search. The training data
+// excludes the positions with few pieces (see preprocessing::filter), so
+// these are exactly where the networks know least.
+
+use std::io;
+use std::path::Path;
+
+use shakmaty::{Chess, Move, Position};
+use shakmaty_syzygy::{Tablebase, Wdl};
+
+use crate::search::{MATE, MAX_PLY};
+
+// Wins found in the tables score this minus the ply, below the mates.
+pub const TB_WIN: i32 = MATE - MAX_PLY as i32 - 1;
+
+#[cfg(windows)]
+const SEPARATOR: char = ';';
+#[cfg(not(windows))]
+const SEPARATOR: char = ':';
+
+pub struct Syzygy {
+    tables: Tablebase<Chess>,
+}
+
+impl Syzygy {
+    // Loads the tables of the directories, fails if there are none.
+    pub fn load(paths: &str) -> io::Result<Self> {
+        let mut tables = Tablebase::new();
+        let mut num_tables = 0;
+        for path in paths.split(SEPARATOR).filter(|path| !path.is_empty()) {
+            num_tables += tables.add_directory(Path::new(path))?;
+        }
+        if num_tables == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no tables in {paths}"),
+            ));
+        }
+        Ok(Syzygy { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    // Whether the tables have the position, which has no castling rights.
+    fn covers(&self, pos: &Chess) -> bool {
+        pos.board().occupied().count() <= self.max_pieces() && !pos.castles().any()
+    }
+
+    // Score of the position at the ply from the perspective of the side to
+    // move, if the tables have it and the last move was a capture or a pawn
+    // move. Cursed wins and blessed losses are draws by the fifty-move rule.
+    pub fn probe_wdl(&self, pos: &Chess, ply: usize) -> Option<i32> {
+        if pos.halfmoves() != 0 || !self.covers(pos) {
+            return None;
+        }
+        let score = match self.tables.probe_wdl_after_zeroing(pos).ok()? {
+            Wdl::Win => TB_WIN - ply as i32,
+            Wdl::Loss => -TB_WIN + ply as i32,
+            Wdl::CursedWin | Wdl::Draw | Wdl::BlessedLoss => 0,
+        };
+        Some(score)
+    }
+
+    // The move to play in the position and its score, if the tables have it.
+    pub fn probe_root(&self, pos: &Chess) -> Option<(Move, i32)> {
+        if !self.covers(pos) {
+            return None;
+        }
+        let (m, _) = self.tables.best_move(pos).ok()??;
+        let dtz = self.tables.probe_dtz(pos).ok()?.ignore_rounding().0;
+        // The result holds if the next zeroing move comes before the
+        // fifty-move rule.
+        let plies = dtz.abs() + pos.halfmoves() as i32;
+        let score = match dtz {
+            _ if dtz == 0 || plies > 100 => 0,
+            _ if dtz > 0 => TB_WIN,
+            _ => -TB_WIN,
+        };
+        Some((m, score))
+    }
+}
@@ -0,0 +1,243 @@
+// Test suites of EPD positions, for tracking the tactical and positional
+// strength of the engine across versions: `attix testsuite [--movetime <ms>]
+// [--net <file>] <epd>`.
+//
+// Every position is searched for the fixed time and its best move checked
+// against the operations of the EPD line, as in WAC or STS:
+// - `bm` (best moves): solved if the move is one of them,
+// - `am` (avoid moves): solved if the move is none of them,
+// - `c0` with `move=points` pairs (STS): the move earns its points, and the
+//   position is solved with the most points if there is no `bm` or `am`.
+//
+// The moves are in SAN. The report gives every position with its `id`, the
+// solved positions and the STS points, in total and per suite for the `id`s
+// of STS ("STS(v1.0) Undermine.001" is the first position of "STS(v1.0)
+// Undermine").
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use preprocessing::nnue::Network;
+use shakmaty::san::SanPlus;
+use shakmaty::{CastlingMode, Chess, Move};
+
+use crate::eval::{Eval, HandCrafted};
+use crate::nnue::Nnue;
+use crate::perft;
+use crate::search::{self, Options, Search};
+use crate::time::Limits;
+use crate::tt::{self, TranspositionTable};
+
+pub const DEFAULT_MOVETIME: Duration = Duration::from_secs(1);
+
+// A position of the suite with its expected moves.
+struct Test {
+    id: String,
+    pos: Chess,
+    castling_mode: CastlingMode,
+    best: Vec<Move>,
+    avoid: Vec<Move>,
+    points: Vec<(Move, u32)>,
+}
+
+// Solved positions and points of the positions of a suite.
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    positions: u32,
+    solved: u32,
+    points: u32,
+    max_points: u32,
+}
+
+impl Tally {
+    fn add(&mut self, solved: bool, points: u32, max_points: u32) {
+        self.positions += 1;
+        self.solved += u32::from(solved);
+        self.points += points;
+        self.max_points += max_points;
+    }
+
+    fn print(&self, name: &str) {
+        print!("{name}: solved {}/{}", self.solved, self.positions);
+        if self.max_points > 0 {
+            print!(", points {}/{}", self.points, self.max_points);
+        }
+        println!();
+    }
+}
+
+fn invalid(line: usize, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {message}", line + 1),
+    )
+}
+
+// The operations of an EPD line after the position, `opcode operands;`, with
+// the quotes of the operands removed.
+fn operations(text: &str) -> Vec<(&str, &str)> {
+    text.split(';')
+        .filter_map(|operation| {
+            let operation = operation.trim();
+            let (opcode, operands) = operation.split_once(' ').unwrap_or((operation, ""));
+            (!opcode.is_empty()).then_some((opcode, operands.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+fn parse_san(pos: &Chess, san: &str, line: usize) -> io::Result<Move> {
+    SanPlus::from_ascii(san.as_bytes())
+        .ok()
+        .and_then(|san| san.san.to_move(pos).ok())
+        .ok_or_else(|| invalid(line, format!("illegal move {san}")))
+}
+
+fn parse_test(text: &str, line: usize) -> io::Result<Test> {
+    let fields: Vec<&str> = text.splitn(5, ' ').collect();
+    let [board, turn, castling, en_passant, rest] = fields[..] else {
+        return Err(invalid(line, "expected a position and operations"));
+    };
+    let fen = format!("{board} {turn} {castling} {en_passant} 0 1");
+    let (pos, castling_mode) = perft::parse_fen(&fen).map_err(|err| invalid(line, err))?;
+    let mut test = Test {
+        id: format!("#{}", line + 1),
+        pos,
+        castling_mode,
+        best: Vec::new(),
+        avoid: Vec::new(),
+        points: Vec::new(),
+    };
+    for (opcode, operands) in operations(rest) {
+        match opcode {
+            "id" => test.id = operands.to_string(),
+            "bm" | "am" => {
+                for san in operands.split_whitespace() {
+                    let m = parse_san(&test.pos, san, line)?;
+                    match opcode {
+                        "bm" => test.best.push(m),
+                        _ => test.avoid.push(m),
+                    }
+                }
+            }
+            "c0" => {
+                // Other suites use c0 for free text.
+                for pair in operands.split(',') {
+                    if let Some((san, points)) = pair.trim().split_once('=') {
+                        if let Ok(points) = points.trim().parse() {
+                            test.points.push((parse_san(&test.pos, san, line)?, points));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(test)
+}
+
+fn read_suite(path: &Path) -> io::Result<Vec<Test>> {
+    let mut tests = Vec::new();
+    for (idx, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        tests.push(parse_test(line, idx)?);
+    }
+    Ok(tests)
+}
+
+// The suite of an STS `id`: the `id` without the number after the last dot.
+fn suite_name(id: &str) -> &str {
+    match id.rsplit_once('.') {
+        Some((name, number)) if number.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => "",
+    }
+}
+
+fn best_move(
+    test: &Test,
+    movetime: Duration,
+    tt: &Arc<TranspositionTable>,
+    network: Option<&Arc<Network>>,
+) -> Option<Move> {
+    let limits = Limits {
+        time: Some(movetime),
+        ..Limits::default()
+    };
+    let mut search = Search::new(
+        test.pos.clone(),
+        vec![search::hash(&test.pos)],
+        limits,
+        tt.clone(),
+        Arc::new(AtomicBool::new(false)),
+        test.castling_mode,
+        Options::default(),
+    );
+    let eval: Box<dyn Eval> = match network {
+        Some(network) => Box::new(Nnue::new(network.clone())),
+        None => Box::new(HandCrafted::default()),
+    };
+    search.set_eval(eval);
+    search.set_silent();
+    search.run()
+}
+
+// Searches every position of the suite and prints whether it was solved,
+// then the totals.
+pub fn run<P: AsRef<Path>>(
+    path: P,
+    movetime: Duration,
+    network: Option<Network>,
+) -> io::Result<()> {
+    let tests = read_suite(path.as_ref())?;
+    let network = network.map(Arc::new);
+    let tt = Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB));
+    let mut total = Tally::default();
+    let mut suites: BTreeMap<&str, Tally> = BTreeMap::new();
+    for test in &tests {
+        // Every position is searched from scratch.
+        tt.clear();
+        let Some(m) = best_move(test, movetime, &tt, network.as_ref()) else {
+            println!("{}: no legal moves, skipped", test.id);
+            continue;
+        };
+        let points = test
+            .points
+            .iter()
+            .find(|(candidate, _)| *candidate == m)
+            .map_or(0, |(_, points)| *points);
+        let max_points = test.points.iter().map(|(_, points)| *points).max();
+        let solved = match (test.best.is_empty(), test.avoid.is_empty()) {
+            (true, true) => max_points.is_some_and(|max| points == max),
+            (no_best, no_avoid) => {
+                (no_best || test.best.contains(&m)) && (no_avoid || !test.avoid.contains(&m))
+            }
+        };
+        let san = SanPlus::from_move(test.pos.clone(), &m);
+        let verdict = if solved { "solved" } else { "failed" };
+        match max_points {
+            Some(max) => println!("{}: {san} {verdict}, {points}/{max} points", test.id),
+            None => println!("{}: {san} {verdict}", test.id),
+        }
+        let max_points = max_points.unwrap_or(0);
+        total.add(solved, points, max_points);
+        suites
+            .entry(suite_name(&test.id))
+            .or_default()
+            .add(solved, points, max_points);
+    }
+    println!();
+    if suites.len() > 1 {
+        for (name, tally) in suites.iter().filter(|(name, _)| !name.is_empty()) {
+            tally.print(name);
+        }
+    }
+    total.print("Total");
+    Ok(())
+}
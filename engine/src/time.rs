@@ -0,0 +1,78 @@
+// Limits of a search given by the `go` command and the time allocated to a
+// move from the clock.
+//
+// The clock gives the move a soft and a hard limit. The share of the
+// remaining time (and most of the increment) is the soft limit: the alpha-beta
+// search starts no iteration after it, and extends it while the best move
+// changes between the iterations or the score drops (see search). The hard
+// limit, a few shares but never the whole clock, interrupts the search.
+
+use std::time::Duration;
+
+// Time kept in reserve for the communication with the GUI.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+// Expected number of moves left in the game if the time control does not
+// say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// The hard limit is this many soft limits, and at most this fraction of the
+// remaining time unless the soft limit is more.
+const HARD_RATIO: u32 = 4;
+const MAX_HARD_FRACTION: f64 = 0.5;
+
+// Parameters of the `go` command.
+#[derive(Debug, Default, Clone)]
+pub struct Go {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // Remaining time and increment of the side to move.
+    pub time: Option<Duration>,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    // Search until `stop`.
+    pub infinite: bool,
+    // Count the legal moves to the depth instead of searching, see perft.
+    pub perft: Option<u32>,
+    // Search for a mate in at most this many moves, see mate.
+    pub mate: Option<u32>,
+}
+
+// When the search has to stop.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    // The hard limit of the time.
+    pub time: Option<Duration>,
+    // The time after which the search stops at the end of an iteration,
+    // before the extensions.
+    pub soft: Option<Duration>,
+}
+
+impl Go {
+    pub fn limits(&self) -> Limits {
+        if self.infinite {
+            return Limits::default();
+        }
+        let (time, soft) = match (self.movetime, self.time) {
+            // The whole time of the move is used.
+            (Some(movetime), _) => (Some(movetime.saturating_sub(MOVE_OVERHEAD)), None),
+            (None, Some(time)) => {
+                let available = time.saturating_sub(MOVE_OVERHEAD);
+                let moves = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+                let soft = (available / moves + self.increment * 3 / 4).min(available);
+                let hard = (soft * HARD_RATIO)
+                    .min(available.mul_f64(MAX_HARD_FRACTION))
+                    .max(soft);
+                (Some(hard), Some(soft))
+            }
+            (None, None) => (None, None),
+        };
+        Limits {
+            depth: self.depth,
+            nodes: self.nodes,
+            time,
+            soft,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Breakdown of the static evaluation of a position, printed by
+// `attix eval [--net <file>] [fen]` to debug the evaluation of specific
+// positions.
+//
+// The hand-crafted evaluation is linear in its parameters, so every term is
+// listed with its coefficient in the position and its contribution. A network
+// has no terms, so the contribution of every piece is estimated instead as
+// the change of the evaluation when it is taken off the board (kings stay),
+// and the accumulators of both perspectives are printed.
+
+use preprocessing::eval::{self, Params};
+use preprocessing::nnue::Network;
+use preprocessing::phase::{self, MAX_SCORE};
+use shakmaty::{Board, Chess, Color, Position, Rank, Role, Square};
+
+use crate::nnue;
+
+// Accumulator values per printed line.
+const VALUES_PER_LINE: usize = 16;
+
+// From the perspective of white to the one of the side to move.
+fn relative(score: i32, turn: Color) -> i32 {
+    match turn {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+fn hand_crafted(pos: &Chess) {
+    let board = pos.board();
+    let params = Params::default();
+    let phase = phase::board_score(board) as f32 / MAX_SCORE as f32;
+    println!("Hand-crafted evaluation, phase {phase:.2} (1 is the middlegame):");
+    println!(
+        "  {:<24} {:>12} {:>8} {:>13}",
+        "term", "coefficient", "value", "contribution"
+    );
+    let coefficients = eval::coefficients(board);
+    for ((name, coefficient), value) in Params::NAMES.iter().zip(&coefficients).zip(params.to_vec())
+    {
+        if *coefficient != 0.0 {
+            let contribution = coefficient * value as f32;
+            println!("  {name:<24} {coefficient:>12.2} {value:>8} {contribution:>13.1}");
+        }
+    }
+    let white = params.evaluate(board);
+    println!(
+        "  white: {white} cp, side to move: {} cp",
+        relative(white, pos.turn())
+    );
+}
+
+// Evaluation of the board by the network in centipawns from the perspective
+// of `turn`.
+fn network_eval(network: &Network, board: &Board, turn: Color) -> i32 {
+    let us = network.transformer.refresh(board, turn);
+    let them = network.transformer.refresh(board, !turn);
+    nnue::centipawns(network.forward(&us, &them))
+}
+
+fn network(pos: &Chess, network: &Network) {
+    let (board, turn) = (pos.board(), pos.turn());
+    let score = network_eval(network, board, turn);
+    println!("NNUE evaluation ({:?}):", network.transformer.set());
+    println!(
+        "  side to move: {score} cp, white: {} cp",
+        relative(score, turn)
+    );
+
+    println!("  Change of the evaluation (white) without the piece:");
+    for rank in Rank::ALL.into_iter().rev() {
+        let mut line = String::from("  ");
+        for square in Square::ALL
+            .into_iter()
+            .filter(|square| square.rank() == rank)
+        {
+            let cell = match board.piece_at(square) {
+                Some(piece) if piece.role != Role::King => {
+                    let mut without = board.clone();
+                    without.discard_piece_at(square);
+                    let change = score - network_eval(network, &without, turn);
+                    format!("{}{:+}", piece.char(), relative(change, turn))
+                }
+                Some(piece) => piece.char().to_string(),
+                None => ".".to_string(),
+            };
+            line.push_str(&format!("{cell:>8}"));
+        }
+        println!("{line}");
+    }
+
+    for perspective in Color::ALL {
+        let features = network
+            .transformer
+            .set()
+            .active_features(board, perspective);
+        let accumulator = network.transformer.refresh(board, perspective);
+        println!(
+            "  Accumulator of {perspective:?} ({} active features):",
+            features.len()
+        );
+        for values in accumulator.chunks(VALUES_PER_LINE) {
+            let values: Vec<String> = values.iter().map(|value| format!("{value:>6}")).collect();
+            println!("  {}", values.join(""));
+        }
+    }
+}
+
+pub fn run(pos: &Chess, net: Option<&Network>) {
+    hand_crafted(pos);
+    if let Some(net) = net {
+        println!();
+        network(pos, net);
+    }
+}
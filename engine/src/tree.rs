@@ -0,0 +1,269 @@
+// Recording of the search tree for debugging the search: `attix tree [--depth
+// <n>] [--plies <n>] [--format json|dot] [fen]` searches the position to the
+// depth and dumps the nodes of the last iteration up to the plies below the
+// root, as JSON or as a Graphviz graph (`dot -Tsvg`).
+//
+// Every node has the label of the move leading to it, with how it was
+// searched (reduced, re-searched, extended), the depth and the window it was
+// searched with, the score and whether it is exact or a bound, and the
+// decisions taken at the node: cutoffs by the transposition table, the
+// tablebases, a null move or a move, the singular extension of the move of the
+// transposition table, draws, mates and the quiescence search at the leaves.
+// The null move searches and the verification searches of null moves and
+// singular extensions are children of the node, labeled as such. The
+// aspiration re-searches and the lines of MultiPV are several roots.
+//
+// The quiescence searches are not recorded, and neither are the nodes beyond
+// MAX_NODES, to keep the dumps readable.
+
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use shakmaty::{CastlingMode, Chess};
+
+use crate::search::{self, Options, Search};
+use crate::time::Limits;
+use crate::tt::{self, TranspositionTable};
+
+pub const DEFAULT_DEPTH: u32 = 4;
+pub const DEFAULT_PLIES: usize = 3;
+const MAX_NODES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Dot,
+}
+
+impl std::str::FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(format: &str) -> io::Result<Self> {
+        match format {
+            "json" => Ok(Format::Json),
+            "dot" => Ok(Format::Dot),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown format {format}, expected json or dot"),
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    label: String,
+    depth: u32,
+    alpha: i32,
+    beta: i32,
+    // None if the search was stopped in the node.
+    score: Option<i32>,
+    notes: Vec<&'static str>,
+    children: Vec<usize>,
+}
+
+impl Node {
+    fn bound(&self) -> &'static str {
+        match self.score {
+            Some(score) if score <= self.alpha => "upper",
+            Some(score) if score >= self.beta => "lower",
+            Some(_) => "exact",
+            None => "stopped",
+        }
+    }
+}
+
+// The nodes of the searched tree up to a number of plies below the roots.
+pub struct SearchTree {
+    plies: usize,
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    // The nodes of the current line, None for the nodes which are not
+    // recorded.
+    stack: Vec<Option<usize>>,
+    // The label of the next node.
+    label: Option<String>,
+}
+
+impl SearchTree {
+    pub fn new(plies: usize) -> Self {
+        SearchTree {
+            plies,
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            stack: Vec::new(),
+            label: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.roots.clear();
+        self.stack.clear();
+        self.label = None;
+    }
+
+    // Labels the next node, "root" if it is not.
+    pub fn label(&mut self, label: String) {
+        self.label = Some(label);
+    }
+
+    // Starts a node below the current one.
+    pub fn enter(&mut self, depth: u32, alpha: i32, beta: i32) {
+        let label = self.label.take().unwrap_or_else(|| "root".to_string());
+        let parent = self.stack.last().copied();
+        let recorded = self.stack.len() <= self.plies
+            && self.nodes.len() < MAX_NODES
+            && !matches!(parent, Some(None));
+        if !recorded {
+            self.stack.push(None);
+            return;
+        }
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            label,
+            depth,
+            alpha,
+            beta,
+            score: None,
+            notes: Vec::new(),
+            children: Vec::new(),
+        });
+        match parent.flatten() {
+            Some(parent) => self.nodes[parent].children.push(idx),
+            None => self.roots.push(idx),
+        }
+        self.stack.push(Some(idx));
+    }
+
+    // Notes a decision taken at the current node.
+    pub fn note(&mut self, note: &'static str) {
+        if let Some(Some(idx)) = self.stack.last() {
+            self.nodes[*idx].notes.push(note);
+        }
+    }
+
+    // Finishes the current node with its score.
+    pub fn leave(&mut self, score: i32) {
+        if let Some(Some(idx)) = self.stack.pop() {
+            self.nodes[idx].score = Some(score);
+        }
+    }
+
+    fn write_json(&self, out: &mut String, idx: usize, indent: usize) {
+        let node = &self.nodes[idx];
+        let pad = "  ".repeat(indent);
+        let score = node
+            .score
+            .map_or("null".to_string(), |score| score.to_string());
+        let notes: Vec<String> = node
+            .notes
+            .iter()
+            .map(|note| format!("\"{note}\""))
+            .collect();
+        let _ = write!(
+            out,
+            "{pad}{{\"move\": \"{}\", \"depth\": {}, \"alpha\": {}, \"beta\": {}, \
+             \"score\": {score}, \"bound\": \"{}\", \"notes\": [{}], \"children\": [",
+            node.label,
+            node.depth,
+            node.alpha,
+            node.beta,
+            node.bound(),
+            notes.join(", "),
+        );
+        if node.children.is_empty() {
+            out.push_str("]}");
+            return;
+        }
+        out.push('\n');
+        for (idx, &child) in node.children.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(",\n");
+            }
+            self.write_json(out, child, indent + 1);
+        }
+        let _ = write!(out, "\n{pad}]}}");
+    }
+
+    // The roots as a JSON array of nested nodes.
+    pub fn to_json(&self) -> String {
+        let mut out = "[\n".to_string();
+        for (idx, &root) in self.roots.iter().enumerate() {
+            if idx > 0 {
+                out.push_str(",\n");
+            }
+            self.write_json(&mut out, root, 1);
+        }
+        out.push_str("\n]\n");
+        out
+    }
+
+    // The nodes as a Graphviz graph, the nodes failing high in red and low
+    // in blue.
+    pub fn to_dot(&self) -> String {
+        let mut out = "digraph search {\n  node [shape=box, fontname=monospace];\n".to_string();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let score = node
+                .score
+                .map_or("-".to_string(), |score| score.to_string());
+            let mut label = format!(
+                "{}\\nd{} ({}, {})\\n{score} {}",
+                node.label,
+                node.depth,
+                node.alpha,
+                node.beta,
+                node.bound()
+            );
+            for note in &node.notes {
+                let _ = write!(label, "\\n{note}");
+            }
+            let color = match node.bound() {
+                "lower" => "red",
+                "upper" => "blue",
+                _ => "black",
+            };
+            let _ = writeln!(out, "  n{idx} [label=\"{label}\", color={color}];");
+            for child in &node.children {
+                let _ = writeln!(out, "  n{idx} -> n{child};");
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// Searches the position to the depth with a single thread and prints the tree
+// of the last iteration.
+pub fn run(
+    pos: Chess,
+    castling_mode: CastlingMode,
+    depth: u32,
+    plies: usize,
+    format: Format,
+) -> io::Result<()> {
+    let limits = Limits {
+        depth: Some(depth),
+        ..Limits::default()
+    };
+    let mut search = Search::new(
+        pos.clone(),
+        vec![search::hash(&pos)],
+        limits,
+        Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+        Arc::new(AtomicBool::new(false)),
+        castling_mode,
+        Options::default(),
+    );
+    search.set_tree(SearchTree::new(plies));
+    search.set_silent();
+    search.run();
+    let tree = search.tree().expect("the tree is set");
+    match format {
+        Format::Json => print!("{}", tree.to_json()),
+        Format::Dot => print!("{}", tree.to_dot()),
+    }
+    Ok(())
+}
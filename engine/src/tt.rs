@@ -0,0 +1,290 @@
+// Transposition table: results of the searches of positions, shared by all
+// searches of a game and looked up by the Zobrist hash of the position.
+//
+// The entries are two 64-bit atomics, the data and the hash XORed with the
+// data, which are written and read without locking. An entry torn by a
+// concurrent write fails the check of the hash and is treated as missing, so
+// the table can be shared by searching threads without locks.
+//
+// The data packs the move (16 bits), the score (16), the depth (8), the
+// bound (2), a bit set in all entries, which tells them from empty slots, and
+// the generation (8) of the search storing it. Mate scores are stored relative
+// to the position of the entry instead of the root, see `to_tt` and `from_tt`.
+//
+// A hash picks a bucket of four entries filling a 64-byte cache line, so that
+// a lookup reads memory once. A position is stored in the entry of the bucket
+// it has already, or else in an empty entry or the least valuable one: the
+// shallowest, with the entries of earlier searches (older generations) losing
+// value with their age. The search prefetches the bucket of a position as soon
+// as it has the hash, while it updates the evaluation for the position.
+
+use std::array;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use shakmaty::zobrist::Zobrist64;
+use shakmaty::Move;
+
+use crate::search::{MATE, MAX_PLY};
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+pub const MAX_SIZE_MB: usize = 65_536;
+const BUCKET_ENTRIES: usize = 4;
+// The depth an entry loses per search since it was stored, when it competes
+// for replacement.
+const AGE_WEIGHT: i32 = 8;
+
+// Whether the score of an entry is exact or a bound of the real score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The real score is at least the stored score (a beta cutoff).
+    Lower,
+    // The real score is at most the stored score (no move raised alpha).
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    // Encoded with `encode_move`, 0 without a move.
+    pub best_move: u16,
+    pub score: i32,
+    pub depth: u32,
+    pub bound: Bound,
+}
+
+// From and to squares and the promotion, which identify the legal moves of a
+// position: squares by their index (6 bits each), the promotion by the role
+// (1 for pawns to 6 for kings). Castling is the king capturing its rook, like
+// in shakmaty. The highest bit is set, so that no move is 0.
+pub fn encode_move(m: &Move) -> u16 {
+    let from = m.from().map_or(0, |square| square as u16);
+    let promotion = m.promotion().map_or(0, |role| role as u16);
+    (1 << 15) | (promotion << 12) | (from << 6) | m.to() as u16
+}
+
+impl Entry {
+    fn pack(&self, generation: u8) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let score = self.score as i16 as u16 as u64;
+        let depth = self.depth.min(u8::MAX as u32) as u64;
+        let generation = generation as u64;
+        self.best_move as u64
+            | (score << 16)
+            | (depth << 32)
+            | (bound << 40)
+            | (1 << 42)
+            | (generation << 43)
+    }
+
+    fn unpack(data: u64) -> Self {
+        let bound = match (data >> 40) & 3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Entry {
+            best_move: data as u16,
+            score: (data >> 16) as u16 as i16 as i32,
+            depth: (data >> 32) as u8 as u32,
+            bound,
+        }
+    }
+}
+
+// Mate scores count the plies from the root, the table stores them counted
+// from the position of the entry, which may be reached at another ply.
+pub fn to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score <= -MATE + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score <= -MATE + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// The hash XORed with the data and the data of every entry of a bucket.
+#[repr(align(64))]
+struct Bucket([[AtomicU64; 2]; BUCKET_ENTRIES]);
+
+fn generation(data: u64) -> u8 {
+    (data >> 43) as u8
+}
+
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    // Incremented by every search, see `new_search`.
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let len = (size_mb.clamp(1, MAX_SIZE_MB) << 20) / size_of::<Bucket>();
+        TranspositionTable {
+            buckets: (0..len)
+                .map(|_| Bucket(array::from_fn(|_| [AtomicU64::new(0), AtomicU64::new(0)])))
+                .collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for [key, data] in self.buckets.iter().flat_map(|bucket| &bucket.0) {
+            key.store(0, Ordering::Relaxed);
+            data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // Ages the entries stored so far, called before every search.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, hash: Zobrist64) -> &Bucket {
+        // The high bits of the product are uniform over the length.
+        let idx = (hash.0 as u128 * self.buckets.len() as u128) >> 64;
+        &self.buckets[idx as usize]
+    }
+
+    // Loads the bucket of the position into the cache ahead of `probe` and
+    // `store`.
+    pub fn prefetch(&self, hash: Zobrist64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let bucket: *const Bucket = self.bucket(hash);
+            // SAFETY: prefetching has no effect besides the cache and the
+            // pointer is to the bucket.
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(bucket.cast()) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = hash;
+    }
+
+    pub fn probe(&self, hash: Zobrist64) -> Option<Entry> {
+        self.bucket(hash).0.iter().find_map(|[key, data]| {
+            let data = data.load(Ordering::Relaxed);
+            // Empty slots never match, the packed entries are not 0.
+            (data != 0 && key.load(Ordering::Relaxed) ^ data == hash.0).then(|| Entry::unpack(data))
+        })
+    }
+
+    // Replaces the entry of another position or of a shallower search of the
+    // same position, exact scores and the entries of earlier searches are
+    // always replaced.
+    pub fn store(&self, hash: Zobrist64, entry: Entry) {
+        let current = self.generation.load(Ordering::Relaxed);
+        let bucket = &self.bucket(hash).0;
+        let mut replaced = &bucket[0];
+        let mut replaced_value = i32::MAX;
+        let mut same = false;
+        for slot in bucket {
+            let [key, data] = slot;
+            let old = data.load(Ordering::Relaxed);
+            if old != 0 && key.load(Ordering::Relaxed) ^ old == hash.0 {
+                (replaced, same) = (slot, true);
+                break;
+            }
+            let value = if old == 0 {
+                i32::MIN
+            } else {
+                let age = current.wrapping_sub(generation(old)) as i32;
+                Entry::unpack(old).depth as i32 - AGE_WEIGHT * age
+            };
+            if value < replaced_value {
+                (replaced, replaced_value) = (slot, value);
+            }
+        }
+        let [key, data] = replaced;
+        let old = data.load(Ordering::Relaxed);
+        if same
+            && entry.bound != Bound::Exact
+            && generation(old) == current
+            && Entry::unpack(old).depth > entry.depth
+        {
+            return;
+        }
+        let mut entry = entry;
+        // Keep the move of the earlier search if this one has none.
+        if same && entry.best_move == 0 {
+            entry.best_move = old as u16;
+        }
+        let new = entry.pack(current);
+        key.store(hash.0 ^ new, Ordering::Relaxed);
+        data.store(new, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: Zobrist64 = Zobrist64(0x0123_4567_89ab_cdef);
+
+    fn entry(best_move: u16, score: i32, depth: u32, bound: Bound) -> Entry {
+        Entry {
+            best_move,
+            score,
+            depth,
+            bound,
+        }
+    }
+
+    #[test]
+    fn probe_returns_stored_entry() {
+        let tt = TranspositionTable::new(1);
+        let entries = [
+            entry(0x8123, 35, 7, Bound::Exact),
+            entry(0x8fff, -MATE + 12, 255, Bound::Upper),
+            entry(0, MATE - 3, 0, Bound::Lower),
+        ];
+        // The high bits pick the slot.
+        let hash = |idx: u64| Zobrist64(HASH.0 ^ (idx << 60));
+        for (idx, &stored) in (0..).zip(&entries) {
+            assert_eq!(tt.probe(hash(idx)), None);
+            tt.store(hash(idx), stored);
+        }
+        for (idx, &stored) in (0..).zip(&entries) {
+            assert_eq!(tt.probe(hash(idx)), Some(stored));
+        }
+        // Another position in the same slot fails the check of the hash.
+        assert_eq!(tt.probe(Zobrist64(HASH.0 ^ 1)), None);
+        tt.clear();
+        assert_eq!(tt.probe(HASH), None);
+    }
+
+    #[test]
+    fn store_keeps_deeper_bounds_and_moves() {
+        let tt = TranspositionTable::new(1);
+        tt.store(HASH, entry(0x8123, 50, 8, Bound::Lower));
+        tt.store(HASH, entry(0x8456, 10, 3, Bound::Upper));
+        assert_eq!(tt.probe(HASH), Some(entry(0x8123, 50, 8, Bound::Lower)));
+        // Exact scores replace deeper entries, the move is kept without one.
+        tt.store(HASH, entry(0, 20, 3, Bound::Exact));
+        assert_eq!(tt.probe(HASH), Some(entry(0x8123, 20, 3, Bound::Exact)));
+    }
+
+    #[test]
+    fn mate_scores_are_relative_to_the_entry() {
+        // A mate 5 plies from the root found at ply 3 is 2 plies from the
+        // position, which is 9 plies from the root when reached at ply 7.
+        assert_eq!(to_tt(MATE - 5, 3), MATE - 2);
+        assert_eq!(from_tt(to_tt(MATE - 5, 3), 7), MATE - 9);
+        assert_eq!(from_tt(to_tt(-MATE + 5, 3), 7), -MATE + 9);
+        assert_eq!(from_tt(to_tt(120, 3), 7), 120);
+    }
+}
@@ -0,0 +1,605 @@
+// The UCI protocol: commands are read line by line from the standard input,
+// the responses are printed to the standard output. The search runs on its
+// own thread, so that `stop`, `isready` and `quit` are answered while
+// searching.
+//
+// Supported commands: uci, isready, setoption, ucinewgame, position, go
+// (depth, nodes, movetime, wtime, btime, winc, binc, movestogo, infinite, mate
+// searching for forced mates instead (see mate), and the non-standard perft,
+// see perft), stop and quit, and the non-standard bench [depth] (see bench).
+// Unknown commands are ignored, as the protocol asks.
+//
+// The SearchMode option chooses between the alpha-beta search (see search)
+// and the Monte Carlo tree search (see mcts), EvalFile loads an NNUE network
+// written by the quantize command to evaluate the positions with instead of
+// the hand-crafted evaluation or with the embedded network, `embedded`, the
+// default of builds with one (see nnue), Threads searches with several
+// threads (see smp), MultiPV reports that many best lines and SyzygyPath
+// loads endgame tablebases (see syzygy), Contempt makes the alpha-beta search
+// avoid draws, or seek them if negative. With OwnBook, the moves of the
+// Polyglot book of the Book option are played without searching, picked at
+// random by their weights. UCI_LimitStrength with UCI_Elo weakens the
+// alpha-beta search (see strength). The tree search evaluates MinibatchSize
+// positions at once, with the network of WeightsFile if the engine is built
+// with the onnx feature (see onnx), on the device of Backend with the cuda
+// feature. Besides the advertised options, the hidden check options NullMove,
+// LMR, Aspiration and Singular turn the selective techniques, the aspiration
+// windows and the singular extensions of the alpha-beta search off, for A/B
+// testing in matches, and the hidden spin options SingularMinDepth and
+// SingularMargin set the parameters of the singular extensions for tuning
+// (see search::Options). All options are declared in `options()`, see
+// options.
+
+use std::io::{self, BufRead};
+#[cfg(feature = "onnx")]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use preprocessing::nnue::Network;
+use preprocessing::polyglot::Book;
+use preprocessing::rng::{self, Rng};
+use shakmaty::fen::Fen;
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Color, Move, Position};
+
+use crate::batch::BatchQueue;
+use crate::bench;
+use crate::eval::{Eval, HandCrafted};
+use crate::mate::MateSearch;
+use crate::mcts::{self, HeuristicNetwork, Mcts};
+use crate::nnue::{self, Nnue};
+#[cfg(feature = "onnx")]
+use crate::onnx::{Backend, OnnxNetwork};
+use crate::options::Registry;
+use crate::perft;
+use crate::search::{self, Options, Search};
+use crate::smp::{self, ParallelSearch};
+use crate::strength::{self, Strength};
+use crate::syzygy::Syzygy;
+use crate::time::Go;
+use crate::tt::{self, TranspositionTable};
+
+const NAME: &str = "attix";
+const AUTHOR: &str = "Kirill Bobyrev";
+// Limit of the Contempt option in centipawns: at most a pawn either way.
+const MAX_CONTEMPT: i32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchMode {
+    AlphaBeta,
+    Mcts,
+}
+
+struct Engine {
+    // Positions of the game, the current one last, for the detection of
+    // repetitions and the history planes of the networks.
+    game: Vec<Chess>,
+    // The UCI_Chess960 option, and the notation of the castling moves of the
+    // game: king takes rook with the option or in Chess960 positions.
+    chess960: bool,
+    castling_mode: CastlingMode,
+    mode: SearchMode,
+    options: Options,
+    // Threads and lines of the alpha-beta search.
+    threads: usize,
+    multi_pv: usize,
+    // The network of EvalFile, the hand-crafted evaluation without one.
+    network: Option<Arc<Network>>,
+    tablebases: Option<Arc<Syzygy>>,
+    book: Option<Book>,
+    own_book: bool,
+    // Contempt of the alpha-beta search in centipawns.
+    contempt: i32,
+    // UCI_Elo if UCI_LimitStrength is on.
+    strength: Option<Strength>,
+    elo: u32,
+    // Picks the book moves and the moves of the limited strength.
+    rng: Rng,
+    // The lc0 network of WeightsFile for the tree search, loaded on the
+    // backend.
+    #[cfg(feature = "onnx")]
+    weights_file: Option<PathBuf>,
+    #[cfg(feature = "onnx")]
+    backend: Backend,
+    #[cfg(feature = "onnx")]
+    weights: Option<BatchQueue>,
+    batch_size: usize,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+}
+
+impl Engine {
+    fn new() -> Self {
+        Engine {
+            game: vec![Chess::default()],
+            chess960: false,
+            castling_mode: CastlingMode::Standard,
+            mode: SearchMode::AlphaBeta,
+            options: Options::default(),
+            threads: 1,
+            multi_pv: 1,
+            network: nnue::embedded().map(Arc::new),
+            tablebases: None,
+            book: None,
+            own_book: false,
+            contempt: 0,
+            strength: None,
+            elo: strength::MAX_ELO,
+            rng: Rng::new(rng::random_seed()),
+            #[cfg(feature = "onnx")]
+            weights_file: None,
+            #[cfg(feature = "onnx")]
+            backend: Backend::Cpu,
+            #[cfg(feature = "onnx")]
+            weights: None,
+            batch_size: mcts::DEFAULT_BATCH_SIZE,
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+        }
+    }
+
+    fn pos(&self) -> &Chess {
+        self.game.last().expect("the game has a position")
+    }
+
+    // Stops the running search, which prints its best move.
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            search.thread().unpark();
+            search.join().expect("the search does not panic");
+        }
+    }
+
+    // Handles a command, returns false on `quit`.
+    fn handle(&mut self, line: &str, options: &Registry<Engine>) -> bool {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name {NAME}");
+                println!("id author {AUTHOR}");
+                options.print();
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("setoption") => {
+                // setoption name <name> [value <value>], the name may have spaces.
+                let tokens: Vec<&str> = tokens.collect();
+                let value_idx = tokens.iter().position(|&token| token == "value");
+                let name = tokens[..value_idx.unwrap_or(tokens.len())]
+                    .iter()
+                    .skip_while(|&&token| token == "name")
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let value = value_idx.map(|idx| tokens[idx + 1..].join(" "));
+                if let Err(err) = options.set(self, &name, value.as_deref()) {
+                    println!("info string {err}");
+                }
+            }
+            Some("ucinewgame") => {
+                self.stop();
+                self.tt.clear();
+                self.game = vec![Chess::default()];
+            }
+            Some("position") => {
+                self.stop();
+                match parse_position(tokens) {
+                    Ok((game, castling_mode)) => {
+                        self.game = game;
+                        self.castling_mode = if self.chess960 {
+                            CastlingMode::Chess960
+                        } else {
+                            castling_mode
+                        };
+                    }
+                    Err(err) => println!("info string invalid position: {err}"),
+                }
+            }
+            Some("go") => {
+                self.stop();
+                self.go(parse_go(tokens, self.pos().turn()));
+            }
+            Some("stop") => self.stop(),
+            Some("bench") => {
+                self.stop();
+                let depth = tokens.next().and_then(|depth| depth.parse().ok());
+                let depth = depth.unwrap_or(bench::DEFAULT_DEPTH);
+                if let Err(err) = bench::run(depth, self.threads) {
+                    println!("info string bench failed: {err}");
+                }
+            }
+            Some("quit") => {
+                self.stop();
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn eval(&self) -> Box<dyn Eval> {
+        match &self.network {
+            Some(network) => Box::new(Nnue::new(network.clone())),
+            None => Box::new(HandCrafted::default()),
+        }
+    }
+
+    #[cfg(feature = "onnx")]
+    fn load_weights(&mut self) {
+        self.stop();
+        self.weights = None;
+        let Some(path) = &self.weights_file else {
+            return;
+        };
+        match OnnxNetwork::load(path, self.backend) {
+            Ok(network) => self.weights = Some(BatchQueue::new(Box::new(network))),
+            Err(err) => println!("info string failed to load {}: {err}", path.display()),
+        }
+    }
+
+    // The queue of the network of the tree search. The network of WeightsFile
+    // is loaded once and keeps its queue between the searches.
+    fn queue(&self) -> BatchQueue {
+        #[cfg(feature = "onnx")]
+        if let Some(weights) = &self.weights {
+            return weights.clone();
+        }
+        BatchQueue::new(Box::new(HeuristicNetwork::new(self.eval())))
+    }
+
+    // A move of the book for the current position if OwnBook is on. Not for
+    // infinite searches, which are for analysis.
+    fn book_move(&mut self, go: &Go) -> Option<Move> {
+        if !self.own_book || go.infinite {
+            return None;
+        }
+        let pos = self.game.last().expect("the game has a position");
+        self.book.as_ref()?.pick(pos, &mut self.rng)
+    }
+
+    fn go(&mut self, go: Go) {
+        if let Some(depth) = go.perft {
+            if let Err(err) = perft::divide(self.pos(), depth, self.castling_mode) {
+                println!("info string perft failed: {err}");
+            }
+            return;
+        }
+        if let Some(m) = self.book_move(&go) {
+            println!("info string book move");
+            println!("bestmove {}", m.to_uci(self.castling_mode));
+            return;
+        }
+        self.stop.store(false, Ordering::Relaxed);
+        let search: Box<dyn FnOnce() -> Option<Move> + Send> = match (go.mate, self.mode) {
+            (Some(moves), _) => {
+                let mut mate = MateSearch::new(
+                    self.pos().clone(),
+                    moves,
+                    go.limits(),
+                    self.stop.clone(),
+                    self.castling_mode,
+                );
+                Box::new(move || mate.run())
+            }
+            (None, SearchMode::AlphaBeta) => {
+                let (limits, multi_pv) = match self.strength {
+                    Some(strength) => (
+                        strength.limits(go.limits()),
+                        self.multi_pv.max(strength::LINES),
+                    ),
+                    None => (go.limits(), self.multi_pv),
+                };
+                let mut search = Search::new(
+                    self.pos().clone(),
+                    self.game.iter().map(search::hash).collect(),
+                    limits,
+                    self.tt.clone(),
+                    self.stop.clone(),
+                    self.castling_mode,
+                    self.options,
+                );
+                search.set_multi_pv(multi_pv);
+                search.set_contempt(self.contempt);
+                if let Some(tablebases) = &self.tablebases {
+                    search.set_tablebases(tablebases.clone());
+                }
+                let mut search = ParallelSearch::new(search, self.threads, || self.eval());
+                let strength = self.strength;
+                let mut rng = Rng::new(self.rng.next_u64());
+                Box::new(move || {
+                    let best = search.run();
+                    match strength {
+                        Some(strength) => strength.pick(search.lines(), &mut rng).or(best),
+                        None => best,
+                    }
+                })
+            }
+            (None, SearchMode::Mcts) => {
+                let mut mcts = Mcts::new(
+                    &self.game,
+                    go.limits(),
+                    self.stop.clone(),
+                    self.castling_mode,
+                    self.queue(),
+                );
+                mcts.set_batch_size(self.batch_size);
+                Box::new(move || mcts.run())
+            }
+        };
+        let (castling_mode, stop) = (self.castling_mode, self.stop.clone());
+        self.search = Some(thread::spawn(move || {
+            let best = search();
+            // The best move of an infinite search is only reported after
+            // `stop`.
+            while go.infinite && !stop.load(Ordering::Relaxed) {
+                thread::park();
+            }
+            match best {
+                Some(m) => println!("bestmove {}", m.to_uci(castling_mode)),
+                // The GUI expects a move even without legal moves.
+                None => println!("bestmove 0000"),
+            }
+        }));
+    }
+}
+
+// position [startpos | fen <fen>] [moves <move>...], all positions from the
+// first to the last, and whether the game is a Chess960 one. The castling
+// rights of the FEN may name the files of the rooks (Shredder-FEN) or their
+// sides (X-FEN), the king and the rooks tell Chess960 positions apart. The
+// castling moves are accepted both as king takes rook and as the king moving
+// two squares.
+fn parse_position<'a>(
+    mut tokens: impl Iterator<Item = &'a str>,
+) -> Result<(Vec<Chess>, CastlingMode), String> {
+    let (pos, castling_mode) = match tokens.next() {
+        Some("startpos") => (Chess::default(), CastlingMode::Standard),
+        Some("fen") => {
+            let fen: Vec<&str> = tokens
+                .by_ref()
+                .take_while(|&token| token != "moves")
+                .collect();
+            let fen: Fen = fen.join(" ").parse().map_err(|err| format!("{err}"))?;
+            let castling_mode = CastlingMode::detect(fen.as_setup());
+            let pos = fen
+                .into_position(castling_mode)
+                .map_err(|err| err.to_string())?;
+            (pos, castling_mode)
+        }
+        other => return Err(format!("expected startpos or fen, got {other:?}")),
+    };
+    // "moves" was consumed by the FEN already.
+    let game = play_moves(pos, tokens.skip_while(|&token| token == "moves"))?;
+    Ok((game, castling_mode))
+}
+
+fn play_moves<'a>(
+    mut pos: Chess,
+    moves: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Chess>, String> {
+    let mut game = vec![pos.clone()];
+    for uci in moves {
+        let m = UciMove::from_ascii(uci.as_bytes())
+            .map_err(|err| format!("{uci}: {err}"))?
+            .to_move(&pos)
+            .map_err(|err| format!("{uci}: {err}"))?;
+        pos.play_unchecked(&m);
+        game.push(pos.clone());
+    }
+    Ok(game)
+}
+
+fn parse_go<'a>(mut tokens: impl Iterator<Item = &'a str>, turn: Color) -> Go {
+    let mut go = Go::default();
+    let (time, increment) = match turn {
+        Color::White => ("wtime", "winc"),
+        Color::Black => ("btime", "binc"),
+    };
+    while let Some(token) = tokens.next() {
+        let mut number = || -> Option<u64> { tokens.next()?.parse().ok() };
+        match token {
+            "depth" => go.depth = number().map(|depth| depth as u32),
+            "nodes" => go.nodes = number(),
+            "movetime" => go.movetime = number().map(Duration::from_millis),
+            "movestogo" => go.moves_to_go = number().map(|moves| moves as u32),
+            "infinite" => go.infinite = true,
+            "perft" => go.perft = number().map(|depth| depth as u32),
+            "mate" => go.mate = number().map(|moves| moves as u32),
+            _ if token == time => go.time = number().map(Duration::from_millis),
+            _ if token == increment => {
+                go.increment = number().map(Duration::from_millis).unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    go
+}
+
+// The options of the engine, see options.
+fn options() -> Registry<Engine> {
+    let defaults = search::Options::default();
+    let mut options = Registry::<Engine>::new();
+    options
+        .spin(
+            "Hash",
+            tt::DEFAULT_SIZE_MB as i64,
+            1,
+            tt::MAX_SIZE_MB as i64,
+            |engine, size_mb| {
+                engine.stop();
+                engine.tt = Arc::new(TranspositionTable::new(size_mb as usize));
+            },
+        )
+        .spin(
+            "Threads",
+            1,
+            1,
+            smp::MAX_THREADS as i64,
+            |engine, threads| {
+                engine.threads = threads as usize;
+            },
+        )
+        .spin(
+            "MultiPV",
+            1,
+            1,
+            search::MAX_MULTI_PV as i64,
+            |engine, multi_pv| engine.multi_pv = multi_pv as usize,
+        )
+        .check("UCI_Chess960", false, |engine, chess960| {
+            engine.chess960 = chess960;
+            engine.castling_mode = CastlingMode::from_chess960(chess960);
+        })
+        .string("EvalFile", nnue::DEFAULT_EVAL_FILE, |engine, path| {
+            engine.network = None;
+            match nnue::load(path) {
+                Ok(Some(network)) => {
+                    let architecture = nnue::architecture(&network);
+                    println!("info string loaded network {architecture}");
+                    engine.network = Some(Arc::new(network));
+                }
+                Ok(None) => {}
+                Err(err) => println!("info string failed to load {path}: {err}"),
+            }
+        })
+        .string("SyzygyPath", "", |engine, path| {
+            engine.tablebases = None;
+            if path.is_empty() {
+                return;
+            }
+            match Syzygy::load(path) {
+                Ok(tablebases) => {
+                    let pieces = tablebases.max_pieces();
+                    println!("info string loaded tablebases up to {pieces} pieces");
+                    engine.tablebases = Some(Arc::new(tablebases));
+                }
+                Err(err) => println!("info string failed to load {path}: {err}"),
+            }
+        })
+        .check("OwnBook", false, |engine, own_book| {
+            engine.own_book = own_book
+        })
+        .spin(
+            "Contempt",
+            0,
+            -MAX_CONTEMPT as i64,
+            MAX_CONTEMPT as i64,
+            |engine, contempt| engine.contempt = contempt as i32,
+        )
+        .check("UCI_LimitStrength", false, |engine, limit| {
+            engine.strength = limit.then(|| Strength::new(engine.elo));
+        })
+        .spin(
+            "UCI_Elo",
+            strength::MAX_ELO as i64,
+            strength::MIN_ELO as i64,
+            strength::MAX_ELO as i64,
+            |engine, elo| {
+                engine.elo = elo as u32;
+                engine.strength = engine.strength.map(|_| Strength::new(engine.elo));
+            },
+        )
+        .string("Book", "", |engine, path| {
+            engine.book = None;
+            if path.is_empty() {
+                return;
+            }
+            match Book::load(path) {
+                Ok(book) => {
+                    println!("info string loaded {} book entries", book.len());
+                    engine.book = Some(book);
+                }
+                Err(err) => println!("info string failed to load {path}: {err}"),
+            }
+        });
+    #[cfg(feature = "onnx")]
+    options.string("WeightsFile", "", |engine, path| {
+        engine.weights_file = (!path.is_empty()).then(|| PathBuf::from(path));
+        engine.load_weights();
+    });
+    #[cfg(feature = "cuda")]
+    options.combo("Backend", "cpu", &["cpu", "cuda"], |engine, backend| {
+        engine.backend = match backend {
+            "cuda" => Backend::Cuda,
+            _ => Backend::Cpu,
+        };
+        engine.load_weights();
+    });
+    options
+        .spin(
+            "MinibatchSize",
+            mcts::DEFAULT_BATCH_SIZE as i64,
+            1,
+            mcts::MAX_BATCH_SIZE as i64,
+            |engine, batch_size| engine.batch_size = batch_size as usize,
+        )
+        .combo(
+            "SearchMode",
+            "AlphaBeta",
+            &["AlphaBeta", "MCTS"],
+            |engine, mode| {
+                engine.mode = match mode {
+                    "MCTS" => SearchMode::Mcts,
+                    _ => SearchMode::AlphaBeta,
+                };
+            },
+        )
+        .check("NullMove", true, |engine, null_move| {
+            engine.options.null_move = null_move;
+        })
+        .hidden()
+        .check("LMR", true, |engine, lmr| engine.options.lmr = lmr)
+        .hidden()
+        .check("Aspiration", true, |engine, aspiration| {
+            engine.options.aspiration = aspiration;
+        })
+        .hidden()
+        .check("Singular", true, |engine, singular| {
+            engine.options.singular = singular;
+        })
+        .hidden()
+        .spin(
+            "SingularMinDepth",
+            defaults.singular_min_depth as i64,
+            4,
+            20,
+            |engine, depth| {
+                engine.options.singular_min_depth = depth as u32;
+            },
+        )
+        .hidden()
+        .spin(
+            "SingularMargin",
+            defaults.singular_margin as i64,
+            0,
+            20,
+            |engine, margin| {
+                engine.options.singular_margin = margin as i32;
+            },
+        )
+        .hidden();
+    options
+}
+
+// Runs the engine with the options of the configuration file, if any.
+pub fn run(config: Option<&str>) -> io::Result<()> {
+    let options = options();
+    let mut engine = Engine::new();
+    if let Some(path) = config {
+        options.load(&mut engine, path)?;
+    }
+    for line in io::stdin().lock().lines() {
+        if !engine.handle(&line?, &options) {
+            return Ok(());
+        }
+    }
+    // The GUI closed the input.
+    engine.stop();
+    Ok(())
+}
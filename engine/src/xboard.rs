@@ -0,0 +1,422 @@
+// The Chess Engine Communication Protocol (CECP) of XBoard and WinBoard, for
+// tournament managers and interfaces without UCI: `attix --protocol xboard`.
+//
+// The engine announces protocol version 2 with `usermove`, `setboard`,
+// `ping` and no signals, and plays standard chess with the alpha-beta search
+// and the hand-crafted evaluation. It plays the side given by `new` (black),
+// `go` and `playother` until `force`, searching with the clock of `time` and
+// the time control of `level`, or `st` seconds and `sd` plies per move.
+// Without any of them, every move gets DEFAULT_MOVETIME. `cores` and `memory`
+// set the threads and the size of the transposition table. With `post`, the
+// result of the search is printed as the thinking output
+// `depth score time nodes pv` before the move.
+//
+// The search runs on its own thread, so that `?` moves now and `force`,
+// `new`, `undo` and the like abandon the search. Its move is sent by the
+// search thread and played on the board before the next command is handled,
+// unless the search was abandoned first.
+
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Color, Move, Position};
+
+use crate::eval::{Eval, HandCrafted};
+use crate::perft;
+use crate::search::{self, Options, Search, MATE, MAX_PLY};
+use crate::smp::{self, ParallelSearch};
+use crate::time::Go;
+use crate::tt::{self, TranspositionTable};
+
+// A mate in n moves scores MATE_SCORE + n in the thinking output, being mated
+// in n moves -(MATE_SCORE + n).
+const MATE_SCORE: i32 = 100_000;
+// Time per move without a clock, `st` or `sd`.
+const DEFAULT_MOVETIME: Duration = Duration::from_secs(5);
+
+// Whether the move of a running search is still wanted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Searching,
+    // The move was sent to the interface and has to be played.
+    Played,
+    Abandoned,
+}
+
+struct Running {
+    handle: JoinHandle<Option<Move>>,
+    state: Arc<Mutex<State>>,
+}
+
+struct XBoard {
+    game: Vec<Chess>,
+    // The side the engine plays, None in force mode.
+    engine_side: Option<Color>,
+    post: bool,
+    // The clock of the engine and the time control.
+    time: Option<Duration>,
+    moves_per_session: u32,
+    increment: Duration,
+    movetime: Option<Duration>,
+    depth: Option<u32>,
+    threads: usize,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    search: Option<Running>,
+}
+
+// The seconds of `level`, either `<seconds>` or `<minutes>:<seconds>` for the
+// base time.
+fn parse_seconds(value: &str) -> Option<Duration> {
+    let seconds = match value.split_once(':') {
+        Some((minutes, seconds)) => {
+            minutes.parse::<f64>().ok()? * 60.0 + seconds.parse::<f64>().ok()?
+        }
+        None => value.parse::<f64>().ok()?,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+// The score of the thinking output: centipawns, or MATE_SCORE + n for mates.
+fn thinking_score(score: i32) -> i32 {
+    if score.abs() >= MATE - MAX_PLY as i32 {
+        let moves = (MATE - score.abs() + 1) / 2;
+        score.signum() * (MATE_SCORE + moves)
+    } else {
+        score
+    }
+}
+
+impl XBoard {
+    fn new() -> Self {
+        XBoard {
+            game: vec![Chess::default()],
+            engine_side: Some(Color::Black),
+            post: false,
+            time: None,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            movetime: None,
+            depth: None,
+            threads: 1,
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+        }
+    }
+
+    fn pos(&self) -> &Chess {
+        self.game.last().expect("the game has a position")
+    }
+
+    fn play(&mut self, m: &Move) {
+        let mut pos = self.pos().clone();
+        pos.play_unchecked(m);
+        self.game.push(pos);
+    }
+
+    // Waits for the search and plays its move if it was sent.
+    fn finish(&mut self, running: Running) {
+        let best = running.handle.join().expect("the search does not panic");
+        let state = *running.state.lock().expect("the search does not panic");
+        if let (State::Played, Some(m)) = (state, best) {
+            self.play(&m);
+        }
+    }
+
+    // Plays the move of the search if it was sent already.
+    fn collect(&mut self) {
+        let played = self.search.as_ref().is_some_and(|running| {
+            *running.state.lock().expect("the search does not panic") == State::Played
+        });
+        if played {
+            let running = self.search.take().expect("the search is running");
+            self.finish(running);
+        }
+    }
+
+    // Stops the search without sending its move.
+    fn abandon(&mut self) {
+        if let Some(running) = self.search.take() {
+            let mut state = running.state.lock().expect("the search does not panic");
+            if *state == State::Searching {
+                *state = State::Abandoned;
+            }
+            drop(state);
+            self.stop.store(true, Ordering::Relaxed);
+            self.finish(running);
+        }
+    }
+
+    // The limits of the search of the next move.
+    fn go(&self) -> Go {
+        let moves_to_go = (self.moves_per_session > 0).then(|| {
+            let played = self.pos().fullmoves().get() - 1;
+            self.moves_per_session - played % self.moves_per_session
+        });
+        let untimed = self.time.is_none() && self.movetime.is_none() && self.depth.is_none();
+        Go {
+            depth: self.depth,
+            movetime: self.movetime.or(untimed.then_some(DEFAULT_MOVETIME)),
+            time: self.time,
+            increment: self.increment,
+            moves_to_go,
+            ..Go::default()
+        }
+    }
+
+    // Searches for a move if the engine is to move.
+    fn think(&mut self) {
+        let pos = self.pos().clone();
+        if self.engine_side != Some(pos.turn()) || pos.legal_moves().is_empty() {
+            return;
+        }
+        self.stop.store(false, Ordering::Relaxed);
+        let mut search = Search::new(
+            pos,
+            self.game.iter().map(search::hash).collect(),
+            self.go().limits(),
+            self.tt.clone(),
+            self.stop.clone(),
+            CastlingMode::Standard,
+            Options::default(),
+        );
+        search.set_silent();
+        let eval = || -> Box<dyn Eval> { Box::new(HandCrafted::default()) };
+        let mut search = ParallelSearch::new(search, self.threads, eval);
+        let state = Arc::new(Mutex::new(State::Searching));
+        let post = self.post;
+        let handle = thread::spawn({
+            let state = state.clone();
+            move || {
+                let start = Instant::now();
+                let best = search.run();
+                let mut state = state.lock().expect("the search does not panic");
+                if *state != State::Searching {
+                    return best;
+                }
+                if let (true, Some((score, pv))) = (post, search.lines().first()) {
+                    let centis = start.elapsed().as_millis() / 10;
+                    let nodes: u64 = search.nodes().iter().sum();
+                    let pv: Vec<String> = pv
+                        .iter()
+                        .map(|m| m.to_uci(CastlingMode::Standard).to_string())
+                        .collect();
+                    println!(
+                        "{} {} {centis} {nodes} {}",
+                        search.depth(),
+                        thinking_score(*score),
+                        pv.join(" ")
+                    );
+                }
+                if let Some(m) = &best {
+                    println!("move {}", m.to_uci(CastlingMode::Standard));
+                    *state = State::Played;
+                }
+                best
+            }
+        });
+        self.search = Some(Running { handle, state });
+    }
+
+    fn user_move(&mut self, uci: &str) {
+        let m = UciMove::from_ascii(uci.as_bytes())
+            .ok()
+            .and_then(|m| m.to_move(self.pos()).ok());
+        match m {
+            Some(m) => {
+                self.play(&m);
+                self.think();
+            }
+            None => println!("Illegal move: {uci}"),
+        }
+    }
+
+    // Handles a command, returns false on `quit`.
+    fn handle(&mut self, line: &str) -> bool {
+        self.collect();
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let number = || args.trim().parse::<u64>().ok();
+        match command {
+            "protover" => {
+                println!(
+                    "feature myname=\"attix\" usermove=1 setboard=1 ping=1 playother=1 \
+                     san=0 colors=0 sigint=0 sigterm=0 reuse=1 analyze=0 variants=\"normal\""
+                );
+                println!("feature done=1");
+            }
+            "new" => {
+                self.abandon();
+                self.tt.clear();
+                self.game = vec![Chess::default()];
+                self.engine_side = Some(Color::Black);
+                self.depth = None;
+                self.movetime = None;
+            }
+            "force" => {
+                self.abandon();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.abandon();
+                self.engine_side = Some(self.pos().turn());
+                self.think();
+            }
+            "playother" => {
+                self.abandon();
+                self.engine_side = Some(!self.pos().turn());
+            }
+            "usermove" => {
+                self.abandon();
+                self.user_move(args.trim());
+            }
+            "setboard" => {
+                self.abandon();
+                match perft::parse_fen(args.trim()) {
+                    Ok((pos, CastlingMode::Standard)) => self.game = vec![pos],
+                    Ok(_) => println!("tellusererror Chess960 positions are not supported"),
+                    Err(err) => println!("tellusererror Illegal position: {err}"),
+                }
+            }
+            "undo" | "remove" => {
+                self.abandon();
+                let plies = if command == "undo" { 1 } else { 2 };
+                let keep = self.game.len().saturating_sub(plies).max(1);
+                self.game.truncate(keep);
+            }
+            "level" => {
+                let fields: Vec<&str> = args.split_whitespace().collect();
+                if let [moves, base, increment] = fields[..] {
+                    self.moves_per_session = moves.parse().unwrap_or(0);
+                    self.time = parse_seconds(base);
+                    self.increment = parse_seconds(increment).unwrap_or_default();
+                    self.movetime = None;
+                }
+            }
+            "st" => self.movetime = number().map(Duration::from_secs),
+            "sd" => self.depth = number().map(|depth| depth as u32),
+            "time" => self.time = number().map(|centis| Duration::from_millis(centis * 10)),
+            "cores" => {
+                let threads = number().unwrap_or(1) as usize;
+                self.threads = threads.clamp(1, smp::MAX_THREADS);
+            }
+            "memory" => {
+                self.abandon();
+                let size_mb = number().unwrap_or(tt::DEFAULT_SIZE_MB as u64) as usize;
+                self.tt = Arc::new(TranspositionTable::new(size_mb));
+            }
+            "?" => self.stop.store(true, Ordering::Relaxed),
+            "ping" => println!("pong {}", args.trim()),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => {
+                self.abandon();
+                self.engine_side = None;
+            }
+            "quit" => {
+                self.abandon();
+                return false;
+            }
+            // xboard, accepted, rejected, otim, hard, easy, random, computer,
+            // name, rating and the like need no reply.
+            _ => {}
+        }
+        true
+    }
+}
+
+pub fn run() -> io::Result<()> {
+    let mut xboard = XBoard::new();
+    for line in io::stdin().lock().lines() {
+        if !xboard.handle(&line?) {
+            return Ok(());
+        }
+    }
+    // The interface closed the input.
+    xboard.abandon();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fen(xboard: &XBoard) -> String {
+        let setup = xboard
+            .pos()
+            .clone()
+            .into_setup(shakmaty::EnPassantMode::Legal);
+        shakmaty::fen::Fen::from_setup(setup).to_string()
+    }
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(parse_seconds("40"), Some(Duration::from_secs(40)));
+        assert_eq!(parse_seconds("5:30"), Some(Duration::from_secs(330)));
+        assert_eq!(parse_seconds("0.5"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_seconds("x"), None);
+
+        let mut xboard = XBoard::new();
+        xboard.handle("level 40 5 2");
+        xboard.handle("time 30000");
+        let go = xboard.go();
+        assert_eq!(go.time, Some(Duration::from_secs(300)));
+        assert_eq!(go.increment, Duration::from_secs(2));
+        assert_eq!(go.moves_to_go, Some(40));
+        assert_eq!(go.movetime, None);
+
+        xboard.handle("new");
+        xboard.handle("st 3");
+        xboard.handle("sd 7");
+        let go = xboard.go();
+        assert_eq!(
+            (go.movetime, go.depth),
+            (Some(Duration::from_secs(3)), Some(7))
+        );
+    }
+
+    #[test]
+    fn thinking_scores() {
+        assert_eq!(thinking_score(35), 35);
+        assert_eq!(thinking_score(MATE - 1), MATE_SCORE + 1);
+        assert_eq!(thinking_score(MATE - 3), MATE_SCORE + 2);
+        assert_eq!(thinking_score(-MATE + 2), -(MATE_SCORE + 1));
+    }
+
+    #[test]
+    fn force_mode_and_undo() {
+        let mut xboard = XBoard::new();
+        xboard.handle("force");
+        xboard.handle("usermove e2e4");
+        xboard.handle("usermove e7e5");
+        // Illegal moves are rejected.
+        xboard.handle("usermove e4e6");
+        assert_eq!(xboard.game.len(), 3);
+        assert!(xboard.search.is_none());
+        xboard.handle("undo");
+        assert_eq!(
+            fen(&xboard),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+        );
+        xboard.handle("remove");
+        assert_eq!(xboard.game.len(), 1);
+        assert!(xboard.handle("ping 7"));
+        assert!(!xboard.handle("quit"));
+    }
+
+    #[test]
+    fn plays_the_searched_move() {
+        let mut xboard = XBoard::new();
+        xboard.handle("setboard 6k1/5ppp/8/8/8/8/5PPP/3Q2K1 w - - 0 1");
+        xboard.handle("sd 2");
+        xboard.handle("go");
+        let running = xboard.search.take().expect("the engine is to move");
+        xboard.finish(running);
+        assert!(xboard.pos().is_checkmate());
+        assert_eq!(xboard.engine_side, Some(Color::White));
+    }
+}
@@ -0,0 +1,56 @@
+// C API of the attix training data reader.
+//
+// Functions returning pointers report errors by returning NULL, the message is
+// available from attix_last_error on the same thread until the next call.
+
+#ifndef ATTIX_H
+#define ATTIX_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Number of bitboards of a sample: pawns, knights, bishops, rooks, queens and
+// the king of the side to move, followed by the pieces of the opponent.
+#define ATTIX_NUM_PLANES 12
+
+typedef struct AttixSamples AttixSamples;
+typedef struct AttixSample AttixSample;
+
+// Message of the last error on this thread or NULL.
+const char *attix_last_error(void);
+
+// Opens a tar archive with .gz training data. Returns NULL on error.
+AttixSamples *attix_samples_open(const char *path);
+
+// Closes the archive, which invalidates the last returned sample.
+void attix_samples_close(AttixSamples *samples);
+
+// Returns the next sample, which stays valid until the next call, or NULL
+// once all samples are read. attix_last_error distinguishes the end of the
+// archive (NULL) from errors.
+const AttixSample *attix_samples_next(AttixSamples *samples);
+
+// ATTIX_NUM_PLANES bitboards of the position seen from the side to move,
+// bit 0 is a1 and bit 63 is h8.
+const uint64_t *attix_sample_planes(const AttixSample *sample);
+
+float attix_sample_best_q(const AttixSample *sample);
+float attix_sample_best_d(const AttixSample *sample);
+float attix_sample_result_q(const AttixSample *sample);
+float attix_sample_result_d(const AttixSample *sample);
+uint16_t attix_sample_best_idx(const AttixSample *sample);
+uint8_t attix_sample_rule50_count(const AttixSample *sample);
+float attix_sample_plies_left(const AttixSample *sample);
+
+// UCI notation of the move with the given policy index or NULL if the index is
+// out of range. Knight promotions have no promotion suffix.
+const char *attix_move_name(uint16_t idx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif  // ATTIX_H
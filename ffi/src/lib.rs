@@ -0,0 +1,182 @@
+// C API of the sample reader for engines and trainers written in other
+// languages, see include/attix.h.
+//
+// Errors are reported by returning NULL and the message is kept per thread,
+// so that the callers do not have to manage the memory of error objects.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::fmt::Display;
+use std::ptr;
+use std::sync::LazyLock;
+
+use preprocessing::{TrainingSample, TrainingSamples, IDX_TO_MOVE};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// NUL-terminated copies of IDX_TO_MOVE.
+static MOVE_NAMES: LazyLock<Vec<CString>> = LazyLock::new(|| {
+    IDX_TO_MOVE
+        .iter()
+        .map(|uci| CString::new(*uci).expect("moves do not contain NUL"))
+        .collect()
+});
+
+fn set_last_error(message: impl Display) {
+    // Interior NUL bytes can't be represented, the message is dropped then.
+    let message = CString::new(message.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|error| *error.borrow_mut() = None);
+}
+
+pub struct AttixSamples {
+    samples: TrainingSamples,
+    // The sample last returned by attix_samples_next.
+    current: Option<TrainingSample>,
+}
+
+pub type AttixSample = TrainingSample;
+
+#[no_mangle]
+pub extern "C" fn attix_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// # Safety
+///
+/// `path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn attix_samples_open(path: *const c_char) -> *mut AttixSamples {
+    if path.is_null() {
+        set_last_error("path is NULL");
+        return ptr::null_mut();
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(err) => {
+            set_last_error(err);
+            return ptr::null_mut();
+        }
+    };
+    match TrainingSamples::from_tar(path) {
+        Ok(samples) => {
+            clear_last_error();
+            Box::into_raw(Box::new(AttixSamples {
+                samples,
+                current: None,
+            }))
+        }
+        Err(err) => {
+            set_last_error(format!("{path}: {err}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `samples` must be NULL or returned by attix_samples_open and not closed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn attix_samples_close(samples: *mut AttixSamples) {
+    if !samples.is_null() {
+        drop(Box::from_raw(samples));
+    }
+}
+
+/// # Safety
+///
+/// `samples` must be NULL or returned by attix_samples_open and not closed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn attix_samples_next(samples: *mut AttixSamples) -> *const AttixSample {
+    let Some(samples) = samples.as_mut() else {
+        set_last_error("samples is NULL");
+        return ptr::null();
+    };
+    clear_last_error();
+    match samples.samples.next() {
+        Some(Ok(sample)) => samples.current.insert(sample) as *const AttixSample,
+        Some(Err(err)) => {
+            set_last_error(err);
+            ptr::null()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_planes(sample: *const AttixSample) -> *const u64 {
+    (*sample).bitboards.as_ptr()
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_best_q(sample: *const AttixSample) -> f32 {
+    (*sample).best_q
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_best_d(sample: *const AttixSample) -> f32 {
+    (*sample).best_d
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_result_q(sample: *const AttixSample) -> f32 {
+    (*sample).result_q
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_result_d(sample: *const AttixSample) -> f32 {
+    (*sample).result_d
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_best_idx(sample: *const AttixSample) -> u16 {
+    (*sample).best_idx
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_rule50_count(sample: *const AttixSample) -> u8 {
+    (*sample).rule50_count
+}
+
+/// # Safety
+///
+/// `sample` must be returned by attix_samples_next and still be valid.
+#[no_mangle]
+pub unsafe extern "C" fn attix_sample_plies_left(sample: *const AttixSample) -> f32 {
+    (*sample).plies_left
+}
+
+#[no_mangle]
+pub extern "C" fn attix_move_name(idx: u16) -> *const c_char {
+    MOVE_NAMES
+        .get(idx as usize)
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
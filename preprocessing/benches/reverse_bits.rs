@@ -0,0 +1,68 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use preprocessing::record::reverse_bits_in_bytes;
+
+// The original implementation swapping progressively larger groups of bits.
+fn reverse_bits_in_bytes_swar(x: u64) -> u64 {
+    let mut v = x;
+    v = ((v >> 1) & 0x5555555555555555) | ((v & 0x5555555555555555) << 1);
+    v = ((v >> 2) & 0x3333333333333333) | ((v & 0x3333333333333333) << 2);
+    v = ((v >> 4) & 0x0F0F0F0F0F0F0F0F) | ((v & 0x0F0F0F0F0F0F0F0F) << 4);
+    v
+}
+
+const LUT: [u8; 256] = {
+    let mut lut = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        lut[i] = (i as u8).reverse_bits();
+        i += 1;
+    }
+    lut
+};
+
+fn reverse_bits_in_bytes_lut(x: u64) -> u64 {
+    u64::from_le_bytes(x.to_le_bytes().map(|b| LUT[b as usize]))
+}
+
+fn planes() -> Vec<u64> {
+    // Deterministic pseudo-random planes (xorshift).
+    let mut state = 0x9E3779B97F4A7C15u64;
+    (0..104)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect()
+}
+
+fn bench_reverse_bits(c: &mut Criterion) {
+    let planes = planes();
+    let mut group = c.benchmark_group("reverse_bits_in_bytes");
+    group.bench_function("swar", |b| {
+        b.iter(|| {
+            for &plane in black_box(&planes) {
+                black_box(reverse_bits_in_bytes_swar(plane));
+            }
+        })
+    });
+    group.bench_function("lut", |b| {
+        b.iter(|| {
+            for &plane in black_box(&planes) {
+                black_box(reverse_bits_in_bytes_lut(plane));
+            }
+        })
+    });
+    group.bench_function("reverse_bits", |b| {
+        b.iter(|| {
+            for &plane in black_box(&planes) {
+                black_box(reverse_bits_in_bytes(plane));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_reverse_bits);
+criterion_main!(benches);
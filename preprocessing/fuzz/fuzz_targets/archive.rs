@@ -0,0 +1,15 @@
+// A whole tar archive of the training data, with the games decoded like the
+// readers of the archives do.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use preprocessing::reader::read_games;
+use preprocessing::{DecodeOptions, Game};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = read_games(data, 0, |entry| {
+        let _ = Game::read_from(entry.data.as_slice(), DecodeOptions::default());
+        true
+    });
+});
@@ -0,0 +1,40 @@
+// A .gz game entry of an archive, decoded and serialized with all optional
+// fields of the output.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use preprocessing::nnue::FeatureSet;
+use preprocessing::output::{serialize_position, OutputFields, OutputSample, ProvenanceFormat};
+use preprocessing::sample::ValueSource;
+use preprocessing::{DecodeOptions, Game};
+
+fuzz_target!(|data: &[u8]| {
+    let decode = DecodeOptions {
+        policy_entropy: true,
+        policy: true,
+        repair_castling: true,
+        history: true,
+    };
+    let Ok(game) = Game::read_from(data, decode) else {
+        return;
+    };
+    let fields = OutputFields {
+        eco: true,
+        phase_score: true,
+        repetitions: true,
+        rule50: true,
+        plies_left: true,
+        plies_left_bucket: Some(10),
+        value_source: ValueSource::Orig,
+        value_columns: [true; 3],
+        played: true,
+        lc0_planes: true,
+        provenance: Some(ProvenanceFormat::Hashed),
+        nnue: Some(FeatureSet::HalfKp),
+    };
+    for sample in &game.samples {
+        let line = serialize_position(sample, &game, fields);
+        OutputSample::parse(&line).expect("serialized samples parse");
+    }
+});
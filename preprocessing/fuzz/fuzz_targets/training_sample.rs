@@ -0,0 +1,28 @@
+// A single raw record, decoded with all fields and converted to a position
+// like the preprocessing pipeline does.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use preprocessing::{
+    filter_position, CastlingBitboards, DecodeOptions, FilterOptions, TrainingSample,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let decode = DecodeOptions {
+        policy_entropy: true,
+        policy: true,
+        repair_castling: true,
+        history: true,
+    };
+    let Ok(sample) = TrainingSample::read_from(data, decode) else {
+        return;
+    };
+    let castling = CastlingBitboards::from_game(std::slice::from_ref(&sample));
+    let _ = castling.matches(&sample);
+    let _ = sample.to_fen(&castling).to_string();
+    let _ = sample.to_position(&castling);
+    let _ = sample.position_hash();
+    let _ = sample.castling_consistent();
+    let _ = filter_position(&sample, &FilterOptions::default());
+});
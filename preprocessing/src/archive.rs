@@ -0,0 +1,71 @@
+use std::io;
+
+use preprocessing::source;
+use tracing::{debug, info_span};
+
+use crate::progress::Progress;
+
+pub use preprocessing::reader::GameEntry;
+pub use preprocessing::source::for_each_game;
+
+// Number of games handed to the analysis commands at once, which decode and
+// process them in parallel.
+const GAMES_PER_BATCH: usize = 256;
+
+pub fn archive_sizes(paths: &[String]) -> io::Result<Vec<u64>> {
+    paths.iter().map(source::size).collect()
+}
+
+// Reads the games of all archives in batches while showing the progress.
+pub fn scan<F>(paths: &[String], f: F) -> io::Result<()>
+where
+    F: FnMut(&[Vec<u8>], &Progress) -> io::Result<()>,
+{
+    scan_with(paths, |_, game| game.data, f)
+}
+
+// Like scan, with the index of the archive in `paths` and the entry of every
+// game, e.g. to point back to the games.
+pub fn scan_entries<F>(paths: &[String], f: F) -> io::Result<()>
+where
+    F: FnMut(&[(usize, GameEntry)], &Progress) -> io::Result<()>,
+{
+    scan_with(paths, |archive, game| (archive, game), f)
+}
+
+fn scan_with<T, M, F>(paths: &[String], map: M, mut f: F) -> io::Result<()>
+where
+    M: Fn(usize, GameEntry) -> T,
+    F: FnMut(&[T], &Progress) -> io::Result<()>,
+{
+    let sizes = archive_sizes(paths)?;
+    let progress = Progress::new(sizes.iter().sum(), paths.len());
+
+    let mut offset = 0;
+    for (archive, (path, size)) in paths.iter().zip(&sizes).enumerate() {
+        let _span = info_span!("archive", path = %path).entered();
+        debug!(size, "reading archive");
+        progress.archive_started();
+
+        let mut batch = Vec::with_capacity(GAMES_PER_BATCH);
+        let mut result = Ok(());
+        for_each_game(path, 0, |game| {
+            progress.set_position(offset + game.end_offset);
+            batch.push(map(archive, game));
+            if batch.len() >= GAMES_PER_BATCH {
+                result = f(&batch, &progress);
+                batch.clear();
+            }
+            result.is_ok()
+        })?;
+        result?;
+        if !batch.is_empty() {
+            f(&batch, &progress)?;
+        }
+
+        offset += size;
+    }
+    progress.finish();
+
+    Ok(())
+}
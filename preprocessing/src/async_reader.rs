@@ -0,0 +1,96 @@
+// Asynchronous counterpart of the reader for streaming the training data from
+// network sources. Only the tar stream is read asynchronously, the games are
+// small enough to be decompressed and decoded in place.
+
+use std::vec;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::StreamExt;
+use tokio_tar::{Archive, Entries};
+
+use crate::error::Result;
+use crate::sample::{DecodeOptions, Game, TrainingSample};
+
+/// Games of a tar archive read from an [`AsyncRead`].
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use preprocessing::async_reader::AsyncGames;
+/// use preprocessing::DecodeOptions;
+///
+/// let file = tokio::fs::File::open("training.tar").await?;
+/// let mut games = AsyncGames::new(file, DecodeOptions::default())?;
+/// while let Some(game) = games.next_game().await {
+///     println!("{} positions", game?.samples.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncGames<R: AsyncRead + Unpin> {
+    entries: Entries<R>,
+    decode: DecodeOptions,
+}
+
+impl<R: AsyncRead + Unpin> AsyncGames<R> {
+    pub fn new(reader: R, decode: DecodeOptions) -> Result<Self> {
+        Ok(AsyncGames {
+            entries: Archive::new(reader).entries()?,
+            decode,
+        })
+    }
+
+    /// Returns None once the whole archive is read.
+    pub async fn next_game(&mut self) -> Option<Result<Game>> {
+        while let Some(entry) = self.entries.next().await {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err.into())),
+            };
+            match entry.path() {
+                Ok(path) if path.to_string_lossy().ends_with(".gz") => {}
+                Ok(_) => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+
+            let mut data = Vec::with_capacity(entry.header().size().unwrap_or(0) as usize);
+            if let Err(err) = entry.read_to_end(&mut data).await {
+                return Some(Err(err.into()));
+            }
+            return Some(Game::read_from(data.as_slice(), self.decode));
+        }
+        None
+    }
+}
+
+/// Samples of a tar archive read from an [`AsyncRead`], game after game.
+pub struct AsyncTrainingSamples<R: AsyncRead + Unpin> {
+    games: AsyncGames<R>,
+    samples: vec::IntoIter<TrainingSample>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncTrainingSamples<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        let games = AsyncGames::new(reader, DecodeOptions::default())?;
+        Ok(Self::from_games(games))
+    }
+
+    pub fn from_games(games: AsyncGames<R>) -> Self {
+        AsyncTrainingSamples {
+            games,
+            samples: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns None once the whole archive is read.
+    pub async fn next_sample(&mut self) -> Option<Result<TrainingSample>> {
+        loop {
+            if let Some(sample) = self.samples.next() {
+                return Some(Ok(sample));
+            }
+            match self.games.next_game().await? {
+                Ok(game) => self.samples = game.samples.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
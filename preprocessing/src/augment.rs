@@ -0,0 +1,154 @@
+// Data augmentation: transformations of the samples into equivalent positions
+// with correspondingly transformed targets.
+//
+// Mirroring the board left to right preserves the rules of chess except for
+// castling, so it is only applied to the positions in which neither side can
+// castle anymore. The best move and the policy are remapped to the mirrored
+// moves, the value targets stay the same.
+//
+// Flipping the colors mirrors the board vertically and swaps the colors of the
+// pieces and the side to move, which keeps the value for the side to move.
+// The samples are stored from the perspective of the side to move, so the
+// planes, the castling rights, the moves and the targets of the flipped sample
+// are the same and only the side to move changes. It only makes a difference
+// for the outputs with the side to move, the lc0 input planes. Swapping the
+// colors while keeping the side to move would hand the move to the opponent,
+// whose value is not the negated value of the original position and whose
+// policy is unknown.
+
+use std::sync::LazyLock;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use shakmaty::Bitboard;
+
+use crate::moves::MOVE_TO_IDX;
+use crate::record::POLICY_SIZE;
+use crate::sample::TrainingSample;
+use crate::IDX_TO_MOVE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Augmentation {
+    /// Mirror the board horizontally (a-file <-> h-file).
+    Mirror,
+    /// Mirror the board vertically and swap the colors and the side to move.
+    ColorFlip,
+}
+
+// Index of the horizontally mirrored move in the policy head for every move.
+static MIRRORED_MOVES: LazyLock<Vec<u16>> = LazyLock::new(|| {
+    IDX_TO_MOVE
+        .iter()
+        .map(|uci| {
+            // Only the files of the squares change, the promotion suffix stays.
+            let mirrored: String = uci
+                .char_indices()
+                .map(|(pos, c)| match pos {
+                    0 | 2 => (b'a' + b'h' - c as u8) as char,
+                    _ => c,
+                })
+                .collect();
+            MOVE_TO_IDX[mirrored.as_str()]
+        })
+        .collect()
+});
+
+impl Augmentation {
+    /// Whether the transformed sample is a valid position with the same
+    /// game-theoretic value.
+    pub fn applies_to(self, sample: &TrainingSample) -> bool {
+        match self {
+            Augmentation::Mirror => {
+                !(sample.castling_us_ooo
+                    || sample.castling_us_oo
+                    || sample.castling_them_ooo
+                    || sample.castling_them_oo)
+            }
+            Augmentation::ColorFlip => true,
+        }
+    }
+
+    /// The transformed sample. Check [`Augmentation::applies_to`] first.
+    ///
+    /// ```
+    /// use preprocessing::augment::Augmentation;
+    /// use preprocessing::record::RECORD_SIZE;
+    /// use preprocessing::{DecodeOptions, TrainingSample, MOVE_TO_IDX};
+    ///
+    /// let mut record = vec![0; RECORD_SIZE];
+    /// record[0] = 6;
+    /// let mut sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+    /// // A king on b1.
+    /// sample.bitboards[5] = 1 << 1;
+    /// sample.best_idx = MOVE_TO_IDX["b1c2"];
+    ///
+    /// assert!(Augmentation::Mirror.applies_to(&sample));
+    /// let mirrored = Augmentation::Mirror.apply(&sample);
+    /// assert_eq!(mirrored.bitboards[5], 1 << 6);
+    /// assert_eq!(mirrored.best_idx, MOVE_TO_IDX["g1f2"]);
+    /// assert_eq!(Augmentation::Mirror.apply(&mirrored), sample);
+    ///
+    /// let flipped = Augmentation::ColorFlip.apply(&sample);
+    /// assert!(flipped.black_to_move);
+    /// assert_eq!(flipped.bitboards, sample.bitboards);
+    /// assert_eq!(flipped.best_idx, sample.best_idx);
+    /// assert_eq!(Augmentation::ColorFlip.apply(&flipped), sample);
+    /// # Ok::<(), preprocessing::PreprocessError>(())
+    /// ```
+    pub fn apply(self, sample: &TrainingSample) -> TrainingSample {
+        match self {
+            Augmentation::Mirror => {
+                let moves = &*MIRRORED_MOVES;
+                let policy = sample.policy.as_ref().map(|policy| {
+                    let mut mirrored = vec![0.0; POLICY_SIZE];
+                    for (idx, &p) in policy.iter().enumerate() {
+                        mirrored[moves[idx] as usize] = p;
+                    }
+                    mirrored
+                });
+                TrainingSample {
+                    bitboards: sample
+                        .bitboards
+                        .map(|plane| Bitboard(plane).flip_horizontal().0),
+                    best_idx: moves[sample.best_idx as usize],
+                    played_idx: moves[sample.played_idx as usize],
+                    policy,
+                    history: sample.history.as_ref().map(|history| {
+                        history
+                            .iter()
+                            .map(|&plane| Bitboard(plane).flip_horizontal().0)
+                            .collect()
+                    }),
+                    ..sample.clone()
+                }
+            }
+            Augmentation::ColorFlip => TrainingSample {
+                black_to_move: !sample.black_to_move,
+                ..sample.clone()
+            },
+        }
+    }
+}
+
+/// Every sample followed by its transformed copies, for the augmentations that
+/// apply to it.
+pub fn with_augmented(
+    samples: Vec<TrainingSample>,
+    augmentations: &[Augmentation],
+) -> Vec<TrainingSample> {
+    if augmentations.is_empty() {
+        return samples;
+    }
+    let mut augmented = Vec::with_capacity(samples.len() * (augmentations.len() + 1));
+    for sample in samples {
+        let copies: Vec<_> = augmentations
+            .iter()
+            .filter(|augmentation| augmentation.applies_to(&sample))
+            .map(|augmentation| augmentation.apply(&sample))
+            .collect();
+        augmented.push(sample);
+        augmented.extend(copies);
+    }
+    augmented
+}
@@ -0,0 +1,277 @@
+// The book command (also build-book): an opening book of the most common
+// positions and moves of the games in the training data, as a Polyglot book
+// for the OwnBook option of the engine or as an EPD book of weighted
+// positions for the --book of selfplay and match.
+//
+// The samples only store the boards, so every game is replayed from the
+// standard starting position, finding the move leading to the next sample.
+// Games starting elsewhere are skipped. The moves are weighted by how often
+// they were played, or by the search policy of the samples merged over all
+// games reaching the position: the mean probability of the move or its
+// estimated visits (probability times the visits of the search).
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use preprocessing::polyglot::{self, BookEntry};
+use preprocessing::sample::planes;
+use preprocessing::{idx_from_move, DecodeOptions, Game};
+use rayon::prelude::*;
+use shakmaty::fen::Epd;
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
+use tracing::info;
+
+use crate::archive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Weight {
+    /// Number of games the move was played in
+    Games,
+    /// Mean search policy of the move in the games reaching the position
+    Policy,
+    /// Search policy of the move times the visits, summed over the games
+    Visits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BookFormat {
+    /// Polyglot book, entries of 16 bytes sorted by the position hash
+    Polyglot,
+    /// A position per line in EPD with the games and the weighted moves in
+    /// UCI notation as operations
+    Epd,
+}
+
+#[derive(Args)]
+pub struct BookArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Path of the book to write
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = BookFormat::Polyglot)]
+    format: BookFormat,
+
+    /// Number of plies from the starting position covered by the book
+    #[arg(long, default_value_t = 16)]
+    max_plies: usize,
+
+    /// Minimum number of games a move has to be played in, or the position
+    /// has to be reached in for the weights of the search policy
+    #[arg(long, default_value_t = 10)]
+    min_games: u64,
+
+    #[arg(long, value_enum, default_value_t = Weight::Games)]
+    weight: Weight,
+}
+
+// A position of a game with the move played in it and the search policy over
+// its legal moves.
+struct Visit {
+    key: u64,
+    pos: Chess,
+    played: u16,
+    // Encoded moves with their probabilities, empty unless the policy is
+    // decoded.
+    policy: Vec<(u16, f32)>,
+    visits: u32,
+}
+
+// Statistics of a move merged over the games.
+#[derive(Debug, Clone, Copy, Default)]
+struct MoveStats {
+    games: u64,
+    policy: f64,
+    visits: f64,
+}
+
+// Statistics of a position merged over the games.
+struct PositionStats {
+    pos: Chess,
+    games: u64,
+    moves: HashMap<u16, MoveStats>,
+}
+
+// The positions of the game from the starting position with the moves played
+// in them.
+fn book_visits(game: &Game, max_plies: usize) -> Vec<Visit> {
+    let mut pos = Chess::default();
+    let Some((first, rest)) = game.samples.split_first() else {
+        return Vec::new();
+    };
+    if first.bitboards != planes(pos.board(), Color::White) {
+        return Vec::new();
+    }
+    let mut visits = Vec::new();
+    for (sample, next) in game.samples.iter().zip(rest).take(max_plies) {
+        let played = pos.legal_moves().into_iter().find(|m| {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            planes(child.board(), child.turn()) == next.bitboards
+        });
+        // The game left the positions the samples describe, e.g. after a
+        // corrupted record.
+        let Some(m) = played else {
+            break;
+        };
+        let policy = match &sample.policy {
+            Some(probabilities) => pos
+                .legal_moves()
+                .iter()
+                .filter_map(|m| {
+                    let idx = idx_from_move(m, pos.turn())?;
+                    let p = probabilities.get(idx as usize)?.max(0.0);
+                    Some((polyglot::encode_move(m), p))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        visits.push(Visit {
+            key: polyglot::key(&pos),
+            pos: pos.clone(),
+            played: polyglot::encode_move(&m),
+            policy,
+            visits: sample.visits,
+        });
+        pos.play_unchecked(&m);
+    }
+    visits
+}
+
+// The weighted moves of the position that enter the book.
+fn weighted_moves(stats: &PositionStats, args: &BookArgs) -> Vec<(u16, f64)> {
+    let moves = stats
+        .moves
+        .iter()
+        .map(|(&raw_move, &move_stats)| (raw_move, move_stats));
+    match args.weight {
+        Weight::Games => moves
+            .filter(|(_, move_stats)| move_stats.games >= args.min_games)
+            .map(|(raw_move, move_stats)| (raw_move, move_stats.games as f64))
+            .collect(),
+        _ if stats.games < args.min_games => Vec::new(),
+        Weight::Policy => moves
+            .map(|(raw_move, move_stats)| (raw_move, move_stats.policy / stats.games as f64))
+            .collect(),
+        Weight::Visits => moves
+            .map(|(raw_move, move_stats)| (raw_move, move_stats.visits))
+            .collect(),
+    }
+}
+
+fn write_epd(
+    positions: &HashMap<u64, PositionStats>,
+    entries: &[BookEntry],
+    args: &BookArgs,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    for by_key in entries.chunk_by(|a, b| a.key == b.key) {
+        let stats = &positions[&by_key[0].key];
+        let moves: Vec<_> = by_key
+            .iter()
+            .filter_map(|entry| {
+                let m = polyglot::decode_move(entry.raw_move, &stats.pos)?;
+                Some(format!(
+                    "{}:{}",
+                    m.to_uci(CastlingMode::Standard),
+                    entry.weight
+                ))
+            })
+            .collect();
+        let epd = Epd::from_position(stats.pos.clone(), EnPassantMode::Legal);
+        writeln!(
+            out,
+            "{epd} games {}; moves \"{}\";",
+            stats.games,
+            moves.join(" ")
+        )?;
+    }
+    out.flush()
+}
+
+pub fn run(args: BookArgs) -> io::Result<()> {
+    let decode = DecodeOptions {
+        policy: args.weight != Weight::Games,
+        ..DecodeOptions::default()
+    };
+    let mut positions: HashMap<u64, PositionStats> = HashMap::new();
+    let mut games = 0u64;
+    archive::scan(&args.tar_path, |batch, progress| {
+        let visits = batch
+            .par_iter()
+            .map(|data| {
+                let game = Game::read_from(data.as_slice(), decode)?;
+                progress.game_decoded(game.samples.len());
+                Ok(book_visits(&game, args.max_plies))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        for visits in visits {
+            games += 1;
+            for visit in visits {
+                let stats = positions.entry(visit.key).or_insert_with(|| PositionStats {
+                    pos: visit.pos,
+                    games: 0,
+                    moves: HashMap::new(),
+                });
+                stats.games += 1;
+                stats.moves.entry(visit.played).or_default().games += 1;
+                for (raw_move, p) in visit.policy {
+                    let move_stats = stats.moves.entry(raw_move).or_default();
+                    move_stats.policy += p as f64;
+                    move_stats.visits += p as f64 * visit.visits as f64;
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut entries = Vec::new();
+    for (&key, stats) in &positions {
+        let moves = weighted_moves(stats, &args);
+        // The weights are scaled to fit in 16 bits, the counts of the games
+        // are only scaled down if needed.
+        let max = moves.iter().map(|&(_, weight)| weight).fold(0.0, f64::max);
+        if max <= 0.0 {
+            continue;
+        }
+        let scale = match args.weight {
+            Weight::Games => (u16::MAX as f64 / max).min(1.0),
+            Weight::Policy | Weight::Visits => u16::MAX as f64 / max,
+        };
+        for (raw_move, weight) in moves {
+            let weight = (weight * scale) as u16;
+            // Moves of the policy that were (almost) never searched are left
+            // out.
+            if weight == 0 && args.weight != Weight::Games {
+                continue;
+            }
+            entries.push(BookEntry {
+                key,
+                raw_move,
+                weight: weight.max(1),
+                learn: 0,
+            });
+        }
+    }
+    // The most frequent moves of a position come first.
+    entries.sort_by_key(|entry| (entry.key, u16::MAX - entry.weight, entry.raw_move));
+    match args.format {
+        BookFormat::Polyglot => polyglot::save(&entries, &args.output)?,
+        BookFormat::Epd => write_epd(&positions, &entries, &args)?,
+    }
+    let book_positions = entries.chunk_by(|a, b| a.key == b.key).count();
+    info!(
+        games,
+        positions = book_positions,
+        entries = entries.len(),
+        path = %args.output.display(),
+        "wrote the book"
+    );
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use preprocessing::output::{ShardState, ShardWriter};
+use preprocessing::resample::{Resampler, ResamplerState};
+use tracing::debug;
+
+// Progress of a preprocessing run that is persisted after every few processed
+// games, so that a crashed or interrupted run can be resumed with `--resume`.
+//
+// The checkpoint is only written after the output has been flushed, hence
+// everything that was written past the `bytes` of the shards is discarded on
+// resume and then regenerated from the recorded archive entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    // Path of the archive that was being processed, exactly as given on the
+    // command line.
+    pub archive: String,
+    // Index of the first tar entry in the archive that is not processed yet.
+    pub entry: usize,
+    // The shard being written by every writer of the run, one per phase with
+    // --split-by-phase.
+    pub shards: Vec<ShardState>,
+    // Seed of the run, which a resumed run has to keep to produce the same
+    // output. Checkpoints of older versions do not have it.
+    pub seed: Option<u64>,
+    // States of the resamplers of the evaluations (--eval-buckets) and of the
+    // results (--balance-outcomes), which continue where they stopped.
+    pub resampler: Option<ResamplerState>,
+    pub outcome_resampler: Option<ResamplerState>,
+}
+
+impl Checkpoint {
+    // The format is a simple list of `key=value` lines to keep it readable and
+    // easy to fix by hand. The shard keys of the writers after the first one
+    // have their index as a suffix: `shard_index.1`, `shard_samples.1`, ...
+    // The samples seen per bucket of a resampler are separated by commas.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+
+        let mut archive = None;
+        let mut entry = None;
+        // Index, samples and bytes of the shard of every writer.
+        let mut shards: Vec<[Option<u64>; 3]> = Vec::new();
+        let mut seed = None;
+        // Seen samples and random state of both resamplers.
+        let mut resamplers: [(Option<Vec<u64>>, Option<u64>); 2] = Default::default();
+
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (name, writer) = match key.split_once('.') {
+                Some((name, writer)) => (name, parse_number(key, writer)? as usize),
+                None => (key, 0),
+            };
+            let field = match name {
+                "shard_index" => 0,
+                "shard_samples" => 1,
+                "shard_bytes" => 2,
+                "archive" => {
+                    archive = Some(value.to_string());
+                    continue;
+                }
+                "entry" => {
+                    entry = Some(parse_number(key, value)?);
+                    continue;
+                }
+                "seed" => {
+                    seed = Some(parse_number(key, value)?);
+                    continue;
+                }
+                "resampler_seen" | "outcome_resampler_seen" => {
+                    let seen = value.split(',').map(|seen| parse_number(key, seen));
+                    resamplers[name.starts_with("outcome") as usize].0 =
+                        Some(seen.collect::<io::Result<_>>()?);
+                    continue;
+                }
+                "resampler_rng" | "outcome_resampler_rng" => {
+                    resamplers[name.starts_with("outcome") as usize].1 =
+                        Some(parse_number(key, value)?);
+                    continue;
+                }
+                _ => return Err(invalid_data(format!("unknown checkpoint key: {key}"))),
+            };
+            if shards.len() <= writer {
+                shards.resize(writer + 1, [None; 3]);
+            }
+            shards[writer][field] = Some(parse_number(key, value)?);
+        }
+
+        let missing = |key: &str| invalid_data(format!("missing checkpoint key: {key}"));
+        if shards.is_empty() {
+            return Err(missing("shard_index"));
+        }
+        let shards = shards
+            .into_iter()
+            .enumerate()
+            .map(|(writer, [index, samples, bytes])| {
+                let key = |name: &str| shard_key(name, writer);
+                Ok(ShardState {
+                    index: index.ok_or_else(|| missing(&key("shard_index")))? as usize,
+                    samples: samples.ok_or_else(|| missing(&key("shard_samples")))?,
+                    bytes: bytes.ok_or_else(|| missing(&key("shard_bytes")))?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let [resampler, outcome_resampler] = resamplers.map(|(seen, rng)| {
+            Some(ResamplerState {
+                seen: seen?,
+                rng: rng?,
+            })
+        });
+        Ok(Checkpoint {
+            archive: archive.ok_or_else(|| missing("archive"))?,
+            entry: entry.ok_or_else(|| missing("entry"))? as usize,
+            shards,
+            seed,
+            resampler,
+            outcome_resampler,
+        })
+    }
+
+    // Writes the checkpoint atomically: a crash while saving leaves the
+    // previous checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = fs::File::create(&tmp_path)?;
+        writeln!(file, "archive={}", self.archive)?;
+        writeln!(file, "entry={}", self.entry)?;
+        for (writer, shard) in self.shards.iter().enumerate() {
+            let fields = [
+                ("shard_index", shard.index as u64),
+                ("shard_samples", shard.samples),
+                ("shard_bytes", shard.bytes),
+            ];
+            for (name, value) in fields {
+                writeln!(file, "{}={value}", shard_key(name, writer))?;
+            }
+        }
+        if let Some(seed) = self.seed {
+            writeln!(file, "seed={seed}")?;
+        }
+        let resamplers = [
+            ("resampler", &self.resampler),
+            ("outcome_resampler", &self.outcome_resampler),
+        ];
+        for (name, state) in resamplers {
+            if let Some(ResamplerState { seen, rng }) = state {
+                let seen: Vec<_> = seen.iter().map(u64::to_string).collect();
+                writeln!(file, "{name}_seen={}", seen.join(","))?;
+                writeln!(file, "{name}_rng={rng}")?;
+            }
+        }
+        file.sync_all()?;
+
+        fs::rename(tmp_path, path)
+    }
+}
+
+// The key of the field of the shard of the writer.
+fn shard_key(name: &str, writer: usize) -> String {
+    match writer {
+        0 => name.to_string(),
+        _ => format!("{name}.{writer}"),
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> io::Result<u64> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid value for {key}: {value}")))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Periodically records the progress of the run.
+pub struct Checkpointer {
+    pub path: PathBuf,
+    // Number of processed games between two checkpoints.
+    pub interval: usize,
+    pub seed: u64,
+}
+
+impl Checkpointer {
+    pub fn save(
+        &self,
+        archive: &str,
+        entry: usize,
+        writers: &mut [ShardWriter],
+        resamplers: [Option<&Resampler>; 2],
+    ) -> io::Result<()> {
+        let shards = writers
+            .iter_mut()
+            .map(ShardWriter::flush)
+            .collect::<io::Result<Vec<_>>>()?;
+        debug!(archive, entry, shard = shards[0].index, "saving checkpoint");
+        Checkpoint {
+            archive: archive.to_string(),
+            entry,
+            shards,
+            seed: Some(self.seed),
+            resampler: resamplers[0].map(Resampler::state),
+            outcome_resampler: resamplers[1].map(Resampler::state),
+        }
+        .save(&self.path)
+    }
+}
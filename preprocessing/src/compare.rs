@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::output::{self, OutputSample};
+use serde_json::json;
+
+use crate::create_output;
+use crate::stats::Histogram;
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// First preprocessed output (directory with shards or a single shard)
+    a: PathBuf,
+
+    /// Second preprocessed output (directory with shards or a single shard)
+    b: PathBuf,
+
+    /// Also write the comparison as JSON to this path ("-" for stdout)
+    #[arg(long)]
+    json: Option<PathBuf>,
+}
+
+struct Dataset {
+    samples: u64,
+    // Hashes of the positions, ignoring the move counters.
+    positions: HashSet<u64>,
+    best_q: Histogram,
+    best_d: Histogram,
+}
+
+impl Dataset {
+    fn read(path: &Path) -> io::Result<Self> {
+        let mut dataset = Dataset {
+            samples: 0,
+            positions: HashSet::new(),
+            best_q: Histogram::linear(-1.0, 0.1, 20),
+            best_d: Histogram::linear(0.0, 0.05, 20),
+        };
+        output::for_each_line(path, |line| {
+            let sample = OutputSample::parse(line)?;
+            let mut hasher = DefaultHasher::new();
+            sample.position().hash(&mut hasher);
+
+            dataset.samples += 1;
+            dataset.positions.insert(hasher.finish());
+            dataset.best_q.add(sample.best_q as f64);
+            dataset.best_d.add(sample.best_d as f64);
+            Ok(())
+        })?;
+        Ok(dataset)
+    }
+}
+
+// Prints the shares of the bins of both histograms next to each other.
+fn write_distributions(out: &mut impl Write, a: &Histogram, b: &Histogram) -> io::Result<()> {
+    writeln!(out, "  {:>18} {:>8} {:>8} {:>9}", "", "A", "B", "B - A")?;
+    for (bin, (a_share, b_share)) in a.shares().into_iter().zip(b.shares()).enumerate() {
+        if a_share == 0.0 && b_share == 0.0 {
+            continue;
+        }
+        let (lower, upper) = a.bounds(bin);
+        let range = format!("[{lower}, {upper})");
+        writeln!(
+            out,
+            "  {range:>18} {:>7.2}% {:>7.2}% {:>+8.2}%",
+            100.0 * a_share,
+            100.0 * b_share,
+            100.0 * (b_share - a_share),
+        )?;
+    }
+    writeln!(out, "  total variation distance: {:.4}", a.distance(b))
+}
+
+pub fn run(args: CompareArgs) -> io::Result<()> {
+    let a = Dataset::read(&args.a)?;
+    let b = Dataset::read(&args.b)?;
+
+    let shared = a.positions.intersection(&b.positions).count();
+    let union = a.positions.len() + b.positions.len() - shared;
+    let jaccard = shared as f64 / union.max(1) as f64;
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "A: {}", args.a.display())?;
+    writeln!(out, "B: {}", args.b.display())?;
+    writeln!(out, "{:>24} {:>12} {:>12}", "", "A", "B")?;
+    writeln!(out, "{:>24} {:>12} {:>12}", "samples", a.samples, b.samples)?;
+    writeln!(
+        out,
+        "{:>24} {:>12} {:>12}",
+        "unique positions",
+        a.positions.len(),
+        b.positions.len()
+    )?;
+    writeln!(
+        out,
+        "{:>24} {:>12} {:>12}",
+        "not in the other",
+        a.positions.len() - shared,
+        b.positions.len() - shared
+    )?;
+    writeln!(
+        out,
+        "shared positions: {shared} (Jaccard index {jaccard:.4})"
+    )?;
+    writeln!(out, "\nbest_q:")?;
+    write_distributions(&mut out, &a.best_q, &b.best_q)?;
+    writeln!(out, "\nbest_d:")?;
+    write_distributions(&mut out, &a.best_d, &b.best_d)?;
+
+    if let Some(path) = args.json {
+        let dataset = |dataset: &Dataset| {
+            json!({
+                "samples": dataset.samples,
+                "unique_positions": dataset.positions.len(),
+                "exclusive_positions": dataset.positions.len() - shared,
+                "best_q": dataset.best_q,
+                "best_d": dataset.best_d,
+            })
+        };
+        let report = json!({
+            "a": dataset(&a),
+            "b": dataset(&b),
+            "shared_positions": shared,
+            "jaccard_index": jaccard,
+            "best_q_distance": a.best_q.distance(&b.best_q),
+            "best_d_distance": a.best_d.distance(&b.best_d),
+        });
+        serde_json::to_writer_pretty(create_output(&path)?, &report).map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+// Pipeline description read from a TOML file (`--config`). Every value can
+// also be given on the command line, which takes precedence:
+//
+//     inputs = ["training-run1-20240101.tar", "training-run1-20240102.tar"]
+//     threads = 16
+//     memory_limit = "8G"
+//     limit = 1000000
+//
+//     [filters]
+//     phases = ["middlegame", "endgame"]
+//     min_policy_entropy = 0.5
+//     castling = "repair"
+//     eval_buckets = [-0.5, -0.1, 0.1, 0.5]
+//
+//     [output]
+//     dir = "data/preprocessed"
+//     shard_size = 500000
+//     eco = true
+//     nnue = "halfkav2-hm"
+//     augment = ["mirror"]
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use preprocessing::augment::Augmentation;
+use preprocessing::filter::CastlingCheck;
+use preprocessing::material::MaterialSignature;
+use preprocessing::nnue::FeatureSet;
+use preprocessing::output::ProvenanceFormat;
+use preprocessing::phase::Phase;
+use preprocessing::sample::ValueSource;
+use preprocessing::source::InputFormat;
+use serde::Deserialize;
+
+use crate::memory;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub inputs: Vec<String>,
+    // Same as --input-format.
+    pub input_format: Option<InputFormat>,
+    // Same as --interleave.
+    pub interleave: Option<u32>,
+    pub threads: Option<usize>,
+    // Same format as --memory-limit.
+    pub memory_limit: Option<String>,
+    pub limit: Option<u64>,
+    pub limit_games: Option<u64>,
+    pub max_errors: Option<u64>,
+    pub seed: Option<u64>,
+    // Same as --report.
+    pub report: Option<PathBuf>,
+    pub filters: FiltersConfig,
+    pub output: OutputConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiltersConfig {
+    // Same as --min-elo, --max-elo and --rated-only.
+    pub min_elo: Option<u32>,
+    pub max_elo: Option<u32>,
+    pub rated_only: bool,
+    // Same as --min-time-control, --normal-termination and --min-plies.
+    pub min_time_control: Option<u32>,
+    pub normal_termination: bool,
+    pub min_plies: Option<u32>,
+    pub phases: Vec<Phase>,
+    pub min_policy_entropy: Option<f32>,
+    pub max_policy_entropy: Option<f32>,
+    // Same as --castling-check.
+    pub castling: Option<CastlingCheck>,
+    // Same as --material, e.g. ["KRPvKR", "KBNvK"].
+    pub material: Vec<MaterialSignature>,
+    // Same as --eval-buckets and --bucket-proportions.
+    pub eval_buckets: Vec<f32>,
+    pub bucket_proportions: Vec<f64>,
+    // Same as --balance-outcomes and --outcome-proportions.
+    pub balance_outcomes: bool,
+    pub outcome_proportions: Vec<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub dir: Option<PathBuf>,
+    pub shard_size: Option<u64>,
+    pub eco: bool,
+    pub phase_score: bool,
+    pub repetitions: bool,
+    pub rule50: bool,
+    pub plies_left: bool,
+    pub plies_left_bucket: Option<u32>,
+    pub value_source: Option<ValueSource>,
+    pub value_columns: Vec<ValueSource>,
+    pub played: bool,
+    pub lc0_planes: bool,
+    pub provenance: Option<ProvenanceFormat>,
+    pub nnue: Option<FeatureSet>,
+    pub augment: Vec<Augmentation>,
+    pub split_by_phase: bool,
+    // Same as --append and --dedup-existing.
+    pub append: bool,
+    pub dedup_existing: bool,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })
+    }
+
+    pub fn memory_limit(&self) -> io::Result<Option<u64>> {
+        self.memory_limit
+            .as_deref()
+            .map(memory::parse_size)
+            .transpose()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
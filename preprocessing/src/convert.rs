@@ -0,0 +1,63 @@
+// Conversion of the training data into JSON lines, one decoded sample per
+// line, for the tools that cannot read the binary format.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::{filter_position, FilterOptions, Game};
+use rayon::prelude::*;
+
+use crate::{archive, create_output};
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Path to write the samples to ("-" for stdout)
+    #[arg(long, default_value = "-")]
+    output: PathBuf,
+
+    /// Drop the samples rejected by the preprocessing filters instead of
+    /// converting all of them
+    #[arg(long)]
+    filter: bool,
+}
+
+pub fn run(args: ConvertArgs) -> io::Result<()> {
+    let mut out = create_output(&args.output)?;
+    let filters = FilterOptions::default();
+    let decode = filters.decode_options();
+
+    archive::scan(&args.tar_path, |batch, progress| {
+        // Games are converted in parallel, but written in the order of the
+        // archive.
+        let lines = batch
+            .par_iter()
+            .map(|data| {
+                let game = Game::read_from(data.as_slice(), decode)?;
+                progress.game_decoded(game.samples.len());
+                let mut lines = Vec::with_capacity(game.samples.len());
+                for sample in &game.samples {
+                    if args.filter {
+                        if let Some(filter) = filter_position(sample, &filters) {
+                            progress.sample_filtered(filter);
+                            continue;
+                        }
+                    }
+                    progress.sample_kept();
+                    lines.push(serde_json::to_string(sample).map_err(io::Error::other)?);
+                }
+                Ok::<_, io::Error>(lines)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        for line in lines.into_iter().flatten() {
+            writeln!(out, "{line}")?;
+        }
+        Ok(())
+    })?;
+
+    out.flush()
+}
@@ -0,0 +1,529 @@
+// Coordinator of preprocessing spread over several machines (`coordinate`),
+// for datasets that take one machine too long: the coordinator serves a queue
+// of the input archives over HTTP, workers (`work`, see worker) lease the
+// archives one at a time, run the preprocessing pipeline on them and upload
+// the output of every archive, its shards, manifest and report. The output
+// directory of the coordinator gets a directory per archive, item-NNNNN, in
+// the order of the inputs:
+//
+//     preprocessing coordinate -o out --addr 0.0.0.0:8090 data/
+//     preprocessing work --coordinator 10.0.0.1:8090 -- --config pipeline.toml
+//
+// A lease lasts --lease seconds and is renewed by the worker while it is
+// busy. The archives whose lease expires, e.g. because the worker died, and
+// the archives the worker reports as failed go back into the queue until they
+// were handed out --max-attempts times. Every lease has its own number, so a
+// worker that lost its lease cannot complete the archive of another one, and
+// the uploads of a lease go to a staging directory that is renamed into place
+// when the worker completes the archive.
+//
+// A coordinator restarted with the same inputs skips the archives with
+// complete directories. It exits once every archive is done or failed, after
+// the idle workers had the time to learn about it, and writes the state of the
+// archives to status.json (also served at /status). Like serve-ui, the server
+// is meant for trusted networks and has no authentication.
+//
+// The API, with the numbers of the archive and of the lease in the paths:
+//
+//     POST /lease                      200 with a Lease as JSON, 204 if all
+//                                      archives are leased, 410 when done
+//     POST /items/ID/LEASE/renew       200, or 409 if the lease was lost
+//     PUT  /items/ID/LEASE/files/PATH  stores a file of the output
+//     POST /items/ID/LEASE/complete    moves the files into place
+//     POST /items/ID/LEASE/fail        the body is the error
+//     GET  /status
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use preprocessing::storage;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::signals;
+
+// How long the workers wait before asking for an archive again when all of
+// them are leased.
+pub const IDLE_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long a finished coordinator keeps answering, so that the waiting
+// workers learn that there is nothing left.
+const DRAIN_TIME: Duration = Duration::from_secs(15);
+
+// How often the waiting coordinator checks for signals and expired leases.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Workers that do not send the next part of their request in time are
+// dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Longest error message of a failed attempt that is kept.
+const MAX_ERROR_LEN: u64 = 4096;
+
+// File in the directory of a done archive with the path of the archive.
+const ARCHIVE_FILE: &str = "archive";
+
+#[derive(Args)]
+pub struct CoordinateArgs {
+    /// Input archives, or directories of them (and prefixes of buckets ending
+    /// with a slash), handed out one archive at a time
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<String>,
+
+    /// Directory to collect the outputs of the archives in
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Address to listen on, use 0.0.0.0:<PORT> for workers on other machines
+    #[arg(long, default_value = "127.0.0.1:8090")]
+    addr: SocketAddr,
+
+    /// Seconds a worker keeps an archive without renewing its lease
+    #[arg(long, default_value_t = 600)]
+    lease: u64,
+
+    /// Number of times an archive is handed out before it counts as failed
+    #[arg(long, default_value_t = 3)]
+    max_attempts: u32,
+}
+
+// An archive handed out to a worker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Lease {
+    pub id: usize,
+    pub lease: u64,
+    pub archive: String,
+    // Seconds until the lease expires unless it is renewed.
+    pub seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum State {
+    Pending,
+    Leased,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct Item {
+    archive: String,
+    state: State,
+    attempts: u32,
+    // Why the last attempt failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    // Number and expiry of the current lease.
+    #[serde(skip)]
+    lease: Option<(u64, Instant)>,
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    pending: usize,
+    leased: usize,
+    done: usize,
+    failed: usize,
+    items: &'a [Item],
+}
+
+struct Queue {
+    items: Vec<Item>,
+    next_lease: u64,
+    // When the last archive was done or failed.
+    finished: Option<Instant>,
+}
+
+impl Queue {
+    fn count(&self, state: State) -> usize {
+        self.items.iter().filter(|item| item.state == state).count()
+    }
+
+    // The item of the lease, if the lease is still the current one.
+    fn leased(&mut self, id: usize, lease: u64) -> Option<&mut Item> {
+        self.items.get_mut(id).filter(|item| {
+            item.state == State::Leased && item.lease.is_some_and(|(number, _)| number == lease)
+        })
+    }
+
+    fn status_json(&self) -> io::Result<String> {
+        let status = Status {
+            pending: self.count(State::Pending),
+            leased: self.count(State::Leased),
+            done: self.count(State::Done),
+            failed: self.count(State::Failed),
+            items: &self.items,
+        };
+        serde_json::to_string_pretty(&status).map_err(io::Error::other)
+    }
+}
+
+// Ends the current attempt of the item, which is retried unless it used up
+// its attempts.
+fn fail_attempt(item: &mut Item, error: String, max_attempts: u32) {
+    item.lease = None;
+    item.state = if item.attempts >= max_attempts {
+        State::Failed
+    } else {
+        State::Pending
+    };
+    warn!(
+        archive = %item.archive,
+        attempts = item.attempts,
+        state = ?item.state,
+        %error,
+        "attempt failed"
+    );
+    item.error = Some(error);
+}
+
+fn item_dir(output_dir: &Path, id: usize) -> PathBuf {
+    output_dir.join(format!("item-{id:05}"))
+}
+
+fn staging_dir(output_dir: &Path, id: usize, lease: u64) -> PathBuf {
+    output_dir.join(format!(".item-{id:05}-{lease}"))
+}
+
+// The relative path of an uploaded file, None if it could leave the
+// directory.
+fn upload_path(name: &str) -> Option<PathBuf> {
+    let safe = |part: &str| {
+        !part.is_empty()
+            && part != "."
+            && part != ".."
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    };
+    name.split('/').all(safe).then(|| name.split('/').collect())
+}
+
+// The archives of the inputs, with the directories listed.
+fn expand(inputs: &[String]) -> io::Result<Vec<String>> {
+    let mut archives = Vec::new();
+    for input in inputs {
+        if Path::new(input).is_dir() || (storage::is_remote(input) && input.ends_with('/')) {
+            archives.extend(
+                storage::list_archives(input)?
+                    .into_iter()
+                    .map(|(path, _)| path),
+            );
+        } else {
+            archives.push(input.clone());
+        }
+    }
+    Ok(archives)
+}
+
+type Reply = (&'static str, &'static str, String);
+
+fn text(status: &'static str, body: &str) -> Reply {
+    (status, "text/plain; charset=utf-8", format!("{body}\n"))
+}
+
+// Method, path and length of the body of a request, the other headers are
+// ignored.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(String, String, u64)> {
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut length = 0;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+                })?;
+            }
+        }
+        line.clear();
+    }
+    Ok((method, path, length))
+}
+
+struct Coordinator {
+    output_dir: PathBuf,
+    lease_time: Duration,
+    max_attempts: u32,
+    queue: Mutex<Queue>,
+}
+
+impl Coordinator {
+    // The queue with the expired leases returned to it.
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        let mut queue = self.queue.lock().expect("no request panicked");
+        let now = Instant::now();
+        for item in &mut queue.items {
+            if item.state == State::Leased && item.lease.is_some_and(|(_, until)| until < now) {
+                fail_attempt(item, "the lease expired".to_string(), self.max_attempts);
+            }
+        }
+        let finished = queue
+            .items
+            .iter()
+            .all(|item| matches!(item.state, State::Done | State::Failed));
+        if finished && queue.finished.is_none() {
+            info!("all archives are done or failed");
+            queue.finished = Some(now);
+        }
+        queue
+    }
+
+    // Whether every archive is done or failed and the workers had the time to
+    // learn about it.
+    fn drained(&self) -> bool {
+        self.queue()
+            .finished
+            .is_some_and(|finished| finished.elapsed() >= DRAIN_TIME)
+    }
+
+    fn lease(&self) -> io::Result<Reply> {
+        let mut queue = self.queue();
+        if queue.finished.is_some() {
+            return Ok(text("410 Gone", "all archives are done"));
+        }
+        let number = queue.next_lease;
+        let Some((id, item)) = queue
+            .items
+            .iter_mut()
+            .enumerate()
+            .find(|(_, item)| item.state == State::Pending)
+        else {
+            return Ok(("204 No Content", "text/plain; charset=utf-8", String::new()));
+        };
+        item.state = State::Leased;
+        item.attempts += 1;
+        item.lease = Some((number, Instant::now() + self.lease_time));
+        info!(id, lease = number, archive = %item.archive, "leased the archive");
+        let lease = Lease {
+            id,
+            lease: number,
+            archive: item.archive.clone(),
+            seconds: self.lease_time.as_secs(),
+        };
+        queue.next_lease += 1;
+        let json = serde_json::to_string(&lease).map_err(io::Error::other)?;
+        Ok(("200 OK", "application/json", json))
+    }
+
+    fn renew(&self, id: usize, lease: u64) -> Reply {
+        let mut queue = self.queue();
+        let Some(item) = queue.leased(id, lease) else {
+            return text("409 Conflict", "the lease was lost");
+        };
+        item.lease = Some((lease, Instant::now() + self.lease_time));
+        text("200 OK", "renewed")
+    }
+
+    fn upload<R: Read>(
+        &self,
+        id: usize,
+        lease: u64,
+        name: &str,
+        body: &mut R,
+    ) -> io::Result<Reply> {
+        if self.queue().leased(id, lease).is_none() {
+            return Ok(text("409 Conflict", "the lease was lost"));
+        }
+        let Some(relative) = upload_path(name) else {
+            return Ok(text("400 Bad Request", "invalid file name"));
+        };
+        let path = staging_dir(&self.output_dir, id, lease).join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(&path)?);
+        io::copy(body, &mut file)?;
+        file.flush()?;
+        debug!(id, lease, name, "stored an uploaded file");
+        Ok(text("200 OK", "stored"))
+    }
+
+    fn complete(&self, id: usize, lease: u64) -> io::Result<Reply> {
+        let mut queue = self.queue();
+        let Some(item) = queue.leased(id, lease) else {
+            return Ok(text("409 Conflict", "the lease was lost"));
+        };
+        let staging = staging_dir(&self.output_dir, id, lease);
+        fs::create_dir_all(&staging)?;
+        fs::write(staging.join(ARCHIVE_FILE), &item.archive)?;
+        let dir = item_dir(&self.output_dir, id);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::rename(&staging, &dir)?;
+        item.state = State::Done;
+        item.lease = None;
+        item.error = None;
+        info!(id, archive = %item.archive, "archive done");
+        Ok(text("200 OK", "done"))
+    }
+
+    fn fail<R: Read>(&self, id: usize, lease: u64, body: &mut R) -> io::Result<Reply> {
+        let mut error = String::new();
+        body.take(MAX_ERROR_LEN).read_to_string(&mut error)?;
+        let mut queue = self.queue();
+        let Some(item) = queue.leased(id, lease) else {
+            return Ok(text("409 Conflict", "the lease was lost"));
+        };
+        fail_attempt(item, error.trim().to_string(), self.max_attempts);
+        let staging = staging_dir(&self.output_dir, id, lease);
+        if staging.exists() {
+            fs::remove_dir_all(staging)?;
+        }
+        Ok(text("200 OK", "failed"))
+    }
+
+    fn dispatch<R: Read>(&self, method: &str, path: &str, body: &mut R) -> io::Result<Reply> {
+        let parts: Vec<_> = path.trim_start_matches('/').splitn(5, '/').collect();
+        match (method, parts.as_slice()) {
+            ("POST", ["lease"]) => self.lease(),
+            ("GET", ["status"]) => {
+                let json = self.queue().status_json()?;
+                Ok(("200 OK", "application/json", json))
+            }
+            (method, ["items", id, lease, action @ ..]) => {
+                let (Ok(id), Ok(lease)) = (id.parse::<usize>(), lease.parse::<u64>()) else {
+                    return Ok(text("400 Bad Request", "invalid archive or lease"));
+                };
+                match (method, action) {
+                    ("POST", ["renew"]) => Ok(self.renew(id, lease)),
+                    ("PUT", ["files", name]) => self.upload(id, lease, name, body),
+                    ("POST", ["complete"]) => self.complete(id, lease),
+                    ("POST", ["fail"]) => self.fail(id, lease, body),
+                    _ => Ok(text("404 Not Found", "not found")),
+                }
+            }
+            _ => Ok(text("404 Not Found", "not found")),
+        }
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let (method, path, length) = read_head(&mut reader)?;
+        let mut body = reader.take(length);
+        let (status, content_type, body) = match self.dispatch(&method, &path, &mut body) {
+            // The rest of the body is read, so that the worker gets to read the
+            // answer.
+            Ok(reply) => {
+                io::copy(&mut body, &mut io::sink())?;
+                reply
+            }
+            Err(err) => {
+                warn!(%err, %method, %path, "failed to answer a request");
+                text("500 Internal Server Error", &err.to_string())
+            }
+        };
+        debug!(%method, %path, status, "answered request");
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+pub fn run(args: CoordinateArgs) -> io::Result<()> {
+    signals::install()?;
+    let archives = expand(&args.inputs)?;
+    if archives.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no archives in the inputs",
+        ));
+    }
+    fs::create_dir_all(&args.output_dir)?;
+    // The uploads of the leases of an earlier run are incomplete.
+    for entry in fs::read_dir(&args.output_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(".item-") {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    let items: Vec<_> = archives
+        .into_iter()
+        .enumerate()
+        .map(|(id, archive)| {
+            let marker = item_dir(&args.output_dir, id).join(ARCHIVE_FILE);
+            let done = fs::read_to_string(marker).is_ok_and(|done| done == archive);
+            Item {
+                archive,
+                state: if done { State::Done } else { State::Pending },
+                attempts: 0,
+                error: None,
+                lease: None,
+            }
+        })
+        .collect();
+    let coordinator = Coordinator {
+        output_dir: args.output_dir.clone(),
+        lease_time: Duration::from_secs(args.lease.max(1)),
+        max_attempts: args.max_attempts.max(1),
+        queue: Mutex::new(Queue {
+            items,
+            next_lease: 0,
+            finished: None,
+        }),
+    };
+    let listener = TcpListener::bind(args.addr)?;
+    listener.set_nonblocking(true)?;
+    {
+        let queue = coordinator.queue();
+        info!(
+            archives = queue.items.len(),
+            done = queue.count(State::Done),
+            "serving the archives at http://{}/",
+            listener.local_addr()?
+        );
+    }
+
+    let coordinator = &coordinator;
+    thread::scope(|scope| loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                scope.spawn(move || {
+                    if let Err(err) = coordinator.respond(stream) {
+                        debug!(%err, "failed to answer a request");
+                    }
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if signals::interrupted() || coordinator.drained() {
+                    return Ok(());
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(err) => return Err(err),
+        }
+    })?;
+
+    let queue = coordinator.queue();
+    fs::write(args.output_dir.join("status.json"), queue.status_json()?)?;
+    let (done, failed) = (queue.count(State::Done), queue.count(State::Failed));
+    println!(
+        "{done} of {} archives done, {failed} failed",
+        queue.items.len()
+    );
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{failed} archives failed, see {}",
+            args.output_dir.join("status.json").display()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+// Random access to the samples of the sharded output.
+//
+// Next to every shard, ShardWriter keeps an index of fixed-size entries: the
+// byte offset of every line as a little-endian u64. A sample starts at the
+// offset recorded for it and ends right before the offset of the next one (or
+// at the end of the shard), so fetching any sample takes two small reads
+// regardless of the size of the dataset. This allows drawing the training
+// samples uniformly at random instead of streaming through the shards.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::output::{index_path, shard_paths, INDEX_ENTRY_SIZE};
+
+struct IndexedShard {
+    path: PathBuf,
+    data: File,
+    index: File,
+    samples: u64,
+    bytes: u64,
+}
+
+/// Samples of an output directory addressed by their global index, in the
+/// order they were written.
+///
+/// ```no_run
+/// use preprocessing::dataset::IndexedDataset;
+/// use preprocessing::rng::Rng;
+///
+/// let mut dataset = IndexedDataset::open("out")?;
+/// let mut rng = Rng::new(42);
+/// for _ in 0..1024 {
+///     let line = dataset.get(rng.below(dataset.len()))?;
+///     println!("{line}");
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct IndexedDataset {
+    shards: Vec<IndexedShard>,
+    // Global index of the first sample of every shard.
+    starts: Vec<u64>,
+    len: u64,
+}
+
+impl IndexedDataset {
+    /// Opens the shards of the output directory (or a single shard) together
+    /// with their indices.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut shards = Vec::new();
+        let mut starts = Vec::new();
+        let mut len = 0;
+        for path in shard_paths(path.as_ref())? {
+            let index_path = index_path(&path);
+            let index = File::open(&index_path).map_err(|err| {
+                io::Error::new(err.kind(), format!("{}: {err}", index_path.display()))
+            })?;
+            let data = File::open(&path)?;
+            let samples = index.metadata()?.len() / INDEX_ENTRY_SIZE;
+            let bytes = data.metadata()?.len();
+            starts.push(len);
+            len += samples;
+            shards.push(IndexedShard {
+                path,
+                data,
+                index,
+                samples,
+                bytes,
+            });
+        }
+        Ok(IndexedDataset {
+            shards,
+            starts,
+            len,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The position of the shard holding the sample and the index of the
+    // sample in the shard.
+    fn find(&self, idx: u64) -> io::Result<(usize, u64)> {
+        if idx >= self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sample {idx} is out of range, the dataset has {}", self.len),
+            ));
+        }
+        // Empty shards start at the same index as the next one and are
+        // skipped.
+        let shard_idx = self.starts.partition_point(|&start| start <= idx) - 1;
+        Ok((shard_idx, idx - self.starts[shard_idx]))
+    }
+
+    /// Path of the shard holding the sample with the given global index and
+    /// the index of the sample within the shard (its line, counting from 0).
+    pub fn locate(&self, idx: u64) -> io::Result<(&Path, u64)> {
+        let (shard_idx, local) = self.find(idx)?;
+        Ok((&self.shards[shard_idx].path, local))
+    }
+
+    /// The serialized sample with the given global index, without the line
+    /// break.
+    pub fn get(&mut self, idx: u64) -> io::Result<String> {
+        let (shard_idx, local) = self.find(idx)?;
+        let shard = &mut self.shards[shard_idx];
+
+        let mut entries = [0; 2 * INDEX_ENTRY_SIZE as usize];
+        let last = local + 1 == shard.samples;
+        let entries = if last {
+            &mut entries[..INDEX_ENTRY_SIZE as usize]
+        } else {
+            &mut entries[..]
+        };
+        let offset = local * INDEX_ENTRY_SIZE;
+        shard.index.seek(SeekFrom::Start(offset))?;
+        shard.index.read_exact(entries)?;
+        let (start, next) = entries.split_at(INDEX_ENTRY_SIZE as usize);
+        let start = u64::from_le_bytes(start.try_into().unwrap());
+        let end = if last {
+            shard.bytes
+        } else {
+            u64::from_le_bytes(next.try_into().unwrap())
+        };
+
+        let mut line = vec![0; end.saturating_sub(start + 1) as usize];
+        shard.data.seek(SeekFrom::Start(start))?;
+        shard.data.read_exact(&mut line)?;
+        String::from_utf8(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
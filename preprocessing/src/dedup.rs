@@ -0,0 +1,361 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::{filter_position, FilterOptions, Game};
+use rayon::prelude::*;
+use serde_json::json;
+
+use crate::{archive, create_output, memory};
+
+// Approximate memory taken by an entry of the exact counter, including the
+// overhead of the hash table.
+const BYTES_PER_EXACT_ENTRY: u64 = 24;
+
+// Approximate memory taken by a hash of the existing positions.
+const BYTES_PER_EXISTING_POSITION: u64 = 16;
+
+// Candidates for the most frequent positions tracked per reported position.
+const CANDIDATES_PER_TOP_POSITION: usize = 10;
+
+#[derive(Args)]
+pub struct DedupReportArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Number of the most frequent positions to report
+    #[arg(long, default_value_t = 100)]
+    top: usize,
+
+    /// Estimate the number of unique positions with HyperLogLog instead of
+    /// counting them exactly
+    #[arg(long)]
+    approximate: bool,
+
+    /// Approximate limit on the memory used for exact counting, e.g. 512M or
+    /// 4G. Counting switches to HyperLogLog once it is exceeded
+    #[arg(long, value_parser = memory::parse_size)]
+    memory_limit: Option<u64>,
+
+    /// Also write the report as JSON to this path ("-" for stdout)
+    #[arg(long)]
+    json: Option<PathBuf>,
+}
+
+// HyperLogLog cardinality estimator with 2^14 registers, which has a standard
+// error of about 0.8%.
+//
+// https://algo.inria.fr/flajolet/Publications/FlFuGaMe07.pdf
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    const PRECISION: u32 = 14;
+
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; 1 << Self::PRECISION],
+        }
+    }
+
+    // The hash has to be uniformly distributed.
+    pub fn add(&mut self, hash: u64) {
+        let register = (hash >> (64 - Self::PRECISION)) as usize;
+        let rest = (hash << Self::PRECISION) | (1 << (Self::PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| (-(rank as f64)).exp2())
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // Linear counting is more precise for small cardinalities.
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+// Bloom filter of hashes with 7 probes per hash, which has a false positive
+// rate of about 1% with 10 bits per hash.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    len: u64,
+}
+
+impl BloomFilter {
+    const PROBES: u64 = 7;
+
+    pub fn new(bytes: u64) -> Self {
+        BloomFilter {
+            bits: vec![0; (bytes / 8).max(1) as usize],
+            len: 0,
+        }
+    }
+
+    // Bits of the hash in a filter of `size` bits, with double hashing of its
+    // halves. The hash has to be uniformly distributed.
+    fn probes(size: u64, hash: u64) -> impl Iterator<Item = u64> {
+        let (low, high) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        (0..Self::PROBES).map(move |probe| (low + probe * high) % size)
+    }
+
+    fn size(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        for bit in Self::probes(self.size(), hash) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        Self::probes(self.size(), hash)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    // Probability that a hash that was never inserted is contained.
+    pub fn false_positive_rate(&self) -> f64 {
+        let probes = Self::PROBES as f64;
+        (1.0 - (-probes * self.len as f64 / self.size() as f64).exp()).powf(probes)
+    }
+}
+
+// Hashes of the positions of the existing shards (`--dedup-existing`). They
+// are exact until they exceed their share of the memory limit, then in a
+// Bloom filter of that size, which drops a few new positions as existing.
+pub enum ExistingPositions {
+    Exact {
+        hashes: HashSet<u64>,
+        max_bytes: Option<u64>,
+    },
+    Approximate(BloomFilter),
+}
+
+impl ExistingPositions {
+    pub fn new(max_bytes: Option<u64>) -> Self {
+        ExistingPositions::Exact {
+            hashes: HashSet::new(),
+            max_bytes,
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        match self {
+            ExistingPositions::Exact { hashes, max_bytes } => {
+                hashes.insert(hash);
+                let bytes = hashes.len() as u64 * BYTES_PER_EXISTING_POSITION;
+                if let Some(max_bytes) = max_bytes.filter(|&max_bytes| bytes > max_bytes) {
+                    let mut filter = BloomFilter::new(max_bytes);
+                    hashes.iter().for_each(|&hash| filter.insert(hash));
+                    *self = ExistingPositions::Approximate(filter);
+                }
+            }
+            ExistingPositions::Approximate(filter) => filter.insert(hash),
+        }
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        match self {
+            ExistingPositions::Exact { hashes, .. } => hashes.contains(&hash),
+            ExistingPositions::Approximate(filter) => filter.contains(hash),
+        }
+    }
+
+    // Approximate memory taken by the hashes.
+    pub fn bytes(&self) -> u64 {
+        match self {
+            ExistingPositions::Exact { hashes, .. } => {
+                hashes.len() as u64 * BYTES_PER_EXISTING_POSITION
+            }
+            ExistingPositions::Approximate(filter) => filter.bits.len() as u64 * 8,
+        }
+    }
+}
+
+// Counts unique positions exactly until the memory limit is reached.
+enum UniqueCounter {
+    Exact {
+        counts: HashMap<u64, u64>,
+        max_entries: Option<usize>,
+    },
+    Approximate(HyperLogLog),
+}
+
+impl UniqueCounter {
+    fn add(&mut self, hash: u64) {
+        match self {
+            UniqueCounter::Exact {
+                counts,
+                max_entries,
+            } => {
+                *counts.entry(hash).or_default() += 1;
+                if max_entries.is_some_and(|max_entries| counts.len() > max_entries) {
+                    let mut hll = HyperLogLog::new();
+                    counts.keys().for_each(|&hash| hll.add(hash));
+                    *self = UniqueCounter::Approximate(hll);
+                }
+            }
+            UniqueCounter::Approximate(hll) => hll.add(hash),
+        }
+    }
+
+    fn is_exact(&self) -> bool {
+        matches!(self, UniqueCounter::Exact { .. })
+    }
+
+    fn unique(&self) -> f64 {
+        match self {
+            UniqueCounter::Exact { counts, .. } => counts.len() as f64,
+            UniqueCounter::Approximate(hll) => hll.estimate(),
+        }
+    }
+
+    fn exact_count(&self, hash: u64) -> Option<u64> {
+        match self {
+            UniqueCounter::Exact { counts, .. } => counts.get(&hash).copied(),
+            UniqueCounter::Approximate(_) => None,
+        }
+    }
+}
+
+// Misra-Gries summary of the most frequent positions. Every position that
+// makes up more than 1/(capacity + 1) of the input is guaranteed to be among
+// the candidates, its count is underestimated by at most the same fraction.
+struct HeavyHitters {
+    capacity: usize,
+    candidates: HashMap<u64, (u64, String)>,
+}
+
+impl HeavyHitters {
+    fn new(capacity: usize) -> Self {
+        HeavyHitters {
+            capacity,
+            candidates: HashMap::with_capacity(capacity),
+        }
+    }
+
+    // The FEN is only rendered for the positions that become candidates.
+    fn add(&mut self, hash: u64, fen: impl FnOnce() -> String) {
+        if let Some((count, _)) = self.candidates.get_mut(&hash) {
+            *count += 1;
+        } else if self.candidates.len() < self.capacity {
+            self.candidates.insert(hash, (1, fen()));
+        } else {
+            self.candidates.retain(|_, (count, _)| {
+                *count -= 1;
+                *count > 0
+            });
+        }
+    }
+}
+
+pub fn run(args: DedupReportArgs) -> io::Result<()> {
+    let mut unique = if args.approximate {
+        UniqueCounter::Approximate(HyperLogLog::new())
+    } else {
+        UniqueCounter::Exact {
+            counts: HashMap::new(),
+            max_entries: args
+                .memory_limit
+                .map(|limit| (limit / BYTES_PER_EXACT_ENTRY) as usize),
+        }
+    };
+    let mut heavy_hitters = HeavyHitters::new((args.top * CANDIDATES_PER_TOP_POSITION).max(1));
+    let mut samples = 0u64;
+    let filters = FilterOptions::default();
+
+    archive::scan(&args.tar_path, |batch, progress| {
+        let games = batch
+            .par_iter()
+            .map(|data| {
+                let mut game = Game::read_from(data.as_slice(), filters.decode_options())?;
+                progress.game_decoded(game.samples.len());
+                game.samples
+                    .retain(|sample| match filter_position(sample, &filters) {
+                        Some(filter) => {
+                            progress.sample_filtered(filter);
+                            false
+                        }
+                        None => {
+                            progress.sample_kept();
+                            true
+                        }
+                    });
+                let hashes: Vec<_> = game.samples.iter().map(|s| s.position_hash()).collect();
+                Ok((game, hashes))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for (game, hashes) in &games {
+            for (sample, &hash) in game.samples.iter().zip(hashes) {
+                samples += 1;
+                unique.add(hash);
+                heavy_hitters.add(hash, || sample.to_fen(&game.castling).to_string());
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut top: Vec<_> = heavy_hitters
+        .candidates
+        .into_iter()
+        .map(|(hash, (count, fen))| (unique.exact_count(hash).unwrap_or(count), fen))
+        .collect();
+    top.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    top.truncate(args.top);
+
+    let num_unique = unique.unique();
+    let duplication_factor = samples as f64 / num_unique.max(1.0);
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "samples:            {samples}")?;
+    if unique.is_exact() {
+        writeln!(out, "unique positions:   {num_unique}")?;
+    } else {
+        writeln!(
+            out,
+            "unique positions:   ~{num_unique:.0} (HyperLogLog estimate)"
+        )?;
+    }
+    writeln!(out, "duplication factor: {duplication_factor:.3}")?;
+    writeln!(out, "\ntop {} positions:", top.len())?;
+    if !unique.is_exact() {
+        writeln!(out, "(counts are lower bounds)")?;
+    }
+    for (count, fen) in &top {
+        let share = 100.0 * *count as f64 / samples.max(1) as f64;
+        writeln!(out, "{count:>12} {share:>7.3}%  {fen}")?;
+    }
+
+    if let Some(path) = args.json {
+        let report = json!({
+            "samples": samples,
+            "unique_positions": num_unique,
+            "exact": unique.is_exact(),
+            "duplication_factor": duplication_factor,
+            "top_positions": top
+                .iter()
+                .map(|(count, fen)| json!({ "fen": fen, "count": count }))
+                .collect::<Vec<_>>(),
+        });
+        serde_json::to_writer_pretty(create_output(&path)?, &report).map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
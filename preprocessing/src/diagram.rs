@@ -0,0 +1,229 @@
+// Board diagrams of positions as SVG images, and as PNG with the `png`
+// feature, for reports and debugging artifacts.
+//
+// The pieces are drawn with the Unicode chess symbols, using the solid
+// symbols for both colors: the white pieces are filled white and outlined. The
+// PNG rasterization therefore needs a system font with the symbols, e.g.
+// DejaVu Sans.
+
+use std::fmt::Write as _;
+use std::io;
+
+use shakmaty::{Board, Color, File, Rank, Role, Square};
+
+const LIGHT_SQUARE: &str = "#f0d9b5";
+const DARK_SQUARE: &str = "#b58863";
+const ARROW: &str = "#15781b";
+
+/// What is drawn on the board besides the pieces.
+#[derive(Debug, Clone)]
+pub struct Annotations {
+    /// A move drawn as an arrow from the first square to the second, e.g. the
+    /// best move.
+    pub arrow: Option<(Square, Square)>,
+    /// Text below the board, e.g. the evaluation.
+    pub caption: Option<String>,
+    /// Draw the board from the side of black.
+    pub flipped: bool,
+    /// Size of a square in pixels.
+    pub square_size: u32,
+}
+
+impl Default for Annotations {
+    fn default() -> Self {
+        Annotations {
+            arrow: None,
+            caption: None,
+            flipped: false,
+            square_size: 45,
+        }
+    }
+}
+
+// Top left corner of the square in the image.
+fn corner(square: Square, annotations: &Annotations) -> (u32, u32) {
+    let (file, rank) = (u32::from(square.file()), u32::from(square.rank()));
+    let (col, row) = if annotations.flipped {
+        (7 - file, rank)
+    } else {
+        (file, 7 - rank)
+    };
+    (col * annotations.square_size, row * annotations.square_size)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The position as an SVG image.
+///
+/// ```
+/// use preprocessing::diagram::{svg, Annotations};
+/// use shakmaty::{Board, Square};
+///
+/// let annotations = Annotations {
+///     arrow: Some((Square::E2, Square::E4)),
+///     caption: Some("best move e2e4".to_string()),
+///     ..Annotations::default()
+/// };
+/// let image = svg(&Board::default(), &annotations);
+/// assert!(image.starts_with("<svg"));
+/// assert!(image.contains("best move e2e4"));
+/// ```
+pub fn svg(board: &Board, annotations: &Annotations) -> String {
+    let size = annotations.square_size;
+    let width = 8 * size;
+    let caption_height = annotations.caption.as_ref().map_or(0, |_| size);
+    let height = width + caption_height;
+    let mut out = String::new();
+    // Writing to a String does not fail.
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" font-family=\"DejaVu Sans, sans-serif\">"
+    );
+    let _ = writeln!(
+        out,
+        "<rect width=\"{width}\" height=\"{height}\" fill=\"#ffffff\"/>"
+    );
+
+    let colors = |square: Square| {
+        if square.is_light() {
+            (LIGHT_SQUARE, DARK_SQUARE)
+        } else {
+            (DARK_SQUARE, LIGHT_SQUARE)
+        }
+    };
+    for square in Square::ALL {
+        let (x, y) = corner(square, annotations);
+        let (fill, _) = colors(square);
+        let _ = writeln!(
+            out,
+            "<rect x=\"{x}\" y=\"{y}\" width=\"{size}\" height=\"{size}\" fill=\"{fill}\"/>"
+        );
+    }
+
+    // Coordinates along the left and the bottom edges, in the color of the
+    // squares of the other color.
+    let label_size = size / 5;
+    let (left, bottom) = if annotations.flipped {
+        (File::H, Rank::Eighth)
+    } else {
+        (File::A, Rank::First)
+    };
+    for i in 0..8 {
+        let file = File::new(i);
+        let square = Square::from_coords(file, bottom);
+        let (x, y) = corner(square, annotations);
+        let (_, fill) = colors(square);
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" font-size=\"{label_size}\" fill=\"{fill}\" \
+             text-anchor=\"end\">{}</text>",
+            x + size - 2,
+            y + size - 3,
+            file.char()
+        );
+        let rank = Rank::new(i);
+        let square = Square::from_coords(left, rank);
+        let (x, y) = corner(square, annotations);
+        let (_, fill) = colors(square);
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" font-size=\"{label_size}\" fill=\"{fill}\">{}</text>",
+            x + 2,
+            y + label_size + 1,
+            rank.char()
+        );
+    }
+
+    for square in board.occupied() {
+        let piece = board.piece_at(square).expect("the square is occupied");
+        let (x, y) = corner(square, annotations);
+        let fill = match piece.color {
+            Color::White => "#ffffff",
+            Color::Black => "#000000",
+        };
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" fill=\"{fill}\" \
+             stroke=\"#000000\" stroke-width=\"{}\">{}</text>",
+            x + size / 2,
+            y + size * 4 / 5,
+            size * 4 / 5,
+            size as f32 / 45.0,
+            piece_symbol(piece.role)
+        );
+    }
+
+    if let Some((from, to)) = annotations.arrow {
+        let center = |square| {
+            let (x, y) = corner(square, annotations);
+            ((x + size / 2) as f32, (y + size / 2) as f32)
+        };
+        let ((x1, y1), (x2, y2)) = (center(from), center(to));
+        // The line stops short of the center, where the tip of the head is.
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len = (dx * dx + dy * dy).sqrt().max(1.0);
+        let head = size as f32 * 0.4;
+        let (ex, ey) = (x2 - dx / len * head, y2 - dy / len * head);
+        let (nx, ny) = (-dy / len * head / 2.0, dx / len * head / 2.0);
+        let _ = writeln!(
+            out,
+            "<g fill=\"{ARROW}\" stroke=\"{ARROW}\" opacity=\"0.8\">\
+             <line x1=\"{x1}\" y1=\"{y1}\" x2=\"{ex}\" y2=\"{ey}\" stroke-width=\"{}\" \
+             stroke-linecap=\"round\"/>\
+             <polygon points=\"{x2},{y2} {},{} {},{}\" stroke-width=\"0\"/></g>",
+            size as f32 / 6.0,
+            ex + nx,
+            ey + ny,
+            ex - nx,
+            ey - ny
+        );
+    }
+
+    if let Some(caption) = &annotations.caption {
+        let _ = writeln!(
+            out,
+            "<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" \
+             fill=\"#000000\">{}</text>",
+            width / 2,
+            width + size * 5 / 8,
+            size * 2 / 5,
+            escape(caption)
+        );
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+// The solid symbol of the piece, see the header.
+fn piece_symbol(role: Role) -> char {
+    ['♟', '♞', '♝', '♜', '♛', '♚'][role as usize - 1]
+}
+
+/// Rasterizes an image made by [`svg`] to PNG, with the system fonts.
+#[cfg(feature = "png")]
+pub fn png(svg: &str) -> io::Result<Vec<u8>> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_str(svg, &options)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the image is empty"))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "png"))]
+pub fn png(_svg: &str) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "rendering PNG images needs the png feature",
+    ))
+}
@@ -0,0 +1,123 @@
+// Classification of the openings by the Encyclopaedia of Chess Openings (ECO).
+//
+// The embedded table (eco.tsv) lists the main line of each opening as UCI
+// moves from the standard starting position. The lines are replayed once and
+// the resulting positions are matched against the early positions of every
+// game, so transpositions into a known line are recognized as well.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use shakmaty::uci::UciMove;
+use shakmaty::{Chess, Position};
+
+use crate::sample::{planes, splitmix64, TrainingSample, NUM_PLANES};
+
+#[derive(Debug)]
+pub struct Opening {
+    pub code: &'static str,
+    pub name: &'static str,
+}
+
+struct EcoTable {
+    openings: Vec<Opening>,
+    // Hash of the final position of the line -> index in `openings`.
+    positions: HashMap<u64, usize>,
+    // Length of the longest line, later positions are not looked up.
+    max_plies: usize,
+}
+
+impl EcoTable {
+    fn parse(tsv: &'static str) -> Self {
+        let mut table = EcoTable {
+            openings: Vec::new(),
+            positions: HashMap::new(),
+            max_plies: 0,
+        };
+        for line in tsv.lines().filter(|line| !line.is_empty()) {
+            let mut columns = line.split('\t');
+            let (Some(code), Some(name), Some(moves)) =
+                (columns.next(), columns.next(), columns.next())
+            else {
+                panic!("invalid ECO table line: {line}");
+            };
+
+            let mut position = Chess::default();
+            let mut plies = 0;
+            for uci in moves.split_whitespace() {
+                let m = UciMove::from_ascii(uci.as_bytes())
+                    .ok()
+                    .and_then(|uci| uci.to_move(&position).ok())
+                    .unwrap_or_else(|| panic!("illegal move {uci} in ECO line {code} {name}"));
+                position.play_unchecked(&m);
+                plies += 1;
+            }
+
+            let hash = position_hash(&planes(position.board(), position.turn()), plies);
+            // The first line reaching a position names it.
+            table.positions.entry(hash).or_insert(table.openings.len());
+            table.openings.push(Opening { code, name });
+            table.max_plies = table.max_plies.max(plies);
+        }
+        table
+    }
+}
+
+fn table() -> &'static EcoTable {
+    static TABLE: OnceLock<EcoTable> = OnceLock::new();
+    TABLE.get_or_init(|| EcoTable::parse(include_str!("eco.tsv")))
+}
+
+// Both sides see the same planes in mirrored positions, so the parity of the
+// ply disambiguates them.
+fn position_hash(planes: &[u64; NUM_PLANES], ply: usize) -> u64 {
+    planes
+        .iter()
+        .fold(splitmix64(ply as u64 % 2), |hash, &plane| {
+            splitmix64(hash ^ plane)
+        })
+}
+
+/// Returns the opening of the game, assuming it starts from the standard
+/// starting position. The deepest known position of the game determines the
+/// opening, also if the game transposes into it.
+///
+/// ```
+/// use preprocessing::eco;
+/// use preprocessing::encoder::GameRecorder;
+/// use preprocessing::synthetic::gzip;
+/// use preprocessing::{DecodeOptions, Game};
+/// use shakmaty::uci::UciMove;
+/// use shakmaty::Chess;
+///
+/// let game = |moves: &str| {
+///     let mut recorder = GameRecorder::new(Chess::default());
+///     for uci in moves.split_whitespace() {
+///         let uci: UciMove = uci.parse().unwrap();
+///         let m = uci.to_move(recorder.position()).unwrap();
+///         recorder.play(&m, 0.0, 1.0);
+///     }
+///     let records = recorder.finish(None);
+///     Game::read_from(gzip(&records).as_slice(), DecodeOptions::default()).unwrap()
+/// };
+/// // The Ruy Lopez (C60) passes through the King's Knight Opening (C44).
+/// let ruy_lopez = game("e2e4 e7e5 g1f3 b8c6 f1b5 a7a6");
+/// assert_eq!(eco::classify(&ruy_lopez.samples).unwrap().code, "C60");
+/// // The knights come out first and transpose into it.
+/// let transposed = game("g1f3 b8c6 e2e4 e7e5 f1b5 a7a6");
+/// assert_eq!(eco::classify(&transposed.samples).unwrap().code, "C60");
+/// ```
+pub fn classify(samples: &[TrainingSample]) -> Option<&'static Opening> {
+    let table = table();
+    samples
+        .iter()
+        .take(table.max_plies + 1)
+        .enumerate()
+        .rev()
+        .find_map(|(ply, sample)| {
+            table
+                .positions
+                .get(&position_hash(&sample.bitboards, ply))
+                .map(|&idx| &table.openings[idx])
+        })
+}
@@ -0,0 +1,300 @@
+// Encoding of positions into the input planes of lc0 networks (classical
+// input format), the inverse of decoding the planes of the training data.
+//
+// Like in the training data, the planes are seen from the perspective of the
+// side to move: the board is flipped vertically when black is to move and the
+// pieces of the side to move come first.
+
+use shakmaty::{Bitboard, CastlingSide, Chess, Color, Move, Position, Role};
+
+use crate::moves::idx_from_move;
+use crate::record::{Record, NUM_INPUT_PLANES};
+use crate::sample::TrainingSample;
+
+/// Planes of the network: the history planes, castling rights, side to move,
+/// rule50 counter and two constant planes.
+pub const NUM_NETWORK_PLANES: usize = NUM_INPUT_PLANES + 8;
+
+/// Number of positions in the history planes, including the current one.
+pub const HISTORY_LENGTH: usize = 8;
+
+// Planes per position in the history: 12 pieces and the repetition plane.
+const PLANES_PER_POSITION: usize = 13;
+
+/// A plane with `value` on the squares of `mask` and zero elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InputPlane {
+    pub mask: u64,
+    pub value: f32,
+}
+
+impl InputPlane {
+    fn filled(value: f32) -> Self {
+        InputPlane {
+            mask: u64::MAX,
+            value,
+        }
+    }
+
+    fn flag(set: bool) -> Self {
+        InputPlane {
+            mask: if set { u64::MAX } else { 0 },
+            value: 1.0,
+        }
+    }
+}
+
+/// Input of the network for a single position. The first
+/// [`NUM_INPUT_PLANES`] masks match the planes stored in the training data,
+/// see [`crate::record::Record::plane`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputPlanes(pub [InputPlane; NUM_NETWORK_PLANES]);
+
+impl InputPlanes {
+    /// The input of the network for the position of the sample, with the
+    /// history planes of its record. None unless the history planes were
+    /// decoded, see [`DecodeOptions::history`](crate::DecodeOptions::history).
+    ///
+    /// ```
+    /// use preprocessing::encoder::{encode, GameRecorder, InputPlanes};
+    /// use preprocessing::synthetic::gzip;
+    /// use preprocessing::{DecodeOptions, Game};
+    /// use shakmaty::{Chess, Position};
+    ///
+    /// let mut recorder = GameRecorder::new(Chess::default());
+    /// let mut positions = Vec::new();
+    /// for _ in 0..3 {
+    ///     positions.push(recorder.position().clone());
+    ///     let m = recorder.position().legal_moves()[0].clone();
+    ///     recorder.play(&m, 0.0, 1.0);
+    /// }
+    /// let records = recorder.finish(None);
+    /// let decode = DecodeOptions {
+    ///     history: true,
+    ///     ..DecodeOptions::default()
+    /// };
+    /// let game = Game::read_from(gzip(&records).as_slice(), decode)?;
+    ///
+    /// // Black to move after two earlier positions.
+    /// let planes = InputPlanes::from_sample(&game.samples[2]).unwrap();
+    /// let expected = encode(&positions[2], &positions[..2]);
+    /// assert_eq!(planes.to_dense(), expected.to_dense());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_sample(sample: &TrainingSample) -> Option<Self> {
+        let history = sample.history.as_deref()?;
+        let mut planes = [InputPlane::default(); NUM_NETWORK_PLANES];
+        for (plane, &mask) in planes.iter_mut().zip(history) {
+            *plane = InputPlane { mask, value: 1.0 };
+        }
+        planes[NUM_INPUT_PLANES] = InputPlane::flag(sample.castling_us_ooo);
+        planes[NUM_INPUT_PLANES + 1] = InputPlane::flag(sample.castling_us_oo);
+        planes[NUM_INPUT_PLANES + 2] = InputPlane::flag(sample.castling_them_ooo);
+        planes[NUM_INPUT_PLANES + 3] = InputPlane::flag(sample.castling_them_oo);
+        planes[NUM_INPUT_PLANES + 4] = InputPlane::flag(sample.black_to_move);
+        planes[NUM_INPUT_PLANES + 5] = InputPlane::filled(sample.rule50_count as f32);
+        planes[NUM_INPUT_PLANES + 6] = InputPlane::filled(0.0);
+        planes[NUM_INPUT_PLANES + 7] = InputPlane::filled(1.0);
+        Some(InputPlanes(planes))
+    }
+
+    /// Values of all squares, plane after plane, starting from a1 and going
+    /// through the files first.
+    pub fn to_dense(&self) -> Vec<f32> {
+        self.0
+            .iter()
+            .flat_map(|plane| {
+                (0..64).map(move |square| {
+                    if (plane.mask >> square) & 1 == 1 {
+                        plane.value
+                    } else {
+                        0.0
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+fn same_position<P: Position>(a: &P, b: &P) -> bool {
+    a.turn() == b.turn()
+        && a.board() == b.board()
+        && a.castles().castling_rights() == b.castles().castling_rights()
+}
+
+/// Encodes the position preceded by `history`, the earlier positions of the
+/// game from the oldest to the most recent one. Missing history positions are
+/// left empty.
+///
+/// ```
+/// use preprocessing::encoder::{encode, NUM_NETWORK_PLANES};
+/// use shakmaty::{Chess, Move, Position, Role, Square};
+///
+/// let start = Chess::default();
+/// let planes = encode(&start, &[]);
+/// // White pawns and the constant plane.
+/// assert_eq!(planes.0[0].mask, 0xFF00);
+/// assert_eq!(planes.0[NUM_NETWORK_PLANES - 1].mask, u64::MAX);
+///
+/// let e4 = Move::Normal {
+///     role: Role::Pawn,
+///     from: Square::E2,
+///     capture: None,
+///     to: Square::E4,
+///     promotion: None,
+/// };
+/// let after = start.clone().play(&e4).unwrap();
+/// let planes = encode(&after, &[start]);
+/// // Black pawns from the perspective of black, then the white pawns.
+/// assert_eq!(planes.0[0].mask, 0xFF00);
+/// assert_eq!(planes.0[6].mask, 0x00EF_0010_0000_0000);
+/// // The previous position is in the next history slot.
+/// assert_eq!(planes.0[13 + 6].mask, 0x00FF_0000_0000_0000);
+/// ```
+pub fn encode<P: Position>(position: &P, history: &[P]) -> InputPlanes {
+    let us = position.turn();
+    let flip = |bitboard: Bitboard| match us {
+        Color::White => bitboard.0,
+        Color::Black => bitboard.flip_vertical().0,
+    };
+
+    let mut planes = [InputPlane::default(); NUM_NETWORK_PLANES];
+    // The current position followed by the history, most recent first.
+    let positions: Vec<&P> = std::iter::once(position)
+        .chain(history.iter().rev())
+        .collect();
+    for (idx, current) in positions.iter().take(HISTORY_LENGTH).enumerate() {
+        let board = current.board();
+        let offset = idx * PLANES_PER_POSITION;
+        for (role_idx, role) in Role::ALL.into_iter().enumerate() {
+            planes[offset + role_idx] = InputPlane {
+                mask: flip(board.by_piece(role.of(us))),
+                value: 1.0,
+            };
+            planes[offset + 6 + role_idx] = InputPlane {
+                mask: flip(board.by_piece(role.of(!us))),
+                value: 1.0,
+            };
+        }
+        let repeated = positions[idx + 1..]
+            .iter()
+            .any(|earlier| same_position(*current, *earlier));
+        planes[offset + 12] = InputPlane::flag(repeated);
+    }
+
+    let castles = position.castles();
+    planes[NUM_INPUT_PLANES] = InputPlane::flag(castles.has(us, CastlingSide::QueenSide));
+    planes[NUM_INPUT_PLANES + 1] = InputPlane::flag(castles.has(us, CastlingSide::KingSide));
+    planes[NUM_INPUT_PLANES + 2] = InputPlane::flag(castles.has(!us, CastlingSide::QueenSide));
+    planes[NUM_INPUT_PLANES + 3] = InputPlane::flag(castles.has(!us, CastlingSide::KingSide));
+    planes[NUM_INPUT_PLANES + 4] = InputPlane::flag(us == Color::Black);
+    planes[NUM_INPUT_PLANES + 5] = InputPlane::filled(position.halfmoves() as f32);
+    // The move counter plane is unused and the last plane helps the
+    // convolutions to detect the edge of the board.
+    planes[NUM_INPUT_PLANES + 6] = InputPlane::filled(0.0);
+    planes[NUM_INPUT_PLANES + 7] = InputPlane::filled(1.0);
+
+    InputPlanes(planes)
+}
+
+/// Builds the version 6 records of a game from its moves, the inverse of
+/// decoding a game of the training data. Every record has the position before
+/// the move with the move as the played and the best one and all of the policy
+/// on it. Sources of games without search data, e.g. PGN files, use it to
+/// convert them to the training data format.
+///
+/// ```
+/// use preprocessing::encoder::GameRecorder;
+/// use preprocessing::synthetic::gzip;
+/// use preprocessing::{DecodeOptions, Game};
+/// use shakmaty::{Chess, Color, Position};
+///
+/// let mut recorder = GameRecorder::new(Chess::default());
+/// for _ in 0..4 {
+///     let m = recorder.position().legal_moves()[0].clone();
+///     recorder.play(&m, 0.0, 1.0);
+/// }
+/// let records = recorder.finish(Some(Color::White));
+/// let game = Game::read_from(gzip(&records).as_slice(), DecodeOptions::default())?;
+/// assert_eq!(game.samples.len(), 4);
+/// assert_eq!(game.samples[0].result_q, 1.0);
+/// assert_eq!(game.samples[1].result_q, -1.0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct GameRecorder {
+    position: Chess,
+    history: Vec<Chess>,
+    records: Vec<Record>,
+    turns: Vec<Color>,
+}
+
+impl GameRecorder {
+    pub fn new(position: Chess) -> Self {
+        GameRecorder {
+            position,
+            history: Vec::new(),
+            records: Vec::new(),
+            turns: Vec::new(),
+        }
+    }
+
+    /// The position the next move is played in.
+    pub fn position(&self) -> &Chess {
+        &self.position
+    }
+
+    /// Records the position with its evaluation (q, d) from the perspective
+    /// of the side to move and plays the move, which has to be legal. The
+    /// record can be changed further, e.g. to spread the policy.
+    pub fn play(&mut self, played: &Move, q: f32, d: f32) -> &mut Record {
+        let pos = &self.position;
+        let turn = pos.turn();
+        let mut record = Record::empty();
+        let planes = encode(pos, &self.history);
+        for (idx, plane) in planes.0.iter().take(NUM_INPUT_PLANES).enumerate() {
+            record.set_plane(idx, plane.mask);
+        }
+        let castles = pos.castles();
+        record.set_castling(
+            castles.has(turn, CastlingSide::QueenSide),
+            castles.has(turn, CastlingSide::KingSide),
+            castles.has(!turn, CastlingSide::QueenSide),
+            castles.has(!turn, CastlingSide::KingSide),
+        );
+        record.set_side_to_move_or_enpassant((turn == Color::Black) as u8);
+        record.set_rule50_count(pos.halfmoves().min(u8::MAX as u32) as u8);
+        for m in &pos.legal_moves() {
+            if let Some(idx) = idx_from_move(m, turn) {
+                record.set_probability(idx as usize, 0.0);
+            }
+        }
+        let played_idx = idx_from_move(played, turn).unwrap_or(0);
+        record.set_probability(played_idx as usize, 1.0);
+        record.set_played_idx(played_idx);
+        record.set_best_idx(played_idx);
+        record.set_root(q, d);
+        record.set_best(q, d);
+        record.set_played(q, d);
+        record.set_visits(1);
+
+        self.history.push(self.position.clone());
+        self.position.play_unchecked(played);
+        self.turns.push(turn);
+        self.records.push(record);
+        self.records.last_mut().expect("a record was just added")
+    }
+
+    /// Sets the result of the game, None for a draw, and returns the records.
+    pub fn finish(mut self, winner: Option<Color>) -> Vec<Record> {
+        let plies = self.records.len();
+        for (ply, (record, turn)) in self.records.iter_mut().zip(self.turns).enumerate() {
+            match winner {
+                Some(winner) if winner == turn => record.set_result(1.0, 0.0),
+                Some(_) => record.set_result(-1.0, 0.0),
+                None => record.set_result(0.0, 1.0),
+            }
+            record.set_plies_left((plies - ply) as f32);
+        }
+        self.records
+    }
+}
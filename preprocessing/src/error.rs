@@ -0,0 +1,98 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::record::RECORD_SIZE;
+
+/// Failures of reading and decoding the training data.
+///
+/// Errors of the games read from archives are wrapped in
+/// [`PreprocessError::InArchive`] and errors of reading the archives
+/// themselves in [`PreprocessError::Archive`], [`PreprocessError::kind`] looks
+/// through both:
+///
+/// ```
+/// use preprocessing::PreprocessError;
+///
+/// let err = PreprocessError::FormatVersion(5).in_archive("training.tar", 3, 1536);
+/// assert!(matches!(err.kind(), PreprocessError::FormatVersion(5)));
+/// assert_eq!(
+///     err.to_string(),
+///     "training.tar: entry 3 at byte 1536: unsupported training data version 5, expected 6"
+/// );
+/// ```
+#[derive(Debug, Error)]
+pub enum PreprocessError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid gzip stream: {0}")]
+    Gzip(#[source] io::Error),
+    #[error("unsupported training data version {0}, expected 6")]
+    FormatVersion(u32),
+    /// Number of bytes of the incomplete record.
+    #[error("truncated record: {0} of {RECORD_SIZE} bytes")]
+    TruncatedRecord(usize),
+    #[error("illegal position: {0}")]
+    IllegalPosition(String),
+    /// A game of a source other than the training data, e.g. a PGN file,
+    /// that cannot be converted to it.
+    #[error("invalid game: {0}")]
+    InvalidGame(String),
+    #[error("{}: entry {entry} at byte {offset}: {source}", archive.display())]
+    InArchive {
+        archive: PathBuf,
+        /// Index of the tar entry holding the game.
+        entry: usize,
+        /// Offset of the data of the entry within the archive.
+        offset: u64,
+        source: Box<PreprocessError>,
+    },
+    #[error("{}: {source}", archive.display())]
+    Archive {
+        archive: PathBuf,
+        source: Box<PreprocessError>,
+    },
+}
+
+pub type Result<T, E = PreprocessError> = std::result::Result<T, E>;
+
+impl PreprocessError {
+    /// Adds the location of the game the error occurred in.
+    pub fn in_archive(self, archive: impl Into<PathBuf>, entry: usize, offset: u64) -> Self {
+        PreprocessError::InArchive {
+            archive: archive.into(),
+            entry,
+            offset,
+            source: Box::new(self),
+        }
+    }
+
+    /// Adds the archive that could not be read.
+    pub fn of_archive(self, archive: impl Into<PathBuf>) -> Self {
+        PreprocessError::Archive {
+            archive: archive.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error without the location in the archive.
+    pub fn kind(&self) -> &PreprocessError {
+        match self {
+            PreprocessError::InArchive { source, .. } | PreprocessError::Archive { source, .. } => {
+                source.kind()
+            }
+            err => err,
+        }
+    }
+}
+
+// The binary and the analysis commands report all errors as I/O errors.
+impl From<PreprocessError> for io::Error {
+    fn from(err: PreprocessError) -> Self {
+        match err {
+            PreprocessError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
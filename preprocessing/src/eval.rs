@@ -0,0 +1,217 @@
+// Hand-crafted evaluation with tunable parameters.
+//
+// The evaluation is a tapered sum of terms: every term has a middlegame and an
+// endgame weight, interpolated by the game phase (see phase::board_score).
+// Since the evaluation is linear in the weights, a position is described by
+// the coefficient of every parameter (see `coefficients`), which the tuner
+// computes once for the whole position set.
+//
+// The parameters are declared once in the `params!` registry below, which
+// generates the struct, the default values and the names used in the tuned
+// output. Adding a term means adding its parameters to the registry and its
+// coefficients to `coefficients`.
+
+use shakmaty::{Board, Color, Role};
+
+use crate::phase::{self, MAX_SCORE};
+
+macro_rules! params {
+    ($($name:ident = $default:expr,)*) => {
+        /// Weights of the evaluation terms in centipawns.
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct Params {
+            $(pub $name: i32,)*
+        }
+
+        impl Default for Params {
+            fn default() -> Self {
+                Params {
+                    $($name: $default,)*
+                }
+            }
+        }
+
+        impl Params {
+            /// Names of the parameters, in the order of [`Params::to_vec`].
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name),)*];
+
+            pub fn to_vec(&self) -> Vec<i32> {
+                vec![$(self.$name,)*]
+            }
+
+            /// Inverse of [`Params::to_vec`].
+            pub fn from_slice(values: &[i32]) -> Self {
+                assert_eq!(values.len(), Self::NAMES.len());
+                let mut values = values.iter().copied();
+                Params {
+                    $($name: values.next().expect("checked length"),)*
+                }
+            }
+        }
+    };
+}
+
+params! {
+    pawn_mg = 82,
+    pawn_eg = 94,
+    knight_mg = 337,
+    knight_eg = 281,
+    bishop_mg = 365,
+    bishop_eg = 297,
+    rook_mg = 477,
+    rook_eg = 512,
+    queen_mg = 1025,
+    queen_eg = 936,
+    bishop_pair_mg = 30,
+    bishop_pair_eg = 50,
+    doubled_pawn_mg = -10,
+    doubled_pawn_eg = -20,
+    isolated_pawn_mg = -10,
+    isolated_pawn_eg = -10,
+    passed_pawn_mg = 10,
+    passed_pawn_eg = 30,
+    // Bonus per rank the passed pawn has advanced beyond its second rank.
+    passed_pawn_rank_eg = 10,
+}
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+
+// Differences of the counts of a pawn structure feature of both sides.
+struct PawnTerms {
+    doubled: i32,
+    isolated: i32,
+    passed: i32,
+    passed_ranks: i32,
+}
+
+// Pawn structure of `color` against the pawns of the opponent.
+fn pawn_terms(board: &Board, color: Color) -> PawnTerms {
+    let ours = (board.pawns() & board.by_color(color)).0;
+    let theirs = (board.pawns() & board.by_color(!color)).0;
+    let mut terms = PawnTerms {
+        doubled: 0,
+        isolated: 0,
+        passed: 0,
+        passed_ranks: 0,
+    };
+    for file in 0..8 {
+        let mask = FILE_A << file;
+        let pawns = (ours & mask).count_ones() as i32;
+        terms.doubled += (pawns - 1).max(0);
+        let neighbours = ((mask << 1) & !FILE_A) | ((mask >> 1) & !(FILE_A << 7));
+        if ours & neighbours == 0 {
+            terms.isolated += pawns;
+        }
+    }
+    let mut pawns = ours;
+    while pawns != 0 {
+        let square = pawns.trailing_zeros();
+        pawns &= pawns - 1;
+        let (file, rank) = (square % 8, square / 8);
+        let mut files = FILE_A << file;
+        files |= ((files << 1) & !FILE_A) | ((files >> 1) & !(FILE_A << 7));
+        // Squares in front of the pawn on its and the adjacent files.
+        let (front, advanced) = match color {
+            Color::White => (
+                u64::MAX.checked_shl(8 * (rank + 1)).unwrap_or(0),
+                rank.saturating_sub(1),
+            ),
+            Color::Black => ((1 << (8 * rank)) - 1, 6u32.saturating_sub(rank)),
+        };
+        if theirs & files & front == 0 {
+            terms.passed += 1;
+            terms.passed_ranks += advanced as i32;
+        }
+    }
+    terms
+}
+
+/// Pawn structure of a position: the differences between white and black in
+/// the doubled, isolated and passed pawns and in the ranks the passed pawns
+/// advanced. It depends only on the pawns, so engines can cache it by them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PawnStructure {
+    pub doubled: i32,
+    pub isolated: i32,
+    pub passed: i32,
+    pub passed_ranks: i32,
+}
+
+impl PawnStructure {
+    pub fn new(board: &Board) -> Self {
+        let (white, black) = (
+            pawn_terms(board, Color::White),
+            pawn_terms(board, Color::Black),
+        );
+        PawnStructure {
+            doubled: white.doubled - black.doubled,
+            isolated: white.isolated - black.isolated,
+            passed: white.passed - black.passed,
+            passed_ranks: white.passed_ranks - black.passed_ranks,
+        }
+    }
+}
+
+/// Coefficients of the parameters in the evaluation of the position from the
+/// perspective of white, in the order of [`Params::NAMES`].
+pub fn coefficients(board: &Board) -> Vec<f32> {
+    coefficients_with_pawns(board, &PawnStructure::new(board))
+}
+
+/// [`coefficients`] with the pawn structure of the board computed before.
+pub fn coefficients_with_pawns(board: &Board, pawns: &PawnStructure) -> Vec<f32> {
+    let phase = phase::board_score(board) as f32 / MAX_SCORE as f32;
+    let taper = |value: i32| [value as f32 * phase, value as f32 * (1.0 - phase)];
+    let count = |role: Role, color: Color| (board.by_role(role) & board.by_color(color)).count();
+    let balance = |role: Role| count(role, Color::White) as i32 - count(role, Color::Black) as i32;
+    let pair = |color: Color| (count(Role::Bishop, color) >= 2) as i32;
+
+    let mut coefficients = Vec::with_capacity(Params::NAMES.len());
+    for role in [
+        Role::Pawn,
+        Role::Knight,
+        Role::Bishop,
+        Role::Rook,
+        Role::Queen,
+    ] {
+        coefficients.extend(taper(balance(role)));
+    }
+    coefficients.extend(taper(pair(Color::White) - pair(Color::Black)));
+    coefficients.extend(taper(pawns.doubled));
+    coefficients.extend(taper(pawns.isolated));
+    coefficients.extend(taper(pawns.passed));
+    coefficients.push(taper(pawns.passed_ranks)[1]);
+    coefficients
+}
+
+impl Params {
+    /// Evaluation of the position in centipawns from the perspective of
+    /// white.
+    ///
+    /// ```
+    /// use preprocessing::eval::Params;
+    /// use shakmaty::fen::Fen;
+    /// use shakmaty::Board;
+    ///
+    /// let params = Params::default();
+    /// assert_eq!(params.evaluate(&Board::default()), 0);
+    /// // White is a knight up.
+    /// let fen: Fen = "r1bqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".parse().unwrap();
+    /// assert!(params.evaluate(&fen.into_setup().board) > 250);
+    /// ```
+    pub fn evaluate(&self, board: &Board) -> i32 {
+        self.evaluate_with_pawns(board, &PawnStructure::new(board))
+    }
+
+    /// [`Params::evaluate`] with the pawn structure of the board computed
+    /// before.
+    pub fn evaluate_with_pawns(&self, board: &Board, pawns: &PawnStructure) -> i32 {
+        let values = self.to_vec();
+        let sum: f32 = coefficients_with_pawns(board, pawns)
+            .iter()
+            .zip(values)
+            .map(|(coefficient, value)| coefficient * value as f32)
+            .sum();
+        sum.round() as i32
+    }
+}
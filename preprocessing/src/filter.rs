@@ -0,0 +1,199 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::material::MaterialSignature;
+use crate::phase::{self, Phase};
+use crate::sample::{DecodeOptions, TrainingSample};
+
+/// Positions with small number of pieces are usually adjudicated by Syzygy
+/// endgame tablebases. They are kept when selecting endgames by their
+/// material, see [`FilterOptions::material`].
+pub const MIN_PIECES: u32 = 7;
+
+/// Reasons for dropping a position from the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    FewPieces,
+    Promotion,
+    Phase,
+    PolicyEntropy,
+    Castling,
+    Material,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 6] = [
+        Filter::FewPieces,
+        Filter::Promotion,
+        Filter::Phase,
+        Filter::PolicyEntropy,
+        Filter::Castling,
+        Filter::Material,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::FewPieces => "few_pieces",
+            Filter::Promotion => "promotion",
+            Filter::Phase => "phase",
+            Filter::PolicyEntropy => "policy_entropy",
+            Filter::Castling => "castling",
+            Filter::Material => "material",
+        }
+    }
+}
+
+/// Handling of the samples with castling flags the board contradicts, see
+/// [`TrainingSample::castling_consistent`].
+///
+/// Independently of the check, [`Game::read_from`](crate::Game::read_from)
+/// drops the samples whose rights have no rook on the castling squares of the
+/// game, which would make wrong FENs. The check comes first: repaired samples
+/// are only dropped if their remaining rights have no rook, and the samples
+/// to skip are left to [`filter_position`] to count them.
+///
+/// ```
+/// use flate2::write::GzEncoder;
+/// use flate2::Compression;
+/// use preprocessing::filter::CastlingCheck;
+/// use preprocessing::record::Record;
+/// use preprocessing::{filter_position, Filter, FilterOptions, Game};
+/// use shakmaty::{Bitboard, Square};
+/// use std::io::Write;
+///
+/// let square = |square: Square| Bitboard::from(square).0;
+/// let record = |queen_rook: bool| {
+///     let mut record = Record::empty();
+///     record.set_plane(0, 0xff00);
+///     record.set_plane(3, square(Square::H1) | if queen_rook { square(Square::A1) } else { 0 });
+///     record.set_plane(5, square(Square::E1));
+///     record.set_plane(6, 0xff00);
+///     record.set_plane(9, square(Square::A8) | square(Square::H8));
+///     record.set_plane(11, square(Square::E8));
+///     record.set_castling(true, true, true, true);
+///     record
+/// };
+/// // The queen rook is captured on its square, keeping the flag.
+/// let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+/// gz.write_all(record(true).as_bytes())?;
+/// gz.write_all(record(false).as_bytes())?;
+/// let data = gz.finish()?;
+///
+/// let filters = |castling| FilterOptions { castling, ..FilterOptions::default() };
+/// let read = |check| Game::read_from(data.as_slice(), filters(check).decode_options());
+/// assert_eq!(read(CastlingCheck::Off)?.samples.len(), 1);
+///
+/// let game = read(CastlingCheck::Repair)?;
+/// assert_eq!(game.samples.len(), 2);
+/// assert!(!game.samples[1].castling_us_ooo);
+///
+/// let game = read(CastlingCheck::Skip)?;
+/// assert_eq!(game.samples.len(), 2);
+/// let skip = filters(CastlingCheck::Skip);
+/// assert_eq!(filter_position(&game.samples[0], &skip), None);
+/// assert_eq!(filter_position(&game.samples[1], &skip), Some(Filter::Castling));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CastlingCheck {
+    /// Keep the flags as they are.
+    #[default]
+    Off,
+    /// Drop the impossible rights while decoding the samples.
+    Repair,
+    /// Drop the samples, counted as [`Filter::Castling`].
+    Skip,
+}
+
+/// Configurable filters, the default only applies the filters that are always
+/// active.
+///
+/// ```
+/// use preprocessing::phase::Phase;
+/// use preprocessing::FilterOptions;
+///
+/// let filters = FilterOptions {
+///     phases: vec![Phase::Middlegame],
+///     min_policy_entropy: Some(0.5),
+///     ..FilterOptions::default()
+/// };
+/// assert!(filters.decode_options().policy_entropy);
+/// assert!(!FilterOptions::default().decode_options().policy_entropy);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FilterOptions {
+    /// Game phases to keep, empty keeps all of them.
+    pub phases: Vec<Phase>,
+    /// Range of the policy entropy (in nats) to keep.
+    pub min_policy_entropy: Option<f32>,
+    pub max_policy_entropy: Option<f32>,
+    pub castling: CastlingCheck,
+    /// Material signatures of the positions to keep, empty keeps all of
+    /// them. Either side may have the pieces of the first side of a
+    /// signature.
+    pub material: Vec<MaterialSignature>,
+}
+
+impl FilterOptions {
+    /// Fields of the samples the filters need to be decoded.
+    pub fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            policy_entropy: self.min_policy_entropy.is_some() || self.max_policy_entropy.is_some(),
+            castling: self.castling,
+            ..DecodeOptions::default()
+        }
+    }
+}
+
+/// Returns the filter rejecting the position or None if it should be kept in
+/// the output.
+pub fn filter_position(data: &TrainingSample, options: &FilterOptions) -> Option<Filter> {
+    // Filter out positions with too few pieces that will be covered by Syzygy
+    // endgame tablebase.
+    let num_pieces = data
+        .bitboards
+        .iter()
+        .fold(0, |acc, plane| acc + plane.count_ones());
+    if options.material.is_empty() && num_pieces <= MIN_PIECES {
+        return Some(Filter::FewPieces);
+    }
+    if !options.material.is_empty()
+        && !options
+            .material
+            .iter()
+            .any(|signature| signature.matches(data))
+    {
+        return Some(Filter::Material);
+    }
+
+    // Filter out promotions early.
+    if crate::IDX_TO_MOVE[data.best_idx as usize].len() > 4 {
+        return Some(Filter::Promotion);
+    }
+
+    if !options.phases.is_empty() {
+        let sample_phase = Phase::from_score(phase::score(data));
+        if !options.phases.contains(&sample_phase) {
+            return Some(Filter::Phase);
+        }
+    }
+
+    // The entropy is decoded whenever the range is set.
+    if let Some(entropy) = data.policy_entropy {
+        if options.min_policy_entropy.is_some_and(|min| entropy < min)
+            || options.max_policy_entropy.is_some_and(|max| entropy > max)
+        {
+            return Some(Filter::PolicyEntropy);
+        }
+    }
+
+    if options.castling == CastlingCheck::Skip && !data.castling_consistent() {
+        return Some(Filter::Castling);
+    }
+
+    // TODO: Filter out captures.
+    // TODO: Filter out checks.
+
+    None
+}
@@ -0,0 +1,72 @@
+// Small archives of synthetic training data (`make-fixtures`) for integration
+// tests and demos, which otherwise need the archives of lc0 with gigabytes of
+// games. The games are random legal moves, either from the initial position
+// or from every position of a file with a FEN (or EPD) per line:
+//
+//     preprocessing make-fixtures -o fixtures.tar --games 32
+//     preprocessing make-fixtures -o endgames.tar --fens endgames.epd --max-plies 1
+//     preprocessing -t fixtures.tar -o out
+//
+// The archives are valid version 6 training data, but the scores and the
+// policy are made up, see preprocessing::synthetic. They are reproducible
+// with --seed.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::rng::Rng;
+use preprocessing::synthetic::{gzip, random_game_from, tar};
+use shakmaty::Chess;
+use tracing::{info, warn};
+
+use crate::selfplay;
+
+#[derive(Args)]
+pub struct MakeFixturesArgs {
+    /// Path of the tar archive to write
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// File with a FEN (or EPD) per line, every position starts a game
+    /// instead of the initial position
+    #[arg(long)]
+    fens: Option<PathBuf>,
+
+    /// Number of games from the initial position, ignored with --fens
+    #[arg(long, default_value_t = 16)]
+    games: usize,
+
+    /// Maximum number of positions in a game
+    #[arg(long, default_value_t = 80)]
+    max_plies: usize,
+}
+
+pub fn run(args: MakeFixturesArgs, seed: u64) -> io::Result<()> {
+    let starts = match &args.fens {
+        Some(path) => selfplay::read_book(path)?,
+        None => vec![Chess::default(); args.games],
+    };
+    let root = Rng::new(seed);
+    let max_plies = args.max_plies.max(1);
+    let mut games = Vec::with_capacity(starts.len());
+    let mut positions = 0;
+    for (idx, start) in starts.into_iter().enumerate() {
+        let records = random_game_from(&mut root.fork(idx as u64), start, max_plies);
+        if records.is_empty() {
+            warn!(idx, "skipping a position without legal moves");
+            continue;
+        }
+        positions += records.len();
+        games.push(gzip(&records));
+    }
+    fs::write(&args.output, tar(&games)?)?;
+    info!(
+        games = games.len(),
+        positions,
+        path = %args.output.display(),
+        "wrote the fixtures"
+    );
+    Ok(())
+}
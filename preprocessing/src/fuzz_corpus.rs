@@ -0,0 +1,79 @@
+// Seeds of the fuzzing corpus (`fuzz-corpus`), see preprocessing::synthetic
+// and the targets in the fuzz directory. Every target gets a directory of
+// seeds named after it, half of them valid and half of them corrupted:
+//
+//     preprocessing fuzz-corpus -o fuzz/corpus
+//     cd fuzz && cargo +nightly fuzz run game
+//
+// The seeds are reproducible with --seed.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::record::Record;
+use preprocessing::rng::Rng;
+use preprocessing::synthetic::{gzip, mutate, random_game, tar};
+use tracing::info;
+
+#[derive(Args)]
+pub struct FuzzCorpusArgs {
+    /// Directory to write the seeds of every fuzz target to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Number of seeds per fuzz target
+    #[arg(long, default_value_t = 64)]
+    seeds: usize,
+
+    /// Maximum number of positions in a game
+    #[arg(long, default_value_t = 80)]
+    max_plies: usize,
+
+    /// Number of games in an archive
+    #[arg(long, default_value_t = 4)]
+    games_per_archive: usize,
+}
+
+fn write_seeds<F>(args: &FuzzCorpusArgs, target: &str, rng: &mut Rng, mut make: F) -> io::Result<()>
+where
+    F: FnMut(&mut Rng) -> io::Result<Vec<u8>>,
+{
+    let dir = args.output_dir.join(target);
+    fs::create_dir_all(&dir)?;
+    for idx in 0..args.seeds {
+        let mut data = make(rng)?;
+        let kind = if idx % 2 == 0 {
+            "valid"
+        } else {
+            mutate(rng, &mut data);
+            "mutated"
+        };
+        fs::write(dir.join(format!("{kind}-{idx:04}")), data)?;
+    }
+    info!(target, seeds = args.seeds, dir = %dir.display(), "wrote seeds");
+    Ok(())
+}
+
+pub fn run(args: FuzzCorpusArgs, seed: u64) -> io::Result<()> {
+    let root = Rng::new(seed);
+    let max_plies = args.max_plies.max(1);
+    let game = |rng: &mut Rng| random_game(rng, max_plies);
+
+    write_seeds(&args, "training_sample", &mut root.fork(0), |rng| {
+        let records = game(rng);
+        let record = &records[rng.below(records.len() as u64) as usize];
+        Ok(record.as_bytes().to_vec())
+    })?;
+    write_seeds(&args, "game", &mut root.fork(1), |rng| Ok(gzip(&game(rng))))?;
+    write_seeds(&args, "archive", &mut root.fork(2), |rng| {
+        let games: Vec<Vec<u8>> = (0..args.games_per_archive)
+            .map(|_| gzip(&game(rng)))
+            .collect();
+        tar(&games)
+    })?;
+    // An empty record, the smallest input that decodes.
+    let empty = args.output_dir.join("training_sample").join("empty");
+    fs::write(empty, Record::empty().as_bytes())
+}
@@ -0,0 +1,161 @@
+// Full shuffling of preprocessed datasets larger than the memory (`shuffle`)
+// with an external sort: every sample gets a random key, the samples are
+// sorted by their keys in runs that fit into --memory-limit, the runs are
+// written to temporary files and merged into the output shards.
+//
+// The key of a sample is derived from the seed and the contents of its line
+// alone, so the output only depends on the seed and the set of samples, not on
+// the order of the games, shards or datasets, and the samples of a game end up
+// spread over the whole output. Repeated lines get the same key and stay
+// together, merge removes the repeated positions beforehand.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::output::{self, ShardWriter};
+use preprocessing::rng::Rng;
+use rayon::prelude::*;
+use tracing::{debug, info};
+
+use crate::memory;
+use crate::merge::check_inputs;
+
+// Approximate memory taken by a sample of a run besides its line: the key and
+// the string.
+const BYTES_PER_SAMPLE: u64 = 32;
+
+#[derive(Args)]
+pub struct ShuffleArgs {
+    /// Preprocessed outputs to shuffle together (directories with shards or
+    /// single shards)
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write the shuffled shards to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Maximum number of samples in a single output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+
+    /// Approximate limit on the memory used for sorting the runs, e.g. 512M
+    /// or 4G
+    #[arg(long, value_parser = memory::parse_size, default_value = "1G")]
+    memory_limit: u64,
+
+    /// Directory for the sorted runs [default: the output directory]
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+}
+
+// FNV-1a, which unlike the hashers of the standard library is guaranteed to
+// stay the same across Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// Sorted run of samples in a temporary file, a line per sample with the key in
+// hex before it.
+struct Run {
+    path: PathBuf,
+    lines: Lines<BufReader<File>>,
+}
+
+impl Run {
+    fn write(path: PathBuf, samples: &mut Vec<(u64, String)>) -> io::Result<Self> {
+        samples.par_sort_unstable();
+        let mut out = BufWriter::new(File::create(&path)?);
+        for (key, line) in samples.drain(..) {
+            writeln!(out, "{key:016x} {line}")?;
+        }
+        out.flush()?;
+        let lines = BufReader::new(File::open(&path)?).lines();
+        Ok(Run { path, lines })
+    }
+
+    fn next(&mut self) -> io::Result<Option<(u64, String)>> {
+        let Some(line) = self.lines.next().transpose()? else {
+            return Ok(None);
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid line in the run {}", self.path.display()),
+            )
+        };
+        let (key, sample) = line.split_once(' ').ok_or_else(invalid)?;
+        let key = u64::from_str_radix(key, 16).map_err(|_| invalid())?;
+        Ok(Some((key, sample.to_string())))
+    }
+}
+
+fn run_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("shuffle-run-{index:05}.tmp"))
+}
+
+pub fn run(args: ShuffleArgs, seed: u64) -> io::Result<()> {
+    check_inputs(&args.inputs, &args.output_dir)?;
+    let temp_dir = args.temp_dir.as_ref().unwrap_or(&args.output_dir);
+    fs::create_dir_all(temp_dir)?;
+
+    let root = Rng::new(seed);
+    let mut runs = Vec::new();
+    let mut samples = Vec::new();
+    let (mut used, mut total) = (0, 0u64);
+    for input in &args.inputs {
+        output::for_each_line(input, |line| {
+            let key = root.fork(fnv1a(line.as_bytes())).next_u64();
+            used += line.len() as u64 + BYTES_PER_SAMPLE;
+            samples.push((key, line.to_string()));
+            total += 1;
+            if used >= args.memory_limit {
+                debug!(
+                    run = runs.len(),
+                    samples = samples.len(),
+                    "writing a sorted run"
+                );
+                runs.push(Run::write(run_path(temp_dir, runs.len()), &mut samples)?);
+                used = 0;
+            }
+            Ok(())
+        })?;
+    }
+    if !samples.is_empty() {
+        runs.push(Run::write(run_path(temp_dir, runs.len()), &mut samples)?);
+    }
+    drop(samples);
+    info!(samples = total, runs = runs.len(), "sorted the runs");
+
+    // The smallest key of every run, the ties are broken by the lines.
+    let mut writer = ShardWriter::create(&args.output_dir, args.shard_size)?;
+    let mut heap = BinaryHeap::with_capacity(runs.len());
+    for (index, run) in runs.iter_mut().enumerate() {
+        if let Some((key, line)) = run.next()? {
+            heap.push(Reverse((key, line, index)));
+        }
+    }
+    while let Some(Reverse((_, line, index))) = heap.pop() {
+        writer.write_sample(&line)?;
+        if let Some((key, line)) = runs[index].next()? {
+            heap.push(Reverse((key, line, index)));
+        }
+    }
+    let last_shard = writer.flush()?.index;
+    for run in &runs {
+        fs::remove_file(&run.path)?;
+    }
+
+    println!(
+        "shuffled {total} samples into {} shards with {} sorted runs",
+        last_shard + 1,
+        runs.len()
+    );
+    Ok(())
+}
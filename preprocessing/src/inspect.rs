@@ -0,0 +1,209 @@
+use std::io::{self, Read, Write};
+
+use clap::Args;
+use flate2::read::GzDecoder;
+use preprocessing::record::{self, Record, NUM_INPUT_PLANES, RECORD_SIZE};
+use preprocessing::{CastlingBitboards, DecodeOptions, TrainingSample, IDX_TO_MOVE};
+use shakmaty::{Board, File, Piece, Rank, Role, Square};
+
+use crate::archive;
+
+// Planes per position in the history: 12 pieces and the repetition plane.
+const PLANES_PER_POSITION: usize = 13;
+
+// Number of the most likely moves of the policy that are shown.
+const TOP_MOVES: usize = 5;
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Path to the tar file containing .gz training data
+    tar_path: String,
+
+    /// Index of the game among the games in the archive
+    #[arg(long)]
+    game: usize,
+
+    /// Index of the position within the game
+    #[arg(long)]
+    ply: usize,
+
+    /// Draw the pieces with Unicode chess symbols instead of letters
+    #[arg(long)]
+    unicode: bool,
+}
+
+fn find_game(path: &str, game: usize) -> io::Result<Vec<u8>> {
+    let mut index = 0;
+    let mut data = None;
+    archive::for_each_game(path, 0, |entry| {
+        if index == game {
+            data = Some(entry.data);
+        }
+        index += 1;
+        data.is_none()
+    })?;
+    data.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path} has only {index} games"),
+        )
+    })
+}
+
+fn piece_symbol(piece: Piece, unicode: bool) -> char {
+    if !unicode {
+        return piece.char();
+    }
+    const WHITE: [char; 6] = ['♙', '♘', '♗', '♖', '♕', '♔'];
+    const BLACK: [char; 6] = ['♟', '♞', '♝', '♜', '♛', '♚'];
+    let symbols = if piece.color.is_white() { WHITE } else { BLACK };
+    symbols[piece.role as usize - 1]
+}
+
+fn write_board(out: &mut impl Write, board: &Board, unicode: bool) -> io::Result<()> {
+    for rank in (0..8).rev() {
+        write!(out, "  {} ", rank + 1)?;
+        for file in 0..8 {
+            let square = Square::from_coords(File::new(file), Rank::new(rank));
+            let symbol = board
+                .piece_at(square)
+                .map_or('.', |p| piece_symbol(p, unicode));
+            write!(out, " {symbol}")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "     a b c d e f g h")
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    let move_name = |idx: u16| IDX_TO_MOVE.get(idx as usize).copied().unwrap_or("invalid");
+
+    writeln!(out, "version:      {}", record.version())?;
+    writeln!(out, "input format: {}", record.input_format())?;
+    writeln!(
+        out,
+        "castling:     us O-O {} O-O-O {}, them O-O {} O-O-O {}",
+        record.castling_us_oo(),
+        record.castling_us_ooo(),
+        record.castling_them_oo(),
+        record.castling_them_ooo()
+    )?;
+    writeln!(
+        out,
+        "side to move or en passant: {}",
+        record.side_to_move_or_enpassant()
+    )?;
+    writeln!(out, "rule50:       {}", record.rule50_count())?;
+    writeln!(out, "invariance:   {:#010b}", record.invariance_info())?;
+    writeln!(out, "visits:       {}", record.visits())?;
+    writeln!(out, "{:>8} {:>10} {:>10} {:>10}", "", "q", "d", "m")?;
+    let qdm = [
+        ("root", record.root_q(), record.root_d(), record.root_m()),
+        ("best", record.best_q(), record.best_d(), record.best_m()),
+        (
+            "played",
+            record.played_q(),
+            record.played_d(),
+            record.played_m(),
+        ),
+        ("orig", record.orig_q(), record.orig_d(), record.orig_m()),
+        (
+            "result",
+            record.result_q(),
+            record.result_d(),
+            record.plies_left(),
+        ),
+    ];
+    for (name, q, d, m) in qdm {
+        writeln!(out, "{name:>8} {q:>10.5} {d:>10.5} {m:>10.2}")?;
+    }
+    writeln!(
+        out,
+        "best move:    {} ({})",
+        move_name(record.best_idx()),
+        record.best_idx()
+    )?;
+    writeln!(
+        out,
+        "played move:  {} ({})",
+        move_name(record.played_idx()),
+        record.played_idx()
+    )?;
+    writeln!(out, "policy KLD:   {}", record.policy_kld())?;
+    writeln!(out, "policy entropy: {:.4} nats", record.policy_entropy())?;
+
+    let mut policy: Vec<_> = record
+        .probabilities()
+        .enumerate()
+        .filter(|&(_, p)| p >= 0.0)
+        .collect();
+    policy.sort_by(|a, b| b.1.total_cmp(&a.1));
+    writeln!(out, "policy ({} legal moves):", policy.len())?;
+    for (idx, p) in policy.iter().take(TOP_MOVES) {
+        writeln!(out, "  {:>6} {:>7.3}%", IDX_TO_MOVE[*idx], 100.0 * p)?;
+    }
+
+    // The planes are shown as stored and after undoing the bit reversal.
+    writeln!(out, "planes (stored -> decoded):")?;
+    for idx in 0..NUM_INPUT_PLANES {
+        let plane = record.plane(idx);
+        if idx % PLANES_PER_POSITION == 0 {
+            writeln!(out, "  history {}:", idx / PLANES_PER_POSITION)?;
+        }
+        writeln!(
+            out,
+            "  {idx:>5}: {:016x} -> {plane:016x}",
+            record::reverse_bits_in_bytes(plane)
+        )?;
+    }
+    Ok(())
+}
+
+pub fn run(args: InspectArgs) -> io::Result<()> {
+    let data = find_game(&args.tar_path, args.game)?;
+    let mut records = Vec::new();
+    GzDecoder::new(data.as_slice()).read_to_end(&mut records)?;
+
+    let num_records = records.len() / RECORD_SIZE;
+    if args.ply >= num_records {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("game {} has only {num_records} positions", args.game),
+        ));
+    }
+    let record_at = |ply: usize| &records[ply * RECORD_SIZE..(ply + 1) * RECORD_SIZE];
+
+    // The castling squares are derived from all positions of the game.
+    let samples = (0..num_records)
+        .map(|ply| TrainingSample::read_from(record_at(ply), DecodeOptions::default()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let castling = CastlingBitboards::from_game(&samples);
+    let sample = &samples[args.ply];
+    let record = Record::read_from(record_at(args.ply))?;
+
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "{} game {} ply {} of {num_records}",
+        args.tar_path, args.game, args.ply
+    )?;
+    writeln!(out)?;
+    write_board(&mut out, &sample.to_board(), args.unicode)?;
+    writeln!(out)?;
+    writeln!(out, "FEN:          {}", sample.to_fen(&castling))?;
+    writeln!(out, "pieces:       {}", count_pieces(sample))?;
+    write_record(&mut out, &record)
+}
+
+fn count_pieces(sample: &TrainingSample) -> String {
+    Role::ALL
+        .iter()
+        .enumerate()
+        .map(|(idx, role)| {
+            let us = sample.bitboards[idx].count_ones();
+            let them = sample.bitboards[idx + 6].count_ones();
+            format!("{}{us}/{them}", role.upper_char())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
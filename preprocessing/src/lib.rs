@@ -1,5 +1,82 @@
-// Mirrors lc0 move index to UCI string mapping.
-pub const IDX_TO_MOVE: [&str; 1858] = [
+//! Reading and preprocessing of lc0 training data.
+//!
+//! The training data is distributed as tar archives of gzip-compressed games,
+//! each being a sequence of fixed-size records (see [`record`]). The samples
+//! are decoded into [`TrainingSample`]s grouped by [`Game`], which are read
+//! from the archives with [`TrainingSamples`] and [`Games`], filtered with
+//! [`filter_position`] and written out with the writers in [`output`] or any
+//! of the sinks in [`sink`]:
+//!
+//! ```no_run
+//! use preprocessing::output::{serialize_position, OutputFields, ShardWriter};
+//! use preprocessing::{filter_position, FilterOptions, Games};
+//!
+//! let filters = FilterOptions::default();
+//! let mut writer = ShardWriter::create("out", 1_000_000)?;
+//! for game in Games::from_tar("training.tar", filters.decode_options())? {
+//!     let game = game?;
+//!     for sample in &game.samples {
+//!         if filter_position(sample, &filters).is_none() {
+//!             writer.write_sample(&serialize_position(sample, &game, OutputFields::default()))?;
+//!         }
+//!     }
+//! }
+//! writer.flush()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`PreprocessOptions`] does all of this in parallel with a single call, and
+//! passes the games to a [`visit::GameVisitor`] for custom analysis.
+
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+pub mod augment;
+pub mod dataset;
+pub mod diagram;
+pub mod eco;
+pub mod encoder;
+pub mod error;
+pub mod eval;
+pub mod filter;
+#[cfg(feature = "ndarray")]
+pub mod loader;
+pub mod material;
+pub mod moves;
+pub mod network;
+pub mod nnue;
+pub mod output;
+pub mod phase;
+pub mod policy;
+pub mod polyglot;
+pub mod preprocess;
+pub mod reader;
+pub mod record;
+pub mod resample;
+pub mod rng;
+pub mod sample;
+pub mod shuffle;
+pub mod sink;
+pub mod source;
+pub mod storage;
+pub mod synthetic;
+pub mod visit;
+
+pub use encoder::{encode, InputPlanes};
+pub use error::{PreprocessError, Result};
+pub use filter::{filter_position, Filter, FilterOptions};
+pub use moves::{idx_from_move, move_from_idx, MOVE_TO_IDX};
+pub use preprocess::{PreprocessOptions, PreprocessSummary};
+pub use reader::{Games, TrainingSamples};
+pub use sample::{CastlingBitboards, DecodeOptions, Game, TrainingSample};
+
+/// Mirrors lc0 move index to UCI string mapping. Knight promotions have no
+/// promotion suffix, see [`moves`] for the conversion to shakmaty moves.
+///
+/// ```
+/// assert_eq!(preprocessing::IDX_TO_MOVE[0], "a1b1");
+/// assert_eq!(preprocessing::IDX_TO_MOVE.len(), preprocessing::record::POLICY_SIZE);
+/// ```
+pub static IDX_TO_MOVE: [&str; 1858] = [
     "a1b1", "a1c1", "a1d1", "a1e1", "a1f1", "a1g1", "a1h1", "a1a2", "a1b2", "a1c2", "a1a3", "a1b3",
     "a1c3", "a1a4", "a1d4", "a1a5", "a1e5", "a1a6", "a1f6", "a1a7", "a1g7", "a1a8", "a1h8", "b1a1",
     "b1c1", "b1d1", "b1e1", "b1f1", "b1g1", "b1h1", "b1a2", "b1b2", "b1c2", "b1d2", "b1a3", "b1b3",
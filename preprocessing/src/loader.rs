@@ -0,0 +1,235 @@
+// Mini-batches of training samples as ndarray arrays for training loops
+// written in Rust.
+//
+// The archives are read, decoded and shuffled on a background thread, which
+// stays up to `prefetch` batches ahead of the consumer.
+//
+// For distributed training, every process creates a loader over the same
+// archives with its own `shard`, so that the processes get disjoint slices of
+// the games.
+
+use std::path::PathBuf;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use ndarray::{s, Array1, Array2, Array4};
+
+use crate::augment::Augmentation;
+use crate::error::Result;
+use crate::filter::{filter_position, FilterOptions};
+use crate::policy::PolicyOptions;
+use crate::reader::Shard;
+use crate::record::POLICY_SIZE;
+use crate::rng::Rng;
+use crate::sample::{DecodeOptions, TrainingSample, NUM_PLANES};
+use crate::shuffle::{ShuffleOptions, ShuffledSamples};
+
+/// Configuration of a [`DataLoader`].
+#[derive(Debug, Clone)]
+pub struct DataLoaderOptions {
+    pub batch_size: usize,
+    /// Number of batches prepared ahead of the consumer.
+    pub prefetch: usize,
+    /// Number of samples the batches are drawn from at random. Samples of the
+    /// same game are strongly correlated, so this should span many games.
+    pub shuffle_buffer: usize,
+    /// Number of archives the games are interleaved from.
+    pub open_archives: usize,
+    pub seed: u64,
+    pub filters: FilterOptions,
+    /// Transformations applied at random to the samples they apply to.
+    pub augment: Vec<Augmentation>,
+    /// Probability of applying each of the augmentations to a sample.
+    pub augment_probability: f64,
+    pub policy: PolicyOptions,
+    /// Append the repetition plane of the lc0 inputs to the planes: all ones
+    /// if the position occurred earlier in the game.
+    pub repetition_plane: bool,
+    /// Append a plane filled with the rule50 count of the sample, after the
+    /// repetition plane if both are enabled.
+    pub rule50_plane: bool,
+    /// Slice of the games of this process when several processes load the
+    /// same data.
+    pub shard: Shard,
+}
+
+impl Default for DataLoaderOptions {
+    fn default() -> Self {
+        DataLoaderOptions {
+            batch_size: 1024,
+            prefetch: 4,
+            shuffle_buffer: 1 << 16,
+            open_archives: 8,
+            seed: 0,
+            filters: FilterOptions::default(),
+            augment: Vec::new(),
+            augment_probability: 0.5,
+            policy: PolicyOptions::default(),
+            repetition_plane: false,
+            rule50_plane: false,
+            shard: Shard::default(),
+        }
+    }
+}
+
+/// Inputs and targets of a mini-batch, the first axis is the sample.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    /// (N, 12, 8, 8): pieces of the side to move followed by the pieces of
+    /// the opponent, indexed by rank and file, followed by the planes of
+    /// [`DataLoaderOptions::repetition_plane`] and
+    /// [`DataLoaderOptions::rule50_plane`].
+    pub planes: Array4<f32>,
+    /// (N, 3): probabilities of win, draw and loss of the side to move from
+    /// the game result.
+    pub wdl: Array2<f32>,
+    /// (N, 1858): search policy over the moves of [`crate::IDX_TO_MOVE`]
+    /// shaped by [`DataLoaderOptions::policy`], illegal moves have
+    /// probability 0.
+    pub policy: Array2<f32>,
+    /// (N,): plies until the end of the game, the target of the moves-left
+    /// head.
+    pub plies_left: Array1<f32>,
+}
+
+impl Batch {
+    fn from_samples(samples: &[TrainingSample], options: &DataLoaderOptions) -> Self {
+        let n = samples.len();
+        let repetition_plane = NUM_PLANES;
+        let rule50_plane = repetition_plane + options.repetition_plane as usize;
+        let num_planes = rule50_plane + options.rule50_plane as usize;
+        let mut planes = Array4::zeros((n, num_planes, 8, 8));
+        let mut wdl = Array2::zeros((n, 3));
+        let mut policy = Array2::zeros((n, POLICY_SIZE));
+        let plies_left = samples.iter().map(|sample| sample.plies_left).collect();
+        for (idx, sample) in samples.iter().enumerate() {
+            for (plane, &bitboard) in sample.bitboards.iter().enumerate() {
+                for square in 0..64 {
+                    if (bitboard >> square) & 1 == 1 {
+                        planes[[idx, plane, square / 8, square % 8]] = 1.0;
+                    }
+                }
+            }
+            if options.repetition_plane && sample.repetitions > 0 {
+                planes
+                    .slice_mut(s![idx, repetition_plane, .., ..])
+                    .fill(1.0);
+            }
+            if options.rule50_plane {
+                let rule50 = sample.rule50_count as f32;
+                planes.slice_mut(s![idx, rule50_plane, .., ..]).fill(rule50);
+            }
+            let (q, d) = (sample.result_q, sample.result_d);
+            wdl[[idx, 0]] = (1.0 + q - d) / 2.0;
+            wdl[[idx, 1]] = d;
+            wdl[[idx, 2]] = (1.0 - q - d) / 2.0;
+            let probabilities = sample.policy.as_deref().unwrap_or_default();
+            let mut target = policy.row_mut(idx);
+            for (target, &p) in target.iter_mut().zip(probabilities) {
+                *target = p;
+            }
+            let target = target.as_slice_mut().expect("the rows are contiguous");
+            options.policy.apply_to_sample(target, sample);
+        }
+        Batch {
+            planes,
+            wdl,
+            policy,
+            plies_left,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.planes.shape()[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Iterator over shuffled mini-batches of the samples of several archives.
+/// The last batch may be smaller than the batch size.
+///
+/// ```no_run
+/// use preprocessing::loader::{DataLoader, DataLoaderOptions};
+///
+/// let options = DataLoaderOptions {
+///     batch_size: 256,
+///     ..DataLoaderOptions::default()
+/// };
+/// for batch in DataLoader::new(vec!["training.tar".into()], options) {
+///     let batch = batch?;
+///     assert_eq!(batch.planes.shape()[1..], [12, 8, 8]);
+/// }
+/// # Ok::<(), preprocessing::PreprocessError>(())
+/// ```
+pub struct DataLoader {
+    receiver: Receiver<Result<Batch>>,
+}
+
+impl DataLoader {
+    pub fn new(archives: Vec<PathBuf>, options: DataLoaderOptions) -> Self {
+        let (sender, receiver) = bounded(options.prefetch.max(1));
+        thread::spawn(move || {
+            if let Err(err) = load(&archives, &options, &sender) {
+                // Nobody is listening if the loader was dropped already.
+                let _ = sender.send(Err(err));
+            }
+        });
+        DataLoader { receiver }
+    }
+}
+
+impl Iterator for DataLoader {
+    type Item = Result<Batch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+// Sends the batches until the archives are exhausted or the loader is dropped.
+fn load(
+    archives: &[PathBuf],
+    options: &DataLoaderOptions,
+    sender: &Sender<Result<Batch>>,
+) -> Result<()> {
+    let shuffle = ShuffleOptions {
+        buffer_size: options.shuffle_buffer,
+        open_archives: options.open_archives,
+        seed: options.seed,
+        decode: DecodeOptions {
+            policy: true,
+            ..options.filters.decode_options()
+        },
+        shard: options.shard,
+    };
+    // The shuffling uses the streams 0 and 1 of the seed.
+    let mut rng = Rng::new(options.seed).fork(2);
+    let batch_size = options.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    for sample in ShuffledSamples::new(archives.to_vec(), shuffle) {
+        let mut sample = sample?;
+        if filter_position(&sample, &options.filters).is_some() {
+            continue;
+        }
+        for &augmentation in &options.augment {
+            if augmentation.applies_to(&sample) && rng.chance(options.augment_probability) {
+                sample = augmentation.apply(&sample);
+            }
+        }
+        batch.push(sample);
+        if batch.len() == batch_size {
+            let ready = Batch::from_samples(&batch, options);
+            if sender.send(Ok(ready)).is_err() {
+                return Ok(());
+            }
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        let _ = sender.send(Ok(Batch::from_samples(&batch, options)));
+    }
+    Ok(())
+}
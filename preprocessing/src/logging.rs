@@ -0,0 +1,38 @@
+// Diagnostics of the run written to stderr with `tracing`.
+//
+// Every archive gets a span, and so does every game at the trace level, so
+// the events of long runs can be attributed to their inputs. The verbosity is
+// controlled by -v/-vv, RUST_LOG takes precedence when it is set.
+
+use std::io;
+
+use clap::ValueEnum;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // One JSON object per event for log aggregation systems.
+    Json,
+}
+
+pub fn init(verbosity: u8, format: LogFormat) -> io::Result<()> {
+    let level = match verbosity {
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+    result.map_err(io::Error::other)
+}
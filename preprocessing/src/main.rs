@@ -1,232 +1,1154 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use clap::Parser;
-use flate2::read::GzDecoder;
-use shakmaty::{Bitboard, Board, ByColor, ByRole};
-use std::fs::File;
-use std::io::{self, Read};
-use std::path::Path;
-use tar::Archive;
+mod archive;
+mod book;
+mod checkpoint;
+mod compare;
+mod config;
+mod convert;
+mod coordinator;
+mod dedup;
+mod fixtures;
+mod fuzz_corpus;
+mod global_shuffle;
+mod inspect;
+mod logging;
+mod manifest;
+mod matches;
+mod memory;
+mod merge;
+mod metrics;
+#[cfg(feature = "train")]
+mod net;
+#[cfg(feature = "train")]
+mod onnx;
+mod pipeline;
+mod progress;
+#[cfg(feature = "train")]
+mod protobuf;
+#[cfg(feature = "train")]
+mod quantize;
+mod query;
+mod render;
+mod report;
+mod rescore;
+mod selfplay;
+#[cfg(all(unix, feature = "serve"))]
+mod serve;
+mod signals;
+mod split;
+mod stats;
+mod subset;
+mod tactics;
+#[cfg(feature = "train")]
+mod tensorboard;
+mod to_pgn;
+#[cfg(feature = "train")]
+mod train;
+mod tune;
+mod uci;
+mod validate;
+mod verify;
+mod viewer;
+mod watch;
+mod worker;
+
+use checkpoint::{Checkpoint, Checkpointer};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use config::Config;
+use dedup::ExistingPositions;
+use indicatif::HumanBytes;
+use logging::LogFormat;
+use manifest::Manifest;
+use memory::MemoryBudget;
+use pipeline::{Output, Summary, OUTCOMES, OUTCOME_EDGES};
+use preprocessing::augment::Augmentation;
+use preprocessing::filter::CastlingCheck;
+use preprocessing::material::MaterialSignature;
+use preprocessing::nnue::FeatureSet;
+use preprocessing::output::{
+    self, OutputFields, OutputSample, ProvenanceFormat, ShardState, ShardWriter, OUTPUT_SCHEMA,
+};
+use preprocessing::phase::Phase;
+use preprocessing::resample::Resampler;
+use preprocessing::rng::{self, Rng};
+use preprocessing::sample::ValueSource;
+use preprocessing::sink::{Registry, SinkConfig};
+use preprocessing::source::{InputFormat, PgnFilter};
+use preprocessing::{Filter, FilterOptions};
+use report::Report;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
 #[derive(Parser)]
 #[command(author, version, about = "Process LC0 training data from tar files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+// Options shared by all commands, accepted before or after the command name.
+#[derive(clap::Args)]
+struct GlobalArgs {
+    /// Number of threads processing the games [default: number of CPUs]
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    /// Do not show the progress bar
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Log more details: -v for every archive, -vv for every game
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of the log written to stderr
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+
+    /// Seed of all randomized steps, the same seed and inputs produce the
+    /// same output [default: random, reported in the log]
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+}
+
+impl GlobalArgs {
+    // The seed of the run, picking and logging a random one if none was given.
+    fn seed(&self, config_seed: Option<u64>) -> u64 {
+        let seed = self.seed.or(config_seed).unwrap_or_else(rng::random_seed);
+        info!(seed, "random seed");
+        seed
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Filter the training data and write the samples to sharded text files
+    Preprocess(Box<Args>),
+    /// Report distributions of the training data without writing samples
+    Stats(stats::StatsArgs),
+    /// Estimate how often positions are repeated in the training data
+    DedupReport(dedup::DedupReportArgs),
+    /// Compare the positions and targets of two preprocessed outputs
+    Compare(compare::CompareArgs),
+    /// Merge preprocessed outputs into one, dropping repeated positions
+    Merge(merge::MergeArgs),
+    /// Shuffle preprocessed outputs larger than the memory into one with an
+    /// external sort
+    Shuffle(global_shuffle::ShuffleArgs),
+    /// Take a uniform random subset of a fixed number of samples of
+    /// preprocessed outputs, e.g. for validation sets
+    Subset(subset::SubsetArgs),
+    /// Find the samples matching a position, material or piece placement
+    Query(query::QueryArgs),
+    /// Check every sample of the training data for inconsistencies
+    Validate(validate::ValidateArgs),
+    /// Check a preprocessed dataset against the report of its run
+    Verify(verify::VerifyArgs),
+    /// Show the board and all fields of a single sample
+    Inspect(inspect::InspectArgs),
+    /// Show random samples of preprocessed data as boards in the browser
+    ServeUi(viewer::ServeUiArgs),
+    /// Draw a position or a sample as an SVG or PNG image
+    Render(render::RenderArgs),
+    /// Write the decoded samples as JSON lines
+    Convert(convert::ConvertArgs),
+    /// Write the games of the training data as PGN with the evaluations as
+    /// comments
+    ToPgn(to_pgn::ToPgnArgs),
+    /// Partition the training data by game into train, validation and test
+    /// sets
+    Split(split::SplitArgs),
+    /// Relabel the preprocessed samples with the search of a UCI engine
+    Rescore(rescore::RescoreArgs),
+    /// Generate training data from games of a UCI engine against itself
+    Selfplay(selfplay::SelfplayArgs),
+    /// Play games between two UCI engines and estimate their Elo difference
+    Match(matches::MatchArgs),
+    /// Stream shuffled batches to a trainer over a Unix socket as Arrow IPC
+    #[cfg(all(unix, feature = "serve"))]
+    Serve(serve::ServeArgs),
+    /// Train an NNUE network on the preprocessed data
+    #[cfg(feature = "train")]
+    Train(train::TrainArgs),
+    /// Quantize the weights of a trained network for the engine
+    #[cfg(feature = "train")]
+    Quantize(quantize::QuantizeArgs),
+    /// Export the weights of a trained network to an ONNX model
+    #[cfg(feature = "train")]
+    ExportOnnx(onnx::ExportOnnxArgs),
+    /// Tools for trained networks
+    #[cfg(feature = "train")]
+    Net(net::NetArgs),
+    /// Build an opening book (Polyglot or EPD) from the most common positions
+    /// and moves of the games
+    #[command(alias = "build-book")]
+    Book(book::BookArgs),
+    /// Find candidate tactical puzzles: a capture or sacrifice clearly
+    /// preferred by the search after a sharp change of the value
+    MineTactics(tactics::MineTacticsArgs),
+    /// Tune the parameters of the hand-crafted evaluation on the
+    /// preprocessed data
+    Tune(tune::TuneArgs),
+    /// Write synthetic training data as seeds of the fuzzing corpus
+    FuzzCorpus(fuzz_corpus::FuzzCorpusArgs),
+    /// Write a small archive of synthetic training data for tests and demos
+    MakeFixtures(fixtures::MakeFixturesArgs),
+    /// Serve the input archives to workers on several machines and collect
+    /// their outputs
+    Coordinate(coordinator::CoordinateArgs),
+    /// Preprocess the archives leased from a coordinator and upload the
+    /// outputs
+    Work(worker::WorkArgs),
+    /// Print the shell completion script to stdout
+    #[command(hide = true)]
+    GenerateCompletions { shell: Shell },
+}
+
+#[derive(clap::Args)]
 struct Args {
-    /// Path to the tar file containing .gz training data
+    /// Print the fields of the output lines and the options enabling them
+    #[arg(long, exclusive = true)]
+    help_format: bool,
+
+    /// Pipeline description in TOML, the other options override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Paths to the tar files containing .gz training data, or s3://, gs:// and
+    /// http(s):// URIs of them with the object-store feature. Directories of
+    /// tar files or .gz chunks, Stockfish .binpack files and .pgn files are
+    /// read as well, recognized by their extensions or their first bytes
+    #[arg(short, long, num_args = 1..)]
+    tar_path: Vec<String>,
+
+    /// Read all inputs in this format instead of detecting it
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormat>,
+
+    /// Read only the PGN games whose players are both rated at least this
+    /// much (WhiteElo and BlackElo), games without ratings are skipped
+    #[arg(long)]
+    min_elo: Option<u32>,
+
+    /// Read only the PGN games whose players are both rated at most this much
+    #[arg(long)]
+    max_elo: Option<u32>,
+
+    /// Read only the rated PGN games, whose Event tag starts with "Rated" like
+    /// on Lichess
+    #[arg(long)]
+    rated_only: bool,
+
+    /// Read only the PGN games whose time control lasts at least this many
+    /// seconds, the base time and 40 times the increment, e.g. 180 skips
+    /// bullet games. Games without a TimeControl tag are skipped
+    #[arg(long)]
+    min_time_control: Option<u32>,
+
+    /// Skip the PGN games that did not end normally on the board, e.g. time
+    /// forfeits and abandoned games (their Termination tag is not Normal)
+    #[arg(long)]
+    normal_termination: bool,
+
+    /// Read only the PGN games with at least this many plies
+    #[arg(long)]
+    min_plies: Option<u32>,
+
+    /// Read this many inputs at the same time and take their games in turn,
+    /// so that consecutive samples come from different archives. Such runs
+    /// are not checkpointed and cannot be resumed [default: 1]
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["resume", "watch"]
+    )]
+    interleave: Option<u32>,
+
+    /// Directory to write the output shards to
     #[arg(short, long)]
-    tar_path: String,
-}
-
-// Positions with small number of pieces are usually adjudicated by Syzygy endgame tablebases.
-const MIN_PIECES: u32 = 7;
-
-// Each plane is a distinct bitboard representing a piece type of a certain color.
-const NUM_PLANES: usize = 12;
-
-// A position from the training data with accompanying metadata.
-//
-// Original format: https://lczero.org/dev/wiki/training-data-format-versions/
-#[derive(Debug)]
-struct TrainingSample {
-    bitboards: [u64; NUM_PLANES],
-    // Prediction targets.
-    best_q: f32,
-    best_d: f32,
-    castling_us_ooo: bool,
-    castling_us_oo: bool,
-    castling_them_ooo: bool,
-    castling_them_oo: bool,
-    // Index of the best move in the policy head. See preprocessing::IDX_TO_MOVE.
-    best_idx: u16,
-}
-
-// For some reason, lc0 reverses the bits in the bytes of the bitboard before
-// storing them in the training data.
-// https://github.com/search?q=repo%3ALeelaChessZero%2Flc0+ReverseBitsInBytes&type=code
-fn reverse_bits_in_bytes(x: u64) -> u64 {
-    let mut v = x;
-    v = ((v >> 1) & 0x5555555555555555) | ((v & 0x5555555555555555) << 1);
-    v = ((v >> 2) & 0x3333333333333333) | ((v & 0x3333333333333333) << 2);
-    v = ((v >> 4) & 0x0F0F0F0F0F0F0F0F) | ((v & 0x0F0F0F0F0F0F0F0F) << 4);
-    v
-}
-
-impl TrainingSample {
-    fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
-        let version = reader.read_u32::<LittleEndian>()?;
-        assert_eq!(version, 6);
-        let _input_format = reader.read_u32::<LittleEndian>()?;
-        let mut _probabilities = vec![0.0; 1858];
-        for prob in _probabilities.iter_mut() {
-            *prob = reader.read_f32::<LittleEndian>()?;
-        }
+    output_dir: Option<PathBuf>,
 
-        let mut planes = vec![0; 104];
-        for plane in planes.iter_mut() {
-            *plane = reverse_bits_in_bytes(reader.read_u64::<LittleEndian>()?);
-        }
+    /// Maximum number of samples in a single output shard [default: 1000000]
+    #[arg(long)]
+    shard_size: Option<u64>,
 
-        let castling_us_ooo = reader.read_u8()? != 0;
-        let castling_us_oo = reader.read_u8()? != 0;
-        let castling_them_ooo = reader.read_u8()? != 0;
-        let castling_them_oo = reader.read_u8()? != 0;
-        let _side_to_move_or_enpassant = reader.read_u8()?;
-        let _rule50_count = reader.read_u8()?;
-        let _invariance_info = reader.read_u8()?;
-        let _dummy = reader.read_u8()?;
-
-        let _root_q = reader.read_f32::<LittleEndian>()?;
-        let best_q = reader.read_f32::<LittleEndian>()?;
-
-        let _root_d = reader.read_f32::<LittleEndian>()?;
-        let best_d = reader.read_f32::<LittleEndian>()?;
-
-        let _root_m = reader.read_f32::<LittleEndian>()?;
-        let _best_m = reader.read_f32::<LittleEndian>()?;
-        let _plies_left = reader.read_f32::<LittleEndian>()?;
-        let _result_q = reader.read_f32::<LittleEndian>()?;
-        let _result_d = reader.read_f32::<LittleEndian>()?;
-        let _played_q = reader.read_f32::<LittleEndian>()?;
-        let _played_d = reader.read_f32::<LittleEndian>()?;
-        let _played_m = reader.read_f32::<LittleEndian>()?;
-        let _orig_q = reader.read_f32::<LittleEndian>()?;
-        let _orig_d = reader.read_f32::<LittleEndian>()?;
-        let _orig_m = reader.read_f32::<LittleEndian>()?;
-        let _visits = reader.read_u32::<LittleEndian>()?;
-        let _played_idx = reader.read_u16::<LittleEndian>()?;
-        let best_idx = reader.read_u16::<LittleEndian>()?;
-        let _policy_kld = reader.read_f32::<LittleEndian>()?;
-        let _reserved = reader.read_u32::<LittleEndian>()?;
-
-        Ok(TrainingSample {
-            bitboards: planes[0..NUM_PLANES].try_into().unwrap(),
-            best_q,
-            best_d,
-            best_idx,
-            castling_us_ooo,
-            castling_us_oo,
-            castling_them_ooo,
-            castling_them_oo,
-        })
-    }
-
-    fn to_board(&self) -> Board {
-        Board::from_bitboards(
-            ByRole {
-                pawn: Bitboard(self.bitboards[0] | self.bitboards[6]),
-                knight: Bitboard(self.bitboards[1] | self.bitboards[7]),
-                bishop: Bitboard(self.bitboards[2] | self.bitboards[8]),
-                rook: Bitboard(self.bitboards[3] | self.bitboards[9]),
-                queen: Bitboard(self.bitboards[4] | self.bitboards[10]),
-                king: Bitboard(self.bitboards[5] | self.bitboards[11]),
-            },
-            ByColor {
-                white: Bitboard(self.bitboards[0..6].iter().fold(0, |acc, &x| acc | x)),
-                black: Bitboard(
-                    self.bitboards[6..NUM_PLANES]
-                        .iter()
-                        .fold(0, |acc, &x| acc | x),
-                ),
-            },
-        )
+    /// Path to the checkpoint file [default: <OUTPUT_DIR>/checkpoint]
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Number of processed games between two checkpoints
+    #[arg(long, default_value_t = 1000)]
+    checkpoint_interval: usize,
+
+    /// Continue the run recorded in the checkpoint instead of starting over
+    #[arg(long)]
+    resume: bool,
+
+    /// Read and filter the inputs without writing anything, then report how
+    /// many samples would be kept and the estimated size of the output
+    #[arg(long, conflicts_with = "resume")]
+    dry_run: bool,
+
+    /// Process the archives listed in <OUTPUT_DIR>/manifest again, overwriting
+    /// the output instead of appending the new archives to it
+    #[arg(long, conflicts_with = "resume")]
+    force: bool,
+
+    /// Number the new shards after all shards in the output directory, also
+    /// the ones missing in the manifest, e.g. of merged datasets or of runs
+    /// that were limited, instead of after the shards of the manifest
+    #[arg(long, conflicts_with_all = ["force", "dry_run"])]
+    append: bool,
+
+    /// Drop the samples of positions that are in the shards of the output
+    /// directory already, which are read at the start of every run. Their
+    /// hashes are held in memory, in a Bloom filter that drops a few new
+    /// positions as well once they exceed half of --memory-limit
+    #[arg(long, requires = "append")]
+    dedup_existing: bool,
+
+    /// Keep processing the tar files appearing in this directory, or under
+    /// this s3:// or gs:// prefix, until interrupted, appending them to the
+    /// output after the archives of --tar-path
+    #[arg(long, conflicts_with_all = ["resume", "dry_run", "limit", "limit_games"])]
+    watch: Option<String>,
+
+    /// Seconds between two listings of the --watch directory, an archive is
+    /// processed once its size did not change between two of them
+    #[arg(long, default_value_t = 60, requires = "watch")]
+    watch_interval: u64,
+
+    /// Stop once this many samples are written, e.g. for producing a small
+    /// test set
+    #[arg(long)]
+    limit: Option<u64>,
+
+    /// Stop after reading this many games
+    #[arg(long)]
+    limit_games: Option<u64>,
+
+    /// Number of unreadable archives and games, which are skipped and listed
+    /// in the report, to tolerate before the run fails [default: 0]
+    #[arg(long)]
+    max_errors: Option<u64>,
+
+    /// Write a JSON summary of the run to this path ("-" for stdout): inputs,
+    /// sample counts per filter, output shards with checksums and throughput
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Serve Prometheus metrics of the run at http://<ADDR>/metrics, e.g.
+    /// 0.0.0.0:9100
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Approximate limit on the memory of the games in flight between the
+    /// reader and the writer and of the positions of --dedup-existing, e.g.
+    /// 512M or 4G
+    #[arg(long, value_parser = memory::parse_size)]
+    memory_limit: Option<u64>,
+
+    /// Append the ECO code and the opening name of the game to every sample
+    #[arg(long)]
+    eco: bool,
+
+    /// Append the game phase score (0 to 24) computed from the remaining
+    /// material to every sample
+    #[arg(long)]
+    phase_score: bool,
+
+    /// Append the number of earlier occurrences of the position in the game
+    /// to every sample
+    #[arg(long)]
+    repetitions: bool,
+
+    /// Append the number of plies since the last capture or pawn move to
+    /// every sample
+    #[arg(long)]
+    rule50: bool,
+
+    /// Append the number of plies until the end of the game, the target of
+    /// moves-left heads, to every sample
+    #[arg(long)]
+    plies_left: bool,
+
+    /// Append the plies until the end of the game in buckets of this many
+    /// plies to every sample
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    plies_left_bucket: Option<u32>,
+
+    /// Search value written as best_q and best_d: of the root of the search,
+    /// of the best move or of the best move before rescoring
+    #[arg(long, value_enum)]
+    value_source: Option<ValueSource>,
+
+    /// Also append the q and d of these search values to every sample, e.g.
+    /// root,orig to compare the targets
+    #[arg(long, value_enum, value_delimiter = ',')]
+    value_columns: Vec<ValueSource>,
+
+    /// Append the move played in the game with its q and d to every sample
+    #[arg(long)]
+    played: bool,
+
+    /// Append the 112 input planes of lc0 networks with the history of the
+    /// position, so that lc0 networks can be trained on the output
+    #[arg(long)]
+    lc0_planes: bool,
+
+    /// Append the archive, entry, byte range, game and ply of every sample,
+    /// to trace it back to the input
+    #[arg(long, value_enum)]
+    provenance: Option<ProvenanceFormat>,
+
+    /// Append the active NNUE features of the side to move and of the
+    /// opponent (comma-separated indices) to every sample
+    #[arg(long, value_enum)]
+    nnue: Option<FeatureSet>,
+
+    /// Only keep the samples from these game phases
+    #[arg(long, value_enum, num_args = 1..)]
+    phase: Vec<Phase>,
+
+    /// Write the samples of every game phase to their own dataset, in the
+    /// opening, middlegame and endgame subdirectories of the output directory
+    #[arg(long)]
+    split_by_phase: bool,
+
+    /// Also write the kept samples to a sink of another format, given as
+    /// FORMAT=PATH, e.g. lines=all.txt (formats: text, lines, null)
+    #[arg(
+        long,
+        value_parser = parse_sink,
+        conflicts_with_all = ["resume", "dry_run", "watch"]
+    )]
+    sink: Vec<(String, PathBuf)>,
+
+    /// Drop the samples whose policy entropy (in nats) is below this value,
+    /// near-deterministic policies carry little training signal
+    #[arg(long)]
+    min_policy_entropy: Option<f32>,
+
+    /// Drop the samples whose policy entropy (in nats) is above this value
+    #[arg(long)]
+    max_policy_entropy: Option<f32>,
+
+    /// Handling of the castling flags without the king and a rook on the back
+    /// rank: drop the impossible rights (repair) or the samples (skip)
+    #[arg(long, value_enum)]
+    castling_check: Option<CastlingCheck>,
+
+    /// Only keep the positions with one of these materials, e.g.
+    /// KRPvKR,KBNvK, with either side having the pieces of the first one. A
+    /// side ending with * may have more pieces, e.g. KQ*vK*. Positions with
+    /// few pieces are kept then
+    #[arg(long, value_delimiter = ',')]
+    material: Vec<MaterialSignature>,
+
+    /// Also write transformed copies of the kept samples the transformation
+    /// applies to, e.g. mirror for the positions without castling rights.
+    /// color-flip only changes the side to move of the --lc0-planes
+    #[arg(long, value_enum, num_args = 1..)]
+    augment: Vec<Augmentation>,
+
+    /// Resample the kept samples to balance their evaluations across the
+    /// buckets split at these best_q values, e.g. -0.5,-0.1,0.1,0.5
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    eval_buckets: Vec<f32>,
+
+    /// Target proportions of the evaluation buckets, one more value than
+    /// --eval-buckets [default: equal]
+    #[arg(long, value_delimiter = ',', requires = "eval_buckets")]
+    bucket_proportions: Vec<f64>,
+
+    /// Resample the kept samples to balance the results of their games:
+    /// losses, draws and wins for the side to move
+    #[arg(long)]
+    balance_outcomes: bool,
+
+    /// Target proportions of the losses, draws and wins with
+    /// --balance-outcomes, e.g. 1,2,1 [default: equal]
+    #[arg(long, value_delimiter = ',', requires = "balance_outcomes")]
+    outcome_proportions: Vec<f64>,
+}
+
+// Opens the file for writing a report, "-" stands for stdout.
+fn create_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path.as_os_str() == "-" {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
     }
 }
 
-struct CastlingBitboards {
-    castling_us_oo: u64,
-    castling_us_ooo: u64,
-    castling_them_oo: u64,
-    castling_them_ooo: u64,
+// A sink of --sink, FORMAT=PATH.
+fn parse_sink(value: &str) -> Result<(String, PathBuf), String> {
+    let (format, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FORMAT=PATH, got {value}"))?;
+    let formats: Vec<_> = Registry::default().formats().map(String::from).collect();
+    if !formats.iter().any(|known| known == format) {
+        return Err(format!(
+            "unknown format {format}, expected one of {}",
+            formats.join(", ")
+        ));
+    }
+    Ok((format.to_string(), PathBuf::from(path)))
 }
 
-fn process_position(data: TrainingSample, castling: &CastlingBitboards) {
-    // Filter out positions with too few pieces that will be covered by Syzygy endgame tablebase.
-    let num_pieces = data
-        .bitboards
-        .iter()
-        .fold(0, |acc, plane| acc + plane.count_ones());
-    if num_pieces <= MIN_PIECES {
-        return;
-    }
-
-    // Filter out promotions early.
-    if preprocessing::IDX_TO_MOVE[data.best_idx as usize].len() > 4 {
-        return;
-    }
-
-    let board = data.to_board();
-    // println!(
-    //     "{} {:.3} {:.3} {} {} {} {} {}",
-    //     board,
-    //     data.best_q,
-    //     data.best_d,
-    //     preprocessing::IDX_TO_MOVE[data.best_idx as usize],
-    //     data.castling_us_ooo,
-    //     data.castling_us_oo,
-    //     data.castling_them_ooo,
-    //     data.castling_them_oo,
-    // );
-
-    // TODO: Filter out captures.
-    // TODO: Filter out checks.
-}
-
-fn process_game<R: Read>(reader: R) -> io::Result<()> {
-    let mut gz = GzDecoder::new(reader);
-
-    // The first position in the game has rooks placed on the castling squares.
-    let initial_position = TrainingSample::read_from(&mut gz)?;
-    let initial_board = initial_position.to_board();
-
-    // Calculate the bitboards for castling (initial rook positions).
-    let our_rooks = initial_position.bitboards[3];
-    let castling_us_oo_bitboard = our_rooks & (!our_rooks + 1);
-    let castling_us_ooo_bitboard = our_rooks ^ castling_us_oo_bitboard;
-    let castling_them_oo_bitboard = castling_us_oo_bitboard.swap_bytes();
-    let castling_them_ooo_bitboard = castling_us_ooo_bitboard.swap_bytes();
-
-    let castling_bitboards = CastlingBitboards {
-        castling_us_oo: castling_us_oo_bitboard,
-        castling_us_ooo: castling_us_ooo_bitboard,
-        castling_them_oo: castling_them_oo_bitboard,
-        castling_them_ooo: castling_them_ooo_bitboard,
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    logging::init(cli.global.verbose, cli.global.log_format)?;
+    if cli.global.quiet {
+        progress::hide();
+    }
+    // The analysis commands run on the global pool, the preprocessing
+    // pipeline builds its own.
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.global.threads.unwrap_or(0))
+        .build_global()
+        .map_err(io::Error::other)?;
+
+    match cli.command {
+        Command::Preprocess(args) => {
+            signals::install()?;
+            preprocess(*args, &cli.global)?;
+            if signals::interrupted() {
+                warn!("interrupted before processing all inputs");
+                std::process::exit(signals::INTERRUPTED_EXIT_CODE);
+            }
+            Ok(())
+        }
+        Command::Stats(args) => stats::run(args),
+        Command::DedupReport(args) => dedup::run(args),
+        Command::Compare(args) => compare::run(args),
+        Command::Merge(args) => merge::run(args),
+        Command::Shuffle(args) => global_shuffle::run(args, cli.global.seed(None)),
+        Command::Subset(args) => subset::run(args, cli.global.seed(None)),
+        Command::Query(args) => query::run(args),
+        Command::Validate(args) => validate::run(args),
+        Command::Verify(args) => verify::run(args, cli.global.seed(None)),
+        Command::Inspect(args) => inspect::run(args),
+        Command::ServeUi(args) => viewer::run(args, cli.global.seed(None)),
+        Command::Render(args) => render::run(args),
+        Command::Convert(args) => convert::run(args),
+        Command::ToPgn(args) => to_pgn::run(args),
+        Command::Split(args) => split::run(args, cli.global.seed(None)),
+        Command::Rescore(args) => rescore::run(args),
+        Command::Selfplay(args) => selfplay::run(args, cli.global.seed(None)),
+        Command::Match(args) => matches::run(args),
+        #[cfg(all(unix, feature = "serve"))]
+        Command::Serve(args) => serve::run(args, cli.global.seed(None)),
+        #[cfg(feature = "train")]
+        Command::Train(args) => train::run(args, cli.global.seed(None)),
+        #[cfg(feature = "train")]
+        Command::Quantize(args) => quantize::run(args, cli.global.seed(None)),
+        #[cfg(feature = "train")]
+        Command::ExportOnnx(args) => onnx::run(args),
+        #[cfg(feature = "train")]
+        Command::Net(args) => net::run(args),
+        Command::Book(args) => book::run(args),
+        Command::MineTactics(args) => tactics::run(args),
+        Command::Tune(args) => tune::run(args, cli.global.seed(None)),
+        Command::FuzzCorpus(args) => fuzz_corpus::run(args, cli.global.seed(None)),
+        Command::MakeFixtures(args) => fixtures::run(args, cli.global.seed(None)),
+        Command::Coordinate(args) => coordinator::run(args),
+        Command::Work(args) => worker::run(args, cli.global.threads, cli.global.seed),
+        Command::GenerateCompletions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+            Ok(())
+        }
+    }
+}
+
+fn preprocess(args: Args, global: &GlobalArgs) -> io::Result<()> {
+    if args.help_format {
+        print_format_help();
+        return Ok(());
+    }
+
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let missing = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{what} has to be given on the command line or in the config"),
+        )
     };
 
-    println!(
-        "{} {} {} {} {}",
-        initial_board,
-        castling_us_oo_bitboard,
-        castling_us_ooo_bitboard,
-        castling_them_oo_bitboard,
-        castling_them_ooo_bitboard
-    );
+    let tar_path = if args.tar_path.is_empty() {
+        config.inputs.clone()
+    } else {
+        args.tar_path
+    };
+    if tar_path.is_empty() && args.watch.is_none() {
+        return Err(missing("--tar-path"));
+    }
+    let input_format = args.input_format.or(config.input_format);
+    let pgn_filter = PgnFilter {
+        min_elo: args.min_elo.or(config.filters.min_elo),
+        max_elo: args.max_elo.or(config.filters.max_elo),
+        rated_only: args.rated_only || config.filters.rated_only,
+        min_time_control: args.min_time_control.or(config.filters.min_time_control),
+        normal_termination: args.normal_termination || config.filters.normal_termination,
+        min_plies: args.min_plies.or(config.filters.min_plies),
+    };
+    let shard_size = args
+        .shard_size
+        .or(config.output.shard_size)
+        .unwrap_or(1_000_000);
+    let memory_limit = match args.memory_limit {
+        Some(limit) => Some(limit),
+        None => config.memory_limit()?,
+    };
+    let filters = FilterOptions {
+        phases: if args.phase.is_empty() {
+            config.filters.phases
+        } else {
+            args.phase
+        },
+        min_policy_entropy: args
+            .min_policy_entropy
+            .or(config.filters.min_policy_entropy),
+        max_policy_entropy: args
+            .max_policy_entropy
+            .or(config.filters.max_policy_entropy),
+        castling: args
+            .castling_check
+            .or(config.filters.castling)
+            .unwrap_or_default(),
+        material: if args.material.is_empty() {
+            config.filters.material
+        } else {
+            args.material
+        },
+    };
+    let fields = OutputFields {
+        eco: args.eco || config.output.eco,
+        phase_score: args.phase_score || config.output.phase_score,
+        repetitions: args.repetitions || config.output.repetitions,
+        rule50: args.rule50 || config.output.rule50,
+        plies_left: args.plies_left || config.output.plies_left,
+        plies_left_bucket: args.plies_left_bucket.or(config.output.plies_left_bucket),
+        value_source: args
+            .value_source
+            .or(config.output.value_source)
+            .unwrap_or_default(),
+        value_columns: {
+            let columns = if args.value_columns.is_empty() {
+                &config.output.value_columns
+            } else {
+                &args.value_columns
+            };
+            ValueSource::ALL.map(|source| columns.contains(&source))
+        },
+        played: args.played || config.output.played,
+        lc0_planes: args.lc0_planes || config.output.lc0_planes,
+        provenance: args.provenance.or(config.output.provenance),
+        nnue: args.nnue.or(config.output.nnue),
+    };
+    let augment = if args.augment.is_empty() {
+        config.output.augment
+    } else {
+        args.augment
+    };
+    let buckets = if args.eval_buckets.is_empty() {
+        eval_buckets(
+            config.filters.eval_buckets,
+            config.filters.bucket_proportions,
+        )?
+    } else {
+        eval_buckets(args.eval_buckets, args.bucket_proportions)?
+    };
+    let outcome_proportions = if args.balance_outcomes || config.filters.balance_outcomes {
+        let proportions = if args.outcome_proportions.is_empty() {
+            config.filters.outcome_proportions
+        } else {
+            args.outcome_proportions
+        };
+        Some(outcome_proportions(proportions)?)
+    } else {
+        None
+    };
+    let threads = global.threads.or(config.threads);
+    let interleave = args.interleave.or(config.interleave).unwrap_or(1).max(1) as usize;
+    let max_samples = args.limit.or(config.limit);
+    let max_games = args.limit_games.or(config.limit_games);
+    let max_errors = args.max_errors.or(config.max_errors).unwrap_or(0);
+    let budget = MemoryBudget::new(memory_limit);
+    let report_path = args.report.or(config.report);
+    if let Some(addr) = args.metrics_addr {
+        metrics::serve(addr)?;
+    }
 
-    // TODO: lc0 training data does not contain en passant squares, but those
-    // can be retroactively calculated.
-    while let Ok(data) = TrainingSample::read_from(&mut gz) {
-        process_position(data, &castling_bitboards);
-        break;
+    if args.dry_run {
+        let started = Instant::now();
+        let inputs = pipeline::Inputs {
+            archives: tar_path.clone(),
+            format: input_format,
+            pgn_filter,
+            first_archive: 0,
+            first_entry: 0,
+            max_games,
+            max_samples,
+            interleave,
+        };
+        let seed = global.seed(config.seed);
+        let processing = pipeline::Processing {
+            filters,
+            augment,
+            fields,
+            resampler: buckets
+                .map(|(edges, proportions)| Resampler::new(edges, proportions, Rng::new(seed))),
+            outcome_resampler: outcome_proportions
+                .map(|proportions| outcome_resampler(proportions, Rng::new(seed))),
+            split_by_phase: false,
+            existing_positions: None,
+        };
+        let summary = pipeline::run(inputs, threads, &budget, processing, Output::DryRun)?;
+        report_dry_run(&summary, shard_size);
+        if let Some(path) = report_path {
+            let mut report = Report::new(&tar_path, &summary, started.elapsed())?;
+            report.dry_run = true;
+            report.seed = Some(seed);
+            report.fields = fields.names();
+            report.write(&path)?;
+        }
+        return check_errors(&summary, max_errors);
     }
 
-    Ok(())
+    let output_dir = args
+        .output_dir
+        .or(config.output.dir)
+        .ok_or_else(|| missing("--output-dir"))?;
+    // The archives processed by earlier runs are skipped, unless the run
+    // starts over.
+    let manifest = output_dir.join("manifest");
+    if args.force {
+        match fs::remove_file(&manifest) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    let job = ShardJob {
+        global,
+        config_seed: config.seed,
+        checkpoint: args
+            .checkpoint
+            .unwrap_or_else(|| output_dir.join("checkpoint")),
+        checkpoint_interval: args.checkpoint_interval.max(1),
+        manifest,
+        input_format,
+        pgn_filter,
+        interleave,
+        output_dir,
+        shard_size,
+        threads,
+        budget,
+        filters,
+        augment,
+        fields,
+        buckets,
+        outcome_proportions,
+        split_by_phase: args.split_by_phase || config.output.split_by_phase,
+        append: args.append || config.output.append,
+        dedup_existing: args.dedup_existing || config.output.dedup_existing,
+        sinks: args.sink,
+        max_games,
+        max_samples,
+        max_errors,
+        report_path,
+    };
+    match args.watch {
+        Some(dir) => {
+            let interval = Duration::from_secs(args.watch_interval.max(1));
+            watch::watch(&dir, interval, tar_path, |archives| {
+                job.run(archives, false)
+            })
+        }
+        None => job.run(tar_path, args.resume),
+    }
 }
 
-fn process_tar_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    let file = File::open(path)?;
-    let mut archive = Archive::new(file);
+// Everything about writing the shards that stays the same between the batches
+// of --watch.
+struct ShardJob<'a> {
+    global: &'a GlobalArgs,
+    config_seed: Option<u64>,
+    output_dir: PathBuf,
+    checkpoint: PathBuf,
+    checkpoint_interval: usize,
+    manifest: PathBuf,
+    input_format: Option<InputFormat>,
+    pgn_filter: PgnFilter,
+    interleave: usize,
+    shard_size: u64,
+    threads: Option<usize>,
+    budget: MemoryBudget,
+    filters: FilterOptions,
+    augment: Vec<Augmentation>,
+    fields: OutputFields,
+    buckets: Option<Buckets>,
+    outcome_proportions: Option<Vec<f64>>,
+    split_by_phase: bool,
+    // Continue after the shards on disk (`--append`) and drop the positions
+    // in them (`--dedup-existing`).
+    append: bool,
+    dedup_existing: bool,
+    // Formats and paths of the sinks written next to the shards (`--sink`).
+    sinks: Vec<(String, PathBuf)>,
+    max_games: Option<u64>,
+    max_samples: Option<u64>,
+    max_errors: u64,
+    report_path: Option<PathBuf>,
+}
 
-    for entry in archive.entries()? {
-        let entry = entry?;
-        if !entry.path()?.to_string_lossy().ends_with(".gz") {
-            continue;
+impl ShardJob<'_> {
+    // Directories of the writers: the output directory, or a subdirectory of
+    // it per phase.
+    fn shard_dirs(&self) -> Vec<PathBuf> {
+        if self.split_by_phase {
+            Phase::ALL
+                .iter()
+                .map(|phase| self.output_dir.join(phase.name()))
+                .collect()
+        } else {
+            vec![self.output_dir.clone()]
         }
+    }
 
-        process_game(entry)?;
+    // Hashes of the positions in the shards of the writers, in at most half of
+    // the memory limit.
+    fn existing_positions(&self, dirs: &[PathBuf]) -> io::Result<ExistingPositions> {
+        let mut positions = ExistingPositions::new(self.budget.limit().map(|limit| limit / 2));
+        let mut count = 0;
+        for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+            output::for_each_line(dir, |line| {
+                positions.insert(pipeline::position_hash(&OutputSample::parse(line)?));
+                count += 1;
+                Ok(())
+            })?;
+        }
+        match &positions {
+            ExistingPositions::Exact { hashes, .. } => info!(
+                samples = count,
+                positions = hashes.len(),
+                "read the positions of the existing shards"
+            ),
+            ExistingPositions::Approximate(filter) => warn!(
+                samples = count,
+                false_positive_rate = filter.false_positive_rate(),
+                "the positions of the existing shards exceed half of the memory limit, \
+                 some new positions are dropped as existing"
+            ),
+        }
+        Ok(positions)
     }
 
+    // Processes the archives that are not in the manifest yet, appending their
+    // samples to the shards, and records them in the manifest. A resumed run
+    // skips the same archives as the run it continues, the manifest only
+    // changes once a run is finished.
+    fn run(&self, tar_path: Vec<String>, resume: bool) -> io::Result<()> {
+        let started = Instant::now();
+        let global = self.global;
+        let mut manifest = Manifest::load(&self.manifest)?;
+        let hashes = manifest::hash_archives(&tar_path);
+        let (tar_path, hashes): (Vec<_>, Vec<_>) = tar_path
+            .into_iter()
+            .zip(hashes)
+            .filter(|(path, hash)| {
+                let processed = hash.as_ref().is_some_and(|hash| manifest.contains(hash));
+                if processed {
+                    info!(archive = %path, "skipping archive processed by an earlier run");
+                }
+                !processed
+            })
+            .unzip();
+        if tar_path.is_empty() {
+            info!("all archives were processed by earlier runs, see --force");
+            return Ok(());
+        }
+        let dirs = self.shard_dirs();
+        let mut first_shard = manifest.next_shard();
+        if self.append {
+            for dir in &dirs {
+                first_shard = first_shard.max(output::next_shard_index(dir)?);
+            }
+        }
+
+        let mut checkpointer = Checkpointer {
+            path: self.checkpoint.clone(),
+            interval: self.checkpoint_interval,
+            seed: 0,
+        };
+        let (writers, first_archive, first_entry, resamplers) = if resume {
+            let checkpoint = Checkpoint::load(&checkpointer.path)?;
+            checkpointer.seed = match (checkpoint.seed, global.seed.or(self.config_seed)) {
+                (Some(recorded), Some(given)) if recorded != given => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("the run was started with seed {recorded}, not {given}"),
+                    ))
+                }
+                (Some(recorded), _) => recorded,
+                (None, given) => global.seed(given),
+            };
+            let first_archive = tar_path
+                .iter()
+                .position(|path| *path == checkpoint.archive)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is not among the inputs", checkpoint.archive),
+                    )
+                })?;
+            if checkpoint.shards.len() != dirs.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the run was started with a different --split-by-phase",
+                ));
+            }
+            let writers = dirs
+                .iter()
+                .zip(checkpoint.shards)
+                .map(|(dir, shard)| ShardWriter::resume(dir, self.shard_size, shard))
+                .collect::<io::Result<_>>()?;
+            let resamplers = [checkpoint.resampler, checkpoint.outcome_resampler];
+            (writers, first_archive, checkpoint.entry, resamplers)
+        } else {
+            checkpointer.seed = global.seed(self.config_seed);
+            let shard = ShardState {
+                index: first_shard,
+                ..ShardState::default()
+            };
+            let writers = dirs
+                .iter()
+                .map(|dir| ShardWriter::resume(dir, self.shard_size, shard))
+                .collect::<io::Result<_>>()?;
+            (writers, 0, 0, [None, None])
+        };
+
+        let registry = Registry::default();
+        let sinks = self
+            .sinks
+            .iter()
+            .map(|(format, path)| {
+                let config = SinkConfig {
+                    path: path.clone(),
+                    shard_size: self.shard_size,
+                };
+                registry.create(format, &config)
+            })
+            .collect::<io::Result<_>>()?;
+
+        let seed = checkpointer.seed;
+        let mut resampler = self
+            .buckets
+            .clone()
+            .map(|(edges, proportions)| Resampler::new(edges, proportions, Rng::new(seed)));
+        let mut outcome_resampler = self
+            .outcome_proportions
+            .clone()
+            .map(|proportions| outcome_resampler(proportions, Rng::new(seed)));
+        // The resamplers of a resumed run continue where they stopped.
+        for (resampler, state) in [&mut resampler, &mut outcome_resampler]
+            .into_iter()
+            .zip(resamplers)
+        {
+            if let (Some(resampler), Some(state)) = (resampler, state) {
+                resampler.restore(state)?;
+            }
+        }
+        let processing = pipeline::Processing {
+            filters: self.filters.clone(),
+            augment: self.augment.clone(),
+            fields: self.fields,
+            resampler,
+            outcome_resampler,
+            split_by_phase: self.split_by_phase,
+            existing_positions: if self.dedup_existing {
+                Some(self.existing_positions(&dirs)?)
+            } else {
+                None
+            },
+        };
+        let summary = pipeline::run(
+            pipeline::Inputs {
+                archives: tar_path.clone(),
+                format: self.input_format,
+                pgn_filter: self.pgn_filter,
+                first_archive,
+                first_entry,
+                max_games: self.max_games,
+                max_samples: self.max_samples,
+                interleave: self.interleave,
+            },
+            self.threads,
+            &self.budget,
+            processing,
+            Output::Shards {
+                writers,
+                checkpointer,
+                sinks,
+            },
+        )?;
+
+        if let Some(path) = &self.report_path {
+            let mut report = Report::new(&tar_path[first_archive..], &summary, started.elapsed())?;
+            report.seed = Some(seed);
+            report.fields = self.fields.names();
+            for dir in &dirs {
+                report.add_shards(dir)?;
+            }
+            report.write(path)?;
+        }
+        if signals::interrupted() || self.max_games.is_some() || self.max_samples.is_some() {
+            info!("not recording the archives of an incomplete run in the manifest");
+        } else if let Some(last_shard) = summary.last_shard {
+            for (idx, (archive, hash)) in tar_path.into_iter().zip(hashes).enumerate() {
+                let Some(sha256) = hash else {
+                    continue;
+                };
+                if !summary.failed_archives.contains(&idx) {
+                    manifest.entries.push(manifest::Entry {
+                        sha256,
+                        shards: first_shard..=last_shard,
+                        archive,
+                    });
+                }
+            }
+            manifest.save(&self.manifest)?;
+        }
+        check_errors(&summary, self.max_errors)
+    }
+}
+
+// Fails the run if more archives and games were skipped than tolerated, after
+// all inputs are processed and the report is written.
+fn check_errors(summary: &Summary, max_errors: u64) -> io::Result<()> {
+    let errors = summary.errors.len() as u64;
+    if errors > max_errors {
+        return Err(io::Error::other(format!(
+            "{errors} archives or games could not be read, more than --max-errors {max_errors}"
+        )));
+    }
     Ok(())
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
+fn print_format_help() {
+    println!("Every output line holds one sample, the fields are separated by spaces:\n");
+    for field in OUTPUT_SCHEMA {
+        let option = field.option.unwrap_or("always");
+        println!("  {:<17} {:<19} {}", field.name, option, field.description);
+    }
+    println!(
+        "\nEvery shard-NNNNN.txt has an index shard-NNNNN.idx holding the byte offset of \
+         every line as a little-endian u64, for random access to the samples."
+    );
+    println!("\nThe convert command writes the decoded samples as JSON objects instead.");
+}
+
+// Edges and target proportions of the evaluation buckets.
+type Buckets = (Vec<f32>, Vec<f64>);
+
+// Validates the evaluation buckets, None if the samples are not resampled.
+fn eval_buckets(edges: Vec<f32>, proportions: Vec<f64>) -> io::Result<Option<Buckets>> {
+    if edges.is_empty() {
+        return Ok(None);
+    }
+    let proportions = if proportions.is_empty() {
+        vec![1.0; edges.len() + 1]
+    } else {
+        proportions
+    };
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if proportions.len() != edges.len() + 1 {
+        return Err(invalid(
+            "there has to be one more bucket proportion than bucket edges",
+        ));
+    }
+    if !edges.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(invalid("the bucket edges have to be ascending"));
+    }
+    if proportions.iter().any(|&p| p < 0.0) || proportions.iter().sum::<f64>() <= 0.0 {
+        return Err(invalid(
+            "the bucket proportions have to be non-negative and not all zero",
+        ));
+    }
+    Ok(Some((edges, proportions)))
+}
+
+// Validates the target proportions of the outcomes, equal if none are given.
+fn outcome_proportions(proportions: Vec<f64>) -> io::Result<Vec<f64>> {
+    if proportions.is_empty() {
+        return Ok(vec![1.0; OUTCOMES.len()]);
+    }
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if proportions.len() != OUTCOMES.len() {
+        return Err(invalid(
+            "there have to be three outcome proportions: losses, draws and wins",
+        ));
+    }
+    if proportions.iter().any(|&p| p < 0.0) || proportions.iter().sum::<f64>() <= 0.0 {
+        return Err(invalid(
+            "the outcome proportions have to be non-negative and not all zero",
+        ));
+    }
+    Ok(proportions)
+}
 
-    process_tar_file(&args.tar_path)
+// The outcomes are resampled with a stream of their own, independent of the
+// evaluation buckets.
+fn outcome_resampler(proportions: Vec<f64>, rng: Rng) -> Resampler {
+    Resampler::new(OUTCOME_EDGES.to_vec(), proportions, rng.fork(1))
+}
+
+fn report_dry_run(summary: &Summary, shard_size: u64) {
+    println!(
+        "would keep {} of {} samples from {} games",
+        summary.kept, summary.samples, summary.games
+    );
+    for filter in Filter::ALL {
+        println!(
+            "dropped by {}: {}",
+            filter.name(),
+            summary.filtered[filter as usize]
+        );
+    }
+    if summary.resampled > 0 {
+        println!("dropped by resampling: {}", summary.resampled);
+    }
+    if summary.existing > 0 {
+        println!("dropped as existing positions: {}", summary.existing);
+    }
+    if summary.balanced > 0 {
+        println!("dropped by balancing the outcomes: {}", summary.balanced);
+    }
+    let kept = summary.kept.max(1) as f64;
+    let outcomes: Vec<String> = OUTCOMES
+        .iter()
+        .zip(summary.outcomes)
+        .map(|(name, count)| format!("{name} {:.1}%", 100.0 * count as f64 / kept))
+        .collect();
+    println!("outcomes of the kept samples: {}", outcomes.join(", "));
+    if !summary.errors.is_empty() {
+        println!("unreadable archives and games: {}", summary.errors.len());
+    }
+    println!(
+        "estimated output: {} in {} shards",
+        HumanBytes(summary.bytes),
+        summary.kept.div_ceil(shard_size.max(1)).max(1)
+    );
 }
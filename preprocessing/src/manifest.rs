@@ -0,0 +1,135 @@
+// Archives that were processed into the output directory, identified by the
+// SHA-256 of their contents, so that a run over a growing list of archives
+// only processes the new ones and appends their samples to the shards of the
+// earlier runs. `--force` processes all archives again from the first shard,
+// `--append` continues after all shards of the directory, also the ones of
+// datasets that were not written by these runs.
+//
+// An archive is recorded once a run processed all of its inputs: interrupted
+// and limited runs and the archives with errors are not recorded. The path is
+// informational, a renamed or moved archive is still recognized.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use preprocessing::storage;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub sha256: String,
+    // Shards written by the run that processed the archive.
+    pub shards: RangeInclusive<usize>,
+    pub archive: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    // One `<sha256> <first shard> <last shard> <archive>` line per archive, to
+    // keep it readable and easy to fix by hand. The manifest is empty if the
+    // file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(err) => return Err(err),
+        };
+        let mut entries = Vec::new();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid manifest line: {line}"),
+                )
+            };
+            let mut fields = line.splitn(4, ' ');
+            let mut next = || fields.next().ok_or_else(invalid);
+            let sha256 = next()?.to_string();
+            let first = next()?.parse().map_err(|_| invalid())?;
+            let last = next()?.parse().map_err(|_| invalid())?;
+            let archive = next()?.to_string();
+            entries.push(Entry {
+                sha256,
+                shards: first..=last,
+                archive,
+            });
+        }
+        Ok(Manifest { entries })
+    }
+
+    // Writes the manifest atomically, like the checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = fs::File::create(&tmp_path)?;
+        for entry in &self.entries {
+            writeln!(
+                file,
+                "{} {} {} {}",
+                entry.sha256,
+                entry.shards.start(),
+                entry.shards.end(),
+                entry.archive
+            )?;
+        }
+        file.sync_all()?;
+
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.entries.iter().any(|entry| entry.sha256 == sha256)
+    }
+
+    // Index of the first shard after the ones of the recorded archives.
+    pub fn next_shard(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|entry| entry.shards.end() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// Checksums the archives in parallel, None for the archives that cannot be
+// read: they are neither skipped nor recorded, the run reports them.
+pub fn hash_archives(archives: &[String]) -> Vec<Option<String>> {
+    info!(archives = archives.len(), "hashing archives");
+    archives
+        .par_iter()
+        .map(|path| match sha256(path) {
+            Ok(sha256) => Some(sha256),
+            Err(err) => {
+                debug!(archive = %path, %err, "failed to hash archive");
+                None
+            }
+        })
+        .collect()
+}
+
+fn sha256(path: &str) -> io::Result<String> {
+    let mut file = storage::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
@@ -0,0 +1,614 @@
+// Matches between two UCI engines (`match`), for quick tests of engine changes
+// without cutechess. Either engine may be attix itself, e.g. two builds of it
+// or one build with different options.
+//
+// Every opening is played twice with the colors swapped, under a clock
+// (--tc), a fixed time per move (--movetime) or a fixed depth or number of
+// nodes per move. A game ends by the rules (checkmate, stalemate, threefold
+// repetition, the fifty-move rule or insufficient material), by an illegal
+// move, by a loss on time or by adjudication:
+// - a draw once the scores of both engines stayed within --draw-score
+//   centipawns for --draw-moves moves each, from move --draw-after on,
+// - a resignation once an engine reported a score of -(--resign-score)
+//   centipawns or worse for --resign-moves of its moves in a row,
+// - a draw after --max-plies.
+//
+// The games are played in parallel, but written to the PGN in the order of
+// their numbers. The result is reported from the perspective of the first
+// engine, with the Elo difference and its 95% confidence interval.
+//
+// With --elo0 and --elo1 the match is a sequential probability ratio test
+// (SPRT) of whether the first engine is elo0 or elo1 Elo stronger, with the
+// error rates --alpha (accepting elo1 when it is elo0) and --beta. The
+// log-likelihood ratio (LLR) is reported after every game, and the match
+// stops once it leaves the bounds given by the error rates, --games being the
+// most games. The games in flight when it stops are not counted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use crossbeam_channel::unbounded;
+use shakmaty::fen::Fen;
+use shakmaty::san::SanPlus;
+use shakmaty::{ByColor, CastlingMode, Chess, Color, EnPassantMode, Position};
+use tracing::{info, warn};
+
+use crate::selfplay;
+use crate::signals;
+use crate::uci::{self, Engine};
+
+// Score of a mate in the adjudication, beyond any centipawn score.
+const MATE_CP: i32 = 100_000;
+const PGN_WIDTH: usize = 80;
+
+#[derive(Args)]
+#[command(group = clap::ArgGroup::new("limit").required(true))]
+pub struct MatchArgs {
+    /// Path of the first UCI engine, whose results are reported
+    #[arg(long)]
+    engine1: PathBuf,
+
+    /// UCI option of the first engine, e.g. --option1 Hash=64
+    #[arg(long = "option1", value_name = "NAME=VALUE", value_parser = uci::parse_option)]
+    options1: Vec<(String, String)>,
+
+    /// Path of the second UCI engine
+    #[arg(long)]
+    engine2: PathBuf,
+
+    /// UCI option of the second engine, e.g. --option2 Threads=2
+    #[arg(long = "option2", value_name = "NAME=VALUE", value_parser = uci::parse_option)]
+    options2: Vec<(String, String)>,
+
+    /// Number of games, every opening is played twice with the colors
+    /// swapped
+    #[arg(long, default_value_t = 100)]
+    games: u64,
+
+    /// Number of games played at the same time, each by its own pair of
+    /// engine processes
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+
+    /// Openings with a FEN (or EPD) per line, played in order [default: the
+    /// initial position]
+    #[arg(long)]
+    openings: Option<PathBuf>,
+
+    /// Time control as BASE+INCREMENT in seconds, e.g. 10+0.1
+    #[arg(long, group = "limit", value_parser = parse_tc)]
+    tc: Option<(Duration, Duration)>,
+
+    /// Milliseconds per move
+    #[arg(long, group = "limit")]
+    movetime: Option<u64>,
+
+    /// Search every move to this depth
+    #[arg(long, group = "limit")]
+    depth: Option<u32>,
+
+    /// Search every move for this many nodes
+    #[arg(long, group = "limit")]
+    nodes: Option<u64>,
+
+    /// Milliseconds an engine may overrun its clock by before losing on time
+    #[arg(long, default_value_t = 50)]
+    time_margin: u64,
+
+    /// Move number from which draws are adjudicated
+    #[arg(long, default_value_t = 40)]
+    draw_after: u32,
+
+    /// Moves of each engine with drawn scores for a draw, 0 never
+    /// adjudicates draws
+    #[arg(long, default_value_t = 8)]
+    draw_moves: u32,
+
+    /// Centipawns of a drawn score
+    #[arg(long, default_value_t = 10)]
+    draw_score: i32,
+
+    /// Moves of an engine with lost scores for it to resign, 0 never
+    /// resigns
+    #[arg(long, default_value_t = 3)]
+    resign_moves: u32,
+
+    /// Centipawns of a lost score
+    #[arg(long, default_value_t = 1000)]
+    resign_score: i32,
+
+    /// Games longer than this many plies are adjudicated as draws
+    #[arg(long, default_value_t = 400)]
+    max_plies: u32,
+
+    /// File to write the games to as PGN
+    #[arg(long)]
+    pgn: Option<PathBuf>,
+
+    /// Elo difference of the null hypothesis of an SPRT
+    #[arg(long, requires = "elo1", allow_hyphen_values = true)]
+    elo0: Option<f64>,
+
+    /// Elo difference of the alternative hypothesis of an SPRT
+    #[arg(long, requires = "elo0", allow_hyphen_values = true)]
+    elo1: Option<f64>,
+
+    /// Probability of the SPRT accepting elo1 when elo0 holds
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+
+    /// Probability of the SPRT accepting elo0 when elo1 holds
+    #[arg(long, default_value_t = 0.05)]
+    beta: f64,
+}
+
+fn parse_tc(s: &str) -> Result<(Duration, Duration), String> {
+    let (base, increment) = s.split_once('+').unwrap_or((s, "0"));
+    let seconds = |value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| format!("expected BASE+INCREMENT in seconds, got {s}"))
+    };
+    Ok((seconds(base)?, seconds(increment)?))
+}
+
+// A finished game.
+struct Game {
+    white: String,
+    black: String,
+    // The FEN of the opening, None for the initial position.
+    fen: Option<String>,
+    winner: Option<Color>,
+    // The Termination tag and the comment after the last move.
+    termination: &'static str,
+    reason: String,
+    // The move numbers, moves and comments of the movetext.
+    tokens: Vec<String>,
+    plies: u32,
+}
+
+impl Game {
+    fn result(&self) -> &'static str {
+        match self.winner {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+
+    fn write_pgn<W: Write>(&self, writer: &mut W, round: u64, tc: &str) -> io::Result<()> {
+        writeln!(writer, "[Event \"match\"]")?;
+        writeln!(writer, "[Site \"?\"]")?;
+        writeln!(writer, "[Date \"????.??.??\"]")?;
+        writeln!(writer, "[Round \"{round}\"]")?;
+        writeln!(writer, "[White \"{}\"]", self.white)?;
+        writeln!(writer, "[Black \"{}\"]", self.black)?;
+        writeln!(writer, "[Result \"{}\"]", self.result())?;
+        if let Some(fen) = &self.fen {
+            writeln!(writer, "[SetUp \"1\"]")?;
+            writeln!(writer, "[FEN \"{fen}\"]")?;
+        }
+        writeln!(writer, "[TimeControl \"{tc}\"]")?;
+        writeln!(writer, "[PlyCount \"{}\"]", self.plies)?;
+        writeln!(writer, "[Termination \"{}\"]", self.termination)?;
+        writeln!(writer)?;
+        let reason = format!("{{{}}}", self.reason);
+        let mut line = String::new();
+        for token in self
+            .tokens
+            .iter()
+            .map(String::as_str)
+            .chain([&*reason, self.result()])
+        {
+            if !line.is_empty() && line.len() + 1 + token.len() > PGN_WIDTH {
+                writeln!(writer, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(token);
+        }
+        writeln!(writer, "{line}")?;
+        writeln!(writer)
+    }
+}
+
+// The score of the search in centipawns from the perspective of the side to
+// move, mates as MATE_CP.
+fn centipawns(analysis: &uci::Analysis) -> Option<i32> {
+    let variation = analysis.variations.first()?;
+    match (variation.mate, variation.cp) {
+        (Some(mate), _) => Some(mate.signum() * MATE_CP),
+        (None, cp) => cp,
+    }
+}
+
+// The comment of a move: the score from the perspective of the engine that
+// played it and the time it took.
+fn move_comment(analysis: &uci::Analysis, elapsed: Duration) -> String {
+    let score =
+        analysis
+            .variations
+            .first()
+            .and_then(|variation| match (variation.mate, variation.cp) {
+                (Some(mate), _) if mate > 0 => Some(format!("+M{mate}")),
+                (Some(mate), _) => Some(format!("-M{}", -mate)),
+                (None, Some(cp)) => Some(format!("{:+.2}", cp as f64 / 100.0)),
+                (None, None) => None,
+            });
+    let seconds = elapsed.as_secs_f64();
+    match score {
+        Some(score) => format!("{{{score} {seconds:.2}s}}"),
+        None => format!("{{{seconds:.2}s}}"),
+    }
+}
+
+fn go_command(args: &MatchArgs, clocks: &ByColor<Duration>) -> String {
+    match (args.tc, args.movetime, args.depth, args.nodes) {
+        (Some((_, increment)), ..) => format!(
+            "go wtime {} btime {} winc {} binc {}",
+            clocks.white.as_millis(),
+            clocks.black.as_millis(),
+            increment.as_millis(),
+            increment.as_millis()
+        ),
+        (None, Some(movetime), ..) => format!("go movetime {movetime}"),
+        (None, None, Some(depth), _) => format!("go depth {depth}"),
+        (None, None, None, Some(nodes)) => format!("go nodes {nodes}"),
+        (None, None, None, None) => unreachable!("the limit is required"),
+    }
+}
+
+// Plays a game from the opening with `engines[white]` as white.
+fn play(
+    engines: &mut [Engine; 2],
+    white: usize,
+    opening: &Chess,
+    args: &MatchArgs,
+) -> io::Result<Game> {
+    for engine in engines.iter_mut() {
+        engine.new_game()?;
+    }
+    let fen = Fen::from_position(opening.clone(), EnPassantMode::Legal);
+    let castling_mode = CastlingMode::detect(fen.as_setup());
+    let fen = fen.to_string();
+    let initial = Fen::from_position(Chess::default(), EnPassantMode::Legal).to_string();
+    let engine_of = |color: Color| {
+        if color == Color::White {
+            white
+        } else {
+            1 - white
+        }
+    };
+
+    let base = args.tc.map_or(Duration::ZERO, |(base, _)| base);
+    let mut clocks = ByColor {
+        white: base,
+        black: base,
+    };
+    // Consecutive moves with lost scores of every side and plies with drawn
+    // scores of both.
+    let mut losing = ByColor { white: 0, black: 0 };
+    let mut drawn = 0;
+
+    let mut pos = opening.clone();
+    let mut moves: Vec<String> = Vec::new();
+    let mut tokens = Vec::new();
+    let mut repetitions: HashMap<String, u32> = HashMap::new();
+    let (winner, termination, reason) = loop {
+        let key = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+        // The position without the move counters.
+        let key = key.rsplitn(3, ' ').last().unwrap_or(&key).to_string();
+        let count = repetitions.entry(key).or_default();
+        *count += 1;
+        if pos.is_checkmate() {
+            break (Some(!pos.turn()), "normal", "checkmate".to_string());
+        }
+        if pos.is_stalemate() {
+            break (None, "normal", "stalemate".to_string());
+        }
+        if pos.is_insufficient_material() {
+            break (None, "normal", "insufficient material".to_string());
+        }
+        if *count >= 3 {
+            break (None, "normal", "threefold repetition".to_string());
+        }
+        if pos.halfmoves() >= 100 {
+            break (None, "normal", "fifty-move rule".to_string());
+        }
+        if moves.len() >= args.max_plies as usize {
+            break (None, "adjudication", "maximum length".to_string());
+        }
+
+        let side = pos.turn();
+        let engine = &mut engines[engine_of(side)];
+        let position = if moves.is_empty() {
+            format!("position fen {fen}")
+        } else {
+            format!("position fen {fen} moves {}", moves.join(" "))
+        };
+        let start = Instant::now();
+        let analysis = engine.search(&position, &go_command(args, &clocks))?;
+        let elapsed = start.elapsed();
+        if let Some((_, increment)) = args.tc {
+            let clock = clocks.get_mut(side);
+            if elapsed > *clock + Duration::from_millis(args.time_margin) {
+                let reason = format!("{} loses on time", engine.name);
+                break (Some(!side), "time forfeit", reason);
+            }
+            *clock = clock.saturating_sub(elapsed) + increment;
+        }
+        let Some(m) = uci::find_move(&pos, &analysis.best_move) else {
+            let reason = format!(
+                "{} plays the illegal move {}",
+                engine.name, analysis.best_move
+            );
+            break (Some(!side), "rules infraction", reason);
+        };
+
+        if side == Color::White || tokens.is_empty() {
+            let number = pos.fullmoves().get();
+            let dots = if side == Color::White { "." } else { "..." };
+            tokens.push(format!("{number}{dots}"));
+        }
+        moves.push(m.to_uci(castling_mode).to_string());
+        let san = SanPlus::from_move_and_play_unchecked(&mut pos, &m);
+        tokens.push(san.to_string());
+        tokens.push(move_comment(&analysis, elapsed));
+
+        let score = centipawns(&analysis);
+        let lost = score.is_some_and(|score| score <= -args.resign_score);
+        *losing.get_mut(side) = if lost { losing.get(side) + 1 } else { 0 };
+        if args.resign_moves > 0 && *losing.get(side) >= args.resign_moves {
+            break (
+                Some(!side),
+                "adjudication",
+                format!("{} resigns", engine.name),
+            );
+        }
+        let draw_started = pos.fullmoves().get() > args.draw_after;
+        let even = score.is_some_and(|score| score.abs() <= args.draw_score);
+        drawn = if draw_started && even { drawn + 1 } else { 0 };
+        if args.draw_moves > 0 && drawn >= 2 * args.draw_moves {
+            break (None, "adjudication", "drawn scores".to_string());
+        }
+    };
+    Ok(Game {
+        white: engines[white].name.clone(),
+        black: engines[1 - white].name.clone(),
+        fen: (fen != initial).then_some(fen),
+        winner,
+        termination,
+        reason,
+        tokens,
+        plies: moves.len() as u32,
+    })
+}
+
+// The results of the first engine.
+#[derive(Debug, Default)]
+struct Score {
+    wins: u64,
+    draws: u64,
+    losses: u64,
+}
+
+// The expected score of a player the Elo difference stronger.
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Score {
+    // The number of games, the mean score of a game and its variance.
+    fn stats(&self) -> (f64, f64, f64) {
+        let games = (self.wins + self.draws + self.losses) as f64;
+        let (wins, draws, losses) = (self.wins as f64, self.draws as f64, self.losses as f64);
+        let score = (wins + draws / 2.0) / games;
+        let variance =
+            (wins * (1.0 - score).powi(2) + draws * (0.5 - score).powi(2) + losses * score.powi(2))
+                / games;
+        (games, score, variance)
+    }
+
+    // The Elo difference of the first engine and the half-width of its 95%
+    // confidence interval, None while either engine has no points.
+    fn elo(&self) -> Option<(f64, f64)> {
+        let (games, score, variance) = self.stats();
+        if games == 0.0 || score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let margin = 1.96 * (variance / games).sqrt();
+        let elo = |score: f64| -400.0 * (1.0 / score - 1.0).log10();
+        let low = elo((score - margin).max(f64::EPSILON));
+        let high = elo((score + margin).min(1.0 - f64::EPSILON));
+        Some((elo(score), (high - low) / 2.0))
+    }
+
+    fn elo_text(&self) -> String {
+        match self.elo() {
+            Some((elo, margin)) => format!("{elo:+.1} +/- {margin:.1}"),
+            None => "-".to_string(),
+        }
+    }
+}
+
+// A sequential probability ratio test of the Elo difference being elo0 (H0)
+// against it being elo1 (H1).
+#[derive(Debug, Clone, Copy)]
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Sprt {
+    // The LLR below which H0 and above which H1 is accepted.
+    fn bounds(&self) -> (f64, f64) {
+        let lower = (self.beta / (1.0 - self.alpha)).ln();
+        let upper = ((1.0 - self.beta) / self.alpha).ln();
+        (lower, upper)
+    }
+
+    // The log-likelihood ratio of H1 to H0, with the mean score of the games
+    // approximated as normally distributed.
+    fn llr(&self, score: &Score) -> f64 {
+        let (games, mean, variance) = score.stats();
+        if games == 0.0 || variance <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        games * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    // Whether H1 is accepted, None while the test goes on.
+    fn accepts_h1(&self, score: &Score) -> Option<bool> {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+        (llr <= lower || llr >= upper).then_some(llr >= upper)
+    }
+}
+
+pub fn run(args: MatchArgs) -> io::Result<()> {
+    signals::install()?;
+    let openings = match &args.openings {
+        Some(path) => selfplay::read_book(path)?,
+        None => vec![Chess::default()],
+    };
+    let mut pgn = args
+        .pgn
+        .as_deref()
+        .map(File::create)
+        .transpose()?
+        .map(BufWriter::new);
+    let tc = match args.tc {
+        Some((base, increment)) => format!("{}+{}", base.as_secs_f64(), increment.as_secs_f64()),
+        None => "-".to_string(),
+    };
+    let concurrency = args.concurrency.max(1);
+    let sprt = args.elo0.zip(args.elo1).map(|(elo0, elo1)| Sprt {
+        elo0,
+        elo1,
+        alpha: args.alpha,
+        beta: args.beta,
+    });
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        info!(elo0 = sprt.elo0, elo1 = sprt.elo1, lower, upper, "SPRT");
+    }
+    // Set once the SPRT accepted a hypothesis.
+    let decided = AtomicBool::new(false);
+
+    let args = &args;
+    let (openings, decided) = (&openings, &decided);
+    let (score, games, verdict) =
+        thread::scope(|scope| -> io::Result<(Score, u64, Option<bool>)> {
+            let (tx, rx) = unbounded();
+            for worker in 0..concurrency {
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let engines = Engine::spawn(&args.engine1, &args.options1).and_then(|first| {
+                        Ok([first, Engine::spawn(&args.engine2, &args.options2)?])
+                    });
+                    let mut engines = match engines {
+                        Ok(engines) => engines,
+                        Err(err) => {
+                            warn!(%err, "engine failed");
+                            return;
+                        }
+                    };
+                    // Every worker plays every `concurrency`-th game, the games of
+                    // an opening one after the other.
+                    let games = (worker as u64..args.games).step_by(concurrency);
+                    let stopped = || signals::interrupted() || decided.load(Ordering::Relaxed);
+                    for game in games.take_while(|_| !stopped()) {
+                        let opening = &openings[(game / 2) as usize % openings.len()];
+                        let white = (game % 2) as usize;
+                        let result = play(&mut engines, white, opening, args);
+                        let failed = result.is_err();
+                        if tx.send((game, white, result)).is_err() || failed {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            // Games finished out of order wait for the earlier ones.
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            let mut score = Score::default();
+            let mut verdict = None;
+            'games: for (game, white, result) in rx {
+                pending.insert(game, (white, result?));
+                while let Some((white, finished)) = pending.remove(&next) {
+                    let first = if white == 0 {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    match finished.winner {
+                        Some(winner) if winner == first => score.wins += 1,
+                        Some(_) => score.losses += 1,
+                        None => score.draws += 1,
+                    }
+                    if let Some(pgn) = &mut pgn {
+                        finished.write_pgn(pgn, next + 1, &tc)?;
+                    }
+                    next += 1;
+                    let llr = sprt.map(|sprt| format!("{:.2}", sprt.llr(&score)));
+                    info!(
+                        game = next,
+                        white = %finished.white,
+                        black = %finished.black,
+                        result = finished.result(),
+                        reason = %finished.reason,
+                        wins = score.wins,
+                        draws = score.draws,
+                        losses = score.losses,
+                        llr = llr.as_deref(),
+                        "finished game"
+                    );
+                    verdict = sprt.and_then(|sprt| sprt.accepts_h1(&score));
+                    if verdict.is_some() {
+                        decided.store(true, Ordering::Relaxed);
+                        break 'games;
+                    }
+                }
+            }
+            Ok((score, next, verdict))
+        })?;
+    if let Some(pgn) = &mut pgn {
+        pgn.flush()?;
+    }
+    if games < args.games && verdict.is_none() {
+        warn!(games, "stopped before playing all games");
+    }
+    info!(
+        games,
+        wins = score.wins,
+        draws = score.draws,
+        losses = score.losses,
+        elo = %score.elo_text(),
+        "finished match"
+    );
+    if let Some(sprt) = sprt {
+        let result = match verdict {
+            Some(true) => "H1 accepted",
+            Some(false) => "H0 accepted",
+            None => "inconclusive",
+        };
+        let llr = format!("{:.2}", sprt.llr(&score));
+        info!(llr = %llr, result, "finished SPRT");
+    }
+    Ok(())
+}
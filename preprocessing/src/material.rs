@@ -0,0 +1,193 @@
+// Material signatures like KRPvKR, the pieces of both sides of a position,
+// for selecting endgames, e.g. for datasets and evaluation suites of a single
+// kind of endgame.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use shakmaty::{Board, Color, Piece, Role};
+
+use crate::sample::TrainingSample;
+
+// Order of the roles in the signatures, the king first.
+const DISPLAY_ORDER: [Role; 6] = [
+    Role::King,
+    Role::Queen,
+    Role::Rook,
+    Role::Bishop,
+    Role::Knight,
+    Role::Pawn,
+];
+
+/// Number of pieces of every role of a side, indexed by `Role as usize - 1`
+/// like the planes of the samples.
+pub type PieceCounts = [u32; 6];
+
+/// The pieces of the color on the board.
+pub fn piece_counts(board: &Board, color: Color) -> PieceCounts {
+    Role::ALL.map(|role| board.by_piece(role.of(color)).count() as u32)
+}
+
+/// Pieces of one side of a [`MaterialSignature`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SideMaterial {
+    pub counts: PieceCounts,
+    /// Whether the side may have more pieces than the listed ones.
+    pub partial: bool,
+}
+
+impl SideMaterial {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (pieces, partial) = match s.strip_suffix('*') {
+            Some(pieces) => (pieces, true),
+            None => (s, false),
+        };
+        let mut counts = [0; 6];
+        for c in pieces.chars() {
+            let piece = Piece::from_char(c.to_ascii_uppercase())
+                .ok_or_else(|| format!("invalid piece {c} in material {s}"))?;
+            counts[piece.role as usize - 1] += 1;
+        }
+        Ok(SideMaterial { counts, partial })
+    }
+
+    pub fn matches(&self, counts: &PieceCounts) -> bool {
+        counts
+            .iter()
+            .zip(self.counts)
+            .all(|(&count, expected)| count == expected || (self.partial && count > expected))
+    }
+
+    // Material in pawns, with the usual values of the pieces.
+    fn value(&self) -> u32 {
+        let values = [1, 3, 3, 5, 9, 0];
+        self.counts
+            .iter()
+            .zip(values)
+            .map(|(count, value)| count * value)
+            .sum()
+    }
+
+    // Orders the sides by their material, then by the number of pieces and
+    // then by the pieces in the order of the signature.
+    fn strength(&self) -> (u32, u32, [u32; 6]) {
+        let pieces = self.counts.iter().sum();
+        let counts = DISPLAY_ORDER.map(|role| self.counts[role as usize - 1]);
+        (self.value(), pieces, counts)
+    }
+}
+
+impl fmt::Display for SideMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for role in DISPLAY_ORDER {
+            for _ in 0..self.counts[role as usize - 1] {
+                write!(f, "{}", role.upper_char())?;
+            }
+        }
+        if self.partial {
+            write!(f, "*")?;
+        }
+        Ok(())
+    }
+}
+
+/// The pieces of both sides separated by v, e.g. KRPvKR. A side ending with *
+/// may have more pieces than the listed ones, e.g. KQ*vK*. The signatures are
+/// case-insensitive and their [`normalized`](MaterialSignature::normalized)
+/// form has the stronger side first.
+///
+/// ```
+/// use preprocessing::material::MaterialSignature;
+///
+/// let signature: MaterialSignature = "krvkpr".parse()?;
+/// assert_eq!(signature.to_string(), "KRvKRP");
+/// assert_eq!(signature.normalized().to_string(), "KRPvKR");
+/// assert_eq!(signature.normalized(), "KRPvKR".parse::<MaterialSignature>()?);
+/// assert!("KRPKR".parse::<MaterialSignature>().is_err());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaterialSignature {
+    pub sides: [SideMaterial; 2],
+}
+
+impl MaterialSignature {
+    /// The signature with the side with more material first.
+    pub fn normalized(self) -> Self {
+        let [first, second] = self.sides;
+        if second.strength() > first.strength() {
+            MaterialSignature {
+                sides: [second, first],
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Whether the first side of the signature has the pieces `first` and the
+    /// second one the pieces `second`.
+    pub fn matches_sides(&self, first: &PieceCounts, second: &PieceCounts) -> bool {
+        self.sides[0].matches(first) && self.sides[1].matches(second)
+    }
+
+    /// Whether either side of the position of the sample has the pieces of
+    /// the first side of the signature and the other side the pieces of the
+    /// second one.
+    ///
+    /// ```
+    /// use preprocessing::material::MaterialSignature;
+    /// use preprocessing::record::RECORD_SIZE;
+    /// use preprocessing::{DecodeOptions, TrainingSample};
+    ///
+    /// let mut record = vec![0; RECORD_SIZE];
+    /// record[0] = 6;
+    /// let mut sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+    /// // The side to move has a king and a rook, the opponent a king and a
+    /// // bishop.
+    /// sample.bitboards[5] = 1 << 4;
+    /// sample.bitboards[3] = 1 << 0;
+    /// sample.bitboards[11] = 1 << 60;
+    /// sample.bitboards[8] = 1 << 58;
+    ///
+    /// let signature = |s: &str| s.parse::<MaterialSignature>().unwrap();
+    /// assert!(signature("KRvKB").matches(&sample));
+    /// assert!(signature("KBvKR").matches(&sample));
+    /// assert!(signature("KR*vK*").matches(&sample));
+    /// assert!(!signature("KRvK").matches(&sample));
+    /// # Ok::<(), preprocessing::PreprocessError>(())
+    /// ```
+    pub fn matches(&self, sample: &TrainingSample) -> bool {
+        let us: PieceCounts = std::array::from_fn(|idx| sample.bitboards[idx].count_ones());
+        let them: PieceCounts = std::array::from_fn(|idx| sample.bitboards[6 + idx].count_ones());
+        self.matches_sides(&us, &them) || self.matches_sides(&them, &us)
+    }
+}
+
+impl FromStr for MaterialSignature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, second) = s
+            .split_once(['v', 'V'])
+            .ok_or_else(|| format!("invalid material: {s}"))?;
+        Ok(MaterialSignature {
+            sides: [SideMaterial::parse(first)?, SideMaterial::parse(second)?],
+        })
+    }
+}
+
+impl TryFrom<String> for MaterialSignature {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for MaterialSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.sides[0], self.sides[1])
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::{Condvar, Mutex};
+
+// Global limit on the memory used by the games in flight between the reader
+// and the writer of the run and by the positions of the existing shards
+// (`--memory-limit`).
+//
+// The reader reserves the memory of every game before passing it on and the
+// writer releases it once the game is written. Reserving blocks until enough
+// memory is freed, so exceeding the budget slows the run down instead of
+// running out of memory. The positions of the existing shards are held for
+// the whole run, the games only get the rest. A writer that fails interrupts the budget, which
+// stops the reader instead of waiting for memory that is never released.
+pub struct MemoryBudget {
+    limit: Option<u64>,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+struct State {
+    used: u64,
+    // Part of `used` held for the whole run.
+    held: u64,
+    interrupted: bool,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        MemoryBudget {
+            limit,
+            state: Mutex::new(State {
+                used: 0,
+                held: 0,
+                interrupted: false,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    // Waits until `bytes` fit into the budget, returns false without
+    // reserving them if the budget is interrupted in the meantime. A single
+    // reservation larger than the rest of the budget is granted once nothing
+    // else but the held memory is reserved, otherwise it would never be.
+    pub fn reserve(&self, bytes: u64) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let mut state = self.state.lock().unwrap();
+        while !state.interrupted && state.used > state.held && state.used + bytes > limit {
+            state = self.released.wait(state).unwrap();
+        }
+        if state.interrupted {
+            return false;
+        }
+        state.used += bytes;
+        true
+    }
+
+    pub fn release(&self, bytes: u64) {
+        if self.limit.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.used -= bytes;
+        self.released.notify_all();
+    }
+
+    // Reserves `bytes` without waiting until `release_held`.
+    pub fn hold(&self, bytes: u64) {
+        if self.limit.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.used += bytes;
+        state.held += bytes;
+    }
+
+    pub fn release_held(&self, bytes: u64) {
+        if self.limit.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.held -= bytes;
+        state.used -= bytes;
+        self.released.notify_all();
+    }
+
+    // Fails the waiting and all later reservations, once the memory is no
+    // longer released.
+    pub fn interrupt(&self) {
+        self.state.lock().unwrap().interrupted = true;
+        self.released.notify_all();
+    }
+}
+
+// Parses sizes like `512M`, `4G`, `1.5GiB` or plain numbers of bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {s}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown size unit: {unit}")),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
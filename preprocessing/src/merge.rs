@@ -0,0 +1,111 @@
+// Merging of preprocessed datasets into one: the samples of the inputs are
+// written to fresh shards in the order of the inputs, keeping the first sample
+// of every position (ignoring the move counters, like compare). Shards with
+// the same contents, e.g. a dataset given twice or a shard copied into two
+// datasets, are read once.
+//
+// The manifests of the inputs (see manifest) are merged into a fresh one, so
+// that preprocess with the merged dataset as the output directory skips the
+// archives that are already in it. All of them are listed with all shards.
+//
+// The hashes of the kept positions are held in memory, 8 bytes each.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::output::{self, OutputSample, ShardWriter};
+use tracing::{info, info_span};
+
+use crate::manifest::Manifest;
+use crate::pipeline::position_hash;
+use crate::report::shard_reports;
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// Preprocessed outputs to merge (directories with shards or single
+    /// shards), the samples of the earlier ones are kept
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write the merged shards to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Maximum number of samples in a single output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+
+    /// Keep the samples of positions that were seen before
+    #[arg(long)]
+    keep_duplicates: bool,
+}
+
+// Fails if writing the output would overwrite one of the inputs.
+pub fn check_inputs(inputs: &[PathBuf], output_dir: &Path) -> io::Result<()> {
+    let Ok(output_dir) = fs::canonicalize(output_dir) else {
+        return Ok(());
+    };
+    for input in inputs {
+        if fs::canonicalize(input)?.starts_with(&output_dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is in the output directory", input.display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: MergeArgs) -> io::Result<()> {
+    check_inputs(&args.inputs, &args.output_dir)?;
+
+    let mut writer = ShardWriter::create(&args.output_dir, args.shard_size)?;
+    let mut manifest = Manifest::default();
+    let mut merged_shards = HashSet::new();
+    let mut positions = HashSet::new();
+    let (mut samples, mut duplicates, mut skipped_shards) = (0u64, 0u64, 0);
+
+    for input in &args.inputs {
+        let _span = info_span!("dataset", path = %input.display()).entered();
+        for shard in shard_reports(input)? {
+            if !merged_shards.insert(shard.sha256) {
+                info!(shard = %shard.path, "skipping shard that was merged already");
+                skipped_shards += 1;
+                continue;
+            }
+            output::for_each_line(Path::new(&shard.path), |line| {
+                let sample = OutputSample::parse(line)?;
+                samples += 1;
+                if !positions.insert(position_hash(&sample)) && !args.keep_duplicates {
+                    duplicates += 1;
+                    return Ok(());
+                }
+                writer.write_sample(line)
+            })?;
+        }
+        if input.is_dir() {
+            for entry in Manifest::load(input.join("manifest"))?.entries {
+                if !manifest.contains(&entry.sha256) {
+                    manifest.entries.push(entry);
+                }
+            }
+        }
+    }
+
+    let last_shard = writer.flush()?.index;
+    for entry in &mut manifest.entries {
+        entry.shards = 0..=last_shard;
+    }
+    manifest.save(args.output_dir.join("manifest"))?;
+
+    println!(
+        "merged {} of {samples} samples into {} shards, dropped {duplicates} repeated \
+         positions and {skipped_shards} shards merged already",
+        samples - duplicates,
+        last_shard + 1
+    );
+    Ok(())
+}
@@ -0,0 +1,172 @@
+// Prometheus metrics of a preprocessing run (`--metrics-addr`), so that runs
+// lasting for days can be monitored and alerted on like other batch jobs. The
+// counters of the progress bar are served at /metrics in the text exposition
+// format: the numbers of archives, bytes, games and samples read, of the kept
+// samples and of the samples dropped by every filter, the errors, the output
+// shards and the average throughput. `rate()` over the counters gives the
+// current throughput. Every run of pipeline::run starts from zero, the batches
+// of `--watch` show as counter resets.
+//
+// A single thread answers the scrapes one at a time, which is plenty for the
+// few scrapers of a job.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use preprocessing::Filter;
+use tracing::{debug, info};
+
+use crate::progress::Counters;
+
+// Scrapers that do not send their request in time are dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Counters of the current run and its start.
+static CURRENT: Mutex<Option<(Arc<Counters>, Instant)>> = Mutex::new(None);
+
+// Serves the counters of a run that is starting, until the next one starts.
+pub fn track(counters: Arc<Counters>) {
+    let mut current = CURRENT
+        .lock()
+        .expect("no thread panicked with the counters");
+    *current = Some((counters, Instant::now()));
+}
+
+// Starts serving the counters in the background until the process exits.
+pub fn serve(addr: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!(addr = %listener.local_addr()?, "serving metrics");
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(respond);
+                if let Err(err) = result {
+                    debug!(%err, "failed to answer a metrics request");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers are ignored.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if path == "/metrics" {
+        let current = CURRENT
+            .lock()
+            .expect("no thread panicked with the counters")
+            .clone();
+        let body = match current {
+            Some((counters, started)) => render(&counters, started.elapsed()),
+            None => render(&Counters::default(), Duration::ZERO),
+        };
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", "not found, see /metrics\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+fn render(counters: &Counters, elapsed: Duration) -> String {
+    let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = writeln!(out, "# HELP attix_preprocess_{name} {help}");
+        let _ = writeln!(out, "# TYPE attix_preprocess_{name} {kind}");
+        let _ = writeln!(out, "attix_preprocess_{name} {value}");
+    };
+    let counter = |counter: &AtomicU64| get(counter) as f64;
+    metric(
+        "archives_total",
+        "counter",
+        "Input archives started.",
+        counter(&counters.archives),
+    );
+    metric(
+        "input_bytes_total",
+        "counter",
+        "Position within the input archives in bytes.",
+        counter(&counters.bytes_read),
+    );
+    metric(
+        "games_total",
+        "counter",
+        "Games decoded.",
+        counter(&counters.games),
+    );
+    metric(
+        "samples_total",
+        "counter",
+        "Samples decoded.",
+        counter(&counters.samples),
+    );
+    metric(
+        "samples_kept_total",
+        "counter",
+        "Samples passing the filters.",
+        counter(&counters.kept),
+    );
+    metric(
+        "errors_total",
+        "counter",
+        "Unreadable archives and games, which were skipped.",
+        counter(&counters.errors),
+    );
+    metric(
+        "shards",
+        "gauge",
+        "Shards in the output directory.",
+        counter(&counters.shards),
+    );
+    let secs = elapsed.as_secs_f64();
+    metric(
+        "samples_per_second",
+        "gauge",
+        "Samples decoded per second since the start of the run.",
+        if secs > 0.0 {
+            counter(&counters.samples) / secs
+        } else {
+            0.0
+        },
+    );
+    metric(
+        "run_seconds",
+        "gauge",
+        "Time since the start of the run.",
+        secs,
+    );
+
+    let name = "attix_preprocess_samples_filtered_total";
+    let _ = writeln!(out, "# HELP {name} Samples dropped by the filter.");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for filter in Filter::ALL {
+        let _ = writeln!(
+            out,
+            "{name}{{filter=\"{}\"}} {}",
+            filter.name(),
+            get(&counters.filtered[filter as usize])
+        );
+    }
+    out
+}
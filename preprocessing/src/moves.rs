@@ -0,0 +1,83 @@
+// Conversion between the moves of the lc0 policy head and shakmaty moves.
+//
+// The policy is indexed from the perspective of the side to move, so the moves
+// of black are flipped vertically. Knight promotions are encoded without the
+// promotion suffix and castling as the king capturing its rook.
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Color, Move, Position, Role};
+
+use crate::IDX_TO_MOVE;
+
+/// Reverse of [`IDX_TO_MOVE`]: index of the move in lc0 UCI notation.
+pub static MOVE_TO_IDX: LazyLock<HashMap<&'static str, u16>> = LazyLock::new(|| {
+    IDX_TO_MOVE
+        .iter()
+        .enumerate()
+        .map(|(idx, &uci)| (uci, idx as u16))
+        .collect()
+});
+
+fn to_lc0_uci(m: &Move, mode: CastlingMode, turn: Color) -> Option<String> {
+    let UciMove::Normal {
+        from,
+        to,
+        promotion,
+    } = m.to_uci(mode)
+    else {
+        return None;
+    };
+    let (from, to) = match turn {
+        Color::White => (from, to),
+        Color::Black => (from.flip_vertical(), to.flip_vertical()),
+    };
+    let mut uci = format!("{from}{to}");
+    if let Some(role) = promotion.filter(|&role| role != Role::Knight) {
+        uci.push(role.char());
+    }
+    Some(uci)
+}
+
+/// Index of the move played by `turn` in the policy head. Drops have no index.
+///
+/// ```
+/// use preprocessing::moves::{idx_from_move, MOVE_TO_IDX};
+/// use shakmaty::{Color, Move, Role, Square};
+///
+/// let m = Move::Normal {
+///     role: Role::Pawn,
+///     from: Square::E7,
+///     capture: None,
+///     to: Square::E5,
+///     promotion: None,
+/// };
+/// assert_eq!(idx_from_move(&m, Color::Black), Some(MOVE_TO_IDX["e2e4"]));
+/// ```
+pub fn idx_from_move(m: &Move, turn: Color) -> Option<u16> {
+    let uci = to_lc0_uci(m, CastlingMode::Chess960, turn)?;
+    MOVE_TO_IDX.get(uci.as_str()).copied()
+}
+
+/// The legal move of the position with the given index in the policy head.
+/// Castling is also recognized in the standard notation of the king moving two
+/// squares.
+///
+/// ```
+/// use preprocessing::moves::{move_from_idx, MOVE_TO_IDX};
+/// use shakmaty::{Chess, Square};
+///
+/// let m = move_from_idx(MOVE_TO_IDX["g1f3"], &Chess::default()).unwrap();
+/// assert_eq!(m.to(), Square::F3);
+/// assert_eq!(move_from_idx(MOVE_TO_IDX["e2e5"], &Chess::default()), None);
+/// ```
+pub fn move_from_idx<P: Position>(idx: u16, pos: &P) -> Option<Move> {
+    let uci = *IDX_TO_MOVE.get(idx as usize)?;
+    pos.legal_moves().into_iter().find(|m| {
+        [CastlingMode::Chess960, CastlingMode::Standard]
+            .into_iter()
+            .any(|mode| to_lc0_uci(m, mode, pos.turn()).as_deref() == Some(uci))
+    })
+}
@@ -0,0 +1,441 @@
+// Tools for the networks trained by the train command (`net`).
+//
+// `net average` averages the weights of several checkpoints of a run (SWA,
+// stochastic weight averaging), which usually gives a stronger network than
+// the last checkpoint alone. The checkpoints are given as safetensors files
+// or as training output directories, whose last --last step checkpoints are
+// taken. All checkpoints must have the same tensors with the same shapes.
+//
+// `net eval` runs a network over holdout data and compares its predictions
+// with the search results of the samples: the mean absolute and squared
+// error of the predicted expected score (-1 to 1, like best_q), the Pearson
+// correlation with best_q and the accuracy of the predicted outcome. The
+// outcome of a sample is the most likely one of the win, draw and loss
+// probabilities given by best_q and best_d, the predicted outcome is a win or
+// a loss if the predicted score is beyond +-1/3 and a draw otherwise. The
+// metrics are reported for all samples and broken down by game phase and by
+// the number of pieces on the board. The networks of the train command have
+// no policy head, so there is no policy accuracy to report.
+//
+// `net embed` writes the activations of the last hidden layer of a network
+// for every sample, for clustering the positions, searching for similar ones
+// and finding near duplicates of the dataset. Every line has the Polyglot hash
+// of the position (see polyglot), the FEN and the activations, separated by
+// tabs. Samples of the same position have the same hash and embedding.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use preprocessing::nnue::FeatureSet;
+use preprocessing::output::{self, OutputSample};
+use preprocessing::phase::{self, Phase};
+use preprocessing::polyglot;
+use safetensors::tensor::TensorView;
+use safetensors::{Dtype, SafeTensors};
+use serde::Serialize;
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess};
+use tracing::info;
+
+use crate::create_output;
+use crate::quantize::{to_floats, FloatNetwork};
+
+// Width of the buckets of the number of pieces on the board.
+const PIECE_BUCKET: u32 = 4;
+
+#[derive(Args)]
+pub struct NetArgs {
+    #[command(subcommand)]
+    command: NetCommand,
+}
+
+#[derive(Subcommand)]
+enum NetCommand {
+    /// Average the weights of training checkpoints (SWA)
+    Average(AverageArgs),
+    /// Report the accuracy of a trained network on holdout data
+    Eval(EvalArgs),
+    /// Write the activations of the last hidden layer for the positions
+    Embed(EmbedArgs),
+}
+
+#[derive(Args)]
+struct AverageArgs {
+    /// Checkpoints (.safetensors) or training output directories
+    #[arg(required = true)]
+    checkpoints: Vec<PathBuf>,
+
+    /// Number of the latest step checkpoints taken from every directory
+    #[arg(long, default_value_t = 5)]
+    last: usize,
+
+    /// Path to write the averaged weights to (.safetensors)
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct EvalArgs {
+    /// Float weights written by the train command (.safetensors)
+    #[arg(long)]
+    weights: PathBuf,
+
+    /// Input features the network was trained with
+    #[arg(long, value_enum, default_value = "halfkp")]
+    features: FeatureSet,
+
+    /// Preprocessed holdout data (directory with shards or a single shard)
+    #[arg(long)]
+    data: PathBuf,
+
+    /// Evaluate at most this many samples
+    #[arg(long)]
+    limit: Option<u64>,
+
+    /// Also write the metrics as JSON to this path ("-" for stdout)
+    #[arg(long)]
+    json: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct EmbedArgs {
+    /// Float weights written by the train command (.safetensors)
+    #[arg(long)]
+    weights: PathBuf,
+
+    /// Input features the network was trained with
+    #[arg(long, value_enum, default_value = "halfkp")]
+    features: FeatureSet,
+
+    /// Preprocessed data (directory with shards or a single shard)
+    #[arg(long)]
+    data: PathBuf,
+
+    /// Embed at most this many samples
+    #[arg(long)]
+    limit: Option<u64>,
+
+    /// Path to write the embeddings to ("-" for stdout)
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+// The step checkpoints of the directory, the latest `last` ones in the order
+// of their steps.
+fn step_checkpoints(dir: &Path, last: usize) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if name.starts_with("step-") && name.ends_with(".safetensors") {
+            paths.push(path);
+        }
+    }
+    // The steps are zero-padded, see train.
+    paths.sort();
+    Ok(paths.split_off(paths.len().saturating_sub(last)))
+}
+
+fn average(args: AverageArgs) -> io::Result<()> {
+    let mut paths = Vec::new();
+    for path in &args.checkpoints {
+        if path.is_dir() {
+            paths.extend(step_checkpoints(path, args.last)?);
+        } else {
+            paths.push(path.clone());
+        }
+    }
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no checkpoints to average",
+        ));
+    }
+
+    let mut sums: BTreeMap<String, (Vec<usize>, Vec<f64>)> = BTreeMap::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let invalid = |message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {message}", path.display()),
+            )
+        };
+        let data = fs::read(path)?;
+        let tensors = SafeTensors::deserialize(&data).map_err(io::Error::other)?;
+        if idx > 0 && tensors.len() != sums.len() {
+            return Err(invalid(
+                "the checkpoints have different tensors".to_string(),
+            ));
+        }
+        for (name, view) in tensors.tensors() {
+            let (shape, values) = to_floats(&view)?;
+            match sums.get_mut(&name) {
+                Some((sum_shape, sum)) if *sum_shape == shape => {
+                    sum.iter_mut().zip(values).for_each(|(s, v)| *s += v as f64);
+                }
+                Some(_) => return Err(invalid(format!("tensor {name} has a different shape"))),
+                None if idx > 0 => return Err(invalid(format!("unexpected tensor {name}"))),
+                None => {
+                    let values = values.into_iter().map(f64::from).collect();
+                    sums.insert(name, (shape, values));
+                }
+            }
+        }
+        info!(path = %path.display(), "loaded checkpoint");
+    }
+
+    let n = paths.len() as f64;
+    let averaged: Vec<(String, Vec<usize>, Vec<u8>)> = sums
+        .into_iter()
+        .map(|(name, (shape, sum))| {
+            let bytes = sum
+                .iter()
+                .flat_map(|s| ((s / n) as f32).to_le_bytes())
+                .collect();
+            (name, shape, bytes)
+        })
+        .collect();
+    let views = averaged
+        .iter()
+        .map(|(name, shape, bytes)| {
+            let view = TensorView::new(Dtype::F32, shape.clone(), bytes);
+            Ok((name.as_str(), view.map_err(io::Error::other)?))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    safetensors::serialize_to_file(views, &None, &args.output).map_err(io::Error::other)?;
+    info!(
+        checkpoints = paths.len(),
+        path = %args.output.display(),
+        "saved averaged weights"
+    );
+    Ok(())
+}
+
+// Win, draw or loss.
+fn outcome(win: f32, draw: f32, loss: f32) -> usize {
+    if win >= draw && win >= loss {
+        0
+    } else if draw >= loss {
+        1
+    } else {
+        2
+    }
+}
+
+// Sums of the errors of a group of samples.
+#[derive(Debug, Default)]
+struct Metrics {
+    samples: u64,
+    abs_error: f64,
+    squared_error: f64,
+    correct_outcomes: u64,
+    // Sums of the predictions x and the targets y for the correlation.
+    x: f64,
+    y: f64,
+    xx: f64,
+    yy: f64,
+    xy: f64,
+}
+
+impl Metrics {
+    fn add(&mut self, predicted: f32, best_q: f32, best_d: f32) {
+        let error = (predicted - best_q) as f64;
+        self.samples += 1;
+        self.abs_error += error.abs();
+        self.squared_error += error * error;
+        let target = outcome(
+            (1.0 + best_q - best_d) / 2.0,
+            best_d,
+            (1.0 - best_q - best_d) / 2.0,
+        );
+        let third = 1.0 / 3.0;
+        let prediction = outcome(predicted - third, 0.0, -predicted - third);
+        self.correct_outcomes += (target == prediction) as u64;
+        let (x, y) = (predicted as f64, best_q as f64);
+        self.x += x;
+        self.y += y;
+        self.xx += x * x;
+        self.yy += y * y;
+        self.xy += x * y;
+    }
+
+    fn mae(&self) -> f64 {
+        self.abs_error / self.samples.max(1) as f64
+    }
+
+    fn mse(&self) -> f64 {
+        self.squared_error / self.samples.max(1) as f64
+    }
+
+    fn outcome_accuracy(&self) -> f64 {
+        self.correct_outcomes as f64 / self.samples.max(1) as f64
+    }
+
+    // Pearson correlation, 0 if either side is constant.
+    fn correlation(&self) -> f64 {
+        let n = self.samples as f64;
+        let covariance = n * self.xy - self.x * self.y;
+        let variance = (n * self.xx - self.x * self.x) * (n * self.yy - self.y * self.y);
+        if variance > 0.0 {
+            covariance / variance.sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GroupReport<'a> {
+    group: &'a str,
+    samples: u64,
+    mae: f64,
+    mse: f64,
+    outcome_accuracy: f64,
+    correlation: f64,
+}
+
+// Metrics of all samples and of the groups they fall into.
+#[derive(Default)]
+struct Evaluation {
+    groups: BTreeMap<String, Metrics>,
+}
+
+impl Evaluation {
+    fn add(&mut self, groups: [String; 3], predicted: f32, best_q: f32, best_d: f32) {
+        for group in groups {
+            self.groups
+                .entry(group)
+                .or_default()
+                .add(predicted, best_q, best_d);
+        }
+    }
+
+    fn reports(&self) -> Vec<GroupReport<'_>> {
+        self.groups
+            .iter()
+            .map(|(group, metrics)| GroupReport {
+                group,
+                samples: metrics.samples,
+                mae: metrics.mae(),
+                mse: metrics.mse(),
+                outcome_accuracy: metrics.outcome_accuracy(),
+                correlation: metrics.correlation(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>12} {:>8} {:>8} {:>8} {:>8}",
+            "group", "samples", "MAE", "MSE", "WDL acc", "corr"
+        )?;
+        for report in self.reports() {
+            write!(
+                f,
+                "\n{:<20} {:>12} {:>8.4} {:>8.4} {:>7.2}% {:>8.4}",
+                report.group,
+                report.samples,
+                report.mae,
+                report.mse,
+                100.0 * report.outcome_accuracy,
+                report.correlation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn eval(args: EvalArgs) -> io::Result<()> {
+    let network = FloatNetwork::load(&args.weights, args.features)?;
+    let limit = args.limit.unwrap_or(u64::MAX);
+    let mut evaluation = Evaluation::default();
+    let mut samples = 0;
+    output::for_each_line(&args.data, |line| {
+        if samples >= limit {
+            return Ok(());
+        }
+        samples += 1;
+        let sample = OutputSample::parse(line)?;
+        let fen: Fen = sample
+            .fen
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let board = fen.into_setup().board;
+        // Expected score of the side to move from -1 to 1.
+        let predicted = 2.0 / (1.0 + (-network.evaluate(&board)).exp()) - 1.0;
+        let phase = Phase::from_score(phase::board_score(&board));
+        let pieces = board.occupied().count() as u32 / PIECE_BUCKET * PIECE_BUCKET;
+        let groups = [
+            "all".to_string(),
+            format!("phase {phase:?}").to_lowercase(),
+            format!("pieces {:02}-{:02}", pieces, pieces + PIECE_BUCKET - 1),
+        ];
+        evaluation.add(groups, predicted, sample.best_q, sample.best_d);
+        Ok(())
+    })?;
+    if samples == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no samples in {}", args.data.display()),
+        ));
+    }
+
+    println!("{evaluation}");
+    if let Some(path) = args.json {
+        serde_json::to_writer_pretty(create_output(&path)?, &evaluation.reports())
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn embed(args: EmbedArgs) -> io::Result<()> {
+    let network = FloatNetwork::load(&args.weights, args.features)?;
+    let limit = args.limit.unwrap_or(u64::MAX);
+    let mut out = create_output(&args.output)?;
+    let mut samples = 0;
+    output::for_each_line(&args.data, |line| {
+        if samples >= limit {
+            return Ok(());
+        }
+        samples += 1;
+        let sample = OutputSample::parse(line)?;
+        let invalid = |err: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {err}", sample.fen))
+        };
+        let fen: Fen = sample
+            .fen
+            .parse()
+            .map_err(|err| invalid(format!("{err}")))?;
+        let embedding = network.embedding(&fen.as_setup().board);
+        let mode = CastlingMode::detect(fen.as_setup());
+        let pos: Chess = fen
+            .into_position(mode)
+            .map_err(|err| invalid(format!("{err}")))?;
+        write!(out, "{:016x}\t{}", polyglot::key(&pos), sample.fen)?;
+        for value in embedding {
+            write!(out, "\t{value}")?;
+        }
+        writeln!(out)
+    })?;
+    out.flush()?;
+    info!(samples, path = %args.output.display(), "wrote embeddings");
+    Ok(())
+}
+
+pub fn run(args: NetArgs) -> io::Result<()> {
+    match args.command {
+        NetCommand::Average(args) => average(args),
+        NetCommand::Eval(args) => eval(args),
+        NetCommand::Embed(args) => embed(args),
+    }
+}
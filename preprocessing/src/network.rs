@@ -0,0 +1,342 @@
+// Binary file format of the quantized networks loaded by the engine.
+//
+// All integers are little-endian. The header describes the architecture, so
+// that the engine can evaluate networks of any size without recompilation:
+//
+//     magic              b"ATXN"
+//     version            u32, currently 1
+//     feature set        u8, 0 for HalfKP and 1 for HalfKAv2_hm
+//     accumulator size   u32
+//     activation scale   i32, see nnue::ACTIVATION_SCALE
+//     number of layers   u32
+//     for every layer:   inputs u32, outputs u32, shift u32
+//
+// followed by the weights: the biases (i16) and the weights (i16, one row per
+// feature) of the feature transformer, then the biases (i32) and the weights
+// (i8, one row per output) of every dense layer. Nothing may follow.
+//
+// The .nnue files of Stockfish 12 (HalfKP 2x256-32-32-1) are read as well.
+// They start with the version and a hash of the architecture, followed by a
+// description, and the hashes of the feature transformer and of the dense
+// layers precede their weights. The layout of the weights and the features is
+// the one of attix, except that the output of Stockfish is in its internal
+// units (208 to a pawn in the endgame, scaled by 16), which are converted to
+// the logit of attix by rescaling the weights of the output layer. The
+// rescaled weights are rounded to 8 bits again, so the evaluations differ
+// from the ones of Stockfish by a few centipawns.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::nnue::{DenseLayer, FeatureSet, FeatureTransformer, Network, ACTIVATION_SCALE};
+
+pub const MAGIC: [u8; 4] = *b"ATXN";
+pub const VERSION: u32 = 1;
+
+// Generous bounds rejecting corrupted headers before allocating the weights.
+const MAX_ACCUMULATOR_SIZE: u32 = 1 << 14;
+const MAX_LAYERS: u32 = 16;
+const MAX_LAYER_SIZE: u32 = 1 << 15;
+
+// Stockfish networks of other architectures share the high bytes of the
+// version, which tells them apart from arbitrary files.
+const STOCKFISH_VERSION: u32 = 0x7AF3_2F16;
+const STOCKFISH_HALF_DIMENSIONS: usize = 256;
+const STOCKFISH_HIDDEN: usize = 32;
+const STOCKFISH_SHIFT: u32 = 6;
+// The output of Stockfish in centipawns is `raw / 16 * 100 / 208`, the one of
+// attix `raw / (ACTIVATION_SCALE << shift) * 400 / ln(10)`.
+const STOCKFISH_OUTPUT_SHIFT: u32 = 5;
+const STOCKFISH_OUTPUT_SCALE: f64 = (ACTIVATION_SCALE << STOCKFISH_OUTPUT_SHIFT) as f64 * 100.0
+    / (16.0 * 208.0)
+    / (400.0 / std::f64::consts::LN_10);
+const MAX_DESCRIPTION: u32 = 1 << 16;
+
+// Hashes of the layers as computed by Stockfish, each one of them depends on
+// the hash of its input.
+const fn affine_hash(outputs: u32, input: u32) -> u32 {
+    0xCC03_DAE4u32.wrapping_add(outputs) ^ (input >> 1) ^ (input << 31)
+}
+
+const fn clipped_relu_hash(input: u32) -> u32 {
+    0x538D_24C7u32.wrapping_add(input)
+}
+
+const STOCKFISH_TRANSFORMER_HASH: u32 = 0x5D69_D5B8 ^ (2 * STOCKFISH_HALF_DIMENSIONS) as u32;
+const STOCKFISH_LAYERS_HASH: u32 = {
+    let input = 0xEC42_E90D ^ (2 * STOCKFISH_HALF_DIMENSIONS) as u32;
+    let hidden = clipped_relu_hash(affine_hash(STOCKFISH_HIDDEN as u32, input));
+    let hidden = clipped_relu_hash(affine_hash(STOCKFISH_HIDDEN as u32, hidden));
+    affine_hash(1, hidden)
+};
+const STOCKFISH_HASH: u32 = STOCKFISH_TRANSFORMER_HASH ^ STOCKFISH_LAYERS_HASH;
+// The hash of the networks of Stockfish 12.
+const _: () = assert!(STOCKFISH_HASH == 0x3E5A_A6EE);
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn feature_set_id(set: FeatureSet) -> u8 {
+    match set {
+        FeatureSet::HalfKp => 0,
+        FeatureSet::HalfKaV2Hm => 1,
+    }
+}
+
+/// Writes the network in the attix format.
+///
+/// ```
+/// use preprocessing::network;
+/// use preprocessing::nnue::{DenseLayer, FeatureSet, FeatureTransformer, Network};
+///
+/// let set = FeatureSet::HalfKp;
+/// let network = Network {
+///     transformer: FeatureTransformer::new(set, vec![0], vec![1; set.dimensions()]),
+///     layers: vec![DenseLayer {
+///         inputs: 2,
+///         outputs: 1,
+///         weights: vec![3, -3],
+///         biases: vec![5],
+///         shift: 6,
+///     }],
+/// };
+/// let mut bytes = Vec::new();
+/// network::write(&network, &mut bytes)?;
+/// assert_eq!(&bytes[..4], b"ATXN");
+///
+/// let loaded = network::read(bytes.as_slice())?;
+/// assert_eq!(loaded.transformer.weights(), network.transformer.weights());
+/// assert_eq!(loaded.layers[0].weights, [3, -3]);
+/// assert_eq!(loaded.layers[0].shift, 6);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn write<W: Write>(network: &Network, mut writer: W) -> io::Result<()> {
+    let transformer = &network.transformer;
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&[feature_set_id(transformer.set())])?;
+    writer.write_all(&(transformer.size() as u32).to_le_bytes())?;
+    writer.write_all(&ACTIVATION_SCALE.to_le_bytes())?;
+    writer.write_all(&(network.layers.len() as u32).to_le_bytes())?;
+    for layer in &network.layers {
+        for value in [layer.inputs as u32, layer.outputs as u32, layer.shift] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    for value in transformer.biases().iter().chain(transformer.weights()) {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for layer in &network.layers {
+        for bias in &layer.biases {
+            writer.write_all(&bias.to_le_bytes())?;
+        }
+        let weights: Vec<u8> = layer.weights.iter().map(|&w| w as u8).collect();
+        writer.write_all(&weights)?;
+    }
+    writer.flush()
+}
+
+/// Reads a network written by [`write`], checking that the layers fit
+/// together.
+pub fn read<R: Read>(mut reader: R) -> io::Result<Network> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not an attix network"));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "unsupported network version {version}, expected {VERSION}"
+        )));
+    }
+    let mut set = [0];
+    reader.read_exact(&mut set)?;
+    let set = match set[0] {
+        0 => FeatureSet::HalfKp,
+        1 => FeatureSet::HalfKaV2Hm,
+        id => return Err(invalid(format!("unknown feature set {id}"))),
+    };
+    let size = read_u32(&mut reader)?;
+    if size == 0 || size > MAX_ACCUMULATOR_SIZE {
+        return Err(invalid(format!("invalid accumulator size {size}")));
+    }
+    let scale = read_u32(&mut reader)? as i32;
+    if scale != ACTIVATION_SCALE {
+        return Err(invalid(format!(
+            "activation scale {scale} is not supported, expected {ACTIVATION_SCALE}"
+        )));
+    }
+    let num_layers = read_u32(&mut reader)?;
+    if num_layers == 0 || num_layers > MAX_LAYERS {
+        return Err(invalid(format!("invalid number of layers {num_layers}")));
+    }
+    let mut shapes = Vec::with_capacity(num_layers as usize);
+    let mut inputs = 2 * size;
+    for idx in 0..num_layers {
+        let layer_inputs = read_u32(&mut reader)?;
+        let outputs = read_u32(&mut reader)?;
+        let shift = read_u32(&mut reader)?;
+        if layer_inputs != inputs || outputs == 0 || outputs > MAX_LAYER_SIZE || shift >= 16 {
+            return Err(invalid(format!(
+                "layer {idx} of shape {layer_inputs}x{outputs} (shift {shift}) does not fit"
+            )));
+        }
+        shapes.push((layer_inputs as usize, outputs as usize, shift));
+        inputs = outputs;
+    }
+    if inputs != 1 {
+        return Err(invalid("the last layer must have a single output"));
+    }
+
+    let size = size as usize;
+    let biases = read_values(&mut reader, size, i16::from_le_bytes)?;
+    let weights = read_values(&mut reader, set.dimensions() * size, i16::from_le_bytes)?;
+    let transformer = FeatureTransformer::new(set, biases, weights);
+    let mut layers = Vec::with_capacity(shapes.len());
+    for (inputs, outputs, shift) in shapes {
+        let biases = read_values(&mut reader, outputs, i32::from_le_bytes)?;
+        let weights = read_values(&mut reader, inputs * outputs, i8::from_le_bytes)?;
+        layers.push(DenseLayer {
+            inputs,
+            outputs,
+            weights,
+            biases,
+            shift,
+        });
+    }
+    if reader.read(&mut [0])? != 0 {
+        return Err(invalid("unexpected data after the weights"));
+    }
+    Ok(Network {
+        transformer,
+        layers,
+    })
+}
+
+/// Reads a HalfKP network of Stockfish 12 in the .nnue format, checking its
+/// version and the hashes of its architecture.
+///
+/// ```
+/// use preprocessing::network;
+///
+/// // A network of a later version of Stockfish.
+/// let mut bytes = 0x7AF3_2F20u32.to_le_bytes().to_vec();
+/// bytes.extend([0; 64]);
+/// let err = network::read_stockfish(bytes.as_slice()).err().unwrap();
+/// assert_eq!(
+///     err.to_string(),
+///     "unsupported Stockfish network version 0x7af32f20, expected 0x7af32f16"
+/// );
+/// ```
+pub fn read_stockfish<R: Read>(mut reader: R) -> io::Result<Network> {
+    let version = read_u32(&mut reader)?;
+    if version != STOCKFISH_VERSION {
+        return Err(invalid(format!(
+            "unsupported Stockfish network version {version:#x}, expected {STOCKFISH_VERSION:#x}"
+        )));
+    }
+    let check_hash = |hash: u32, expected: u32, part: &str| {
+        if hash == expected {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "{part} hash {hash:#x} is not the one of HalfKP 2x256-32-32-1 ({expected:#x})"
+            )))
+        }
+    };
+    check_hash(read_u32(&mut reader)?, STOCKFISH_HASH, "architecture")?;
+    let description = read_u32(&mut reader)?;
+    if description > MAX_DESCRIPTION {
+        return Err(invalid(format!("invalid description length {description}")));
+    }
+    read_values(&mut reader, description as usize, u8::from_le_bytes)?;
+
+    check_hash(
+        read_u32(&mut reader)?,
+        STOCKFISH_TRANSFORMER_HASH,
+        "feature transformer",
+    )?;
+    let set = FeatureSet::HalfKp;
+    let size = STOCKFISH_HALF_DIMENSIONS;
+    let biases = read_values(&mut reader, size, i16::from_le_bytes)?;
+    let weights = read_values(&mut reader, set.dimensions() * size, i16::from_le_bytes)?;
+    let transformer = FeatureTransformer::new(set, biases, weights);
+
+    check_hash(read_u32(&mut reader)?, STOCKFISH_LAYERS_HASH, "layers")?;
+    let mut layers = Vec::with_capacity(3);
+    for (inputs, outputs) in [
+        (2 * size, STOCKFISH_HIDDEN),
+        (STOCKFISH_HIDDEN, STOCKFISH_HIDDEN),
+        (STOCKFISH_HIDDEN, 1),
+    ] {
+        let biases = read_values(&mut reader, outputs, i32::from_le_bytes)?;
+        let weights = read_values(&mut reader, inputs * outputs, i8::from_le_bytes)?;
+        layers.push(DenseLayer {
+            inputs,
+            outputs,
+            weights,
+            biases,
+            shift: STOCKFISH_SHIFT,
+        });
+    }
+    let output = layers.last_mut().expect("the network has layers");
+    output.shift = STOCKFISH_OUTPUT_SHIFT;
+    let rescale = |value: f64| (value * STOCKFISH_OUTPUT_SCALE).round();
+    output.biases[0] = rescale(output.biases[0] as f64) as i32;
+    for weight in &mut output.weights {
+        *weight = rescale(*weight as f64) as i8;
+    }
+    if reader.read(&mut [0])? != 0 {
+        return Err(invalid("unexpected data after the weights"));
+    }
+    Ok(Network {
+        transformer,
+        layers,
+    })
+}
+
+/// Reads the network from a file in the attix or the Stockfish format.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Network> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut head = [0; 4];
+    let network = reader.read_exact(&mut head).and_then(|()| {
+        let reader = head.as_slice().chain(reader);
+        if head == MAGIC {
+            read(reader)
+        } else if u32::from_le_bytes(head) >> 8 == STOCKFISH_VERSION >> 8 {
+            read_stockfish(reader)
+        } else {
+            Err(invalid("not an attix or Stockfish network"))
+        }
+    });
+    network.map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+}
+
+/// Writes the network to a file.
+pub fn save<P: AsRef<Path>>(network: &Network, path: P) -> io::Result<()> {
+    write(network, BufWriter::new(File::create(path)?))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_values<R: Read, T, const N: usize>(
+    reader: &mut R,
+    len: usize,
+    from_le_bytes: fn([u8; N]) -> T,
+) -> io::Result<Vec<T>> {
+    let mut bytes = vec![0; len * N];
+    reader.read_exact(&mut bytes)?;
+    let values = bytes
+        .chunks_exact(N)
+        .map(|chunk| from_le_bytes(chunk.try_into().unwrap()));
+    Ok(values.collect())
+}
@@ -0,0 +1,297 @@
+// Input features of NNUE networks as used by Stockfish.
+//
+// Every feature is a (king square, piece, square) triple seen from one of the
+// sides, so a position activates one sparse set of features per perspective.
+// The first layer (the feature transformer) sums the weights of the active
+// features, which is cheap to update incrementally as pieces move.
+//
+// HalfKP: the king of the perspective with every other non-king piece. The
+// board is rotated by 180 degrees for black.
+//
+// HalfKAv2_hm: the king with every piece, including both kings. The board is
+// flipped vertically for black and mirrored horizontally so that the king is
+// always on the files e-h, which leaves 32 king buckets.
+//
+// The quantized network follows the usual integer scheme: the activations of
+// the clipped ReLUs (0 to 1) are stored as 0 to ACTIVATION_SCALE in a byte,
+// the accumulator shares their scale and the weights of the dense layers are
+// scaled by a power of two chosen per layer.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use shakmaty::{Board, Color, Piece, Role, Square};
+
+// Piece-square planes per king square.
+const HALF_KP_PIECE_SQUARES: u32 = 10 * 64 + 1;
+const HALF_KA_PIECE_SQUARES: u32 = 11 * 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum FeatureSet {
+    #[value(name = "halfkp")]
+    #[serde(rename = "halfkp")]
+    HalfKp,
+    #[value(name = "halfkav2-hm")]
+    #[serde(rename = "halfkav2-hm")]
+    HalfKaV2Hm,
+}
+
+impl FeatureSet {
+    /// Number of distinct features.
+    pub fn dimensions(self) -> usize {
+        match self {
+            FeatureSet::HalfKp => 64 * HALF_KP_PIECE_SQUARES as usize,
+            FeatureSet::HalfKaV2Hm => 32 * HALF_KA_PIECE_SQUARES as usize,
+        }
+    }
+
+    /// Upper bound on the number of active features per perspective.
+    pub fn max_active_features(self) -> usize {
+        match self {
+            FeatureSet::HalfKp => 30,
+            FeatureSet::HalfKaV2Hm => 32,
+        }
+    }
+
+    /// Index of the feature of `piece` on `square` from the perspective of the
+    /// side with the king on `king`. HalfKP has no features for the kings.
+    pub fn feature(
+        self,
+        perspective: Color,
+        king: Square,
+        piece: Piece,
+        square: Square,
+    ) -> Option<u32> {
+        // Pieces of the perspective come first for every role.
+        let piece_plane = 2 * (piece.role as u32 - 1) + (piece.color != perspective) as u32;
+        match self {
+            FeatureSet::HalfKp => {
+                if piece.role == Role::King {
+                    return None;
+                }
+                let orient = |square: Square| match perspective {
+                    Color::White => square as u32,
+                    Color::Black => square as u32 ^ 63,
+                };
+                Some(orient(square) + 64 * piece_plane + 1 + HALF_KP_PIECE_SQUARES * orient(king))
+            }
+            FeatureSet::HalfKaV2Hm => {
+                let mut orientation = match perspective {
+                    Color::White => 0,
+                    Color::Black => 56,
+                };
+                if (king as u32 ^ orientation) % 8 < 4 {
+                    orientation ^= 7;
+                }
+                let king = king as u32 ^ orientation;
+                // Both kings share a plane.
+                let piece_plane = piece_plane.min(10);
+                // Buckets follow Stockfish: the 8th rank comes first and the
+                // files are numbered from the edge of the board.
+                let bucket = 4 * (7 - king / 8) + (7 - king % 8);
+                Some(
+                    (square as u32 ^ orientation)
+                        + 64 * piece_plane
+                        + HALF_KA_PIECE_SQUARES * bucket,
+                )
+            }
+        }
+    }
+
+    /// Active features of the position from the perspective of `perspective`.
+    ///
+    /// ```
+    /// use preprocessing::nnue::FeatureSet;
+    /// use shakmaty::{Chess, Color, Position};
+    ///
+    /// let board = Chess::default().board().clone();
+    /// for set in [FeatureSet::HalfKp, FeatureSet::HalfKaV2Hm] {
+    ///     let features = set.active_features(&board, Color::White);
+    ///     assert_eq!(features.len(), set.max_active_features());
+    ///     assert!(features.iter().all(|&idx| (idx as usize) < set.dimensions()));
+    /// }
+    ///
+    /// // The starting position is symmetric and HalfKAv2_hm flips the board.
+    /// let set = FeatureSet::HalfKaV2Hm;
+    /// assert_eq!(
+    ///     set.active_features(&board, Color::White),
+    ///     set.active_features(&board, Color::Black)
+    /// );
+    /// ```
+    pub fn active_features(self, board: &Board, perspective: Color) -> Vec<u32> {
+        let Some(king) = board.king_of(perspective) else {
+            return Vec::new();
+        };
+        let mut features: Vec<_> = board
+            .clone()
+            .into_iter()
+            .filter_map(|(square, piece)| self.feature(perspective, king, piece, square))
+            .collect();
+        features.sort_unstable();
+        features
+    }
+}
+
+/// First layer of the network: sums the weights of the active features of a
+/// perspective (the accumulator).
+pub struct FeatureTransformer {
+    set: FeatureSet,
+    biases: Vec<i16>,
+    // The weights of every feature are stored next to each other.
+    weights: Vec<i16>,
+}
+
+impl FeatureTransformer {
+    pub fn new(set: FeatureSet, biases: Vec<i16>, weights: Vec<i16>) -> Self {
+        assert_eq!(weights.len(), set.dimensions() * biases.len());
+        FeatureTransformer {
+            set,
+            biases,
+            weights,
+        }
+    }
+
+    /// Number of outputs.
+    pub fn size(&self) -> usize {
+        self.biases.len()
+    }
+
+    /// Computes the accumulator from scratch.
+    pub fn refresh(&self, board: &Board, perspective: Color) -> Vec<i16> {
+        let mut accumulator = self.biases.clone();
+        for feature in self.set.active_features(board, perspective) {
+            self.add_feature(&mut accumulator, feature);
+        }
+        accumulator
+    }
+
+    pub fn set(&self) -> FeatureSet {
+        self.set
+    }
+
+    pub fn biases(&self) -> &[i16] {
+        &self.biases
+    }
+
+    pub fn weights(&self) -> &[i16] {
+        &self.weights
+    }
+
+    pub fn add_feature(&self, accumulator: &mut [i16], feature: u32) {
+        for (value, weight) in accumulator.iter_mut().zip(self.feature_weights(feature)) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    pub fn remove_feature(&self, accumulator: &mut [i16], feature: u32) {
+        for (value, weight) in accumulator.iter_mut().zip(self.feature_weights(feature)) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+
+    /// Computes the accumulator of a position from the accumulator of the
+    /// previous position and the features that changed between them, in one
+    /// pass over the lanes, which the compiler vectorizes.
+    ///
+    /// ```
+    /// use preprocessing::nnue::{FeatureSet, FeatureTransformer};
+    /// use shakmaty::{Chess, Color, Position};
+    ///
+    /// let set = FeatureSet::HalfKp;
+    /// let weights = (0..set.dimensions() * 2).map(|idx| (idx % 7) as i16).collect();
+    /// let transformer = FeatureTransformer::new(set, vec![1, 2], weights);
+    /// let board = Chess::default().board().clone();
+    /// let accumulator = transformer.refresh(&board, Color::White);
+    ///
+    /// let features = set.active_features(&board, Color::White);
+    /// let mut updated = vec![0; 2];
+    /// transformer.update(&accumulator, &mut updated, &features[..1], &features[1..2]);
+    /// let mut expected = accumulator.clone();
+    /// transformer.add_feature(&mut expected, features[0]);
+    /// transformer.remove_feature(&mut expected, features[1]);
+    /// assert_eq!(updated, expected);
+    /// ```
+    pub fn update(&self, from: &[i16], to: &mut [i16], added: &[u32], removed: &[u32]) {
+        to.copy_from_slice(from);
+        for &feature in removed {
+            self.remove_feature(to, feature);
+        }
+        for &feature in added {
+            self.add_feature(to, feature);
+        }
+    }
+
+    fn feature_weights(&self, feature: u32) -> &[i16] {
+        let offset = feature as usize * self.size();
+        &self.weights[offset..offset + self.size()]
+    }
+}
+
+/// Clipped ReLU outputs of 1 are represented by this value.
+pub const ACTIVATION_SCALE: i32 = 127;
+
+/// Fully connected layer with 8-bit weights.
+pub struct DenseLayer {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// The weights of every output are stored next to each other.
+    pub weights: Vec<i8>,
+    /// Scaled by `ACTIVATION_SCALE << shift`.
+    pub biases: Vec<i32>,
+    /// The weights are scaled by `1 << shift`.
+    pub shift: u32,
+}
+
+impl DenseLayer {
+    /// Outputs scaled by `ACTIVATION_SCALE << shift`.
+    pub fn forward(&self, input: &[u8]) -> Vec<i32> {
+        assert_eq!(input.len(), self.inputs);
+        self.weights
+            .chunks_exact(self.inputs)
+            .zip(&self.biases)
+            .map(|(weights, &bias)| {
+                let products = input.iter().zip(weights);
+                bias + products.map(|(&x, &w)| x as i32 * w as i32).sum::<i32>()
+            })
+            .collect()
+    }
+}
+
+/// Quantized network: the feature transformer applied to both perspectives
+/// (side to move first), followed by dense layers with clipped ReLU
+/// activations between them.
+pub struct Network {
+    pub transformer: FeatureTransformer,
+    /// The last layer has a single output.
+    pub layers: Vec<DenseLayer>,
+}
+
+impl Network {
+    /// Predicted logit of the expected score of the side to move, which is
+    /// white.
+    pub fn evaluate(&self, board: &Board) -> f32 {
+        let us = self.transformer.refresh(board, Color::White);
+        let them = self.transformer.refresh(board, Color::Black);
+        self.forward(&us, &them)
+    }
+
+    /// Predicted logit of the expected score of the side to move from the
+    /// accumulators of the side to move and of the other side, which may be
+    /// updated incrementally.
+    pub fn forward(&self, us: &[i16], them: &[i16]) -> f32 {
+        let crelu = |value: i32| value.clamp(0, ACTIVATION_SCALE) as u8;
+        let mut x: Vec<u8> = us
+            .iter()
+            .chain(them)
+            .map(|&value| crelu(value as i32))
+            .collect();
+        let (output, hidden) = self.layers.split_last().expect("the network has layers");
+        for layer in hidden {
+            x = layer
+                .forward(&x)
+                .into_iter()
+                .map(|value| crelu(value >> layer.shift))
+                .collect();
+        }
+        output.forward(&x)[0] as f32 / (ACTIVATION_SCALE << output.shift) as f32
+    }
+}
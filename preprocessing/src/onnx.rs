@@ -0,0 +1,208 @@
+// Export of the networks trained by the train command to ONNX (`export-onnx`).
+//
+// The graph computes the same function as the trainers and takes the same
+// sparse inputs: for each perspective (side to move first) the indices of the
+// active features, padded to the maximum number of active features, and a mask
+// of the valid slots:
+//
+//     us_features, them_features   int64 [batch, slots]
+//     us_mask, them_mask           float [batch, slots, 1]
+//     score                        float [batch, 1], expected score of the
+//                                  side to move
+//
+// The ONNX protobuf messages are encoded by hand, only the handful of fields
+// of the graph are needed.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::nnue::FeatureSet;
+use tracing::info;
+
+use crate::protobuf::Message;
+use crate::quantize::{FloatNetwork, LAYERS};
+
+// Opset 13 takes the axes of ReduceSum as an input.
+const OPSET: u64 = 13;
+const IR_VERSION: u64 = 7;
+
+// TensorProto.DataType
+const FLOAT: u64 = 1;
+const INT64: u64 = 7;
+
+// AttributeProto.AttributeType
+const ATTRIBUTE_INT: u64 = 2;
+
+#[derive(Args)]
+pub struct ExportOnnxArgs {
+    /// Float weights written by the train command (.safetensors)
+    #[arg(long)]
+    weights: PathBuf,
+
+    /// Path to write the ONNX model to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Input features the network was trained with
+    #[arg(long, value_enum, default_value = "halfkp")]
+    features: FeatureSet,
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str) -> Message {
+    let node = inputs
+        .iter()
+        .fold(Message::default(), |node, input| node.string(1, input));
+    node.string(2, output).string(3, output).string(4, op_type)
+}
+
+fn int_attribute(name: &str, value: i64) -> Message {
+    Message::default()
+        .string(1, name)
+        .varint(3, value as u64)
+        .varint(20, ATTRIBUTE_INT)
+}
+
+fn initializer(name: &str, dims: &[usize], values: &[f32]) -> Message {
+    let tensor = dims.iter().fold(Message::default(), |tensor, &dim| {
+        tensor.varint(1, dim as u64)
+    });
+    let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    tensor.varint(2, FLOAT).string(8, name).bytes(9, &data)
+}
+
+// Dimensions are either named (symbolic) or fixed.
+enum Dim {
+    Param(&'static str),
+    Value(usize),
+}
+
+fn value_info(name: &str, elem_type: u64, dims: &[Dim]) -> Message {
+    let shape = dims.iter().fold(Message::default(), |shape, dim| {
+        let dim = match dim {
+            Dim::Param(param) => Message::default().string(2, param),
+            Dim::Value(value) => Message::default().varint(1, *value as u64),
+        };
+        shape.message(1, dim)
+    });
+    let tensor_type = Message::default().varint(1, elem_type).message(2, shape);
+    let type_proto = Message::default().message(1, tensor_type);
+    Message::default().string(1, name).message(2, type_proto)
+}
+
+fn model(network: &FloatNetwork) -> Message {
+    let hidden = network.hidden();
+    let slots = network.set.max_active_features();
+    // GraphProto fields: node 1, name 2, initializer 5, input 11, output 12.
+    let mut graph = Message::default().string(2, "attix");
+
+    for side in ["us", "them"] {
+        let features = format!("{side}_features");
+        let mask = format!("{side}_mask");
+        let gathered = format!("{side}_gathered");
+        let masked = format!("{side}_masked");
+        let summed = format!("{side}_summed");
+        graph = graph
+            .message(
+                1,
+                node("Gather", &["transformer.weight", &features], &gathered),
+            )
+            .message(1, node("Mul", &[&gathered, &mask], &masked))
+            .message(
+                1,
+                node("ReduceSum", &[&masked, "sum_axes"], &summed)
+                    .message(5, int_attribute("keepdims", 0)),
+            )
+            .message(1, node("Add", &[&summed, "transformer.bias"], side));
+    }
+    graph = graph
+        .message(
+            1,
+            node("Concat", &["us", "them"], "accumulator").message(5, int_attribute("axis", 1)),
+        )
+        .message(1, node("Clip", &["accumulator", "zero", "one"], "x0"));
+    let mut input = "x0".to_string();
+    for (idx, name) in LAYERS.iter().enumerate() {
+        let weight = format!("{name}.weight");
+        let bias = format!("{name}.bias");
+        let linear = format!("{name}.linear");
+        let gemm = node("Gemm", &[&input, &weight, &bias], &linear);
+        graph = graph.message(1, gemm.message(5, int_attribute("transB", 1)));
+        input = if idx + 1 < LAYERS.len() {
+            let activation = format!("x{}", idx + 1);
+            graph = graph.message(1, node("Clip", &[&linear, "zero", "one"], &activation));
+            activation
+        } else {
+            linear
+        };
+    }
+    graph = graph.message(1, node("Sigmoid", &[&input], "score"));
+
+    let dimensions = network.set.dimensions();
+    graph = graph
+        .message(
+            5,
+            initializer(
+                "transformer.weight",
+                &[dimensions, hidden],
+                &network.transformer,
+            ),
+        )
+        .message(
+            5,
+            initializer("transformer.bias", &[hidden], &network.transformer_bias),
+        )
+        .message(5, initializer("zero", &[], &[0.0]))
+        .message(5, initializer("one", &[], &[1.0]));
+    for (name, layer) in LAYERS.iter().zip(&network.layers) {
+        let outputs = layer.biases.len();
+        let weight = initializer(
+            &format!("{name}.weight"),
+            &[outputs, layer.inputs],
+            &layer.weights,
+        );
+        let bias = initializer(&format!("{name}.bias"), &[outputs], &layer.biases);
+        graph = graph.message(5, weight).message(5, bias);
+    }
+    let axes = Message::default()
+        .varint(1, 1)
+        .varint(2, INT64)
+        .string(8, "sum_axes")
+        .bytes(9, &1i64.to_le_bytes());
+    graph = graph.message(5, axes);
+
+    for side in ["us", "them"] {
+        let features = [Dim::Param("batch"), Dim::Value(slots)];
+        let mask = [Dim::Param("batch"), Dim::Value(slots), Dim::Value(1)];
+        graph = graph
+            .message(
+                11,
+                value_info(&format!("{side}_features"), INT64, &features),
+            )
+            .message(11, value_info(&format!("{side}_mask"), FLOAT, &mask));
+    }
+    let score = [Dim::Param("batch"), Dim::Value(1)];
+    graph = graph.message(12, value_info("score", FLOAT, &score));
+
+    // ModelProto fields: ir_version 1, producer_name 2, producer_version 3,
+    // graph 7, opset_import 8, metadata_props 14.
+    let features = serde_json::to_string(&network.set).expect("serializable");
+    let metadata = Message::default()
+        .string(1, "features")
+        .string(2, features.trim_matches('"'));
+    Message::default()
+        .varint(1, IR_VERSION)
+        .string(2, "attix")
+        .string(3, env!("CARGO_PKG_VERSION"))
+        .message(7, graph)
+        .message(8, Message::default().string(1, "").varint(2, OPSET))
+        .message(14, metadata)
+}
+
+pub fn run(args: ExportOnnxArgs) -> io::Result<()> {
+    let network = FloatNetwork::load(&args.weights, args.features)?;
+    fs::write(&args.output, model(&network).into_bytes())?;
+    info!(path = %args.output.display(), "wrote ONNX model");
+    Ok(())
+}
@@ -0,0 +1,617 @@
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shakmaty::Color;
+
+use crate::encoder::InputPlanes;
+use crate::nnue::FeatureSet;
+use crate::phase;
+use crate::record::NUM_INPUT_PLANES;
+use crate::sample::{Game, Provenance, TrainingSample, ValueSource};
+use crate::sink::{Manifest, SampleSink};
+
+// Position of the writer within the sharded output: the shard that is being
+// written and how much of it is filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShardState {
+    pub index: usize,
+    pub samples: u64,
+    pub bytes: u64,
+}
+
+// Writes processed samples (one per line) into a directory of numbered shards,
+// each holding at most `shard_size` samples. Every shard is accompanied by an
+// index of the byte offsets of its lines, see crate::dataset.
+pub struct ShardWriter {
+    dir: PathBuf,
+    shard_size: u64,
+    state: ShardState,
+    file: BufWriter<File>,
+    index: BufWriter<File>,
+    // Shard the writer started in and the samples and bytes it wrote, for the
+    // manifest of the sink.
+    first_index: usize,
+    samples: u64,
+    bytes: u64,
+}
+
+impl ShardWriter {
+    pub fn create<P: AsRef<Path>>(dir: P, shard_size: u64) -> io::Result<Self> {
+        Self::resume(dir, shard_size, ShardState::default())
+    }
+
+    // Continues writing the shard described by `state`, dropping everything
+    // that was written to it after the state was recorded.
+    pub fn resume<P: AsRef<Path>>(dir: P, shard_size: u64, state: ShardState) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let path = shard_path(&dir, state.index);
+        let file = open_truncated(&path, state.bytes)?;
+        let index = open_truncated(&index_path(&path), state.samples * INDEX_ENTRY_SIZE)?;
+
+        Ok(ShardWriter {
+            dir,
+            shard_size,
+            state,
+            file: BufWriter::new(file),
+            index: BufWriter::new(index),
+            first_index: state.index,
+            samples: 0,
+            bytes: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, line: &str) -> io::Result<()> {
+        if self.state.samples >= self.shard_size {
+            self.next_shard()?;
+        }
+        self.index.write_all(&self.state.bytes.to_le_bytes())?;
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.state.samples += 1;
+        self.state.bytes += line.len() as u64 + 1;
+        self.samples += 1;
+        self.bytes += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    // Position of the writer, including the samples that are not flushed yet.
+    pub fn state(&self) -> ShardState {
+        self.state
+    }
+
+    // Flushes the buffered samples to disk and reports the state that is safe
+    // to resume from.
+    pub fn flush(&mut self) -> io::Result<ShardState> {
+        self.file.flush()?;
+        self.index.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.index.get_ref().sync_data()?;
+        Ok(self.state)
+    }
+
+    fn next_shard(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.index.flush()?;
+        self.state = ShardState {
+            index: self.state.index + 1,
+            samples: 0,
+            bytes: 0,
+        };
+        let path = shard_path(&self.dir, self.state.index);
+        self.file = BufWriter::new(File::create(&path)?);
+        self.index = BufWriter::new(File::create(index_path(&path))?);
+        Ok(())
+    }
+}
+
+// The text shards as a sink, format "text".
+impl SampleSink for ShardWriter {
+    fn write(&mut self, sample: &OutputSample) -> io::Result<()> {
+        self.write_sample(sample.line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        ShardWriter::flush(self).map(|_| ())
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<Manifest> {
+        ShardWriter::flush(&mut self)?;
+        Ok(Manifest {
+            format: "text".to_string(),
+            files: (self.first_index..=self.state.index)
+                .map(|index| shard_path(&self.dir, index))
+                .collect(),
+            samples: self.samples,
+            bytes: self.bytes,
+        })
+    }
+}
+
+// Opens the file for appending after its first `len` bytes.
+fn open_truncated(path: &Path, len: u64) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.set_len(len)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(file)
+}
+
+fn shard_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("shard-{index:05}.txt"))
+}
+
+// Size of an entry of the shard index: the byte offset of a line as a
+// little-endian u64.
+pub const INDEX_ENTRY_SIZE: u64 = 8;
+
+// Path of the index of the shard at `shard`.
+pub fn index_path(shard: &Path) -> PathBuf {
+    shard.with_extension("idx")
+}
+
+// Paths of the shards in the output directory in the order they were written.
+// A path to a single file is returned as is.
+pub fn shard_paths(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with("shard-") && name.ends_with(".txt") {
+            paths.push(path);
+        }
+    }
+    // The shard index is zero-padded.
+    paths.sort();
+    Ok(paths)
+}
+
+// Index of the first shard after the ones in the output directory, 0 if there
+// are none.
+pub fn next_shard_index(dir: &Path) -> io::Result<usize> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let last = shard_paths(dir)?
+        .iter()
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?;
+            name.strip_prefix("shard-")?.parse::<usize>().ok()
+        })
+        .max();
+    Ok(last.map_or(0, |last| last + 1))
+}
+
+// Calls `f` for every sample line of the output at `path`, which is either the
+// output directory or a single shard.
+pub fn for_each_line<F>(path: &Path, mut f: F) -> io::Result<()>
+where
+    F: FnMut(&str) -> io::Result<()>,
+{
+    for shard in shard_paths(path)? {
+        for line in BufReader::new(File::open(shard)?).lines() {
+            f(&line?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Optional fields appended to the serialized samples.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputFields {
+    pub eco: bool,
+    pub phase_score: bool,
+    /// Number of earlier occurrences of the position in the game, see
+    /// [`TrainingSample::repetitions`].
+    pub repetitions: bool,
+    /// Number of plies since the last capture or pawn move, see
+    /// [`TrainingSample::rule50_count`].
+    pub rule50: bool,
+    /// Number of plies until the end of the game, see
+    /// [`TrainingSample::plies_left`].
+    pub plies_left: bool,
+    /// Width of the buckets of the plies until the end of the game, for
+    /// moves-left heads predicting a class instead of the number.
+    pub plies_left_bucket: Option<u32>,
+    /// Search value written as best_q and best_d.
+    pub value_source: ValueSource,
+    /// Search values appended as separate columns, indexed by the
+    /// [`ValueSource`].
+    pub value_columns: [bool; ValueSource::ALL.len()],
+    /// The move played in the game and its value, see
+    /// [`TrainingSample::played_idx`].
+    pub played: bool,
+    /// The complete input of lc0 networks, see [`InputPlanes::from_sample`].
+    /// Needs the history planes, see
+    /// [`DecodeOptions::history`](crate::DecodeOptions::history).
+    pub lc0_planes: bool,
+    /// Location of the sample in the input, see [`Provenance`].
+    pub provenance: Option<ProvenanceFormat>,
+    /// Active NNUE features of the side to move and of the opponent.
+    pub nnue: Option<FeatureSet>,
+}
+
+impl OutputFields {
+    /// Names of the fields of the output lines written with these options, in
+    /// the order of [`OUTPUT_SCHEMA`].
+    ///
+    /// ```
+    /// use preprocessing::output::OutputFields;
+    ///
+    /// let fields = OutputFields {
+    ///     phase_score: true,
+    ///     ..OutputFields::default()
+    /// };
+    /// assert_eq!(fields.names(), ["fen", "best_q", "best_d", "best_move", "phase_score"]);
+    /// ```
+    pub fn names(&self) -> Vec<&'static str> {
+        OUTPUT_SCHEMA
+            .iter()
+            .filter(|field| match field.option {
+                None => true,
+                Some("--phase-score") => self.phase_score,
+                Some("--repetitions") => self.repetitions,
+                Some("--rule50") => self.rule50,
+                Some("--plies-left") => self.plies_left,
+                Some("--plies-left-bucket") => self.plies_left_bucket.is_some(),
+                Some("--value-columns") => ValueSource::ALL.into_iter().any(|source| {
+                    self.value_columns[source as usize]
+                        && field.name.starts_with(&format!("value_{}_", source.name()))
+                }),
+                Some("--played") => self.played,
+                Some("--lc0-planes") => self.lc0_planes,
+                Some("--provenance") => self.provenance.is_some(),
+                Some("--nnue") => self.nnue.is_some(),
+                Some("--eco") => self.eco,
+                Some(_) => false,
+            })
+            .map(|field| field.name)
+            .collect()
+    }
+}
+
+/// How the archive of the [`Provenance`] of the samples is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvenanceFormat {
+    /// The path of the archive, with spaces escaped as %20.
+    Full,
+    /// The first 8 bytes of the SHA-256 of the path in hex, which keeps the
+    /// lines short.
+    Hashed,
+}
+
+/// Description of a field of the output lines.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    /// Option enabling the field, None for the fields that are always written.
+    pub option: Option<&'static str>,
+    pub description: &'static str,
+}
+
+/// Version of the format of the output lines, increased with incompatible
+/// changes of the fields.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Fields of the output lines in the order they are written, separated by
+/// spaces. See [`serialize_position`].
+pub const OUTPUT_SCHEMA: &[FieldSchema] = &[
+    FieldSchema {
+        name: "fen",
+        option: None,
+        description: "position in FEN (6 space-separated fields), the side to move is always white",
+    },
+    FieldSchema {
+        name: "best_q",
+        option: None,
+        description: "expected score for the side to move from -1 to 1, of the best move unless \
+                      --value-source says otherwise",
+    },
+    FieldSchema {
+        name: "best_d",
+        option: None,
+        description: "draw probability from 0 to 1, of the best move unless --value-source says \
+                      otherwise",
+    },
+    FieldSchema {
+        name: "best_move",
+        option: None,
+        description: "best move in UCI notation, castling as king captures rook",
+    },
+    FieldSchema {
+        name: "phase_score",
+        option: Some("--phase-score"),
+        description: "game phase from the remaining material, 0 (endgame) to 24 (opening)",
+    },
+    FieldSchema {
+        name: "repetitions",
+        option: Some("--repetitions"),
+        description: "number of earlier occurrences of the position in the game",
+    },
+    FieldSchema {
+        name: "rule50",
+        option: Some("--rule50"),
+        description: "plies since the last capture or pawn move, for the fifty-move rule",
+    },
+    FieldSchema {
+        name: "plies_left",
+        option: Some("--plies-left"),
+        description: "plies until the end of the game, the target of moves-left heads",
+    },
+    FieldSchema {
+        name: "plies_left_bucket",
+        option: Some("--plies-left-bucket"),
+        description: "plies until the end of the game divided by the bucket width, rounded down",
+    },
+    FieldSchema {
+        name: "value_root_q",
+        option: Some("--value-columns"),
+        description: "expected score of the root of the search",
+    },
+    FieldSchema {
+        name: "value_root_d",
+        option: Some("--value-columns"),
+        description: "draw probability of the root of the search",
+    },
+    FieldSchema {
+        name: "value_best_q",
+        option: Some("--value-columns"),
+        description: "expected score of the best move",
+    },
+    FieldSchema {
+        name: "value_best_d",
+        option: Some("--value-columns"),
+        description: "draw probability of the best move",
+    },
+    FieldSchema {
+        name: "value_orig_q",
+        option: Some("--value-columns"),
+        description: "expected score of the best move before rescoring, NaN if not rescored",
+    },
+    FieldSchema {
+        name: "value_orig_d",
+        option: Some("--value-columns"),
+        description: "draw probability of the best move before rescoring",
+    },
+    FieldSchema {
+        name: "played_move",
+        option: Some("--played"),
+        description: "move played in the game in UCI notation, castling as king captures rook",
+    },
+    FieldSchema {
+        name: "played_q",
+        option: Some("--played"),
+        description: "expected score of the played move for the side to move, from -1 to 1",
+    },
+    FieldSchema {
+        name: "played_d",
+        option: Some("--played"),
+        description: "draw probability of the played move, from 0 to 1",
+    },
+    FieldSchema {
+        name: "lc0_planes",
+        option: Some("--lc0-planes"),
+        description: "the 112 input planes of lc0 networks (classical format), comma-separated: \
+                      the squares set to 1 of every plane as a hex bitboard, except the rule50 \
+                      plane, which is its value, - if the history is not decoded",
+    },
+    FieldSchema {
+        name: "nnue_us",
+        option: Some("--nnue"),
+        description: "active NNUE feature indices of the side to move, comma-separated",
+    },
+    FieldSchema {
+        name: "nnue_them",
+        option: Some("--nnue"),
+        description: "active NNUE feature indices of the opponent, comma-separated",
+    },
+    FieldSchema {
+        name: "provenance",
+        option: Some("--provenance"),
+        description: "archive:entry:start-end:game:ply of the sample in the input, start-end is \
+                      the byte range of the compressed game, - if unknown",
+    },
+    FieldSchema {
+        name: "eco",
+        option: Some("--eco"),
+        description: "ECO code of the opening or - if it is unknown",
+    },
+    FieldSchema {
+        name: "opening",
+        option: Some("--eco"),
+        description: "quoted name of the opening, may contain spaces",
+    },
+];
+
+/// Formats the sample as a line of the output: the FEN, best_q, best_d and the
+/// best move in UCI notation, followed by the requested optional fields.
+pub fn serialize_position(data: &TrainingSample, game: &Game, fields: OutputFields) -> String {
+    let (q, d) = data.value(fields.value_source);
+    let mut line = format!(
+        "{} {} {} {}",
+        data.to_fen(&game.castling),
+        q,
+        d,
+        crate::IDX_TO_MOVE[data.best_idx as usize]
+    );
+    if fields.phase_score {
+        write!(line, " {}", phase::score(data)).expect("writing to a String does not fail");
+    }
+    if fields.repetitions {
+        write!(line, " {}", data.repetitions).expect("writing to a String does not fail");
+    }
+    if fields.rule50 {
+        write!(line, " {}", data.rule50_count).expect("writing to a String does not fail");
+    }
+    if fields.plies_left {
+        write!(line, " {}", data.plies_left).expect("writing to a String does not fail");
+    }
+    if let Some(width) = fields.plies_left_bucket {
+        write!(line, " {}", plies_left_bucket(data.plies_left, width))
+            .expect("writing to a String does not fail");
+    }
+    for source in ValueSource::ALL {
+        if fields.value_columns[source as usize] {
+            let (q, d) = data.value(source);
+            write!(line, " {q} {d}").expect("writing to a String does not fail");
+        }
+    }
+    if fields.played {
+        write!(
+            line,
+            " {} {} {}",
+            crate::IDX_TO_MOVE[data.played_idx as usize],
+            data.played_q,
+            data.played_d
+        )
+        .expect("writing to a String does not fail");
+    }
+    if fields.lc0_planes {
+        write_lc0_planes(&mut line, data);
+    }
+    if let Some(set) = fields.nnue {
+        let board = data.to_board();
+        for perspective in [Color::White, Color::Black] {
+            let features: Vec<_> = set
+                .active_features(&board, perspective)
+                .iter()
+                .map(u32::to_string)
+                .collect();
+            write!(line, " {}", features.join(",")).expect("writing to a String does not fail");
+        }
+    }
+    if let Some(format) = fields.provenance {
+        write_provenance(&mut line, game.provenance.as_ref(), data.ply, format);
+    }
+    // The name contains spaces, so it is quoted and always comes last.
+    if fields.eco {
+        match game.opening {
+            Some(opening) => write!(line, " {} \"{}\"", opening.code, opening.name),
+            None => write!(line, " - \"\""),
+        }
+        .expect("writing to a String does not fail");
+    }
+    line
+}
+
+/// Bucket of the plies until the end of the game for buckets of `width`
+/// plies. Negative and missing values fall into the first bucket.
+///
+/// ```
+/// use preprocessing::output::plies_left_bucket;
+///
+/// assert_eq!(plies_left_bucket(0.0, 10), 0);
+/// assert_eq!(plies_left_bucket(57.5, 10), 5);
+/// assert_eq!(plies_left_bucket(-1.0, 10), 0);
+/// ```
+pub fn plies_left_bucket(plies_left: f32, width: u32) -> u32 {
+    (plies_left.max(0.0) / width.max(1) as f32) as u32
+}
+
+// The rule50 plane of the lc0 inputs, the only one with values other than 0
+// and 1.
+const RULE50_PLANE: usize = NUM_INPUT_PLANES + 5;
+
+fn write_lc0_planes(line: &mut String, data: &TrainingSample) {
+    let Some(planes) = InputPlanes::from_sample(data) else {
+        line.push_str(" -");
+        return;
+    };
+    for (idx, plane) in planes.0.iter().enumerate() {
+        let separator = if idx == 0 { ' ' } else { ',' };
+        if idx == RULE50_PLANE {
+            write!(line, "{separator}{}", plane.value)
+        } else {
+            write!(line, "{separator}{:x}", plane.mask)
+        }
+        .expect("writing to a String does not fail");
+    }
+}
+
+fn write_provenance(
+    line: &mut String,
+    provenance: Option<&Provenance>,
+    ply: u16,
+    format: ProvenanceFormat,
+) {
+    let Some(provenance) = provenance else {
+        line.push_str(" -");
+        return;
+    };
+    let archive = match format {
+        ProvenanceFormat::Full => provenance.archive.replace(' ', "%20"),
+        ProvenanceFormat::Hashed => {
+            let digest = Sha256::digest(provenance.archive.as_bytes());
+            let prefix: [u8; 8] = digest[..8].try_into().expect("8 bytes");
+            format!("{:016x}", u64::from_be_bytes(prefix))
+        }
+    };
+    write!(
+        line,
+        " {archive}:{}:{}-{}:{}:{ply}",
+        provenance.entry,
+        provenance.offset,
+        provenance.offset + provenance.len,
+        provenance.game
+    )
+    .expect("writing to a String does not fail");
+}
+
+// A sample parsed back from its serialized form, see serialize_position.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputSample<'a> {
+    // The whole line.
+    pub line: &'a str,
+    pub fen: &'a str,
+    pub best_q: f32,
+    pub best_d: f32,
+    pub best_move: &'a str,
+    // Optional fields in the order they were written, possibly empty.
+    pub extra: &'a str,
+}
+
+impl<'a> OutputSample<'a> {
+    pub fn parse(line: &'a str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid sample: {line}"),
+            )
+        };
+
+        // FEN (6 fields), best_q, best_d and the best move, then the extras.
+        let mut fields = line.splitn(10, ' ');
+        let mut fen_len = 0;
+        for _ in 0..6 {
+            fen_len += fields.next().ok_or_else(invalid)?.len() + 1;
+        }
+        let best_q = fields.next().ok_or_else(invalid)?;
+        let best_d = fields.next().ok_or_else(invalid)?;
+        Ok(OutputSample {
+            line,
+            fen: &line[..fen_len - 1],
+            best_q: best_q.parse().map_err(|_| invalid())?,
+            best_d: best_d.parse().map_err(|_| invalid())?,
+            best_move: fields.next().ok_or_else(invalid)?,
+            extra: fields.next().unwrap_or(""),
+        })
+    }
+
+    // The FEN without the move counters, which identifies the position.
+    pub fn position(&self) -> &'a str {
+        self.fen.rsplitn(3, ' ').last().unwrap_or(self.fen)
+    }
+}
@@ -0,0 +1,84 @@
+// Game phase derived from the material remaining on the board.
+//
+// The score follows the usual tapered evaluation weights: every knight and
+// bishop counts 1, rook 2 and queen 4, so the starting position scores 24 and
+// positions with only kings and pawns score 0. Promotions can push the score
+// above 24, which is clamped.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use shakmaty::Board;
+
+use crate::sample::TrainingSample;
+
+pub const MAX_SCORE: u32 = 24;
+
+// Positions scoring above this still have (almost) all pieces on the board.
+const OPENING_MIN_SCORE: u32 = 21;
+// Positions scoring at most this have traded most of the pieces.
+const ENDGAME_MAX_SCORE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::Opening, Phase::Middlegame, Phase::Endgame];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Opening => "opening",
+            Phase::Middlegame => "middlegame",
+            Phase::Endgame => "endgame",
+        }
+    }
+
+    /// ```
+    /// use preprocessing::phase::{Phase, MAX_SCORE};
+    ///
+    /// assert_eq!(Phase::from_score(MAX_SCORE), Phase::Opening);
+    /// assert_eq!(Phase::from_score(0), Phase::Endgame);
+    /// ```
+    pub fn from_score(score: u32) -> Self {
+        if score >= OPENING_MIN_SCORE {
+            Phase::Opening
+        } else if score > ENDGAME_MAX_SCORE {
+            Phase::Middlegame
+        } else {
+            Phase::Endgame
+        }
+    }
+}
+
+pub fn score(data: &TrainingSample) -> u32 {
+    // Knights, bishops, rooks and queens of both sides.
+    const WEIGHTS: [u32; 4] = [1, 1, 2, 4];
+    let count =
+        |role: usize| data.bitboards[role].count_ones() + data.bitboards[role + 6].count_ones();
+    let score: u32 = WEIGHTS
+        .iter()
+        .enumerate()
+        .map(|(idx, weight)| weight * count(idx + 1))
+        .sum();
+    score.min(MAX_SCORE)
+}
+
+/// Same as [`score`] for a board.
+///
+/// ```
+/// use preprocessing::phase::{board_score, MAX_SCORE};
+/// use shakmaty::Board;
+///
+/// assert_eq!(board_score(&Board::default()), MAX_SCORE);
+/// ```
+pub fn board_score(board: &Board) -> u32 {
+    let pieces = board.knights().count()
+        + board.bishops().count()
+        + 2 * board.rooks().count()
+        + 4 * board.queens().count();
+    (pieces as u32).min(MAX_SCORE)
+}
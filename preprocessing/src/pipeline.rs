@@ -0,0 +1,772 @@
+// The preprocessing pipeline consists of the following stages connected by
+// bounded channels:
+//
+//   tar reading -> gz decoding and parsing -> filtering -> serialization -> writing
+//
+// Decoding is the most expensive stage and runs on a thread pool, all other
+// stages are running on their own threads. Every game is tagged with its
+// position in the input, so that the writer can restore the original order.
+//
+// The number of games in flight is limited, hence a slow stage blocks the
+// preceding stages instead of accumulating an unbounded amount of data.
+//
+// Unreadable archives and games that fail to decode do not stop the run: the
+// rest of the archive or the game is skipped and the error is recorded in the
+// summary, the caller decides how many errors it tolerates (`--max-errors`).
+// Only failures to write the output are fatal.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use preprocessing::augment::{with_augmented, Augmentation};
+use preprocessing::output::{serialize_position, OutputFields, OutputSample, ShardWriter};
+use preprocessing::phase::{self, Phase};
+use preprocessing::resample::Resampler;
+use preprocessing::sample::Provenance;
+use preprocessing::sink::SampleSink;
+use preprocessing::source::{self, InputFormat, PgnFilter};
+use preprocessing::{filter_position, Filter, FilterOptions, Game};
+use rayon::prelude::*;
+use tracing::{debug, info, info_span, trace, trace_span, warn};
+
+use crate::checkpoint::Checkpointer;
+use crate::dedup::ExistingPositions;
+use crate::memory::MemoryBudget;
+use crate::metrics;
+use crate::progress::Progress;
+use crate::signals;
+
+// Capacity of the channels between the stages (in games).
+const CHANNEL_CAPACITY: usize = 64;
+
+// Games read ahead from every archive of an interleaved run.
+const GAMES_AHEAD_PER_ARCHIVE: usize = 4;
+
+// Maximum number of games that were read but are not written yet.
+const MAX_GAMES_IN_FLIGHT: usize = 1024;
+
+// The compressed game is dropped after decoding, but the decoded samples and
+// their serialized form take roughly the same amount of memory while the game
+// moves through the later stages.
+const FOOTPRINT_PER_COMPRESSED_BYTE: u64 = 2;
+
+// Typical footprint of a game, used to size the channels under a memory limit.
+const TYPICAL_GAME_FOOTPRINT: u64 = 1 << 20;
+
+pub struct Inputs {
+    // Paths to the tar archives.
+    pub archives: Vec<String>,
+    // Format of all inputs (`--input-format`), detected per input if None.
+    pub format: Option<InputFormat>,
+    // Ratings and kind of the PGN games to read (`--min-elo`, `--max-elo` and
+    // `--rated-only`).
+    pub pgn_filter: PgnFilter,
+    // Where to start processing the inputs.
+    pub first_archive: usize,
+    pub first_entry: usize,
+    // Number of games to read (`--limit-games`) and samples to write
+    // (`--limit`) at most, counted from where this run starts.
+    pub max_games: Option<u64>,
+    pub max_samples: Option<u64>,
+    // Number of archives read at the same time with their games interleaved
+    // (`--interleave`), 1 reads them one after another. Interleaved runs are
+    // not checkpointed: the position in the input is not a single entry.
+    pub interleave: usize,
+}
+
+// Results of the games for the side to move, split at these values of
+// result_q.
+pub const OUTCOME_EDGES: [f32; 2] = [-0.5, 0.5];
+pub const OUTCOMES: [&str; 3] = ["loss", "draw", "win"];
+
+// Index of the result of the game in OUTCOMES.
+fn outcome(result_q: f32) -> usize {
+    OUTCOME_EDGES.partition_point(|&edge| edge <= result_q)
+}
+
+// Hash of the position of a serialized sample, ignoring the move counters like
+// merge. It is only stable within a process.
+pub fn position_hash(sample: &OutputSample) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    sample.position().hash(&mut hasher);
+    hasher.finish()
+}
+
+// What happens to the games between reading and writing them.
+pub struct Processing {
+    pub filters: FilterOptions,
+    // Copies of the kept samples written after them (`--augment`).
+    pub augment: Vec<Augmentation>,
+    pub fields: OutputFields,
+    // Balancing of the evaluations (`--eval-buckets`). The samples are
+    // resampled by the writer, which sees them in the order of the input.
+    pub resampler: Option<Resampler>,
+    // Balancing of the results of the games (`--balance-outcomes`), by the
+    // writer as well, with the buckets of OUTCOME_EDGES.
+    pub outcome_resampler: Option<Resampler>,
+    // Every sample goes to the writer of its phase (`--split-by-phase`).
+    pub split_by_phase: bool,
+    // Hashes of the positions in the shards written by earlier runs, whose
+    // samples are dropped (`--dedup-existing`), see position_hash.
+    pub existing_positions: Option<ExistingPositions>,
+}
+
+// Destination of the processed samples.
+pub enum Output {
+    // A single writer, or one per phase in the order of Phase::ALL.
+    Shards {
+        writers: Vec<ShardWriter>,
+        checkpointer: Checkpointer,
+        // Further sinks getting every written sample (`--sink`).
+        sinks: Vec<Box<dyn SampleSink>>,
+    },
+    // The samples are serialized to measure their size, but not written
+    // anywhere (`--dry-run`).
+    DryRun,
+}
+
+// Totals of a finished run. When a limit stops the run early, the games that
+// were already read ahead are included in the counts of the decoded games and
+// samples, but only the written samples count as kept.
+#[derive(Debug, Default)]
+pub struct Summary {
+    // Number of archives the run started reading.
+    pub archives: u64,
+    pub games: u64,
+    pub samples: u64,
+    // Written samples, including the augmented copies.
+    pub kept: u64,
+    pub filtered: [u64; Filter::ALL.len()],
+    // Samples dropped by the resampling.
+    pub resampled: u64,
+    // Samples dropped by the balancing of the outcomes.
+    pub balanced: u64,
+    // Samples dropped because their positions are in the existing shards.
+    pub existing: u64,
+    // Written samples by the result of their game, see OUTCOMES.
+    pub outcomes: [u64; 3],
+    // Size of the serialized samples, including the line breaks.
+    pub bytes: u64,
+    // Archives that could not be read to the end and games that could not be
+    // decoded, which were skipped.
+    pub errors: Vec<String>,
+    // Indices of the archives with errors in `Inputs::archives`.
+    pub failed_archives: BTreeSet<usize>,
+    // Highest index of the shards written, None for dry runs.
+    pub last_shard: Option<usize>,
+}
+
+// A unit of work passed between the stages.
+struct Item<T> {
+    // Position of the game in the input stream.
+    seq: u64,
+    // Index of the archive in `Inputs::archives` and of the entry within it.
+    archive: usize,
+    entry: usize,
+    // Byte offset of the entry data within the archive.
+    offset: u64,
+    // Memory reserved for the game until it is written.
+    footprint: u64,
+    payload: T,
+}
+
+// The lines of a game with the index of their writer and the result of the
+// game for the side to move.
+type SerializedGame = preprocessing::Result<Vec<(usize, f32, String)>>;
+
+impl<T> Item<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Item<U> {
+        Item {
+            seq: self.seq,
+            archive: self.archive,
+            entry: self.entry,
+            offset: self.offset,
+            footprint: self.footprint,
+            payload: f(self.payload),
+        }
+    }
+}
+
+pub fn run(
+    inputs: Inputs,
+    threads: Option<usize>,
+    budget: &MemoryBudget,
+    processing: Processing,
+    output: Output,
+) -> io::Result<Summary> {
+    // Unreadable archives fail when they are read, see read_archives.
+    let archive_sizes: Vec<u64> = inputs
+        .archives
+        .iter()
+        .map(|path| source::size(path).unwrap_or(0))
+        .collect();
+    let progress = Progress::new(archive_sizes.iter().sum(), inputs.archives.len());
+    metrics::track(progress.shared_counters());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .map_err(io::Error::other)?;
+
+    let Processing {
+        filters,
+        augment,
+        fields,
+        resampler,
+        outcome_resampler,
+        split_by_phase,
+        existing_positions,
+    } = processing;
+    let augment = &augment;
+    let mut decode = filters.decode_options();
+    decode.history = fields.lc0_planes;
+
+    let capacity = match budget.limit() {
+        Some(limit) => (limit / TYPICAL_GAME_FOOTPRINT).clamp(1, CHANNEL_CAPACITY as u64) as usize,
+        None => CHANNEL_CAPACITY,
+    };
+    let (raw_tx, raw_rx) = bounded(capacity);
+    let (parsed_tx, parsed_rx) = bounded(capacity);
+    let (filtered_tx, filtered_rx) = bounded(capacity);
+    let (serialized_tx, serialized_rx) = bounded(capacity);
+    // Every game takes a permit when it is read and returns it once it is
+    // written.
+    let (permits_tx, permits_rx) = bounded(MAX_GAMES_IN_FLIGHT);
+    // The positions are held until the end of the run.
+    let held = existing_positions
+        .as_ref()
+        .map_or(0, ExistingPositions::bytes);
+    budget.hold(held);
+    let shared = Shared {
+        inputs: &inputs,
+        progress: &progress,
+        budget,
+        stop: AtomicBool::new(false),
+        errors: Mutex::new(Vec::new()),
+    };
+    let shared = &shared;
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            if inputs.interleave > 1 {
+                read_interleaved(shared, raw_tx, permits_tx)
+            } else {
+                read_archives(shared, &archive_sizes, raw_tx, permits_tx)
+            }
+        });
+        let progress = &progress;
+        let archives = &inputs.archives;
+        let decoder = scope.spawn(move || {
+            pool.install(|| {
+                raw_rx.into_iter().par_bridge().try_for_each_with(
+                    parsed_tx,
+                    |tx, item: Item<Vec<u8>>| {
+                        let _span = trace_span!(
+                            "game",
+                            archive = %archives[item.archive],
+                            entry = item.entry
+                        )
+                        .entered();
+                        let provenance = fields.provenance.map(|_| Provenance {
+                            archive: archives[item.archive].clone(),
+                            entry: item.entry,
+                            game: item.seq,
+                            offset: item.offset,
+                            len: item.payload.len() as u64,
+                        });
+                        let item = item.map(|data| {
+                            Game::read_from(data.as_slice(), decode).map(|mut game| {
+                                game.provenance = provenance;
+                                game
+                            })
+                        });
+                        if let Ok(game) = &item.payload {
+                            trace!(samples = game.samples.len(), "decoded game");
+                            progress.game_decoded(game.samples.len());
+                        }
+                        // Only fails once the later stages stopped, the game
+                        // is not needed then.
+                        tx.send(item).map_err(drop)
+                    },
+                )
+            })
+        });
+        let filter = scope.spawn(move || {
+            for item in parsed_rx {
+                let item = item.map(|game| {
+                    game.map(|mut game| {
+                        game.samples
+                            .retain(|sample| match filter_position(sample, &filters) {
+                                Some(filter) => {
+                                    progress.sample_filtered(filter);
+                                    false
+                                }
+                                None => {
+                                    progress.sample_kept();
+                                    true
+                                }
+                            });
+                        game.samples = with_augmented(game.samples, augment);
+                        game
+                    })
+                });
+                if filtered_tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        let serializer = scope.spawn(move || {
+            for item in filtered_rx {
+                let item = item.map(|game| {
+                    game.map(|game| {
+                        game.samples
+                            .iter()
+                            .map(|sample| {
+                                let writer = if split_by_phase {
+                                    Phase::from_score(phase::score(sample)) as usize
+                                } else {
+                                    0
+                                };
+                                let line = serialize_position(sample, &game, fields);
+                                (writer, sample.result_q, line)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                });
+                if serialized_tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        let written = write_samples(
+            shared,
+            serialized_rx,
+            permits_rx,
+            resampler,
+            outcome_resampler,
+            existing_positions,
+            output,
+        );
+        // The games of a failed writer are never released, the reader would
+        // wait for their memory forever.
+        if written.is_err() {
+            budget.interrupt();
+        }
+        budget.release_held(held);
+
+        // Downstream stages only stop early if the writer failed, so its error
+        // is the most relevant one. Failed sends in the upstream stages are
+        // consequences of it. Reaching the limit stops the reader instead.
+        reader.join().expect("reader panicked");
+        let _ = decoder.join().expect("decoder panicked");
+        filter.join().expect("filter panicked");
+        serializer.join().expect("serializer panicked");
+        progress.finish();
+        let written = written?;
+        // The threads borrowing the errors only end with the scope.
+        let errors = mem::take(
+            &mut *shared
+                .errors
+                .lock()
+                .expect("no thread panicked with the errors"),
+        );
+
+        let counters = progress.counters();
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let summary = Summary {
+            archives: get(&counters.archives),
+            games: get(&counters.games),
+            samples: get(&counters.samples),
+            kept: written.samples,
+            filtered: counters.filtered.each_ref().map(get),
+            resampled: written.resampled,
+            balanced: written.balanced,
+            existing: written.existing,
+            outcomes: written.outcomes,
+            bytes: written.bytes,
+            failed_archives: errors.iter().map(|(archive, _)| *archive).collect(),
+            errors: errors.into_iter().map(|(_, err)| err).collect(),
+            last_shard: written.last_shard,
+        };
+        info!(
+            games = summary.games,
+            samples = summary.samples,
+            kept = summary.kept,
+            losses = summary.outcomes[0],
+            draws = summary.outcomes[1],
+            wins = summary.outcomes[2],
+            bytes = summary.bytes,
+            errors = summary.errors.len(),
+            "finished processing"
+        );
+        Ok(summary)
+    })
+}
+
+// State of a run shared by the reader and the writer.
+struct Shared<'a> {
+    inputs: &'a Inputs,
+    progress: &'a Progress,
+    budget: &'a MemoryBudget,
+    // Set by the writer once the sample limit is reached, the reader stops.
+    // The reader also stops on SIGINT and SIGTERM, see signals.
+    stop: AtomicBool,
+    // Indices of the archives that could not be read to the end, with the
+    // errors.
+    errors: Mutex<Vec<(usize, String)>>,
+}
+
+fn read_archives(
+    shared: &Shared,
+    archive_sizes: &[u64],
+    tx: Sender<Item<Vec<u8>>>,
+    permits: Sender<()>,
+) {
+    let Shared {
+        inputs,
+        progress,
+        budget,
+        stop,
+        errors,
+    } = shared;
+    let mut seq = 0;
+    // Total size of the archives preceding the current one.
+    let mut offset = 0;
+    for (archive_index, path) in inputs.archives.iter().enumerate() {
+        if archive_index > 0 {
+            offset += archive_sizes[archive_index - 1];
+        }
+        if archive_index < inputs.first_archive {
+            continue;
+        }
+        let _span = info_span!("archive", path = %path).entered();
+        progress.archive_started();
+        let first_entry = if archive_index == inputs.first_archive {
+            inputs.first_entry
+        } else {
+            0
+        };
+        let size = archive_sizes[archive_index];
+        debug!(size, first_entry, "reading archive");
+
+        let result = source::for_each_game_filtered(
+            path,
+            inputs.format,
+            inputs.pgn_filter,
+            first_entry,
+            |game| {
+                let stopped = stop.load(Ordering::Relaxed) || signals::interrupted();
+                if stopped || inputs.max_games.is_some_and(|max| seq >= max) {
+                    return false;
+                }
+                progress.set_position(offset + game.end_offset);
+
+                let footprint = game.data.len() as u64 * FOOTPRINT_PER_COMPRESSED_BYTE;
+                // Only fails if the writer failed.
+                if !budget.reserve(footprint) {
+                    return false;
+                }
+
+                let item = Item {
+                    seq,
+                    archive: archive_index,
+                    entry: game.index,
+                    offset: game.end_offset - game.data.len() as u64,
+                    footprint,
+                    payload: game.data,
+                };
+                seq += 1;
+                // Both sends only fail if the writer stopped.
+                permits.send(()).is_ok() && tx.send(item).is_ok()
+            },
+        );
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            // The games read before the error are processed.
+            Err(err) => {
+                let err = format!("{path}: {err}");
+                warn!(%err, "skipping the rest of the archive");
+                progress.error();
+                errors
+                    .lock()
+                    .expect("no thread panicked with the errors")
+                    .push((archive_index, err));
+            }
+        }
+    }
+}
+
+// Totals of the writer.
+struct Written {
+    samples: u64,
+    bytes: u64,
+    resampled: u64,
+    balanced: u64,
+    existing: u64,
+    outcomes: [u64; 3],
+    last_shard: Option<usize>,
+}
+
+// Reads up to `inputs.interleave` archives at the same time, each on a thread
+// of its own, and passes their games on round-robin: a game of every open
+// archive in turn. The next archive is opened once one is exhausted.
+fn read_interleaved(shared: &Shared, tx: Sender<Item<Vec<u8>>>, permits: Sender<()>) {
+    let Shared {
+        inputs,
+        progress,
+        budget,
+        stop,
+        errors,
+    } = shared;
+    let mut seq = 0;
+    // Position within every archive, the progress is their sum.
+    let mut positions = vec![0; inputs.archives.len()];
+    let mut remaining = inputs.first_archive..inputs.archives.len();
+    thread::scope(|scope| {
+        let mut open = VecDeque::with_capacity(inputs.interleave);
+        loop {
+            while open.len() < inputs.interleave {
+                let Some(archive_index) = remaining.next() else {
+                    break;
+                };
+                let path = &inputs.archives[archive_index];
+                let first_entry = if archive_index == inputs.first_archive {
+                    inputs.first_entry
+                } else {
+                    0
+                };
+                progress.archive_started();
+                let (games_tx, games_rx) = bounded(GAMES_AHEAD_PER_ARCHIVE);
+                scope.spawn(move || {
+                    let _span = info_span!("archive", path = %path).entered();
+                    debug!(first_entry, "reading archive");
+                    // Sending fails once the reading stopped.
+                    let result = source::for_each_game_filtered(
+                        path,
+                        inputs.format,
+                        inputs.pgn_filter,
+                        first_entry,
+                        |game| games_tx.send(game).is_ok(),
+                    );
+                    if let Err(err) = result {
+                        let err = format!("{path}: {err}");
+                        warn!(%err, "skipping the rest of the archive");
+                        progress.error();
+                        errors
+                            .lock()
+                            .expect("no thread panicked with the errors")
+                            .push((archive_index, err));
+                    }
+                });
+                open.push_back((archive_index, games_rx));
+            }
+
+            let Some((archive_index, games)) = open.pop_front() else {
+                break;
+            };
+            // The archive is exhausted once its reader is done.
+            let Ok(game) = games.recv() else {
+                continue;
+            };
+            let stopped = stop.load(Ordering::Relaxed) || signals::interrupted();
+            if stopped || inputs.max_games.is_some_and(|max| seq >= max) {
+                break;
+            }
+            positions[archive_index] = game.end_offset;
+            progress.set_position(positions.iter().sum());
+
+            let footprint = game.data.len() as u64 * FOOTPRINT_PER_COMPRESSED_BYTE;
+            if !budget.reserve(footprint) {
+                break;
+            }
+            let item = Item {
+                seq,
+                archive: archive_index,
+                entry: game.index,
+                offset: game.end_offset - game.data.len() as u64,
+                footprint,
+                payload: game.data,
+            };
+            seq += 1;
+            if !(permits.send(()).is_ok() && tx.send(item).is_ok()) {
+                break;
+            }
+            open.push_back((archive_index, games));
+        }
+        // Dropping the receivers of the open archives stops their readers
+        // before the scope waits for them.
+        drop(open);
+    });
+}
+
+fn write_samples(
+    shared: &Shared,
+    rx: Receiver<Item<SerializedGame>>,
+    permits: Receiver<()>,
+    mut resampler: Option<Resampler>,
+    mut outcome_resampler: Option<Resampler>,
+    existing_positions: Option<ExistingPositions>,
+    mut output: Output,
+) -> io::Result<Written> {
+    let Shared {
+        inputs,
+        progress,
+        budget,
+        stop,
+        errors,
+    } = shared;
+    // Games that arrived before the ones preceding them in the input.
+    let mut pending = BTreeMap::new();
+    let mut next_seq = 0;
+    // Position right after the last written game.
+    let mut position = (inputs.first_archive, inputs.first_entry);
+    let mut games_since_checkpoint = 0;
+    let checkpointed = inputs.interleave <= 1;
+    let mut samples = 0;
+    let mut bytes = 0;
+    let mut resampled = 0;
+    let mut balanced = 0;
+    let mut existing = 0;
+    let mut outcomes = [0; 3];
+
+    for item in rx {
+        pending.insert(item.seq, item);
+
+        while let Some(item) = pending.remove(&next_seq) {
+            next_seq += 1;
+            let _ = permits.recv();
+            budget.release(item.footprint);
+            // The games read ahead of the limit still pass through, so that
+            // the earlier stages are not blocked until the reader stops.
+            if stop.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let archive = &inputs.archives[item.archive];
+            position = (item.archive, item.entry + 1);
+            let lines = match item.payload {
+                Ok(lines) => lines,
+                Err(err) => {
+                    let err = err.in_archive(archive, item.entry, item.offset).to_string();
+                    warn!(%err, "skipping the game");
+                    progress.error();
+                    errors
+                        .lock()
+                        .expect("no thread panicked with the errors")
+                        .push((item.archive, err));
+                    Vec::new()
+                }
+            };
+            for (writer, result_q, line) in lines {
+                // The rest of the game is skipped when resuming after the
+                // limit.
+                if inputs.max_samples.is_some_and(|max| samples >= max) {
+                    break;
+                }
+                if let Some(positions) = &existing_positions {
+                    if positions.contains(position_hash(&OutputSample::parse(&line)?)) {
+                        existing += 1;
+                        continue;
+                    }
+                }
+                if let Some(resampler) = &mut resampler {
+                    if !resampler.keep(OutputSample::parse(&line)?.best_q) {
+                        resampled += 1;
+                        continue;
+                    }
+                }
+                if let Some(resampler) = &mut outcome_resampler {
+                    if !resampler.keep(result_q) {
+                        balanced += 1;
+                        continue;
+                    }
+                }
+                outcomes[outcome(result_q)] += 1;
+                samples += 1;
+                bytes += line.len() as u64 + 1;
+                if let Output::Shards { writers, sinks, .. } = &mut output {
+                    writers[writer].write_sample(&line)?;
+                    if !sinks.is_empty() {
+                        let sample = OutputSample::parse(&line)?;
+                        for sink in sinks.iter_mut() {
+                            sink.write(&sample)?;
+                        }
+                    }
+                }
+            }
+            if let Output::Shards { writers, .. } = &output {
+                let shards = writers.iter().map(|writer| writer.state().index + 1);
+                progress.set_shards(shards.sum());
+            }
+            if inputs.max_samples.is_some_and(|max| samples >= max) {
+                stop.store(true, Ordering::Relaxed);
+            }
+
+            if let Output::Shards {
+                writers,
+                checkpointer,
+                ..
+            } = &mut output
+            {
+                games_since_checkpoint += 1;
+                if checkpointed && games_since_checkpoint >= checkpointer.interval {
+                    checkpointer.save(
+                        &inputs.archives[position.0],
+                        position.1,
+                        writers,
+                        [resampler.as_ref(), outcome_resampler.as_ref()],
+                    )?;
+                    games_since_checkpoint = 0;
+                }
+            }
+        }
+    }
+
+    let mut last_shard = None;
+    if let Output::Shards {
+        mut writers,
+        checkpointer,
+        sinks,
+    } = output
+    {
+        if checkpointed {
+            checkpointer.save(
+                &inputs.archives[position.0],
+                position.1,
+                &mut writers,
+                [resampler.as_ref(), outcome_resampler.as_ref()],
+            )?;
+        } else {
+            for writer in &mut writers {
+                writer.flush()?;
+            }
+        }
+        last_shard = writers.iter().map(|writer| writer.state().index).max();
+        for sink in sinks {
+            let manifest = sink.finalize()?;
+            info!(
+                format = manifest.format,
+                files = manifest.files.len(),
+                samples = manifest.samples,
+                bytes = manifest.bytes,
+                "finalized the sink"
+            );
+        }
+    }
+    Ok(Written {
+        samples,
+        bytes,
+        resampled,
+        balanced,
+        existing,
+        outcomes,
+        last_shard,
+    })
+}
@@ -0,0 +1,181 @@
+// Shaping of the policy targets for the trainers.
+//
+// The training data stores the probabilities of the search policy with -1 for
+// the illegal moves. Trainers differ in the targets they expect: the search
+// distribution as is, a sharpened or flattened one, or only the best move.
+// Shaping the targets while exporting them saves the trainers another pass
+// over the data.
+//
+// A small fraction of the records has policy mass on moves that are illegal
+// in the position, which mask_illegal moves to the legal ones. The position
+// is reconstructed from the sample alone, so the castling rooks are derived
+// from the castling flags and the board, and en passant captures, whose
+// square is not stored, are kept whenever they may have been legal.
+
+use shakmaty::{
+    Bitboard, CastlingMode, Chess, Color, EnPassantMode, FromSetup, Position, Rank, Square,
+};
+
+use crate::moves::idx_from_move;
+use crate::sample::{CastlingBitboards, TrainingSample};
+
+/// Transformation of the policy targets. The illegal moves always get
+/// probability 0.
+#[derive(Debug, Clone, Copy)]
+pub struct PolicyOptions {
+    /// Positive exponent scaling the probabilities to p^(1 / temperature),
+    /// values below 1 sharpen the distribution and values above 1 flatten
+    /// it. The result is renormalized.
+    pub temperature: f32,
+    /// Rescale the probabilities of the legal moves to sum to 1, the stored
+    /// ones may be off due to rounding and pruned moves.
+    pub renormalize: bool,
+    /// Probability 1 for the best move and 0 for all others, overriding the
+    /// other options.
+    pub one_hot: bool,
+    /// Zero the probabilities of the moves that are illegal in the position
+    /// of the sample and renormalize the rest, see
+    /// [`PolicyOptions::apply_to_sample`].
+    pub mask_illegal: bool,
+}
+
+impl Default for PolicyOptions {
+    fn default() -> Self {
+        PolicyOptions {
+            temperature: 1.0,
+            renormalize: false,
+            one_hot: false,
+            mask_illegal: false,
+        }
+    }
+}
+
+impl PolicyOptions {
+    /// Shapes the stored policy into the target in place.
+    ///
+    /// ```
+    /// use preprocessing::policy::PolicyOptions;
+    ///
+    /// let mut policy = [0.6, -1.0, 0.2, 0.0];
+    /// let options = PolicyOptions {
+    ///     temperature: 0.5,
+    ///     ..PolicyOptions::default()
+    /// };
+    /// options.apply(&mut policy, 0);
+    /// assert!((policy[0] - 0.9).abs() < 1e-6);
+    /// assert_eq!(policy[1], 0.0);
+    /// assert!((policy[2] - 0.1).abs() < 1e-6);
+    ///
+    /// let one_hot = PolicyOptions {
+    ///     one_hot: true,
+    ///     ..PolicyOptions::default()
+    /// };
+    /// one_hot.apply(&mut policy, 2);
+    /// assert_eq!(policy, [0.0, 0.0, 1.0, 0.0]);
+    /// ```
+    pub fn apply(&self, policy: &mut [f32], best_idx: u16) {
+        if self.one_hot {
+            policy.fill(0.0);
+            if let Some(p) = policy.get_mut(best_idx as usize) {
+                *p = 1.0;
+            }
+            return;
+        }
+        for p in policy.iter_mut() {
+            *p = p.max(0.0);
+        }
+        let scaled = self.temperature != 1.0;
+        if scaled {
+            let exponent = 1.0 / self.temperature;
+            for p in policy.iter_mut() {
+                *p = p.powf(exponent);
+            }
+        }
+        if self.renormalize || self.mask_illegal || scaled {
+            let sum: f32 = policy.iter().sum();
+            if sum > 0.0 {
+                for p in policy.iter_mut() {
+                    *p /= sum;
+                }
+            }
+        }
+    }
+
+    /// Shapes the stored policy of the sample into the target in place,
+    /// masking the illegal moves first if enabled. Samples with an illegal
+    /// position are shaped without the mask.
+    ///
+    /// ```
+    /// use preprocessing::policy::PolicyOptions;
+    /// use preprocessing::record::{POLICY_SIZE, RECORD_SIZE};
+    /// use preprocessing::sample::planes;
+    /// use preprocessing::{DecodeOptions, TrainingSample, MOVE_TO_IDX};
+    /// use shakmaty::{Chess, Color, Position};
+    ///
+    /// let mut record = vec![0; RECORD_SIZE];
+    /// record[0] = 6;
+    /// let mut sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+    /// sample.bitboards = planes(Chess::default().board(), Color::White);
+    ///
+    /// // Half of the mass on a rook move through the own pawn.
+    /// let mut policy = vec![0.0; POLICY_SIZE];
+    /// policy[MOVE_TO_IDX["e2e4"] as usize] = 0.5;
+    /// policy[MOVE_TO_IDX["a1a8"] as usize] = 0.5;
+    /// let options = PolicyOptions {
+    ///     mask_illegal: true,
+    ///     ..PolicyOptions::default()
+    /// };
+    /// options.apply_to_sample(&mut policy, &sample);
+    /// assert_eq!(policy[MOVE_TO_IDX["e2e4"] as usize], 1.0);
+    /// assert_eq!(policy[MOVE_TO_IDX["a1a8"] as usize], 0.0);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn apply_to_sample(&self, policy: &mut [f32], sample: &TrainingSample) {
+        if self.mask_illegal && !self.one_hot {
+            if let Some(legal) = legal_indices(sample) {
+                for (idx, p) in policy.iter_mut().enumerate() {
+                    if legal.binary_search(&(idx as u16)).is_err() {
+                        *p = 0.0;
+                    }
+                }
+            }
+        }
+        self.apply(policy, sample.best_idx);
+    }
+}
+
+/// Sorted indices of the moves of the policy head that are legal in the
+/// position of the sample, `None` if the position is illegal. En passant
+/// captures count as legal whenever a pawn of the opponent may have just
+/// moved two squares.
+pub fn legal_indices(sample: &TrainingSample) -> Option<Vec<u16>> {
+    let castling = CastlingBitboards::from_game(std::slice::from_ref(sample));
+    let pos = sample.to_position(&castling).ok()?;
+    let mut legal: Vec<u16> = pos
+        .legal_moves()
+        .iter()
+        .filter_map(|m| idx_from_move(m, Color::White))
+        .collect();
+
+    let board = pos.board();
+    let pawns = board.pawns() & board.by_color(Color::Black) & Bitboard::from_rank(Rank::Fifth);
+    for pawn in pawns {
+        let passed = Square::from_coords(pawn.file(), Rank::Sixth);
+        let origin = Square::from_coords(pawn.file(), Rank::Seventh);
+        if board.occupied().contains(passed) || board.occupied().contains(origin) {
+            continue;
+        }
+        let mut setup = pos.clone().into_setup(EnPassantMode::Always);
+        setup.ep_square = Some(passed);
+        if let Ok(pos) = Chess::from_setup(setup, CastlingMode::Chess960) {
+            legal.extend(
+                pos.en_passant_moves()
+                    .iter()
+                    .filter_map(|m| idx_from_move(m, Color::White)),
+            );
+        }
+    }
+    legal.sort_unstable();
+    legal.dedup();
+    Some(legal)
+}
@@ -0,0 +1,196 @@
+// Opening books in the Polyglot format, read by the engine and written by the
+// book command.
+//
+// A book is a sequence of 16-byte entries sorted by the key, all integers
+// big-endian:
+//
+//     key      u64, the Polyglot hash of the position
+//     move     u16, to square (bits 0-5), from square (6-11), promotion (12-14)
+//     weight   u16, relative frequency of the move among the moves of the key
+//     learn    u32, unused
+//
+// The squares count from a1 = 0 to h8 = 63 and the promotions from knight = 1
+// to queen = 4. Castling is encoded as the king capturing its own rook, like
+// in shakmaty. The Polyglot keys are the Zobrist hashes of shakmaty, with the
+// en passant square only if a pawn could capture on it.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, EnPassantMode, Move, Position, Role};
+
+use crate::rng::Rng;
+
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BookEntry {
+    pub key: u64,
+    pub raw_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+/// The Polyglot hash of the position.
+///
+/// ```
+/// use preprocessing::polyglot::key;
+/// use shakmaty::Chess;
+///
+/// assert_eq!(key(&Chess::default()), 0x463b96181691fc9c);
+/// ```
+pub fn key(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal).0
+}
+
+pub fn encode_move(m: &Move) -> u16 {
+    let from = m.from().map_or(0, |square| square as u16);
+    let promotion = m
+        .promotion()
+        .map_or(0, |role| role as u16 - Role::Pawn as u16);
+    (promotion << 12) | (from << 6) | m.to() as u16
+}
+
+/// The legal move of the position encoded by `raw_move`.
+///
+/// ```
+/// use preprocessing::polyglot::{decode_move, encode_move};
+/// use shakmaty::fen::Fen;
+/// use shakmaty::{CastlingMode, Chess, Position};
+///
+/// let fen: Fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".parse().unwrap();
+/// let pos: Chess = fen.into_position(CastlingMode::Standard).unwrap();
+/// for m in pos.legal_moves() {
+///     assert_eq!(decode_move(encode_move(&m), &pos), Some(m));
+/// }
+/// // e1h1, short castling.
+/// assert!(decode_move(0x0107, &pos).is_some_and(|m| m.is_castle()));
+/// ```
+pub fn decode_move(raw_move: u16, pos: &Chess) -> Option<Move> {
+    pos.legal_moves()
+        .into_iter()
+        .find(|m| encode_move(m) == raw_move)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Writes the entries, which have to be sorted.
+pub fn write<W: Write>(entries: &[BookEntry], mut writer: W) -> io::Result<()> {
+    for entry in entries {
+        writer.write_all(&entry.key.to_be_bytes())?;
+        writer.write_all(&entry.raw_move.to_be_bytes())?;
+        writer.write_all(&entry.weight.to_be_bytes())?;
+        writer.write_all(&entry.learn.to_be_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Reads the entries of a book written by [`write`] or any Polyglot tool.
+pub fn read<R: Read>(mut reader: R) -> io::Result<Vec<BookEntry>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() % ENTRY_SIZE != 0 {
+        return Err(invalid(format!(
+            "the size {} is not a multiple of the entry size",
+            bytes.len()
+        )));
+    }
+    let entries: Vec<BookEntry> = bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| BookEntry {
+            key: u64::from_be_bytes(entry[0..8].try_into().expect("8 bytes")),
+            raw_move: u16::from_be_bytes(entry[8..10].try_into().expect("2 bytes")),
+            weight: u16::from_be_bytes(entry[10..12].try_into().expect("2 bytes")),
+            learn: u32::from_be_bytes(entry[12..16].try_into().expect("4 bytes")),
+        })
+        .collect();
+    if !entries.is_sorted_by_key(|entry| entry.key) {
+        return Err(invalid("the entries are not sorted by key"));
+    }
+    Ok(entries)
+}
+
+/// An opening book loaded into memory.
+///
+/// ```
+/// use preprocessing::polyglot::{encode_move, key, write, Book, BookEntry};
+/// use preprocessing::rng::Rng;
+/// use shakmaty::{Chess, Position};
+///
+/// let pos = Chess::default();
+/// let e4 = pos.legal_moves().into_iter().find(|m| m.to().to_string() == "e4").unwrap();
+/// let entry = BookEntry { key: key(&pos), raw_move: encode_move(&e4), weight: 1, learn: 0 };
+/// let mut bytes = Vec::new();
+/// write(&[entry], &mut bytes)?;
+///
+/// let book = Book::read(bytes.as_slice())?;
+/// assert_eq!(book.moves(&pos), [(e4.clone(), 1)]);
+/// assert_eq!(book.pick(&pos, &mut Rng::new(42)), Some(e4));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Book {
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        Ok(Book {
+            entries: read(reader)?,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        Book::read(BufReader::new(file))
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The legal moves of the position in the book with their weights.
+    /// Entries with moves that are not legal in the position are skipped.
+    pub fn moves(&self, pos: &Chess) -> Vec<(Move, u16)> {
+        let key = key(pos);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| Some((decode_move(entry.raw_move, pos)?, entry.weight)))
+            .collect()
+    }
+
+    /// A move of the position picked at random with the probabilities of
+    /// the weights, None if the position is not in the book or all of its
+    /// moves have weight 0.
+    pub fn pick(&self, pos: &Chess, rng: &mut Rng) -> Option<Move> {
+        let moves = self.moves(pos);
+        let total: u64 = moves.iter().map(|&(_, weight)| weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut target = rng.below(total);
+        for (m, weight) in moves {
+            if target < weight as u64 {
+                return Some(m);
+            }
+            target -= weight as u64;
+        }
+        unreachable!("the target is below the total weight")
+    }
+}
+
+/// Writes the entries, which have to be sorted, to a file.
+pub fn save<P: AsRef<Path>>(entries: &[BookEntry], path: P) -> io::Result<()> {
+    write(entries, BufWriter::new(File::create(path)?))
+}
@@ -0,0 +1,377 @@
+// Preprocessing as a single library call: PreprocessOptions describes a run
+// and PreprocessOptions::run processes the archives and returns the totals.
+//
+// The games are read in batches, which are decoded, filtered and serialized
+// in parallel and written in the order of the input, so the output does not
+// depend on the number of threads. Unreadable archives and games that fail to
+// decode are skipped and reported in the summary, only failures to write the
+// output stop the run.
+//
+// The samples are written to any number of sinks (see the sink module), e.g.
+// sharded text files and a single file of lines at the same time, and the
+// games can be passed to a visitor for custom analysis (see the visit module).
+//
+// The preprocess command of the binary runs the same steps as a pipeline of
+// stages with checkpoints, progress reporting and a memory budget on top.
+
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use crate::augment::{with_augmented, Augmentation};
+use crate::error::PreprocessError;
+use crate::filter::{filter_position, Filter, FilterOptions};
+use crate::output::{serialize_position, OutputFields, OutputSample};
+use crate::reader::GameEntry;
+use crate::sample::{Game, Provenance};
+use crate::sink::{Manifest, SampleSink};
+use crate::source::{self, InputFormat, PgnFilter};
+use crate::visit::{GameMeta, GameVisitor};
+
+// Number of games decoded in parallel before they are written.
+const BATCH_SIZE: usize = 256;
+
+/// Configuration of a preprocessing run, see [`PreprocessOptions::run`]. All
+/// options but the input archives have defaults: the default filters, no
+/// optional fields, no augmentations, a thread per core, no limits and no
+/// output.
+///
+/// ```no_run
+/// use preprocessing::output::{OutputFields, ShardWriter};
+/// use preprocessing::phase::Phase;
+/// use preprocessing::sink::LineSink;
+/// use preprocessing::{FilterOptions, PreprocessOptions};
+///
+/// let summary = PreprocessOptions::new(["training-1.tar", "training-2.tar"])
+///     .filters(FilterOptions {
+///         phases: vec![Phase::Middlegame],
+///         ..FilterOptions::default()
+///     })
+///     .fields(OutputFields {
+///         eco: true,
+///         ..OutputFields::default()
+///     })
+///     .sink(ShardWriter::create("out", 1_000_000)?)
+///     .sink(LineSink::create("all.txt")?)
+///     .threads(8)
+///     .max_samples(10_000_000)
+///     .run()?;
+/// println!("kept {} of {} samples", summary.kept, summary.samples);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct PreprocessOptions {
+    archives: Vec<PathBuf>,
+    input_format: Option<InputFormat>,
+    pgn_filter: PgnFilter,
+    filters: FilterOptions,
+    fields: OutputFields,
+    augment: Vec<Augmentation>,
+    sinks: Vec<Box<dyn SampleSink>>,
+    threads: Option<usize>,
+    max_games: Option<u64>,
+    max_samples: Option<u64>,
+}
+
+/// Totals of a finished run.
+#[derive(Debug, Default)]
+pub struct PreprocessSummary {
+    /// Number of archives the run started reading.
+    pub archives: u64,
+    pub games: u64,
+    pub samples: u64,
+    /// Written samples, including the augmented copies.
+    pub kept: u64,
+    /// Samples dropped by every filter, indexed by [`Filter`] as usize.
+    pub filtered: [u64; Filter::ALL.len()],
+    /// Size of the written samples, including the line breaks.
+    pub bytes: u64,
+    /// Archives that could not be read to the end and games that could not
+    /// be decoded, which were skipped.
+    pub errors: Vec<PreprocessError>,
+    /// What the sinks wrote, in the order they were added.
+    pub manifests: Vec<Manifest>,
+}
+
+impl PreprocessOptions {
+    /// Processes the inputs in the given order: tar archives, directories of
+    /// them and the other sources of [`source::open`].
+    pub fn new<I, P>(archives: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        PreprocessOptions {
+            archives: archives.into_iter().map(Into::into).collect(),
+            input_format: None,
+            pgn_filter: PgnFilter::default(),
+            filters: FilterOptions::default(),
+            fields: OutputFields::default(),
+            augment: Vec::new(),
+            sinks: Vec::new(),
+            threads: None,
+            max_games: None,
+            max_samples: None,
+        }
+    }
+
+    /// Reads all inputs in this format instead of the one
+    /// [`source::detect`] recognizes.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.input_format = Some(format);
+        self
+    }
+
+    /// Reads only the games of PGN inputs the filter accepts.
+    pub fn pgn_filter(mut self, filter: PgnFilter) -> Self {
+        self.pgn_filter = filter;
+        self
+    }
+
+    pub fn filters(mut self, filters: FilterOptions) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Optional fields written after the required ones.
+    pub fn fields(mut self, fields: OutputFields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Transformed copies written after every kept sample.
+    pub fn augment(mut self, augment: Vec<Augmentation>) -> Self {
+        self.augment = augment;
+        self
+    }
+
+    /// Adds a sink the samples are written to, every sink gets all of them.
+    pub fn sink(mut self, sink: impl SampleSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Number of threads decoding the games, 0 for a thread per core.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Stops after reading this many games.
+    pub fn max_games(mut self, max_games: u64) -> Self {
+        self.max_games = Some(max_games);
+        self
+    }
+
+    /// Stops after writing this many samples.
+    pub fn max_samples(mut self, max_samples: u64) -> Self {
+        self.max_samples = Some(max_samples);
+        self
+    }
+
+    /// Processes the archives. Fails only if the output cannot be written.
+    ///
+    /// ```
+    /// use preprocessing::rng::Rng;
+    /// use preprocessing::synthetic::{gzip, random_game, tar};
+    /// use preprocessing::PreprocessOptions;
+    ///
+    /// let mut rng = Rng::new(42);
+    /// let games: Vec<Vec<u8>> = (0..4).map(|_| gzip(&random_game(&mut rng, 40))).collect();
+    /// let path = std::env::temp_dir().join("preprocess-options-doctest.tar");
+    /// std::fs::write(&path, tar(&games)?)?;
+    ///
+    /// let summary = PreprocessOptions::new([&path]).max_games(3).run()?;
+    /// assert_eq!(summary.games, 3);
+    /// let filtered: u64 = summary.filtered.iter().sum();
+    /// assert_eq!(summary.kept + filtered, summary.samples);
+    /// assert!(summary.errors.is_empty());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn run(self) -> io::Result<PreprocessSummary> {
+        self.visit(&mut ())
+    }
+
+    /// Processes the archives like [`PreprocessOptions::run`] and passes the
+    /// games to the visitor.
+    pub fn visit(self, visitor: &mut dyn GameVisitor) -> io::Result<PreprocessSummary> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()
+            .map_err(io::Error::other)?;
+        let mut run = Run {
+            filters: self.filters,
+            fields: self.fields,
+            augment: self.augment,
+            max_samples: self.max_samples,
+            pool,
+            sinks: self.sinks,
+            visitor,
+            summary: PreprocessSummary::default(),
+        };
+
+        let mut games_read = 0;
+        let games_left = |read: u64| self.max_games.is_none_or(|max| read < max);
+        for archive in &self.archives {
+            if run.done() || !games_left(games_read) {
+                break;
+            }
+            run.summary.archives += 1;
+            let mut batch = Vec::new();
+            let mut failed = None;
+            let result = source::for_each_game_filtered(
+                archive,
+                self.input_format,
+                self.pgn_filter,
+                0,
+                |entry| {
+                    if !games_left(games_read) {
+                        return false;
+                    }
+                    games_read += 1;
+                    batch.push(entry);
+                    if batch.len() < BATCH_SIZE {
+                        return true;
+                    }
+                    if let Err(err) = run.process(archive, mem::take(&mut batch)) {
+                        failed = Some(err);
+                        return false;
+                    }
+                    !run.done()
+                },
+            );
+            if let Some(err) = failed {
+                return Err(err);
+            }
+            // The games read before an error are processed.
+            run.process(archive, batch)?;
+            if let Err(err) = result {
+                run.summary.errors.push(err.of_archive(archive));
+            }
+        }
+        run.finish()
+    }
+}
+
+// A decoded game with its kept samples serialized.
+struct Processed {
+    entry: usize,
+    game: Game,
+    samples: u64,
+    filtered: [u64; Filter::ALL.len()],
+    lines: Vec<String>,
+}
+
+struct Run<'a> {
+    filters: FilterOptions,
+    fields: OutputFields,
+    augment: Vec<Augmentation>,
+    max_samples: Option<u64>,
+    pool: ThreadPool,
+    sinks: Vec<Box<dyn SampleSink>>,
+    visitor: &'a mut dyn GameVisitor,
+    summary: PreprocessSummary,
+}
+
+impl Run<'_> {
+    fn done(&self) -> bool {
+        self.max_samples.is_some_and(|max| self.summary.kept >= max)
+    }
+
+    fn process(&mut self, archive: &Path, games: Vec<GameEntry>) -> io::Result<()> {
+        let mut decode = self.filters.decode_options();
+        decode.history = self.fields.lc0_planes;
+        let (filters, fields, augment) = (&self.filters, self.fields, &self.augment);
+        let first_game = self.summary.games;
+        let processed: Vec<Result<Processed, PreprocessError>> = self.pool.install(|| {
+            games
+                .into_par_iter()
+                .enumerate()
+                .map(|(idx, entry)| {
+                    let offset = entry.end_offset - entry.data.len() as u64;
+                    let mut game = Game::read_from(entry.data.as_slice(), decode)
+                        .map_err(|err| err.in_archive(archive, entry.index, offset))?;
+                    if fields.provenance.is_some() {
+                        game.provenance = Some(Provenance {
+                            archive: archive.display().to_string(),
+                            entry: entry.index,
+                            game: first_game + idx as u64,
+                            offset,
+                            len: entry.data.len() as u64,
+                        });
+                    }
+                    let samples = game.samples.len() as u64;
+                    let mut filtered = [0; Filter::ALL.len()];
+                    game.samples
+                        .retain(|sample| match filter_position(sample, filters) {
+                            Some(filter) => {
+                                filtered[filter as usize] += 1;
+                                false
+                            }
+                            None => true,
+                        });
+                    game.samples = with_augmented(mem::take(&mut game.samples), augment);
+                    let lines = game
+                        .samples
+                        .iter()
+                        .map(|sample| serialize_position(sample, &game, fields))
+                        .collect();
+                    Ok(Processed {
+                        entry: entry.index,
+                        game,
+                        samples,
+                        filtered,
+                        lines,
+                    })
+                })
+                .collect()
+        });
+
+        for game in processed {
+            self.summary.games += 1;
+            let game = match game {
+                Ok(game) => game,
+                Err(err) => {
+                    self.summary.errors.push(err);
+                    continue;
+                }
+            };
+            self.summary.samples += game.samples;
+            for (total, count) in self.summary.filtered.iter_mut().zip(game.filtered) {
+                *total += count;
+            }
+            let meta = GameMeta {
+                archive,
+                entry: game.entry,
+                game: &game.game,
+                samples: game.samples,
+            };
+            self.visitor.on_game_start(&meta);
+            for (sample, line) in game.game.samples.iter().zip(&game.lines) {
+                if self.done() {
+                    break;
+                }
+                self.visitor.on_sample(sample);
+                if !self.sinks.is_empty() {
+                    let sample = OutputSample::parse(line)?;
+                    for sink in &mut self.sinks {
+                        sink.write(&sample)?;
+                    }
+                }
+                self.summary.kept += 1;
+                self.summary.bytes += line.len() as u64 + 1;
+            }
+            self.visitor.on_game_end(&meta);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<PreprocessSummary> {
+        for sink in self.sinks {
+            self.summary.manifests.push(sink.finalize()?);
+        }
+        Ok(self.summary)
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use preprocessing::Filter;
+
+// Set by the global --quiet option before any command starts.
+static HIDDEN: AtomicBool = AtomicBool::new(false);
+
+pub fn hide() {
+    HIDDEN.store(true, Ordering::Relaxed);
+}
+
+// Counters updated by the pipeline stages and rendered by the progress bar and
+// the metrics endpoint (see metrics).
+#[derive(Default)]
+pub struct Counters {
+    pub archives: AtomicU64,
+    // Position within the input archives.
+    pub bytes_read: AtomicU64,
+    pub games: AtomicU64,
+    pub samples: AtomicU64,
+    pub kept: AtomicU64,
+    pub filtered: [AtomicU64; Filter::ALL.len()],
+    // Unreadable archives and games.
+    pub errors: AtomicU64,
+    // Shards in the output directory, including the ones of earlier runs.
+    pub shards: AtomicU64,
+}
+
+// Progress of the run shown on stderr. The bar tracks the position within the
+// input archives, while the counters are updated from the worker threads and
+// only read when the bar is redrawn.
+pub struct Progress {
+    bar: ProgressBar,
+    counters: Arc<Counters>,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64, num_archives: usize) -> Self {
+        let counters = Arc::new(Counters::default());
+
+        let stats = {
+            let counters = Arc::clone(&counters);
+            move |_: &ProgressState, w: &mut dyn Write| {
+                let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+                let _ = write!(
+                    w,
+                    "archives {}/{num_archives} | games {} | samples {} | kept {}",
+                    get(&counters.archives),
+                    get(&counters.games),
+                    get(&counters.samples),
+                    get(&counters.kept),
+                );
+                for filter in Filter::ALL {
+                    let _ = write!(
+                        w,
+                        " | {} {}",
+                        filter.name(),
+                        get(&counters.filtered[filter as usize])
+                    );
+                }
+            }
+        };
+
+        let style = ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] {wide_bar} {binary_bytes}/{binary_total_bytes} \
+             ({binary_bytes_per_sec}, ETA {eta})\n{stats}",
+        )
+        .expect("valid template")
+        .with_key("stats", stats);
+
+        let bar = ProgressBar::new(total_bytes).with_style(style);
+        if HIDDEN.load(Ordering::Relaxed) {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        bar.enable_steady_tick(Duration::from_millis(200));
+
+        Progress { bar, counters }
+    }
+
+    pub fn archive_started(&self) {
+        self.counters.archives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Position within the concatenation of all input archives.
+    pub fn set_position(&self, bytes: u64) {
+        self.bar.set_position(bytes);
+        self.counters.bytes_read.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn game_decoded(&self, num_samples: usize) {
+        self.counters.games.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .samples
+            .fetch_add(num_samples as u64, Ordering::Relaxed);
+    }
+
+    pub fn sample_kept(&self) {
+        self.counters.kept.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sample_filtered(&self, filter: Filter) {
+        self.counters.filtered[filter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.counters.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_shards(&self, shards: usize) {
+        self.counters.shards.store(shards as u64, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    // The counters for readers that outlive the run.
+    pub fn shared_counters(&self) -> Arc<Counters> {
+        Arc::clone(&self.counters)
+    }
+
+    // Leaves the final state of the progress bar on the screen.
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
@@ -0,0 +1,57 @@
+// Minimal encoding of protobuf messages, enough for writing ONNX models and
+// TensorBoard events without generated code.
+
+// Message being encoded, the fields are appended in order.
+#[derive(Default)]
+pub struct Message(Vec<u8>);
+
+impl Message {
+    pub fn varint(mut self, field: u32, value: u64) -> Self {
+        self.tag(field, 0);
+        self.write_varint(value);
+        self
+    }
+
+    pub fn fixed64(mut self, field: u32, value: u64) -> Self {
+        self.tag(field, 1);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn fixed32(mut self, field: u32, value: u32) -> Self {
+        self.tag(field, 5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+        self.tag(field, 2);
+        self.write_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(self, field: u32, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn message(self, field: u32, value: Message) -> Self {
+        self.bytes(field, &value.0)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u8) {
+        self.write_varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
@@ -0,0 +1,288 @@
+// Quantization of the networks trained by the train command (`quantize`).
+//
+// The float weights are converted into the integer form evaluated by the
+// engine, see preprocessing::nnue::Network: 16-bit weights of the feature
+// transformer and 8-bit weights of the dense layers, scaled by a power of two
+// chosen per layer so that the largest weight still fits. The quantized net is
+// compared with the float one on a sample of positions from the preprocessed
+// data, the rounding must not move the predicted score by more than
+// --max-error. The result is written in the format of
+// preprocessing::network.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::dataset::IndexedDataset;
+use preprocessing::network;
+use preprocessing::nnue::{DenseLayer, FeatureSet, FeatureTransformer, Network, ACTIVATION_SCALE};
+use preprocessing::output::OutputSample;
+use preprocessing::rng::Rng;
+use safetensors::tensor::TensorView;
+use safetensors::{Dtype, SafeTensors};
+use shakmaty::fen::Fen;
+use shakmaty::{Board, Color};
+use tracing::info;
+
+// Largest scale of the dense layer weights, as in Stockfish.
+const MAX_WEIGHT_SHIFT: u32 = 6;
+
+// Names of the dense layers written by the train command, in order.
+pub const LAYERS: [&str; 3] = ["hidden1", "hidden2", "output"];
+
+#[derive(Args)]
+pub struct QuantizeArgs {
+    /// Float weights written by the train command (.safetensors)
+    #[arg(long)]
+    weights: PathBuf,
+
+    /// Path to write the quantized network to, in the attix network format
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Input features the network was trained with
+    #[arg(long, value_enum, default_value = "halfkp")]
+    features: FeatureSet,
+
+    /// Preprocessed data (directory with indexed shards) to compare the
+    /// quantized network with the float one on
+    #[arg(long)]
+    data: PathBuf,
+
+    /// Number of positions drawn from the data for the comparison
+    #[arg(long, default_value_t = 10_000)]
+    samples: u64,
+
+    /// Largest tolerated difference of the predicted expected scores (0 to 1)
+    #[arg(long, default_value_t = 0.05)]
+    max_error: f32,
+}
+
+pub struct FloatLayer {
+    pub inputs: usize,
+    // (outputs, inputs)
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl FloatLayer {
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        self.weights
+            .chunks_exact(self.inputs)
+            .zip(&self.biases)
+            .map(|(weights, &bias)| {
+                bias + input.iter().zip(weights).map(|(x, w)| x * w).sum::<f32>()
+            })
+            .collect()
+    }
+}
+
+// The network as trained, mirroring Network.
+pub struct FloatNetwork {
+    pub set: FeatureSet,
+    // (features, hidden)
+    pub transformer: Vec<f32>,
+    pub transformer_bias: Vec<f32>,
+    pub layers: Vec<FloatLayer>,
+}
+
+impl FloatNetwork {
+    pub fn load(path: &Path, set: FeatureSet) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let tensors = SafeTensors::deserialize(&data).map_err(io::Error::other)?;
+        let mut floats: HashMap<String, (Vec<usize>, Vec<f32>)> = HashMap::new();
+        for (name, view) in tensors.tensors() {
+            floats.insert(name, to_floats(&view)?);
+        }
+        let mut get = |name: &str| {
+            floats.remove(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: missing tensor {name}", path.display()),
+                )
+            })
+        };
+
+        let (shape, transformer) = get("transformer.weight")?;
+        if shape.first() != Some(&set.dimensions()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the network was not trained with {set:?} features"),
+            ));
+        }
+        let (_, transformer_bias) = get("transformer.bias")?;
+        let mut layers = Vec::with_capacity(LAYERS.len());
+        for name in LAYERS {
+            // (outputs, inputs) as in candle and torch.
+            let (shape, weights) = get(&format!("{name}.weight"))?;
+            let (_, biases) = get(&format!("{name}.bias"))?;
+            layers.push(FloatLayer {
+                inputs: shape.get(1).copied().unwrap_or(0),
+                weights,
+                biases,
+            });
+        }
+        Ok(FloatNetwork {
+            set,
+            transformer,
+            transformer_bias,
+            layers,
+        })
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.transformer_bias.len()
+    }
+
+    // Output of the network (a logit of the expected score of the side to
+    // move).
+    pub fn evaluate(&self, board: &Board) -> f32 {
+        let output = self.layers.last().expect("the network has layers");
+        output.forward(&self.embedding(board))[0]
+    }
+
+    // Activations of the last hidden layer, the inputs of the output layer.
+    pub fn embedding(&self, board: &Board) -> Vec<f32> {
+        let mut x = Vec::with_capacity(2 * self.hidden());
+        for perspective in [Color::White, Color::Black] {
+            let mut accumulator = self.transformer_bias.clone();
+            for feature in self.set.active_features(board, perspective) {
+                let offset = feature as usize * self.hidden();
+                let weights = &self.transformer[offset..offset + self.hidden()];
+                for (value, weight) in accumulator.iter_mut().zip(weights) {
+                    *value += weight;
+                }
+            }
+            x.extend(accumulator.iter().map(|value| value.clamp(0.0, 1.0)));
+        }
+        let (_, hidden) = self.layers.split_last().expect("the network has layers");
+        for layer in hidden {
+            x = layer
+                .forward(&x)
+                .iter()
+                .map(|value| value.clamp(0.0, 1.0))
+                .collect();
+        }
+        x
+    }
+
+    fn quantize(&self) -> io::Result<Network> {
+        let scale = ACTIVATION_SCALE as f32;
+        let to_i16 = |values: &[f32]| -> io::Result<Vec<i16>> {
+            values
+                .iter()
+                .map(|&value| {
+                    let quantized = (value * scale).round();
+                    if quantized.abs() > i16::MAX as f32 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("feature transformer weight {value} does not fit 16 bits"),
+                        ));
+                    }
+                    Ok(quantized as i16)
+                })
+                .collect()
+        };
+        let transformer = FeatureTransformer::new(
+            self.set,
+            to_i16(&self.transformer_bias)?,
+            to_i16(&self.transformer)?,
+        );
+
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let max = layer.weights.iter().fold(0f32, |max, w| max.max(w.abs()));
+                let shift = (0..=MAX_WEIGHT_SHIFT)
+                    .rev()
+                    .find(|&shift| max * (1 << shift) as f32 <= i8::MAX as f32)
+                    .unwrap_or(0);
+                let weight_scale = (1 << shift) as f32;
+                DenseLayer {
+                    inputs: layer.inputs,
+                    outputs: layer.biases.len(),
+                    // Weights that do not fit even without scaling saturate.
+                    weights: layer
+                        .weights
+                        .iter()
+                        .map(|w| (w * weight_scale).round().clamp(-127.0, 127.0) as i8)
+                        .collect(),
+                    biases: layer
+                        .biases
+                        .iter()
+                        .map(|b| (b * scale * weight_scale).round() as i32)
+                        .collect(),
+                    shift,
+                }
+            })
+            .collect();
+        Ok(Network {
+            transformer,
+            layers,
+        })
+    }
+}
+
+pub fn to_floats(view: &TensorView) -> io::Result<(Vec<usize>, Vec<f32>)> {
+    if view.dtype() != Dtype::F32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected f32 weights, got {:?}", view.dtype()),
+        ));
+    }
+    let values = view
+        .data()
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    Ok((view.shape().to_vec(), values))
+}
+
+pub fn run(args: QuantizeArgs, seed: u64) -> io::Result<()> {
+    let float = FloatNetwork::load(&args.weights, args.features)?;
+    let network = float.quantize()?;
+    for (name, layer) in LAYERS.iter().zip(&network.layers) {
+        info!(layer = name, shift = layer.shift, "quantized layer");
+    }
+
+    let mut dataset = IndexedDataset::open(&args.data)?;
+    if dataset.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no samples in {}", args.data.display()),
+        ));
+    }
+    let sigmoid = |x: f32| 1.0 / (1.0 + (-x).exp());
+    let mut rng = Rng::new(seed);
+    let (mut max_error, mut total_error) = (0f32, 0f64);
+    for _ in 0..args.samples {
+        let line = dataset.get(rng.below(dataset.len()))?;
+        let fen: Fen = OutputSample::parse(&line)?
+            .fen
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let board = fen.into_setup().board;
+        let error = (sigmoid(float.evaluate(&board)) - sigmoid(network.evaluate(&board))).abs();
+        max_error = max_error.max(error);
+        total_error += error as f64;
+    }
+    let mean_error = total_error / args.samples.max(1) as f64;
+    println!("max error:  {max_error:.5}");
+    println!("mean error: {mean_error:.5}");
+    if max_error > args.max_error {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the quantized network is off by up to {max_error}, more than {}",
+                args.max_error
+            ),
+        ));
+    }
+
+    network::save(&network, &args.output)?;
+    info!(path = %args.output.display(), "wrote quantized network");
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::material::{piece_counts, MaterialSignature};
+use preprocessing::output::{self, OutputSample};
+use preprocessing::{DecodeOptions, Game};
+use shakmaty::fen::Fen;
+use shakmaty::{Board, Color, File, Piece, Rank, Square};
+
+use crate::archive;
+
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Preprocessed outputs (directories with shards or single shards) or raw
+    /// .tar archives with the training data
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Only match this position. The move counters are ignored and the
+    /// positions are always seen from the side to move, which plays white
+    #[arg(long)]
+    fen: Option<String>,
+
+    /// Only match this material, e.g. KRPvKR. A side ending with * may have
+    /// more pieces, e.g. KQ*vK*
+    #[arg(long)]
+    material: Option<String>,
+
+    /// Only match this piece placement: 8 ranks separated by /, starting from
+    /// the 8th, with FEN pieces, digits or . for empty squares and ? for any
+    /// square, e.g. ????k???/8/8/8/8/8/8/????K??R
+    #[arg(long)]
+    pattern: Option<String>,
+
+    /// Maximum number of matches to print
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+}
+
+// Piece placement with wildcards, indexed by square.
+struct Pattern([Option<Option<Piece>>; 64]);
+
+impl Pattern {
+    fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid pattern: {s}");
+        let ranks: Vec<_> = s.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(invalid());
+        }
+
+        let mut squares = [None; 64];
+        for (rank, row) in ranks.iter().enumerate() {
+            let rank = Rank::new(7 - rank as u32);
+            let mut file = 0;
+            for c in row.chars() {
+                let expected = match c {
+                    '?' => vec![None],
+                    '.' => vec![Some(None)],
+                    '1'..='8' => vec![Some(None); c.to_digit(10).unwrap() as usize],
+                    _ => vec![Some(Some(Piece::from_char(c).ok_or_else(invalid)?))],
+                };
+                for expected in expected {
+                    if file >= 8 {
+                        return Err(invalid());
+                    }
+                    squares[Square::from_coords(File::new(file), rank) as usize] = expected;
+                    file += 1;
+                }
+            }
+            if file != 8 {
+                return Err(invalid());
+            }
+        }
+        Ok(Pattern(squares))
+    }
+
+    fn matches(&self, board: &Board) -> bool {
+        Square::ALL
+            .iter()
+            .all(|&square| match self.0[square as usize] {
+                Some(expected) => board.piece_at(square) == expected,
+                None => true,
+            })
+    }
+}
+
+struct Query {
+    // Position part of the FEN: placement, side to move, castling and en
+    // passant.
+    position: Option<String>,
+    material: Option<MaterialSignature>,
+    pattern: Option<Pattern>,
+}
+
+impl Query {
+    fn new(args: &QueryArgs) -> Result<Self, String> {
+        let position = match &args.fen {
+            Some(fen) => {
+                let fen = Fen::from_ascii(fen.as_bytes()).map_err(|err| format!("{err}: {fen}"))?;
+                Some(position_part(&fen.to_string()).to_string())
+            }
+            None => None,
+        };
+        let material = args.material.as_deref().map(str::parse).transpose()?;
+        let pattern = args.pattern.as_deref().map(Pattern::parse).transpose()?;
+        Ok(Query {
+            position,
+            material,
+            pattern,
+        })
+    }
+
+    // The board is only needed for the material and pattern matching.
+    fn matches(&self, fen: &str, board: impl FnOnce() -> Option<Board>) -> bool {
+        if self
+            .position
+            .as_ref()
+            .is_some_and(|position| position != position_part(fen))
+        {
+            return false;
+        }
+        if self.material.is_none() && self.pattern.is_none() {
+            return true;
+        }
+        let Some(board) = board() else {
+            return false;
+        };
+        self.material.as_ref().is_none_or(|material| {
+            material.matches_sides(
+                &piece_counts(&board, Color::White),
+                &piece_counts(&board, Color::Black),
+            )
+        }) && self
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches(&board))
+    }
+}
+
+// The FEN without the move counters.
+fn position_part(fen: &str) -> &str {
+    fen.rsplitn(3, ' ').last().unwrap_or(fen)
+}
+
+fn query_output(
+    path: &Path,
+    query: &Query,
+    out: &mut impl Write,
+    remaining: &mut usize,
+) -> io::Result<()> {
+    for shard in output::shard_paths(path)? {
+        if *remaining == 0 {
+            break;
+        }
+        let mut line_number = 0;
+        output::for_each_line(&shard, |line| {
+            line_number += 1;
+            if *remaining == 0 {
+                return Ok(());
+            }
+            let sample = OutputSample::parse(line)?;
+            let board = || {
+                Fen::from_ascii(sample.fen.as_bytes())
+                    .ok()
+                    .map(|fen| fen.0.board)
+            };
+            if query.matches(sample.fen, board) {
+                *remaining -= 1;
+                writeln!(out, "{}:{line_number}", shard.display())?;
+                writeln!(out, "  {line}")?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn query_archive(
+    path: &Path,
+    query: &Query,
+    out: &mut impl Write,
+    remaining: &mut usize,
+) -> io::Result<()> {
+    let mut result = Ok(());
+    let mut game_index = 0;
+    archive::for_each_game(path, 0, |entry| {
+        result =
+            Game::read_from(entry.data.as_slice(), DecodeOptions::default()).and_then(|game| {
+                for (ply, sample) in game.samples.iter().enumerate() {
+                    let fen = sample.to_fen(&game.castling).to_string();
+                    if query.matches(&fen, || Some(sample.to_board())) {
+                        *remaining -= 1;
+                        writeln!(out, "{}: game {game_index} ply {ply}", path.display())?;
+                        writeln!(
+                            out,
+                            "  {fen} {} {} {}",
+                            sample.best_q,
+                            sample.best_d,
+                            preprocessing::IDX_TO_MOVE[sample.best_idx as usize]
+                        )?;
+                        if *remaining == 0 {
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            });
+        game_index += 1;
+        result.is_ok() && *remaining > 0
+    })?;
+    result.map_err(io::Error::from)
+}
+
+pub fn run(args: QueryArgs) -> io::Result<()> {
+    let query =
+        Query::new(&args).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut out = io::stdout().lock();
+    let mut remaining = args.limit;
+    for input in &args.inputs {
+        if remaining == 0 {
+            break;
+        }
+        if input
+            .extension()
+            .is_some_and(|extension| extension == "tar")
+        {
+            query_archive(input, &query, &mut out, &mut remaining)?;
+        } else {
+            query_output(input, &query, &mut out, &mut remaining)?;
+        }
+    }
+    if remaining == args.limit {
+        writeln!(out, "no matches")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+// Reading of the training data archives: tar files with a gzip-compressed
+// game in every .gz entry.
+
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::vec;
+
+use crossbeam_channel::{bounded, Receiver};
+use tar::Archive;
+
+use crate::error::Result;
+use crate::sample::{DecodeOptions, Game, TrainingSample};
+use crate::storage;
+
+// Number of decoded games buffered ahead of the consumer of the iterators.
+const GAMES_AHEAD: usize = 16;
+
+/// A compressed game stored as a .gz entry of a tar archive.
+pub struct GameEntry {
+    /// Index of the entry among all entries of the archive.
+    pub index: usize,
+    /// Offset right after the data of the entry within the archive.
+    pub end_offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Slice of the games of every archive for one of several processes reading
+/// the same data, e.g. the ranks of distributed training. The slices of all
+/// indices are disjoint and together cover all games.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// Number of the slice, below `count`.
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Default for Shard {
+    /// All games.
+    fn default() -> Self {
+        Shard { index: 0, count: 1 }
+    }
+}
+
+impl Shard {
+    /// Whether the game at the entry with this index of an archive belongs to
+    /// the slice.
+    ///
+    /// ```
+    /// use preprocessing::reader::Shard;
+    ///
+    /// let shard = Shard { index: 1, count: 4 };
+    /// let entries: Vec<usize> = (0..12).filter(|&entry| shard.contains(entry)).collect();
+    /// assert_eq!(entries, [1, 5, 9]);
+    /// ```
+    pub fn contains(&self, entry: usize) -> bool {
+        entry % self.count.max(1) == self.index
+    }
+}
+
+/// Calls `f` for every game in the archive, starting from the entry with index
+/// `first_entry`. Returns false if `f` stopped the iteration by returning
+/// false. The archive may be in object storage, see [`storage`].
+pub fn for_each_game<P, F>(path: P, first_entry: usize, f: F) -> Result<bool>
+where
+    P: AsRef<Path>,
+    F: FnMut(GameEntry) -> bool,
+{
+    read_games(open(path.as_ref())?, first_entry, f)
+}
+
+// Opens a local archive or an object.
+fn open(path: &Path) -> Result<Box<dyn Read + Send>> {
+    Ok(storage::open(&path.to_string_lossy())?)
+}
+
+/// Like [`for_each_game`], for an archive that is already open, e.g. in
+/// memory.
+pub fn read_games<R, F>(reader: R, first_entry: usize, mut f: F) -> Result<bool>
+where
+    R: Read,
+    F: FnMut(GameEntry) -> bool,
+{
+    let mut archive = Archive::new(reader);
+    for (index, entry) in archive.entries()?.enumerate() {
+        if index < first_entry {
+            continue;
+        }
+
+        let mut entry = entry?;
+        if !entry.path()?.to_string_lossy().ends_with(".gz") {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+
+        let game = GameEntry {
+            index,
+            end_offset: entry.raw_file_position() + entry.size(),
+            data,
+        };
+        if !f(game) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Iterator over the games of an archive.
+///
+/// The archive is read and the games are decoded on a background thread, which
+/// stops once the iterator is dropped.
+///
+/// ```no_run
+/// use preprocessing::{DecodeOptions, Games};
+///
+/// for game in Games::from_tar("training.tar", DecodeOptions::default())? {
+///     let game = game?;
+///     println!("{} positions", game.samples.len());
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Games {
+    receiver: Receiver<Result<Game>>,
+}
+
+impl Games {
+    pub fn from_tar<P: AsRef<Path>>(path: P, decode: DecodeOptions) -> Result<Self> {
+        Self::from_tar_shard(path, decode, Shard::default())
+    }
+
+    /// Iterates over the games of the slice only, the others are skipped
+    /// without decoding them.
+    pub fn from_tar_shard<P: AsRef<Path>>(
+        path: P,
+        decode: DecodeOptions,
+        shard: Shard,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open(&path)?;
+        let (sender, receiver) = bounded(GAMES_AHEAD);
+        thread::spawn(move || {
+            let result = read_games(file, 0, |entry| {
+                if !shard.contains(entry.index) {
+                    return true;
+                }
+                let offset = entry.end_offset - entry.data.len() as u64;
+                let game = Game::read_from(entry.data.as_slice(), decode)
+                    .map_err(|err| err.in_archive(&path, entry.index, offset));
+                sender.send(game).is_ok()
+            });
+            if let Err(err) = result {
+                // Nobody is listening if the iterator was dropped already.
+                let _ = sender.send(Err(err));
+            }
+        });
+        Ok(Games { receiver })
+    }
+}
+
+impl Iterator for Games {
+    type Item = Result<Game>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The channel is disconnected once the whole archive is read.
+        self.receiver.recv().ok()
+    }
+}
+
+/// Iterator over all samples of an archive, game after game.
+///
+/// ```no_run
+/// use preprocessing::TrainingSamples;
+///
+/// for sample in TrainingSamples::from_tar("training.tar")? {
+///     let sample = sample?;
+///     println!("{} {}", sample.best_q, sample.best_d);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TrainingSamples {
+    games: Games,
+    samples: vec::IntoIter<TrainingSample>,
+}
+
+impl TrainingSamples {
+    pub fn from_tar<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let games = Games::from_tar(path, DecodeOptions::default())?;
+        Ok(Self::from_games(games))
+    }
+
+    /// Flattens the games, e.g. to decode additional fields of the samples.
+    pub fn from_games(games: Games) -> Self {
+        TrainingSamples {
+            games,
+            samples: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for TrainingSamples {
+    type Item = Result<TrainingSample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(sample) = self.samples.next() {
+                return Some(Ok(sample));
+            }
+            match self.games.next()? {
+                Ok(game) => self.samples = game.samples.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+// Raw version 6 training data record.
+//
+// The layout of the record is fixed, so instead of decoding every field on
+// reading, the record is kept as bytes and the fields are only decoded when
+// they are accessed. This way the 1858 policy probabilities and 104 planes are
+// skipped entirely unless the active filters and outputs need them.
+//
+// Original format: https://lczero.org/dev/wiki/training-data-format-versions/
+//
+// Records can also be written field by field, which the synthetic training
+// data is made with (see synthetic).
+
+use std::io::{ErrorKind, Read};
+
+use crate::error::{PreprocessError, Result};
+
+pub const RECORD_SIZE: usize = 8356;
+
+// Number of moves in the policy head. See IDX_TO_MOVE.
+pub const POLICY_SIZE: usize = 1858;
+
+// 8 positions of history with 13 planes each.
+pub const NUM_INPUT_PLANES: usize = 104;
+
+// Byte offsets of the fields within the record.
+const VERSION: usize = 0;
+const INPUT_FORMAT: usize = 4;
+const PROBABILITIES: usize = 8;
+const PLANES: usize = PROBABILITIES + 4 * POLICY_SIZE;
+const CASTLING_US_OOO: usize = PLANES + 8 * NUM_INPUT_PLANES;
+const CASTLING_US_OO: usize = CASTLING_US_OOO + 1;
+const CASTLING_THEM_OOO: usize = CASTLING_US_OOO + 2;
+const CASTLING_THEM_OO: usize = CASTLING_US_OOO + 3;
+const SIDE_TO_MOVE_OR_ENPASSANT: usize = CASTLING_US_OOO + 4;
+const RULE50_COUNT: usize = CASTLING_US_OOO + 5;
+const INVARIANCE_INFO: usize = CASTLING_US_OOO + 6;
+const ROOT_Q: usize = CASTLING_US_OOO + 8;
+const BEST_Q: usize = ROOT_Q + 4;
+const ROOT_D: usize = ROOT_Q + 8;
+const BEST_D: usize = ROOT_Q + 12;
+const ROOT_M: usize = ROOT_Q + 16;
+const BEST_M: usize = ROOT_Q + 20;
+const PLIES_LEFT: usize = ROOT_Q + 24;
+const RESULT_Q: usize = ROOT_Q + 28;
+const RESULT_D: usize = ROOT_Q + 32;
+const PLAYED_Q: usize = ROOT_Q + 36;
+const PLAYED_D: usize = ROOT_Q + 40;
+const PLAYED_M: usize = ROOT_Q + 44;
+const ORIG_Q: usize = ROOT_Q + 48;
+const ORIG_D: usize = ROOT_Q + 52;
+const ORIG_M: usize = ROOT_Q + 56;
+const VISITS: usize = ROOT_Q + 60;
+const PLAYED_IDX: usize = VISITS + 4;
+const BEST_IDX: usize = VISITS + 6;
+const POLICY_KLD: usize = VISITS + 8;
+
+pub struct Record([u8; RECORD_SIZE]);
+
+impl Record {
+    // Reading an empty stream fails with TruncatedRecord(0).
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut filled = 0;
+        while filled < RECORD_SIZE {
+            match reader.read(&mut bytes[filled..]) {
+                Ok(0) => return Err(PreprocessError::TruncatedRecord(filled)),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Record(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; RECORD_SIZE] {
+        &self.0
+    }
+
+    pub fn from_bytes(bytes: [u8; RECORD_SIZE]) -> Self {
+        Record(bytes)
+    }
+
+    /// A version 6 record of the classical input format with all moves
+    /// illegal and all other fields zero.
+    ///
+    /// ```
+    /// use preprocessing::record::Record;
+    ///
+    /// let mut record = Record::empty();
+    /// record.set_best(0.25, 0.5);
+    /// record.set_plane(0, 0xFF00);
+    /// assert_eq!(record.version(), 6);
+    /// assert_eq!(record.best_q(), 0.25);
+    /// assert_eq!(record.plane(0), 0xFF00);
+    /// assert_eq!(record.probability(0), -1.0);
+    /// ```
+    pub fn empty() -> Self {
+        let mut record = Record([0; RECORD_SIZE]);
+        record.put_u32(VERSION, 6);
+        record.put_u32(INPUT_FORMAT, 1);
+        for idx in 0..POLICY_SIZE {
+            record.set_probability(idx, -1.0);
+        }
+        record
+    }
+
+    pub fn version(&self) -> u32 {
+        self.u32_at(VERSION)
+    }
+
+    pub fn input_format(&self) -> u32 {
+        self.u32_at(INPUT_FORMAT)
+    }
+
+    pub fn probability(&self, idx: usize) -> f32 {
+        assert!(idx < POLICY_SIZE);
+        self.f32_at(PROBABILITIES + 4 * idx)
+    }
+
+    pub fn probabilities(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..POLICY_SIZE).map(|idx| self.probability(idx))
+    }
+
+    // Shannon entropy of the policy in nats. Illegal moves are stored as -1 and
+    // do not contribute.
+    pub fn policy_entropy(&self) -> f32 {
+        -self
+            .probabilities()
+            .filter(|&p| p > 0.0)
+            .map(|p| p * p.ln())
+            .sum::<f32>()
+    }
+
+    pub fn plane(&self, idx: usize) -> u64 {
+        assert!(idx < NUM_INPUT_PLANES);
+        reverse_bits_in_bytes(self.u64_at(PLANES + 8 * idx))
+    }
+
+    pub fn castling_us_ooo(&self) -> bool {
+        self.0[CASTLING_US_OOO] != 0
+    }
+
+    pub fn castling_us_oo(&self) -> bool {
+        self.0[CASTLING_US_OO] != 0
+    }
+
+    pub fn castling_them_ooo(&self) -> bool {
+        self.0[CASTLING_THEM_OOO] != 0
+    }
+
+    pub fn castling_them_oo(&self) -> bool {
+        self.0[CASTLING_THEM_OO] != 0
+    }
+
+    pub fn side_to_move_or_enpassant(&self) -> u8 {
+        self.0[SIDE_TO_MOVE_OR_ENPASSANT]
+    }
+
+    pub fn rule50_count(&self) -> u8 {
+        self.0[RULE50_COUNT]
+    }
+
+    pub fn invariance_info(&self) -> u8 {
+        self.0[INVARIANCE_INFO]
+    }
+
+    pub fn root_q(&self) -> f32 {
+        self.f32_at(ROOT_Q)
+    }
+
+    pub fn best_q(&self) -> f32 {
+        self.f32_at(BEST_Q)
+    }
+
+    pub fn root_d(&self) -> f32 {
+        self.f32_at(ROOT_D)
+    }
+
+    pub fn best_d(&self) -> f32 {
+        self.f32_at(BEST_D)
+    }
+
+    pub fn root_m(&self) -> f32 {
+        self.f32_at(ROOT_M)
+    }
+
+    pub fn best_m(&self) -> f32 {
+        self.f32_at(BEST_M)
+    }
+
+    pub fn plies_left(&self) -> f32 {
+        self.f32_at(PLIES_LEFT)
+    }
+
+    pub fn result_q(&self) -> f32 {
+        self.f32_at(RESULT_Q)
+    }
+
+    pub fn result_d(&self) -> f32 {
+        self.f32_at(RESULT_D)
+    }
+
+    pub fn played_q(&self) -> f32 {
+        self.f32_at(PLAYED_Q)
+    }
+
+    pub fn played_d(&self) -> f32 {
+        self.f32_at(PLAYED_D)
+    }
+
+    pub fn played_m(&self) -> f32 {
+        self.f32_at(PLAYED_M)
+    }
+
+    pub fn orig_q(&self) -> f32 {
+        self.f32_at(ORIG_Q)
+    }
+
+    pub fn orig_d(&self) -> f32 {
+        self.f32_at(ORIG_D)
+    }
+
+    pub fn orig_m(&self) -> f32 {
+        self.f32_at(ORIG_M)
+    }
+
+    pub fn visits(&self) -> u32 {
+        self.u32_at(VISITS)
+    }
+
+    pub fn played_idx(&self) -> u16 {
+        self.u16_at(PLAYED_IDX)
+    }
+
+    pub fn best_idx(&self) -> u16 {
+        self.u16_at(BEST_IDX)
+    }
+
+    pub fn policy_kld(&self) -> f32 {
+        self.f32_at(POLICY_KLD)
+    }
+
+    pub fn set_probability(&mut self, idx: usize, p: f32) {
+        assert!(idx < POLICY_SIZE);
+        self.put_u32(PROBABILITIES + 4 * idx, p.to_bits());
+    }
+
+    pub fn set_plane(&mut self, idx: usize, bitboard: u64) {
+        assert!(idx < NUM_INPUT_PLANES);
+        let offset = PLANES + 8 * idx;
+        self.0[offset..offset + 8].copy_from_slice(&reverse_bits_in_bytes(bitboard).to_le_bytes());
+    }
+
+    pub fn set_castling(&mut self, us_ooo: bool, us_oo: bool, them_ooo: bool, them_oo: bool) {
+        self.0[CASTLING_US_OOO] = us_ooo as u8;
+        self.0[CASTLING_US_OO] = us_oo as u8;
+        self.0[CASTLING_THEM_OOO] = them_ooo as u8;
+        self.0[CASTLING_THEM_OO] = them_oo as u8;
+    }
+
+    pub fn set_side_to_move_or_enpassant(&mut self, value: u8) {
+        self.0[SIDE_TO_MOVE_OR_ENPASSANT] = value;
+    }
+
+    pub fn set_rule50_count(&mut self, count: u8) {
+        self.0[RULE50_COUNT] = count;
+    }
+
+    pub fn set_root(&mut self, q: f32, d: f32) {
+        self.put_u32(ROOT_Q, q.to_bits());
+        self.put_u32(ROOT_D, d.to_bits());
+    }
+
+    pub fn set_best(&mut self, q: f32, d: f32) {
+        self.put_u32(BEST_Q, q.to_bits());
+        self.put_u32(BEST_D, d.to_bits());
+    }
+
+    pub fn set_result(&mut self, q: f32, d: f32) {
+        self.put_u32(RESULT_Q, q.to_bits());
+        self.put_u32(RESULT_D, d.to_bits());
+    }
+
+    pub fn set_played(&mut self, q: f32, d: f32) {
+        self.put_u32(PLAYED_Q, q.to_bits());
+        self.put_u32(PLAYED_D, d.to_bits());
+    }
+
+    pub fn set_plies_left(&mut self, plies: f32) {
+        self.put_u32(PLIES_LEFT, plies.to_bits());
+    }
+
+    pub fn set_visits(&mut self, visits: u32) {
+        self.put_u32(VISITS, visits);
+    }
+
+    pub fn set_played_idx(&mut self, idx: u16) {
+        self.0[PLAYED_IDX..PLAYED_IDX + 2].copy_from_slice(&idx.to_le_bytes());
+    }
+
+    pub fn set_best_idx(&mut self, idx: u16) {
+        self.0[BEST_IDX..BEST_IDX + 2].copy_from_slice(&idx.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.0[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.0[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.0[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn f32_at(&self, offset: usize) -> f32 {
+        f32::from_bits(self.u32_at(offset))
+    }
+}
+
+// For some reason, lc0 reverses the bits in the bytes of the bitboard before
+// storing them in the training data.
+// https://github.com/search?q=repo%3ALeelaChessZero%2Flc0+ReverseBitsInBytes&type=code
+//
+// Reversing all bits also reverses the order of the bytes, which is undone by
+// swapping them back. Both compile to a few instructions (or a single one on
+// architectures like ARM). See benches/reverse_bits.rs.
+pub fn reverse_bits_in_bytes(x: u64) -> u64 {
+    x.reverse_bits().swap_bytes()
+}
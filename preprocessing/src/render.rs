@@ -0,0 +1,114 @@
+// Board diagrams of single positions (`render`), see preprocessing::diagram.
+//
+// The position is a FEN, a sample line of the preprocessed output or a sample
+// of a preprocessed dataset picked by its index. Samples can be annotated with
+// the best move as an arrow and their targets as a caption. The samples are
+// always seen from the side to move, which plays white.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::dataset::IndexedDataset;
+use preprocessing::diagram::{self, Annotations};
+use preprocessing::output::OutputSample;
+use shakmaty::fen::Fen;
+use shakmaty::{Board, Square};
+use tracing::info;
+
+#[derive(Args)]
+pub struct RenderArgs {
+    /// FEN or sample line of the preprocessed output
+    #[arg(required_unless_present = "data", conflicts_with = "data")]
+    position: Option<String>,
+
+    /// Preprocessed output to take the sample from (directory with shards or
+    /// a single shard)
+    #[arg(long, requires = "index")]
+    data: Option<PathBuf>,
+
+    /// Index of the sample in the preprocessed output, counting from 0
+    #[arg(long, requires = "data")]
+    index: Option<u64>,
+
+    /// Path to write the image to, PNG if it ends with .png and SVG otherwise
+    /// ("-" for stdout)
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Draw the best move of the sample and write its targets below the board
+    #[arg(long)]
+    annotate: bool,
+
+    /// Draw the board from the side of black
+    #[arg(long)]
+    flip: bool,
+
+    /// Size of a square in pixels
+    #[arg(long, default_value_t = 45)]
+    square_size: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn parse_board(fen: &str) -> io::Result<Board> {
+    let fen: Fen = fen
+        .parse()
+        .map_err(|err| invalid(format!("invalid FEN {fen}: {err}")))?;
+    Ok(fen.into_setup().board)
+}
+
+// The squares of a move in UCI notation, ignoring the promotion.
+fn move_squares(uci: &str) -> Option<(Square, Square)> {
+    let from = Square::from_ascii(uci.as_bytes().get(0..2)?).ok()?;
+    let to = Square::from_ascii(uci.as_bytes().get(2..4)?).ok()?;
+    Some((from, to))
+}
+
+pub fn run(args: RenderArgs) -> io::Result<()> {
+    let line = match (&args.position, &args.data, args.index) {
+        (Some(position), _, _) => position.clone(),
+        (None, Some(data), Some(index)) => IndexedDataset::open(data)?.get(index)?,
+        _ => unreachable!("clap requires a position or --data with --index"),
+    };
+
+    let mut annotations = Annotations {
+        flipped: args.flip,
+        square_size: args.square_size,
+        ..Annotations::default()
+    };
+    let board = match OutputSample::parse(&line) {
+        Ok(sample) => {
+            if args.annotate {
+                annotations.arrow = move_squares(sample.best_move);
+                annotations.caption = Some(format!(
+                    "{}  best_q {:+.3}  best_d {:.3}",
+                    sample.best_move, sample.best_q, sample.best_d
+                ));
+            }
+            parse_board(sample.fen)?
+        }
+        // Not a sample, so it has to be a FEN.
+        Err(_) => {
+            if args.annotate {
+                info!("a FEN has no targets to annotate");
+            }
+            parse_board(&line)?
+        }
+    };
+
+    let svg = diagram::svg(&board, &annotations);
+    if args.output.as_os_str() == "-" {
+        return io::stdout().write_all(svg.as_bytes());
+    }
+    if args.output.extension().is_some_and(|ext| ext == "png") {
+        fs::write(&args.output, diagram::png(&svg)?)?;
+    } else {
+        fs::write(&args.output, svg)?;
+    }
+    info!(path = %args.output.display(), "rendered the board");
+    Ok(())
+}
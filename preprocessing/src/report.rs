@@ -0,0 +1,155 @@
+// Machine-readable summary of a preprocessing run (`--report`), so that
+// orchestration systems can verify and catalogue the output.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use preprocessing::output::{shard_paths, FORMAT_VERSION};
+use preprocessing::storage;
+use preprocessing::Filter;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::create_output;
+use crate::pipeline::{Summary, OUTCOMES};
+use crate::signals;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    // Archives this run read from, in order. The last one may be incomplete
+    // if a limit stopped the run.
+    pub inputs: Vec<String>,
+    // Version of the format of the lines and the names of their fields, see
+    // preprocessing::output::OUTPUT_SCHEMA.
+    pub format_version: u32,
+    pub fields: Vec<&'static str>,
+    pub input_bytes: u64,
+    pub games: u64,
+    pub samples_read: u64,
+    pub samples_kept: u64,
+    pub dropped: BTreeMap<&'static str, u64>,
+    // Kept samples by the result of their game for the side to move.
+    pub outcomes: BTreeMap<&'static str, u64>,
+    pub output_bytes: u64,
+    // Archives and games that were skipped because they could not be read.
+    pub errors: Vec<String>,
+    // Empty for dry runs.
+    pub shards: Vec<ShardReport>,
+    pub seed: Option<u64>,
+    pub dry_run: bool,
+    // The run was stopped by a signal and can be resumed.
+    pub interrupted: bool,
+    pub wall_time_secs: f64,
+    pub games_per_sec: f64,
+    pub samples_per_sec: f64,
+    pub input_bytes_per_sec: f64,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardReport {
+    pub path: String,
+    pub samples: u64,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl Report {
+    // `archives` are the inputs starting from where the run started.
+    pub fn new(archives: &[String], summary: &Summary, elapsed: Duration) -> io::Result<Self> {
+        let inputs: Vec<String> = archives
+            .iter()
+            .take(summary.archives as usize)
+            .cloned()
+            .collect();
+        // Unreadable archives are among the errors.
+        let input_bytes = inputs
+            .iter()
+            .map(|path| storage::size(path).unwrap_or(0))
+            .sum();
+        let secs = elapsed.as_secs_f64();
+        let rate = |count: u64| if secs > 0.0 { count as f64 / secs } else { 0.0 };
+        Ok(Report {
+            inputs,
+            format_version: FORMAT_VERSION,
+            fields: Vec::new(),
+            input_bytes,
+            games: summary.games,
+            samples_read: summary.samples,
+            samples_kept: summary.kept,
+            dropped: Filter::ALL
+                .iter()
+                .map(|&filter| (filter.name(), summary.filtered[filter as usize]))
+                .chain([
+                    ("resampled", summary.resampled),
+                    ("balanced_outcomes", summary.balanced),
+                    ("existing_positions", summary.existing),
+                ])
+                .collect(),
+            outcomes: OUTCOMES.into_iter().zip(summary.outcomes).collect(),
+            output_bytes: summary.bytes,
+            errors: summary.errors.clone(),
+            shards: Vec::new(),
+            seed: None,
+            dry_run: false,
+            interrupted: signals::interrupted(),
+            wall_time_secs: secs,
+            games_per_sec: rate(summary.games),
+            samples_per_sec: rate(summary.samples),
+            input_bytes_per_sec: rate(input_bytes),
+        })
+    }
+
+    // Checksums all shards in the output directory, including the ones written
+    // by earlier runs that this run resumed.
+    pub fn add_shards(&mut self, output_dir: &Path) -> io::Result<()> {
+        self.shards.extend(shard_reports(output_dir)?);
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = create_output(path)?;
+        serde_json::to_writer_pretty(&mut out, self).map_err(io::Error::other)?;
+        writeln!(out)?;
+        out.flush()
+    }
+}
+
+// Sample counts and checksums of the shards in the output directory.
+pub fn shard_reports(output_dir: &Path) -> io::Result<Vec<ShardReport>> {
+    shard_paths(output_dir)?
+        .iter()
+        .map(|path| shard_report(path))
+        .collect()
+}
+
+// Sample count and checksum of a single shard.
+pub fn shard_report(path: &Path) -> io::Result<ShardReport> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let (mut samples, mut bytes) = (0, 0);
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        hasher.update(buffer);
+        samples += buffer.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        bytes += buffer.len() as u64;
+        let len = buffer.len();
+        reader.consume(len);
+    }
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok(ShardReport {
+        path: path.display().to_string(),
+        samples,
+        bytes,
+        sha256,
+    })
+}
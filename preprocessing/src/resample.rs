@@ -0,0 +1,137 @@
+// Resampling of the positions to balance their evaluations.
+//
+// Most positions of the training data are roughly equal, so the value head
+// sees few decided ones. The samples are grouped into buckets by best_q and
+// every sample is kept with a probability that brings the proportions of the
+// buckets towards the target ones. Only downsampling is possible: the most
+// underrepresented bucket (relative to its target) keeps all of its samples.
+//
+// The same resampling balances the results of the games (wins, draws and
+// losses) with buckets of result_q instead.
+//
+// The frequencies of the buckets are estimated from the samples seen so far,
+// hence the proportions are approximate at the start and depend on the order
+// of the samples.
+
+use std::io;
+
+use crate::rng::Rng;
+
+/// What a resampler has seen so far: the samples of every bucket and the
+/// state of its random numbers. A resumed run continues with it where the
+/// interrupted one stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResamplerState {
+    pub seen: Vec<u64>,
+    pub rng: u64,
+}
+
+pub struct Resampler {
+    // Upper bounds of all buckets but the last one, ascending.
+    edges: Vec<f32>,
+    targets: Vec<f64>,
+    seen: Vec<u64>,
+    total: u64,
+    rng: Rng,
+}
+
+impl Resampler {
+    /// `edges` split the range of best_q into `edges.len() + 1` buckets with
+    /// the given target `proportions`, which are normalized to sum to 1.
+    ///
+    /// ```
+    /// use preprocessing::resample::Resampler;
+    /// use preprocessing::rng::Rng;
+    ///
+    /// // Decided positions are a third of the input, but should be half of
+    /// // the output.
+    /// let mut resampler = Resampler::new(vec![-0.5, 0.5], vec![0.25, 0.5, 0.25], Rng::new(0));
+    /// let mut kept = [0u32; 3];
+    /// for idx in 0..30_000 {
+    ///     let best_q = [-0.9, 0.0, 0.0, 0.0, 0.0, 0.9][idx % 6];
+    ///     if resampler.keep(best_q) {
+    ///         kept[resampler.bucket(best_q)] += 1;
+    ///     }
+    /// }
+    /// assert!((kept[0] as f64 / kept[1] as f64 - 0.5).abs() < 0.05);
+    /// assert!(kept[0].abs_diff(kept[2]) < 100);
+    /// ```
+    pub fn new(edges: Vec<f32>, proportions: Vec<f64>, rng: Rng) -> Self {
+        assert_eq!(proportions.len(), edges.len() + 1);
+        assert!(edges.windows(2).all(|pair| pair[0] < pair[1]));
+        let sum: f64 = proportions.iter().sum();
+        assert!(sum > 0.0 && proportions.iter().all(|&p| p >= 0.0));
+        Resampler {
+            seen: vec![0; proportions.len()],
+            targets: proportions.iter().map(|p| p / sum).collect(),
+            edges,
+            total: 0,
+            rng,
+        }
+    }
+
+    pub fn bucket(&self, best_q: f32) -> usize {
+        self.edges.partition_point(|&edge| edge <= best_q)
+    }
+
+    /// Whether to keep the next sample.
+    pub fn keep(&mut self, best_q: f32) -> bool {
+        let bucket = self.bucket(best_q);
+        self.seen[bucket] += 1;
+        self.total += 1;
+        // Ratio of the target and the observed proportion of every bucket.
+        let ratio = |idx: usize| self.targets[idx] * self.total as f64 / self.seen[idx] as f64;
+        let max = (0..self.seen.len())
+            .filter(|&idx| self.seen[idx] > 0)
+            .map(ratio)
+            .fold(0.0, f64::max);
+        if max <= 0.0 {
+            return false;
+        }
+        let probability = ratio(bucket) / max;
+        probability >= 1.0 || self.rng.chance(probability)
+    }
+
+    pub fn state(&self) -> ResamplerState {
+        ResamplerState {
+            seen: self.seen.clone(),
+            rng: self.rng.state(),
+        }
+    }
+
+    /// Continues from the state of a resampler with the same buckets.
+    ///
+    /// ```
+    /// use preprocessing::resample::Resampler;
+    /// use preprocessing::rng::Rng;
+    ///
+    /// let new = || Resampler::new(vec![0.0], vec![0.2, 0.8], Rng::new(7));
+    /// let samples: Vec<f32> = (0..1000).map(|idx| [-0.5, 0.5][idx % 3 / 2]).collect();
+    /// let mut uninterrupted = new();
+    /// let kept: Vec<bool> = samples.iter().map(|&q| uninterrupted.keep(q)).collect();
+    ///
+    /// let mut interrupted = new();
+    /// let mut resumed_kept: Vec<bool> = samples[..400].iter().map(|&q| interrupted.keep(q)).collect();
+    /// let mut resumed = new();
+    /// resumed.restore(interrupted.state())?;
+    /// resumed_kept.extend(samples[400..].iter().map(|&q| resumed.keep(q)));
+    /// assert_eq!(kept, resumed_kept);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn restore(&mut self, state: ResamplerState) -> io::Result<()> {
+        if state.seen.len() != self.seen.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the resampler has {} buckets, the resumed one {}",
+                    self.seen.len(),
+                    state.seen.len()
+                ),
+            ));
+        }
+        self.total = state.seen.iter().sum();
+        self.seen = state.seen;
+        self.rng = Rng::new(state.rng);
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+// Relabeling of the preprocessed samples with an external UCI engine
+// (`rescore`).
+//
+// Every position is searched by a pool of engine processes with a fixed depth
+// or number of nodes, and best_q, best_d and the best move of the sample are
+// replaced with the result of the search. The optional fields are kept as
+// they are. best_q is the engine's WDL estimate (UCI_ShowWDL) if it reports
+// one, otherwise the centipawn score mapped to an expected score with
+// --cp-scale. Without WDL, best_d is kept.
+//
+// The samples are written in the order of the input, in chunks. After every
+// chunk the number of processed samples is recorded in a checkpoint in the
+// output directory, which `--resume` continues from.
+
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+
+use clap::Args;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use preprocessing::output::{self, OutputSample, ShardWriter};
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess, PositionError};
+use tracing::{debug, info, warn};
+
+use crate::checkpoint::Checkpoint;
+use crate::signals;
+use crate::uci::{self, Engine, EngineArgs};
+
+const CHECKPOINT_NAME: &str = "rescore.checkpoint";
+
+#[derive(Args)]
+pub struct RescoreArgs {
+    /// Preprocessed data (directory with shards or a single shard)
+    #[arg(long)]
+    input: PathBuf,
+
+    /// Directory to write the relabeled shards to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Number of engine processes [default: the number of threads]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Maximum number of samples per output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+
+    /// Number of samples between two checkpoints
+    #[arg(long, default_value_t = 10_000)]
+    checkpoint_interval: usize,
+
+    /// Continue an interrupted run from the checkpoint in the output
+    /// directory
+    #[arg(long)]
+    resume: bool,
+}
+
+// Relabels the sample, None if the engine found no move in the position.
+fn rescore(engine: &mut Engine, line: &str, cp_scale: f32) -> io::Result<Option<String>> {
+    let sample = OutputSample::parse(line)?;
+    let fen: Fen = sample
+        .fen
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let pos: Chess = fen
+        .into_position(CastlingMode::Chess960)
+        .or_else(PositionError::ignore_invalid_castling_rights)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let analysis = engine.analyse(sample.fen)?;
+    let best_move =
+        uci::find_move(&pos, &analysis.best_move).and_then(|m| uci::policy_move(&pos, &m));
+    let (Some(best_move), Some(best)) = (best_move, analysis.variations.first()) else {
+        debug!(fen = sample.fen, best_move = %analysis.best_move, "no legal best move");
+        return Ok(None);
+    };
+    let (best_q, best_d) = best.q_and_d(cp_scale);
+    let best_d = best_d.unwrap_or(sample.best_d);
+    let mut line = format!("{} {best_q} {best_d} {best_move}", sample.fen);
+    if !sample.extra.is_empty() {
+        line.push(' ');
+        line.push_str(sample.extra);
+    }
+    Ok(Some(line))
+}
+
+fn worker(
+    args: &RescoreArgs,
+    jobs: Receiver<(usize, String)>,
+    results: Sender<(usize, io::Result<Option<String>>)>,
+) -> io::Result<()> {
+    let mut engine = Engine::start(&args.engine, &[])?;
+    for (idx, line) in jobs {
+        let result = rescore(&mut engine, &line, args.engine.cp_scale);
+        let failed = result.is_err();
+        if results.send((idx, result)).is_err() || failed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: RescoreArgs) -> io::Result<()> {
+    signals::install()?;
+    let checkpoint_path = args.output_dir.join(CHECKPOINT_NAME);
+    let input = args.input.display().to_string();
+    let (mut writer, skip) = if args.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_path)?;
+        if checkpoint.archive != input {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the checkpoint is of a different input: {}",
+                    checkpoint.archive
+                ),
+            ));
+        }
+        info!(samples = checkpoint.entry, "resuming");
+        let writer = ShardWriter::resume(&args.output_dir, args.shard_size, checkpoint.shards[0])?;
+        (writer, checkpoint.entry)
+    } else {
+        (ShardWriter::create(&args.output_dir, args.shard_size)?, 0)
+    };
+    // The checkpoint reuses the one of the preprocessing: the input is
+    // recorded as the archive and the number of processed samples as the
+    // entry.
+    let save = |writer: &mut ShardWriter, processed: usize| -> io::Result<()> {
+        let checkpoint = Checkpoint {
+            archive: input.clone(),
+            entry: processed,
+            shards: vec![writer.flush()?],
+            seed: None,
+            resampler: None,
+            outcome_resampler: None,
+        };
+        checkpoint.save(&checkpoint_path)
+    };
+
+    let workers = args
+        .workers
+        .unwrap_or_else(rayon::current_num_threads)
+        .max(1);
+    let chunk_size = args.checkpoint_interval.max(1);
+    let args = &args;
+    thread::scope(|scope| {
+        // Created within the scope, so that returning early closes the
+        // channels and stops the workers before they are joined.
+        let (job_tx, job_rx) = bounded::<(usize, String)>(chunk_size);
+        let (result_tx, result_rx) = bounded(chunk_size);
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let (jobs, results) = (job_rx.clone(), result_tx.clone());
+                scope.spawn(move || {
+                    worker(args, jobs, results).inspect_err(|err| warn!(%err, "engine failed"))
+                })
+            })
+            .collect();
+        drop((job_rx, result_tx));
+
+        let mut processed = skip;
+        let mut skipped = 0;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        // Sends the chunk to the workers and writes the results in order.
+        let mut flush = |chunk: &mut Vec<String>, processed: &mut usize| -> io::Result<()> {
+            let len = chunk.len();
+            // The channels are as large as the chunk, sending does not block.
+            for job in chunk.drain(..).enumerate() {
+                job_tx.send(job).map_err(|_| engine_failed())?;
+            }
+            let mut results = vec![None; len];
+            for _ in 0..len {
+                let (idx, result) = result_rx.recv().map_err(|_| engine_failed())?;
+                results[idx] = Some(result?);
+            }
+            for line in results.into_iter().flatten() {
+                match line {
+                    Some(line) => writer.write_sample(&line)?,
+                    None => skipped += 1,
+                }
+            }
+            *processed += len;
+            save(&mut writer, *processed)?;
+            info!(processed = *processed, skipped, "rescored samples");
+            Ok(())
+        };
+
+        let mut line_idx = 0;
+        output::for_each_line(&args.input, |line| {
+            line_idx += 1;
+            if line_idx <= skip || signals::interrupted() {
+                return Ok(());
+            }
+            chunk.push(line.to_string());
+            if chunk.len() == chunk_size {
+                flush(&mut chunk, &mut processed)?;
+            }
+            Ok(())
+        })?;
+        if !chunk.is_empty() && !signals::interrupted() {
+            flush(&mut chunk, &mut processed)?;
+        }
+        drop(job_tx);
+        for handle in handles {
+            handle.join().expect("the worker does not panic")?;
+        }
+        if signals::interrupted() {
+            warn!(processed, "interrupted, continue with --resume");
+        }
+        Ok(())
+    })
+}
+
+fn engine_failed() -> io::Error {
+    io::Error::other("all engine processes exited")
+}
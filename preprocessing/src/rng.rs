@@ -0,0 +1,102 @@
+// Seeded pseudo-random numbers for the randomized parts of preprocessing.
+//
+// All randomness of a run is derived from a single seed, so that the exact same
+// output can be regenerated. Games are processed in parallel and in no
+// particular order, hence instead of sharing one sequence, every unit of work
+// forks its own stream from the root generator and a key identifying it (e.g.
+// the position of the game in the input). The streams do not depend on the
+// scheduling of the threads.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::sample::splitmix64;
+
+/// SplitMix64 generator: tiny, fast and good enough for sampling and
+/// shuffling, but not cryptographically secure.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+///
+/// let root = Rng::new(42);
+/// let mut a = root.fork(7);
+/// let mut b = root.fork(7);
+/// assert_eq!(a.next_u64(), b.next_u64());
+/// assert_ne!(root.fork(7).next_u64(), root.fork(8).next_u64());
+///
+/// let mut items = [1, 2, 3, 4, 5];
+/// a.shuffle(&mut items);
+/// items.sort();
+/// assert_eq!(items, [1, 2, 3, 4, 5]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+// Increment of the SplitMix64 state, the golden ratio.
+const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// The state of the generator, `Rng::new` with it continues the
+    /// sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// An independent stream identified by `key`. Forking does not advance
+    /// this generator.
+    pub fn fork(&self, key: u64) -> Rng {
+        Rng {
+            state: splitmix64(self.state ^ splitmix64(key)),
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GAMMA);
+        splitmix64(self.state)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n), `n` has to be positive.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0);
+        // Lemire's multiply-shift with rejection of the biased low values.
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let product = self.next_u64() as u128 * n as u128;
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+
+    /// Returns true with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// Fisher-Yates shuffle.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Seed for runs that were not given one. It should be reported, so that the
+/// run can be reproduced.
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    splitmix64(nanos ^ ((std::process::id() as u64) << 32))
+}
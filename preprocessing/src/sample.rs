@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use shakmaty::fen::Fen;
+use shakmaty::{
+    Bitboard, Board, ByColor, ByRole, CastlingMode, Chess, Color, FromSetup, PositionError, Role,
+    Setup,
+};
+use tracing::warn;
+
+use crate::eco::{self, Opening};
+use crate::error::{PreprocessError, Result};
+use crate::filter::CastlingCheck;
+use crate::record::{Record, NUM_INPUT_PLANES};
+
+/// Each plane is a distinct bitboard representing a piece type of a certain
+/// color: pawns, knights, bishops, rooks, queens and the king of the side to
+/// move, followed by the same pieces of the opponent.
+pub const NUM_PLANES: usize = 12;
+
+const FIRST_RANK: u64 = 0xff;
+
+// The rooks on the first rank on the queenside and on the kingside of the
+// king, None without the king on the first rank.
+fn back_rank_rooks(rooks: u64, king: u64) -> Option<(u64, u64)> {
+    let (rooks, king) = (rooks & FIRST_RANK, king & FIRST_RANK);
+    if king == 0 {
+        return None;
+    }
+    Some((rooks & (king - 1), rooks & !(king | (king - 1))))
+}
+
+/// Planes of the board as stored in the training data: from the perspective
+/// of the side to move, with the board flipped vertically when black is to
+/// move.
+///
+/// ```
+/// use preprocessing::sample::planes;
+/// use shakmaty::{Chess, Color, Position};
+///
+/// let board = Chess::default().board().clone();
+/// assert_eq!(planes(&board, Color::White), planes(&board, Color::Black));
+/// ```
+pub fn planes(board: &Board, turn: Color) -> [u64; NUM_PLANES] {
+    let orient = |bitboard: Bitboard| match turn {
+        Color::White => bitboard.0,
+        Color::Black => bitboard.flip_vertical().0,
+    };
+    std::array::from_fn(|idx| {
+        let role = Role::ALL[idx % 6];
+        let color = if idx < 6 { turn } else { !turn };
+        orient(board.by_piece(role.of(color)))
+    })
+}
+
+/// A position from the training data with accompanying metadata.
+///
+/// The position is seen from the perspective of the side to move, which is
+/// always white.
+///
+/// Original format: https://lczero.org/dev/wiki/training-data-format-versions/
+///
+/// Samples can be serialized with serde. Human-readable formats get the
+/// bitboards as hex strings, binary formats store them as plain integers:
+///
+/// ```
+/// use preprocessing::record::RECORD_SIZE;
+/// use preprocessing::{DecodeOptions, TrainingSample};
+///
+/// let mut record = vec![0; RECORD_SIZE];
+/// record[0] = 6;
+/// let sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+///
+/// let json = serde_json::to_string(&sample)?;
+/// assert!(json.contains(r#""best_q":0.0"#));
+/// assert_eq!(serde_json::from_str::<TrainingSample>(&json)?, sample);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrainingSample {
+    #[serde(with = "bitboards")]
+    pub bitboards: [u64; NUM_PLANES],
+    // Prediction targets.
+    pub best_q: f32,
+    pub best_d: f32,
+    /// Value of the root of the search, see [`TrainingSample::value`].
+    #[serde(default)]
+    pub root_q: f32,
+    #[serde(default)]
+    pub root_d: f32,
+    /// Value of the best move before it was rescored with the endgame
+    /// tablebases, NaN if the sample was not rescored.
+    #[serde(default)]
+    pub orig_q: f32,
+    #[serde(default)]
+    pub orig_d: f32,
+    pub castling_us_ooo: bool,
+    pub castling_us_oo: bool,
+    pub castling_them_ooo: bool,
+    pub castling_them_oo: bool,
+    /// Index of the best move in the policy head, see [`crate::IDX_TO_MOVE`].
+    pub best_idx: u16,
+    /// Index of the move played in the game, which differs from the best move
+    /// when the search was randomized.
+    #[serde(default)]
+    pub played_idx: u16,
+    /// Value of the played move.
+    #[serde(default)]
+    pub played_q: f32,
+    #[serde(default)]
+    pub played_d: f32,
+    pub rule50_count: u8,
+    /// Whether black is to move in the game. Only stored by the records of
+    /// the classical input format (1), false for the other formats.
+    #[serde(default)]
+    pub black_to_move: bool,
+    /// Number of earlier samples of the game with the same position, side to
+    /// move and castling rights. Counted by [`Game::read_from`], 0 for
+    /// samples read on their own.
+    #[serde(default)]
+    pub repetitions: u8,
+    /// Index of the sample in its game. Counted by [`Game::read_from`], 0
+    /// for samples read on their own.
+    #[serde(default)]
+    pub ply: u16,
+    pub visits: u32,
+    /// Game result from the perspective of the side to move: 1 for a win, 0
+    /// for a draw and -1 for a loss.
+    pub result_q: f32,
+    /// Probability that the game ended in a draw, either 0 or 1.
+    pub result_d: f32,
+    /// Number of plies until the end of the game, the target of the
+    /// moves-left head.
+    #[serde(default)]
+    pub plies_left: f32,
+    /// Only decoded if requested by [`DecodeOptions`].
+    #[serde(default)]
+    pub policy_entropy: Option<f32>,
+    /// Probabilities of the moves in the policy head, illegal moves are -1.
+    /// Only decoded if requested by [`DecodeOptions`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Vec<f32>>,
+    /// The input planes of the record: 8 positions with 13 planes each, the
+    /// current one first, see [`crate::encoder::InputPlanes::from_sample`].
+    /// Only decoded if requested by [`DecodeOptions`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<u64>>,
+}
+
+/// The search values stored in the records, as expected score and draw
+/// probability for the side to move.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueSource {
+    /// The root of the search, averaged over all explored moves.
+    Root,
+    /// The best move of the search.
+    #[default]
+    Best,
+    /// The best move before rescoring with the endgame tablebases.
+    Orig,
+}
+
+impl ValueSource {
+    pub const ALL: [ValueSource; 3] = [ValueSource::Root, ValueSource::Best, ValueSource::Orig];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueSource::Root => "root",
+            ValueSource::Best => "best",
+            ValueSource::Orig => "orig",
+        }
+    }
+}
+
+/// Fields of the samples which are expensive to decode and are skipped unless
+/// they are needed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    pub policy_entropy: bool,
+    pub policy: bool,
+    /// Handling of the castling flags the board contradicts: repaired while
+    /// decoding with [`CastlingCheck::Repair`], see
+    /// [`TrainingSample::repair_castling`], and kept in the games for the
+    /// filter with [`CastlingCheck::Skip`], see [`Game::read_from`].
+    pub castling: CastlingCheck,
+    /// The planes of the earlier positions, see [`TrainingSample::history`].
+    pub history: bool,
+}
+
+impl TrainingSample {
+    /// Reads a single version 6 record. Only the fields that are used are
+    /// decoded, see [`crate::record`].
+    pub fn read_from<R: Read>(reader: R, decode: DecodeOptions) -> Result<Self> {
+        let record = Record::read_from(reader)?;
+        if record.version() != 6 {
+            return Err(PreprocessError::FormatVersion(record.version()));
+        }
+
+        let mut sample = TrainingSample {
+            bitboards: std::array::from_fn(|idx| record.plane(idx)),
+            best_q: record.best_q(),
+            best_d: record.best_d(),
+            root_q: record.root_q(),
+            root_d: record.root_d(),
+            orig_q: record.orig_q(),
+            orig_d: record.orig_d(),
+            best_idx: record.best_idx(),
+            played_idx: record.played_idx(),
+            played_q: record.played_q(),
+            played_d: record.played_d(),
+            castling_us_ooo: record.castling_us_ooo(),
+            castling_us_oo: record.castling_us_oo(),
+            castling_them_ooo: record.castling_them_ooo(),
+            castling_them_oo: record.castling_them_oo(),
+            rule50_count: record.rule50_count(),
+            black_to_move: record.input_format() == 1 && record.side_to_move_or_enpassant() != 0,
+            repetitions: 0,
+            ply: 0,
+            visits: record.visits(),
+            result_q: record.result_q(),
+            result_d: record.result_d(),
+            plies_left: record.plies_left(),
+            policy_entropy: decode.policy_entropy.then(|| record.policy_entropy()),
+            policy: decode.policy.then(|| record.probabilities().collect()),
+            history: decode
+                .history
+                .then(|| (0..NUM_INPUT_PLANES).map(|idx| record.plane(idx)).collect()),
+        };
+        if decode.castling == CastlingCheck::Repair {
+            sample.repair_castling();
+        }
+        Ok(sample)
+    }
+
+    // Whether the king and a rook on the back rank allow the castling of each
+    // flag, in the order us O-O-O, us O-O, them O-O-O, them O-O.
+    fn possible_castling(&self) -> [bool; 4] {
+        // The pieces of the opponent are flipped to the first rank.
+        let sides = |rooks: u64, king: u64| {
+            back_rank_rooks(rooks, king).map_or((false, false), |(queenside, kingside)| {
+                (queenside != 0, kingside != 0)
+            })
+        };
+        let (us_ooo, us_oo) = sides(self.bitboards[3], self.bitboards[5]);
+        let (them_ooo, them_oo) = sides(
+            self.bitboards[9].swap_bytes(),
+            self.bitboards[11].swap_bytes(),
+        );
+        [us_ooo, us_oo, them_ooo, them_oo]
+    }
+
+    /// Whether every castling flag has the king of its side on the back rank
+    /// and a rook on the back rank on the side of the castling.
+    pub fn castling_consistent(&self) -> bool {
+        let flags = [
+            self.castling_us_ooo,
+            self.castling_us_oo,
+            self.castling_them_ooo,
+            self.castling_them_oo,
+        ];
+        flags
+            .into_iter()
+            .zip(self.possible_castling())
+            .all(|(allowed, possible)| !allowed || possible)
+    }
+
+    /// Drops the castling flags that are not
+    /// [consistent](TrainingSample::castling_consistent) with the board and
+    /// returns whether any was dropped.
+    ///
+    /// ```
+    /// use preprocessing::record::RECORD_SIZE;
+    /// use preprocessing::{DecodeOptions, TrainingSample};
+    /// use shakmaty::{Bitboard, Square};
+    ///
+    /// let mut record = vec![0; RECORD_SIZE];
+    /// record[0] = 6;
+    /// let mut sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+    /// let square = |square: Square| Bitboard::from(square).0;
+    /// sample.bitboards[3] = square(Square::H1);
+    /// sample.bitboards[5] = square(Square::E1);
+    /// sample.bitboards[11] = square(Square::E8);
+    /// sample.castling_us_oo = true;
+    /// sample.castling_us_ooo = true;
+    /// assert!(!sample.castling_consistent());
+    ///
+    /// // No rook on the queenside.
+    /// assert!(sample.repair_castling());
+    /// assert!(sample.castling_us_oo);
+    /// assert!(!sample.castling_us_ooo);
+    /// assert!(sample.castling_consistent());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn repair_castling(&mut self) -> bool {
+        let possible = self.possible_castling();
+        let flags = [
+            &mut self.castling_us_ooo,
+            &mut self.castling_us_oo,
+            &mut self.castling_them_ooo,
+            &mut self.castling_them_oo,
+        ];
+        let mut repaired = false;
+        for (allowed, possible) in flags.into_iter().zip(possible) {
+            if *allowed && !possible {
+                *allowed = false;
+                repaired = true;
+            }
+        }
+        repaired
+    }
+
+    /// Expected score and draw probability of the value source.
+    ///
+    /// ```
+    /// use preprocessing::record::Record;
+    /// use preprocessing::sample::ValueSource;
+    /// use preprocessing::{DecodeOptions, TrainingSample};
+    ///
+    /// let mut record = Record::empty();
+    /// record.set_root(0.25, 0.5);
+    /// record.set_best(0.5, 0.25);
+    /// let bytes = record.as_bytes().as_slice();
+    /// let sample = TrainingSample::read_from(bytes, DecodeOptions::default())?;
+    /// assert_eq!(sample.value(ValueSource::Root), (0.25, 0.5));
+    /// assert_eq!(sample.value(ValueSource::Best), (sample.best_q, sample.best_d));
+    /// # Ok::<(), preprocessing::PreprocessError>(())
+    /// ```
+    pub fn value(&self, source: ValueSource) -> (f32, f32) {
+        match source {
+            ValueSource::Root => (self.root_q, self.root_d),
+            ValueSource::Best => (self.best_q, self.best_d),
+            ValueSource::Orig => (self.orig_q, self.orig_d),
+        }
+    }
+
+    /// Identifies the position regardless of the targets. Castling rights are
+    /// part of the position, the side to move is always the same.
+    pub fn position_hash(&self) -> u64 {
+        let castling = (self.castling_us_ooo as u64)
+            | (self.castling_us_oo as u64) << 1
+            | (self.castling_them_ooo as u64) << 2
+            | (self.castling_them_oo as u64) << 3;
+        self.bitboards
+            .iter()
+            .fold(splitmix64(castling), |hash, &plane| {
+                splitmix64(hash ^ plane)
+            })
+    }
+
+    pub fn to_board(&self) -> Board {
+        Board::from_bitboards(
+            ByRole {
+                pawn: Bitboard(self.bitboards[0] | self.bitboards[6]),
+                knight: Bitboard(self.bitboards[1] | self.bitboards[7]),
+                bishop: Bitboard(self.bitboards[2] | self.bitboards[8]),
+                rook: Bitboard(self.bitboards[3] | self.bitboards[9]),
+                queen: Bitboard(self.bitboards[4] | self.bitboards[10]),
+                king: Bitboard(self.bitboards[5] | self.bitboards[11]),
+            },
+            ByColor {
+                white: Bitboard(self.bitboards[0..6].iter().fold(0, |acc, &x| acc | x)),
+                black: Bitboard(
+                    self.bitboards[6..NUM_PLANES]
+                        .iter()
+                        .fold(0, |acc, &x| acc | x),
+                ),
+            },
+        )
+    }
+
+    /// The castling rights depend on the initial position of the game, see
+    /// [`CastlingBitboards`].
+    pub fn to_fen(&self, castling: &CastlingBitboards) -> Fen {
+        // The planes are stored from the perspective of the side to move, so
+        // it is always white's turn.
+        Fen(Setup {
+            board: self.to_board(),
+            castling_rights: castling.castling_rights(self),
+            ..Setup::empty()
+        })
+    }
+
+    /// The position with white to move. Castling rights that do not match the
+    /// rooks and the king are dropped.
+    pub fn to_position(&self, castling: &CastlingBitboards) -> Result<Chess> {
+        Chess::from_setup(self.to_fen(castling).0, CastlingMode::Chess960)
+            .or_else(PositionError::ignore_invalid_castling_rights)
+            .map_err(|err| PreprocessError::IllegalPosition(err.to_string()))
+    }
+}
+
+/// Initial squares of the castling rooks. The samples only store whether
+/// castling is still allowed, the squares are derived from the positions of
+/// the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CastlingBitboards {
+    pub castling_us_oo: u64,
+    pub castling_us_ooo: u64,
+    pub castling_them_oo: u64,
+    pub castling_them_ooo: u64,
+}
+
+impl CastlingBitboards {
+    /// The castling rook of a side is the outermost rook on the back rank on
+    /// that side of the king in the first position of the game allowing
+    /// castling to it. Games starting from opening books or odds positions
+    /// may have lost some of the rights or rooks already, which the later
+    /// positions and the flags of the opponent make up for. Both colors are
+    /// assumed to castle with the rooks of the same files. Wings without a
+    /// rook in any position allowing castling to them are left empty, see
+    /// [`CastlingBitboards::matches`].
+    ///
+    /// ```
+    /// use preprocessing::record::RECORD_SIZE;
+    /// use preprocessing::{CastlingBitboards, DecodeOptions, TrainingSample};
+    /// use shakmaty::{Bitboard, Square};
+    ///
+    /// let mut record = vec![0; RECORD_SIZE];
+    /// record[0] = 6;
+    /// let mut sample = TrainingSample::read_from(record.as_slice(), DecodeOptions::default())?;
+    /// // Queen rook odds: white to move has no rook on a1.
+    /// let square = |square: Square| Bitboard::from(square).0;
+    /// sample.bitboards[3] = square(Square::H1);
+    /// sample.bitboards[5] = square(Square::E1);
+    /// sample.bitboards[9] = square(Square::A8) | square(Square::H8);
+    /// sample.bitboards[11] = square(Square::E8);
+    /// sample.castling_us_oo = true;
+    /// sample.castling_them_oo = true;
+    /// sample.castling_them_ooo = true;
+    ///
+    /// let castling = CastlingBitboards::from_game(&[sample.clone()]);
+    /// assert_eq!(castling.castling_us_oo, square(Square::H1));
+    /// assert_eq!(castling.castling_us_ooo, square(Square::A1));
+    /// assert_eq!(castling.castling_them_ooo, square(Square::A8));
+    /// assert!(castling.matches(&sample));
+    ///
+    /// // White cannot castle queenside without the rook.
+    /// sample.castling_us_ooo = true;
+    /// assert!(!castling.matches(&sample));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_game(samples: &[TrainingSample]) -> Self {
+        // The rooks of both wings on the first rank.
+        let (mut oo, mut ooo) = (0, 0);
+        for sample in samples {
+            // The pieces of the opponent are flipped to the first rank.
+            let sides = [
+                (
+                    sample.bitboards[3],
+                    sample.bitboards[5],
+                    sample.castling_us_oo,
+                    sample.castling_us_ooo,
+                ),
+                (
+                    sample.bitboards[9].swap_bytes(),
+                    sample.bitboards[11].swap_bytes(),
+                    sample.castling_them_oo,
+                    sample.castling_them_ooo,
+                ),
+            ];
+            for (rooks, king, can_oo, can_ooo) in sides {
+                let Some((queenside, kingside)) = back_rank_rooks(rooks, king) else {
+                    continue;
+                };
+                // The lowest bit is the rook closest to the A file.
+                if can_oo && oo == 0 && kingside != 0 {
+                    oo = 1 << (63 - kingside.leading_zeros());
+                }
+                if can_ooo && ooo == 0 && queenside != 0 {
+                    ooo = queenside & queenside.wrapping_neg();
+                }
+            }
+            if oo != 0 && ooo != 0 {
+                break;
+            }
+        }
+
+        CastlingBitboards {
+            castling_us_oo: oo,
+            castling_us_ooo: ooo,
+            castling_them_oo: oo.swap_bytes(),
+            castling_them_ooo: ooo.swap_bytes(),
+        }
+    }
+
+    /// Rooks that can still castle according to the flags of the sample.
+    pub fn castling_rights(&self, data: &TrainingSample) -> Bitboard {
+        let mut rights = 0;
+        if data.castling_us_oo {
+            rights |= self.castling_us_oo;
+        }
+        if data.castling_us_ooo {
+            rights |= self.castling_us_ooo;
+        }
+        if data.castling_them_oo {
+            rights |= self.castling_them_oo;
+        }
+        if data.castling_them_ooo {
+            rights |= self.castling_them_ooo;
+        }
+        Bitboard(rights)
+    }
+
+    /// Whether every castling flag of the sample has its rook on the castling
+    /// square. Flags without a rook (a side with fewer rooks than rights, or
+    /// rooks on other files than derived for the game) would make wrong FENs.
+    pub fn matches(&self, data: &TrainingSample) -> bool {
+        let our_rooks = data.bitboards[3];
+        let their_rooks = data.bitboards[9];
+        [
+            (data.castling_us_oo, self.castling_us_oo, our_rooks),
+            (data.castling_us_ooo, self.castling_us_ooo, our_rooks),
+            (data.castling_them_oo, self.castling_them_oo, their_rooks),
+            (data.castling_them_ooo, self.castling_them_ooo, their_rooks),
+        ]
+        .into_iter()
+        .all(|(allowed, square, rooks)| !allowed || (square != 0 && rooks & square != 0))
+    }
+}
+
+/// Location of a game in the input, written with its samples by
+/// [`OutputFields::provenance`](crate::output::OutputFields::provenance).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Path of the archive the game was read from.
+    pub archive: String,
+    /// Index of the entry holding the game in the archive.
+    pub entry: usize,
+    /// Index of the game among all games read by the run.
+    pub game: u64,
+    /// Byte range of the compressed game within the archive.
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// All positions of a single game.
+#[derive(Debug)]
+pub struct Game {
+    pub castling: CastlingBitboards,
+    /// Classified from the early positions, None for unknown openings.
+    pub opening: Option<&'static Opening>,
+    pub samples: Vec<TrainingSample>,
+    /// Where the game was read from, set by the reader of the archive.
+    pub provenance: Option<Provenance>,
+}
+
+impl Game {
+    /// Reads a game from a gzip-compressed stream of records, as stored in
+    /// the .gz entries of the training data archives.
+    ///
+    /// ```no_run
+    /// use preprocessing::{DecodeOptions, Game};
+    ///
+    /// let data = std::fs::read("training.123.gz")?;
+    /// let game = Game::read_from(data.as_slice(), DecodeOptions::default())?;
+    /// for sample in &game.samples {
+    ///     println!("{} {}", sample.to_fen(&game.castling), sample.best_q);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn read_from<R: Read>(reader: R, decode: DecodeOptions) -> Result<Self> {
+        let mut gz = GzDecoder::new(reader);
+        // The stream is decompressed while reading the records, so all I/O
+        // errors come from the decoder.
+        let mut read_sample = || {
+            TrainingSample::read_from(&mut gz, decode).map_err(|err| match err {
+                PreprocessError::Io(err) => PreprocessError::Gzip(err),
+                err => err,
+            })
+        };
+
+        let mut samples = vec![read_sample()?];
+        // TODO: lc0 training data does not contain en passant squares, but those
+        // can be retroactively calculated.
+        loop {
+            match read_sample() {
+                Ok(data) => samples.push(data),
+                Err(PreprocessError::TruncatedRecord(0)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        for (ply, sample) in samples.iter_mut().enumerate() {
+            sample.ply = ply as u16;
+        }
+        count_repetitions(&mut samples);
+        let castling = CastlingBitboards::from_game(&samples);
+        let opening = eco::classify(&samples);
+        // Positions whose castling rights do not fit the rooks are dropped
+        // rather than converted to wrong FENs. Those the board contradicts
+        // are left to the filter when skipping them, which counts them.
+        let num_samples = samples.len();
+        samples.retain(|sample| {
+            castling.matches(sample)
+                || (decode.castling == CastlingCheck::Skip && !sample.castling_consistent())
+        });
+        if samples.len() < num_samples {
+            warn!(
+                skipped = num_samples - samples.len(),
+                "skipped positions with castling rights without their rooks"
+            );
+        }
+
+        Ok(Game {
+            castling,
+            opening,
+            samples,
+            provenance: None,
+        })
+    }
+}
+
+// Sets the repetition counts of the samples of a game. The side to move
+// alternates, so positions can only repeat every other sample.
+fn count_repetitions(samples: &mut [TrainingSample]) {
+    let mut seen = HashMap::new();
+    for (ply, sample) in samples.iter_mut().enumerate() {
+        let count = seen.entry((sample.position_hash(), ply % 2)).or_insert(0u8);
+        sample.repetitions = *count;
+        *count = count.saturating_add(1);
+    }
+}
+
+// Bitboards are written as hex strings in human-readable formats, which keeps
+// them exact for JSON parsers that only have doubles, and as integers in the
+// compact binary formats.
+mod bitboards {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::NUM_PLANES;
+
+    pub fn serialize<S: Serializer>(
+        planes: &[u64; NUM_PLANES],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return planes.serialize(serializer);
+        }
+        let hex: Vec<_> = planes.iter().map(|plane| format!("{plane:016x}")).collect();
+        hex.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u64; NUM_PLANES], D::Error> {
+        if !deserializer.is_human_readable() {
+            return <[u64; NUM_PLANES]>::deserialize(deserializer);
+        }
+        let planes = Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|plane| u64::from_str_radix(plane, 16).map_err(D::Error::custom))
+            .collect::<Result<Vec<_>, _>>()?;
+        planes
+            .try_into()
+            .map_err(|planes: Vec<u64>| D::Error::invalid_length(planes.len(), &"12 bitboards"))
+    }
+}
+
+// https://prng.di.unimi.it/splitmix64.c
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
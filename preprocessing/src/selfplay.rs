@@ -0,0 +1,293 @@
+// Generation of training data by self-play of a UCI engine (`selfplay`).
+//
+// Every game starts from the initial position or a random position of the
+// opening book and is played by the engine against itself. All positions of
+// the game are searched, the first --temperature-plies moves are sampled from
+// the best --multipv moves with a softmax over their expected scores, the
+// rest are the best moves. Every searched position is written as a sample in
+// the format of the preprocessing output, from the perspective of the side to
+// move, with the scores and the best move of the search.
+//
+// The games are played in parallel, but written in the order of their
+// numbers, so the same seed and engine settings produce the same output for a
+// deterministic engine.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use clap::Args;
+use crossbeam_channel::unbounded;
+use preprocessing::output::ShardWriter;
+use preprocessing::rng::Rng;
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess, EnPassantMode, Position, PositionError};
+use tracing::{debug, info, warn};
+
+use crate::signals;
+use crate::uci::{self, Engine, EngineArgs};
+
+#[derive(Args)]
+pub struct SelfplayArgs {
+    /// Directory to write the samples to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Number of games to play
+    #[arg(long, default_value_t = 1000)]
+    games: u64,
+
+    /// Number of engine processes [default: the number of threads]
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Opening book with a FEN (or EPD) per line, every game starts from a
+    /// random one [default: the initial position]
+    #[arg(long)]
+    book: Option<PathBuf>,
+
+    /// Softmax temperature over the expected scores (-1 to 1) of the
+    /// candidate moves, 0 always plays the best move
+    #[arg(long, default_value_t = 0.1)]
+    temperature: f32,
+
+    /// Number of plies at the start of the game with sampled moves
+    #[arg(long, default_value_t = 30)]
+    temperature_plies: u32,
+
+    /// Number of candidate moves the sampled moves are drawn from
+    #[arg(long, default_value_t = 4)]
+    multipv: usize,
+
+    /// Games longer than this many plies are adjudicated as draws
+    #[arg(long, default_value_t = 400)]
+    max_plies: u32,
+
+    /// Maximum number of samples per output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+}
+
+// The positions of a file with a FEN (or EPD) per line, also the openings of
+// the match command.
+pub fn read_book(path: &Path) -> io::Result<Vec<Chess>> {
+    let mut positions = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // EPD has no move counters and may have operations after them.
+        let fields: Vec<&str> = line.split_whitespace().take(6).collect();
+        let fen = match fields.len() {
+            6 if fields[4].parse::<u32>().is_ok() => fields.join(" "),
+            _ => format!("{} 0 1", fields[..4.min(fields.len())].join(" ")),
+        };
+        let invalid = |err: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {line}: {err}", path.display()),
+            )
+        };
+        let fen: Fen = fen.parse().map_err(|err| invalid(format!("{err}")))?;
+        let pos = fen
+            .into_position(CastlingMode::Chess960)
+            .or_else(PositionError::ignore_invalid_castling_rights)
+            .map_err(|err| invalid(err.to_string()))?;
+        positions.push(pos);
+    }
+    if positions.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no positions in {}", path.display()),
+        ));
+    }
+    Ok(positions)
+}
+
+// The FEN with the board flipped vertically and the colors swapped if black is
+// to move, like the positions of the training data.
+fn relative_fen(fen: &str) -> String {
+    let fields: Vec<&str> = fen.split(' ').collect();
+    if fields.get(1) != Some(&"b") || fields.len() != 6 {
+        return fen.to_string();
+    }
+    let swap_case = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_uppercase() {
+                    c.to_ascii_lowercase()
+                } else {
+                    c.to_ascii_uppercase()
+                }
+            })
+            .collect()
+    };
+    let board: Vec<String> = fields[0].split('/').rev().map(swap_case).collect();
+    let mut castling: Vec<char> = swap_case(fields[2]).chars().collect();
+    // White's rights come first.
+    castling.sort_by_key(char::is_ascii_lowercase);
+    let castling: String = castling.into_iter().collect();
+    let en_passant = match fields[3].as_bytes() {
+        [file, rank] => format!("{}{}", *file as char, (b'1' + b'8' - rank) as char),
+        _ => "-".to_string(),
+    };
+    let (halfmoves, fullmoves) = (fields[4], fields[5]);
+    format!(
+        "{} w {castling} {en_passant} {halfmoves} {fullmoves}",
+        board.join("/")
+    )
+}
+
+// Draws one of the candidates (at least one) with probability proportional to
+// exp(q / temperature).
+fn sample_move(candidates: &[(f32, String)], temperature: f32, rng: &mut Rng) -> usize {
+    let max = candidates.iter().fold(f32::MIN, |max, (q, _)| max.max(*q));
+    let weights: Vec<f64> = candidates
+        .iter()
+        .map(|(q, _)| (((q - max) / temperature) as f64).exp())
+        .collect();
+    let mut x = rng.next_f64() * weights.iter().sum::<f64>();
+    for (idx, weight) in weights.iter().enumerate() {
+        if x < *weight {
+            return idx;
+        }
+        x -= weight;
+    }
+    candidates.len() - 1
+}
+
+// Plays a game and returns its samples.
+fn play(
+    engine: &mut Engine,
+    args: &SelfplayArgs,
+    start: Chess,
+    rng: &mut Rng,
+) -> io::Result<Vec<String>> {
+    let mut pos = start;
+    let mut samples = Vec::new();
+    let mut repetitions: HashMap<String, u32> = HashMap::new();
+    for ply in 0..args.max_plies {
+        let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+        // The position without the move counters.
+        let key = fen.rsplitn(3, ' ').last().unwrap_or(&fen).to_string();
+        let count = repetitions.entry(key).or_default();
+        *count += 1;
+        if pos.is_game_over() || pos.halfmoves() >= 100 || *count >= 3 {
+            break;
+        }
+
+        let analysis = engine.analyse(&fen)?;
+        let best = uci::find_move(&pos, &analysis.best_move);
+        let (Some(best), Some(variation)) = (best, analysis.variations.first()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no legal best move in {fen}: {}", analysis.best_move),
+            ));
+        };
+        if let Some(policy_move) = uci::policy_move(&pos, &best) {
+            let (best_q, best_d) = variation.q_and_d(args.engine.cp_scale);
+            let best_d = best_d.unwrap_or(0.0);
+            samples.push(format!(
+                "{} {best_q} {best_d} {policy_move}",
+                relative_fen(&fen)
+            ));
+        }
+
+        let mut m = best;
+        if args.temperature > 0.0 && ply < args.temperature_plies {
+            let candidates: Vec<(f32, String)> = analysis
+                .variations
+                .iter()
+                .filter_map(|variation| {
+                    let (q, _) = variation.q_and_d(args.engine.cp_scale);
+                    Some((q, variation.first_move.clone()?))
+                })
+                .collect();
+            if !candidates.is_empty() {
+                let (_, sampled) = &candidates[sample_move(&candidates, args.temperature, rng)];
+                m = uci::find_move(&pos, sampled).unwrap_or(m);
+            }
+        }
+        pos.play_unchecked(&m);
+    }
+    Ok(samples)
+}
+
+pub fn run(args: SelfplayArgs, seed: u64) -> io::Result<()> {
+    signals::install()?;
+    let book = args.book.as_deref().map(read_book).transpose()?;
+    let mut writer = ShardWriter::create(&args.output_dir, args.shard_size)?;
+    let workers = args
+        .workers
+        .unwrap_or_else(rayon::current_num_threads)
+        .max(1);
+    let root = Rng::new(seed);
+    let mut extra_options = Vec::new();
+    if args.temperature > 0.0 && args.multipv > 1 {
+        extra_options.push(("MultiPV".to_string(), args.multipv.to_string()));
+    }
+
+    let args = &args;
+    let (book, root, extra_options) = (&book, &root, &extra_options);
+    let (samples, games) = thread::scope(|scope| -> io::Result<(u64, u64)> {
+        let (tx, rx) = unbounded();
+        for worker in 0..workers {
+            let tx = tx.clone();
+            scope.spawn(move || {
+                let mut engine = match Engine::start(&args.engine, extra_options) {
+                    Ok(engine) => engine,
+                    Err(err) => {
+                        warn!(%err, "engine failed");
+                        return;
+                    }
+                };
+                // Every worker plays every `workers`-th game.
+                let games = (worker as u64..args.games).step_by(workers);
+                for game in games.take_while(|_| !signals::interrupted()) {
+                    let mut rng = root.fork(game);
+                    let start = match book {
+                        Some(book) => book[rng.below(book.len() as u64) as usize].clone(),
+                        None => Chess::default(),
+                    };
+                    let result = play(&mut engine, args, start, &mut rng);
+                    let failed = result.is_err();
+                    if tx.send((game, result)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Games finished out of order wait for the earlier ones.
+        let mut pending = BTreeMap::new();
+        let (mut next, mut samples) = (0, 0);
+        for (game, result) in rx {
+            pending.insert(game, result?);
+            while let Some(lines) = pending.remove(&next) {
+                debug!(game = next, samples = lines.len(), "finished game");
+                for line in &lines {
+                    writer.write_sample(line)?;
+                }
+                samples += lines.len() as u64;
+                next += 1;
+                if next % 100 == 0 {
+                    info!(games = next, samples, "played games");
+                }
+            }
+        }
+        Ok((samples, next))
+    })?;
+    writer.flush()?;
+    if games < args.games {
+        warn!(games, "stopped before playing all games");
+    }
+    info!(games, samples, "finished self-play");
+    Ok(())
+}
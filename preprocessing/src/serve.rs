@@ -0,0 +1,241 @@
+// Serving shuffled mini-batches to trainers running in another process.
+//
+// The trainer connects to a Unix socket and reads an Arrow IPC stream with one
+// record batch per mini-batch, e.g. in Python:
+//
+//     sock = socket.socket(socket.AF_UNIX)
+//     sock.connect("/tmp/attix.sock")
+//     for batch in pyarrow.ipc.open_stream(sock.makefile("rb")):
+//         ...
+//
+// Every connection gets its own pass over the inputs, shuffled with a seed
+// forked by the number of the connection, so that several data-parallel
+// workers can read at the same time. The stream ends once the inputs are
+// exhausted, reconnecting starts the next epoch.
+//
+// For distributed training, every process gets its own server started with
+// its --rank and the common --world-size, which serves only its slice of the
+// games (see preprocessing::reader::Shard), so that the processes never see
+// the same game.
+
+use std::fs;
+use std::io::{self, BufWriter};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use arrow_array::{
+    ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, UInt16Array, UInt64Array, UInt8Array,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use clap::Args;
+use preprocessing::policy::PolicyOptions;
+use preprocessing::reader::Shard;
+use preprocessing::record::POLICY_SIZE;
+use preprocessing::rng::Rng;
+use preprocessing::sample::NUM_PLANES;
+use preprocessing::shuffle::{ShuffleOptions, ShuffledSamples};
+use preprocessing::{DecodeOptions, TrainingSample};
+use tracing::{info, warn};
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<PathBuf>,
+
+    /// Path of the Unix socket to listen on
+    #[arg(long)]
+    socket: PathBuf,
+
+    /// Number of samples in a batch, the last batch may be smaller
+    #[arg(long, default_value_t = 1024)]
+    batch_size: usize,
+
+    /// Number of samples the batches are drawn from at random
+    #[arg(long, default_value_t = 1 << 16)]
+    shuffle_buffer: usize,
+
+    /// Number of archives the games are interleaved from
+    #[arg(long, default_value_t = 8)]
+    open_archives: usize,
+
+    /// Include the search policy over the 1858 moves of the policy head
+    #[arg(long)]
+    policy: bool,
+
+    /// Scale the policy to p^(1 / temperature) and renormalize it, below 1
+    /// sharpens and above 1 flattens the distribution
+    #[arg(long, default_value_t = 1.0, requires = "policy")]
+    policy_temperature: f32,
+
+    /// Rescale the probabilities of the legal moves to sum to 1
+    #[arg(long, requires = "policy")]
+    renormalize_policy: bool,
+
+    /// Replace the policy with probability 1 for the best move
+    #[arg(long, requires = "policy")]
+    one_hot_policy: bool,
+
+    /// Zero the probabilities of the moves that are illegal in the position
+    /// and renormalize the rest
+    #[arg(long, requires = "policy")]
+    mask_illegal_policy: bool,
+
+    /// Number of the training process this server feeds, below --world-size
+    #[arg(long, default_value_t = 0)]
+    rank: usize,
+
+    /// Number of training processes reading disjoint slices of the games
+    #[arg(long, default_value_t = 1)]
+    world_size: usize,
+}
+
+// Columns of the record batches. The bitboards are sent packed, the trainer
+// unpacks them into planes.
+fn schema(policy: bool) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("bitboards", list_type(DataType::UInt64, NUM_PLANES), false),
+        Field::new("best_q", DataType::Float32, false),
+        Field::new("best_d", DataType::Float32, false),
+        Field::new("best_idx", DataType::UInt16, false),
+        Field::new("result_q", DataType::Float32, false),
+        Field::new("result_d", DataType::Float32, false),
+        Field::new("rule50_count", DataType::UInt8, false),
+        Field::new("plies_left", DataType::Float32, false),
+    ];
+    if policy {
+        fields.push(Field::new(
+            "policy",
+            list_type(DataType::Float32, POLICY_SIZE),
+            false,
+        ));
+    }
+    Arc::new(Schema::new(fields))
+}
+
+fn list_type(item: DataType, len: usize) -> DataType {
+    DataType::FixedSizeList(Arc::new(Field::new("item", item, false)), len as i32)
+}
+
+fn list_array(values: ArrayRef, len: usize) -> ArrayRef {
+    let item = Arc::new(Field::new("item", values.data_type().clone(), false));
+    Arc::new(FixedSizeListArray::new(item, len as i32, values, None))
+}
+
+fn record_batch(
+    schema: &SchemaRef,
+    samples: &[TrainingSample],
+    policy: Option<PolicyOptions>,
+) -> io::Result<RecordBatch> {
+    let floats = |field: fn(&TrainingSample) -> f32| -> ArrayRef {
+        Arc::new(Float32Array::from_iter_values(samples.iter().map(field)))
+    };
+    let bitboards = UInt64Array::from_iter_values(samples.iter().flat_map(|s| s.bitboards));
+    let best_idx = UInt16Array::from_iter_values(samples.iter().map(|s| s.best_idx));
+    let rule50_count = UInt8Array::from_iter_values(samples.iter().map(|s| s.rule50_count));
+    let mut columns = vec![
+        list_array(Arc::new(bitboards), NUM_PLANES),
+        floats(|s| s.best_q),
+        floats(|s| s.best_d),
+        Arc::new(best_idx),
+        floats(|s| s.result_q),
+        floats(|s| s.result_d),
+        Arc::new(rule50_count),
+        floats(|s| s.plies_left),
+    ];
+    if let Some(options) = policy {
+        let mut values = Vec::with_capacity(samples.len() * POLICY_SIZE);
+        for sample in samples {
+            let start = values.len();
+            let policy = sample.policy.as_deref().expect("the policy is decoded");
+            values.extend_from_slice(policy);
+            options.apply_to_sample(&mut values[start..], sample);
+        }
+        let policy = Float32Array::from(values);
+        columns.push(list_array(Arc::new(policy), POLICY_SIZE));
+    }
+    RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)
+}
+
+fn serve_connection(stream: UnixStream, args: &ServeArgs, seed: u64) -> io::Result<()> {
+    let schema = schema(args.policy);
+    let policy = args.policy.then_some(PolicyOptions {
+        temperature: args.policy_temperature,
+        renormalize: args.renormalize_policy,
+        one_hot: args.one_hot_policy,
+        mask_illegal: args.mask_illegal_policy,
+    });
+    let mut writer =
+        StreamWriter::try_new(BufWriter::new(stream), &schema).map_err(io::Error::other)?;
+    let options = ShuffleOptions {
+        buffer_size: args.shuffle_buffer,
+        open_archives: args.open_archives,
+        seed,
+        decode: DecodeOptions {
+            policy: args.policy,
+            ..DecodeOptions::default()
+        },
+        shard: Shard {
+            index: args.rank,
+            count: args.world_size,
+        },
+    };
+    let batch_size = args.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    for sample in ShuffledSamples::new(args.tar_path.clone(), options) {
+        batch.push(sample?);
+        if batch.len() == batch_size {
+            let record_batch = record_batch(&schema, &batch, policy)?;
+            writer.write(&record_batch).map_err(io::Error::other)?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        let record_batch = record_batch(&schema, &batch, policy)?;
+        writer.write(&record_batch).map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+pub fn run(args: ServeArgs, seed: u64) -> io::Result<()> {
+    if args.world_size == 0 || args.rank >= args.world_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--rank {} has to be below --world-size {}",
+                args.rank, args.world_size
+            ),
+        ));
+    }
+    // The socket of an earlier run is left behind and would fail the binding.
+    if fs::metadata(&args.socket).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(&args.socket)?;
+    }
+    let listener = UnixListener::bind(&args.socket)?;
+    info!(
+        socket = %args.socket.display(),
+        rank = args.rank,
+        world_size = args.world_size,
+        "serving batches"
+    );
+
+    let root = Rng::new(seed);
+    let args = &args;
+    thread::scope(|scope| {
+        for (connection, stream) in listener.incoming().enumerate() {
+            let stream = stream?;
+            let seed = root.fork(connection as u64).next_u64();
+            info!(connection, "trainer connected");
+            scope.spawn(move || match serve_connection(stream, args, seed) {
+                Ok(()) => info!(connection, "all batches sent"),
+                // Most likely the trainer disconnected.
+                Err(err) => warn!(connection, %err, "connection closed"),
+            });
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,250 @@
+// Streaming shuffling and sampling of the training data.
+//
+// Consecutive samples come from the same game and are strongly correlated.
+// Similarly to the chunk shuffling of lc0, the games are read from several
+// archives at once in random order and the samples pass through a buffer that
+// emits a random one of them for every new sample. The output is only
+// approximately uniform, but needs a single pass and bounded memory.
+
+use std::mem;
+use std::path::PathBuf;
+use std::vec;
+
+use crate::error::Result;
+use crate::reader::{Games, Shard};
+use crate::rng::Rng;
+use crate::sample::{DecodeOptions, Game, TrainingSample};
+
+/// Fixed-size buffer emitting its items in random order.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::shuffle::ShuffleBuffer;
+///
+/// let mut buffer = ShuffleBuffer::new(4, Rng::new(0));
+/// let mut output: Vec<_> = (0..10).filter_map(|item| buffer.push(item)).collect();
+/// assert_eq!(output.len(), 6);
+/// output.extend(buffer.drain());
+/// output.sort();
+/// assert_eq!(output, (0..10).collect::<Vec<_>>());
+/// ```
+pub struct ShuffleBuffer<T> {
+    items: Vec<T>,
+    capacity: usize,
+    rng: Rng,
+}
+
+impl<T> ShuffleBuffer<T> {
+    pub fn new(capacity: usize, rng: Rng) -> Self {
+        let capacity = capacity.max(1);
+        ShuffleBuffer {
+            items: Vec::with_capacity(capacity),
+            capacity,
+            rng,
+        }
+    }
+
+    /// Adds the item and, once the buffer is full, returns a random one in
+    /// its place.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return None;
+        }
+        let idx = self.rng.below(self.capacity as u64) as usize;
+        Some(mem::replace(&mut self.items[idx], item))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Empties the buffer in random order.
+    pub fn drain(&mut self) -> vec::IntoIter<T> {
+        self.rng.shuffle(&mut self.items);
+        mem::take(&mut self.items).into_iter()
+    }
+}
+
+/// Uniform random subset of a fixed size of a stream of unknown length,
+/// sampled in a single pass (reservoir sampling, algorithm R).
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::shuffle::Reservoir;
+///
+/// let mut reservoir = Reservoir::new(5, Rng::new(0));
+/// (0..1000).for_each(|item| reservoir.push(item));
+/// assert_eq!(reservoir.seen(), 1000);
+/// let mut subset = reservoir.into_shuffled();
+/// assert_eq!(subset.len(), 5);
+/// subset.sort();
+/// subset.dedup();
+/// assert_eq!(subset.len(), 5);
+///
+/// // Shorter streams are kept whole.
+/// let mut reservoir = Reservoir::new(5, Rng::new(0));
+/// (0..3).for_each(|item| reservoir.push(item));
+/// assert_eq!(reservoir.into_shuffled().len(), 3);
+/// ```
+pub struct Reservoir<T> {
+    items: Vec<T>,
+    capacity: usize,
+    seen: u64,
+    rng: Rng,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize, rng: Rng) -> Self {
+        Reservoir {
+            items: Vec::with_capacity(capacity),
+            capacity,
+            seen: 0,
+            rng,
+        }
+    }
+
+    /// Keeps the item with probability capacity / seen, in place of a random
+    /// kept one.
+    pub fn push(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return;
+        }
+        let idx = self.rng.below(self.seen) as usize;
+        if idx < self.capacity {
+            self.items[idx] = item;
+        }
+    }
+
+    /// Number of items pushed so far.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// The subset in random order.
+    pub fn into_shuffled(mut self) -> Vec<T> {
+        self.rng.shuffle(&mut self.items);
+        self.items
+    }
+}
+
+/// Configuration of [`ShuffledSamples`].
+#[derive(Debug, Clone, Copy)]
+pub struct ShuffleOptions {
+    /// Number of samples in the shuffle buffer.
+    pub buffer_size: usize,
+    /// Number of archives the games are read from at the same time.
+    pub open_archives: usize,
+    pub seed: u64,
+    pub decode: DecodeOptions,
+    /// Slice of the games read from every archive.
+    pub shard: Shard,
+}
+
+impl Default for ShuffleOptions {
+    fn default() -> Self {
+        ShuffleOptions {
+            buffer_size: 1 << 16,
+            open_archives: 8,
+            seed: 0,
+            decode: DecodeOptions::default(),
+            shard: Shard::default(),
+        }
+    }
+}
+
+/// Iterator over the samples of many archives in shuffled order. The archives
+/// are visited in random order and the games of the open ones are
+/// interleaved at random.
+///
+/// ```no_run
+/// use preprocessing::shuffle::{ShuffleOptions, ShuffledSamples};
+///
+/// let archives = vec!["a.tar".into(), "b.tar".into(), "c.tar".into()];
+/// for sample in ShuffledSamples::new(archives, ShuffleOptions::default()) {
+///     println!("{}", sample?.best_q);
+/// }
+/// # Ok::<(), preprocessing::PreprocessError>(())
+/// ```
+pub struct ShuffledSamples {
+    archives: vec::IntoIter<PathBuf>,
+    open: Vec<Games>,
+    options: ShuffleOptions,
+    // Picks the archives and the games.
+    rng: Rng,
+    buffer: ShuffleBuffer<TrainingSample>,
+    // Samples of the last read game that are not in the buffer yet.
+    incoming: vec::IntoIter<TrainingSample>,
+    // Rest of the buffer once all archives are read.
+    rest: Option<vec::IntoIter<TrainingSample>>,
+}
+
+impl ShuffledSamples {
+    pub fn new(mut archives: Vec<PathBuf>, options: ShuffleOptions) -> Self {
+        let root = Rng::new(options.seed);
+        let mut rng = root.fork(0);
+        rng.shuffle(&mut archives);
+        ShuffledSamples {
+            archives: archives.into_iter(),
+            open: Vec::with_capacity(options.open_archives),
+            options,
+            rng,
+            buffer: ShuffleBuffer::new(options.buffer_size, root.fork(1)),
+            incoming: Vec::new().into_iter(),
+            rest: None,
+        }
+    }
+
+    fn next_game(&mut self) -> Option<Result<Game>> {
+        loop {
+            while self.open.len() < self.options.open_archives.max(1) {
+                let Some(path) = self.archives.next() else {
+                    break;
+                };
+                match Games::from_tar_shard(path, self.options.decode, self.options.shard) {
+                    Ok(games) => self.open.push(games),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            if self.open.is_empty() {
+                return None;
+            }
+            let idx = self.rng.below(self.open.len() as u64) as usize;
+            match self.open[idx].next() {
+                Some(game) => return Some(game),
+                None => {
+                    self.open.swap_remove(idx);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for ShuffledSamples {
+    type Item = Result<TrainingSample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(rest) = &mut self.rest {
+                return rest.next().map(Ok);
+            }
+            if let Some(sample) = self.incoming.next() {
+                if let Some(sample) = self.buffer.push(sample) {
+                    return Some(Ok(sample));
+                }
+                continue;
+            }
+            match self.next_game() {
+                Some(Ok(game)) => self.incoming = game.samples.into_iter(),
+                Some(Err(err)) => return Some(Err(err)),
+                None => self.rest = Some(self.buffer.drain()),
+            }
+        }
+    }
+}
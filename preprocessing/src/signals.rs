@@ -0,0 +1,31 @@
+// Graceful shutdown on SIGINT and SIGTERM.
+//
+// The first signal stops reading new games, while the games that are already
+// in the pipeline are written, the output is flushed and the checkpoint is
+// saved, so the run can be continued with --resume. A second signal exits
+// immediately.
+
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::warn;
+
+// Exit code of processes terminated by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn install() -> io::Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        warn!("interrupted, finishing the games in flight (interrupt again to exit immediately)");
+    })
+    .map_err(io::Error::other)
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,255 @@
+// Output formats of the preprocessed samples behind a common interface, so
+// that other formats can be added without touching the pipeline and the
+// samples can be written in several formats at once.
+//
+// A sink gets the kept samples in the order of the input and describes what
+// it wrote once it is finalized. The sinks are created by the name of their
+// format from a Registry, which has the built-in formats and can be extended:
+//
+//     text    sharded text files with indices in a directory, see ShardWriter
+//     lines   a single text file with a sample per line
+//     null    nothing, e.g. for counting the samples
+//
+// The text format is what the preprocess command writes to the output
+// directory, the others are written next to it with --sink.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::output::{OutputSample, ShardWriter};
+
+/// What a sink wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Name of the format of the sink.
+    pub format: String,
+    /// Files written by the sink, empty for sinks that do not write files.
+    pub files: Vec<PathBuf>,
+    pub samples: u64,
+    /// Size of the samples as text, including the line breaks.
+    pub bytes: u64,
+}
+
+/// A destination of the preprocessed samples.
+pub trait SampleSink: Send {
+    fn write(&mut self, sample: &OutputSample) -> io::Result<()>;
+
+    /// Writes the buffered samples out.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Flushes the sink and describes what it wrote.
+    fn finalize(self: Box<Self>) -> io::Result<Manifest>;
+}
+
+// The sinks created by a Registry can be passed where a sink is expected.
+impl SampleSink for Box<dyn SampleSink> {
+    fn write(&mut self, sample: &OutputSample) -> io::Result<()> {
+        (**self).write(sample)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn finalize(self: Box<Self>) -> io::Result<Manifest> {
+        (*self).finalize()
+    }
+}
+
+/// Every sample as a line of the writer, format "lines".
+pub struct LineSink<W: Write + Send> {
+    out: W,
+    path: Option<PathBuf>,
+    samples: u64,
+    bytes: u64,
+}
+
+impl LineSink<BufWriter<File>> {
+    pub fn create<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let out = BufWriter::new(File::create(&path)?);
+        Ok(LineSink {
+            out,
+            path: Some(path),
+            samples: 0,
+            bytes: 0,
+        })
+    }
+}
+
+impl<W: Write + Send> LineSink<W> {
+    pub fn new(out: W) -> Self {
+        LineSink {
+            out,
+            path: None,
+            samples: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl<W: Write + Send> SampleSink for LineSink<W> {
+    fn write(&mut self, sample: &OutputSample) -> io::Result<()> {
+        writeln!(self.out, "{}", sample.line)?;
+        self.samples += 1;
+        self.bytes += sample.line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<Manifest> {
+        self.out.flush()?;
+        Ok(Manifest {
+            format: "lines".to_string(),
+            files: self.path.into_iter().collect(),
+            samples: self.samples,
+            bytes: self.bytes,
+        })
+    }
+}
+
+/// Counts the samples without writing them, format "null".
+#[derive(Debug, Default)]
+pub struct NullSink {
+    samples: u64,
+    bytes: u64,
+}
+
+impl SampleSink for NullSink {
+    fn write(&mut self, sample: &OutputSample) -> io::Result<()> {
+        self.samples += 1;
+        self.bytes += sample.line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> io::Result<Manifest> {
+        Ok(Manifest {
+            format: "null".to_string(),
+            files: Vec::new(),
+            samples: self.samples,
+            bytes: self.bytes,
+        })
+    }
+}
+
+/// Where a sink created from the [`Registry`] writes to.
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    /// The directory or the file of the sink, depending on the format.
+    pub path: PathBuf,
+    /// Maximum number of samples in a file, for the formats that split their
+    /// output.
+    pub shard_size: u64,
+}
+
+pub type SinkFactory = Box<dyn Fn(&SinkConfig) -> io::Result<Box<dyn SampleSink>> + Send + Sync>;
+
+/// Constructors of the sinks by the name of their format.
+///
+/// ```
+/// use std::io;
+///
+/// use preprocessing::output::OutputSample;
+/// use preprocessing::sink::{Manifest, Registry, SampleSink, SinkConfig};
+///
+/// // Counts the samples.
+/// struct CountingSink(u64);
+///
+/// impl SampleSink for CountingSink {
+///     fn write(&mut self, _sample: &OutputSample) -> io::Result<()> {
+///         self.0 += 1;
+///         Ok(())
+///     }
+///
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+///
+///     fn finalize(self: Box<Self>) -> io::Result<Manifest> {
+///         Ok(Manifest {
+///             format: "count".to_string(),
+///             files: Vec::new(),
+///             samples: self.0,
+///             bytes: 0,
+///         })
+///     }
+/// }
+///
+/// let mut registry = Registry::default();
+/// registry.register("count", |_: &SinkConfig| {
+///     Ok(Box::new(CountingSink(0)) as Box<dyn SampleSink>)
+/// });
+/// assert!(registry.formats().any(|format| format == "count"));
+///
+/// let config = SinkConfig {
+///     path: "unused".into(),
+///     shard_size: 1,
+/// };
+/// let mut sink = registry.create("count", &config)?;
+/// let line = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1 0.1 0.3 e7e5";
+/// sink.write(&OutputSample::parse(line)?)?;
+/// assert_eq!(sink.finalize()?.samples, 1);
+/// assert!(registry.create("parquet", &config).is_err());
+/// # Ok::<(), io::Error>(())
+/// ```
+pub struct Registry {
+    factories: BTreeMap<String, SinkFactory>,
+}
+
+impl Default for Registry {
+    /// The built-in formats.
+    fn default() -> Self {
+        let mut registry = Registry {
+            factories: BTreeMap::new(),
+        };
+        registry.register("text", |config: &SinkConfig| {
+            let writer = ShardWriter::create(&config.path, config.shard_size)?;
+            Ok(Box::new(writer) as Box<dyn SampleSink>)
+        });
+        registry.register("lines", |config: &SinkConfig| {
+            Ok(Box::new(LineSink::create(&config.path)?) as Box<dyn SampleSink>)
+        });
+        registry.register("null", |_: &SinkConfig| {
+            Ok(Box::new(NullSink::default()) as Box<dyn SampleSink>)
+        });
+        registry
+    }
+}
+
+impl Registry {
+    /// Adds a format, replacing the format of the same name.
+    pub fn register<F>(&mut self, format: impl Into<String>, factory: F)
+    where
+        F: Fn(&SinkConfig) -> io::Result<Box<dyn SampleSink>> + Send + Sync + 'static,
+    {
+        self.factories.insert(format.into(), Box::new(factory));
+    }
+
+    /// Names of the formats in alphabetical order.
+    pub fn formats(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    pub fn create(&self, format: &str, config: &SinkConfig) -> io::Result<Box<dyn SampleSink>> {
+        let factory = self.factories.get(format).ok_or_else(|| {
+            let formats: Vec<_> = self.formats().collect();
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown sink format {format}, expected one of {}",
+                    formats.join(", ")
+                ),
+            )
+        })?;
+        factory(config)
+    }
+}
@@ -0,0 +1,1220 @@
+// Inputs of the preprocessing behind a common interface, so that other
+// origins of games can be added without touching the pipeline and several
+// inputs can be read as one. The source of a path is picked by open, by the
+// format detect recognizes or the one given explicitly:
+//
+//     tar        lc0 training data, a local file, an object or a URL (see
+//                storage)
+//     directory  the tar archives in a directory, in the order of their paths
+//     chunks     the .gz chunks of lc0 training data in a directory, as the
+//                training runs write them before packing them into tar files,
+//                or a single chunk
+//     binpack    Stockfish training data (.binpack), a game per chain of
+//                positions
+//     pgn        games in PGN (.pgn)
+//
+// Every source passes its games on as .gz entries of version 6 records, like
+// the entries of the tar archives, so the rest of the pipeline only knows the
+// training data format. The games of the other formats are converted with
+// encoder::GameRecorder: the policy is all on the played move, the targets
+// of binpack positions are their scores mapped to an expected score and the
+// targets of PGN games are their results.
+//
+// Games of a binpack or PGN file that cannot be converted are skipped with a
+// warning, like the corrupted entries of an archive. PGN games can also be
+// restricted with a PgnFilter, to the ratings of their players, to rated games,
+// to time controls, terminations and lengths that make for good targets. The
+// skipped games keep their entry indices so that resuming stays at the same
+// game.
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use shakmaty::fen::Fen;
+use shakmaty::san::SanPlus;
+use shakmaty::{
+    attacks, Bitboard, Board, CastlingMode, CastlingSide, Chess, Color, EnPassantMode, FromSetup,
+    Move, Piece, Position, PositionError, Rank, Role, Setup, Square,
+};
+use tracing::{debug, warn};
+
+use crate::encoder::GameRecorder;
+use crate::error::{PreprocessError, Result};
+use crate::reader::{self, GameEntry};
+use crate::record::Record;
+use crate::storage;
+use crate::synthetic::gzip;
+
+// Centipawns of an advantage with an expected score of about 0.91, like the
+// default --cp-scale of the rescore command.
+const CP_SCALE: f32 = 400.0;
+
+// Maximum size of a binpack chunk, larger ones are corrupted.
+const MAX_CHUNK_SIZE: usize = 100 << 20;
+
+// Number of bytes at the start of a file that detect looks at, enough for the
+// magic of a tar header.
+const SNIFF_LEN: usize = 512;
+
+/// Format of an input, see [`detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// A tar archive of lc0 training data.
+    Tar,
+    /// The tar archives in a directory.
+    Directory,
+    /// The .gz chunks of lc0 training data in a directory, or a single one.
+    Chunks,
+    /// Stockfish training data.
+    Binpack,
+    /// Games in PGN.
+    Pgn,
+}
+
+/// Origin of games, e.g. a tar archive of training data or a PGN file.
+pub trait SampleSource: Send {
+    /// Name of the source in logs and errors, e.g. its path.
+    fn name(&self) -> &str;
+
+    /// Calls `f` for every game of the source, starting from the game with
+    /// index `first_entry`. Returns false if `f` stopped the iteration by
+    /// returning false. The indices of the games are stable, so a later call
+    /// can continue where an earlier one stopped.
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool>;
+
+    /// The games of this source followed by the games of `other`.
+    fn chain<S: SampleSource + 'static>(self, other: S) -> Chain
+    where
+        Self: Sized + 'static,
+    {
+        Chain::new(vec![Box::new(self), Box::new(other)])
+    }
+}
+
+// The sources picked by open can be passed where a source is expected.
+impl SampleSource for Box<dyn SampleSource> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        (**self).for_each_game(first_entry, f)
+    }
+}
+
+/// The format of the input at the path. Local directories hold tar archives,
+/// or only .gz chunks. Files are recognized by their extension, otherwise by
+/// their first bytes (see [`sniff`]), and are read as tar archives if neither
+/// tells the format, like the archives of old tar versions without a magic.
+pub fn detect<P: AsRef<Path>>(path: P) -> io::Result<InputFormat> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+    if !storage::is_remote(&name) && path.is_dir() {
+        let only_chunks = storage::list_archives(&name)?.is_empty()
+            && !storage::list_files(&name, ".gz")?.is_empty();
+        return Ok(if only_chunks {
+            InputFormat::Chunks
+        } else {
+            InputFormat::Directory
+        });
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("tar") => return Ok(InputFormat::Tar),
+        Some("gz") => return Ok(InputFormat::Chunks),
+        Some("binpack") => return Ok(InputFormat::Binpack),
+        Some("pgn") => return Ok(InputFormat::Pgn),
+        _ => {}
+    }
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    storage::open(&name)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(sniff(&header).unwrap_or(InputFormat::Tar))
+}
+
+/// The format of a file starting with these bytes, None if they do not tell:
+/// the magic of a tar header, a binpack chunk or a gzip stream, or the start
+/// of the tags or the moves of a PGN game.
+///
+/// ```
+/// use preprocessing::source::{sniff, InputFormat};
+///
+/// assert_eq!(sniff(b"BINP\x10\x00\x00\x00"), Some(InputFormat::Binpack));
+/// assert_eq!(sniff(b"\x1f\x8b\x08\x00"), Some(InputFormat::Chunks));
+/// assert_eq!(sniff(b"\n[Event \"?\"]\n"), Some(InputFormat::Pgn));
+/// assert_eq!(sniff(b"1. e4 e5 *"), Some(InputFormat::Pgn));
+/// let mut header = [0; 512];
+/// header[257..262].copy_from_slice(b"ustar");
+/// assert_eq!(sniff(&header), Some(InputFormat::Tar));
+/// assert_eq!(sniff(b""), None);
+/// ```
+pub fn sniff(header: &[u8]) -> Option<InputFormat> {
+    if header.get(257..262) == Some(b"ustar".as_slice()) {
+        return Some(InputFormat::Tar);
+    }
+    if header.starts_with(b"BINP") {
+        return Some(InputFormat::Binpack);
+    }
+    if header.starts_with(&[0x1f, 0x8b]) {
+        return Some(InputFormat::Chunks);
+    }
+    let text = header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header);
+    let start = text.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    match &text[start..] {
+        [b'[', ..] | [b'1', b'.', ..] => Some(InputFormat::Pgn),
+        _ => None,
+    }
+}
+
+/// The source of the path, in the format given or the one [`detect`]
+/// recognizes. Only local directories are read as directories.
+pub fn open_as<P: AsRef<Path>>(
+    path: P,
+    format: Option<InputFormat>,
+) -> io::Result<Box<dyn SampleSource>> {
+    open_filtered(path, format, PgnFilter::default())
+}
+
+/// Like [`open_as`], with the games of PGN sources restricted by the filter.
+pub fn open_filtered<P: AsRef<Path>>(
+    path: P,
+    format: Option<InputFormat>,
+    pgn_filter: PgnFilter,
+) -> io::Result<Box<dyn SampleSource>> {
+    let path = path.as_ref();
+    let format = match format {
+        Some(format) => format,
+        None => detect(path)?,
+    };
+    Ok(match format {
+        InputFormat::Tar => Box::new(TarSource::new(path)),
+        InputFormat::Directory => Box::new(directory(&path.to_string_lossy())?),
+        InputFormat::Chunks => Box::new(ChunkSource::new(path)?),
+        InputFormat::Binpack => Box::new(BinpackSource::new(path)),
+        InputFormat::Pgn => Box::new(PgnSource::new(path).with_filter(pgn_filter)),
+    })
+}
+
+/// The source of the path in the format [`detect`] recognizes.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn SampleSource>> {
+    open_as(path, None)
+}
+
+/// Calls `f` for every game of the source of the path, see [`open`] and
+/// [`SampleSource::for_each_game`].
+pub fn for_each_game<P, F>(path: P, first_entry: usize, f: F) -> Result<bool>
+where
+    P: AsRef<Path>,
+    F: FnMut(GameEntry) -> bool,
+{
+    for_each_game_as(path, None, first_entry, f)
+}
+
+/// Like [`for_each_game`], in the format given or the detected one.
+pub fn for_each_game_as<P, F>(
+    path: P,
+    format: Option<InputFormat>,
+    first_entry: usize,
+    f: F,
+) -> Result<bool>
+where
+    P: AsRef<Path>,
+    F: FnMut(GameEntry) -> bool,
+{
+    for_each_game_filtered(path, format, PgnFilter::default(), first_entry, f)
+}
+
+/// Like [`for_each_game_as`], with the games of PGN sources restricted by the
+/// filter, see [`open_filtered`].
+pub fn for_each_game_filtered<P, F>(
+    path: P,
+    format: Option<InputFormat>,
+    pgn_filter: PgnFilter,
+    first_entry: usize,
+    mut f: F,
+) -> Result<bool>
+where
+    P: AsRef<Path>,
+    F: FnMut(GameEntry) -> bool,
+{
+    open_filtered(path, format, pgn_filter)?.for_each_game(first_entry, &mut f)
+}
+
+/// Size of the source of the path in bytes, the total size of the archives or
+/// chunks of a directory.
+pub fn size<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+    if !storage::is_remote(&name) && path.is_dir() {
+        let files = match detect(path)? {
+            InputFormat::Chunks => storage::list_files(&name, ".gz")?,
+            _ => storage::list_archives(&name)?,
+        };
+        return Ok(files.iter().map(|(_, size)| size).sum());
+    }
+    storage::size(&name)
+}
+
+/// A tar archive of lc0 training data, see [`reader::for_each_game`]. The
+/// indices of the games are the indices of their tar entries.
+pub struct TarSource {
+    path: PathBuf,
+    name: String,
+}
+
+impl TarSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let name = path.display().to_string();
+        TarSource { path, name }
+    }
+}
+
+impl SampleSource for TarSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        reader::for_each_game(&self.path, first_entry, f)
+    }
+}
+
+/// The tar archives in the directory, or under the prefix of the bucket, see
+/// [`storage::list_archives`].
+pub fn directory(dir: &str) -> io::Result<Chain> {
+    let sources = storage::list_archives(dir)?
+        .into_iter()
+        .map(|(path, _)| Box::new(TarSource::new(path)) as Box<dyn SampleSource>)
+        .collect();
+    Ok(Chain::named(dir, sources))
+}
+
+/// The .gz chunks of lc0 training data in a local directory, in the order of
+/// their paths, or a single chunk. Every chunk is a game, its index is the
+/// number of chunks before it.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::source::{detect, ChunkSource, InputFormat, SampleSource};
+/// use preprocessing::synthetic::{gzip, random_game};
+///
+/// let mut rng = Rng::new(42);
+/// let dir = std::env::temp_dir().join("chunk-source-doctest");
+/// std::fs::create_dir_all(&dir)?;
+/// for name in ["game_000001.gz", "game_000002.gz", "game_000003.gz"] {
+///     std::fs::write(dir.join(name), gzip(&random_game(&mut rng, 10)))?;
+/// }
+/// assert_eq!(detect(&dir)?, InputFormat::Chunks);
+///
+/// let mut indices = Vec::new();
+/// ChunkSource::new(&dir)?.for_each_game(1, &mut |entry| {
+///     indices.push(entry.index);
+///     true
+/// })?;
+/// assert_eq!(indices, [1, 2]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ChunkSource {
+    name: String,
+    // Paths and sizes of the chunks.
+    chunks: Vec<(String, u64)>,
+}
+
+impl ChunkSource {
+    /// A single chunk may be in object storage, see [`storage`].
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let chunks = if !storage::is_remote(&name) && path.is_dir() {
+            storage::list_files(&name, ".gz")?
+        } else {
+            vec![(name.clone(), storage::size(&name)?)]
+        };
+        Ok(ChunkSource { name, chunks })
+    }
+}
+
+impl SampleSource for ChunkSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        let mut end_offset = 0;
+        for (index, (path, size)) in self.chunks.iter().enumerate() {
+            end_offset += size;
+            if index < first_entry {
+                continue;
+            }
+            let mut data = Vec::with_capacity(*size as usize);
+            storage::open(path)?.read_to_end(&mut data)?;
+            let entry = GameEntry {
+                index,
+                end_offset,
+                data,
+            };
+            if !f(entry) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Several sources read one after the other. The games are numbered across
+/// all of them and their offsets continue from the last offset of the
+/// preceding source.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::source::{SampleSource, TarSource};
+/// use preprocessing::synthetic::{gzip, random_game, tar};
+///
+/// let mut rng = Rng::new(42);
+/// let dir = std::env::temp_dir();
+/// let mut paths = Vec::new();
+/// for name in ["source-doctest-1.tar", "source-doctest-2.tar"] {
+///     let games: Vec<Vec<u8>> = (0..2).map(|_| gzip(&random_game(&mut rng, 10))).collect();
+///     std::fs::write(dir.join(name), tar(&games)?)?;
+///     paths.push(dir.join(name));
+/// }
+///
+/// let mut chain = TarSource::new(&paths[0]).chain(TarSource::new(&paths[1]));
+/// let mut indices = Vec::new();
+/// chain.for_each_game(1, &mut |entry| {
+///     indices.push(entry.index);
+///     true
+/// })?;
+/// assert_eq!(indices, [1, 2, 3]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Chain {
+    name: String,
+    sources: Vec<Box<dyn SampleSource>>,
+}
+
+impl Chain {
+    pub fn new(sources: Vec<Box<dyn SampleSource>>) -> Self {
+        let names: Vec<_> = sources.iter().map(|source| source.name()).collect();
+        let name = names.join(" + ");
+        Chain::named(name, sources)
+    }
+
+    pub fn named(name: impl Into<String>, sources: Vec<Box<dyn SampleSource>>) -> Self {
+        Chain {
+            name: name.into(),
+            sources,
+        }
+    }
+}
+
+impl SampleSource for Chain {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        let mut index = 0;
+        let mut offset = 0;
+        for source in &mut self.sources {
+            let mut end = 0;
+            let finished = source
+                .for_each_game(0, &mut |mut entry| {
+                    end = end.max(entry.end_offset);
+                    entry.index = index;
+                    entry.end_offset += offset;
+                    index += 1;
+                    entry.index < first_entry || f(entry)
+                })
+                .map_err(|err| err.of_archive(source.name()))?;
+            if !finished {
+                return Ok(false);
+            }
+            offset += end;
+        }
+        Ok(true)
+    }
+}
+
+// The game as a .gz entry.
+fn entry(index: usize, end_offset: u64, records: &[Record]) -> GameEntry {
+    GameEntry {
+        index,
+        end_offset,
+        data: gzip(records),
+    }
+}
+
+// Expected score of a centipawn score, mate scores are wins and losses.
+fn cp_to_q(cp: i16) -> f32 {
+    if cp.unsigned_abs() >= 32000 {
+        return cp.signum() as f32;
+    }
+    2.0 / (1.0 + 10f32.powf(-(cp as f32) / CP_SCALE)) - 1.0
+}
+
+fn invalid(message: impl Into<String>) -> PreprocessError {
+    PreprocessError::InvalidGame(message.into())
+}
+
+/// Stockfish training data in the binpack format: chunks of chains of
+/// positions, each of which is a position followed by the moves played from
+/// it. Every chain is a game, its index is the number of chains before it.
+pub struct BinpackSource {
+    name: String,
+}
+
+impl BinpackSource {
+    /// The file may be in object storage, see [`storage`].
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let name = path.as_ref().display().to_string();
+        BinpackSource { name }
+    }
+}
+
+impl SampleSource for BinpackSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        let mut file = storage::open(&self.name)?;
+        let mut index = 0;
+        // Offset of the data of the chunk within the file.
+        let mut chunk_offset = 0;
+        loop {
+            let mut header = [0; 8];
+            match file.read_exact(&mut header) {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(true),
+                result => result?,
+            }
+            if &header[..4] != b"BINP" {
+                return Err(invalid(format!("no binpack chunk at byte {chunk_offset}")));
+            }
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            if size > MAX_CHUNK_SIZE {
+                return Err(invalid(format!("binpack chunk of {size} bytes")));
+            }
+            chunk_offset += header.len() as u64;
+            let mut chunk = vec![0; size];
+            file.read_exact(&mut chunk)?;
+
+            let mut offset = 0;
+            while offset < chunk.len() {
+                let game = index;
+                index += 1;
+                let records = match read_chain(&chunk, &mut offset) {
+                    Ok(records) => records,
+                    // The chains have no markers to find the next one.
+                    Err(err) => {
+                        let at = chunk_offset + offset as u64;
+                        warn!(source = %self.name, %err, at, "skipping the rest of the chunk");
+                        break;
+                    }
+                };
+                let end_offset = chunk_offset + offset as u64;
+                if game >= first_entry && !f(entry(game, end_offset, &records)) {
+                    return Ok(false);
+                }
+            }
+            chunk_offset += size as u64;
+        }
+    }
+}
+
+// Records of the chain starting at the offset in the chunk, moving the offset
+// past it.
+fn read_chain(chunk: &[u8], offset: &mut usize) -> Result<Vec<Record>> {
+    let truncated = || invalid("truncated chain");
+    let stem = chunk.get(*offset..*offset + 32).ok_or_else(truncated)?;
+    let plies = chunk
+        .get(*offset + 32..*offset + 34)
+        .ok_or_else(truncated)?;
+    let plies = u16::from_be_bytes([plies[0], plies[1]]);
+    *offset += 34;
+
+    let score = unsigned_to_signed(u16::from_be_bytes([stem[26], stem[27]]));
+    let ply_and_result = u16::from_be_bytes([stem[28], stem[29]]);
+    let rule50 = u16::from_be_bytes([stem[30], stem[31]]);
+    let pos = unpack_position(&stem[..24], rule50, ply_and_result & 0x3FFF)?;
+    let winner = match unsigned_to_signed(ply_and_result >> 14) {
+        1 => Some(pos.turn()),
+        -1 => Some(!pos.turn()),
+        _ => None,
+    };
+    let packed = u16::from_be_bytes([stem[24], stem[25]]);
+    let from = Square::new(((packed >> 8) & 63) as u32);
+    let to = Square::new(((packed >> 2) & 63) as u32);
+    let m = match packed >> 14 {
+        1 => {
+            let promotion = [Role::Knight, Role::Bishop, Role::Rook, Role::Queen];
+            normal_move(&pos, from, to, Some(promotion[(packed & 3) as usize]))?
+        }
+        2 => Move::Castle {
+            king: from,
+            rook: to,
+        },
+        3 => Move::EnPassant { from, to },
+        _ => normal_move(&pos, from, to, None)?,
+    };
+
+    let mut recorder = GameRecorder::new(pos);
+    play_checked(&mut recorder, &m, score)?;
+    let mut text = MoveText::new(&chunk[*offset..]);
+    // The scores are stored as the differences to the negated score of the
+    // previous position.
+    let mut last_score = score.wrapping_neg();
+    for _ in 0..plies {
+        let m = next_move(recorder.position(), &mut text)?;
+        let score = last_score.wrapping_add(unsigned_to_signed(text.vle16(4)?));
+        last_score = score.wrapping_neg();
+        play_checked(&mut recorder, &m, score)?;
+    }
+    if plies > 0 {
+        *offset += text.bytes_read();
+    }
+    Ok(recorder.finish(winner))
+}
+
+fn play_checked(recorder: &mut GameRecorder, m: &Move, score: i16) -> Result<()> {
+    if !recorder.position().is_legal(m) {
+        return Err(invalid(format!("illegal move {m}")));
+    }
+    recorder.play(m, cp_to_q(score), 0.0);
+    Ok(())
+}
+
+// The signed values are stored with the sign in the lowest bit.
+fn unsigned_to_signed(value: u16) -> i16 {
+    let mut value = value.rotate_right(1);
+    if value & 0x8000 != 0 {
+        value ^= 0x7FFF;
+    }
+    value as i16
+}
+
+// The occupied squares followed by a nibble per piece, which also encodes the
+// castling rights, the en passant square and the side to move.
+fn unpack_position(bytes: &[u8], rule50: u16, ply: u16) -> Result<Chess> {
+    let occupied = Bitboard(u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes")));
+    if occupied.count() > 32 {
+        return Err(invalid("more than 32 pieces"));
+    }
+    let mut setup = Setup::empty();
+    let mut board = Board::empty();
+    for (idx, square) in occupied.into_iter().enumerate() {
+        let nibble = (bytes[8 + idx / 2] >> ((idx % 2) * 4)) & 0xF;
+        let piece = match nibble {
+            0..=11 => Piece {
+                color: Color::from_white(nibble.is_multiple_of(2)),
+                role: Role::ALL[nibble as usize / 2],
+            },
+            // A pawn that just moved two squares.
+            12 => {
+                let color = Color::from_white(square.rank() == Rank::Fourth);
+                setup.ep_square = square.offset(color.fold_wb(-8, 8));
+                Piece {
+                    color,
+                    role: Role::Pawn,
+                }
+            }
+            // A rook that can castle.
+            13 | 14 => {
+                setup.castling_rights.add(square);
+                Piece {
+                    color: Color::from_white(nibble == 13),
+                    role: Role::Rook,
+                }
+            }
+            _ => {
+                setup.turn = Color::Black;
+                Piece {
+                    color: Color::Black,
+                    role: Role::King,
+                }
+            }
+        };
+        board.set_piece_at(square, piece);
+    }
+    setup.board = board;
+    setup.halfmoves = rule50 as u32;
+    setup.fullmoves = NonZeroU32::new(1 + ply as u32 / 2).expect("at least 1");
+    Chess::from_setup(setup, CastlingMode::Chess960)
+        .or_else(PositionError::ignore_invalid_castling_rights)
+        .or_else(PositionError::ignore_invalid_ep_square)
+        .map_err(|err| PreprocessError::IllegalPosition(err.to_string()))
+}
+
+fn normal_move(pos: &Chess, from: Square, to: Square, promotion: Option<Role>) -> Result<Move> {
+    let role = pos
+        .board()
+        .role_at(from)
+        .ok_or_else(|| invalid(format!("no piece on {from}")))?;
+    Ok(Move::Normal {
+        role,
+        from,
+        capture: pos.board().role_at(to),
+        to,
+        promotion,
+    })
+}
+
+// Number of bits of the index of one of `count` choices.
+fn index_bits(count: usize) -> usize {
+    if count <= 1 {
+        0
+    } else {
+        (usize::BITS - (count - 1).leading_zeros()) as usize
+    }
+}
+
+fn nth_square(squares: Bitboard, n: u8) -> Result<Square> {
+    squares
+        .into_iter()
+        .nth(n as usize)
+        .ok_or_else(|| invalid("move index out of range"))
+}
+
+// A move of a chain, stored as the index of the moving piece among the pieces
+// of the side to move and the index of the move among the moves of the piece
+// in the order of their destinations.
+fn next_move(pos: &Chess, text: &mut MoveText) -> Result<Move> {
+    let turn = pos.turn();
+    let board = pos.board();
+    let ours = board.by_color(turn);
+    let occupied = board.occupied();
+    let from = nth_square(ours, text.bits(index_bits(ours.count()))?)?;
+    let role = board.role_at(from).expect("a piece of the side to move");
+    match role {
+        Role::Pawn => {
+            let ep_square = pos.ep_square(EnPassantMode::Legal);
+            let mut targets = board.by_color(!turn);
+            if let Some(ep_square) = ep_square {
+                targets.add(ep_square);
+            }
+            let mut destinations = attacks::pawn_attacks(turn, from) & targets;
+            let forward = turn.fold_wb(8, -8);
+            if let Some(single) = from.offset(forward).filter(|sq| !occupied.contains(*sq)) {
+                destinations.add(single);
+                let start = turn.fold_wb(Rank::Second, Rank::Seventh);
+                if let Some(double) = single.offset(forward) {
+                    if from.rank() == start && !occupied.contains(double) {
+                        destinations.add(double);
+                    }
+                }
+            }
+            if from.rank() == turn.fold_wb(Rank::Seventh, Rank::Second) {
+                let idx = text.bits(index_bits(destinations.count() * 4))?;
+                let to = nth_square(destinations, idx / 4)?;
+                let promotion = [Role::Knight, Role::Bishop, Role::Rook, Role::Queen];
+                normal_move(pos, from, to, Some(promotion[(idx % 4) as usize]))
+            } else {
+                let to = nth_square(destinations, text.bits(index_bits(destinations.count()))?)?;
+                if Some(to) == ep_square {
+                    Ok(Move::EnPassant { from, to })
+                } else {
+                    normal_move(pos, from, to, None)
+                }
+            }
+        }
+        Role::King => {
+            let destinations = attacks::king_attacks(from) & !ours;
+            let castles = pos.castles();
+            let long = castles.has(turn, CastlingSide::QueenSide);
+            let short = castles.has(turn, CastlingSide::KingSide);
+            let count = destinations.count() + long as usize + short as usize;
+            let idx = text.bits(index_bits(count))? as usize;
+            if idx < destinations.count() {
+                return normal_move(pos, from, nth_square(destinations, idx as u8)?, None);
+            }
+            let side = if idx == destinations.count() && long {
+                CastlingSide::QueenSide
+            } else {
+                CastlingSide::KingSide
+            };
+            let rook = castles
+                .rook(turn, side)
+                .ok_or_else(|| invalid("castling without the right"))?;
+            Ok(Move::Castle { king: from, rook })
+        }
+        _ => {
+            let destinations = attacks::attacks(from, role.of(turn), occupied) & !ours;
+            let to = nth_square(destinations, text.bits(index_bits(destinations.count()))?)?;
+            normal_move(pos, from, to, None)
+        }
+    }
+}
+
+// The bits of the moves and scores of a chain, most significant bit first.
+struct MoveText<'a> {
+    data: &'a [u8],
+    offset: usize,
+    // Bits of the byte at the offset that are not read yet.
+    bits_left: usize,
+}
+
+impl<'a> MoveText<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        MoveText {
+            data,
+            offset: 0,
+            bits_left: 8,
+        }
+    }
+
+    fn byte(&self, offset: usize) -> Result<u8> {
+        self.data
+            .get(offset)
+            .copied()
+            .ok_or_else(|| invalid("truncated chain"))
+    }
+
+    // Up to 8 bits.
+    fn bits(&mut self, count: usize) -> Result<u8> {
+        if count == 0 {
+            return Ok(0);
+        }
+        if self.bits_left == 0 {
+            self.offset += 1;
+            self.bits_left = 8;
+        }
+        let byte = self.byte(self.offset)? << (8 - self.bits_left);
+        let mut bits = byte >> (8 - count);
+        if count > self.bits_left {
+            let spilled = count - self.bits_left;
+            bits |= self.byte(self.offset + 1)? >> (8 - spilled);
+            self.bits_left += 8;
+            self.offset += 1;
+        }
+        self.bits_left -= count;
+        Ok(bits)
+    }
+
+    // A value in blocks of `block` bits, each followed by a bit telling
+    // whether another block follows, the lowest block first.
+    fn vle16(&mut self, block: usize) -> Result<u16> {
+        let mask = (1 << block) - 1;
+        let mut value = 0u16;
+        let mut shift = 0;
+        loop {
+            let bits = self.bits(block + 1)? as u16;
+            if shift >= 16 {
+                return Err(invalid("score out of range"));
+            }
+            value |= (bits & mask) << shift;
+            if bits >> block == 0 {
+                return Ok(value);
+            }
+            shift += block;
+        }
+    }
+
+    fn bytes_read(&self) -> usize {
+        self.offset + (self.bits_left != 8) as usize
+    }
+}
+
+/// Games in PGN, with the results of the games as the targets of their
+/// positions. Games with a FEN tag start from that position, the Chess960
+/// variant is supported. Every game is a game entry, its index is the number
+/// of games before it.
+///
+/// ```
+/// use preprocessing::source::{PgnSource, SampleSource};
+/// use preprocessing::{DecodeOptions, Game};
+///
+/// let path = std::env::temp_dir().join("source-doctest.pgn");
+/// std::fs::write(
+///     &path,
+///     "[Event \"?\"]\n[Result \"0-1\"]\n\n1. f3 e5 2. g4?? {blunder} Qh4# 0-1\n\n\
+///      [Event \"?\"]\n\n1. e4 (1. d4) e5 2. Nf3 *\n",
+/// )?;
+///
+/// let mut games = Vec::new();
+/// PgnSource::new(&path).for_each_game(0, &mut |entry| {
+///     games.push(Game::read_from(entry.data.as_slice(), DecodeOptions::default()));
+///     true
+/// })?;
+/// assert_eq!(games.len(), 2);
+/// let fools_mate = games[0].as_ref().unwrap();
+/// assert_eq!(fools_mate.samples.len(), 4);
+/// // White to move and lost.
+/// assert_eq!(fools_mate.samples[0].result_q, -1.0);
+/// assert_eq!(games[1].as_ref().unwrap().samples.len(), 3);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct PgnSource {
+    name: String,
+    filter: PgnFilter,
+}
+
+impl PgnSource {
+    /// The file may be in object storage, see [`storage`].
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let name = path.as_ref().display().to_string();
+        PgnSource {
+            name,
+            filter: PgnFilter::default(),
+        }
+    }
+
+    /// Skips the games the filter rejects. They still count for the indices
+    /// of the later games.
+    pub fn with_filter(mut self, filter: PgnFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Which PGN games are read, by the ratings of both players in the WhiteElo
+/// and BlackElo tags, by whether the games are rated, by their time controls
+/// and terminations and by their lengths. Games with a missing or unknown
+/// rating or time control are skipped when a bound on it is set.
+///
+/// Rated games are the ones whose Event tag starts with "Rated", like "Rated
+/// Blitz game" of Lichess. The duration of a time control "base+increment" in
+/// seconds is estimated like Lichess does, as the base time and 40 times the
+/// increment, so that 180 excludes bullet games. Games without a clock ("-")
+/// are never too short. Games end normally unless their Termination tag says
+/// otherwise, e.g. "Time forfeit", "Abandoned" or "Rules infraction".
+///
+/// ```
+/// use preprocessing::source::{PgnFilter, PgnSource, SampleSource};
+///
+/// let path = std::env::temp_dir().join("pgn-filter-doctest.pgn");
+/// std::fs::write(
+///     &path,
+///     "[Event \"Rated Blitz game\"]\n[WhiteElo \"1850\"]\n[BlackElo \"1920\"]\n\
+///      [TimeControl \"180+2\"]\n\n1. e4 e5 *\n\n\
+///      [Event \"Casual Blitz game\"]\n[WhiteElo \"1900\"]\n[BlackElo \"1900\"]\n\
+///      [TimeControl \"180+2\"]\n\n1. d4 d5 *\n\n\
+///      [Event \"Rated Blitz game\"]\n[WhiteElo \"2400\"]\n[BlackElo \"1800\"]\n\
+///      [TimeControl \"180+2\"]\n\n1. c4 c5 *\n\n\
+///      [Event \"Rated Bullet game\"]\n[WhiteElo \"1900\"]\n[BlackElo \"1900\"]\n\
+///      [TimeControl \"60+0\"]\n\n1. Nf3 Nf6 *\n\n\
+///      [Event \"Rated Blitz game\"]\n[WhiteElo \"1900\"]\n[BlackElo \"1900\"]\n\
+///      [TimeControl \"300+0\"]\n[Termination \"Abandoned\"]\n\n1. g3 g6 *\n",
+/// )?;
+///
+/// let filter = PgnFilter {
+///     min_elo: Some(1800),
+///     max_elo: Some(2000),
+///     rated_only: true,
+///     min_time_control: Some(180),
+///     normal_termination: true,
+///     min_plies: Some(2),
+/// };
+/// let mut indices = Vec::new();
+/// PgnSource::new(&path)
+///     .with_filter(filter)
+///     .for_each_game(0, &mut |entry| {
+///         indices.push(entry.index);
+///         true
+///     })?;
+/// assert_eq!(indices, [0]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PgnFilter {
+    /// Lowest rating of both players.
+    pub min_elo: Option<u32>,
+    /// Highest rating of both players.
+    pub max_elo: Option<u32>,
+    pub rated_only: bool,
+    /// Shortest estimated duration of the time control in seconds.
+    pub min_time_control: Option<u32>,
+    /// Skips the games that did not end normally.
+    pub normal_termination: bool,
+    /// Fewest moves of the main line of a game, counting both sides.
+    pub min_plies: Option<u32>,
+}
+
+// Estimated duration of the time control of the TimeControl tag in seconds,
+// u32::MAX without a clock and None if the tag is not "base+increment".
+fn time_control_seconds(tag: &str) -> Option<u32> {
+    let tag = tag.trim();
+    if tag == "-" {
+        return Some(u32::MAX);
+    }
+    let (base, increment) = tag.split_once('+').unwrap_or((tag, "0"));
+    let base = base.parse::<u32>().ok()?;
+    let increment = increment.parse::<u32>().ok()?;
+    Some(base.saturating_add(increment.saturating_mul(40)))
+}
+
+impl PgnFilter {
+    fn accepts(&self, game: &PgnGame) -> bool {
+        if self.rated_only
+            && !game
+                .tag("Event")
+                .is_some_and(|event| event.to_ascii_lowercase().starts_with("rated"))
+        {
+            return false;
+        }
+        if self
+            .min_plies
+            .is_some_and(|min| game.moves.len() < min as usize)
+        {
+            return false;
+        }
+        if self.normal_termination
+            && game
+                .tag("Termination")
+                .is_some_and(|termination| !termination.eq_ignore_ascii_case("normal"))
+        {
+            return false;
+        }
+        if let Some(min) = self.min_time_control {
+            let seconds = game.tag("TimeControl").and_then(time_control_seconds);
+            if seconds.is_none_or(|seconds| seconds < min) {
+                return false;
+            }
+        }
+        if self.min_elo.is_none() && self.max_elo.is_none() {
+            return true;
+        }
+        ["WhiteElo", "BlackElo"].iter().all(|tag| {
+            game.tag(tag)
+                .and_then(|elo| elo.trim().parse::<u32>().ok())
+                .is_some_and(|elo| {
+                    self.min_elo.is_none_or(|min| elo >= min)
+                        && self.max_elo.is_none_or(|max| elo <= max)
+                })
+        })
+    }
+}
+
+impl SampleSource for PgnSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn for_each_game(
+        &mut self,
+        first_entry: usize,
+        f: &mut dyn FnMut(GameEntry) -> bool,
+    ) -> Result<bool> {
+        let file = storage::open(&self.name)?;
+        let mut index = 0;
+        let mut skipped = 0u64;
+        let result = read_pgn(BufReader::new(file), |game, end_offset| {
+            let current = index;
+            index += 1;
+            if current < first_entry {
+                return true;
+            }
+            if !self.filter.accepts(&game) {
+                skipped += 1;
+                return true;
+            }
+            match game.records() {
+                Ok(records) => f(entry(current, end_offset, &records)),
+                Err(err) => {
+                    warn!(source = %self.name, game = current, %err, "skipping the game");
+                    true
+                }
+            }
+        });
+        if skipped > 0 {
+            debug!(source = %self.name, games = skipped, "skipped the games outside the filter");
+        }
+        result
+    }
+}
+
+// The tags and the moves of the main line of a PGN game.
+#[derive(Debug, Default)]
+struct PgnGame {
+    tags: Vec<(String, String)>,
+    moves: Vec<String>,
+    // The termination of the movetext, if any.
+    result: Option<String>,
+}
+
+impl PgnGame {
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn records(&self) -> Result<Vec<Record>> {
+        let mode = match self.tag("Variant") {
+            None => CastlingMode::Standard,
+            Some(variant) if variant.eq_ignore_ascii_case("chess960") => CastlingMode::Chess960,
+            Some(variant)
+                if ["standard", "chess", "from position"]
+                    .iter()
+                    .any(|name| variant.eq_ignore_ascii_case(name)) =>
+            {
+                CastlingMode::Standard
+            }
+            Some(variant) => return Err(invalid(format!("unsupported variant {variant}"))),
+        };
+        let pos = match self.tag("FEN") {
+            Some(fen) => fen
+                .parse::<Fen>()
+                .map_err(|err| invalid(format!("invalid FEN {fen}: {err}")))?
+                .into_position(mode)
+                .map_err(|err| PreprocessError::IllegalPosition(err.to_string()))?,
+            None => Chess::default(),
+        };
+        // Unfinished games count as draws.
+        let winner = match self.result.as_deref().or(self.tag("Result")) {
+            Some("1-0") => Some(Color::White),
+            Some("0-1") => Some(Color::Black),
+            _ => None,
+        };
+        if self.moves.is_empty() {
+            return Err(invalid("no moves"));
+        }
+
+        let mut recorder = GameRecorder::new(pos);
+        for san in &self.moves {
+            let m = san
+                .parse::<SanPlus>()
+                .ok()
+                .and_then(|san| san.san.to_move(recorder.position()).ok())
+                .ok_or_else(|| invalid(format!("illegal move {san}")))?;
+            let (q, d) = match winner {
+                Some(winner) if winner == recorder.position().turn() => (1.0, 0.0),
+                Some(_) => (-1.0, 0.0),
+                None => (0.0, 1.0),
+            };
+            recorder.play(&m, q, d);
+        }
+        Ok(recorder.finish(winner))
+    }
+}
+
+// Calls `f` with every game of the PGN and the offset right after it, until
+// `f` returns false. Comments, variations and annotations are skipped.
+fn read_pgn<R, F>(mut reader: R, mut f: F) -> Result<bool>
+where
+    R: BufRead,
+    F: FnMut(PgnGame, u64) -> bool,
+{
+    let mut game = PgnGame::default();
+    let mut offset = 0;
+    let mut in_comment = false;
+    let mut depth = 0usize;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let len = reader.read_until(b'\n', &mut line)?;
+        if len == 0 {
+            break;
+        }
+        offset += len as u64;
+        let text = String::from_utf8_lossy(&line);
+        let trimmed = text.trim();
+        if !in_comment && depth == 0 {
+            if trimmed.starts_with('[') {
+                // A game without a termination ends with the tags of the next
+                // one.
+                if !game.moves.is_empty() && !f(mem::take(&mut game), offset - len as u64) {
+                    return Ok(false);
+                }
+                if let Some(tag) = parse_tag(trimmed) {
+                    game.tags.push(tag);
+                }
+                continue;
+            }
+            if trimmed.starts_with('%') {
+                continue;
+            }
+        }
+
+        let mut token = String::new();
+        let mut tokens = Vec::new();
+        for c in text.chars() {
+            if in_comment {
+                in_comment = c != '}';
+                continue;
+            }
+            match c {
+                '{' | '(' | ')' | ';' => {
+                    if depth == 0 && !token.is_empty() {
+                        tokens.push(mem::take(&mut token));
+                    }
+                    token.clear();
+                    match c {
+                        '{' => in_comment = true,
+                        '(' => depth += 1,
+                        ')' => depth = depth.saturating_sub(1),
+                        _ => break,
+                    }
+                }
+                c if c.is_whitespace() => {
+                    if depth == 0 && !token.is_empty() {
+                        tokens.push(mem::take(&mut token));
+                    }
+                    token.clear();
+                }
+                c => token.push(c),
+            }
+        }
+        if depth == 0 && !token.is_empty() {
+            tokens.push(token);
+        }
+
+        for token in tokens {
+            if matches!(token.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+                game.result = Some(token);
+                if !f(mem::take(&mut game), offset) {
+                    return Ok(false);
+                }
+                continue;
+            }
+            // Move numbers, possibly glued to the move, and annotations.
+            let san = token.rsplit('.').next().unwrap_or_default();
+            let san = san.trim_end_matches(['!', '?']);
+            if !san.is_empty() && !san.starts_with('$') {
+                game.moves.push(san.to_string());
+            }
+        }
+    }
+    if !game.moves.is_empty() {
+        return Ok(f(game, offset));
+    }
+    Ok(true)
+}
+
+// The name and the value of a tag pair, e.g. [Result "1-0"].
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.trim_end().strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"")))
+}
@@ -0,0 +1,162 @@
+// Partitioning of the training data into train, validation and test sets.
+//
+// Positions of the same game are strongly correlated, so the split is done by
+// game: every game goes to a single split, picked by a hash of the seed, the
+// name of the archive and the entry index. The assignment does not depend on
+// the order of the inputs, the number of threads or on the other archives, so
+// adding archives later leaves the existing games in their splits.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::output::{serialize_position, OutputFields, ShardWriter};
+use preprocessing::rng::Rng;
+use preprocessing::{filter_position, FilterOptions, Game};
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::{info, info_span};
+
+use crate::archive::{self, GameEntry};
+use crate::report::{shard_reports, ShardReport};
+
+// Number of games processed in parallel.
+const GAMES_PER_BATCH: usize = 256;
+
+const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+#[derive(Args)]
+pub struct SplitArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Directory to write the train, val and test subdirectories to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Fractions of the games in the train, validation and test sets
+    #[arg(long, value_delimiter = ',', num_args = 3, default_values_t = [0.9, 0.05, 0.05])]
+    ratios: Vec<f64>,
+
+    /// Maximum number of samples in a single output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+}
+
+// Written to manifest.json in the directory of every split.
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    split: &'a str,
+    ratio: f64,
+    seed: u64,
+    inputs: &'a [String],
+    games: u64,
+    samples: u64,
+    shards: Vec<ShardReport>,
+}
+
+// Index of the split of the game.
+fn assign(root: &Rng, archive: &str, entry: usize, thresholds: &[f64; 3]) -> usize {
+    // The file name identifies the archive wherever it is stored.
+    let name = Path::new(archive)
+        .file_name()
+        .map_or(archive.into(), |name| name.to_string_lossy());
+    // FNV-1a, unlike the hashers of std it is stable across Rust versions.
+    let key = name.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    });
+    let x = root.fork(key).fork(entry as u64).next_f64();
+    thresholds
+        .iter()
+        .position(|&t| x < t)
+        .unwrap_or(SPLITS.len() - 1)
+}
+
+pub fn run(args: SplitArgs, seed: u64) -> io::Result<()> {
+    let total: f64 = args.ratios.iter().sum();
+    if args.ratios.iter().any(|&ratio| ratio < 0.0) || total <= 0.0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the ratios have to be non-negative and not all zero",
+        ));
+    }
+    let ratios: Vec<f64> = args.ratios.iter().map(|ratio| ratio / total).collect();
+    let thresholds = [ratios[0], ratios[0] + ratios[1], 1.0];
+
+    let root = Rng::new(seed);
+    let filters = FilterOptions::default();
+    let fields = OutputFields::default();
+    let mut writers = SPLITS
+        .iter()
+        .map(|split| ShardWriter::create(args.output_dir.join(split), args.shard_size))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut games = [0; 3];
+    let mut samples = [0; 3];
+
+    for archive in &args.tar_path {
+        let _span = info_span!("archive", path = %archive).entered();
+        let mut result = Ok(());
+        let mut batch = Vec::with_capacity(GAMES_PER_BATCH);
+        let mut flush = |batch: &mut Vec<GameEntry>| -> io::Result<()> {
+            let processed = batch
+                .par_iter()
+                .map(|entry| {
+                    let game = Game::read_from(entry.data.as_slice(), filters.decode_options())?;
+                    let lines: Vec<_> = game
+                        .samples
+                        .iter()
+                        .filter(|sample| filter_position(sample, &filters).is_none())
+                        .map(|sample| serialize_position(sample, &game, fields))
+                        .collect();
+                    let split = assign(&root, archive, entry.index, &thresholds);
+                    Ok::<_, io::Error>((split, lines))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            for (split, lines) in processed {
+                games[split] += 1;
+                samples[split] += lines.len() as u64;
+                for line in lines {
+                    writers[split].write_sample(&line)?;
+                }
+            }
+            batch.clear();
+            Ok(())
+        };
+        archive::for_each_game(archive, 0, |entry| {
+            batch.push(entry);
+            if batch.len() >= GAMES_PER_BATCH {
+                result = flush(&mut batch);
+            }
+            result.is_ok()
+        })?;
+        result?;
+        flush(&mut batch)?;
+    }
+
+    for (idx, split) in SPLITS.iter().enumerate() {
+        writers[idx].flush()?;
+        let dir = args.output_dir.join(split);
+        let manifest = Manifest {
+            split,
+            ratio: ratios[idx],
+            seed,
+            inputs: &args.tar_path,
+            games: games[idx],
+            samples: samples[idx],
+            shards: shard_reports(&dir)?,
+        };
+        let mut file = fs::File::create(dir.join("manifest.json"))?;
+        serde_json::to_writer_pretty(&mut file, &manifest).map_err(io::Error::other)?;
+        writeln!(file)?;
+        info!(
+            split,
+            games = games[idx],
+            samples = samples[idx],
+            "split written"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,602 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::phase;
+use preprocessing::{filter_position, DecodeOptions, Filter, FilterOptions, Game, TrainingSample};
+use rayon::prelude::*;
+use serde::Serialize;
+use shakmaty::{Bitboard, Square};
+
+use crate::progress::Progress;
+use crate::{archive, create_output};
+
+// Number of openings listed in the text output, the JSON has all of them.
+const TOP_OPENINGS: usize = 20;
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Also report the distribution of the policy entropy, which requires
+    /// decoding the whole policy of every sample
+    #[arg(long)]
+    policy_entropy: bool,
+
+    /// Also write the statistics as JSON to this path ("-" for stdout)
+    #[arg(long)]
+    json: Option<PathBuf>,
+
+    /// Write how often every piece is on every square as CSV to this path
+    /// ("-" for stdout), a row per piece and square
+    #[arg(long)]
+    occupancy_csv: Option<PathBuf>,
+
+    /// Write how often every piece is on every square as JSON heatmaps to
+    /// this path ("-" for stdout), the ranks of a piece from 8 to 1
+    #[arg(long)]
+    occupancy_json: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Scale {
+    Linear,
+    // Bin `i` holds the values in [2^i, 2^(i+1)), bin 0 also holds 0.
+    Log2,
+}
+
+// Histogram with equally sized bins, values outside of the range are counted
+// in the first or last bin.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    scale: Scale,
+    min: f64,
+    width: f64,
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn linear(min: f64, width: f64, bins: usize) -> Self {
+        Histogram {
+            scale: Scale::Linear,
+            min,
+            width,
+            counts: vec![0; bins],
+        }
+    }
+
+    fn log2(bins: usize) -> Self {
+        Histogram {
+            scale: Scale::Log2,
+            min: 0.0,
+            width: 1.0,
+            counts: vec![0; bins],
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        let value = match self.scale {
+            Scale::Linear => value,
+            Scale::Log2 => value.max(1.0).log2(),
+        };
+        let bin = ((value - self.min) / self.width).floor();
+        let bin = (bin.max(0.0) as usize).min(self.counts.len() - 1);
+        self.counts[bin] += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Fraction of the values in each bin.
+    pub fn shares(&self) -> Vec<f64> {
+        let total = self.total().max(1) as f64;
+        self.counts
+            .iter()
+            .map(|&count| count as f64 / total)
+            .collect()
+    }
+
+    // Total variation distance between the distributions of two histograms
+    // with the same bins: 0 for identical distributions, 1 for disjoint ones.
+    pub fn distance(&self, other: &Histogram) -> f64 {
+        let shares = self.shares().into_iter().zip(other.shares());
+        shares.map(|(a, b)| (a - b).abs()).sum::<f64>() / 2.0
+    }
+
+    pub fn bounds(&self, bin: usize) -> (f64, f64) {
+        // Hides the floating point error accumulated by the bin arithmetic.
+        let round = |x: f64| (x * 1e6).round() / 1e6;
+
+        let lower = self.min + bin as f64 * self.width;
+        match self.scale {
+            Scale::Linear => (round(lower), round(lower + self.width)),
+            Scale::Log2 => (lower.exp2(), (lower + self.width).exp2()),
+        }
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const BAR_WIDTH: u64 = 40;
+
+        let total = self.counts.iter().sum::<u64>().max(1);
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        // Empty bins at both ends are omitted.
+        let first = self.counts.iter().position(|&count| count > 0).unwrap_or(0);
+        let last = self
+            .counts
+            .iter()
+            .rposition(|&count| count > 0)
+            .unwrap_or(0);
+        for (bin, &count) in self.counts.iter().enumerate().take(last + 1).skip(first) {
+            let (lower, upper) = self.bounds(bin);
+            let range = if matches!(self.scale, Scale::Linear) && self.width == 1.0 {
+                format!("{lower}")
+            } else {
+                format!("[{lower}, {upper})")
+            };
+            writeln!(
+                f,
+                "  {range:>18} {count:>12} {:>6.2}% {}",
+                100.0 * count as f64 / total as f64,
+                "#".repeat((BAR_WIDTH * count / max) as usize)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Sums of win, draw and loss probabilities from the perspective of the side
+// to move.
+#[derive(Debug, Clone, Copy, Default)]
+struct Wdl {
+    win: f64,
+    draw: f64,
+    loss: f64,
+}
+
+impl Wdl {
+    // The expected score `q` is win - loss and `d` is the draw probability.
+    fn add(&mut self, q: f32, d: f32) {
+        let (q, d) = (q as f64, d as f64);
+        self.win += (1.0 + q - d) / 2.0;
+        self.draw += d;
+        self.loss += (1.0 - q - d) / 2.0;
+    }
+
+    fn merge(&mut self, other: &Wdl) {
+        self.win += other.win;
+        self.draw += other.draw;
+        self.loss += other.loss;
+    }
+
+    fn mean(&self, samples: u64) -> [f64; 3] {
+        let samples = samples.max(1) as f64;
+        [self.win / samples, self.draw / samples, self.loss / samples]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct WdlBucket {
+    samples: u64,
+    result: Wdl,
+    best: Wdl,
+}
+
+const PIECES_PER_WDL_BUCKET: u32 = 4;
+// The last bucket also holds the starting position with 32 pieces.
+const WDL_BUCKETS: usize = 8;
+
+// Distribution of the game results and of the search targets per number of
+// pieces on the board.
+#[derive(Debug, Clone, Default)]
+struct WdlTable {
+    buckets: [WdlBucket; WDL_BUCKETS],
+}
+
+impl WdlTable {
+    fn add(&mut self, num_pieces: u32, sample: &TrainingSample) {
+        let bucket = ((num_pieces / PIECES_PER_WDL_BUCKET) as usize).min(WDL_BUCKETS - 1);
+        let bucket = &mut self.buckets[bucket];
+        bucket.samples += 1;
+        bucket.result.add(sample.result_q, sample.result_d);
+        bucket.best.add(sample.best_q, sample.best_d);
+    }
+
+    fn merge(&mut self, other: &WdlTable) {
+        for (bucket, other) in self.buckets.iter_mut().zip(&other.buckets) {
+            bucket.samples += other.samples;
+            bucket.result.merge(&other.result);
+            bucket.best.merge(&other.best);
+        }
+    }
+
+    // Non-empty buckets with the range of the number of pieces they hold.
+    fn non_empty(&self) -> impl Iterator<Item = ((u32, u32), &WdlBucket)> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| bucket.samples > 0)
+            .map(|(idx, bucket)| {
+                let lower = idx as u32 * PIECES_PER_WDL_BUCKET;
+                let upper = if idx == WDL_BUCKETS - 1 {
+                    32
+                } else {
+                    lower + PIECES_PER_WDL_BUCKET - 1
+                };
+                ((lower, upper), bucket)
+            })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        self.non_empty()
+            .map(|((min_pieces, max_pieces), bucket)| {
+                serde_json::json!({
+                    "min_pieces": min_pieces,
+                    "max_pieces": max_pieces,
+                    "samples": bucket.samples,
+                    "result": bucket.result.mean(bucket.samples),
+                    "best": bucket.best.mean(bucket.samples),
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for WdlTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "  {:>6} {:>12}   {:>7} {:>7} {:>7}   {:>7} {:>7} {:>7}",
+            "pieces", "samples", "res W", "res D", "res L", "best W", "best D", "best L"
+        )?;
+        for ((min_pieces, max_pieces), bucket) in self.non_empty() {
+            let [rw, rd, rl] = bucket.result.mean(bucket.samples).map(|p| 100.0 * p);
+            let [bw, bd, bl] = bucket.best.mean(bucket.samples).map(|p| 100.0 * p);
+            let pieces = format!("{min_pieces}-{max_pieces}");
+            writeln!(
+                f,
+                "  {pieces:>6} {:>12}   {rw:>6.2}% {rd:>6.2}% {rl:>6.2}%   \
+                 {bw:>6.2}% {bd:>6.2}% {bl:>6.2}%",
+                bucket.samples,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Letters of the pieces of the planes of the samples, the pieces of the side
+// to move are uppercase.
+const PIECE_LETTERS: [char; 12] = ['P', 'N', 'B', 'R', 'Q', 'K', 'p', 'n', 'b', 'r', 'q', 'k'];
+
+// Number of samples with every piece on every square, from the perspective of
+// the side to move like the samples: white pieces are the ones of the side to
+// move. Skewed heatmaps point to biased data or to planes decoded wrongly.
+#[derive(Debug, Clone)]
+struct Occupancy {
+    counts: [[u64; 64]; 12],
+}
+
+impl Default for Occupancy {
+    fn default() -> Self {
+        Occupancy {
+            counts: [[0; 64]; 12],
+        }
+    }
+}
+
+impl Occupancy {
+    fn add(&mut self, sample: &TrainingSample) {
+        for (counts, &plane) in self.counts.iter_mut().zip(&sample.bitboards) {
+            for square in Bitboard(plane) {
+                counts[square as usize] += 1;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &Occupancy) {
+        for (counts, other) in self.counts.iter_mut().zip(&other.counts) {
+            for (count, other) in counts.iter_mut().zip(other) {
+                *count += other;
+            }
+        }
+    }
+
+    fn write_csv<W: Write>(&self, mut out: W, samples: u64) -> io::Result<()> {
+        writeln!(out, "piece,square,count,frequency")?;
+        for (letter, counts) in PIECE_LETTERS.iter().zip(&self.counts) {
+            for (square, &count) in Square::ALL.iter().zip(counts) {
+                let frequency = count as f64 / samples.max(1) as f64;
+                writeln!(out, "{letter},{square},{count},{frequency:.6}")?;
+            }
+        }
+        out.flush()
+    }
+
+    // Frequencies of every piece as 8 ranks of 8 files, starting from a8 like
+    // a diagram.
+    fn to_json(&self, samples: u64) -> serde_json::Value {
+        let pieces: serde_json::Map<_, _> = PIECE_LETTERS
+            .iter()
+            .zip(&self.counts)
+            .map(|(letter, counts)| {
+                let ranks: Vec<Vec<f64>> = counts
+                    .chunks(8)
+                    .rev()
+                    .map(|rank| {
+                        rank.iter()
+                            .map(|&count| count as f64 / samples.max(1) as f64)
+                            .collect()
+                    })
+                    .collect();
+                (letter.to_string(), ranks.into())
+            })
+            .collect();
+        serde_json::json!({
+            "samples": samples,
+            "perspective": "side to move",
+            "pieces": pieces,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Stats {
+    archives: u64,
+    games: u64,
+    samples: u64,
+    kept: u64,
+    filtered: BTreeMap<&'static str, u64>,
+    // Games which ended in a draw.
+    drawn_games: u64,
+    // Number of games per "<ECO code> <opening name>", "unknown" for the games
+    // that could not be classified.
+    openings: BTreeMap<String, u64>,
+    // Sum of the searched draw probabilities, divided by `samples` on output.
+    #[serde(skip)]
+    best_d_sum: f64,
+    piece_counts: Histogram,
+    phase_scores: Histogram,
+    game_lengths: Histogram,
+    best_q: Histogram,
+    rule50: Histogram,
+    visits: Histogram,
+    // Only collected with --policy-entropy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy_entropy: Option<Histogram>,
+    // Reported as the mean probabilities, see WdlTable::to_json.
+    #[serde(skip)]
+    wdl: WdlTable,
+    // Written with --occupancy-csv and --occupancy-json.
+    #[serde(skip)]
+    occupancy: Occupancy,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            archives: 0,
+            games: 0,
+            samples: 0,
+            kept: 0,
+            filtered: Filter::ALL
+                .iter()
+                .map(|filter| (filter.name(), 0))
+                .collect(),
+            drawn_games: 0,
+            openings: BTreeMap::new(),
+            best_d_sum: 0.0,
+            piece_counts: Histogram::linear(0.0, 1.0, 33),
+            phase_scores: Histogram::linear(0.0, 1.0, phase::MAX_SCORE as usize + 1),
+            game_lengths: Histogram::linear(0.0, 20.0, 25),
+            best_q: Histogram::linear(-1.0, 0.1, 20),
+            rule50: Histogram::linear(0.0, 10.0, 11),
+            visits: Histogram::log2(24),
+            policy_entropy: None,
+            wdl: WdlTable::default(),
+            occupancy: Occupancy::default(),
+        }
+    }
+}
+
+impl Stats {
+    fn add_game(&mut self, game: &Game, progress: &Progress) {
+        self.games += 1;
+        self.game_lengths.add(game.samples.len() as f64);
+        // The result is the same for all positions of the game.
+        if game.samples[0].result_d > 0.5 {
+            self.drawn_games += 1;
+        }
+        let opening = match game.opening {
+            Some(opening) => format!("{} {}", opening.code, opening.name),
+            None => "unknown".to_string(),
+        };
+        *self.openings.entry(opening).or_default() += 1;
+
+        let filters = FilterOptions::default();
+        for sample in &game.samples {
+            self.samples += 1;
+            match filter_position(sample, &filters) {
+                Some(filter) => {
+                    *self.filtered.get_mut(filter.name()).unwrap() += 1;
+                    progress.sample_filtered(filter);
+                }
+                None => {
+                    self.kept += 1;
+                    progress.sample_kept();
+                }
+            }
+
+            let num_pieces: u32 = sample
+                .bitboards
+                .iter()
+                .map(|plane| plane.count_ones())
+                .sum();
+            self.piece_counts.add(num_pieces as f64);
+            self.phase_scores.add(phase::score(sample) as f64);
+            self.best_q.add(sample.best_q as f64);
+            self.best_d_sum += sample.best_d as f64;
+            self.rule50.add(sample.rule50_count as f64);
+            self.visits.add(sample.visits as f64);
+            self.wdl.add(num_pieces, sample);
+            self.occupancy.add(sample);
+            if let Some(entropy) = sample.policy_entropy {
+                self.policy_entropy
+                    .get_or_insert_with(|| Histogram::linear(0.0, 0.25, 24))
+                    .add(entropy as f64);
+            }
+        }
+    }
+
+    fn merge(mut self, other: Stats) -> Stats {
+        self.archives += other.archives;
+        self.games += other.games;
+        self.samples += other.samples;
+        self.kept += other.kept;
+        for (name, count) in other.filtered {
+            *self.filtered.entry(name).or_default() += count;
+        }
+        self.drawn_games += other.drawn_games;
+        for (opening, count) in other.openings {
+            *self.openings.entry(opening).or_default() += count;
+        }
+        self.best_d_sum += other.best_d_sum;
+        self.piece_counts.merge(&other.piece_counts);
+        self.phase_scores.merge(&other.phase_scores);
+        self.game_lengths.merge(&other.game_lengths);
+        self.best_q.merge(&other.best_q);
+        self.rule50.merge(&other.rule50);
+        self.visits.merge(&other.visits);
+        self.wdl.merge(&other.wdl);
+        self.occupancy.merge(&other.occupancy);
+        match (&mut self.policy_entropy, other.policy_entropy) {
+            (Some(histogram), Some(other)) => histogram.merge(&other),
+            (None, other) => self.policy_entropy = other,
+            (Some(_), None) => {}
+        }
+        self
+    }
+
+    fn mean_best_d(&self) -> f64 {
+        self.best_d_sum / self.samples.max(1) as f64
+    }
+
+    fn draw_rate(&self) -> f64 {
+        self.drawn_games as f64 / self.games.max(1) as f64
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |count: u64| 100.0 * count as f64 / self.samples.max(1) as f64;
+
+        writeln!(f, "archives: {}", self.archives)?;
+        writeln!(f, "games:    {}", self.games)?;
+        writeln!(f, "samples:  {}", self.samples)?;
+        writeln!(f, "kept:     {} ({:.2}%)", self.kept, percent(self.kept))?;
+        for (name, &count) in &self.filtered {
+            writeln!(f, "filtered by {name}: {count} ({:.2}%)", percent(count))?;
+        }
+        writeln!(
+            f,
+            "draw rate (game results): {:.2}%",
+            100.0 * self.draw_rate()
+        )?;
+        writeln!(f, "mean best_d: {:.4}", self.mean_best_d())?;
+        writeln!(f, "\npieces on the board:\n{}", self.piece_counts)?;
+        writeln!(f, "game phase scores:\n{}", self.phase_scores)?;
+        writeln!(f, "game lengths (plies):\n{}", self.game_lengths)?;
+        writeln!(f, "best_q:\n{}", self.best_q)?;
+        writeln!(f, "rule50 counter:\n{}", self.rule50)?;
+        writeln!(f, "visits:\n{}", self.visits)?;
+        if let Some(policy_entropy) = &self.policy_entropy {
+            writeln!(f, "policy entropy (nats):\n{policy_entropy}")?;
+        }
+        writeln!(
+            f,
+            "win/draw/loss of the game results and of the best move by pieces on the board:\n{}",
+            self.wdl
+        )?;
+
+        let mut openings: Vec<_> = self.openings.iter().collect();
+        openings.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        write!(f, "most common openings:")?;
+        for (opening, &count) in openings.iter().take(TOP_OPENINGS) {
+            let share = 100.0 * count as f64 / self.games.max(1) as f64;
+            write!(f, "\n  {count:>12} {share:>6.2}% {opening}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(args: StatsArgs) -> io::Result<()> {
+    let mut stats = Stats {
+        archives: args.tar_path.len() as u64,
+        ..Stats::default()
+    };
+    let decode = DecodeOptions {
+        policy_entropy: args.policy_entropy,
+        ..DecodeOptions::default()
+    };
+    archive::scan(&args.tar_path, |batch, progress| {
+        process_batch(&mut stats, batch, decode, progress)
+    })?;
+
+    println!("{stats}");
+
+    if let Some(path) = args.json {
+        let mut json = serde_json::to_value(&stats).map_err(io::Error::other)?;
+        json["mean_best_d"] = stats.mean_best_d().into();
+        json["draw_rate"] = stats.draw_rate().into();
+        json["wdl"] = stats.wdl.to_json();
+        serde_json::to_writer_pretty(create_output(&path)?, &json).map_err(io::Error::other)?;
+    }
+    if let Some(path) = args.occupancy_csv {
+        stats
+            .occupancy
+            .write_csv(create_output(&path)?, stats.samples)?;
+    }
+    if let Some(path) = args.occupancy_json {
+        let json = stats.occupancy.to_json(stats.samples);
+        let mut out = create_output(&path)?;
+        serde_json::to_writer_pretty(&mut out, &json).map_err(io::Error::other)?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+fn process_batch(
+    stats: &mut Stats,
+    games: &[Vec<u8>],
+    decode: DecodeOptions,
+    progress: &Progress,
+) -> io::Result<()> {
+    let batch = games
+        .par_iter()
+        .map(|data| {
+            let game = Game::read_from(data.as_slice(), decode)?;
+            progress.game_decoded(game.samples.len());
+
+            let mut stats = Stats::default();
+            stats.add_game(&game, progress);
+            Ok::<_, io::Error>(stats)
+        })
+        .try_reduce(Stats::default, |a, b| Ok(a.merge(b)))?;
+
+    *stats = std::mem::take(stats).merge(batch);
+    Ok(())
+}
@@ -0,0 +1,284 @@
+// Access to the input archives, which are either local paths or objects in
+// object storage given as `s3://bucket/key` and `gs://bucket/key` URIs, or
+// files on a web server given as http:// and https:// URLs. The objects and
+// the URLs need the `object-store` feature.
+//
+// Objects are streamed with range requests, several of which are in flight at
+// once to hide the latency of the storage. The credentials come from the
+// standard chains of the providers: the AWS_* environment variables, web
+// identity tokens and the instance metadata for S3, and the GOOGLE_*
+// environment variables, the application default credentials and the metadata
+// server for GCS. Web servers have to support range requests, listing a
+// directory on them needs WebDAV.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+
+/// Whether the path is an object storage URI or a URL rather than a local
+/// path.
+///
+/// ```
+/// use preprocessing::storage::is_remote;
+///
+/// assert!(is_remote("s3://training-data/run1/training.tar"));
+/// assert!(is_remote("gs://training-data/run1/training.tar"));
+/// assert!(is_remote("https://storage.lczero.org/files/training.tar"));
+/// assert!(!is_remote("data/training.tar"));
+/// ```
+pub fn is_remote(path: &str) -> bool {
+    ["s3://", "gs://", "http://", "https://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+/// Opens the archive for reading from the start.
+pub fn open(path: &str) -> io::Result<Box<dyn Read + Send>> {
+    if is_remote(path) {
+        remote::open(path)
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Size of the archive in bytes.
+pub fn size(path: &str) -> io::Result<u64> {
+    if is_remote(path) {
+        remote::size(path)
+    } else {
+        Ok(fs::metadata(path)?.len())
+    }
+}
+
+/// Paths and sizes of the tar archives in the directory, or under the prefix
+/// of the bucket, sorted by path. Subdirectories are not searched.
+pub fn list_archives(dir: &str) -> io::Result<Vec<(String, u64)>> {
+    list_files(dir, ".tar")
+}
+
+/// Like [`list_archives`], for the files whose names end with `suffix`.
+pub fn list_files(dir: &str, suffix: &str) -> io::Result<Vec<(String, u64)>> {
+    let mut files = if is_remote(dir) {
+        remote::list(dir)?
+    } else {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                files.push((entry.path().display().to_string(), metadata.len()));
+            }
+        }
+        files
+    };
+    files.retain(|(path, _)| path.ends_with(suffix));
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(feature = "object-store")]
+mod remote {
+    use std::collections::VecDeque;
+    use std::io::{self, Read};
+    use std::sync::{Arc, OnceLock};
+
+    use bytes::Bytes;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::http::HttpBuilder;
+    use object_store::path::Path;
+    use object_store::ObjectStore;
+    use tokio::runtime::{Builder, Runtime};
+    use tokio::task::JoinHandle;
+    use tracing::debug;
+
+    // Size of a range request.
+    const CHUNK_SIZE: u64 = 8 << 20;
+
+    // Number of range requests in flight per object.
+    const MAX_REQUESTS: usize = 8;
+
+    // The requests of all objects run on one runtime, the readers block on
+    // them from their own threads.
+    fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        RUNTIME.get_or_init(|| {
+            Builder::new_multi_thread()
+                .thread_name("object-store")
+                .enable_all()
+                .build()
+                .expect("failed to start the object storage runtime")
+        })
+    }
+
+    fn invalid(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    }
+
+    // The store of the bucket, the URI of the bucket and the key in it, which
+    // is empty for the whole bucket. The bucket of a URL is its host.
+    fn locate(uri: &str) -> io::Result<(Arc<dyn ObjectStore>, String, Path)> {
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| invalid(format!("invalid object URI: {uri}")))?;
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(invalid(format!("{uri} does not name a bucket")));
+        }
+        let bucket_uri = format!("{scheme}://{bucket}");
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_url(&bucket_uri)
+                    .build()
+                    .map_err(io::Error::other)?,
+            ),
+            "gs" => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(&bucket_uri)
+                    .build()
+                    .map_err(io::Error::other)?,
+            ),
+            "http" | "https" => Arc::new(
+                HttpBuilder::new()
+                    .with_url(&bucket_uri)
+                    .build()
+                    .map_err(io::Error::other)?,
+            ),
+            _ => return Err(invalid(format!("unsupported object storage: {scheme}"))),
+        };
+        let key = Path::parse(key.trim_end_matches('/'))
+            .map_err(|err| invalid(format!("{uri}: {err}")))?;
+        Ok((store, bucket_uri, key))
+    }
+
+    fn locate_object(uri: &str) -> io::Result<(Arc<dyn ObjectStore>, Path)> {
+        let (store, _, key) = locate(uri)?;
+        if key.as_ref().is_empty() {
+            return Err(invalid(format!(
+                "{uri} does not name an object in a bucket"
+            )));
+        }
+        Ok((store, key))
+    }
+
+    pub fn open(uri: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(ObjectReader::open(uri)?))
+    }
+
+    pub fn size(uri: &str) -> io::Result<u64> {
+        let (store, key) = locate_object(uri)?;
+        head(store.as_ref(), &key)
+    }
+
+    pub fn list(uri: &str) -> io::Result<Vec<(String, u64)>> {
+        let (store, bucket_uri, prefix) = locate(uri)?;
+        let prefix = Some(&prefix).filter(|prefix| !prefix.as_ref().is_empty());
+        let listing = runtime()
+            .block_on(store.list_with_delimiter(prefix))
+            .map_err(io::Error::other)?;
+        Ok(listing
+            .objects
+            .into_iter()
+            .map(|meta| (format!("{bucket_uri}/{}", meta.location), meta.size as u64))
+            .collect())
+    }
+
+    fn head(store: &dyn ObjectStore, key: &Path) -> io::Result<u64> {
+        let meta = runtime()
+            .block_on(store.head(key))
+            .map_err(io::Error::other)?;
+        Ok(meta.size as u64)
+    }
+
+    // Reads an object sequentially, requesting the chunks ahead of the reader
+    // in parallel.
+    struct ObjectReader {
+        store: Arc<dyn ObjectStore>,
+        key: Path,
+        size: u64,
+        // Start of the next chunk to request.
+        next: u64,
+        // The requested chunks, in order.
+        requests: VecDeque<JoinHandle<object_store::Result<Bytes>>>,
+        // The rest of the chunk being read.
+        chunk: Bytes,
+    }
+
+    impl ObjectReader {
+        fn open(uri: &str) -> io::Result<Self> {
+            let (store, key) = locate_object(uri)?;
+            let size = head(store.as_ref(), &key)?;
+            debug!(uri, size, "opened object");
+            Ok(ObjectReader {
+                store,
+                key,
+                size,
+                next: 0,
+                requests: VecDeque::new(),
+                chunk: Bytes::new(),
+            })
+        }
+
+        fn request_chunks(&mut self) {
+            while self.requests.len() < MAX_REQUESTS && self.next < self.size {
+                let end = (self.next + CHUNK_SIZE).min(self.size);
+                let range = self.next as usize..end as usize;
+                let (store, key) = (self.store.clone(), self.key.clone());
+                self.requests
+                    .push_back(runtime().spawn(async move { store.get_range(&key, range).await }));
+                self.next = end;
+            }
+        }
+    }
+
+    impl Read for ObjectReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.chunk.is_empty() {
+                self.request_chunks();
+                let Some(request) = self.requests.pop_front() else {
+                    return Ok(0);
+                };
+                self.chunk = runtime()
+                    .block_on(request)
+                    .map_err(io::Error::other)?
+                    .map_err(io::Error::other)?;
+                self.request_chunks();
+            }
+            let len = buf.len().min(self.chunk.len());
+            buf[..len].copy_from_slice(&self.chunk.split_to(len));
+            Ok(len)
+        }
+    }
+
+    impl Drop for ObjectReader {
+        fn drop(&mut self) {
+            for request in &self.requests {
+                request.abort();
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "object-store"))]
+mod remote {
+    use std::io::{self, Read};
+
+    fn unsupported(uri: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{uri}: reading from object storage needs the object-store feature"),
+        )
+    }
+
+    pub fn open(uri: &str) -> io::Result<Box<dyn Read + Send>> {
+        Err(unsupported(uri))
+    }
+
+    pub fn size(uri: &str) -> io::Result<u64> {
+        Err(unsupported(uri))
+    }
+
+    pub fn list(uri: &str) -> io::Result<Vec<(String, u64)>> {
+        Err(unsupported(uri))
+    }
+}
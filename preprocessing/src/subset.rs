@@ -0,0 +1,66 @@
+// Uniform random subsets of exactly --samples samples of preprocessed outputs
+// (`subset`), e.g. fixed-size validation sets, taken in a single pass with
+// reservoir sampling (see preprocessing::shuffle::Reservoir). Only the subset
+// is held in memory, inputs with fewer samples are written whole. The subset
+// is written in random order and is reproducible with --seed.
+
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::output::{self, ShardWriter};
+use preprocessing::rng::Rng;
+use preprocessing::shuffle::Reservoir;
+use tracing::warn;
+
+use crate::merge::check_inputs;
+
+#[derive(Args)]
+pub struct SubsetArgs {
+    /// Preprocessed outputs to sample from (directories with shards or single
+    /// shards)
+    #[arg(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write the subset to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Number of samples in the subset
+    #[arg(long)]
+    samples: usize,
+
+    /// Maximum number of samples in a single output shard
+    #[arg(long, default_value_t = 1_000_000)]
+    shard_size: u64,
+}
+
+pub fn run(args: SubsetArgs, seed: u64) -> io::Result<()> {
+    check_inputs(&args.inputs, &args.output_dir)?;
+
+    let mut reservoir = Reservoir::new(args.samples, Rng::new(seed));
+    for input in &args.inputs {
+        output::for_each_line(input, |line| {
+            reservoir.push(line.to_string());
+            Ok(())
+        })?;
+    }
+    let seen = reservoir.seen();
+    if seen < args.samples as u64 {
+        warn!(seen, "the inputs have fewer samples than the subset");
+    }
+
+    let mut writer = ShardWriter::create(&args.output_dir, args.shard_size)?;
+    let subset = reservoir.into_shuffled();
+    for line in &subset {
+        writer.write_sample(line)?;
+    }
+    let last_shard = writer.flush()?.index;
+
+    println!(
+        "wrote {} of {seen} samples into {} shards",
+        subset.len(),
+        last_shard + 1
+    );
+    Ok(())
+}
@@ -0,0 +1,156 @@
+// Synthetic training data in the format of lc0: games of random legal moves
+// encoded as version 6 records, compressed into .gz game entries and packed
+// into tar archives, and random corruptions of all three. They are the seeds
+// of the fuzzing corpus (see the fuzz directory and the fuzz-corpus command),
+// which lets the fuzzer start from inputs that get past the format checks,
+// and the fixtures for tests and demos (the make-fixtures command).
+//
+// The targets are made up: the scores are random and the policy is uniform
+// over the legal moves, the best move is the one that was played.
+
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use shakmaty::{Chess, Outcome, Position};
+
+use crate::encoder::GameRecorder;
+use crate::moves::idx_from_move;
+use crate::record::Record;
+use crate::rng::Rng;
+
+/// Records of a game of random legal moves from the initial position, with
+/// at most `max_plies` positions.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::synthetic::{gzip, random_game};
+/// use preprocessing::{DecodeOptions, Game};
+/// use shakmaty::Board;
+///
+/// let records = random_game(&mut Rng::new(42), 60);
+/// let game = Game::read_from(gzip(&records).as_slice(), DecodeOptions::default())?;
+/// assert_eq!(game.samples.len(), records.len());
+/// assert_eq!(game.samples[0].to_board(), Board::default());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn random_game(rng: &mut Rng, max_plies: usize) -> Vec<Record> {
+    random_game_from(rng, Chess::default(), max_plies)
+}
+
+/// Records of a game of random legal moves from the position, with at most
+/// `max_plies` positions. There are none if the game is already over.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::synthetic::{gzip, random_game_from};
+/// use preprocessing::{DecodeOptions, Game};
+/// use shakmaty::fen::Fen;
+/// use shakmaty::{CastlingMode, Chess, Position};
+///
+/// let fen: Fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".parse()?;
+/// let pos: Chess = fen.into_position(CastlingMode::Standard)?;
+/// let records = random_game_from(&mut Rng::new(42), pos.clone(), 10);
+/// let game = Game::read_from(gzip(&records).as_slice(), DecodeOptions::default())?;
+/// assert_eq!(game.samples[0].to_board(), *pos.board());
+///
+/// let fen: Fen = "7k/5QQ1/8/8/8/8/8/K7 b - - 0 1".parse()?;
+/// let mate: Chess = fen.into_position(CastlingMode::Standard)?;
+/// assert!(random_game_from(&mut Rng::new(42), mate, 10).is_empty());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn random_game_from(rng: &mut Rng, start: Chess, max_plies: usize) -> Vec<Record> {
+    let mut recorder = GameRecorder::new(start);
+    let mut plies = 0;
+    while plies < max_plies && !recorder.position().is_game_over() {
+        let pos = recorder.position();
+        let turn = pos.turn();
+        let moves = pos.legal_moves();
+        let played = moves[rng.below(moves.len() as u64) as usize].clone();
+        let q = (2.0 * rng.next_f64() - 1.0) as f32;
+        let d = (rng.next_f64() * (1.0 - q.abs() as f64)) as f32;
+        let visits = 1 + rng.below(1000) as u32;
+
+        let record = recorder.play(&played, q, d);
+        for m in &moves {
+            if let Some(idx) = idx_from_move(m, turn) {
+                record.set_probability(idx as usize, 1.0 / moves.len() as f32);
+            }
+        }
+        record.set_visits(visits);
+        plies += 1;
+    }
+
+    // Unfinished games count as draws.
+    let winner = match recorder.position().outcome() {
+        Some(Outcome::Decisive { winner }) => Some(winner),
+        _ => None,
+    };
+    recorder.finish(winner)
+}
+
+/// The records as a .gz game entry of the training data.
+pub fn gzip(records: &[Record]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for record in records {
+        encoder
+            .write_all(record.as_bytes())
+            .expect("writing to a Vec does not fail");
+    }
+    encoder.finish().expect("writing to a Vec does not fail")
+}
+
+/// A tar archive with the games as `training.<index>.gz` entries.
+///
+/// ```
+/// use preprocessing::reader::read_games;
+/// use preprocessing::rng::Rng;
+/// use preprocessing::synthetic::{gzip, random_game, tar};
+///
+/// let mut rng = Rng::new(42);
+/// let games: Vec<Vec<u8>> = (0..3).map(|_| gzip(&random_game(&mut rng, 20))).collect();
+/// let archive = tar(&games)?;
+/// let mut entries = 0;
+/// read_games(archive.as_slice(), 0, |_| {
+///     entries += 1;
+///     true
+/// })?;
+/// assert_eq!(entries, 3);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn tar(games: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (idx, game) in games.iter().enumerate() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(game.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, format!("training.{idx}.gz"), game.as_slice())?;
+    }
+    builder.into_inner()
+}
+
+/// Corrupts the data like storage and transfer errors would: flips bits,
+/// overwrites a run of bytes, truncates or repeats a part of it.
+pub fn mutate(rng: &mut Rng, data: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    let len = data.len() as u64;
+    let start = rng.below(len) as usize;
+    let end = start + 1 + rng.below((len - start as u64).min(64)) as usize;
+    match rng.below(4) {
+        0 => {
+            for _ in 0..1 + rng.below(8) {
+                let idx = rng.below(len) as usize;
+                data[idx] ^= 1 << rng.below(8);
+            }
+        }
+        1 => data[start..end].fill_with(|| rng.next_u64() as u8),
+        2 => data.truncate(start),
+        _ => {
+            let repeated = data[start..end].to_vec();
+            data.splice(start..start, repeated);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// The mine-tactics command: candidate tactical puzzles for curation, as EPD
+// lines with the solution or in the CSV format of the Lichess puzzle database
+// for importing them into puzzle trainers.
+//
+// A candidate is a position right after the opponent's move made the value of
+// the side to move jump by at least --min-swing, e.g. after a blunder, whose
+// best move is a capture or a sacrifice (a piece left where the opponent can
+// take it without losing as much). The records have no value of the moves
+// besides the best and the played one, so the gap between the search policy
+// of the best move and the second best one stands in for the difference of
+// their values: the search spending its visits on a single move means that
+// the alternatives are clearly worse.
+//
+// The solution is the best move, followed by the moves of the game as long as
+// both sides played the best moves of the search, ending with a move of the
+// side to move. Every position is written once.
+//
+// The Lichess puzzles start before the move of the opponent, which is the
+// first move of the solution, and the side to move of the samples is always
+// white, so the solver of these puzzles plays black. Their rating and
+// popularity are placeholders, the themes are only the kind of the first move
+// and the game URL is the source of the game (the archive, the entry and the
+// ply of the puzzle).
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use preprocessing::sample::planes;
+use preprocessing::{idx_from_move, move_from_idx, DecodeOptions, Game, TrainingSample};
+use rayon::prelude::*;
+use shakmaty::fen::{Epd, Fen};
+use shakmaty::san::SanPlus;
+use shakmaty::{CastlingMode, Chess, EnPassantMode, Move, Position, Role};
+use tracing::info;
+
+use crate::archive;
+
+// Columns of the Lichess puzzle database.
+const LICHESS_HEADER: &str =
+    "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PuzzleFormat {
+    /// A position per line in EPD with the first move of the solution as bm,
+    /// the solution in UCI notation as pv and the source as id
+    Epd,
+    /// CSV with the columns of the Lichess puzzle database, starting before
+    /// the move of the opponent
+    Lichess,
+}
+
+#[derive(Args)]
+pub struct MineTacticsArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Path of the file to write the puzzles to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = PuzzleFormat::Epd)]
+    format: PuzzleFormat,
+
+    /// Minimum increase of the expected score of the side to move (from -1 to
+    /// 1) by the last move of the opponent
+    #[arg(long, default_value_t = 0.5)]
+    min_swing: f32,
+
+    /// Minimum difference between the search policy of the best move and the
+    /// second best move
+    #[arg(long, default_value_t = 0.5)]
+    min_policy_gap: f32,
+
+    /// Maximum number of plies of the solutions
+    #[arg(long, default_value_t = 7)]
+    max_solution_plies: usize,
+}
+
+struct Puzzle {
+    hash: u64,
+    epd: String,
+    solution: Vec<String>,
+    best_san: String,
+    capture: bool,
+    swing: f32,
+    policy_gap: f32,
+    // The position before the move of the opponent, from its perspective, and
+    // the move in UCI notation. None if the move is not found.
+    lead_in: Option<(String, String)>,
+}
+
+// Material of the piece in pawns, the king cannot be given up.
+fn value(role: Role) -> u32 {
+    match role {
+        Role::Pawn => 1,
+        Role::Knight | Role::Bishop => 3,
+        Role::Rook => 5,
+        Role::Queen => 9,
+        Role::King => 0,
+    }
+}
+
+// Whether the move captures, or leaves the piece where the opponent can take
+// it: on an undefended square it attacks, or attacked by a cheaper piece.
+fn captures_or_sacrifices(pos: &Chess, m: &Move) -> bool {
+    if m.is_capture() {
+        return true;
+    }
+    if m.role() == Role::King {
+        return false;
+    }
+    let mut child = pos.clone();
+    child.play_unchecked(m);
+    let board = child.board();
+    let them = child.turn();
+    let attackers = board.attacks_to(m.to(), them, board.occupied());
+    let Some(cheapest) = attackers
+        .into_iter()
+        .filter_map(|square| board.role_at(square))
+        .map(value)
+        .min()
+    else {
+        return false;
+    };
+    let defended = board.attacks_to(m.to(), !them, board.occupied()).any();
+    !defended || cheapest < value(m.role())
+}
+
+// The move in UCI notation on the board seen from the other side: the ranks
+// are mirrored.
+fn flip_uci(uci: &str) -> String {
+    uci.chars()
+        .map(|c| match c {
+            '1'..='8' => char::from(b'1' + b'8' - c as u8),
+            _ => c,
+        })
+        .collect()
+}
+
+// The FEN of the previous sample and the move leading to the sample in UCI
+// notation.
+fn lead_in(
+    game: &Game,
+    previous: &TrainingSample,
+    sample: &TrainingSample,
+) -> Option<(String, String)> {
+    let before = previous.to_position(&game.castling).ok()?;
+    let m = before.legal_moves().into_iter().find(|m| {
+        let mut child = before.clone();
+        child.play_unchecked(m);
+        planes(child.board(), child.turn()) == sample.bitboards
+    })?;
+    let fen = Fen::from_position(before, EnPassantMode::Legal).to_string();
+    Some((fen, m.to_uci(CastlingMode::Standard).to_string()))
+}
+
+// Difference between the policy of the best move and the second best one,
+// None if the policy is not decoded.
+fn policy_gap(sample: &TrainingSample) -> Option<f32> {
+    let policy = sample.policy.as_ref()?;
+    let best = *policy.get(sample.best_idx as usize)?;
+    let second = policy
+        .iter()
+        .enumerate()
+        .filter(|&(idx, _)| idx != sample.best_idx as usize)
+        .map(|(_, &p)| p)
+        .fold(0.0, f32::max);
+    Some(best - second)
+}
+
+// The puzzle at the sample with the index `ply` of the game, if it is one.
+fn puzzle(game: &Game, ply: usize, args: &MineTacticsArgs) -> Option<Puzzle> {
+    let previous = game.samples.get(ply.checked_sub(1)?)?;
+    let sample = &game.samples[ply];
+    // The value of the previous sample is from the perspective of the
+    // opponent.
+    let swing = sample.best_q + previous.best_q;
+    if swing < args.min_swing {
+        return None;
+    }
+    let policy_gap = policy_gap(sample)?;
+    if policy_gap < args.min_policy_gap {
+        return None;
+    }
+    let start = sample.to_position(&game.castling).ok()?;
+    let best = move_from_idx(sample.best_idx, &start)?;
+    if !captures_or_sacrifices(&start, &best) {
+        return None;
+    }
+
+    let mut solution = vec![best.to_uci(CastlingMode::Standard).to_string()];
+    let mut pos = start.clone();
+    pos.play_unchecked(&best);
+    // The game continues the solution as long as both sides played the best
+    // moves of the search.
+    if sample.played_idx == sample.best_idx {
+        let rest = &game.samples[ply + 1..];
+        for (current, next) in rest.iter().zip(rest.iter().skip(1)) {
+            if solution.len() >= args.max_solution_plies || current.played_idx != current.best_idx {
+                break;
+            }
+            let Some(m) = pos.legal_moves().into_iter().find(|m| {
+                let mut child = pos.clone();
+                child.play_unchecked(m);
+                planes(child.board(), child.turn()) == next.bitboards
+            }) else {
+                break;
+            };
+            if idx_from_move(&m, pos.turn()) != Some(current.best_idx) {
+                break;
+            }
+            solution.push(m.to_uci(CastlingMode::Standard).to_string());
+            pos.play_unchecked(&m);
+        }
+    }
+    // The puzzle ends with a move of the solver.
+    solution.truncate(solution.len() - (solution.len() + 1) % 2);
+
+    Some(Puzzle {
+        hash: sample.position_hash(),
+        epd: Epd::from_position(start.clone(), EnPassantMode::Legal).to_string(),
+        solution,
+        best_san: SanPlus::from_move(start, &best).to_string(),
+        capture: best.is_capture(),
+        swing,
+        policy_gap,
+        lead_in: lead_in(game, previous, sample),
+    })
+}
+
+// A field of a CSV line, quoted if needed.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Writes the puzzle in the format, false if it cannot be written in it.
+fn write_puzzle<W: Write>(
+    out: &mut W,
+    puzzle: &Puzzle,
+    source: &str,
+    format: PuzzleFormat,
+) -> io::Result<bool> {
+    match format {
+        PuzzleFormat::Epd => writeln!(
+            out,
+            "{} bm {}; pv \"{}\"; id \"{}\"; c0 \"swing {:.3} policy gap {:.3}\";",
+            puzzle.epd,
+            puzzle.best_san,
+            puzzle.solution.join(" "),
+            source,
+            puzzle.swing,
+            puzzle.policy_gap
+        )?,
+        PuzzleFormat::Lichess => {
+            let Some((fen, opponent_move)) = &puzzle.lead_in else {
+                return Ok(false);
+            };
+            let moves: Vec<_> = std::iter::once(opponent_move.clone())
+                .chain(puzzle.solution.iter().map(|m| flip_uci(m)))
+                .collect();
+            let theme = if puzzle.capture {
+                "capture"
+            } else {
+                "sacrifice"
+            };
+            writeln!(
+                out,
+                "{:016x},{},{},1500,500,0,0,{},{},",
+                puzzle.hash,
+                fen,
+                moves.join(" "),
+                theme,
+                csv_field(source)
+            )?;
+        }
+    }
+    Ok(true)
+}
+
+pub fn run(args: MineTacticsArgs) -> io::Result<()> {
+    let decode = DecodeOptions {
+        policy: true,
+        ..DecodeOptions::default()
+    };
+    let mut out = BufWriter::new(File::create(&args.output)?);
+    if args.format == PuzzleFormat::Lichess {
+        writeln!(out, "{LICHESS_HEADER}")?;
+    }
+    let mut seen = HashSet::new();
+    let (mut games, mut positions, mut puzzles) = (0u64, 0u64, 0u64);
+    archive::scan_entries(&args.tar_path, |batch, progress| {
+        let found = batch
+            .par_iter()
+            .map(|(archive, entry)| {
+                let game = Game::read_from(entry.data.as_slice(), decode)?;
+                progress.game_decoded(game.samples.len());
+                let puzzles: Vec<_> = (0..game.samples.len())
+                    .filter_map(|ply| {
+                        let puzzle = puzzle(&game, ply, &args)?;
+                        let source = format!("{}:{}:{ply}", args.tar_path[*archive], entry.index);
+                        Some((puzzle, source))
+                    })
+                    .collect();
+                Ok((game.samples.len(), puzzles))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        for (samples, found) in found {
+            games += 1;
+            positions += samples as u64;
+            for (puzzle, source) in found {
+                if !seen.insert(puzzle.hash) {
+                    continue;
+                }
+                if write_puzzle(&mut out, &puzzle, &source, args.format)? {
+                    puzzles += 1;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    info!(
+        games,
+        positions,
+        puzzles,
+        path = %args.output.display(),
+        "wrote the puzzles"
+    );
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Training metrics in the event file format of TensorBoard.
+//
+// An event file is a sequence of TFRecords, each holding one serialized Event
+// protobuf: the length of the record, the record and a masked CRC-32C of
+// both. The first event declares the version of the format, every following
+// one carries scalar summaries of a step. TensorBoard picks up every file with
+// "tfevents" in its name from the directory it is pointed to:
+//
+//     tensorboard --logdir <log dir>
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::protobuf::Message;
+
+// CRC-32C (Castagnoli), reflected.
+static CRC_TABLE: LazyLock<[u32; 256]> = LazyLock::new(|| {
+    let mut table = [0; 256];
+    for (byte, entry) in table.iter_mut().enumerate() {
+        let mut crc = byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    table
+});
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = !data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+pub struct EventWriter {
+    file: BufWriter<File>,
+}
+
+impl EventWriter {
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!("events.out.tfevents.{}.attix", now.as_secs());
+        let mut writer = EventWriter {
+            file: BufWriter::new(File::create(dir.join(name))?),
+        };
+        // Event fields: wall_time 1, step 2, file_version 3, summary 5.
+        let version = Message::default()
+            .fixed64(1, wall_time().to_bits())
+            .string(3, "brain.Event:2");
+        writer.write_record(&version.into_bytes())?;
+        Ok(writer)
+    }
+
+    // Adds scalars of the step, shown in TensorBoard under their tags.
+    pub fn scalars(&mut self, step: u64, scalars: &[(&str, f64)]) -> io::Result<()> {
+        // Summary.value 1, Summary.Value fields: tag 1, simple_value 2.
+        let summary = scalars
+            .iter()
+            .fold(Message::default(), |summary, (tag, value)| {
+                let value = Message::default()
+                    .string(1, tag)
+                    .fixed32(2, (*value as f32).to_bits());
+                summary.message(1, value)
+            });
+        let event = Message::default()
+            .fixed64(1, wall_time().to_bits())
+            .varint(2, step)
+            .message(5, summary);
+        self.write_record(&event.into_bytes())?;
+        // Keep the file readable by TensorBoard while training.
+        self.file.flush()
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&len)?;
+        self.file.write_all(&masked_crc(&len).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc(data).to_le_bytes())
+    }
+}
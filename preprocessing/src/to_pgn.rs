@@ -0,0 +1,193 @@
+// Games of the training data as PGN (`to-pgn`), e.g. to look through them in
+// a chess GUI or to share them.
+//
+// The samples of a game only store the boards, so the moves are reconstructed
+// by playing every legal move of a sample and comparing the result with the
+// next sample. The game stops at the first sample no move leads to, e.g. after
+// a corrupted record. Games starting from the standard position are written
+// as they are, others start from the FEN of their first sample, which is
+// always seen from the side to move: if black moved first, the colors of the
+// whole game are swapped.
+//
+// Every move is followed by the evaluation of the position it leads to, as an
+// [%eval] command in pawns from the perspective of white and as the best_q
+// and best_d of the sample.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::sample::planes;
+use preprocessing::{DecodeOptions, Game, TrainingSample};
+use shakmaty::fen::Fen;
+use shakmaty::san::SanPlus;
+use shakmaty::{Chess, Color, EnPassantMode, Move, Position};
+use tracing::{info, warn};
+
+use crate::{archive, create_output};
+
+// Maximum length of the lines of the movetext.
+const PGN_WIDTH: usize = 80;
+
+// Centipawns of an advantage with an expected score of about 0.91, like the
+// default --cp-scale of the rescore command.
+const CP_SCALE: f32 = 400.0;
+
+#[derive(Args)]
+pub struct ToPgnArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Path to write the games to ("-" for stdout)
+    #[arg(short, long, default_value = "-")]
+    output: PathBuf,
+
+    /// Stop after writing this many games
+    #[arg(long)]
+    limit: Option<u64>,
+}
+
+// The moves leading from every sample to the next one, starting from `start`,
+// up to the first sample no move leads to.
+fn replay(start: &Chess, samples: &[TrainingSample]) -> Vec<Move> {
+    let mut pos = start.clone();
+    let mut moves = Vec::new();
+    for next in samples.iter().skip(1) {
+        let played = pos.legal_moves().into_iter().find(|m| {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            planes(child.board(), child.turn()) == next.bitboards
+        });
+        let Some(m) = played else {
+            break;
+        };
+        pos.play_unchecked(&m);
+        moves.push(m);
+    }
+    moves
+}
+
+// The expected score as centipawns, the inverse of the mapping of rescore.
+fn q_to_pawns(q: f32) -> f32 {
+    let q = q.clamp(-0.999, 0.999);
+    CP_SCALE * ((1.0 + q) / (1.0 - q)).log10() / 100.0
+}
+
+fn comment(sample: &TrainingSample, turn: Color) -> String {
+    let white_q = match turn {
+        Color::White => sample.best_q,
+        Color::Black => -sample.best_q,
+    };
+    format!(
+        "{{[%eval {:.2}] best_q {:+.3} best_d {:.3}}}",
+        q_to_pawns(white_q),
+        sample.best_q,
+        sample.best_d
+    )
+}
+
+fn write_game<W: Write>(out: &mut W, game: &Game, archive: &str, entry: usize) -> io::Result<()> {
+    let Some(first) = game.samples.first() else {
+        return Ok(());
+    };
+    let standard = Chess::default();
+    let (start, fen) = if first.bitboards == planes(standard.board(), Color::White) {
+        (standard, None)
+    } else {
+        let pos = first.to_position(&game.castling)?;
+        let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal);
+        (pos, Some(fen))
+    };
+    let moves = replay(&start, &game.samples);
+    // The result is stored from the perspective of the side to move, which
+    // is white in the first sample.
+    let result = match first.result_q {
+        q if q > 0.0 => "1-0",
+        q if q < 0.0 => "0-1",
+        _ => "1/2-1/2",
+    };
+
+    writeln!(out, "[Event \"lc0 training data\"]")?;
+    writeln!(out, "[Site \"{archive}\"]")?;
+    writeln!(out, "[Date \"????.??.??\"]")?;
+    writeln!(out, "[Round \"{entry}\"]")?;
+    writeln!(out, "[White \"?\"]")?;
+    writeln!(out, "[Black \"?\"]")?;
+    writeln!(out, "[Result \"{result}\"]")?;
+    if let Some(fen) = &fen {
+        writeln!(out, "[SetUp \"1\"]")?;
+        writeln!(out, "[FEN \"{fen}\"]")?;
+    }
+    writeln!(out, "[PlyCount \"{}\"]", moves.len())?;
+    writeln!(out)?;
+
+    let mut tokens = Vec::new();
+    let mut pos = start;
+    for (ply, m) in moves.iter().enumerate() {
+        let turn = pos.turn();
+        if turn == Color::White || ply == 0 {
+            let dots = if turn == Color::White { "." } else { "..." };
+            tokens.push(format!("{}{dots}", pos.fullmoves()));
+        }
+        tokens.push(SanPlus::from_move_and_play_unchecked(&mut pos, m).to_string());
+        tokens.push(comment(&game.samples[ply + 1], pos.turn()));
+    }
+    if moves.len() + 1 < game.samples.len() {
+        tokens.push(format!(
+            "{{no move leads to sample {} of {}}}",
+            moves.len() + 1,
+            game.samples.len()
+        ));
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in &tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > PGN_WIDTH {
+            writeln!(out, "{line}")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(token);
+    }
+    writeln!(out, "{line}")?;
+    writeln!(out)
+}
+
+pub fn run(args: ToPgnArgs) -> io::Result<()> {
+    let mut out = create_output(&args.output)?;
+    let mut games = 0u64;
+    let mut failed = None;
+    for path in &args.tar_path {
+        let finished = archive::for_each_game(path, 0, |entry| {
+            if args.limit.is_some_and(|limit| games >= limit) {
+                return false;
+            }
+            let game = match Game::read_from(entry.data.as_slice(), DecodeOptions::default()) {
+                Ok(game) => game,
+                Err(err) => {
+                    warn!(archive = %path, entry = entry.index, %err, "skipping the game");
+                    return true;
+                }
+            };
+            if let Err(err) = write_game(&mut out, &game, path, entry.index) {
+                failed = Some(err);
+                return false;
+            }
+            games += 1;
+            true
+        })?;
+        if let Some(err) = failed {
+            return Err(err);
+        }
+        if !finished {
+            break;
+        }
+    }
+    out.flush()?;
+    info!(games, "wrote the games");
+    Ok(())
+}
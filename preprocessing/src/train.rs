@@ -0,0 +1,359 @@
+// Training of a small NNUE network on the preprocessed data (`train`).
+//
+// The network follows the classic Stockfish layout: the feature transformer
+// sums the weights of the active features of each perspective, the two
+// accumulators (side to move first) are concatenated and pass through two
+// hidden layers with clipped ReLU activations to a single output. The output
+// is trained to predict the expected score of the side to move,
+// (best_q + 1) / 2, through a sigmoid.
+//
+// The batches are drawn uniformly at random (with replacement) from the
+// indexed shards, see preprocessing::dataset. An epoch is as many samples as
+// there are in the training set.
+//
+// The network is trained with candle by default. Building with the torch
+// feature adds a libtorch backend (`--backend torch`), which runs on the GPU
+// if CUDA is available. Both save the weights with the same names in the
+// safetensors format.
+//
+// The learning rate follows a schedule with a warmup, see schedule.
+//
+// With --log-dir, the losses, the learning rate and the norm of the gradients
+// are written as TensorBoard events, see tensorboard.
+
+mod candle;
+mod schedule;
+#[cfg(feature = "torch")]
+mod torch;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use preprocessing::dataset::IndexedDataset;
+use preprocessing::nnue::FeatureSet;
+use preprocessing::output::{self, OutputSample};
+use preprocessing::rng::Rng;
+use serde::Deserialize;
+use shakmaty::fen::Fen;
+use shakmaty::Color;
+use tracing::{debug, info};
+
+use self::schedule::{Decay, Schedule};
+use crate::tensorboard::EventWriter;
+
+#[derive(Args)]
+pub struct TrainArgs {
+    /// Preprocessed training data (directory with indexed shards)
+    #[arg(long)]
+    train: PathBuf,
+
+    /// Preprocessed validation data, evaluated after every epoch
+    #[arg(long)]
+    val: Option<PathBuf>,
+
+    /// Directory to write the checkpoints and the final model to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Input features of the network
+    #[arg(long, value_enum, default_value = "halfkp")]
+    features: FeatureSet,
+
+    /// Size of the accumulator of each perspective
+    #[arg(long, default_value_t = 256)]
+    hidden: usize,
+
+    #[arg(long, default_value_t = 1024)]
+    batch_size: usize,
+
+    #[arg(long, default_value_t = 1)]
+    epochs: u64,
+
+    /// Peak learning rate of AdamW
+    #[arg(long, default_value_t = 1e-3)]
+    lr: f64,
+
+    /// Decay of the learning rate after the warmup
+    #[arg(long, value_enum, default_value_t)]
+    decay: Decay,
+
+    /// Learning rate at the end of the cosine decay
+    #[arg(long, default_value_t = 1e-5)]
+    final_lr: f64,
+
+    /// Number of steps the learning rate rises linearly to the peak
+    #[arg(long, default_value_t = 100)]
+    warmup_steps: u64,
+
+    /// Number of steps between two decays of the step schedule
+    #[arg(long, default_value_t = 10_000)]
+    lr_step_size: u64,
+
+    /// Factor of every decay of the step schedule
+    #[arg(long, default_value_t = 0.1)]
+    lr_gamma: f64,
+
+    /// Training config (TOML), its [schedule] table replaces the learning
+    /// rate options
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 0.0)]
+    weight_decay: f64,
+
+    /// Save the weights every this many steps
+    #[arg(long, default_value_t = 10_000)]
+    checkpoint_interval: u64,
+
+    /// Directory to write TensorBoard event files with the training metrics
+    /// to
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+
+    /// Number of steps the logged training metrics are averaged over
+    #[arg(long, default_value_t = 100)]
+    log_interval: u64,
+
+    /// Library the network is trained with, torch requires building with the
+    /// torch feature
+    #[arg(long, value_enum, default_value_t)]
+    backend: Backend,
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum Backend {
+    #[default]
+    Candle,
+    Torch,
+}
+
+// Metrics of an optimizer step.
+struct Step {
+    loss: f32,
+    // L2 norm of the gradients of all weights.
+    grad_norm: f32,
+}
+
+// Network and optimizer of a backend.
+trait Trainer {
+    // Takes an optimizer step on the batch.
+    fn step(&mut self, batch: &Batch, lr: f64) -> io::Result<Step>;
+
+    // Loss of the batch, without updating the weights.
+    fn loss(&self, batch: &Batch) -> io::Result<f32>;
+
+    fn save(&self, path: &Path) -> io::Result<()>;
+}
+
+fn trainer(args: &TrainArgs) -> io::Result<Box<dyn Trainer>> {
+    match args.backend {
+        Backend::Candle => Ok(Box::new(candle::CandleTrainer::new(args)?)),
+        #[cfg(feature = "torch")]
+        Backend::Torch => Ok(Box::new(torch::TorchTrainer::new(args)?)),
+        #[cfg(not(feature = "torch"))]
+        Backend::Torch => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the torch backend requires building with the torch feature",
+        )),
+    }
+}
+
+// Inputs and targets of a mini-batch. Every sample has a fixed number of
+// feature slots per perspective, the unused ones are masked out. The side to
+// move (always white) comes first.
+struct Batch {
+    len: usize,
+    slots: usize,
+    features: [Vec<u32>; 2],
+    masks: [Vec<f32>; 2],
+    targets: Vec<f32>,
+}
+
+impl Batch {
+    fn from_lines(lines: &[String], set: FeatureSet) -> io::Result<Self> {
+        let slots = set.max_active_features();
+        let mut batch = Batch {
+            len: lines.len(),
+            slots,
+            features: [(); 2].map(|_| Vec::with_capacity(lines.len() * slots)),
+            masks: [(); 2].map(|_| Vec::with_capacity(lines.len() * slots)),
+            targets: Vec::with_capacity(lines.len()),
+        };
+        for line in lines {
+            let sample = OutputSample::parse(line)?;
+            let fen: Fen = sample
+                .fen
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let board = fen.into_setup().board;
+            for (side, perspective) in [Color::White, Color::Black].into_iter().enumerate() {
+                let active = set.active_features(&board, perspective);
+                for slot in 0..slots {
+                    batch.features[side].push(active.get(slot).copied().unwrap_or(0));
+                    batch.masks[side].push(if slot < active.len() { 1.0 } else { 0.0 });
+                }
+            }
+            batch.targets.push((sample.best_q + 1.0) / 2.0);
+        }
+        Ok(batch)
+    }
+}
+
+// Description of the run read from a TOML file (`--config`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TrainConfig {
+    schedule: Option<Schedule>,
+}
+
+impl TrainConfig {
+    fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })
+    }
+}
+
+fn schedule(args: &TrainArgs) -> io::Result<Schedule> {
+    let config = match &args.config {
+        Some(path) => TrainConfig::load(path)?,
+        None => TrainConfig::default(),
+    };
+    Ok(config.schedule.unwrap_or(Schedule {
+        decay: args.decay,
+        lr: args.lr,
+        warmup_steps: args.warmup_steps,
+        final_lr: args.final_lr,
+        step_size: args.lr_step_size,
+        gamma: args.lr_gamma,
+    }))
+}
+
+// Mean loss over all samples of the validation set.
+fn validation_loss(trainer: &dyn Trainer, path: &Path, args: &TrainArgs) -> io::Result<f64> {
+    let batch_size = args.batch_size.max(1);
+    let mut total = 0.0;
+    let mut samples = 0;
+    let mut lines = Vec::with_capacity(batch_size);
+    let mut evaluate = |lines: &mut Vec<String>| -> io::Result<()> {
+        let batch = Batch::from_lines(lines, args.features)?;
+        let loss = trainer.loss(&batch)?;
+        total += loss as f64 * batch.len as f64;
+        samples += batch.len;
+        lines.clear();
+        Ok(())
+    };
+    output::for_each_line(path, |line| {
+        lines.push(line.to_string());
+        if lines.len() == batch_size {
+            evaluate(&mut lines)?;
+        }
+        Ok(())
+    })?;
+    if !lines.is_empty() {
+        evaluate(&mut lines)?;
+    }
+    Ok(total / samples.max(1) as f64)
+}
+
+// Saves the weights and the position in the learning rate schedule next to
+// them.
+fn save(
+    trainer: &dyn Trainer,
+    path: &Path,
+    schedule: &Schedule,
+    step: u64,
+    total_steps: u64,
+) -> io::Result<()> {
+    trainer.save(path)?;
+    schedule.save(&path.with_extension("schedule.json"), step, total_steps)?;
+    info!(path = %path.display(), "saved weights");
+    Ok(())
+}
+
+pub fn run(args: TrainArgs, seed: u64) -> io::Result<()> {
+    let mut train = IndexedDataset::open(&args.train)?;
+    if train.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no training samples in {}", args.train.display()),
+        ));
+    }
+    fs::create_dir_all(&args.output_dir)?;
+
+    let mut trainer = trainer(&args)?;
+
+    let batch_size = args.batch_size.max(1);
+    let steps_per_epoch = train.len().div_ceil(batch_size as u64);
+    let total_steps = steps_per_epoch * args.epochs;
+    let samples = train.len();
+    let schedule = schedule(&args)?;
+    info!(samples, steps_per_epoch, total_steps, ?schedule, "training");
+
+    let mut events = args
+        .log_dir
+        .as_deref()
+        .map(EventWriter::create)
+        .transpose()?;
+    let log_interval = args.log_interval.max(1);
+    let (mut interval_loss, mut interval_norm) = (0.0, 0.0);
+
+    let mut rng = Rng::new(seed);
+    let mut epoch_loss = 0.0;
+    for step in 0..total_steps {
+        let lines = (0..batch_size)
+            .map(|_| train.get(rng.below(train.len())))
+            .collect::<io::Result<Vec<_>>>()?;
+        let batch = Batch::from_lines(&lines, args.features)?;
+        let lr = schedule.lr(step, total_steps);
+        let Step { loss, grad_norm } = trainer.step(&batch, lr)?;
+        epoch_loss += loss as f64;
+        interval_loss += loss as f64;
+        interval_norm += grad_norm as f64;
+        debug!(step, loss, grad_norm, lr, "trained batch");
+
+        if (step + 1) % log_interval == 0 {
+            if let Some(events) = &mut events {
+                let scalars = [
+                    ("train/loss", interval_loss / log_interval as f64),
+                    ("train/grad_norm", interval_norm / log_interval as f64),
+                    ("train/lr", lr),
+                ];
+                events.scalars(step + 1, &scalars)?;
+            }
+            (interval_loss, interval_norm) = (0.0, 0.0);
+        }
+
+        if (step + 1) % args.checkpoint_interval.max(1) == 0 {
+            let name = format!("step-{:08}.safetensors", step + 1);
+            let path = args.output_dir.join(name);
+            save(trainer.as_ref(), &path, &schedule, step + 1, total_steps)?;
+        }
+        if (step + 1) % steps_per_epoch == 0 {
+            let epoch = (step + 1) / steps_per_epoch;
+            let train_loss = epoch_loss / steps_per_epoch as f64;
+            epoch_loss = 0.0;
+            let mut scalars = vec![("epoch/train_loss", train_loss)];
+            match &args.val {
+                Some(path) => {
+                    let val_loss = validation_loss(trainer.as_ref(), path, &args)?;
+                    info!(epoch, train_loss, val_loss, "finished epoch");
+                    scalars.push(("epoch/val_loss", val_loss));
+                }
+                None => info!(epoch, train_loss, "finished epoch"),
+            }
+            if let Some(events) = &mut events {
+                events.scalars(step + 1, &scalars)?;
+            }
+        }
+    }
+
+    let path = args.output_dir.join("model.safetensors");
+    save(trainer.as_ref(), &path, &schedule, total_steps, total_steps)
+}
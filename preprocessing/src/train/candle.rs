@@ -0,0 +1,127 @@
+// Training backend using candle, running on the CPU.
+
+use std::io;
+use std::path::Path;
+
+use candle_core::{DType, Device, Result, Tensor};
+use candle_nn::init::Init;
+use candle_nn::{
+    linear, loss, ops, AdamW, Linear, Module, Optimizer, ParamsAdamW, VarBuilder, VarMap,
+};
+use preprocessing::nnue::FeatureSet;
+
+use super::{Batch, Step, TrainArgs, Trainer};
+
+struct Nnue {
+    // (features, hidden), shared by both perspectives.
+    transformer: Tensor,
+    transformer_bias: Tensor,
+    hidden1: Linear,
+    hidden2: Linear,
+    output: Linear,
+}
+
+impl Nnue {
+    fn new(set: FeatureSet, hidden: usize, vb: VarBuilder) -> Result<Self> {
+        // The accumulator starts at roughly unit variance.
+        let stdev = 1.0 / (set.max_active_features() as f64).sqrt();
+        Ok(Nnue {
+            transformer: vb.get_with_hints(
+                (set.dimensions(), hidden),
+                "transformer.weight",
+                Init::Randn { mean: 0.0, stdev },
+            )?,
+            transformer_bias: vb.get_with_hints(hidden, "transformer.bias", Init::Const(0.0))?,
+            hidden1: linear(2 * hidden, 32, vb.pp("hidden1"))?,
+            hidden2: linear(32, 32, vb.pp("hidden2"))?,
+            output: linear(32, 1, vb.pp("output"))?,
+        })
+    }
+
+    fn accumulate(&self, batch: &Batch, side: usize) -> Result<Tensor> {
+        let device = self.transformer.device();
+        let shape = (batch.len, batch.slots, 1);
+        let features = Tensor::from_slice(&batch.features[side], batch.len * batch.slots, device)?;
+        let mask = Tensor::from_slice(&batch.masks[side], shape, device)?;
+        self.transformer
+            .index_select(&features, 0)?
+            .reshape((batch.len, batch.slots, ()))?
+            .broadcast_mul(&mask)?
+            .sum(1)?
+            .broadcast_add(&self.transformer_bias)
+    }
+
+    // Predicted expected score of the side to move.
+    fn forward(&self, batch: &Batch) -> Result<Tensor> {
+        let us = self.accumulate(batch, 0)?;
+        let them = self.accumulate(batch, 1)?;
+        let x = Tensor::cat(&[us, them], 1)?.clamp(0f32, 1f32)?;
+        let x = self.hidden1.forward(&x)?.clamp(0f32, 1f32)?;
+        let x = self.hidden2.forward(&x)?.clamp(0f32, 1f32)?;
+        ops::sigmoid(&self.output.forward(&x)?)
+    }
+
+    fn loss(&self, batch: &Batch) -> Result<Tensor> {
+        let device = self.transformer.device();
+        let targets = Tensor::from_slice(&batch.targets, (batch.len, 1), device)?;
+        loss::mse(&self.forward(batch)?, &targets)
+    }
+}
+
+pub struct CandleTrainer {
+    varmap: VarMap,
+    model: Nnue,
+    optimizer: AdamW,
+}
+
+impl CandleTrainer {
+    pub fn new(args: &TrainArgs) -> io::Result<Self> {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let model = Nnue::new(args.features, args.hidden, vb).map_err(io::Error::other)?;
+        let params = ParamsAdamW {
+            lr: args.lr,
+            weight_decay: args.weight_decay,
+            ..ParamsAdamW::default()
+        };
+        let optimizer = AdamW::new(varmap.all_vars(), params).map_err(io::Error::other)?;
+        Ok(CandleTrainer {
+            varmap,
+            model,
+            optimizer,
+        })
+    }
+}
+
+impl Trainer for CandleTrainer {
+    fn step(&mut self, batch: &Batch, lr: f64) -> io::Result<Step> {
+        self.optimizer.set_learning_rate(lr);
+        let mut step = || -> Result<Step> {
+            let loss = self.model.loss(batch)?;
+            let grads = loss.backward()?;
+            let mut squares = 0f32;
+            for var in self.varmap.all_vars() {
+                if let Some(grad) = grads.get(&var) {
+                    squares += grad.sqr()?.sum_all()?.to_scalar::<f32>()?;
+                }
+            }
+            self.optimizer.step(&grads)?;
+            Ok(Step {
+                loss: loss.to_scalar::<f32>()?,
+                grad_norm: squares.sqrt(),
+            })
+        };
+        step().map_err(io::Error::other)
+    }
+
+    fn loss(&self, batch: &Batch) -> io::Result<f32> {
+        self.model
+            .loss(batch)
+            .and_then(|loss| loss.to_scalar::<f32>())
+            .map_err(io::Error::other)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        self.varmap.save(path).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,108 @@
+// Learning rate schedules of the training loop.
+//
+// Every schedule rises linearly from 0 to the peak learning rate during the
+// warmup steps and then decays:
+//
+// - constant: stays at the peak.
+// - step: multiplied by `gamma` every `step_size` steps.
+// - cosine: follows half a cosine wave to `final_lr` at the last step.
+//
+// The schedule is given by the command line options or the [schedule] table
+// of the training config (`--config`), which replaces them:
+//
+//     [schedule]
+//     decay = "step"
+//     lr = 1e-3
+//     warmup_steps = 500
+//     step_size = 20000
+//     gamma = 0.3
+//
+// It is written next to every checkpoint, see Schedule::save.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decay {
+    Constant,
+    Step,
+    #[default]
+    Cosine,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    pub decay: Decay,
+    // Peak learning rate, reached at the end of the warmup.
+    pub lr: f64,
+    pub warmup_steps: u64,
+    // Learning rate at the last step (cosine).
+    pub final_lr: f64,
+    // Number of steps between two decays (step).
+    pub step_size: u64,
+    // Factor of every decay (step).
+    pub gamma: f64,
+}
+
+// Same as the defaults of the command line options.
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            decay: Decay::Cosine,
+            lr: 1e-3,
+            warmup_steps: 100,
+            final_lr: 1e-5,
+            step_size: 10_000,
+            gamma: 0.1,
+        }
+    }
+}
+
+impl Schedule {
+    // Learning rate of the step (counted from 0) of a run of `total_steps`.
+    pub fn lr(&self, step: u64, total_steps: u64) -> f64 {
+        if step < self.warmup_steps {
+            return self.lr * (step + 1) as f64 / self.warmup_steps as f64;
+        }
+        let decay_step = step - self.warmup_steps;
+        match self.decay {
+            Decay::Constant => self.lr,
+            Decay::Step => {
+                let decays = decay_step / self.step_size.max(1);
+                self.lr * self.gamma.powi(decays.min(i32::MAX as u64) as i32)
+            }
+            Decay::Cosine => {
+                let decay_steps = total_steps.saturating_sub(self.warmup_steps).max(1);
+                let progress = decay_step as f64 / decay_steps as f64;
+                let cosine = (1.0 + (std::f64::consts::PI * progress).cos()) / 2.0;
+                self.final_lr + (self.lr - self.final_lr) * cosine
+            }
+        }
+    }
+
+    // Records the schedule and the position in it of a checkpoint, so that
+    // the run can be reproduced.
+    pub fn save(&self, path: &Path, step: u64, total_steps: u64) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct Record<'a> {
+            step: u64,
+            total_steps: u64,
+            lr: f64,
+            schedule: &'a Schedule,
+        }
+        let record = Record {
+            step,
+            total_steps,
+            lr: self.lr(step.saturating_sub(1), total_steps),
+            schedule: self,
+        };
+        let json = serde_json::to_string_pretty(&record).map_err(io::Error::other)?;
+        fs::write(path, json + "\n")
+    }
+}
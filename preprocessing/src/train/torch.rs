@@ -0,0 +1,115 @@
+// Training backend using libtorch through tch, running on the GPU if CUDA is
+// available. The network is the same as the one of the candle backend.
+
+use std::io;
+use std::path::Path;
+
+use tch::nn::{self, Module, OptimizerConfig};
+use tch::{Device, Kind, Reduction, Tensor};
+
+use super::{Batch, Step, TrainArgs, Trainer};
+
+pub struct TorchTrainer {
+    vs: nn::VarStore,
+    // (features, hidden), shared by both perspectives.
+    transformer: Tensor,
+    transformer_bias: Tensor,
+    hidden1: nn::Linear,
+    hidden2: nn::Linear,
+    output: nn::Linear,
+    optimizer: nn::Optimizer,
+}
+
+impl TorchTrainer {
+    pub fn new(args: &TrainArgs) -> io::Result<Self> {
+        let vs = nn::VarStore::new(Device::cuda_if_available());
+        let root = vs.root();
+        let hidden = args.hidden as i64;
+        // The accumulator starts at roughly unit variance.
+        let stdev = 1.0 / (args.features.max_active_features() as f64).sqrt();
+        let transformer = root.var(
+            "transformer.weight",
+            &[args.features.dimensions() as i64, hidden],
+            nn::Init::Randn { mean: 0.0, stdev },
+        );
+        let transformer_bias = root.var("transformer.bias", &[hidden], nn::Init::Const(0.0));
+        let hidden1 = nn::linear(&root / "hidden1", 2 * hidden, 32, Default::default());
+        let hidden2 = nn::linear(&root / "hidden2", 32, 32, Default::default());
+        let output = nn::linear(&root / "output", 32, 1, Default::default());
+        let optimizer = nn::AdamW {
+            wd: args.weight_decay,
+            ..nn::AdamW::default()
+        }
+        .build(&vs, args.lr)
+        .map_err(io::Error::other)?;
+        Ok(TorchTrainer {
+            vs,
+            transformer,
+            transformer_bias,
+            hidden1,
+            hidden2,
+            output,
+            optimizer,
+        })
+    }
+
+    fn accumulate(&self, batch: &Batch, side: usize) -> Tensor {
+        let device = self.vs.device();
+        let shape = [batch.len as i64, batch.slots as i64, 1];
+        // Indices have to be 64-bit.
+        let features: Vec<i64> = batch.features[side].iter().map(|&f| f as i64).collect();
+        let features = Tensor::from_slice(&features).to_device(device);
+        let mask = Tensor::from_slice(&batch.masks[side]).to_device(device);
+        let weights = self.transformer.index_select(0, &features);
+        let masked = weights.view([shape[0], shape[1], -1]) * mask.view(shape);
+        masked.sum_dim_intlist([1].as_slice(), false, Kind::Float) + &self.transformer_bias
+    }
+
+    // Predicted expected score of the side to move.
+    fn forward(&self, batch: &Batch) -> Tensor {
+        let us = self.accumulate(batch, 0);
+        let them = self.accumulate(batch, 1);
+        let x = Tensor::cat(&[us, them], 1).clamp(0.0, 1.0);
+        let x = self.hidden1.forward(&x).clamp(0.0, 1.0);
+        let x = self.hidden2.forward(&x).clamp(0.0, 1.0);
+        self.output.forward(&x).sigmoid()
+    }
+
+    fn batch_loss(&self, batch: &Batch) -> Tensor {
+        let targets = Tensor::from_slice(&batch.targets)
+            .view([batch.len as i64, 1])
+            .to_device(self.vs.device());
+        self.forward(batch).mse_loss(&targets, Reduction::Mean)
+    }
+}
+
+impl Trainer for TorchTrainer {
+    fn step(&mut self, batch: &Batch, lr: f64) -> io::Result<Step> {
+        self.optimizer.set_lr(lr);
+        let loss = self.batch_loss(batch);
+        self.optimizer.zero_grad();
+        loss.backward();
+        let squares: f64 = tch::no_grad(|| {
+            let variables = self.vs.trainable_variables();
+            let squares = variables
+                .iter()
+                .map(|var| var.grad().square().sum(Kind::Float));
+            squares.map(|square| square.double_value(&[])).sum()
+        });
+        self.optimizer.step();
+        Ok(Step {
+            loss: loss.double_value(&[]) as f32,
+            grad_norm: squares.sqrt() as f32,
+        })
+    }
+
+    fn loss(&self, batch: &Batch) -> io::Result<f32> {
+        let loss = tch::no_grad(|| self.batch_loss(batch));
+        Ok(loss.double_value(&[]) as f32)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        // The format follows the extension, .safetensors like candle.
+        self.vs.save(path).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,353 @@
+// Tuning of the hand-crafted evaluation parameters (`tune`).
+//
+// The parameters of preprocessing::eval are fitted to the expected scores of
+// positions drawn from the preprocessed data: the evaluation is mapped to an
+// expected score with a logistic curve, 1 / (1 + 10^(-eval / --scale)), and
+// the mean squared error to (best_q + 1) / 2 is minimized. The evaluation is
+// linear in the parameters, so the coefficients of every position are
+// computed once and the error of a parameter vector is a pass over them.
+//
+// Two methods are available:
+//
+// - texel: gradient descent on the error with Adam, the usual Texel tuning
+//   with an analytic gradient.
+// - spsa: simultaneous perturbation stochastic approximation, which only
+//   evaluates the error of two randomly perturbed parameter vectors per
+//   iteration. The gains follow Spall's guidelines, the step size is
+//   calibrated so that the first iterations move the parameters by about
+//   --spsa-step centipawns.
+//
+// The tuned values are rounded and written as Rust constants or as a TOML
+// table, which --init reads back to continue tuning.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use preprocessing::dataset::IndexedDataset;
+use preprocessing::eval::{self, Params};
+use preprocessing::output::OutputSample;
+use preprocessing::rng::Rng;
+use shakmaty::fen::Fen;
+use tracing::info;
+
+use crate::signals;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Method {
+    Texel,
+    Spsa,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Rust,
+    Toml,
+}
+
+#[derive(Args)]
+pub struct TuneArgs {
+    /// Preprocessed data (directory with indexed shards) to fit the
+    /// parameters to
+    #[arg(long)]
+    data: PathBuf,
+
+    /// Optimization method
+    #[arg(long, value_enum, default_value = "texel")]
+    method: Method,
+
+    /// Number of positions drawn from the data
+    #[arg(long, default_value_t = 100_000)]
+    samples: u64,
+
+    /// Number of optimization steps
+    #[arg(long, default_value_t = 1000)]
+    iterations: u32,
+
+    /// Initial parameters written by a previous run in the TOML format
+    /// [default: the defaults of the evaluation]
+    #[arg(long)]
+    init: Option<PathBuf>,
+
+    /// Centipawns of an advantage with an expected score of about 0.91
+    #[arg(long, default_value_t = 400.0)]
+    scale: f64,
+
+    /// Step size of Adam in centipawns (texel)
+    #[arg(long, default_value_t = 1.0)]
+    learning_rate: f64,
+
+    /// Perturbation of the parameters in centipawns (spsa)
+    #[arg(long, default_value_t = 4.0)]
+    spsa_c: f64,
+
+    /// Change of the parameters in centipawns in the first iterations (spsa)
+    #[arg(long, default_value_t = 2.0)]
+    spsa_step: f64,
+
+    /// Path to write the tuned parameters to [default: standard output]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Format of the tuned parameters
+    #[arg(long, value_enum, default_value = "rust")]
+    format: Format,
+}
+
+// The coefficients of the parameters in the evaluation of the positions and
+// the expected scores the evaluations are fitted to.
+struct Positions {
+    coefficients: Vec<f32>,
+    targets: Vec<f64>,
+}
+
+impl Positions {
+    fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    fn rows(&self) -> impl Iterator<Item = (&[f32], f64)> {
+        self.coefficients
+            .chunks_exact(Params::NAMES.len())
+            .zip(self.targets.iter().copied())
+    }
+}
+
+fn read_positions(args: &TuneArgs, rng: &mut Rng) -> io::Result<Positions> {
+    let mut dataset = IndexedDataset::open(&args.data)?;
+    if dataset.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no samples in {}", args.data.display()),
+        ));
+    }
+    let mut positions = Positions {
+        coefficients: Vec::new(),
+        targets: Vec::new(),
+    };
+    for _ in 0..args.samples {
+        let line = dataset.get(rng.below(dataset.len()))?;
+        let sample = OutputSample::parse(&line)?;
+        let fen: Fen = sample
+            .fen
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // The positions are from the perspective of the side to move, which
+        // is white.
+        let board = fen.into_setup().board;
+        positions.coefficients.extend(eval::coefficients(&board));
+        positions.targets.push((sample.best_q as f64 + 1.0) / 2.0);
+    }
+    Ok(positions)
+}
+
+fn read_init(path: &Path) -> io::Result<Vec<f64>> {
+    let text = fs::read_to_string(path)?;
+    let values: BTreeMap<String, i32> = toml::from_str(&text).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {err}", path.display()),
+        )
+    })?;
+    let mut params = Params::default().to_vec();
+    for (name, value) in values {
+        let Some(idx) = Params::NAMES.iter().position(|n| *n == name) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: unknown parameter {name}", path.display()),
+            ));
+        };
+        params[idx] = value;
+    }
+    Ok(params.into_iter().map(f64::from).collect())
+}
+
+fn sigmoid(eval: f64, scale: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-eval / scale))
+}
+
+fn evaluate(coefficients: &[f32], params: &[f64]) -> f64 {
+    coefficients
+        .iter()
+        .zip(params)
+        .map(|(&coefficient, value)| coefficient as f64 * value)
+        .sum()
+}
+
+// Mean squared error of the predicted expected scores.
+fn loss(positions: &Positions, params: &[f64], scale: f64) -> f64 {
+    let sum: f64 = positions
+        .rows()
+        .map(|(coefficients, target)| {
+            let error = sigmoid(evaluate(coefficients, params), scale) - target;
+            error * error
+        })
+        .sum();
+    sum / positions.len() as f64
+}
+
+fn gradient(positions: &Positions, params: &[f64], scale: f64) -> Vec<f64> {
+    let mut gradient = vec![0.0; params.len()];
+    for (coefficients, target) in positions.rows() {
+        let p = sigmoid(evaluate(coefficients, params), scale);
+        // d/d eval of (p - target)^2.
+        let d = 2.0 * (p - target) * p * (1.0 - p) * std::f64::consts::LN_10 / scale;
+        for (g, &coefficient) in gradient.iter_mut().zip(coefficients) {
+            *g += d * coefficient as f64;
+        }
+    }
+    let n = positions.len() as f64;
+    gradient.iter_mut().for_each(|g| *g /= n);
+    gradient
+}
+
+fn texel(args: &TuneArgs, positions: &Positions, params: &mut [f64]) {
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const EPSILON: f64 = 1e-12;
+    let mut m = vec![0.0; params.len()];
+    let mut v = vec![0.0; params.len()];
+    for t in 1..=args.iterations {
+        if signals::interrupted() {
+            break;
+        }
+        let gradient = gradient(positions, params, args.scale);
+        let (correction1, correction2) = (1.0 - BETA1.powi(t as i32), 1.0 - BETA2.powi(t as i32));
+        let moments = m.iter_mut().zip(v.iter_mut());
+        for ((value, g), (m, v)) in params.iter_mut().zip(gradient).zip(moments) {
+            *m = BETA1 * *m + (1.0 - BETA1) * g;
+            *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+            let (m_hat, v_hat) = (*m / correction1, *v / correction2);
+            *value -= args.learning_rate * m_hat / (v_hat.sqrt() + EPSILON);
+        }
+        if t % 100 == 0 {
+            info!(
+                iteration = t,
+                loss = loss(positions, params, args.scale),
+                "texel"
+            );
+        }
+    }
+}
+
+// Estimate of the gradient from the parameters perturbed by +-c in random
+// directions.
+fn spsa_gradient(
+    positions: &Positions,
+    params: &[f64],
+    c: f64,
+    scale: f64,
+    rng: &mut Rng,
+) -> Vec<f64> {
+    let delta: Vec<f64> = params
+        .iter()
+        .map(|_| if rng.chance(0.5) { 1.0 } else { -1.0 })
+        .collect();
+    let perturbed = |sign: f64| -> Vec<f64> {
+        params
+            .iter()
+            .zip(&delta)
+            .map(|(value, d)| value + sign * c * d)
+            .collect()
+    };
+    let diff = loss(positions, &perturbed(1.0), scale) - loss(positions, &perturbed(-1.0), scale);
+    delta.iter().map(|d| diff / (2.0 * c * d)).collect()
+}
+
+fn spsa(args: &TuneArgs, positions: &Positions, params: &mut [f64], rng: &mut Rng) {
+    // Spall's exponents and stability constant.
+    const ALPHA: f64 = 0.602;
+    const GAMMA: f64 = 0.101;
+    const CALIBRATION_STEPS: u32 = 10;
+    let stability = args.iterations as f64 / 10.0;
+    // The mean magnitude of the gradient estimates at the start sets the
+    // step size, so that --spsa-step does not depend on the scale of the
+    // loss.
+    let magnitude = (0..CALIBRATION_STEPS)
+        .map(|_| {
+            let gradient = spsa_gradient(positions, params, args.spsa_c, args.scale, rng);
+            gradient.iter().map(|g| g.abs()).sum::<f64>() / gradient.len() as f64
+        })
+        .sum::<f64>()
+        / CALIBRATION_STEPS as f64;
+    let a = args.spsa_step * (stability + 1.0).powf(ALPHA) / magnitude.max(f64::MIN_POSITIVE);
+
+    for k in 0..args.iterations {
+        if signals::interrupted() {
+            break;
+        }
+        let a_k = a / (k as f64 + 1.0 + stability).powf(ALPHA);
+        let c_k = args.spsa_c / (k as f64 + 1.0).powf(GAMMA);
+        let gradient = spsa_gradient(positions, params, c_k, args.scale, rng);
+        for (value, g) in params.iter_mut().zip(gradient) {
+            *value -= a_k * g;
+        }
+        if (k + 1) % 100 == 0 {
+            info!(
+                iteration = k + 1,
+                loss = loss(positions, params, args.scale),
+                "spsa"
+            );
+        }
+    }
+}
+
+fn write_params(args: &TuneArgs, params: &Params, loss: f64) -> io::Result<()> {
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let comment = match args.format {
+        Format::Rust => "//",
+        Format::Toml => "#",
+    };
+    writeln!(
+        out,
+        "{comment} Tuned with {:?} on {} positions, loss {loss:.6}.",
+        args.method, args.samples
+    )?;
+    for (name, value) in Params::NAMES.iter().zip(params.to_vec()) {
+        match args.format {
+            Format::Rust => writeln!(out, "pub const {}: i32 = {value};", name.to_uppercase())?,
+            Format::Toml => writeln!(out, "{name} = {value}")?,
+        }
+    }
+    out.flush()
+}
+
+pub fn run(args: TuneArgs, seed: u64) -> io::Result<()> {
+    signals::install()?;
+    let rng = Rng::new(seed);
+    let positions = read_positions(&args, &mut rng.fork(0))?;
+    let mut params = match &args.init {
+        Some(path) => read_init(path)?,
+        None => Params::default()
+            .to_vec()
+            .into_iter()
+            .map(f64::from)
+            .collect(),
+    };
+    let initial = loss(&positions, &params, args.scale);
+    info!(
+        positions = positions.len(),
+        loss = initial,
+        "initial parameters"
+    );
+
+    match args.method {
+        Method::Texel => texel(&args, &positions, &mut params),
+        Method::Spsa => spsa(&args, &positions, &mut params, &mut rng.fork(1)),
+    }
+
+    let rounded: Vec<i32> = params.iter().map(|value| value.round() as i32).collect();
+    let rounded_params: Vec<f64> = rounded.iter().copied().map(f64::from).collect();
+    let tuned = loss(&positions, &rounded_params, args.scale);
+    info!(
+        loss = tuned,
+        improvement = initial - tuned,
+        "tuned parameters"
+    );
+    write_params(&args, &Params::from_slice(&rounded), tuned)
+}
@@ -0,0 +1,271 @@
+// External UCI engines searching positions for the rescore and selfplay
+// commands, and playing games for the match command.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use clap::Args;
+use preprocessing::moves::idx_from_move;
+use preprocessing::IDX_TO_MOVE;
+use shakmaty::{CastlingMode, Chess, Move, Position};
+
+// Options of the engine shared by the commands.
+#[derive(Args)]
+#[command(group = clap::ArgGroup::new("limit").required(true))]
+pub struct EngineArgs {
+    /// Path of the UCI engine executable
+    #[arg(long)]
+    pub engine: PathBuf,
+
+    /// UCI option set before the search, e.g. --engine-option Hash=64
+    #[arg(long = "engine-option", value_name = "NAME=VALUE", value_parser = parse_option)]
+    pub engine_options: Vec<(String, String)>,
+
+    /// Search every position to this depth
+    #[arg(long, group = "limit")]
+    pub depth: Option<u32>,
+
+    /// Search every position for this many nodes
+    #[arg(long, group = "limit")]
+    pub nodes: Option<u64>,
+
+    /// Centipawns of an advantage with an expected score of about 0.91, used
+    /// when the engine does not report WDL
+    #[arg(long, default_value_t = 400.0)]
+    pub cp_scale: f32,
+}
+
+pub fn parse_option(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {s}"))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+// Score of a line of the search, from the perspective of the side to move.
+#[derive(Debug, Default, Clone)]
+pub struct Variation {
+    pub cp: Option<i32>,
+    // Positive if the side to move mates.
+    pub mate: Option<i32>,
+    // Permille of wins, draws and losses.
+    pub wdl: Option<[u32; 3]>,
+    // First move of the principal variation.
+    pub first_move: Option<String>,
+}
+
+impl Variation {
+    // Expected score in [-1, 1] and the draw probability if the engine
+    // reports WDL.
+    pub fn q_and_d(&self, cp_scale: f32) -> (f32, Option<f32>) {
+        if let Some([w, d, l]) = self.wdl {
+            let total = (w + d + l).max(1) as f32;
+            return ((w as f32 - l as f32) / total, Some(d as f32 / total));
+        }
+        let q = match (self.mate, self.cp) {
+            (Some(mate), _) => mate.signum() as f32,
+            (None, Some(cp)) => 2.0 / (1.0 + 10f32.powf(-(cp as f32) / cp_scale)) - 1.0,
+            (None, None) => 0.0,
+        };
+        (q, None)
+    }
+}
+
+// Result of the search of a position.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    // Lines of the search ordered by MultiPV, the best one first.
+    pub variations: Vec<Variation>,
+    pub best_move: String,
+}
+
+// An engine process speaking UCI over its standard input and output.
+pub struct Engine {
+    process: Child,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    go: String,
+    // The `id name` of the engine, the file name of the executable if it
+    // sends none.
+    pub name: String,
+}
+
+impl Engine {
+    // Starts the engine and sets the options, `extra_options` after the ones
+    // given on the command line.
+    pub fn start(args: &EngineArgs, extra_options: &[(String, String)]) -> io::Result<Self> {
+        let options: Vec<(String, String)> = args
+            .engine_options
+            .iter()
+            .chain(extra_options)
+            .cloned()
+            .collect();
+        let mut engine = Engine::spawn(&args.engine, &options)?;
+        engine.go = match (args.depth, args.nodes) {
+            (Some(depth), _) => format!("go depth {depth}"),
+            (None, Some(nodes)) => format!("go nodes {nodes}"),
+            (None, None) => unreachable!("the limit is required"),
+        };
+        Ok(engine)
+    }
+
+    // Starts the engine at the path and sets the options, for searches with
+    // their own `go` commands.
+    pub fn spawn(path: &Path, options: &[(String, String)]) -> io::Result<Self> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into(),
+        );
+        let mut engine = Engine {
+            stdin: BufWriter::new(process.stdin.take().expect("piped")),
+            stdout: BufReader::new(process.stdout.take().expect("piped")),
+            process,
+            go: String::new(),
+            name,
+        };
+        engine.send("uci")?;
+        loop {
+            let line = engine.read_line()?;
+            if line == "uciok" {
+                break;
+            }
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            }
+        }
+        engine.send("setoption name UCI_ShowWDL value true")?;
+        for (name, value) in options {
+            engine.send(&format!("setoption name {name} value {value}"))?;
+        }
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the engine exited",
+            ));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    fn wait_for(&mut self, response: &str) -> io::Result<()> {
+        while self.read_line()? != response {}
+        Ok(())
+    }
+
+    // Searches the position given as a FEN.
+    pub fn analyse(&mut self, fen: &str) -> io::Result<Analysis> {
+        // Every position is searched from a clean state, so that the result
+        // does not depend on the positions searched before.
+        self.send("ucinewgame")?;
+        let go = self.go.clone();
+        self.search(&format!("position fen {fen}"), &go)
+    }
+
+    // Tells the engine that the next search is from another game.
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok")
+    }
+
+    // Searches the `position` command with the `go` command.
+    pub fn search(&mut self, position: &str, go: &str) -> io::Result<Analysis> {
+        self.send(position)?;
+        self.send(go)?;
+        let mut analysis = Analysis::default();
+        loop {
+            let line = self.read_line()?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => parse_info(tokens, &mut analysis),
+                Some("bestmove") => {
+                    analysis.best_move = tokens.next().unwrap_or_default().to_string();
+                    return Ok(analysis);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.process.wait();
+    }
+}
+
+// Updates the analysis with the score of an info line, the last one of every
+// MultiPV line is the result of the deepest search.
+fn parse_info<'a>(mut tokens: impl Iterator<Item = &'a str>, analysis: &mut Analysis) {
+    let mut multipv = 1;
+    let mut variation = Variation::default();
+    let mut scored = false;
+    while let Some(token) = tokens.next() {
+        match token {
+            "multipv" => multipv = tokens.next().and_then(|v| v.parse().ok()).unwrap_or(1),
+            "score" => {
+                scored = true;
+                match (tokens.next(), tokens.next().map(str::parse)) {
+                    (Some("cp"), Some(Ok(cp))) => variation.cp = Some(cp),
+                    (Some("mate"), Some(Ok(mate))) => variation.mate = Some(mate),
+                    _ => {}
+                }
+            }
+            "wdl" => {
+                let mut wdl = [0; 3];
+                for value in &mut wdl {
+                    *value = tokens.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+                }
+                variation.wdl = Some(wdl);
+            }
+            "pv" => {
+                variation.first_move = tokens.next().map(str::to_string);
+                break;
+            }
+            // The rest of the line is free text.
+            "string" => return,
+            _ => {}
+        }
+    }
+    if !scored || multipv == 0 {
+        return;
+    }
+    if analysis.variations.len() < multipv {
+        analysis.variations.resize(multipv, Variation::default());
+    }
+    analysis.variations[multipv - 1] = variation;
+}
+
+// The legal move of the position given in UCI notation, castling either as the
+// king moving two squares or capturing its rook.
+pub fn find_move(pos: &Chess, uci: &str) -> Option<Move> {
+    pos.legal_moves().into_iter().find(|m| {
+        [CastlingMode::Standard, CastlingMode::Chess960]
+            .into_iter()
+            .any(|mode| m.to_uci(mode).to_string() == uci)
+    })
+}
+
+// The move in the notation of the policy head, see preprocessing::moves.
+pub fn policy_move(pos: &Chess, m: &Move) -> Option<&'static str> {
+    let idx = idx_from_move(m, pos.turn())?;
+    Some(IDX_TO_MOVE[idx as usize])
+}
@@ -0,0 +1,210 @@
+// Sanity checks of every sample in the training data. Violations are written
+// as JSON lines with their location, followed by a summary on stderr.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use preprocessing::{move_from_idx, DecodeOptions, Game, TrainingSample};
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::{info, info_span, warn};
+
+use crate::archive::{self, GameEntry};
+use crate::create_output;
+
+// Number of games validated in parallel.
+const GAMES_PER_BATCH: usize = 256;
+
+const RANK_1: u64 = 0xFF;
+const RANK_8: u64 = 0xFF << 56;
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// Paths to the tar files containing .gz training data
+    #[arg(short, long, num_args = 1.., required = true)]
+    tar_path: Vec<String>,
+
+    /// Path to write the violations to as JSON lines ("-" for stdout)
+    #[arg(long, default_value = "-")]
+    output: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Check {
+    // The gz stream of the game could not be decoded.
+    Decoding,
+    Kings,
+    BackRankPawns,
+    Castling,
+    InvalidPosition,
+    IllegalBestMove,
+    TargetRange,
+}
+
+#[derive(Debug, Serialize)]
+struct Violation<'a> {
+    archive: &'a str,
+    // Index of the tar entry and of the sample within the game.
+    entry: usize,
+    ply: Option<usize>,
+    check: Check,
+    message: String,
+}
+
+fn check_sample(sample: &TrainingSample, game: &Game, mut report: impl FnMut(Check, String)) {
+    let planes = &sample.bitboards;
+
+    let (our_kings, their_kings) = (planes[5].count_ones(), planes[11].count_ones());
+    if our_kings != 1 || their_kings != 1 {
+        report(
+            Check::Kings,
+            format!("{our_kings} kings of the side to move, {their_kings} of the opponent"),
+        );
+    }
+
+    if (planes[0] | planes[6]) & (RANK_1 | RANK_8) != 0 {
+        report(
+            Check::BackRankPawns,
+            "pawns on the first or last rank".to_string(),
+        );
+    }
+
+    // The castling rook has to be on its initial square and the king on the
+    // back rank.
+    let castling = &game.castling;
+    let our_king = planes[5] & RANK_1 != 0;
+    let their_king = planes[11] & RANK_8 != 0;
+    let sides = [
+        (
+            "us_oo",
+            sample.castling_us_oo,
+            castling.castling_us_oo & planes[3],
+            our_king,
+        ),
+        (
+            "us_ooo",
+            sample.castling_us_ooo,
+            castling.castling_us_ooo & planes[3],
+            our_king,
+        ),
+        (
+            "them_oo",
+            sample.castling_them_oo,
+            castling.castling_them_oo & planes[9],
+            their_king,
+        ),
+        (
+            "them_ooo",
+            sample.castling_them_ooo,
+            castling.castling_them_ooo & planes[9],
+            their_king,
+        ),
+    ];
+    for (name, allowed, rook, king) in sides {
+        if allowed && (rook == 0 || !king) {
+            report(
+                Check::Castling,
+                format!("castling_{name} is set, but the king or the rook has moved"),
+            );
+        }
+    }
+
+    match sample.to_position(castling) {
+        Ok(position) => {
+            if move_from_idx(sample.best_idx, &position).is_none() {
+                let best_move = preprocessing::IDX_TO_MOVE[sample.best_idx as usize];
+                report(Check::IllegalBestMove, format!("{best_move} is not legal"));
+            }
+        }
+        Err(err) => report(Check::InvalidPosition, err.to_string()),
+    }
+
+    let (q, d) = (sample.best_q, sample.best_d);
+    if !(-1.0..=1.0).contains(&q) || !(0.0..=1.0).contains(&d) || q.abs() > 1.0 - d + 1e-3 {
+        report(Check::TargetRange, format!("best_q {q}, best_d {d}"));
+    }
+    let (q, d) = (sample.result_q, sample.result_d);
+    if ![-1.0, 0.0, 1.0].contains(&q) || ![0.0, 1.0].contains(&d) || q.abs() + d != 1.0 {
+        report(Check::TargetRange, format!("result_q {q}, result_d {d}"));
+    }
+}
+
+fn validate_game<'a>(archive: &'a str, entry: &GameEntry) -> Vec<Violation<'a>> {
+    let mut violations = Vec::new();
+    let game = match Game::read_from(entry.data.as_slice(), DecodeOptions::default()) {
+        Ok(game) => game,
+        Err(err) => {
+            violations.push(Violation {
+                archive,
+                entry: entry.index,
+                ply: None,
+                check: Check::Decoding,
+                message: err.to_string(),
+            });
+            return violations;
+        }
+    };
+    for (ply, sample) in game.samples.iter().enumerate() {
+        check_sample(sample, &game, |check, message| {
+            violations.push(Violation {
+                archive,
+                entry: entry.index,
+                ply: Some(ply),
+                check,
+                message,
+            })
+        });
+    }
+    violations
+}
+
+pub fn run(args: ValidateArgs) -> io::Result<()> {
+    let mut out = create_output(&args.output)?;
+    let mut counts: BTreeMap<Check, u64> = BTreeMap::new();
+    let mut games = 0;
+
+    for archive in &args.tar_path {
+        let _span = info_span!("archive", path = %archive).entered();
+        let mut result = Ok(());
+        let mut batch = Vec::with_capacity(GAMES_PER_BATCH);
+        let mut flush = |batch: &mut Vec<GameEntry>| -> io::Result<()> {
+            let violations: Vec<_> = batch
+                .par_iter()
+                .flat_map_iter(|entry| validate_game(archive, entry))
+                .collect();
+            for violation in violations {
+                *counts.entry(violation.check).or_default() += 1;
+                serde_json::to_writer(&mut out, &violation).map_err(io::Error::other)?;
+                writeln!(out)?;
+            }
+            games += batch.len();
+            batch.clear();
+            Ok(())
+        };
+        archive::for_each_game(archive, 0, |entry| {
+            batch.push(entry);
+            if batch.len() >= GAMES_PER_BATCH {
+                result = flush(&mut batch);
+            }
+            result.is_ok()
+        })?;
+        result?;
+        flush(&mut batch)?;
+    }
+    out.flush()?;
+
+    info!(games, "validated all games");
+    if counts.is_empty() {
+        info!("no violations");
+    }
+    for (check, count) in counts {
+        let name = serde_json::to_value(check).map_err(io::Error::other)?;
+        let check = name.as_str().unwrap_or_default();
+        warn!(check, count, "violations");
+    }
+
+    Ok(())
+}
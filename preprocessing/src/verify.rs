@@ -0,0 +1,178 @@
+// Verification of a preprocessed dataset against the report of the run that
+// wrote it (`--report`): the format version of the lines, the sample counts,
+// sizes and checksums of the shards and the sizes of their indices, and the
+// number of fields, the legality of the positions and the best moves of a
+// random fraction of the samples. Every mismatch is logged and the command fails if there was any.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use preprocessing::output::{
+    for_each_line, index_path, OutputSample, FORMAT_VERSION, INDEX_ENTRY_SIZE,
+};
+use preprocessing::rng::Rng;
+use preprocessing::{move_from_idx, MOVE_TO_IDX};
+use serde::Deserialize;
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess};
+use tracing::{error, info, warn};
+
+use crate::report::{self, ShardReport};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Report written by the preprocessing run with --report
+    #[arg(short, long)]
+    report: PathBuf,
+
+    /// Fraction of the samples whose positions and best moves are checked
+    #[arg(long, default_value_t = 0.01)]
+    sample_rate: f64,
+}
+
+// The parts of the report that are verified.
+#[derive(Deserialize)]
+struct Expected {
+    // Missing in the reports of versions before the format was versioned.
+    #[serde(default)]
+    format_version: Option<u32>,
+    // Names of the fields of the lines, empty in older reports.
+    #[serde(default)]
+    fields: Vec<String>,
+    shards: Vec<ShardReport>,
+    dry_run: bool,
+}
+
+// Whether the sample has the optional fields of the report and describes a
+// legal position with a legal best move.
+fn check_line(line: &str, extra_fields: Option<usize>) -> Result<(), String> {
+    let sample = OutputSample::parse(line).map_err(|err| err.to_string())?;
+    // The last field may contain spaces (the name of the opening).
+    if let Some(expected) = extra_fields {
+        let found = match sample.extra {
+            "" => 0,
+            extra => extra.splitn(expected.max(1), ' ').count(),
+        };
+        if found != expected {
+            return Err(format!("{found} optional fields, expected {expected}"));
+        }
+    }
+    let fen: Fen = sample
+        .fen
+        .parse()
+        .map_err(|err| format!("invalid FEN {}: {err}", sample.fen))?;
+    let pos: Chess = fen
+        .into_position(CastlingMode::Chess960)
+        .map_err(|err| format!("illegal position {}: {err}", sample.fen))?;
+    let idx = MOVE_TO_IDX
+        .get(sample.best_move)
+        .ok_or_else(|| format!("unknown best move {}", sample.best_move))?;
+    if move_from_idx(*idx, &pos).is_none() {
+        return Err(format!(
+            "illegal best move {} in {}",
+            sample.best_move, sample.fen
+        ));
+    }
+    Ok(())
+}
+
+pub fn run(args: VerifyArgs, seed: u64) -> io::Result<()> {
+    let file = File::open(&args.report)?;
+    let expected: Expected = serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {err}", args.report.display()),
+        )
+    })?;
+    if expected.dry_run {
+        warn!("the report is of a dry run, which wrote no shards");
+    }
+
+    let mut mismatches = 0u64;
+    let mut mismatch = |message: String| {
+        error!("{message}");
+        mismatches += 1;
+    };
+    match expected.format_version {
+        Some(FORMAT_VERSION) => {}
+        Some(version) => mismatch(format!(
+            "format version {version}, expected {FORMAT_VERSION}"
+        )),
+        None => mismatch("the report has no format version".to_string()),
+    }
+
+    // The fen, best_q, best_d and best_move are always written.
+    let extra_fields =
+        (!expected.fields.is_empty()).then(|| expected.fields.len().saturating_sub(4));
+    let mut rng = Rng::new(seed);
+    let (mut samples, mut checked) = (0u64, 0u64);
+    for shard in &expected.shards {
+        let path = Path::new(&shard.path);
+        let actual = match report::shard_report(path) {
+            Ok(actual) => actual,
+            Err(err) => {
+                mismatch(format!("{}: {err}", shard.path));
+                continue;
+            }
+        };
+        if actual.samples != shard.samples {
+            mismatch(format!(
+                "{}: {} samples, expected {}",
+                shard.path, actual.samples, shard.samples
+            ));
+        }
+        if actual.bytes != shard.bytes {
+            mismatch(format!(
+                "{}: {} bytes, expected {}",
+                shard.path, actual.bytes, shard.bytes
+            ));
+        }
+        if actual.sha256 != shard.sha256 {
+            mismatch(format!(
+                "{}: SHA-256 {}, expected {}",
+                shard.path, actual.sha256, shard.sha256
+            ));
+        }
+        let index = index_path(path);
+        match fs::metadata(&index) {
+            Ok(metadata) if metadata.len() == actual.samples * INDEX_ENTRY_SIZE => {}
+            Ok(metadata) => mismatch(format!(
+                "{}: {} entries, expected one per sample ({})",
+                index.display(),
+                metadata.len() / INDEX_ENTRY_SIZE,
+                actual.samples
+            )),
+            Err(err) => mismatch(format!("{}: {err}", index.display())),
+        }
+
+        let mut line_number = 0;
+        for_each_line(path, |line| {
+            line_number += 1;
+            if rng.chance(args.sample_rate) {
+                checked += 1;
+                if let Err(message) = check_line(line, extra_fields) {
+                    mismatch(format!("{}:{line_number}: {message}", shard.path));
+                }
+            }
+            Ok(())
+        })?;
+        samples += actual.samples;
+    }
+
+    info!(
+        shards = expected.shards.len(),
+        samples, checked, mismatches, "verified the dataset"
+    );
+    if mismatches > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{mismatches} mismatches with the report {}",
+                args.report.display()
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+// A web page for looking through a preprocessed dataset (`serve-ui`), so that
+// its quality can be checked with nothing but a browser. Every visit draws
+// positions uniformly at random from the dataset (see
+// preprocessing::dataset) and shows them as boards with their targets and
+// their provenance: the shard and line of the sample and the archives the
+// shard was written from, if the output directory has a manifest (see
+// manifest). Clicking a board flips it, hovering over it marks the best move.
+//
+// The page (viewer.html) fetches the samples as JSON from /api/samples, which
+// can also be used by scripts. Every request is answered on its own thread.
+// The server is meant for trusted networks: it listens on localhost by
+// default and has no authentication.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use clap::Args;
+use preprocessing::dataset::IndexedDataset;
+use preprocessing::output::OutputSample;
+use preprocessing::rng::Rng;
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::manifest::Manifest;
+
+const PAGE: &str = include_str!("viewer.html");
+
+// Clients that do not send their request in time are dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Args)]
+pub struct ServeUiArgs {
+    /// Preprocessed output (directory with shards or a single shard)
+    data: PathBuf,
+
+    /// Address to listen on, use 0.0.0.0:<PORT> to share it with others
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+
+    /// Number of positions drawn per page
+    #[arg(long, default_value_t = 24)]
+    positions: usize,
+}
+
+#[derive(Serialize)]
+struct Sample<'a> {
+    index: u64,
+    fen: &'a str,
+    best_q: f32,
+    best_d: f32,
+    best_move: &'a str,
+    extra: &'a str,
+    shard: String,
+    // Counting from 1, like editors.
+    line: u64,
+    archives: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Samples<'a> {
+    dataset: String,
+    total: u64,
+    samples: Vec<Sample<'a>>,
+}
+
+struct Viewer {
+    data: PathBuf,
+    dataset: Mutex<IndexedDataset>,
+    manifest: Manifest,
+    positions: usize,
+}
+
+// The manifest of the output directory, or of its parent for the shards of a
+// single phase (see --split-by-phase) and for single shards.
+fn load_manifest(data: &Path) -> io::Result<Manifest> {
+    let dir = if data.is_dir() {
+        Some(data)
+    } else {
+        data.parent()
+    };
+    for dir in dir.into_iter().chain(dir.and_then(Path::parent)) {
+        let path = dir.join("manifest");
+        if path.is_file() {
+            return Manifest::load(path);
+        }
+    }
+    Ok(Manifest::default())
+}
+
+// Index of the shard from its file name, see output::shard_path.
+fn shard_index(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix("shard-")?
+        .strip_suffix(".txt")?
+        .parse()
+        .ok()
+}
+
+impl Viewer {
+    fn samples_json(&self, rng: &mut Rng) -> io::Result<String> {
+        let mut dataset = self.dataset.lock().expect("no request panicked");
+        let mut drawn = Vec::with_capacity(self.positions);
+        for _ in 0..self.positions {
+            let index = rng.below(dataset.len());
+            let line = dataset.get(index)?;
+            let (shard, local) = dataset.locate(index)?;
+            drawn.push((index, line, shard.to_path_buf(), local));
+        }
+        let total = dataset.len();
+        drop(dataset);
+
+        let mut samples = Vec::with_capacity(drawn.len());
+        for (index, line, shard, local) in &drawn {
+            let sample = OutputSample::parse(line)?;
+            let archives = match shard_index(shard) {
+                Some(shard) => self
+                    .manifest
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.shards.contains(&shard))
+                    .map(|entry| entry.archive.as_str())
+                    .collect(),
+                None => Vec::new(),
+            };
+            samples.push(Sample {
+                index: *index,
+                fen: sample.fen,
+                best_q: sample.best_q,
+                best_d: sample.best_d,
+                best_move: sample.best_move,
+                extra: sample.extra,
+                shard: shard.display().to_string(),
+                line: local + 1,
+                archives,
+            });
+        }
+        let samples = Samples {
+            dataset: self.data.display().to_string(),
+            total,
+            samples,
+        };
+        serde_json::to_string(&samples).map_err(io::Error::other)
+    }
+
+    fn respond(&self, mut stream: TcpStream, mut rng: Rng) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The headers are ignored.
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+
+        let target = request.split_whitespace().nth(1).unwrap_or_default();
+        let path = target.split('?').next().unwrap_or_default();
+        let (status, content_type, body) = match path {
+            "/" => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+            "/api/samples" => match self.samples_json(&mut rng) {
+                Ok(json) => ("200 OK", "application/json", json),
+                Err(err) => (
+                    "500 Internal Server Error",
+                    "text/plain; charset=utf-8",
+                    format!("{err}\n"),
+                ),
+            },
+            _ => (
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                "not found\n".to_string(),
+            ),
+        };
+        debug!(path, status, "answered request");
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nCache-Control: no-store\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+pub fn run(args: ServeUiArgs, seed: u64) -> io::Result<()> {
+    let dataset = IndexedDataset::open(&args.data)?;
+    if dataset.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no samples in {}", args.data.display()),
+        ));
+    }
+    let viewer = Viewer {
+        manifest: load_manifest(&args.data)?,
+        data: args.data,
+        dataset: Mutex::new(dataset),
+        positions: args.positions.max(1),
+    };
+    let listener = TcpListener::bind(args.addr)?;
+    info!(
+        samples = viewer.dataset.lock().expect("not shared yet").len(),
+        "serving the dataset at http://{}/",
+        listener.local_addr()?
+    );
+
+    let root = Rng::new(seed);
+    let viewer = &viewer;
+    thread::scope(|scope| {
+        for (request, stream) in listener.incoming().enumerate() {
+            let stream = stream?;
+            let rng = root.fork(request as u64);
+            scope.spawn(move || {
+                if let Err(err) = viewer.respond(stream, rng) {
+                    debug!(%err, "failed to answer a request");
+                }
+            });
+        }
+        Ok(())
+    })
+}
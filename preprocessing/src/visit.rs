@@ -0,0 +1,81 @@
+// Custom analysis of the games of a preprocessing run, see
+// PreprocessOptions::visit. The games are decoded, filtered and augmented in
+// parallel, the visitor sees them one at a time in the order of the input.
+
+use std::path::Path;
+
+use crate::sample::{Game, TrainingSample};
+
+/// A game passed to a [`GameVisitor`].
+#[derive(Debug, Clone, Copy)]
+pub struct GameMeta<'a> {
+    /// Path of the input the game was read from.
+    pub archive: &'a Path,
+    /// Index of the game in the input, see
+    /// [`SampleSource`](crate::source::SampleSource).
+    pub entry: usize,
+    /// The game with the samples that passed the filters and their augmented
+    /// copies.
+    pub game: &'a Game,
+    /// Number of samples of the game before filtering.
+    pub samples: u64,
+}
+
+/// Callbacks of a preprocessing run for every decoded game: the start of the
+/// game, every sample that is written and the end of the game. All methods
+/// do nothing by default.
+///
+/// ```
+/// use preprocessing::rng::Rng;
+/// use preprocessing::synthetic::{gzip, random_game, tar};
+/// use preprocessing::visit::{GameMeta, GameVisitor};
+/// use preprocessing::{PreprocessOptions, TrainingSample};
+///
+/// // Average best_q of the kept samples of every game.
+/// #[derive(Default)]
+/// struct MeanQ {
+///     sum: f32,
+///     count: u32,
+///     means: Vec<f32>,
+/// }
+///
+/// impl GameVisitor for MeanQ {
+///     fn on_game_start(&mut self, _meta: &GameMeta) {
+///         self.sum = 0.0;
+///         self.count = 0;
+///     }
+///
+///     fn on_sample(&mut self, sample: &TrainingSample) {
+///         self.sum += sample.best_q;
+///         self.count += 1;
+///     }
+///
+///     fn on_game_end(&mut self, _meta: &GameMeta) {
+///         self.means.push(self.sum / self.count.max(1) as f32);
+///     }
+/// }
+///
+/// let mut rng = Rng::new(42);
+/// let games: Vec<Vec<u8>> = (0..5).map(|_| gzip(&random_game(&mut rng, 30))).collect();
+/// let path = std::env::temp_dir().join("visit-doctest.tar");
+/// std::fs::write(&path, tar(&games)?)?;
+///
+/// let mut visitor = MeanQ::default();
+/// let summary = PreprocessOptions::new([&path]).visit(&mut visitor)?;
+/// assert_eq!(visitor.means.len() as u64, summary.games);
+/// assert!(visitor.means.iter().all(|mean| (-1.0..=1.0).contains(mean)));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait GameVisitor {
+    fn on_game_start(&mut self, _meta: &GameMeta) {}
+
+    /// Called for the samples that are written, in the order of the game.
+    /// Once the sample limit of the run is reached, the rest of the game is
+    /// skipped.
+    fn on_sample(&mut self, _sample: &TrainingSample) {}
+
+    fn on_game_end(&mut self, _meta: &GameMeta) {}
+}
+
+/// Visits nothing.
+impl GameVisitor for () {}
@@ -0,0 +1,76 @@
+// Daemon mode of preprocess (`--watch <dir>`), for processing the training
+// data while the run generating it is still going: the directory (or the
+// prefix of a bucket) is listed every interval and the new tar archives are
+// processed in batches, each appending its samples to the output and
+// recording its archives in the manifest (see manifest).
+//
+// An archive counts as complete once its size did not change between two
+// listings, so that the archives being copied into the directory are not read
+// halfway. Writers that rename complete archives into the directory are
+// picked up one interval later than necessary.
+//
+// Every archive is handed to a batch once, also if it could not be read: the
+// errors count towards --max-errors, and a later run without --watch retries
+// the archives that are missing from the manifest.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use preprocessing::storage;
+use tracing::{debug, info};
+
+use crate::signals;
+
+// How often a waiting daemon checks for signals.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Runs `process` on the inputs and then on the complete archives appearing in
+// the directory, until SIGINT or SIGTERM.
+pub fn watch<F>(
+    dir: &str,
+    interval: Duration,
+    inputs: Vec<String>,
+    mut process: F,
+) -> io::Result<()>
+where
+    F: FnMut(Vec<String>) -> io::Result<()>,
+{
+    info!(
+        dir,
+        interval = interval.as_secs(),
+        "watching for new archives"
+    );
+    let mut handed: HashSet<String> = HashSet::new();
+    // Sizes of the archives in the previous listing.
+    let mut sizes: HashMap<String, u64> = HashMap::new();
+    let mut batch = inputs;
+    loop {
+        let listing = storage::list_archives(dir)?;
+        for (path, size) in &listing {
+            if sizes.get(path) == Some(size) && !batch.contains(path) {
+                batch.push(path.clone());
+            }
+        }
+        sizes = listing.into_iter().collect();
+        batch.retain(|path| !handed.contains(path));
+
+        if batch.is_empty() {
+            debug!("no new archives");
+        } else {
+            info!(archives = batch.len(), "processing new archives");
+            handed.extend(batch.iter().cloned());
+            process(std::mem::take(&mut batch))?;
+        }
+
+        let listed = Instant::now();
+        while listed.elapsed() < interval {
+            if signals::interrupted() {
+                info!("stopped watching");
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+// Worker of distributed preprocessing (`work`), see coordinator: leases the
+// archives from the coordinator one at a time, runs `preprocess` on every
+// archive in a child process with the options after `--`, and uploads the
+// output directory of the run with its report before completing the archive.
+// The sinks written outside of the output directory are not uploaded.
+//
+// The lease is renewed while the child runs. A worker that lost its lease
+// stops the child and asks for the next archive; a child that fails fails the
+// archive, which the coordinator hands out again. The worker exits once the
+// coordinator has no archives left, or when it cannot reach the coordinator
+// --retries times in a row.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use tracing::{debug, info, warn};
+
+use crate::coordinator::{Lease, IDLE_INTERVAL};
+use crate::signals;
+
+// How often the worker checks on the child and on signals.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Coordinators that do not answer in time count as unreachable.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Args)]
+pub struct WorkArgs {
+    /// Address of the coordinator, e.g. 10.0.0.1:8090
+    #[arg(long)]
+    coordinator: String,
+
+    /// Directory for the outputs of the archives until they are uploaded
+    /// [default: attix-work in the temporary directory]
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+
+    /// Number of failed attempts in a row to reach the coordinator before
+    /// giving up
+    #[arg(long, default_value_t = 5)]
+    retries: u32,
+
+    /// Options of preprocess for every archive, e.g. --config pipeline.toml,
+    /// besides the inputs, the output directory and the report
+    #[arg(last = true)]
+    pipeline: Vec<String>,
+}
+
+struct Client {
+    addr: String,
+    retries: u32,
+}
+
+impl Client {
+    // Sends the request and returns the status code and the body of the
+    // response.
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: &mut dyn Read,
+        len: u64,
+    ) -> io::Result<(u16, String)> {
+        let stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        let mut out = BufWriter::new(&stream);
+        write!(
+            out,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nContent-Length: {len}\r\n\
+             Connection: close\r\n\r\n",
+            self.addr
+        )?;
+        io::copy(body, &mut out)?;
+        out.flush()?;
+        drop(out);
+
+        let mut response = String::new();
+        BufReader::new(&stream).read_to_string(&mut response)?;
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid response of the coordinator",
+                )
+            })?;
+        let body = response
+            .split_once("\r\n\r\n")
+            .map_or("", |(_, body)| body)
+            .trim()
+            .to_string();
+        Ok((status, body))
+    }
+
+    // Like send, retrying with growing pauses while the coordinator cannot be
+    // reached.
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> io::Result<(u16, String)> {
+        self.retry(path, || {
+            self.send(method, path, &mut &body[..], body.len() as u64)
+        })
+    }
+
+    fn upload(&self, path: &str, file: &Path) -> io::Result<(u16, String)> {
+        self.retry(path, || {
+            let len = fs::metadata(file)?.len();
+            self.send("PUT", path, &mut BufReader::new(File::open(file)?), len)
+        })
+    }
+
+    fn retry<F>(&self, path: &str, mut send: F) -> io::Result<(u16, String)>
+    where
+        F: FnMut() -> io::Result<(u16, String)>,
+    {
+        let mut attempt = 1;
+        loop {
+            match send() {
+                Ok(response) => return Ok(response),
+                Err(err) if attempt < self.retries.max(1) => {
+                    let pause = Duration::from_secs(1 << attempt.min(6));
+                    warn!(%err, path, attempt, "cannot reach the coordinator, retrying");
+                    thread::sleep(pause);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+// The files in the directory and its subdirectories, with their paths
+// relative to it.
+fn files(dir: &Path, prefix: &str, out: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            files(&entry.path(), &format!("{name}/"), out)?;
+        } else {
+            out.push((name, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+// What became of a leased archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Done,
+    // preprocess failed, or could not be run.
+    Failed,
+    // The lease expired before the archive was done.
+    Lost,
+    // The worker was interrupted while preprocess ran.
+    Interrupted,
+}
+
+struct Worker {
+    client: Client,
+    work_dir: PathBuf,
+    // Options of every preprocess run.
+    options: Vec<String>,
+}
+
+impl Worker {
+    // Runs preprocess on the archive of the lease, renewing the lease until
+    // it exits. None if the lease was lost.
+    fn preprocess(&self, lease: &Lease, dir: &Path) -> io::Result<Option<ExitStatus>> {
+        let base = format!("/items/{}/{}", lease.id, lease.lease);
+        let mut child = Command::new(env::current_exe()?)
+            .arg("preprocess")
+            .arg("--quiet")
+            .arg("--tar-path")
+            .arg(&lease.archive)
+            .arg("--output-dir")
+            .arg(dir)
+            .arg("--report")
+            .arg(dir.join("report.json"))
+            .args(&self.options)
+            .spawn()?;
+        let renew_every = Duration::from_secs((lease.seconds / 3).max(1));
+        let mut renewed = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if signals::interrupted() {
+                child.kill()?;
+                child.wait()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "the worker was interrupted",
+                ));
+            }
+            if renewed.elapsed() >= renew_every {
+                match self.client.request("POST", &format!("{base}/renew"), b"") {
+                    Ok((200, _)) => renewed = Instant::now(),
+                    Ok((409, _)) => {
+                        warn!(archive = %lease.archive, "lost the lease, stopping");
+                        child.kill()?;
+                        child.wait()?;
+                        return Ok(None);
+                    }
+                    // The coordinator may be back before the lease expires.
+                    Ok((status, body)) => warn!(status, %body, "failed to renew the lease"),
+                    Err(err) => warn!(%err, "failed to renew the lease"),
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Processes the archive of the lease. The output directory is removed
+    // whatever happens, also when the coordinator cannot be reached.
+    fn process(&self, lease: &Lease) -> io::Result<Outcome> {
+        info!(id = lease.id, archive = %lease.archive, "processing the archive");
+        let base = format!("/items/{}/{}", lease.id, lease.lease);
+        let dir = self
+            .work_dir
+            .join(format!("item-{:05}-{}", lease.id, lease.lease));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        let outcome = match self.preprocess(lease, &dir) {
+            Ok(None) => Ok(Outcome::Lost),
+            Ok(Some(status)) if status.success() => {
+                self.upload(&base, &dir)
+                    .map(|done| if done { Outcome::Done } else { Outcome::Lost })
+            }
+            Ok(Some(status)) => self
+                .fail(&base, &format!("preprocess exited with {status}"))
+                .map(|()| Outcome::Failed),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => self
+                .fail(&base, &err.to_string())
+                .map(|()| Outcome::Interrupted),
+            Err(err) => self
+                .fail(&base, &format!("failed to run preprocess: {err}"))
+                .map(|()| Outcome::Failed),
+        };
+        let removed = if dir.exists() {
+            fs::remove_dir_all(&dir)
+        } else {
+            Ok(())
+        };
+        let outcome = outcome?;
+        removed?;
+        Ok(outcome)
+    }
+
+    // Uploads the output and completes the archive, false if the lease was
+    // lost.
+    fn upload(&self, base: &str, dir: &Path) -> io::Result<bool> {
+        let mut uploads = Vec::new();
+        files(dir, "", &mut uploads)?;
+        for (name, path) in &uploads {
+            // Checkpoints are only useful for resuming on this machine.
+            if name == "checkpoint" {
+                continue;
+            }
+            let (status, body) = self.client.upload(&format!("{base}/files/{name}"), path)?;
+            match status {
+                200 => debug!(%name, "uploaded"),
+                409 => return Ok(false),
+                _ => return Err(unexpected(status, &body)),
+            }
+        }
+        match self
+            .client
+            .request("POST", &format!("{base}/complete"), b"")?
+        {
+            (200, _) => Ok(true),
+            (409, _) => Ok(false),
+            (status, body) => Err(unexpected(status, &body)),
+        }
+    }
+
+    fn fail(&self, base: &str, error: &str) -> io::Result<()> {
+        warn!(error, "the archive failed");
+        match self
+            .client
+            .request("POST", &format!("{base}/fail"), error.as_bytes())?
+        {
+            (200 | 409, _) => Ok(()),
+            (status, body) => Err(unexpected(status, &body)),
+        }
+    }
+}
+
+fn unexpected(status: u16, body: &str) -> io::Error {
+    io::Error::other(format!(
+        "unexpected answer of the coordinator: {status} {body}"
+    ))
+}
+
+pub fn run(args: WorkArgs, threads: Option<usize>, seed: Option<u64>) -> io::Result<()> {
+    signals::install()?;
+    let work_dir = args
+        .work_dir
+        .unwrap_or_else(|| env::temp_dir().join("attix-work"));
+    fs::create_dir_all(&work_dir)?;
+    let mut options = Vec::new();
+    if let Some(threads) = threads {
+        options.extend(["--threads".to_string(), threads.to_string()]);
+    }
+    if let Some(seed) = seed {
+        options.extend(["--seed".to_string(), seed.to_string()]);
+    }
+    options.extend(args.pipeline);
+    let worker = Worker {
+        client: Client {
+            addr: args.coordinator,
+            retries: args.retries,
+        },
+        work_dir,
+        options,
+    };
+
+    let (mut done, mut failed, mut lost, mut interrupted) = (0u64, 0u64, 0u64, 0u64);
+    while !signals::interrupted() {
+        let (status, body) = worker.client.request("POST", "/lease", b"")?;
+        match status {
+            200 => {
+                let lease: Lease = serde_json::from_str(&body).map_err(io::Error::other)?;
+                match worker.process(&lease)? {
+                    Outcome::Done => done += 1,
+                    Outcome::Failed => failed += 1,
+                    Outcome::Lost => lost += 1,
+                    Outcome::Interrupted => interrupted += 1,
+                }
+            }
+            204 => {
+                debug!("all archives are leased, waiting");
+                let asked = Instant::now();
+                while asked.elapsed() < IDLE_INTERVAL && !signals::interrupted() {
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+            410 => {
+                info!("no archives left");
+                break;
+            }
+            _ => return Err(unexpected(status, &body)),
+        }
+    }
+    println!(
+        "processed {done} archives, {failed} failed, {lost} lost with their leases, \
+         {interrupted} interrupted"
+    );
+    Ok(())
+}
@@ -0,0 +1,166 @@
+// Python bindings of the preprocessing library.
+//
+// The samples are read in batches and converted into NumPy arrays, which can
+// be handed to PyTorch without copying them sample by sample. Reading and
+// decoding happens without holding the GIL.
+
+use std::io;
+use std::path::PathBuf;
+use std::vec;
+
+use numpy::{PyArray1, PyArrayMethods};
+use preprocessing::phase::Phase;
+use preprocessing::sample::NUM_PLANES;
+use preprocessing::{filter_position, FilterOptions, Games, TrainingSample, IDX_TO_MOVE};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+fn parse_phase(name: &str) -> PyResult<Phase> {
+    match name {
+        "opening" => Ok(Phase::Opening),
+        "middlegame" => Ok(Phase::Middlegame),
+        "endgame" => Ok(Phase::Endgame),
+        _ => Err(PyValueError::new_err(format!("unknown phase: {name}"))),
+    }
+}
+
+/// Iterator over the samples of a training data archive in batches. Every
+/// batch is a dict of NumPy arrays:
+///
+/// - planes: (N, 12, 8, 8) uint8, pieces of the side to move followed by the
+///   pieces of the opponent, indexed by rank and file
+/// - best_q, best_d, result_q, result_d, plies_left: (N,) float32
+/// - best_idx: (N,) int64, index of the best move in POLICY_MOVES
+/// - played_idx: (N,) int64, index of the move played in the game
+/// - played_q, played_d: (N,) float32, value of the played move
+/// - rule50_count: (N,) uint8
+/// - repetitions: (N,) uint8, earlier occurrences of the position in the game
+///
+/// The samples are filtered the same way as by the preprocessing command.
+#[pyclass(module = "pyattix")]
+struct Samples {
+    games: Games,
+    samples: vec::IntoIter<TrainingSample>,
+    filters: FilterOptions,
+    batch_size: usize,
+}
+
+impl Samples {
+    fn next_batch(&mut self) -> preprocessing::Result<Vec<TrainingSample>> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        while batch.len() < self.batch_size {
+            if let Some(sample) = self.samples.next() {
+                if filter_position(&sample, &self.filters).is_none() {
+                    batch.push(sample);
+                }
+                continue;
+            }
+            match self.games.next() {
+                Some(game) => self.samples = game?.samples.into_iter(),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+}
+
+#[pymethods]
+impl Samples {
+    #[new]
+    #[pyo3(signature = (
+        path,
+        batch_size = 1024,
+        phases = Vec::new(),
+        min_policy_entropy = None,
+        max_policy_entropy = None
+    ))]
+    fn new(
+        path: PathBuf,
+        batch_size: usize,
+        phases: Vec<String>,
+        min_policy_entropy: Option<f32>,
+        max_policy_entropy: Option<f32>,
+    ) -> PyResult<Self> {
+        if batch_size == 0 {
+            return Err(PyValueError::new_err("batch_size must be positive"));
+        }
+        let filters = FilterOptions {
+            phases: phases
+                .iter()
+                .map(|name| parse_phase(name))
+                .collect::<PyResult<_>>()?,
+            min_policy_entropy,
+            max_policy_entropy,
+            ..FilterOptions::default()
+        };
+        Ok(Samples {
+            games: Games::from_tar(path, filters.decode_options()).map_err(io::Error::from)?,
+            samples: Vec::new().into_iter(),
+            filters,
+            batch_size,
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let batch = py
+            .allow_threads(|| self.next_batch())
+            .map_err(io::Error::from)?;
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        to_arrays(py, &batch).map(Some)
+    }
+}
+
+fn to_arrays<'py>(py: Python<'py>, batch: &[TrainingSample]) -> PyResult<Bound<'py, PyDict>> {
+    let mut planes = vec![0u8; batch.len() * NUM_PLANES * 64];
+    for (sample, sample_planes) in batch.iter().zip(planes.chunks_mut(NUM_PLANES * 64)) {
+        for (bitboard, plane) in sample.bitboards.iter().zip(sample_planes.chunks_mut(64)) {
+            for (square, value) in plane.iter_mut().enumerate() {
+                *value = ((bitboard >> square) & 1) as u8;
+            }
+        }
+    }
+    let column = |f: fn(&TrainingSample) -> f32| {
+        PyArray1::from_vec(py, batch.iter().map(f).collect::<Vec<_>>())
+    };
+
+    let arrays = PyDict::new(py);
+    arrays.set_item(
+        "planes",
+        PyArray1::from_vec(py, planes).reshape([batch.len(), NUM_PLANES, 8, 8])?,
+    )?;
+    arrays.set_item("best_q", column(|sample| sample.best_q))?;
+    arrays.set_item("best_d", column(|sample| sample.best_d))?;
+    arrays.set_item("result_q", column(|sample| sample.result_q))?;
+    arrays.set_item("result_d", column(|sample| sample.result_d))?;
+    arrays.set_item("plies_left", column(|sample| sample.plies_left))?;
+    // PyTorch indexes with int64.
+    let best_idx: Vec<_> = batch.iter().map(|sample| sample.best_idx as i64).collect();
+    arrays.set_item("best_idx", PyArray1::from_vec(py, best_idx))?;
+    let played_idx: Vec<_> = batch
+        .iter()
+        .map(|sample| sample.played_idx as i64)
+        .collect();
+    arrays.set_item("played_idx", PyArray1::from_vec(py, played_idx))?;
+    arrays.set_item("played_q", column(|sample| sample.played_q))?;
+    arrays.set_item("played_d", column(|sample| sample.played_d))?;
+    let rule50_count: Vec<_> = batch.iter().map(|sample| sample.rule50_count).collect();
+    arrays.set_item("rule50_count", PyArray1::from_vec(py, rule50_count))?;
+    let repetitions: Vec<_> = batch.iter().map(|sample| sample.repetitions).collect();
+    arrays.set_item("repetitions", PyArray1::from_vec(py, repetitions))?;
+    Ok(arrays)
+}
+
+#[pymodule]
+fn pyattix(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Samples>()?;
+    // UCI notation of the moves in the policy head, see IDX_TO_MOVE.
+    m.add("POLICY_MOVES", IDX_TO_MOVE.to_vec())?;
+    Ok(())
+}
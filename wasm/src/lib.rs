@@ -0,0 +1,170 @@
+// WebAssembly build of the engine for browser-based analysis boards, with the
+// small JavaScript API they need instead of the UCI protocol:
+//
+//     const engine = new Engine();
+//     engine.onInfo((line) => console.log(line));
+//     engine.setPosition("startpos", ["e2e4", "e7e5"]);
+//     const best = engine.go(12, undefined, 1000);
+//
+// The `info` lines of the search are those of UCI. The search runs on the
+// calling thread until one of its limits, so pages run the engine in a Web
+// Worker to stay responsive. Without limits, it searches to DEFAULT_DEPTH.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use engine::eval::{Eval, HandCrafted};
+use engine::nnue::Nnue;
+use engine::search::{self, Options, Search};
+use engine::time::Limits;
+use engine::tt::{self, TranspositionTable};
+use js_sys::Function;
+use preprocessing::network;
+use preprocessing::nnue::Network;
+use shakmaty::fen::Fen;
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Position};
+use wasm_bindgen::prelude::*;
+
+const DEFAULT_DEPTH: u32 = 10;
+
+// The callback of the `info` lines, which the search requires to be Send.
+struct InfoCallback(Function);
+
+// SAFETY: WebAssembly without the atomics feature has a single thread, so the
+// callback never leaves the thread it was created on.
+unsafe impl Send for InfoCallback {}
+
+impl InfoCallback {
+    fn call(&self, line: &str) {
+        // An exception of the callback does not stop the search.
+        let _ = self.0.call1(&JsValue::NULL, &JsValue::from_str(line));
+    }
+}
+
+#[wasm_bindgen]
+pub struct Engine {
+    // Positions of the game, the current one last.
+    game: Vec<Chess>,
+    castling_mode: CastlingMode,
+    network: Option<Arc<Network>>,
+    tt: Arc<TranspositionTable>,
+    info: Option<Function>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Engine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Engine {
+        Engine {
+            game: vec![Chess::default()],
+            castling_mode: CastlingMode::Standard,
+            network: None,
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB)),
+            info: None,
+        }
+    }
+
+    // Sets the position from "startpos" or a FEN, Chess960 ones included,
+    // and the moves played since in UCI notation.
+    #[wasm_bindgen(js_name = setPosition)]
+    pub fn set_position(&mut self, fen: &str, moves: Vec<String>) -> Result<(), JsError> {
+        let (mut pos, castling_mode) = match fen {
+            "startpos" => (Chess::default(), CastlingMode::Standard),
+            fen => {
+                let fen: Fen = fen.parse()?;
+                let castling_mode = CastlingMode::detect(fen.as_setup());
+                (fen.into_position(castling_mode)?, castling_mode)
+            }
+        };
+        let mut game = vec![pos.clone()];
+        for uci in &moves {
+            let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&pos)?;
+            pos.play_unchecked(&m);
+            game.push(pos.clone());
+        }
+        self.game = game;
+        self.castling_mode = castling_mode;
+        Ok(())
+    }
+
+    // Clears the transposition table for a new game.
+    #[wasm_bindgen(js_name = newGame)]
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.game = vec![Chess::default()];
+    }
+
+    // Resizes the transposition table, which clears it.
+    #[wasm_bindgen(js_name = setHash)]
+    pub fn set_hash(&mut self, size_mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(size_mb));
+    }
+
+    // Evaluates with the network of the bytes of a file written by the
+    // quantize command, the hand-crafted evaluation with no bytes.
+    #[wasm_bindgen(js_name = loadNetwork)]
+    pub fn load_network(&mut self, bytes: Option<Vec<u8>>) -> Result<(), JsError> {
+        self.network = match bytes {
+            Some(bytes) => Some(Arc::new(network::read(bytes.as_slice())?)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    // Calls the function with every `info` line of the searches, or stops
+    // calling one without a function.
+    #[wasm_bindgen(js_name = onInfo)]
+    pub fn on_info(&mut self, callback: Option<Function>) {
+        self.info = callback;
+    }
+
+    // Searches the position to the depth, the nodes or the milliseconds,
+    // whichever comes first, and returns the best move in UCI notation,
+    // undefined without legal moves.
+    pub fn go(
+        &mut self,
+        depth: Option<u32>,
+        nodes: Option<u32>,
+        movetime: Option<u32>,
+    ) -> Option<String> {
+        let unlimited = depth.is_none() && nodes.is_none() && movetime.is_none();
+        let limits = Limits {
+            depth: depth.or(unlimited.then_some(DEFAULT_DEPTH)),
+            nodes: nodes.map(u64::from),
+            time: movetime.map(|millis| Duration::from_millis(millis.into())),
+            soft: None,
+        };
+        let pos = self.game.last().expect("the game has a position");
+        let mut search = Search::new(
+            pos.clone(),
+            self.game.iter().map(search::hash).collect(),
+            limits,
+            self.tt.clone(),
+            Arc::new(AtomicBool::new(false)),
+            self.castling_mode,
+            Options::default(),
+        );
+        let eval: Box<dyn Eval> = match &self.network {
+            Some(network) => Box::new(Nnue::new(network.clone())),
+            None => Box::new(HandCrafted::default()),
+        };
+        search.set_eval(eval);
+        match &self.info {
+            Some(callback) => {
+                let callback = InfoCallback(callback.clone());
+                search.set_info(Box::new(move |line| callback.call(line)));
+            }
+            None => search.set_silent(),
+        }
+        let best = search.run()?;
+        Some(best.to_uci(self.castling_mode).to_string())
+    }
+}